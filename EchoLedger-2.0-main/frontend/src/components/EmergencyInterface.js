@@ -22,8 +22,10 @@ const EmergencyInterface = ({ actors }) => {
   const loadRecentAlerts = async () => {
     try {
       if (actors.emergencyBridge) {
-        const alerts = await actors.emergencyBridge.get_recent_alerts(10);
-        setRecentAlerts(alerts);
+        const result = await actors.emergencyBridge.get_recent_alerts(10);
+        if (result.Ok) {
+          setRecentAlerts(result.Ok);
+        }
       }
     } catch (error) {
       console.error('Failed to load recent alerts:', error);
@@ -0,0 +1,71 @@
+// The canisters' only source of time. Production reads the IC clock; tests, and builds made with
+// the "test-clock" feature, can pin it so expiry, waiting periods and windows can be exercised
+// without waiting them out.
+//
+// IC time is the block time: every message executed in the same round sees the same nanosecond.
+// Records that must be ordered take a sequence number from next_sequence as well.
+//
+// Each canister declares its own set_test_clock endpoint, so the method is exported from its own wasm.
+
+thread_local! {
+    static OVERRIDE: std::cell::Cell<Option<u64>> = const { std::cell::Cell::new(None) };
+    static LAST_SEQUENCE: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+}
+
+pub fn now() -> u64 {
+    OVERRIDE.with(|o| o.get()).unwrap_or_else(ic_cdk::api::time)
+}
+
+// Strictly increasing for the life of the canister; the last value is carried across upgrades
+pub fn next_sequence() -> u64 {
+    LAST_SEQUENCE.with(|s| {
+        let next = s.get() + 1;
+        s.set(next);
+        next
+    })
+}
+
+pub fn snapshot() -> u64 {
+    LAST_SEQUENCE.with(|s| s.get())
+}
+
+// Images saved before the counter was carried restore None; it then starts again from zero
+pub fn restore(last_sequence: Option<u64>) {
+    LAST_SEQUENCE.with(|s| s.set(s.get().max(last_sequence.unwrap_or(0))));
+}
+
+#[cfg(feature = "test-clock")]
+pub fn set(at: Option<u64>) {
+    OVERRIDE.with(|o| o.set(at));
+}
+
+#[cfg(feature = "test-clock")]
+pub fn advance(nanos: u64) {
+    set(Some(now() + nanos));
+}
+
+// What set_test_clock does once the caller is authorized: pin or release the clock, then move it on
+#[cfg(feature = "test-clock")]
+pub fn apply_test_setting(at: Option<u64>, advance_nanos: u64) -> u64 {
+    if at.is_some() || advance_nanos == 0 {
+        set(at);
+    }
+    if advance_nanos > 0 {
+        advance(advance_nanos);
+    }
+    now()
+}
+
+// Unit tests pin the clock a whole number of minutes or hours past one fixed instant
+#[cfg(feature = "test-clock")]
+pub const TEST_EPOCH: u64 = 1_700_000_000_000_000_000;
+
+#[cfg(feature = "test-clock")]
+pub fn at_minutes(minutes: u64) {
+    set(Some(TEST_EPOCH + minutes * 60 * 1_000_000_000));
+}
+
+#[cfg(feature = "test-clock")]
+pub fn at_hours(hours: u64) {
+    set(Some(TEST_EPOCH + hours * 60 * 60 * 1_000_000_000));
+}
@@ -0,0 +1,240 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::emergency::EmergencyAccessLog;
+use crate::{
+    clock, deliver_to_contacts, directive_owner, hashing, i18n, ids, tenants, ContactEvent, CONTACT_NOTIFICATIONS,
+    EMERGENCY_CONTACTS,
+};
+
+// Every access that disclosed directive content produces a letter to the patient and the people
+// listed for them: who looked, when, why and which directives they saw. Letters are held and sent
+// together on the delivery schedule, so an ED reading a record several times in one night does not
+// page the family each time, and each letter keeps the notifications it went out as.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AccessLetter {
+    pub letter_id: String,
+    pub patient_id_hash: Vec<u8>,
+    pub accessed_by: Principal,
+    pub organization: Option<String>, // the requester's tenant, when it belongs to one
+    pub via: Principal,
+    pub accessed_at: u64,
+    pub reason: String,
+    pub disclosed: Vec<String>,
+    pub status: String, // "PENDING", "DELIVERED", "NO_RECIPIENTS"
+    pub delivered_at: Option<u64>,
+    pub notification_ids: Vec<String>,
+    pub acknowledged_notifications: u32, // filled in on read
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AccessLetterSchedule {
+    pub delivery_interval_seconds: u64,
+    pub next_delivery_at: Option<u64>, // ignored when set
+}
+
+// Carried across upgrades with the hash key ring; letters waiting for delivery must not be lost
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct LetterState {
+    letters: Vec<AccessLetter>,
+    delivery_interval_seconds: u64,
+}
+
+// Outcomes that put directive content in front of someone, and how the letter explains them
+const DISCLOSING_OUTCOMES: [(&str, &str); 4] = [
+    ("GRANTED", "emergency lookup of your directives"),
+    ("OFFLINE_EXPORT", "download of your directives for offline emergency use"),
+    ("QUERY_VERIFIED", "verified query about your directives"),
+    ("TRANSLATION_SERVED", "translation of your directive for a treating clinician"),
+];
+
+const DEFAULT_DELIVERY_INTERVAL_SECONDS: u64 = 24 * 60 * 60;
+const MIN_DELIVERY_INTERVAL_SECONDS: u64 = 60;
+const MAX_DELIVERY_INTERVAL_SECONDS: u64 = 30 * 24 * 60 * 60;
+const MAX_LETTERS: usize = 50_000;
+const TICK_INTERVAL: Duration = Duration::from_secs(60);
+
+thread_local! {
+    static LETTERS: std::cell::RefCell<BTreeMap<String, AccessLetter>> = const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    static DELIVERY_INTERVAL_SECONDS: std::cell::Cell<u64> = const { std::cell::Cell::new(DEFAULT_DELIVERY_INTERVAL_SECONDS) };
+    static NEXT_DELIVERY_AT: std::cell::Cell<Option<u64>> = const { std::cell::Cell::new(None) };
+    static TIMER_STARTED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// The patient and their proxies read their own letters
+#[ic_cdk::query]
+fn get_access_letters(patient_id: String) -> Result<Vec<AccessLetter>, String> {
+    let requester = caller();
+    if directive_owner(&patient_id) != Some(requester)
+        && !tenants::is_proxy(&patient_id, requester)
+        && !ic_cdk::api::is_controller(&requester)
+    {
+        return Err("Only the patient or their proxy may read access letters".to_string());
+    }
+    let storage_key = hashing::storage_key(&hashing::patient_hash(&patient_id));
+    Ok(LETTERS.with(|l| {
+        l.borrow().values().filter(|letter| letter.patient_id_hash == storage_key).map(with_acknowledgments).collect()
+    }))
+}
+
+#[ic_cdk::query]
+fn get_access_letter_schedule() -> AccessLetterSchedule {
+    AccessLetterSchedule {
+        delivery_interval_seconds: DELIVERY_INTERVAL_SECONDS.with(|i| i.get()),
+        next_delivery_at: NEXT_DELIVERY_AT.with(|n| n.get()),
+    }
+}
+
+// Takes effect from the next delivery; letters already waiting go out then
+#[ic_cdk::update]
+fn set_access_letter_schedule(schedule: AccessLetterSchedule) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may change the access letter schedule".to_string());
+    }
+    let interval = schedule.delivery_interval_seconds;
+    if !(MIN_DELIVERY_INTERVAL_SECONDS..=MAX_DELIVERY_INTERVAL_SECONDS).contains(&interval) {
+        return Err(format!(
+            "delivery_interval_seconds must be between {} and {}",
+            MIN_DELIVERY_INTERVAL_SECONDS, MAX_DELIVERY_INTERVAL_SECONDS
+        ));
+    }
+    DELIVERY_INTERVAL_SECONDS.with(|i| i.set(interval));
+    NEXT_DELIVERY_AT.with(|n| n.set(Some(clock::now() + interval * 1_000_000_000)));
+    Ok(())
+}
+
+// Sends everything pending now, outside the schedule
+#[ic_cdk::update]
+fn deliver_access_letters() -> Result<u32, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may deliver access letters out of schedule".to_string());
+    }
+    Ok(deliver_pending())
+}
+
+// Called for every logged access; accesses that disclosed nothing produce no letter
+pub(crate) fn record_access(entry: &EmergencyAccessLog) {
+    let Some((_, reason)) = DISCLOSING_OUTCOMES.iter().find(|(outcome, _)| *outcome == entry.outcome) else {
+        return;
+    };
+    ensure_delivery_timer();
+    let letter = AccessLetter {
+        letter_id: ids::new_id("LETTER"),
+        patient_id_hash: entry.patient_id_hash.clone(),
+        accessed_by: entry.requester,
+        organization: tenants::membership_of(entry.requester).map(|m| m.tenant_id),
+        via: entry.via,
+        accessed_at: entry.accessed_at,
+        reason: reason.to_string(),
+        disclosed: entry.directive_types.clone(),
+        status: "PENDING".to_string(),
+        delivered_at: None,
+        notification_ids: Vec::new(),
+        acknowledged_notifications: 0,
+    };
+    LETTERS.with(|l| {
+        let mut letters = l.borrow_mut();
+        if letters.len() >= MAX_LETTERS {
+            // Oldest settled letters make room; pending ones are never dropped unsent
+            let settled: Vec<String> = letters.values()
+                .filter(|letter| letter.status != "PENDING")
+                .take(letters.len() + 1 - MAX_LETTERS)
+                .map(|letter| letter.letter_id.clone())
+                .collect();
+            for letter_id in settled {
+                letters.remove(&letter_id);
+            }
+        }
+        letters.insert(letter.letter_id.clone(), letter);
+    });
+}
+
+pub(crate) fn rekey_patient(old_hash: &[u8], new_hash: &[u8]) {
+    LETTERS.with(|l| {
+        for letter in l.borrow_mut().values_mut().filter(|letter| letter.patient_id_hash == old_hash) {
+            letter.patient_id_hash = new_hash.to_vec();
+        }
+    });
+}
+
+// Timers do not survive upgrades; post_upgrade and the first new letter restart the schedule
+pub(crate) fn ensure_delivery_timer() {
+    if TIMER_STARTED.with(|s| s.replace(true)) {
+        return;
+    }
+    ic_cdk_timers::set_timer_interval(TICK_INTERVAL, || {
+        let now = clock::now();
+        let interval = DELIVERY_INTERVAL_SECONDS.with(|i| i.get()) * 1_000_000_000;
+        let next = NEXT_DELIVERY_AT.with(|n| *n.get().get_or_insert(now + interval));
+        if now >= next {
+            NEXT_DELIVERY_AT.with(|n| n.set(Some(now + interval)));
+            deliver_pending();
+        }
+    });
+}
+
+pub(crate) fn snapshot() -> LetterState {
+    LetterState {
+        letters: LETTERS.with(|l| l.borrow().values().cloned().collect()),
+        delivery_interval_seconds: DELIVERY_INTERVAL_SECONDS.with(|i| i.get()),
+    }
+}
+
+pub(crate) fn restore(state: Option<LetterState>) {
+    let Some(state) = state else {
+        return;
+    };
+    LETTERS.with(|l| *l.borrow_mut() = state.letters.into_iter().map(|letter| (letter.letter_id.clone(), letter)).collect());
+    DELIVERY_INTERVAL_SECONDS.with(|i| i.set(state.delivery_interval_seconds));
+}
+
+// One notification per letter per listed contact; returns how many letters were settled
+fn deliver_pending() -> u32 {
+    let pending: Vec<AccessLetter> = LETTERS.with(|l| l.borrow().values().filter(|letter| letter.status == "PENDING").cloned().collect());
+    let now = clock::now();
+    for letter in &pending {
+        let contacts = EMERGENCY_CONTACTS.with(|c| c.borrow().get(&letter.patient_id_hash).cloned().unwrap_or_default());
+        let locale = i18n::patient_locale(&letter.patient_id_hash);
+        let notifications = deliver_to_contacts(&contacts, &letter_event(letter), &locale, Some(&letter.patient_id_hash));
+        LETTERS.with(|l| {
+            if let Some(stored) = l.borrow_mut().get_mut(&letter.letter_id) {
+                stored.status = if notifications.is_empty() { "NO_RECIPIENTS" } else { "DELIVERED" }.to_string();
+                stored.delivered_at = Some(now);
+                stored.notification_ids = notifications.into_iter().map(|n| n.notification_id).collect();
+            }
+        });
+    }
+    if !pending.is_empty() {
+        ic_cdk::println!("AUDIT: Delivered {} access letter(s)", pending.len());
+    }
+    pending.len() as u32
+}
+
+fn letter_event(letter: &AccessLetter) -> ContactEvent {
+    let who = match &letter.organization {
+        Some(organization) => format!("{} ({})", letter.accessed_by.to_text(), organization),
+        None => letter.accessed_by.to_text(),
+    };
+    let disclosed = if letter.disclosed.is_empty() { "none".to_string() } else { letter.disclosed.join(", ") };
+    ContactEvent {
+        event_type: "RECORD_ACCESSED".to_string(),
+        reference_id: letter.letter_id.clone(),
+        summary: format!("Your directive record was accessed by {} for a {}.", who, letter.reason),
+        details: format!("Accessed at {} (nanoseconds since epoch). Directives disclosed: {}.", letter.accessed_at, disclosed),
+    }
+}
+
+fn with_acknowledgments(letter: &AccessLetter) -> AccessLetter {
+    let acknowledged = CONTACT_NOTIFICATIONS.with(|n| {
+        let notifications = n.borrow();
+        letter.notification_ids.iter()
+            .filter(|id| notifications.get(*id).is_some_and(|n| n.acknowledged_at.is_some()))
+            .count()
+    });
+    AccessLetter { acknowledged_notifications: acknowledged as u32, ..letter.clone() }
+}
@@ -0,0 +1,512 @@
+use candid::{CandidType, Deserialize, Principal};
+use ed25519_dalek::{Signature, VerifyingKey};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::clock;
+use crate::hashing;
+use crate::honeytokens;
+use crate::replication;
+use crate::tenants;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ActivationCondition {
+    pub condition_type: String, // "AFTER_DATE", "DIAGNOSIS_RECORDED", "INCAPACITY_ATTESTED"
+    pub not_before: Option<u64>,
+    pub diagnosis_codes: Vec<String>,
+    pub required_attestations: u32,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DirectiveActivation {
+    pub patient_id_hash: Vec<u8>,
+    pub directive_type: String,
+    pub conditions: Vec<ActivationCondition>,
+    pub require_all: bool,
+    pub status: String, // "PENDING", "ACTIVE"
+    pub activated_at: Option<u64>,
+    pub activation_reason: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ActivationStatus {
+    pub directive_type: String,
+    pub active: bool,
+    pub satisfied_conditions: Vec<String>,
+    pub pending_conditions: Vec<String>,
+    pub attestation_status: Option<AttestationStatus>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AttestationStatus {
+    pub attestations: u32,
+    pub required: u32,
+    pub certified: bool,
+    pub attested_by: Vec<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct RegisteredPhysician {
+    pub principal: Principal,
+    pub name: String,
+    pub license_number: String,
+    pub public_key: Vec<u8>, // Ed25519; attestations are checked against it
+    pub registered_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct RecordedDiagnosis {
+    pub code: String,
+    pub recorded_by: Principal,
+    pub recorded_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct IncapacityAttestation {
+    pub physician: Principal,
+    pub signature: Vec<u8>,
+    pub attested_at: u64,
+}
+
+// Carried across upgrades with the hash key ring; losing it would leave every certified living will
+// reading as uncertified and reset every time- or condition-locked directive
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct ActivationState {
+    pub(crate) activations: Vec<DirectiveActivation>,
+    pub(crate) diagnoses: Vec<(Vec<u8>, Vec<RecordedDiagnosis>)>,
+    pub(crate) attestations: Vec<(Vec<u8>, Vec<IncapacityAttestation>)>,
+    pub(crate) physicians: Vec<RegisteredPhysician>,
+    pub(crate) required_attestations: u32,
+}
+
+thread_local! {
+    static DIRECTIVE_ACTIVATIONS: std::cell::RefCell<BTreeMap<(Vec<u8>, String), DirectiveActivation>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static RECORDED_DIAGNOSES: std::cell::RefCell<BTreeMap<Vec<u8>, Vec<RecordedDiagnosis>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static INCAPACITY_ATTESTATIONS: std::cell::RefCell<BTreeMap<Vec<u8>, Vec<IncapacityAttestation>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static REGISTERED_PHYSICIANS: std::cell::RefCell<BTreeMap<Principal, RegisteredPhysician>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    // Two-physician rule by default
+    static REQUIRED_INCAPACITY_ATTESTATIONS: std::cell::RefCell<u32> = std::cell::RefCell::new(2);
+}
+
+const CONDITION_TYPES: [&str; 3] = ["AFTER_DATE", "DIAGNOSIS_RECORDED", "INCAPACITY_ATTESTED"];
+
+// Domain separation for the bytes a physician signs when attesting incapacity
+const ATTESTATION_DOMAIN: &[u8] = b"echoledger-incapacity-attestation:";
+
+// Lock a directive behind activation conditions; date conditions get a timer.
+// Only the patient or their proxy decides when their own directive takes effect.
+#[ic_cdk::update]
+fn set_activation_conditions(
+    patient_id_hash: Vec<u8>,
+    directive_type: String,
+    conditions: Vec<ActivationCondition>,
+    require_all: bool
+) -> Result<ActivationStatus, String> {
+    if !tenants::manages_patient_hash(caller(), &patient_id_hash) {
+        return Err("Only the patient or their proxy may set activation conditions".to_string());
+    }
+    for condition in &conditions {
+        validate_condition(condition)?;
+    }
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+
+    let activation = DirectiveActivation {
+        patient_id_hash: patient_id_hash.clone(),
+        directive_type: directive_type.clone(),
+        conditions,
+        require_all,
+        status: "PENDING".to_string(),
+        activated_at: None,
+        activation_reason: None,
+    };
+
+    let now = clock::now();
+    for not_before in activation.conditions.iter().filter_map(|c| c.not_before) {
+        if not_before > now {
+            schedule_date_activation(patient_id_hash.clone(), directive_type.clone(), not_before - now);
+        }
+    }
+
+    DIRECTIVE_ACTIVATIONS.with(|activations| {
+        activations.borrow_mut().insert((patient_id_hash.clone(), directive_type.clone()), activation);
+    });
+
+    Ok(refresh_activation(&patient_id_hash, &directive_type))
+}
+
+// A recorded diagnosis can satisfy DIAGNOSIS_RECORDED conditions; registered physicians record them
+#[ic_cdk::update]
+fn record_diagnosis(patient_id_hash: Vec<u8>, code: String) -> Result<Vec<ActivationStatus>, String> {
+    if !is_registered_physician(&caller()) {
+        return Err("Only registered physicians may record diagnoses".to_string());
+    }
+    if code.trim().is_empty() {
+        return Err("A diagnosis code is required".to_string());
+    }
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+    RECORDED_DIAGNOSES.with(|diagnoses| {
+        diagnoses.borrow_mut().entry(patient_id_hash.clone()).or_default().push(RecordedDiagnosis {
+            code: code.trim().to_uppercase(),
+            recorded_by: caller(),
+            recorded_at: clock::now(),
+        });
+    });
+
+    Ok(refresh_patient_activations(&patient_id_hash))
+}
+
+// Registered physicians certify incapacity with a signature, under their registered key, over the
+// attestation domain followed by the patient hash they attest for
+#[ic_cdk::update]
+fn attest_incapacity(patient_id_hash: Vec<u8>, signature: Vec<u8>) -> Result<AttestationStatus, String> {
+    let physician = caller();
+    let public_key = REGISTERED_PHYSICIANS.with(|physicians| physicians.borrow().get(&physician).map(|p| p.public_key.clone()))
+        .ok_or("Only registered physicians may attest incapacity")?;
+    verify_attestation(&public_key, &patient_id_hash, &signature)?;
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+
+    INCAPACITY_ATTESTATIONS.with(|attestations| {
+        let mut attestations = attestations.borrow_mut();
+        let list = attestations.entry(patient_id_hash.clone()).or_default();
+        if list.iter().any(|a| a.physician == physician) {
+            return Err("Physician has already attested incapacity for this patient".to_string());
+        }
+        list.push(IncapacityAttestation { physician, signature, attested_at: clock::now() });
+        Ok(())
+    })?;
+
+    refresh_patient_activations(&patient_id_hash);
+    Ok(attestation_status(&patient_id_hash))
+}
+
+#[ic_cdk::update]
+fn register_physician(principal: Principal, name: String, license_number: String, public_key: Vec<u8>) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may register physicians".to_string());
+    }
+    if license_number.trim().is_empty() {
+        return Err("A license number is required".to_string());
+    }
+    parse_public_key(&public_key)?;
+
+    REGISTERED_PHYSICIANS.with(|physicians| {
+        physicians.borrow_mut().insert(principal, RegisteredPhysician {
+            principal,
+            name,
+            license_number,
+            public_key,
+            registered_at: clock::now(),
+        });
+    });
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn deregister_physician(principal: Principal) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may deregister physicians".to_string());
+    }
+    REGISTERED_PHYSICIANS.with(|physicians| physicians.borrow_mut().remove(&principal))
+        .map(|_| ())
+        .ok_or_else(|| "Physician not registered".to_string())
+}
+
+#[ic_cdk::update]
+fn set_required_incapacity_attestations(required: u32) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may change the attestation rule".to_string());
+    }
+    if required == 0 {
+        return Err("At least one attestation must be required".to_string());
+    }
+    REQUIRED_INCAPACITY_ATTESTATIONS.with(|r| *r.borrow_mut() = required);
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn get_incapacity_attestation_status(patient_id_hash: Vec<u8>) -> AttestationStatus {
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+    honeytokens::touch_hash(&patient_id_hash, caller(), "Incapacity attestation read");
+    attestation_status(&patient_id_hash)
+}
+
+// Evaluation hook used by emergency_bridge; unlocked directives are always active
+#[ic_cdk::update]
+fn evaluate_activation(patient_id_hash: Vec<u8>, directive_type: String) -> ActivationStatus {
+    honeytokens::touch_hash(&hashing::storage_key(&patient_id_hash), caller(), "Activation evaluated");
+    activation_status(patient_id_hash, directive_type)
+}
+
+pub(crate) fn activation_status(patient_id_hash: Vec<u8>, directive_type: String) -> ActivationStatus {
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+    let activation = DIRECTIVE_ACTIVATIONS.with(|activations| {
+        activations.borrow().get(&(patient_id_hash.clone(), directive_type.clone())).cloned()
+    });
+
+    evaluate(&activation.unwrap_or_else(|| unconfigured(patient_id_hash, directive_type)))
+}
+
+#[ic_cdk::update]
+fn get_directive_activation(patient_id_hash: Vec<u8>, directive_type: String) -> Option<DirectiveActivation> {
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+    honeytokens::touch_hash(&patient_id_hash, caller(), "Activation conditions read");
+    DIRECTIVE_ACTIVATIONS.with(|activations| activations.borrow().get(&(patient_id_hash, directive_type)).cloned())
+}
+
+// A directive with no conditions on file; living wills still wait on attestation
+fn unconfigured(patient_id_hash: Vec<u8>, directive_type: String) -> DirectiveActivation {
+    DirectiveActivation {
+        patient_id_hash,
+        directive_type,
+        conditions: vec![],
+        require_all: true,
+        status: "PENDING".to_string(),
+        activated_at: None,
+        activation_reason: None,
+    }
+}
+
+fn parse_public_key(public_key: &[u8]) -> Result<VerifyingKey, String> {
+    let bytes: [u8; 32] = public_key.try_into()
+        .map_err(|_| "Physician public keys are 32-byte Ed25519 keys".to_string())?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| "Invalid Ed25519 public key".to_string())
+}
+
+fn verify_attestation(public_key: &[u8], patient_id_hash: &[u8], signature: &[u8]) -> Result<(), String> {
+    let key = parse_public_key(public_key)?;
+    let signature = Signature::from_slice(signature).map_err(|_| "Attestation must carry a 64-byte Ed25519 signature".to_string())?;
+    let message = [ATTESTATION_DOMAIN, patient_id_hash].concat();
+    key.verify_strict(&message, &signature)
+        .map_err(|_| "Attestation signature does not verify against the physician's registered key".to_string())
+}
+
+fn validate_condition(condition: &ActivationCondition) -> Result<(), String> {
+    match condition.condition_type.as_str() {
+        "AFTER_DATE" if condition.not_before.is_none() => {
+            Err("AFTER_DATE conditions require not_before".to_string())
+        },
+        "DIAGNOSIS_RECORDED" if condition.diagnosis_codes.is_empty() => {
+            Err("DIAGNOSIS_RECORDED conditions require at least one diagnosis code".to_string())
+        },
+        "INCAPACITY_ATTESTED" if condition.required_attestations == 0 => {
+            Err("INCAPACITY_ATTESTED conditions require at least one attestation".to_string())
+        },
+        t if !CONDITION_TYPES.contains(&t) => Err(format!("Unknown activation condition: {}", t)),
+        _ => Ok(()),
+    }
+}
+
+// Living wills take effect on certified incapacity, whatever else the patient configured
+fn effective_conditions(activation: &DirectiveActivation) -> Vec<ActivationCondition> {
+    let mut conditions = activation.conditions.clone();
+    if activation.directive_type == "LIVING_WILL" {
+        conditions.push(ActivationCondition {
+            condition_type: "INCAPACITY_ATTESTED".to_string(),
+            not_before: None,
+            diagnosis_codes: vec![],
+            required_attestations: required_attestations(),
+        });
+    }
+    conditions
+}
+
+fn evaluate(activation: &DirectiveActivation) -> ActivationStatus {
+    let mut satisfied = Vec::new();
+    let mut pending = Vec::new();
+    let conditions = effective_conditions(activation);
+    let is_living_will = activation.directive_type == "LIVING_WILL";
+    let mut incapacity_pending = false;
+
+    for condition in &conditions {
+        let label = describe_condition(condition);
+        if condition_met(&activation.patient_id_hash, condition) {
+            satisfied.push(label);
+        } else {
+            incapacity_pending |= is_living_will && condition.condition_type == "INCAPACITY_ATTESTED";
+            pending.push(label);
+        }
+    }
+
+    let conditions_hold = if activation.require_all {
+        pending.is_empty()
+    } else {
+        !satisfied.is_empty() || conditions.is_empty()
+    };
+    let active = (activation.status == "ACTIVE" || conditions_hold) && !incapacity_pending;
+
+    let uses_attestation = conditions.iter().any(|c| c.condition_type == "INCAPACITY_ATTESTED");
+    ActivationStatus {
+        directive_type: activation.directive_type.clone(),
+        active,
+        satisfied_conditions: satisfied,
+        pending_conditions: pending,
+        attestation_status: uses_attestation.then(|| attestation_status(&activation.patient_id_hash)),
+    }
+}
+
+fn condition_met(patient_id_hash: &[u8], condition: &ActivationCondition) -> bool {
+    match condition.condition_type.as_str() {
+        "AFTER_DATE" => condition.not_before.map_or(false, |t| clock::now() >= t),
+        "DIAGNOSIS_RECORDED" => RECORDED_DIAGNOSES.with(|diagnoses| {
+            diagnoses.borrow().get(patient_id_hash).map_or(false, |recorded| {
+                recorded.iter().any(|d| {
+                    condition.diagnosis_codes.iter().any(|c| c.trim().eq_ignore_ascii_case(&d.code))
+                })
+            })
+        }),
+        "INCAPACITY_ATTESTED" => attestation_count(patient_id_hash) >= condition.required_attestations,
+        _ => false,
+    }
+}
+
+fn describe_condition(condition: &ActivationCondition) -> String {
+    match condition.condition_type.as_str() {
+        "AFTER_DATE" => format!("AFTER_DATE {}", condition.not_before.unwrap_or_default()),
+        "DIAGNOSIS_RECORDED" => format!("DIAGNOSIS_RECORDED {}", condition.diagnosis_codes.join("|")),
+        "INCAPACITY_ATTESTED" => format!("INCAPACITY_ATTESTED x{}", condition.required_attestations),
+        other => other.to_string(),
+    }
+}
+
+// Only attestations from physicians who are still registered count toward certification
+pub(crate) fn attestation_count(patient_id_hash: &[u8]) -> u32 {
+    attestation_status(patient_id_hash).attestations
+}
+
+fn attestation_status(patient_id_hash: &[u8]) -> AttestationStatus {
+    let attested_by: Vec<Principal> = INCAPACITY_ATTESTATIONS.with(|attestations| {
+        attestations.borrow().get(patient_id_hash).map_or(vec![], |list| {
+            list.iter().map(|a| a.physician).collect()
+        })
+    });
+    let attested_by: Vec<Principal> = REGISTERED_PHYSICIANS.with(|physicians| {
+        let physicians = physicians.borrow();
+        attested_by.into_iter().filter(|p| physicians.contains_key(p)).collect()
+    });
+    let required = required_attestations();
+
+    AttestationStatus {
+        attestations: attested_by.len() as u32,
+        required,
+        certified: attested_by.len() as u32 >= required,
+        attested_by,
+    }
+}
+
+fn is_registered_physician(principal: &Principal) -> bool {
+    REGISTERED_PHYSICIANS.with(|physicians| physicians.borrow().contains_key(principal))
+}
+
+fn required_attestations() -> u32 {
+    REQUIRED_INCAPACITY_ATTESTATIONS.with(|r| *r.borrow())
+}
+
+// Persist activation once conditions hold, so later evaluations don't depend on re-checking
+fn refresh_activation(patient_id_hash: &[u8], directive_type: &str) -> ActivationStatus {
+    let status = DIRECTIVE_ACTIVATIONS.with(|activations| {
+        let mut activations = activations.borrow_mut();
+        let Some(activation) = activations.get_mut(&(patient_id_hash.to_vec(), directive_type.to_string())) else {
+            return evaluate(&unconfigured(patient_id_hash.to_vec(), directive_type.to_string()));
+        };
+
+        let status = evaluate(activation);
+        if status.active && activation.status != "ACTIVE" {
+            activation.status = "ACTIVE".to_string();
+            activation.activated_at = Some(clock::now());
+            activation.activation_reason = Some(status.satisfied_conditions.join(", "));
+            ic_cdk::println!("🔓 Directive activated: {} ({})", directive_type, status.satisfied_conditions.join(", "));
+        }
+        status
+    });
+    replication::mark_patient_changed(patient_id_hash);
+    status
+}
+
+fn refresh_patient_activations(patient_id_hash: &[u8]) -> Vec<ActivationStatus> {
+    let directive_types: Vec<String> = DIRECTIVE_ACTIVATIONS.with(|activations| {
+        activations.borrow()
+            .keys()
+            .filter(|(hash, _)| hash == patient_id_hash)
+            .map(|(_, directive_type)| directive_type.clone())
+            .collect()
+    });
+
+    directive_types.iter()
+        .map(|directive_type| refresh_activation(patient_id_hash, directive_type))
+        .collect()
+}
+
+fn schedule_date_activation(patient_id_hash: Vec<u8>, directive_type: String, delay_ns: u64) {
+    ic_cdk_timers::set_timer(Duration::from_nanos(delay_ns), move || {
+        // The patient may have been re-keyed while the timer was pending
+        refresh_activation(&hashing::storage_key(&patient_id_hash), &directive_type);
+    });
+}
+
+pub(crate) fn snapshot() -> ActivationState {
+    ActivationState {
+        activations: DIRECTIVE_ACTIVATIONS.with(|a| a.borrow().values().cloned().collect()),
+        diagnoses: RECORDED_DIAGNOSES.with(|d| d.borrow().clone().into_iter().collect()),
+        attestations: INCAPACITY_ATTESTATIONS.with(|a| a.borrow().clone().into_iter().collect()),
+        physicians: REGISTERED_PHYSICIANS.with(|p| p.borrow().values().cloned().collect()),
+        required_attestations: required_attestations(),
+    }
+}
+
+pub(crate) fn restore(state: Option<ActivationState>) {
+    let Some(state) = state else {
+        return;
+    };
+    DIRECTIVE_ACTIVATIONS.with(|a| {
+        *a.borrow_mut() = state.activations.into_iter()
+            .map(|x| ((x.patient_id_hash.clone(), x.directive_type.clone()), x))
+            .collect();
+    });
+    RECORDED_DIAGNOSES.with(|d| *d.borrow_mut() = state.diagnoses.into_iter().collect());
+    INCAPACITY_ATTESTATIONS.with(|a| *a.borrow_mut() = state.attestations.into_iter().collect());
+    REGISTERED_PHYSICIANS.with(|p| *p.borrow_mut() = state.physicians.into_iter().map(|x| (x.principal, x)).collect());
+    REQUIRED_INCAPACITY_ATTESTATIONS.with(|r| *r.borrow_mut() = state.required_attestations);
+}
+
+// Timers do not survive an upgrade; date conditions still ahead are scheduled again, and any that
+// came due while the canister was upgrading are evaluated now
+pub(crate) fn ensure_date_timers() {
+    let pending: Vec<(Vec<u8>, String, Vec<u64>)> = DIRECTIVE_ACTIVATIONS.with(|activations| {
+        activations.borrow()
+            .values()
+            .filter(|a| a.status != "ACTIVE")
+            .map(|a| (a.patient_id_hash.clone(), a.directive_type.clone(), a.conditions.iter().filter_map(|c| c.not_before).collect()))
+            .collect()
+    });
+    let now = clock::now();
+    for (patient_id_hash, directive_type, dates) in pending {
+        for not_before in dates {
+            schedule_date_activation(patient_id_hash.clone(), directive_type.clone(), not_before.saturating_sub(now));
+        }
+    }
+}
+
+pub(crate) fn rekey_patient(old_hash: &[u8], new_hash: &[u8]) {
+    DIRECTIVE_ACTIVATIONS.with(|activations| {
+        let mut activations = activations.borrow_mut();
+        let keys: Vec<(Vec<u8>, String)> = activations.keys().filter(|(hash, _)| hash == old_hash).cloned().collect();
+        for key in keys {
+            if let Some(mut activation) = activations.remove(&key) {
+                activation.patient_id_hash = new_hash.to_vec();
+                activations.insert((new_hash.to_vec(), key.1), activation);
+            }
+        }
+    });
+    RECORDED_DIAGNOSES.with(|diagnoses| hashing::rekey_entry(&mut diagnoses.borrow_mut(), old_hash, new_hash));
+    INCAPACITY_ATTESTATIONS.with(|attestations| hashing::rekey_entry(&mut attestations.borrow_mut(), old_hash, new_hash));
+}
@@ -0,0 +1,341 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+
+use crate::{clock, ids};
+
+// Who administers this canister. The council is seeded at install (the installing controller unless
+// the install argument names one) and destructive operations need M of its N admins to approve
+// before they run. A lost admin key is replaced by the rest of the council, with controllers
+// allowed to co-sign, so recovery never requires a redeploy.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AdminBootstrap {
+    pub admins: Vec<Principal>,
+    pub approval_threshold: u32,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AdminCouncil {
+    pub admins: Vec<Principal>,
+    pub approval_threshold: u32,
+    pub updated_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub enum AdminOperation {
+    RotatePatientHashKey,
+    RetirePatientHashKey { version: u32 },
+    SetArchiveCanister { archive: Principal },
+    AddAdmin { admin: Principal },
+    RemoveAdmin { admin: Principal },
+    ReplaceAdmin { lost: Principal, replacement: Principal },
+    SetApprovalThreshold { approval_threshold: u32 },
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AdminProposal {
+    pub proposal_id: String,
+    pub operation: AdminOperation,
+    pub proposed_by: Principal,
+    pub proposed_at: u64,
+    pub expires_at: u64,
+    pub approvals: Vec<Principal>,
+    pub status: String, // "PENDING", "APPROVED", "EXECUTED", "EXPIRED"
+    pub executed_at: Option<u64>,
+}
+
+// Carried across upgrades alongside the hash key ring
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct AdminState {
+    council: AdminCouncil,
+    proposals: Vec<AdminProposal>,
+}
+
+const PROPOSAL_TTL_NS: u64 = 72 * 60 * 60 * 1_000_000_000;
+const MAX_ADMINS: usize = 16;
+const MAX_PROPOSALS: usize = 256;
+
+thread_local! {
+    static COUNCIL: std::cell::RefCell<Option<AdminCouncil>> = const { std::cell::RefCell::new(None) };
+
+    static PROPOSALS: std::cell::RefCell<Vec<AdminProposal>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+// Without an argument the installing controller becomes the sole admin
+#[ic_cdk::init]
+fn init(bootstrap: Option<AdminBootstrap>) {
+    let bootstrap = bootstrap.unwrap_or_else(|| AdminBootstrap { admins: vec![caller()], approval_threshold: 1 });
+    let council = valid_council(bootstrap.admins, bootstrap.approval_threshold)
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("Invalid admin bootstrap: {}", e)));
+    audit("BOOTSTRAP", &council);
+    COUNCIL.with(|c| *c.borrow_mut() = Some(council));
+}
+
+#[ic_cdk::update]
+fn propose_admin_operation(operation: AdminOperation) -> Result<AdminProposal, String> {
+    let proposer = caller();
+    let may_propose = is_admin(proposer) || (is_recovery(&operation) && ic_cdk::api::is_controller(&proposer));
+    if !may_propose {
+        return Err("Only admins may propose admin operations".to_string());
+    }
+    validate(&operation)?;
+    let now = clock::now();
+    let proposal = AdminProposal {
+        proposal_id: ids::new_id("ADMINOP"),
+        operation,
+        proposed_by: proposer,
+        proposed_at: now,
+        expires_at: now + PROPOSAL_TTL_NS,
+        approvals: Vec::new(),
+        status: "PENDING".to_string(),
+        executed_at: None,
+    };
+    PROPOSALS.with(|p| {
+        let mut proposals = p.borrow_mut();
+        expire(&mut proposals, now);
+        if proposals.len() >= MAX_PROPOSALS {
+            if let Some(oldest) = proposals.iter().position(|p| p.status != "PENDING" && p.status != "APPROVED") {
+                proposals.remove(oldest);
+            }
+        }
+        proposals.push(proposal.clone());
+    });
+    ic_cdk::println!("AUDIT: Admin operation {:?} proposed by {} as {}", proposal.operation, proposer, proposal.proposal_id);
+    approve_admin_operation(proposal.proposal_id)
+}
+
+// The approval that reaches the threshold applies council changes at once; other operations are
+// then run by any admin through their own endpoint
+#[ic_cdk::update]
+fn approve_admin_operation(proposal_id: String) -> Result<AdminProposal, String> {
+    let approver = caller();
+    let now = clock::now();
+    let proposal = PROPOSALS.with(|p| {
+        let mut proposals = p.borrow_mut();
+        expire(&mut proposals, now);
+        let proposal = proposals.iter_mut().find(|p| p.proposal_id == proposal_id)
+            .ok_or_else(|| format!("Unknown proposal: {}", proposal_id))?;
+        if proposal.status != "PENDING" {
+            return Err(format!("Proposal {} is {}", proposal_id, proposal.status));
+        }
+        if !may_approve(approver, &proposal.operation) {
+            return Err("Only admins may approve admin operations".to_string());
+        }
+        if !proposal.approvals.contains(&approver) {
+            proposal.approvals.push(approver);
+        }
+        if proposal.approvals.len() as u32 >= required_approvals(&proposal.operation) {
+            proposal.status = "APPROVED".to_string();
+        }
+        Ok(proposal.clone())
+    })?;
+    ic_cdk::println!("AUDIT: Admin operation {} approved by {} ({})", proposal_id, approver, proposal.status);
+
+    if proposal.status == "APPROVED" && is_council_change(&proposal.operation) {
+        apply_council_change(&proposal.operation)?;
+        return mark_executed(&proposal_id);
+    }
+    Ok(proposal)
+}
+
+#[ic_cdk::query]
+fn get_admin_council() -> Result<AdminCouncil, String> {
+    if !is_admin(caller()) && !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only admins and controllers may read the admin council".to_string());
+    }
+    COUNCIL.with(|c| c.borrow().clone()).ok_or_else(|| "No admin council has been bootstrapped".to_string())
+}
+
+#[ic_cdk::query]
+fn get_admin_proposals() -> Result<Vec<AdminProposal>, String> {
+    if !is_admin(caller()) && !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only admins and controllers may read admin proposals".to_string());
+    }
+    Ok(PROPOSALS.with(|p| p.borrow().clone()))
+}
+
+// Gate for destructive endpoints: the caller must be an admin and, on a council with more than
+// one required approval, an approved proposal for exactly this operation is used up
+pub(crate) fn authorize(operation: AdminOperation) -> Result<(), String> {
+    let requester = caller();
+    if !is_admin(requester) {
+        return Err("Only admins may perform this operation".to_string());
+    }
+    if required_approvals(&operation) <= 1 {
+        ic_cdk::println!("AUDIT: Admin operation {:?} performed by {}", operation, requester);
+        return Ok(());
+    }
+    let now = clock::now();
+    let proposal_id = PROPOSALS.with(|p| {
+        let mut proposals = p.borrow_mut();
+        expire(&mut proposals, now);
+        proposals.iter().find(|p| p.status == "APPROVED" && p.operation == operation).map(|p| p.proposal_id.clone())
+    }).ok_or_else(|| format!("{:?} needs an approved admin proposal", operation))?;
+    mark_executed(&proposal_id)?;
+    ic_cdk::println!("AUDIT: Admin operation {:?} performed by {} under {}", operation, requester, proposal_id);
+    Ok(())
+}
+
+pub(crate) fn is_admin(principal: Principal) -> bool {
+    COUNCIL.with(|c| c.borrow().as_ref().is_some_and(|council| council.admins.contains(&principal)))
+}
+
+pub(crate) fn snapshot() -> AdminState {
+    AdminState {
+        council: COUNCIL.with(|c| c.borrow().clone()).unwrap_or_else(|| AdminCouncil {
+            admins: Vec::new(),
+            approval_threshold: 1,
+            updated_at: clock::now(),
+        }),
+        proposals: PROPOSALS.with(|p| p.borrow().clone()),
+    }
+}
+
+// Canisters installed before the council existed adopt the controller running the upgrade
+pub(crate) fn restore(state: Option<AdminState>) {
+    let state = state.filter(|s| !s.council.admins.is_empty()).unwrap_or_else(|| {
+        let council = AdminCouncil { admins: vec![caller()], approval_threshold: 1, updated_at: clock::now() };
+        audit("BOOTSTRAP", &council);
+        AdminState { council, proposals: Vec::new() }
+    });
+    COUNCIL.with(|c| *c.borrow_mut() = Some(state.council));
+    PROPOSALS.with(|p| *p.borrow_mut() = state.proposals);
+}
+
+fn may_approve(principal: Principal, operation: &AdminOperation) -> bool {
+    if is_recovery(operation) {
+        let lost = match operation {
+            AdminOperation::ReplaceAdmin { lost, .. } => *lost,
+            _ => Principal::anonymous(),
+        };
+        return principal != lost && (is_admin(principal) || ic_cdk::api::is_controller(&principal));
+    }
+    is_admin(principal)
+}
+
+// Recovery cannot wait on the key that was lost, so it needs one approval fewer when that key is an admin
+fn required_approvals(operation: &AdminOperation) -> u32 {
+    let (admins, threshold) = COUNCIL.with(|c| {
+        c.borrow().as_ref().map_or((0, 1), |council| (council.admins.len() as u32, council.approval_threshold))
+    });
+    match operation {
+        AdminOperation::ReplaceAdmin { .. } => threshold.min(admins.saturating_sub(1)).max(1),
+        _ => threshold,
+    }
+}
+
+fn is_recovery(operation: &AdminOperation) -> bool {
+    matches!(operation, AdminOperation::ReplaceAdmin { .. })
+}
+
+fn is_council_change(operation: &AdminOperation) -> bool {
+    matches!(
+        operation,
+        AdminOperation::AddAdmin { .. }
+            | AdminOperation::RemoveAdmin { .. }
+            | AdminOperation::ReplaceAdmin { .. }
+            | AdminOperation::SetApprovalThreshold { .. }
+    )
+}
+
+fn validate(operation: &AdminOperation) -> Result<(), String> {
+    let council = COUNCIL.with(|c| c.borrow().clone()).ok_or("No admin council has been bootstrapped")?;
+    if is_council_change(operation) {
+        let (admins, threshold) = changed_council(&council, operation)?;
+        valid_council(admins, threshold)?;
+    }
+    Ok(())
+}
+
+fn apply_council_change(operation: &AdminOperation) -> Result<(), String> {
+    let council = COUNCIL.with(|c| c.borrow().clone()).ok_or("No admin council has been bootstrapped")?;
+    let (admins, threshold) = changed_council(&council, operation)?;
+    let council = valid_council(admins, threshold)?;
+    audit("CHANGED", &council);
+    COUNCIL.with(|c| *c.borrow_mut() = Some(council));
+
+    // Approvals given by a removed key no longer count toward anything still open
+    if let AdminOperation::RemoveAdmin { admin: gone } | AdminOperation::ReplaceAdmin { lost: gone, .. } = operation {
+        PROPOSALS.with(|p| {
+            for proposal in p.borrow_mut().iter_mut().filter(|p| p.status == "PENDING") {
+                proposal.approvals.retain(|a| a != gone);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn changed_council(council: &AdminCouncil, operation: &AdminOperation) -> Result<(Vec<Principal>, u32), String> {
+    let mut admins = council.admins.clone();
+    let mut threshold = council.approval_threshold;
+    match operation {
+        AdminOperation::AddAdmin { admin } => {
+            if admins.contains(admin) {
+                return Err(format!("{} is already an admin", admin));
+            }
+            admins.push(*admin);
+        }
+        AdminOperation::RemoveAdmin { admin } => {
+            admins.retain(|a| a != admin);
+            threshold = threshold.min(admins.len() as u32);
+        }
+        AdminOperation::ReplaceAdmin { lost, replacement } => {
+            let slot = admins.iter().position(|a| a == lost).ok_or_else(|| format!("{} is not an admin", lost))?;
+            if admins.contains(replacement) {
+                return Err(format!("{} is already an admin", replacement));
+            }
+            admins[slot] = *replacement;
+        }
+        AdminOperation::SetApprovalThreshold { approval_threshold } => threshold = *approval_threshold,
+        _ => {}
+    }
+    if admins == council.admins && threshold == council.approval_threshold {
+        return Err("Operation does not change the admin council".to_string());
+    }
+    Ok((admins, threshold))
+}
+
+fn valid_council(mut admins: Vec<Principal>, approval_threshold: u32) -> Result<AdminCouncil, String> {
+    admins.sort();
+    admins.dedup();
+    if admins.is_empty() || admins.len() > MAX_ADMINS {
+        return Err(format!("The council needs between 1 and {} admins", MAX_ADMINS));
+    }
+    if admins.contains(&Principal::anonymous()) {
+        return Err("The anonymous principal cannot be an admin".to_string());
+    }
+    if approval_threshold == 0 || approval_threshold as usize > admins.len() {
+        return Err(format!("approval_threshold must be between 1 and {}", admins.len()));
+    }
+    Ok(AdminCouncil { admins, approval_threshold, updated_at: clock::now() })
+}
+
+fn mark_executed(proposal_id: &str) -> Result<AdminProposal, String> {
+    PROPOSALS.with(|p| {
+        let mut proposals = p.borrow_mut();
+        let proposal = proposals.iter_mut().find(|p| p.proposal_id == proposal_id)
+            .ok_or_else(|| format!("Unknown proposal: {}", proposal_id))?;
+        proposal.status = "EXECUTED".to_string();
+        proposal.executed_at = Some(clock::now());
+        Ok(proposal.clone())
+    })
+}
+
+fn expire(proposals: &mut [AdminProposal], now: u64) {
+    for proposal in proposals.iter_mut().filter(|p| p.status == "PENDING" || p.status == "APPROVED") {
+        if now >= proposal.expires_at {
+            proposal.status = "EXPIRED".to_string();
+        }
+    }
+}
+
+fn audit(action: &str, council: &AdminCouncil) {
+    let admins: Vec<String> = council.admins.iter().map(|a| a.to_text()).collect();
+    ic_cdk::println!(
+        "AUDIT: Admin council {}: {} of [{}]",
+        action,
+        council.approval_threshold,
+        admins.join(", ")
+    );
+}
@@ -0,0 +1,243 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use crate::emergency::EmergencyAccessLog;
+use crate::{clock, compliance, tenants, PATIENT_HASH_INDEX};
+
+// Watches the emergency access log for requesters behaving unlike a hospital treating its own
+// patients: reaching for many different patients in a short time, failing verification again and
+// again, or reading patients enrolled in another jurisdiction. A rule that trips opens a compliance
+// incident and, unless the rule only alerts, throttles the requester's emergency lookups until the
+// throttle runs out or a controller lifts it.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AnomalyRule {
+    pub rule_id: String, // One of RULE_IDS
+    pub enabled: bool,
+    pub threshold: u32,
+    pub window_minutes: u32,
+    pub throttle_minutes: u32, // 0 alerts without throttling
+    pub severity: String, // "LOW", "MEDIUM", "HIGH", "CRITICAL"
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AccessThrottle {
+    pub principal: Principal,
+    pub rule_id: String,
+    pub incident_id: String,
+    pub throttled_at: u64,
+    pub throttled_until: u64,
+}
+
+struct AccessSample {
+    at: u64,
+    patient_id_hash: Vec<u8>,
+    denied: bool,
+    out_of_region: bool,
+}
+
+const RULE_IDS: [&str; 3] = ["PATIENT_SPREAD", "FAILED_VERIFICATIONS", "OUT_OF_REGION"];
+const SEVERITIES: [&str; 4] = ["LOW", "MEDIUM", "HIGH", "CRITICAL"];
+const MAX_WINDOW_MINUTES: u32 = 24 * 60;
+const MAX_THROTTLE_MINUTES: u32 = 7 * 24 * 60;
+const MAX_SAMPLES_PER_PRINCIPAL: usize = 5_000;
+const MAX_TRACKED_PRINCIPALS: usize = 10_000;
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+
+thread_local! {
+    static RULES: std::cell::RefCell<BTreeMap<String, AnomalyRule>> = std::cell::RefCell::new(default_rules());
+
+    static SAMPLES: std::cell::RefCell<BTreeMap<Principal, VecDeque<AccessSample>>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    static THROTTLES: std::cell::RefCell<BTreeMap<Principal, AccessThrottle>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    // (principal, rule) -> when it last opened an incident, so one burst opens one incident
+    static LAST_TRIPPED: std::cell::RefCell<BTreeMap<(Principal, String), u64>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+}
+
+fn default_rules() -> BTreeMap<String, AnomalyRule> {
+    [
+        ("PATIENT_SPREAD", 200, 60, 60, "HIGH"),
+        ("FAILED_VERIFICATIONS", 10, 15, 30, "MEDIUM"),
+        // Patients travel; out-of-region reads alert but never block on their own
+        ("OUT_OF_REGION", 20, 60, 0, "MEDIUM"),
+    ]
+    .into_iter()
+    .map(|(rule_id, threshold, window_minutes, throttle_minutes, severity)| {
+        (rule_id.to_string(), AnomalyRule {
+            rule_id: rule_id.to_string(),
+            enabled: true,
+            threshold,
+            window_minutes,
+            throttle_minutes,
+            severity: severity.to_string(),
+        })
+    })
+    .collect()
+}
+
+#[ic_cdk::update]
+fn set_anomaly_rule(rule: AnomalyRule) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may configure anomaly rules".to_string());
+    }
+    if !RULE_IDS.contains(&rule.rule_id.as_str()) {
+        return Err(format!("Unknown anomaly rule: {}", rule.rule_id));
+    }
+    if !SEVERITIES.contains(&rule.severity.as_str()) {
+        return Err(format!("severity must be one of {}", SEVERITIES.join(", ")));
+    }
+    if rule.threshold == 0 {
+        return Err("threshold must be at least 1".to_string());
+    }
+    if rule.window_minutes == 0 || rule.window_minutes > MAX_WINDOW_MINUTES {
+        return Err(format!("window_minutes must be between 1 and {}", MAX_WINDOW_MINUTES));
+    }
+    if rule.throttle_minutes > MAX_THROTTLE_MINUTES {
+        return Err(format!("throttle_minutes may be at most {}", MAX_THROTTLE_MINUTES));
+    }
+    RULES.with(|r| r.borrow_mut().insert(rule.rule_id.clone(), rule));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_anomaly_rules() -> Vec<AnomalyRule> {
+    RULES.with(|r| r.borrow().values().cloned().collect())
+}
+
+#[ic_cdk::query]
+fn get_access_throttles() -> Result<Vec<AccessThrottle>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read access throttles".to_string());
+    }
+    let now = clock::now();
+    Ok(THROTTLES.with(|t| t.borrow().values().filter(|x| x.throttled_until > now).cloned().collect()))
+}
+
+// For a false positive; the incident stays open for the record
+#[ic_cdk::update]
+fn lift_access_throttle(principal: Principal) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may lift access throttles".to_string());
+    }
+    THROTTLES.with(|t| t.borrow_mut().remove(&principal)).ok_or("This principal is not throttled")?;
+    SAMPLES.with(|s| s.borrow_mut().remove(&principal));
+    ic_cdk::println!("AUDIT: Access throttle on {} lifted by {}", principal.to_text(), caller().to_text());
+    Ok(())
+}
+
+// Emergency lookups by a throttled requester are refused before the token is checked
+pub(crate) fn check_throttle(requester: Principal) -> Result<(), String> {
+    let now = clock::now();
+    match THROTTLES.with(|t| t.borrow().get(&requester).cloned()) {
+        Some(throttle) if throttle.throttled_until > now => Err(format!(
+            "Emergency lookups by this requester are throttled pending review of incident {}",
+            throttle.incident_id
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Called for every logged access
+pub(crate) fn observe(entry: &EmergencyAccessLog) {
+    let rules: Vec<AnomalyRule> = RULES.with(|r| r.borrow().values().filter(|x| x.enabled).cloned().collect());
+    let longest_window = rules.iter().map(|r| r.window_minutes).max().unwrap_or(0) as u64 * NANOS_PER_MINUTE;
+    if longest_window == 0 {
+        return;
+    }
+    let sample = AccessSample {
+        at: entry.accessed_at,
+        patient_id_hash: entry.patient_id_hash.clone(),
+        denied: entry.outcome == "DENIED",
+        out_of_region: is_out_of_region(entry.requester, &entry.patient_id_hash),
+    };
+
+    let tripped: Vec<(AnomalyRule, u32)> = SAMPLES.with(|s| {
+        let mut samples = s.borrow_mut();
+        if samples.len() >= MAX_TRACKED_PRINCIPALS && !samples.contains_key(&entry.requester) {
+            // Requesters whose newest access is oldest have the least left to say
+            let quietest = samples.iter().min_by_key(|(_, q)| q.back().map_or(0, |x| x.at)).map(|(p, _)| *p);
+            if let Some(principal) = quietest {
+                samples.remove(&principal);
+            }
+        }
+        let history = samples.entry(entry.requester).or_default();
+        history.push_back(sample);
+        let cutoff = entry.accessed_at.saturating_sub(longest_window);
+        while history.front().is_some_and(|x| x.at < cutoff) || history.len() > MAX_SAMPLES_PER_PRINCIPAL {
+            history.pop_front();
+        }
+
+        rules.into_iter().filter_map(|rule| {
+            let since = entry.accessed_at.saturating_sub(rule.window_minutes as u64 * NANOS_PER_MINUTE);
+            let recent = history.iter().filter(|x| x.at >= since);
+            let observed = match rule.rule_id.as_str() {
+                "PATIENT_SPREAD" => recent.map(|x| &x.patient_id_hash).collect::<BTreeSet<_>>().len() as u32,
+                "FAILED_VERIFICATIONS" => recent.filter(|x| x.denied).count() as u32,
+                "OUT_OF_REGION" => recent.filter(|x| x.out_of_region).count() as u32,
+                _ => 0,
+            };
+            (observed >= rule.threshold).then_some((rule, observed))
+        }).collect()
+    });
+
+    for (rule, observed) in tripped {
+        trip(entry.requester, &rule, observed, entry.accessed_at);
+    }
+}
+
+fn trip(requester: Principal, rule: &AnomalyRule, observed: u32, at: u64) {
+    let window = rule.window_minutes as u64 * NANOS_PER_MINUTE;
+    let key = (requester, rule.rule_id.clone());
+    let recently_tripped = LAST_TRIPPED.with(|l| l.borrow().get(&key).is_some_and(|last| at < last.saturating_add(window)));
+    if recently_tripped {
+        return;
+    }
+    LAST_TRIPPED.with(|l| l.borrow_mut().insert(key, at));
+
+    let throttled = rule.throttle_minutes > 0;
+    let incident_id = compliance::open_incident(
+        "ACCESS_ANOMALY",
+        &rule.severity,
+        Some(requester),
+        format!("{} tripped by {}", rule.rule_id, requester.to_text()),
+        format!(
+            "{} observed against a threshold of {} within {} minutes{}",
+            observed,
+            rule.threshold,
+            rule.window_minutes,
+            if throttled { format!("; emergency lookups throttled for {} minutes", rule.throttle_minutes) } else { String::new() }
+        ),
+    );
+    if throttled {
+        let throttled_until = at.saturating_add(rule.throttle_minutes as u64 * NANOS_PER_MINUTE);
+        THROTTLES.with(|t| {
+            let mut throttles = t.borrow_mut();
+            // A second rule tripping never shortens a throttle already in place
+            if throttles.get(&requester).is_none_or(|x| x.throttled_until < throttled_until) {
+                throttles.insert(requester, AccessThrottle {
+                    principal: requester,
+                    rule_id: rule.rule_id.clone(),
+                    incident_id,
+                    throttled_at: at,
+                    throttled_until,
+                });
+            }
+        });
+    }
+}
+
+// The requester's tenant jurisdiction against the patient's; unknown on either side is not anomalous
+fn is_out_of_region(requester: Principal, patient_id_hash: &[u8]) -> bool {
+    let Some(membership) = tenants::membership_of(requester) else {
+        return false;
+    };
+    let patient_tenant = PATIENT_HASH_INDEX.with(|index| index.borrow().get(patient_id_hash).cloned())
+        .and_then(|patient_id| tenants::patient_tenant(&patient_id));
+    patient_tenant.is_some_and(|tenant_id| tenants::effective_config(&tenant_id).jurisdiction != membership.jurisdiction)
+}
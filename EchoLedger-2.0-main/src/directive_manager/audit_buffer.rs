@@ -0,0 +1,245 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::clock;
+use crate::emergency::EmergencyAccessLog;
+use crate::storage::{self, Store};
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AuditBufferStatus {
+    pub buffered_entries: u64,
+    pub oldest_buffered_at: Option<u64>,
+    pub flushed_entries: u64,
+    pub flushed_batches: u64,
+    pub last_flush_at: Option<u64>,
+    pub last_flush_error: Option<String>,
+    pub high_water_flushes: u64,
+    pub shed_entries: u64,
+    pub last_shed_at: Option<u64>,
+}
+
+#[derive(Default)]
+struct FlushStats {
+    flushed_entries: u64,
+    flushed_batches: u64,
+    last_flush_at: Option<u64>,
+    last_flush_error: Option<String>,
+    high_water_flushes: u64,
+    shed_entries: u64,
+    last_shed_at: Option<u64>,
+}
+
+// Sequence numbers of the buffered entries. Flushes and shedding only ever take the oldest, so the
+// buffer holds exactly first..next and neither enqueue nor status has to read every entry.
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub(crate) struct AuditBufferState {
+    first: u64,
+    next: u64,
+}
+
+thread_local! {
+    // Access records not yet in the archive canister. Kept in stable memory, so an entry that has been
+    // acknowledged to the caller survives an upgrade and is only dropped once the archive has it.
+    static AUDIT_BUFFER: std::cell::RefCell<Box<dyn Store<u64, EmergencyAccessLog>>> =
+        std::cell::RefCell::new(storage::backend(storage::AUDIT_BUFFER_MEMORY));
+
+    // Carried across upgrades; rebuilt from the buffer's keys only after an upgrade from an image without it
+    static AUDIT_SEQ: std::cell::RefCell<Option<AuditBufferState>> = std::cell::RefCell::new(None);
+
+    static FLUSH_TIMER_STARTED: std::cell::RefCell<bool> = std::cell::RefCell::new(false);
+    static FLUSH_STARTED_AT: std::cell::RefCell<Option<u64>> = std::cell::RefCell::new(None);
+    static EARLY_FLUSH_SCHEDULED: std::cell::RefCell<bool> = std::cell::RefCell::new(false);
+    static FLUSH_STATS: std::cell::RefCell<FlushStats> = std::cell::RefCell::new(FlushStats::default());
+}
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+const FLUSH_BATCH_SIZE: usize = 200;
+// Past this many waiting entries a flush is started at once instead of waiting for the timer
+const FLUSH_HIGH_WATER: u64 = 1_000;
+// Past this many the oldest entry is shed for each new one. An emergency lookup is never refused for
+// want of audit space while the archive is down; what was shed is counted in the status.
+const MAX_BUFFERED_ENTRIES: u64 = 100_000;
+// A flush whose callback never came back (trapped) stops blocking new ones after this long
+const STALE_FLUSH_NANOS: u64 = 5 * 60 * 1_000_000_000;
+
+#[ic_cdk::update]
+async fn flush_audit_buffer() -> Result<AuditBufferStatus, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may flush the audit buffer".to_string());
+    }
+    if flush_in_flight() {
+        return Err("A flush is already in progress".to_string());
+    }
+    while flush_batch().await? > 0 {}
+    Ok(status())
+}
+
+#[ic_cdk::query]
+fn get_audit_buffer_status() -> Result<AuditBufferStatus, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read the audit buffer status".to_string());
+    }
+    Ok(status())
+}
+
+// Called on every logged access; the archive write happens later, off the caller's path
+pub(crate) fn enqueue(entry: EmergencyAccessLog) {
+    ensure_flush_timer();
+    let buffered = buffer(entry);
+    let schedule_early = buffered >= FLUSH_HIGH_WATER
+        && !flush_in_flight()
+        && !EARLY_FLUSH_SCHEDULED.with(|s| std::mem::replace(&mut *s.borrow_mut(), true));
+    if schedule_early {
+        FLUSH_STATS.with(|s| s.borrow_mut().high_water_flushes += 1);
+        ic_cdk_timers::set_timer(Duration::ZERO, || {
+            EARLY_FLUSH_SCHEDULED.with(|s| *s.borrow_mut() = false);
+            ic_cdk::spawn(flush_quietly());
+        });
+    }
+}
+
+// Stores the entry under the next sequence, shedding the oldest past the cap; returns how many are buffered
+pub(crate) fn buffer(entry: EmergencyAccessLog) -> u64 {
+    let mut range = seq_range();
+    let mut shed = 0;
+    let buffered = AUDIT_BUFFER.with(|b| {
+        let mut buffer = b.borrow_mut();
+        buffer.insert(range.next, entry);
+        range.next += 1;
+        while range.next - range.first > MAX_BUFFERED_ENTRIES {
+            buffer.remove(&range.first);
+            range.first += 1;
+            shed += 1;
+        }
+        range.next - range.first
+    });
+    AUDIT_SEQ.with(|s| *s.borrow_mut() = Some(range));
+    if shed > 0 {
+        FLUSH_STATS.with(|s| {
+            let mut stats = s.borrow_mut();
+            stats.shed_entries += shed;
+            stats.last_shed_at = Some(clock::now());
+        });
+        ic_cdk::println!("⚠️ Audit buffer full; {} unarchived access entries shed", shed);
+    }
+    buffered
+}
+
+// Timers do not survive upgrades; post_upgrade restarts the flush loop so buffered entries still drain
+pub(crate) fn ensure_flush_timer() {
+    let started = FLUSH_TIMER_STARTED.with(|s| std::mem::replace(&mut *s.borrow_mut(), true));
+    if !started {
+        ic_cdk_timers::set_timer_interval(FLUSH_INTERVAL, || ic_cdk::spawn(flush_quietly()));
+    }
+}
+
+async fn flush_quietly() {
+    if let Err(e) = flush_batch().await {
+        ic_cdk::println!("⚠️ Audit buffer flush failed, entries stay buffered: {}", e);
+    }
+}
+
+// Oldest entries first; they leave the buffer only after the archive has acknowledged them.
+// Returns how many were flushed, zero when there was nothing to do or another flush is running.
+async fn flush_batch() -> Result<usize, String> {
+    if flush_in_flight() {
+        return Ok(0);
+    }
+    let range = seq_range();
+    let end = range.next.min(range.first + FLUSH_BATCH_SIZE as u64);
+    let batch: Vec<(u64, EmergencyAccessLog)> = AUDIT_BUFFER.with(|b| {
+        let buffer = b.borrow();
+        (range.first..end).filter_map(|seq| buffer.get(&seq).map(|entry| (seq, entry))).collect()
+    });
+    if batch.is_empty() {
+        return Ok(0);
+    }
+
+    let started_at = clock::now();
+    FLUSH_STARTED_AT.with(|f| *f.borrow_mut() = Some(started_at));
+    let key = (started_at, batch[0].0);
+    let entries: Vec<EmergencyAccessLog> = batch.iter().map(|(_, entry)| entry.clone()).collect();
+    let result = storage::EMERGENCY_ACCESS_ARCHIVE.put(&key, &entries).await;
+    FLUSH_STARTED_AT.with(|f| *f.borrow_mut() = None);
+
+    FLUSH_STATS.with(|s| {
+        let mut stats = s.borrow_mut();
+        match &result {
+            Ok(()) => {
+                stats.flushed_entries += batch.len() as u64;
+                stats.flushed_batches += 1;
+                stats.last_flush_at = Some(clock::now());
+                stats.last_flush_error = None;
+            }
+            Err(e) => stats.last_flush_error = Some(e.clone()),
+        }
+    });
+    result?;
+
+    AUDIT_BUFFER.with(|b| {
+        let mut buffer = b.borrow_mut();
+        for (seq, _) in &batch {
+            buffer.remove(seq);
+        }
+    });
+    // Entries shed while the batch was in flight may already have moved first past it
+    let flushed_through = batch[batch.len() - 1].0 + 1;
+    AUDIT_SEQ.with(|s| {
+        if let Some(range) = s.borrow_mut().as_mut() {
+            range.first = range.first.max(flushed_through);
+        }
+    });
+    Ok(batch.len())
+}
+
+fn seq_range() -> AuditBufferState {
+    if let Some(range) = AUDIT_SEQ.with(|s| *s.borrow()) {
+        return range;
+    }
+    let keys: Vec<u64> = AUDIT_BUFFER.with(|b| b.borrow().entries().into_iter().map(|(k, _)| k).collect());
+    let range = AuditBufferState {
+        first: keys.iter().copied().min().unwrap_or(0),
+        next: keys.iter().map(|k| k + 1).max().unwrap_or(0),
+    };
+    AUDIT_SEQ.with(|s| *s.borrow_mut() = Some(range));
+    range
+}
+
+pub(crate) fn snapshot() -> AuditBufferState {
+    seq_range()
+}
+
+pub(crate) fn restore(state: Option<AuditBufferState>) {
+    let Some(state) = state else {
+        return;
+    };
+    AUDIT_SEQ.with(|s| *s.borrow_mut() = Some(state));
+}
+
+fn flush_in_flight() -> bool {
+    FLUSH_STARTED_AT.with(|f| f.borrow().is_some_and(|at| clock::now().saturating_sub(at) < STALE_FLUSH_NANOS))
+}
+
+pub(crate) fn status() -> AuditBufferStatus {
+    let range = seq_range();
+    let (buffered_entries, oldest_buffered_at) = AUDIT_BUFFER.with(|b| {
+        let buffer = b.borrow();
+        (buffer.len(), buffer.get(&range.first).map(|e| e.accessed_at))
+    });
+    FLUSH_STATS.with(|s| {
+        let stats = s.borrow();
+        AuditBufferStatus {
+            buffered_entries,
+            oldest_buffered_at,
+            flushed_entries: stats.flushed_entries,
+            flushed_batches: stats.flushed_batches,
+            last_flush_at: stats.last_flush_at,
+            last_flush_error: stats.last_flush_error.clone(),
+            high_water_flushes: stats.high_water_flushes,
+            shed_entries: stats.shed_entries,
+            last_shed_at: stats.last_shed_at,
+        }
+    })
+}
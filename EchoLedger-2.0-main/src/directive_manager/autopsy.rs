@@ -0,0 +1,230 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{clock, honeytokens, tenants, validation, ConsentDirective, CONSENT_DIRECTIVES};
+
+// Consent to or refusal of autopsy, carried on the directive. A refusal binds only as far as the
+// law lets it: medical examiners read it through get_autopsy_directive, which applies the rule for
+// the examiner's jurisdiction to the circumstances of the death and says whether the refusal can be
+// overridden and what the examiner must document when it is.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AutopsyPreference {
+    pub decision: String,                   // "CONSENT" or "REFUSE"
+    pub scope: Option<String>,              // with consent only; One of AUTOPSY_SCOPES
+    pub religious_objection: bool,          // the refusal rests on religious grounds
+    pub tradition: Option<String>,
+    pub accepted_alternatives: Vec<String>, // One of ALTERNATIVES each, acceptable even under a refusal
+    pub notes: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct MedicalExaminer {
+    pub examiner_id: String,
+    pub name: String,
+    pub principal: Principal,
+    pub jurisdiction: String,
+    pub active: bool,
+    pub registered_at: u64,
+}
+
+// When the law may order an autopsy over the patient's refusal
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AutopsyJurisdictionRule {
+    pub jurisdiction: String,
+    pub mandatory_grounds: Vec<String>,           // circumstances that override a refusal; One of GROUNDS each
+    pub religious_objection_grounds: Vec<String>, // the subset that also overrides a religious objection
+    pub required_documentation: Vec<String>,
+    pub updated_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AutopsyDirectiveView {
+    pub patient_id: String,
+    pub preference: Option<AutopsyPreference>,
+    pub jurisdiction: String,
+    pub rule: Option<AutopsyJurisdictionRule>, // None: no rule registered, consult local law
+    pub override_permitted: bool,
+    pub override_grounds: Vec<String>,         // the given circumstances the rule counts
+    pub required_documentation: Vec<String>,
+}
+
+const DIRECTIVE_TYPE: &str = "AUTOPSY";
+const DECISIONS: [&str; 2] = ["CONSENT", "REFUSE"];
+const AUTOPSY_SCOPES: [&str; 3] = ["FULL", "LIMITED", "EXTERNAL_ONLY"];
+const ALTERNATIVES: [&str; 3] = ["EXTERNAL_EXAMINATION", "CT_IMAGING", "MINIMALLY_INVASIVE"];
+const GROUNDS: [&str; 6] = ["HOMICIDE", "SUSPICIOUS", "UNATTENDED", "IN_CUSTODY", "PUBLIC_HEALTH", "WORKPLACE"];
+const MAX_EXAMINERS: usize = 1_000;
+const MAX_RULES: usize = 500;
+const MAX_DOCUMENTS: usize = 20;
+
+thread_local! {
+    static MEDICAL_EXAMINERS: std::cell::RefCell<BTreeMap<String, MedicalExaminer>> = const { std::cell::RefCell::new(BTreeMap::new()) };
+    static JURISDICTION_RULES: std::cell::RefCell<BTreeMap<String, AutopsyJurisdictionRule>> = const { std::cell::RefCell::new(BTreeMap::new()) };
+}
+
+#[ic_cdk::update]
+fn register_medical_examiner(examiner_id: String, name: String, principal: Principal, jurisdiction: String) -> Result<MedicalExaminer, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may register medical examiners".to_string());
+    }
+    let examiner_id = validation::identifier("examiner_id", &examiner_id)?.to_uppercase();
+    let name = validation::identifier("name", &name)?;
+    tenants::validate_jurisdiction(&jurisdiction)?;
+    MEDICAL_EXAMINERS.with(|e| {
+        let mut examiners = e.borrow_mut();
+        if examiners.len() >= MAX_EXAMINERS && !examiners.contains_key(&examiner_id) {
+            return Err("Too many medical examiners are registered".to_string());
+        }
+        if examiners.values().any(|x| x.principal == principal && x.examiner_id != examiner_id) {
+            return Err("This principal is already registered for another examiner".to_string());
+        }
+        let examiner = MedicalExaminer { examiner_id: examiner_id.clone(), name, principal, jurisdiction, active: true, registered_at: clock::now() };
+        examiners.insert(examiner_id, examiner.clone());
+        ic_cdk::println!("AUDIT: Medical examiner {} registered for {}", examiner.examiner_id, examiner.jurisdiction);
+        Ok(examiner)
+    })
+}
+
+#[ic_cdk::update]
+fn set_medical_examiner_active(examiner_id: String, active: bool) -> Result<MedicalExaminer, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may change medical examiners".to_string());
+    }
+    MEDICAL_EXAMINERS.with(|e| {
+        let mut examiners = e.borrow_mut();
+        let examiner = examiners.get_mut(&examiner_id).ok_or_else(|| format!("Unknown medical examiner: {}", examiner_id))?;
+        examiner.active = active;
+        Ok(examiner.clone())
+    })
+}
+
+#[ic_cdk::update]
+fn set_autopsy_jurisdiction_rule(mut rule: AutopsyJurisdictionRule) -> Result<AutopsyJurisdictionRule, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may set autopsy jurisdiction rules".to_string());
+    }
+    tenants::validate_jurisdiction(&rule.jurisdiction)?;
+    rule.mandatory_grounds = grounds("mandatory_grounds", &rule.mandatory_grounds)?;
+    rule.religious_objection_grounds = grounds("religious_objection_grounds", &rule.religious_objection_grounds)?;
+    if let Some(extra) = rule.religious_objection_grounds.iter().find(|g| !rule.mandatory_grounds.contains(g)) {
+        return Err(validation::invalid("religious_objection_grounds", &format!("{} is not a mandatory ground", extra)));
+    }
+    validation::collection("required_documentation", rule.required_documentation.len(), MAX_DOCUMENTS)?;
+    rule.required_documentation = rule.required_documentation.iter()
+        .map(|d| validation::text("required_documentation", d, validation::MAX_REASON_BYTES))
+        .collect::<Result<Vec<_>, _>>()?;
+    rule.updated_at = clock::now();
+    JURISDICTION_RULES.with(|r| {
+        let mut rules = r.borrow_mut();
+        if rules.len() >= MAX_RULES && !rules.contains_key(&rule.jurisdiction) {
+            return Err("Too many autopsy jurisdiction rules are registered".to_string());
+        }
+        rules.insert(rule.jurisdiction.clone(), rule.clone());
+        Ok(rule)
+    })
+}
+
+#[ic_cdk::query]
+fn get_autopsy_jurisdiction_rules() -> Vec<AutopsyJurisdictionRule> {
+    JURISDICTION_RULES.with(|r| r.borrow().values().cloned().collect())
+}
+
+// The examiner describes the death with GROUNDS codes; the answer is read against their own jurisdiction
+#[ic_cdk::update]
+fn get_autopsy_directive(patient_id: String, circumstances: Vec<String>) -> Result<AutopsyDirectiveView, String> {
+    let requester = caller();
+    honeytokens::touch(&patient_id, requester, "Autopsy directive read");
+    let jurisdiction = match MEDICAL_EXAMINERS.with(|e| e.borrow().values().find(|x| x.active && x.principal == requester).cloned()) {
+        Some(examiner) => examiner.jurisdiction,
+        // Controllers read it against the patient's tenant jurisdiction, or the global default
+        None if ic_cdk::api::is_controller(&requester) => {
+            tenants::effective_config(&tenants::patient_tenant(&patient_id).unwrap_or_default()).jurisdiction
+        }
+        None => return Err("Only registered medical examiners may read autopsy directives".to_string()),
+    };
+    let circumstances = grounds("circumstances", &circumstances)?;
+    let preference = CONSENT_DIRECTIVES.with(|d| d.borrow().get(&patient_id)).and_then(|directive| directive.autopsy);
+    let rule = rule_for(&jurisdiction);
+
+    let refused = preference.as_ref().is_some_and(|p| p.decision == "REFUSE");
+    let override_grounds: Vec<String> = match (&rule, &preference) {
+        (Some(rule), Some(preference)) if refused => {
+            let counted = if preference.religious_objection { &rule.religious_objection_grounds } else { &rule.mandatory_grounds };
+            circumstances.into_iter().filter(|c| counted.contains(c)).collect()
+        }
+        _ => vec![],
+    };
+    Ok(AutopsyDirectiveView {
+        patient_id,
+        override_permitted: !refused || !override_grounds.is_empty(),
+        required_documentation: if refused && !override_grounds.is_empty() {
+            rule.as_ref().map(|r| r.required_documentation.clone()).unwrap_or_default()
+        } else {
+            vec![]
+        },
+        override_grounds,
+        preference,
+        jurisdiction,
+        rule,
+    })
+}
+
+// Normalizes the preference in place; an AUTOPSY directive must carry one
+pub(crate) fn validate(directive: &mut ConsentDirective) -> Result<(), String> {
+    if directive.directive_type == DIRECTIVE_TYPE && directive.autopsy.is_none() {
+        return Err(validation::invalid("autopsy", "an AUTOPSY directive needs its preference"));
+    }
+    let Some(preference) = directive.autopsy.as_mut() else {
+        return Ok(());
+    };
+    preference.decision = preference.decision.trim().to_uppercase();
+    if !DECISIONS.contains(&preference.decision.as_str()) {
+        return Err(validation::invalid("autopsy.decision", &format!("must be one of {}", DECISIONS.join(", "))));
+    }
+    match (preference.decision.as_str(), preference.scope.as_mut()) {
+        ("CONSENT", Some(scope)) => {
+            *scope = scope.trim().to_uppercase();
+            if !AUTOPSY_SCOPES.contains(&scope.as_str()) {
+                return Err(validation::invalid("autopsy.scope", &format!("must be one of {}", AUTOPSY_SCOPES.join(", "))));
+            }
+        }
+        ("REFUSE", Some(_)) => return Err(validation::invalid("autopsy.scope", "only applies to CONSENT")),
+        _ => {}
+    }
+    if preference.religious_objection && preference.decision != "REFUSE" {
+        return Err(validation::invalid("autopsy.religious_objection", "only applies to REFUSE"));
+    }
+    if let Some(tradition) = &preference.tradition {
+        preference.tradition = Some(validation::text("autopsy.tradition", tradition, validation::MAX_REASON_BYTES)?);
+    }
+    preference.accepted_alternatives = preference.accepted_alternatives.iter().map(|a| a.trim().to_uppercase()).collect();
+    if let Some(unknown) = preference.accepted_alternatives.iter().find(|a| !ALTERNATIVES.contains(&a.as_str())) {
+        return Err(validation::invalid("autopsy.accepted_alternatives", &format!("unknown alternative {}", unknown)));
+    }
+    if let Some(notes) = &preference.notes {
+        preference.notes = Some(validation::text("autopsy.notes", notes, validation::MAX_RATIONALE_BYTES)?);
+    }
+    Ok(())
+}
+
+// "US-CA" falls back to the national "US" rule when it has none of its own
+fn rule_for(jurisdiction: &str) -> Option<AutopsyJurisdictionRule> {
+    JURISDICTION_RULES.with(|r| {
+        let rules = r.borrow();
+        rules.get(jurisdiction)
+            .or_else(|| jurisdiction.split_once('-').and_then(|(country, _)| rules.get(country)))
+            .cloned()
+    })
+}
+
+fn grounds(field: &str, values: &[String]) -> Result<Vec<String>, String> {
+    validation::collection(field, values.len(), GROUNDS.len())?;
+    let values: Vec<String> = values.iter().map(|g| g.trim().to_uppercase()).collect();
+    if let Some(unknown) = values.iter().find(|g| !GROUNDS.contains(&g.as_str())) {
+        return Err(validation::invalid(field, &format!("unknown ground {}; expected one of {}", unknown, GROUNDS.join(", "))));
+    }
+    Ok(values)
+}
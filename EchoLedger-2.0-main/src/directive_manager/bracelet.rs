@@ -0,0 +1,140 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::emergency::{self, EmergencyDirective};
+use crate::{clock, directive_owner, hashing, load_shedding};
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct TokenBinding {
+    pub token_uid_hash: Vec<u8>,
+    pub patient_id_hash: Vec<u8>,
+    pub token_type: String, // "NFC_BRACELET", "NFC_CARD"
+    pub label: String,
+    pub bound_by: Principal,
+    pub bound_at: u64,
+    pub revoked_at: Option<u64>,
+}
+
+thread_local! {
+    // sha256(tag UID) -> binding; a revoked binding is kept for the audit trail
+    static TOKEN_BINDINGS: std::cell::RefCell<BTreeMap<Vec<u8>, TokenBinding>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+const TOKEN_TYPES: [&str; 2] = ["NFC_BRACELET", "NFC_CARD"];
+const MAX_BINDINGS_PER_PATIENT: usize = 5;
+
+// The patient binds a bracelet they wear; the reader hashes the tag UID before it leaves the device
+#[ic_cdk::update]
+fn register_token_binding(
+    patient_id: String,
+    token_uid_hash: Vec<u8>,
+    token_type: String,
+    label: String
+) -> Result<TokenBinding, String> {
+    let owner = caller();
+    if directive_owner(&patient_id) != Some(owner) {
+        return Err("Only the patient may bind a hardware token to their directives".to_string());
+    }
+    if !TOKEN_TYPES.contains(&token_type.as_str()) {
+        return Err(format!("Unknown token type: {}", token_type));
+    }
+    if token_uid_hash.len() != 32 {
+        return Err("token_uid_hash must be a SHA-256 digest".to_string());
+    }
+
+    let patient_id_hash = hashing::patient_hash(&patient_id);
+    let binding = TokenBinding {
+        token_uid_hash: token_uid_hash.clone(),
+        patient_id_hash: patient_id_hash.clone(),
+        token_type,
+        label,
+        bound_by: owner,
+        bound_at: clock::now(),
+        revoked_at: None,
+    };
+
+    TOKEN_BINDINGS.with(|b| {
+        let mut bindings = b.borrow_mut();
+        if bindings.get(&token_uid_hash).is_some_and(|existing| existing.revoked_at.is_none()) {
+            return Err("Token is already bound; revoke the existing binding first".to_string());
+        }
+        let active = bindings.values()
+            .filter(|x| x.patient_id_hash == patient_id_hash && x.revoked_at.is_none())
+            .count();
+        if active >= MAX_BINDINGS_PER_PATIENT {
+            return Err(format!("At most {} active tokens may be bound per patient", MAX_BINDINGS_PER_PATIENT));
+        }
+        bindings.insert(token_uid_hash, binding.clone());
+        Ok(())
+    })?;
+    Ok(binding)
+}
+
+// Lost or replaced bracelets are revoked by the patient, or by a controller on their behalf
+#[ic_cdk::update]
+fn revoke_token_binding(token_uid_hash: Vec<u8>) -> Result<TokenBinding, String> {
+    let requester = caller();
+    TOKEN_BINDINGS.with(|b| {
+        let mut bindings = b.borrow_mut();
+        let binding = bindings.get_mut(&token_uid_hash).ok_or("Token is not bound")?;
+        if binding.bound_by != requester && !ic_cdk::api::is_controller(&requester) {
+            return Err("Only the patient who bound this token may revoke it".to_string());
+        }
+        if binding.revoked_at.is_some() {
+            return Err("Token binding is already revoked".to_string());
+        }
+        binding.revoked_at = Some(clock::now());
+        Ok(binding.clone())
+    })
+}
+
+#[ic_cdk::query]
+fn get_token_bindings(patient_id: String) -> Result<Vec<TokenBinding>, String> {
+    if directive_owner(&patient_id) != Some(caller()) {
+        return Err("Only the patient may list their bound tokens".to_string());
+    }
+    let patient_id_hash = hashing::patient_hash(&patient_id);
+    Ok(TOKEN_BINDINGS.with(|b| {
+        b.borrow()
+            .values()
+            .filter(|x| hashing::storage_key(&x.patient_id_hash) == hashing::storage_key(&patient_id_hash))
+            .cloned()
+            .collect()
+    }))
+}
+
+// Called by emergency_bridge with the same token checks and access logging as emergency_lookup
+#[ic_cdk::update]
+fn bracelet_lookup(
+    token_uid_hash: Vec<u8>,
+    requester: Principal,
+    token: String,
+    emergency_justification: Option<String>
+) -> Result<(Vec<u8>, Vec<EmergencyDirective>), String> {
+    let _permit = load_shedding::admit("EMERGENCY")?;
+    let binding = TOKEN_BINDINGS.with(|b| b.borrow().get(&token_uid_hash).cloned())
+        .filter(|binding| binding.revoked_at.is_none())
+        .ok_or("No active binding for this token")?;
+    emergency::authorized_lookup(binding.patient_id_hash, requester, &token, emergency_justification)
+}
+
+pub(crate) fn active_token_hashes(patient_id_hash: &[u8]) -> Vec<Vec<u8>> {
+    TOKEN_BINDINGS.with(|b| {
+        b.borrow()
+            .values()
+            .filter(|x| x.patient_id_hash == patient_id_hash && x.revoked_at.is_none())
+            .map(|x| x.token_uid_hash.clone())
+            .collect()
+    })
+}
+
+pub(crate) fn rekey_patient(old_hash: &[u8], new_hash: &[u8]) {
+    TOKEN_BINDINGS.with(|b| {
+        for binding in b.borrow_mut().values_mut().filter(|x| x.patient_id_hash == old_hash) {
+            binding.patient_id_hash = new_hash.to_vec();
+        }
+    });
+}
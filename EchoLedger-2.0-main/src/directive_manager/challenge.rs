@@ -0,0 +1,168 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::caller;
+use std::collections::BTreeMap;
+
+use crate::clock;
+use crate::hashing;
+use crate::tenants::{self, TENANTS};
+use crate::validation;
+
+// Carried across upgrades with the hash key ring, so no tenant has to re-register its key. Proofs
+// are not: they last an hour and callers simply answer a new challenge.
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct ChallengeState {
+    pub(crate) keys: Vec<(String, Vec<u8>, u64)>,
+}
+
+thread_local! {
+    // tenant_id -> (key the hospital's own systems hold, set_at); set by a tenant admin, never returned
+    static HOSPITAL_CHALLENGE_KEYS: std::cell::RefCell<BTreeMap<String, (Vec<u8>, u64)>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    // caller -> (nonce, issued_at); one outstanding challenge each, spent on the first answer
+    static PENDING_CHALLENGES: std::cell::RefCell<BTreeMap<Principal, (Vec<u8>, u64)>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    // caller -> proven until
+    static PROVEN_CALLERS: std::cell::RefCell<BTreeMap<Principal, u64>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+// Endpoints that do real work for callers outside the platform; unproven ingress to them is dropped before it runs.
+// check_directive_exists is left out: EMT field devices are not tenant members, and its own rate limits bound it.
+const CHALLENGED_METHODS: [&str; 3] = [
+    "get_inclusion_proof",
+    "record_verification_receipts",
+    "export_offline_bundle",
+];
+const CHALLENGE_METHODS: [&str; 2] = ["request_hospital_challenge", "answer_hospital_challenge"];
+const CHALLENGE_TTL_NANOS: u64 = 5 * 60 * 1_000_000_000;
+const PROOF_TTL_NANOS: u64 = 60 * 60 * 1_000_000_000;
+const MIN_CHALLENGE_KEY_BYTES: usize = 32;
+
+// Runs before consensus for every ingress update, so it only reads: a caller that has not
+// proven itself never gets a challenged endpoint scheduled. Inter-canister calls skip this
+// hook, which is why the endpoints check require_proof again.
+#[ic_cdk::inspect_message]
+fn inspect_message() {
+    let method = ic_cdk::api::call::method_name();
+    let requester = caller();
+    // Oversized arguments are dropped before they are decoded, whatever the method
+    let accept = if !validation::within_ingress_limit(&method) {
+        false
+    } else if CHALLENGED_METHODS.contains(&method.as_str()) {
+        require_proof(requester).is_ok()
+    } else if CHALLENGE_METHODS.contains(&method.as_str()) {
+        requester != Principal::anonymous()
+    } else {
+        true
+    };
+    if accept {
+        ic_cdk::api::call::accept_message();
+    }
+}
+
+#[ic_cdk::update]
+fn set_hospital_challenge_key(tenant_id: String, key: Vec<u8>) -> Result<(), String> {
+    let tenant = TENANTS.with(|t| t.borrow().get(&tenant_id).cloned())
+        .ok_or(format!("Unknown tenant: {}", tenant_id))?;
+    tenants::ensure_tenant_admin(&tenant, caller())?;
+    if key.len() < MIN_CHALLENGE_KEY_BYTES {
+        return Err(format!("Challenge keys must be at least {} bytes", MIN_CHALLENGE_KEY_BYTES));
+    }
+    HOSPITAL_CHALLENGE_KEYS.with(|k| k.borrow_mut().insert(tenant_id.clone(), (key, clock::now())));
+    // Proofs made with the old key no longer vouch for anyone
+    PROVEN_CALLERS.with(|p| {
+        p.borrow_mut().retain(|principal, _| tenants::membership_of(*principal).is_none_or(|m| m.tenant_id != tenant_id))
+    });
+    ic_cdk::println!("AUDIT: Challenge key rotated for tenant {}", tenant_id);
+    Ok(())
+}
+
+// The caller answers with HMAC-SHA256(key, nonce || caller principal bytes) under its hospital's key
+#[ic_cdk::update]
+async fn request_hospital_challenge() -> Result<Vec<u8>, String> {
+    let requester = caller();
+    challenge_key_for(requester)?;
+
+    let (nonce,) = raw_rand().await.map_err(|(_, msg)| format!("Failed to generate challenge: {}", msg))?;
+    let now = clock::now();
+    PENDING_CHALLENGES.with(|c| {
+        let mut pending = c.borrow_mut();
+        pending.retain(|_, (_, issued_at)| now.saturating_sub(*issued_at) < CHALLENGE_TTL_NANOS);
+        pending.insert(requester, (nonce.clone(), now));
+    });
+    Ok(nonce)
+}
+
+#[ic_cdk::update]
+fn answer_hospital_challenge(response: Vec<u8>) -> Result<u64, String> {
+    let requester = caller();
+    let key = challenge_key_for(requester)?;
+    let now = clock::now();
+    let (nonce, issued_at) = PENDING_CHALLENGES.with(|c| c.borrow_mut().remove(&requester))
+        .ok_or("No outstanding challenge; request one first")?;
+    if now.saturating_sub(issued_at) >= CHALLENGE_TTL_NANOS {
+        return Err("Challenge expired; request a new one".to_string());
+    }
+
+    let mut message = nonce;
+    message.extend_from_slice(requester.as_slice());
+    if hashing::hmac_sha256(&key, &message) != response {
+        ic_cdk::println!("AUDIT: Failed hospital challenge from {}", requester.to_text());
+        return Err("Challenge response does not match the hospital registry key".to_string());
+    }
+
+    let proven_until = now + PROOF_TTL_NANOS;
+    PROVEN_CALLERS.with(|p| {
+        let mut proven = p.borrow_mut();
+        proven.retain(|_, until| *until > now);
+        proven.insert(requester, proven_until);
+    });
+    Ok(proven_until)
+}
+
+// Platform canisters and controllers are trusted already; everyone else needs a live proof from an active tenant
+pub(crate) fn require_proof(principal: Principal) -> Result<(), String> {
+    if tenants::is_platform(principal) {
+        return Ok(());
+    }
+    let proven = PROVEN_CALLERS.with(|p| p.borrow().get(&principal).is_some_and(|until| *until > clock::now()));
+    let active = tenants::membership_of(principal).is_some_and(|m| m.status == "ACTIVE");
+    if proven && active {
+        Ok(())
+    } else {
+        Err("Answer a hospital challenge before calling this endpoint".to_string())
+    }
+}
+
+fn challenge_key_for(principal: Principal) -> Result<Vec<u8>, String> {
+    let membership = tenants::membership_of(principal)
+        .filter(|m| m.status == "ACTIVE")
+        .ok_or("Only members of an active tenant may take a hospital challenge")?;
+    HOSPITAL_CHALLENGE_KEYS.with(|k| k.borrow().get(&membership.tenant_id).map(|(key, _)| key.clone()))
+        .ok_or(format!("Tenant {} has no challenge key registered", membership.tenant_id))
+}
+
+pub(crate) fn snapshot() -> ChallengeState {
+    ChallengeState {
+        keys: HOSPITAL_CHALLENGE_KEYS.with(|k| {
+            k.borrow().iter().map(|(tenant_id, (key, set_at))| (tenant_id.clone(), key.clone(), *set_at)).collect()
+        }),
+    }
+}
+
+pub(crate) fn restore(state: Option<ChallengeState>) {
+    let Some(state) = state else {
+        return;
+    };
+    HOSPITAL_CHALLENGE_KEYS.with(|k| {
+        *k.borrow_mut() = state.keys.into_iter().map(|(tenant_id, key, set_at)| (tenant_id, (key, set_at))).collect();
+    });
+}
+
+// tenant_id -> when its challenge key was last set, for the key inventory
+pub(crate) fn key_ages() -> Vec<(String, u64)> {
+    HOSPITAL_CHALLENGE_KEYS.with(|k| k.borrow().iter().map(|(tenant_id, (_, set_at))| (tenant_id.clone(), *set_at)).collect())
+}
@@ -0,0 +1,117 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::emergency::{self, INACTIVE_STATUSES};
+use crate::{hashing, honeytokens, tenants, CONSENT_DIRECTIVES, EMERGENCY_CONTACTS, PATIENT_HASH_INDEX, PROXY_GRANTS};
+
+// Three short lines a clinician can take in from a monitor or a badge: code status, the
+// restrictions in force, and who to call. Derived from the event log like the rest of directive
+// state, so every directive, contact or proxy change regenerates it and a replay rebuilds it.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ClinicianSummary {
+    pub code_status: String,
+    pub restrictions: String,
+    pub proxy_contact: String,
+    pub generated_at: u64,
+}
+
+// Wide enough for a bedside monitor banner
+const MAX_LINE_CHARS: usize = 64;
+
+const PROXY_RELATIONSHIPS: [&str; 4] = ["PROXY", "AGENT", "POWER_OF_ATTORNEY", "GUARDIAN"];
+
+thread_local! {
+    // storage key -> summary; a projection of the event log
+    pub(crate) static CLINICIAN_SUMMARIES: std::cell::RefCell<BTreeMap<Vec<u8>, ClinicianSummary>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+}
+
+// What emergency_check will show, for the patient and the people acting for them
+#[ic_cdk::update]
+fn get_clinician_summary(patient_id: String) -> Result<Option<ClinicianSummary>, String> {
+    honeytokens::touch(&patient_id, caller(), "Clinician summary read");
+    if !ic_cdk::api::is_controller(&caller()) && !tenants::may_access_patient(caller(), &patient_id, None) {
+        return Err("Not authorized to read this patient's clinician summary".to_string());
+    }
+    Ok(for_hash(&hashing::storage_key(&hashing::patient_hash(&patient_id))))
+}
+
+pub(crate) fn for_hash(storage_key: &[u8]) -> Option<ClinicianSummary> {
+    CLINICIAN_SUMMARIES.with(|s| s.borrow().get(storage_key).cloned())
+}
+
+pub(crate) fn refresh_patient(patient_id: &str, at: u64) {
+    refresh(&hashing::storage_key(&hashing::patient_hash(patient_id)), at);
+}
+
+// Regenerates from current state; a patient with nothing in force has no summary
+pub(crate) fn refresh(storage_key: &[u8], at: u64) {
+    let summary = PATIENT_HASH_INDEX
+        .with(|index| index.borrow().get(storage_key).cloned())
+        .and_then(|patient_id| generate(&patient_id, storage_key, at));
+    CLINICIAN_SUMMARIES.with(|s| {
+        let mut summaries = s.borrow_mut();
+        match summary {
+            Some(summary) => summaries.insert(storage_key.to_vec(), summary),
+            None => summaries.remove(storage_key),
+        }
+    });
+}
+
+fn generate(patient_id: &str, storage_key: &[u8], at: u64) -> Option<ClinicianSummary> {
+    let in_force: Vec<_> = CONSENT_DIRECTIVES
+        .with(|d| d.borrow().get(&patient_id.to_string()))
+        .into_iter()
+        .filter(|d| !INACTIVE_STATUSES.contains(&d.status.as_str()))
+        .collect();
+    if in_force.is_empty() {
+        return None;
+    }
+
+    let code_status = if in_force.iter().any(|d| d.directive_type == "DNR") {
+        "CODE STATUS: DNR - no CPR, no intubation".to_string()
+    } else {
+        "CODE STATUS: FULL CODE - no DNR on file".to_string()
+    };
+
+    let mut restrictions: Vec<String> = Vec::new();
+    for directive in &in_force {
+        for condition in emergency::emergency_conditions(&directive.directive_type, &directive.consent_items) {
+            if !restrictions.contains(&condition) {
+                restrictions.push(condition);
+            }
+        }
+    }
+    let restrictions = format!("RESTRICTIONS: {}", restrictions.join("; "));
+
+    let contacts = EMERGENCY_CONTACTS.with(|c| c.borrow().get(storage_key).cloned().unwrap_or_default());
+    let proxy = contacts.iter()
+        .find(|c| PROXY_RELATIONSHIPS.iter().any(|r| c.relationship.to_uppercase().replace(' ', "_").contains(r)))
+        .or_else(|| contacts.first());
+    let proxy_contact = match proxy {
+        Some(contact) => format!("PROXY: {} ({}) {} {}", contact.name, contact.relationship, contact.channel, contact.address),
+        None if PROXY_GRANTS.with(|g| g.borrow().get(patient_id).is_some_and(|grants| !grants.is_empty())) => {
+            "PROXY: delegated proxy on file, no contact details".to_string()
+        }
+        None => "PROXY: none on file".to_string(),
+    };
+
+    Some(ClinicianSummary {
+        code_status: fit(&code_status),
+        restrictions: fit(&restrictions),
+        proxy_contact: fit(&proxy_contact),
+        generated_at: at,
+    })
+}
+
+fn fit(line: &str) -> String {
+    if line.chars().count() <= MAX_LINE_CHARS {
+        return line.to_string();
+    }
+    let mut cut: String = line.chars().take(MAX_LINE_CHARS - 3).collect();
+    cut.push_str("...");
+    cut
+}
@@ -0,0 +1,19 @@
+// Time and sequence numbers come from the shared canister_clock crate; tests, and builds made with
+// the "test-clock" feature, pin it so expiry, waiting periods and activation windows can be
+// exercised without waiting them out. The test endpoint is declared here so this canister's wasm
+// exports it.
+
+pub(crate) use canister_clock::{next_sequence, now, restore, snapshot};
+
+#[cfg(test)]
+pub(crate) use canister_clock::advance;
+
+// Never compiled into production builds
+#[cfg(feature = "test-clock")]
+#[ic_cdk::update]
+fn set_test_clock(at: Option<u64>, advance_nanos: u64) -> Result<u64, String> {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        return Err("Only canister controllers may set the test clock".to_string());
+    }
+    Ok(canister_clock::apply_test_setting(at, advance_nanos))
+}
@@ -0,0 +1,149 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{clock, deliver_to_contacts, i18n, ids, validation, ContactEvent, OPERATOR_CONTACTS};
+
+// Compliance incidents: anything the privacy officer has to look at and close out, opened by the
+// detectors in this canister rather than by hand. High and critical incidents page the operator
+// contacts as they open; every incident stays on file, resolved or not, for breach reporting.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ComplianceIncident {
+    pub incident_id: String,
+    pub category: String, // "ACCESS_ANOMALY", ...
+    pub severity: String, // "LOW", "MEDIUM", "HIGH", "CRITICAL"
+    pub principal: Option<Principal>, // whose behaviour opened it, when there is one
+    pub summary: String,
+    pub details: String,
+    pub opened_at: u64,
+    pub status: String, // "OPEN", "ACKNOWLEDGED", "RESOLVED"
+    pub acknowledged_by: Option<Principal>,
+    pub resolved_at: Option<u64>,
+    pub resolution_note: Option<String>,
+}
+
+// Carried across upgrades with the hash key ring; open incidents must not be lost
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct ComplianceState {
+    incidents: Vec<ComplianceIncident>,
+}
+
+const PAGED_SEVERITIES: [&str; 2] = ["HIGH", "CRITICAL"];
+const MAX_INCIDENTS: usize = 10_000;
+
+thread_local! {
+    static INCIDENTS: std::cell::RefCell<BTreeMap<String, ComplianceIncident>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+}
+
+#[ic_cdk::query]
+fn get_compliance_incidents(status: Option<String>) -> Result<Vec<ComplianceIncident>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read compliance incidents".to_string());
+    }
+    Ok(INCIDENTS.with(|i| {
+        i.borrow()
+            .values()
+            .filter(|x| status.as_ref().is_none_or(|s| &x.status == s))
+            .cloned()
+            .collect()
+    }))
+}
+
+#[ic_cdk::update]
+fn acknowledge_compliance_incident(incident_id: String) -> Result<ComplianceIncident, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may acknowledge compliance incidents".to_string());
+    }
+    INCIDENTS.with(|i| {
+        let mut incidents = i.borrow_mut();
+        let incident = incidents.get_mut(&incident_id).ok_or_else(|| format!("Unknown compliance incident: {}", incident_id))?;
+        if incident.status != "OPEN" {
+            return Err(format!("This incident is already {}", incident.status));
+        }
+        incident.status = "ACKNOWLEDGED".to_string();
+        incident.acknowledged_by = Some(caller());
+        Ok(incident.clone())
+    })
+}
+
+#[ic_cdk::update]
+fn resolve_compliance_incident(incident_id: String, resolution_note: String) -> Result<ComplianceIncident, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may resolve compliance incidents".to_string());
+    }
+    let resolution_note = validation::text("resolution_note", &resolution_note, validation::MAX_REASON_BYTES)?;
+    INCIDENTS.with(|i| {
+        let mut incidents = i.borrow_mut();
+        let incident = incidents.get_mut(&incident_id).ok_or_else(|| format!("Unknown compliance incident: {}", incident_id))?;
+        if incident.status == "RESOLVED" {
+            return Err("This incident is already resolved".to_string());
+        }
+        incident.status = "RESOLVED".to_string();
+        incident.resolved_at = Some(clock::now());
+        incident.resolution_note = Some(resolution_note);
+        Ok(incident.clone())
+    })
+}
+
+// Opens an incident and pages operators when it is serious enough; returns its id
+pub(crate) fn open_incident(
+    category: &str,
+    severity: &str,
+    principal: Option<Principal>,
+    summary: String,
+    details: String
+) -> String {
+    let incident = ComplianceIncident {
+        incident_id: ids::new_id("INCIDENT"),
+        category: category.to_string(),
+        severity: severity.to_string(),
+        principal,
+        summary,
+        details,
+        opened_at: clock::now(),
+        status: "OPEN".to_string(),
+        acknowledged_by: None,
+        resolved_at: None,
+        resolution_note: None,
+    };
+    let incident_id = incident.incident_id.clone();
+    ic_cdk::println!("AUDIT: Compliance incident {} ({} {}) opened", incident_id, severity, category);
+    if PAGED_SEVERITIES.contains(&severity) {
+        let event = ContactEvent {
+            event_type: "COMPLIANCE_INCIDENT".to_string(),
+            reference_id: incident_id.clone(),
+            summary: format!("{} {}: {}", severity, category, incident.summary),
+            details: incident.details.clone(),
+        };
+        let contacts = OPERATOR_CONTACTS.with(|contacts| contacts.borrow().clone());
+        deliver_to_contacts(&contacts, &event, i18n::DEFAULT_LOCALE, None);
+    }
+    INCIDENTS.with(|i| {
+        let mut incidents = i.borrow_mut();
+        // Resolved incidents make room first; open ones are never dropped for space
+        if incidents.len() >= MAX_INCIDENTS {
+            let oldest_resolved = incidents.values().find(|x| x.status == "RESOLVED").map(|x| x.incident_id.clone());
+            if let Some(id) = oldest_resolved {
+                incidents.remove(&id);
+            }
+        }
+        incidents.insert(incident_id.clone(), incident);
+    });
+    incident_id
+}
+
+pub(crate) fn snapshot() -> ComplianceState {
+    ComplianceState { incidents: INCIDENTS.with(|i| i.borrow().values().cloned().collect()) }
+}
+
+pub(crate) fn restore(state: Option<ComplianceState>) {
+    let Some(state) = state else {
+        return;
+    };
+    INCIDENTS.with(|i| {
+        *i.borrow_mut() = state.incidents.into_iter().map(|incident| (incident.incident_id.clone(), incident)).collect();
+    });
+}
@@ -0,0 +1,170 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{tenants, validation, PATIENT_HASH_INDEX};
+
+// Cross-border disclosure. Every logged access is tagged with the jurisdiction the requester's
+// tenant is registered in. Where a policy covers the patient's jurisdiction, emergency lookups by a
+// requester outside its permitted jurisdictions are refused unless the request carries an emergency
+// justification and the policy allows one to override it; the decision and the justification go
+// into the access log with the access. Codes match themselves and their subdivisions, so "US"
+// covers "US-CA". Requesters or patients with no registered jurisdiction are not cross-border.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct CrossBorderPolicy {
+    pub policy_id: String,
+    pub patient_jurisdictions: Vec<String>,
+    pub permitted_requester_jurisdictions: Vec<String>, // the patient jurisdictions are always permitted
+    pub allow_emergency_override: bool,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct CrossBorderDecision {
+    pub policy_id: Option<String>,
+    pub requester_jurisdiction: Option<String>,
+    pub patient_jurisdiction: Option<String>,
+    pub decision: String, // "NOT_CROSS_BORDER", "NO_POLICY", "PERMITTED", "EMERGENCY_OVERRIDE", "DENIED"
+    pub justification: Option<String>,
+}
+
+const MAX_POLICIES: usize = 50;
+const MAX_JURISDICTIONS_PER_POLICY: usize = 100;
+
+// EU member states
+const EU: [&str; 27] = [
+    "AT", "BE", "BG", "CY", "CZ", "DE", "DK", "EE", "ES", "FI", "FR", "GR", "HR", "HU", "IE", "IT", "LT", "LU", "LV",
+    "MT", "NL", "PL", "PT", "RO", "SE", "SI", "SK",
+];
+// The rest of the EEA and the countries with a general adequacy decision
+const EU_ADEQUATE: [&str; 16] = [
+    "IS", "LI", "NO", "AD", "AR", "CA", "CH", "FO", "GB", "GG", "IL", "IM", "JE", "JP", "KR", "NZ",
+];
+
+thread_local! {
+    static POLICIES: std::cell::RefCell<BTreeMap<String, CrossBorderPolicy>> = std::cell::RefCell::new(default_policies());
+}
+
+// GDPR: vital interests justify a transfer the adequacy list does not cover
+fn default_policies() -> BTreeMap<String, CrossBorderPolicy> {
+    let policy = CrossBorderPolicy {
+        policy_id: "EU_GDPR".to_string(),
+        patient_jurisdictions: EU.iter().map(|c| c.to_string()).collect(),
+        permitted_requester_jurisdictions: EU_ADEQUATE.iter().map(|c| c.to_string()).collect(),
+        allow_emergency_override: true,
+    };
+    BTreeMap::from([(policy.policy_id.clone(), policy)])
+}
+
+#[ic_cdk::update]
+fn set_cross_border_policy(policy: CrossBorderPolicy) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may set cross-border policies".to_string());
+    }
+    let policy_id = validation::identifier("policy_id", &policy.policy_id)?.to_uppercase();
+    for (field, codes) in [
+        ("patient_jurisdictions", &policy.patient_jurisdictions),
+        ("permitted_requester_jurisdictions", &policy.permitted_requester_jurisdictions),
+    ] {
+        validation::collection(field, codes.len(), MAX_JURISDICTIONS_PER_POLICY)?;
+        codes.iter().try_for_each(|code| tenants::validate_jurisdiction(code))?;
+    }
+    if policy.patient_jurisdictions.is_empty() {
+        return Err(validation::invalid("patient_jurisdictions", "must name at least one jurisdiction"));
+    }
+    POLICIES.with(|p| {
+        let mut policies = p.borrow_mut();
+        if policies.len() >= MAX_POLICIES && !policies.contains_key(&policy_id) {
+            return Err("Too many cross-border policies are set".to_string());
+        }
+        // Two policies claiming one patient jurisdiction would leave the outcome to map order
+        let overlapping = policies.values().find(|other| {
+            other.policy_id != policy_id
+                && other.patient_jurisdictions.iter().any(|j| policy.patient_jurisdictions.contains(j))
+        });
+        if let Some(other) = overlapping {
+            return Err(format!("Policy {} already covers one of these patient jurisdictions", other.policy_id));
+        }
+        policies.insert(policy_id.clone(), CrossBorderPolicy { policy_id, ..policy });
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+fn remove_cross_border_policy(policy_id: String) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may remove cross-border policies".to_string());
+    }
+    POLICIES.with(|p| p.borrow_mut().remove(&policy_id))
+        .map(|_| ())
+        .ok_or_else(|| format!("Unknown cross-border policy: {}", policy_id))
+}
+
+#[ic_cdk::query]
+fn get_cross_border_policies() -> Vec<CrossBorderPolicy> {
+    POLICIES.with(|p| p.borrow().values().cloned().collect())
+}
+
+// The tag every logged access carries
+pub(crate) fn requester_jurisdiction(requester: Principal) -> Option<String> {
+    tenants::membership_of(requester).map(|m| m.jurisdiction)
+}
+
+// Takes the storage key; a blank justification counts as none
+pub(crate) fn evaluate(requester: Principal, patient_id_hash: &[u8], justification: Option<&str>) -> CrossBorderDecision {
+    let requester_jurisdiction = requester_jurisdiction(requester);
+    let patient_jurisdiction = patient_jurisdiction(patient_id_hash);
+    let justification = justification.map(str::trim).filter(|j| !j.is_empty()).map(str::to_string);
+    let mut decision = CrossBorderDecision {
+        policy_id: None,
+        requester_jurisdiction: requester_jurisdiction.clone(),
+        patient_jurisdiction: patient_jurisdiction.clone(),
+        decision: "NOT_CROSS_BORDER".to_string(),
+        justification: justification.clone(),
+    };
+    let (Some(requester_jurisdiction), Some(patient_jurisdiction)) = (requester_jurisdiction, patient_jurisdiction) else {
+        return decision;
+    };
+    if requester_jurisdiction == patient_jurisdiction {
+        return decision;
+    }
+    let Some(policy) = policy_covering(&patient_jurisdiction) else {
+        decision.decision = "NO_POLICY".to_string();
+        return decision;
+    };
+    decision.policy_id = Some(policy.policy_id.clone());
+    let permitted = policy.patient_jurisdictions.iter()
+        .chain(&policy.permitted_requester_jurisdictions)
+        .any(|j| covers(j, &requester_jurisdiction));
+    decision.decision = if permitted {
+        "PERMITTED"
+    } else if policy.allow_emergency_override && justification.is_some() {
+        "EMERGENCY_OVERRIDE"
+    } else {
+        "DENIED"
+    }
+    .to_string();
+    decision
+}
+
+// Read replicas hold no tenants; the primary tells them which patients a policy covers
+pub(crate) fn policy_for_patient(patient_id_hash: &[u8]) -> Option<String> {
+    patient_jurisdiction(patient_id_hash).and_then(|j| policy_covering(&j)).map(|policy| policy.policy_id)
+}
+
+fn patient_jurisdiction(patient_id_hash: &[u8]) -> Option<String> {
+    PATIENT_HASH_INDEX.with(|index| index.borrow().get(patient_id_hash).cloned())
+        .and_then(|patient_id| tenants::patient_tenant(&patient_id))
+        .map(|tenant_id| tenants::effective_config(&tenant_id).jurisdiction)
+}
+
+fn policy_covering(patient_jurisdiction: &str) -> Option<CrossBorderPolicy> {
+    POLICIES.with(|p| {
+        p.borrow().values().find(|x| x.patient_jurisdictions.iter().any(|j| covers(j, patient_jurisdiction))).cloned()
+    })
+}
+
+fn covers(code: &str, jurisdiction: &str) -> bool {
+    jurisdiction == code || jurisdiction.strip_prefix(code).is_some_and(|rest| rest.starts_with('-'))
+}
@@ -0,0 +1,70 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+
+use crate::{directive_owner, validation, ConsentDirective, CONSENT_DIRECTIVES, EXECUTOR_AI_CANISTER_ID};
+
+// Wishes for online accounts and personal data, one per account: delete it, memorialize it, or hand
+// it to a named person. They ride on the directive like its consent items, so they are versioned,
+// amended and replayed with it; executor_ai reads them after death and instructs the services.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DigitalLegacyWish {
+    pub service: String,            // "GOOGLE", "META", "APPLE", ...; matched against executor_ai's legacy services
+    pub account_identifier: String, // the handle or address the service knows the account by
+    pub action: String,             // One of LEGACY_ACTIONS
+    pub transfer_to: Option<String>, // required for "TRANSFER"
+    pub note: Option<String>,
+}
+
+const DIRECTIVE_TYPE: &str = "DIGITAL_LEGACY";
+const LEGACY_ACTIONS: [&str; 3] = ["DELETE", "MEMORIALIZE", "TRANSFER"];
+const MAX_WISHES: usize = 50;
+
+// What executor_ai acts on; empty when the patient left none
+#[ic_cdk::query]
+fn get_digital_legacy_wishes(patient_id: String) -> Result<Vec<DigitalLegacyWish>, String> {
+    let requester = caller();
+    let executor = Principal::from_text(EXECUTOR_AI_CANISTER_ID).ok() == Some(requester);
+    if !executor && directive_owner(&patient_id) != Some(requester) && !ic_cdk::api::is_controller(&requester) {
+        return Err("Not authorized to read this patient's digital legacy wishes".to_string());
+    }
+    Ok(CONSENT_DIRECTIVES
+        .with(|d| d.borrow().get(&patient_id))
+        .and_then(|directive| directive.digital_legacy)
+        .unwrap_or_default())
+}
+
+// Normalizes the wishes in place; a DIGITAL_LEGACY directive must carry at least one
+pub(crate) fn validate(directive: &mut ConsentDirective) -> Result<(), String> {
+    if directive.directive_type == DIRECTIVE_TYPE && directive.digital_legacy.as_ref().is_none_or(|w| w.is_empty()) {
+        return Err(validation::invalid("digital_legacy", "a DIGITAL_LEGACY directive needs at least one wish"));
+    }
+    let Some(wishes) = directive.digital_legacy.as_mut() else {
+        return Ok(());
+    };
+    validation::collection("digital_legacy", wishes.len(), MAX_WISHES)?;
+    for wish in wishes.iter_mut() {
+        wish.service = validation::identifier("digital_legacy.service", &wish.service)?.to_uppercase();
+        wish.account_identifier = validation::text("digital_legacy.account_identifier", &wish.account_identifier, validation::MAX_REASON_BYTES)?;
+        if wish.account_identifier.is_empty() {
+            return Err(validation::invalid("digital_legacy.account_identifier", "must not be empty"));
+        }
+        wish.action = wish.action.trim().to_uppercase();
+        if !LEGACY_ACTIONS.contains(&wish.action.as_str()) {
+            return Err(validation::invalid("digital_legacy.action", &format!("must be one of {}", LEGACY_ACTIONS.join(", "))));
+        }
+        match (wish.action.as_str(), &wish.transfer_to) {
+            ("TRANSFER", None) => return Err(validation::invalid("digital_legacy.transfer_to", "names who receives the account")),
+            ("TRANSFER", Some(to)) => {
+                wish.transfer_to = Some(validation::text("digital_legacy.transfer_to", to, validation::MAX_REASON_BYTES)?);
+            }
+            (_, Some(_)) => return Err(validation::invalid("digital_legacy.transfer_to", "only applies to TRANSFER")),
+            _ => {}
+        }
+        if let Some(note) = &wish.note {
+            wish.note = Some(validation::text("digital_legacy.note", note, validation::MAX_REASON_BYTES)?);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+
+use crate::{directive_owner, validation, ConsentDirective, CONSENT_DIRECTIVES, EXECUTOR_AI_CANISTER_ID};
+
+// Funeral and disposition wishes: what happens to the body, the religious requirements around it,
+// and which funeral provider to use. Carried on the directive like the digital legacy wishes and
+// read by executor_ai for the post-death report to next-of-kin and the provider.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DispositionPreferences {
+    pub method: String, // One of DISPOSITION_METHODS
+    pub religious_requirements: Vec<String>,
+    pub funeral_provider_id: Option<String>, // a provider registered with executor_ai
+    pub funeral_home_name: Option<String>,   // as the patient wrote it, when the provider is not registered
+    pub notes: Option<String>,
+}
+
+const DIRECTIVE_TYPE: &str = "DISPOSITION";
+const DISPOSITION_METHODS: [&str; 6] = ["BURIAL", "CREMATION", "BODY_DONATION", "GREEN_BURIAL", "ENTOMBMENT", "NO_PREFERENCE"];
+const MAX_REQUIREMENTS: usize = 20;
+
+// What executor_ai reports after death; None when the patient left no preferences
+#[ic_cdk::query]
+fn get_disposition_preferences(patient_id: String) -> Result<Option<DispositionPreferences>, String> {
+    let requester = caller();
+    let executor = Principal::from_text(EXECUTOR_AI_CANISTER_ID).ok() == Some(requester);
+    if !executor && directive_owner(&patient_id) != Some(requester) && !ic_cdk::api::is_controller(&requester) {
+        return Err("Not authorized to read this patient's disposition preferences".to_string());
+    }
+    Ok(CONSENT_DIRECTIVES.with(|d| d.borrow().get(&patient_id)).and_then(|directive| directive.disposition))
+}
+
+// Normalizes the preferences in place; a DISPOSITION directive must carry them
+pub(crate) fn validate(directive: &mut ConsentDirective) -> Result<(), String> {
+    if directive.directive_type == DIRECTIVE_TYPE && directive.disposition.is_none() {
+        return Err(validation::invalid("disposition", "a DISPOSITION directive needs its preferences"));
+    }
+    let Some(preferences) = directive.disposition.as_mut() else {
+        return Ok(());
+    };
+    preferences.method = preferences.method.trim().to_uppercase();
+    if !DISPOSITION_METHODS.contains(&preferences.method.as_str()) {
+        return Err(validation::invalid("disposition.method", &format!("must be one of {}", DISPOSITION_METHODS.join(", "))));
+    }
+    validation::collection("disposition.religious_requirements", preferences.religious_requirements.len(), MAX_REQUIREMENTS)?;
+    preferences.religious_requirements = preferences.religious_requirements.iter()
+        .map(|r| validation::text("disposition.religious_requirements", r, validation::MAX_REASON_BYTES))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(provider_id) = &preferences.funeral_provider_id {
+        preferences.funeral_provider_id = Some(validation::identifier("disposition.funeral_provider_id", provider_id)?.to_uppercase());
+    }
+    if let Some(name) = &preferences.funeral_home_name {
+        preferences.funeral_home_name = Some(validation::text("disposition.funeral_home_name", name, validation::MAX_REASON_BYTES)?);
+    }
+    if let Some(notes) = &preferences.notes {
+        preferences.notes = Some(validation::text("disposition.notes", notes, validation::MAX_RATIONALE_BYTES)?);
+    }
+    Ok(())
+}
@@ -0,0 +1,364 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::clinician_summary::{self, ClinicianSummary};
+use crate::{access_letters, activation, anomaly, audit_buffer, clock, cross_border, directive_owner, hashing, honeytokens, identity, load_shedding, tenants, validation, ConsentDirective, CONSENT_DIRECTIVES, PATIENT_HASH_INDEX};
+
+// Mirrors emergency_bridge's PatientDirective
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct EmergencyDirective {
+    pub directive_type: String,
+    pub details: String,
+    pub confidence_score: f32,
+    pub timestamp: u64,
+    pub legal_validity: f32,
+    pub emergency_conditions: Vec<String>,
+}
+
+// Mirrors emergency_bridge's DirectiveLookup
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct EmergencyLookup {
+    pub directives: Vec<EmergencyDirective>,
+    pub clinician_summary: Option<ClinicianSummary>,
+    pub token_expires_at: Option<u64>, // So the bridge never serves a cached lookup past the token's life
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct EmergencyAccessToken {
+    pub token_hash: Vec<u8>,
+    pub requester: Principal,
+    pub issued_at: u64,
+    pub expires_at: u64,
+    pub revoked: bool,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct EmergencyAccessLog {
+    pub patient_id_hash: Vec<u8>,
+    pub requester: Principal,
+    pub via: Principal,
+    pub accessed_at: u64,
+    pub sequence: Option<u64>, // orders entries with the same accessed_at; None on entries archived before it was kept
+    pub outcome: String, // "GRANTED", "DENIED", "NOT_FOUND", "EXISTENCE_CHECK", "OFFLINE_EXPORT", "QUERY_VERIFIED", "TRANSLATION_SERVED"
+    pub directive_types: Vec<String>,
+    pub requester_jurisdiction: Option<String>, // None on entries logged before requests were tagged
+    pub cross_border: Option<cross_border::CrossBorderDecision>, // on emergency lookups only
+}
+
+thread_local! {
+    static EMERGENCY_TOKENS: std::cell::RefCell<BTreeMap<Vec<u8>, EmergencyAccessToken>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static EMERGENCY_ACCESS_LOG: std::cell::RefCell<Vec<EmergencyAccessLog>> =
+        std::cell::RefCell::new(Vec::new());
+}
+
+pub(crate) const EMERGENCY_BRIDGE_CANISTER_ID: &str = "rno2w-sqaaa-aaaaa-aaacq-cai";
+// The heap log holds recent accesses only; every entry also goes to the archive through the audit buffer.
+// Past the cap the oldest are dropped a batch at a time, so the drain is not paid on every access.
+const MAX_ACCESS_LOG_ENTRIES: usize = 100_000;
+const ACCESS_LOG_DROP_BATCH: usize = 1_000;
+const MAX_TOKEN_TTL_MINUTES: u64 = 24 * 60;
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+// emergency_bridge passes the situation, which it accepts up to this size
+const MAX_JUSTIFICATION_BYTES: usize = 4 * 1024;
+pub(crate) const INACTIVE_STATUSES: [&str; 4] = ["REVOKED", "WITHDRAWN", "INACTIVE", "EXPIRED"];
+
+// Controllers issue a hospital a bearer token bound to its principal; only the hash is kept
+#[ic_cdk::update]
+async fn issue_emergency_token(requester: Principal, ttl_minutes: u64) -> Result<String, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may issue emergency tokens".to_string());
+    }
+    if ttl_minutes == 0 || ttl_minutes > MAX_TOKEN_TTL_MINUTES {
+        return Err(format!("Token lifetime must be between 1 and {} minutes", MAX_TOKEN_TTL_MINUTES));
+    }
+
+    let (random,) = raw_rand().await.map_err(|(_, msg)| format!("Failed to generate token: {}", msg))?;
+    let token: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+    let issued_at = clock::now();
+    let record = EmergencyAccessToken {
+        token_hash: ic_cdk::api::sha256(token.as_bytes()),
+        requester,
+        issued_at,
+        expires_at: issued_at + ttl_minutes * NANOS_PER_MINUTE,
+        revoked: false,
+    };
+
+    EMERGENCY_TOKENS.with(|tokens| {
+        tokens.borrow_mut().insert(record.token_hash.clone(), record);
+    });
+    Ok(token)
+}
+
+#[ic_cdk::update]
+fn revoke_emergency_token(token: String) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may revoke emergency tokens".to_string());
+    }
+    EMERGENCY_TOKENS.with(|tokens| {
+        let mut tokens = tokens.borrow_mut();
+        let record = tokens.get_mut(&ic_cdk::api::sha256(token.as_bytes()))
+            .ok_or("Unknown emergency token")?;
+        record.revoked = true;
+        invalidate_bridge_cache(None, Some(record.requester));
+        Ok(())
+    })
+}
+
+// Called by emergency_bridge on behalf of a hospital; every attempt is logged. The justification is
+// what the requester says the emergency is, and only matters when the lookup crosses a border.
+#[ic_cdk::update]
+fn emergency_lookup(
+    patient_id_hash: Vec<u8>,
+    requester: Principal,
+    token: String,
+    emergency_justification: Option<String>
+) -> Result<Vec<EmergencyDirective>, String> {
+    let _permit = load_shedding::admit("EMERGENCY")?;
+    authorized_lookup(patient_id_hash, requester, &token, emergency_justification).map(|(_, directives)| directives)
+}
+
+// emergency_lookup plus the clinician summary, in the same round trip
+#[ic_cdk::update]
+fn emergency_lookup_with_summary(
+    patient_id_hash: Vec<u8>,
+    requester: Principal,
+    token: String,
+    emergency_justification: Option<String>
+) -> Result<EmergencyLookup, String> {
+    let _permit = load_shedding::admit("EMERGENCY")?;
+    let (patient_id_hash, directives) = authorized_lookup(patient_id_hash, requester, &token, emergency_justification)?;
+    Ok(EmergencyLookup {
+        directives,
+        clinician_summary: clinician_summary::for_hash(&patient_id_hash),
+        token_expires_at: token_expires_at(&token),
+    })
+}
+
+// Shared by identifier and bracelet lookups; returns the resolved hash alongside the directives
+pub(crate) fn authorized_lookup(
+    patient_id_hash: Vec<u8>,
+    requester: Principal,
+    token: &str,
+    emergency_justification: Option<String>
+) -> Result<(Vec<u8>, Vec<EmergencyDirective>), String> {
+    let justification = emergency_justification
+        .map(|j| validation::text("emergency_justification", &j, MAX_JUSTIFICATION_BYTES))
+        .transpose()?;
+    let via = caller();
+    let bridge = Principal::from_text(EMERGENCY_BRIDGE_CANISTER_ID).ok();
+
+    let authorized = if Some(via) != bridge && !ic_cdk::api::is_controller(&via) {
+        Err("Emergency lookups must come through emergency_bridge".to_string())
+    } else {
+        anomaly::check_throttle(requester).and_then(|_| validate_token(token, requester))
+    };
+    if let Err(e) = authorized {
+        log_access(&patient_id_hash, requester, via, "DENIED", vec![]);
+        return Err(e);
+    }
+
+    // A retired duplicate record resolves to the surviving one
+    let patient_id_hash = identity::canonical_patient_hash(&hashing::storage_key(&patient_id_hash));
+    let decision = cross_border::evaluate(requester, &patient_id_hash, justification.as_deref());
+    let log = |outcome: &str, directive_types: Vec<String>| {
+        log_access_at(&patient_id_hash, requester, via, outcome, directive_types, clock::now(), Some(decision.clone()));
+    };
+    if decision.decision == "DENIED" {
+        log("DENIED", vec![]);
+        return Err(format!(
+            "Cross-border policy {} does not permit disclosure to a requester in {} without an emergency justification",
+            decision.policy_id.as_deref().unwrap_or_default(),
+            decision.requester_jurisdiction.as_deref().unwrap_or_default()
+        ));
+    }
+    let active = active_directives(&patient_id_hash);
+    if active.is_empty() {
+        log("NOT_FOUND", vec![]);
+        return Err("No active directive found for patient".to_string());
+    }
+
+    log("GRANTED", active.iter().map(|d| d.directive_type.clone()).collect());
+    Ok((patient_id_hash, active))
+}
+
+// Wallet-card issuance: emergency_bridge signs what the patient themself may see
+#[ic_cdk::update]
+fn wallet_directive_summary(patient_id: String, requester: Principal) -> Result<(Vec<u8>, Vec<EmergencyDirective>), String> {
+    let _permit = load_shedding::admit("EMERGENCY")?;
+    let bridge = Principal::from_text(EMERGENCY_BRIDGE_CANISTER_ID).ok();
+    if Some(caller()) != bridge {
+        return Err("Wallet summaries are only issued through emergency_bridge".to_string());
+    }
+    if directive_owner(&patient_id) != Some(requester) {
+        return Err("Only the patient may issue a wallet token for their directives".to_string());
+    }
+
+    let patient_id_hash = hashing::patient_hash(&patient_id);
+    let active = active_directives(&patient_id_hash);
+    if active.is_empty() {
+        return Err("No active directive found for patient".to_string());
+    }
+    Ok((patient_id_hash, active))
+}
+
+#[ic_cdk::update]
+fn get_emergency_access_log(patient_id_hash: Vec<u8>) -> Result<Vec<EmergencyAccessLog>, String> {
+    honeytokens::touch_hash(&hashing::storage_key(&patient_id_hash), caller(), "Emergency access log read");
+    if !tenants::may_review_patient_hash(caller(), &patient_id_hash) {
+        return Err("Only the patient, their proxies or their tenant may read this access log".to_string());
+    }
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+    Ok(EMERGENCY_ACCESS_LOG.with(|log| {
+        log.borrow()
+            .iter()
+            .filter(|entry| entry.patient_id_hash == patient_id_hash)
+            .cloned()
+            .collect()
+    }))
+}
+
+// emergency_bridge keeps recent lookups briefly; tell it when one may have gone stale.
+// One-way, so an unreachable bridge never holds up the change itself.
+pub(crate) fn invalidate_bridge_cache(patient_id_hash: Option<Vec<u8>>, requester: Option<Principal>) {
+    let Ok(bridge) = Principal::from_text(EMERGENCY_BRIDGE_CANISTER_ID) else {
+        return;
+    };
+    if let Err(code) = ic_cdk::notify(bridge, "invalidate_lookup_cache", (patient_id_hash, requester)) {
+        ic_cdk::println!("⚠️ Emergency lookup cache invalidation not sent: {:?}", code);
+    }
+}
+
+pub(crate) fn rekey_patient(old_hash: &[u8], new_hash: &[u8]) {
+    EMERGENCY_ACCESS_LOG.with(|log| {
+        for entry in log.borrow_mut().iter_mut().filter(|e| e.patient_id_hash == old_hash) {
+            entry.patient_id_hash = new_hash.to_vec();
+        }
+    });
+}
+
+// Directives that are in force and whose activation conditions are met
+pub(crate) fn active_directives(patient_id_hash: &[u8]) -> Vec<EmergencyDirective> {
+    PATIENT_HASH_INDEX
+        .with(|index| index.borrow().get(patient_id_hash).cloned())
+        .and_then(|patient_id| CONSENT_DIRECTIVES.with(|d| d.borrow().get(&patient_id)))
+        .into_iter()
+        .filter(|d| !INACTIVE_STATUSES.contains(&d.status.as_str()))
+        .filter(|d| activation::activation_status(patient_id_hash.to_vec(), d.directive_type.clone()).active)
+        .map(|d| to_emergency_directive(&d))
+        .collect()
+}
+
+pub(crate) fn validate_token(token: &str, requester: Principal) -> Result<(), String> {
+    let record = EMERGENCY_TOKENS.with(|tokens| {
+        tokens.borrow().get(&ic_cdk::api::sha256(token.as_bytes())).cloned()
+    }).ok_or("Invalid emergency token")?;
+
+    if record.revoked {
+        return Err("Emergency token has been revoked".to_string());
+    }
+    if clock::now() > record.expires_at {
+        return Err("Emergency token has expired".to_string());
+    }
+    if record.requester != requester {
+        return Err("Emergency token was not issued to this requester".to_string());
+    }
+    Ok(())
+}
+
+pub(crate) fn token_expires_at(token: &str) -> Option<u64> {
+    EMERGENCY_TOKENS.with(|tokens| tokens.borrow().get(&ic_cdk::api::sha256(token.as_bytes())).map(|t| t.expires_at))
+}
+
+// Revoked and expired tokens still count: a receipt may describe a read made before either happened
+pub(crate) fn token_issued_to(token_hash: &[u8], requester: Principal) -> bool {
+    EMERGENCY_TOKENS.with(|tokens| tokens.borrow().get(token_hash).is_some_and(|t| t.requester == requester))
+}
+
+// Unexpired tokens, revoked ones included, so a read replica honours revocations too
+pub(crate) fn live_tokens() -> Vec<EmergencyAccessToken> {
+    let now = clock::now();
+    EMERGENCY_TOKENS.with(|tokens| tokens.borrow().values().filter(|t| t.expires_at >= now).cloned().collect())
+}
+
+pub(crate) fn replace_tokens(tokens: Vec<EmergencyAccessToken>) {
+    EMERGENCY_TOKENS.with(|t| *t.borrow_mut() = tokens.into_iter().map(|token| (token.token_hash.clone(), token)).collect());
+}
+
+pub(crate) fn log_access(patient_id_hash: &[u8], requester: Principal, via: Principal, outcome: &str, directive_types: Vec<String>) {
+    log_access_at(patient_id_hash, requester, via, outcome, directive_types, clock::now(), None);
+}
+
+// For accesses recorded after the fact, such as flushed query receipts, or with a cross-border decision
+pub(crate) fn log_access_at(
+    patient_id_hash: &[u8],
+    requester: Principal,
+    via: Principal,
+    outcome: &str,
+    directive_types: Vec<String>,
+    accessed_at: u64,
+    cross_border: Option<cross_border::CrossBorderDecision>,
+) {
+    ic_cdk::println!(
+        "AUDIT: Emergency access - Requester: {} - Outcome: {} - Time: {}",
+        requester.to_text(),
+        outcome,
+        accessed_at
+    );
+    let entry = EmergencyAccessLog {
+        patient_id_hash: patient_id_hash.to_vec(),
+        requester,
+        via,
+        accessed_at,
+        sequence: Some(clock::next_sequence()),
+        outcome: outcome.to_string(),
+        directive_types,
+        requester_jurisdiction: cross_border::requester_jurisdiction(requester),
+        cross_border,
+    };
+    EMERGENCY_ACCESS_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        log.push(entry.clone());
+        if log.len() > MAX_ACCESS_LOG_ENTRIES {
+            log.drain(..ACCESS_LOG_DROP_BATCH);
+        }
+    });
+    access_letters::record_access(&entry);
+    anomaly::observe(&entry);
+    honeytokens::touch_hash(&entry.patient_id_hash, requester, &format!("Emergency access ({})", outcome));
+    // Archived in batches by timer, so the lookup never waits on the archive canister
+    audit_buffer::enqueue(entry);
+}
+
+fn to_emergency_directive(directive: &ConsentDirective) -> EmergencyDirective {
+    // An unsigned directive is disclosed but flagged as lower legal weight
+    let legal_validity = if directive.signature.is_empty() { 0.5 } else { 1.0 };
+    EmergencyDirective {
+        directive_type: directive.directive_type.clone(),
+        details: format!("Directive verified on-chain - {} conditions apply", directive.directive_type),
+        confidence_score: legal_validity,
+        timestamp: directive.timestamp,
+        legal_validity,
+        emergency_conditions: emergency_conditions(&directive.directive_type, &directive.consent_items),
+    }
+}
+
+pub(crate) fn emergency_conditions(directive_type: &str, consent_items: &[String]) -> Vec<String> {
+    let conditions: &[&str] = match directive_type {
+        "DNR" => &["No resuscitation", "No mechanical ventilation", "Comfort care only"],
+        "ORGAN_DONATION" => &["Organ harvesting authorized", "Contact organ network", "Time-sensitive coordination required"],
+        "DATA_CONSENT" => &["Research data sharing authorized", "Anonymization required"],
+        "DIGITAL_LEGACY" => &["Online accounts and personal data only", "No bearing on emergency treatment"],
+        "DISPOSITION" => &["Funeral and disposition wishes only", "No bearing on emergency treatment"],
+        "WHOLE_BODY_DONATION" => &["Whole-body donation to medical science after death", "No bearing on emergency treatment"],
+        "AUTOPSY" => &["Autopsy consent or refusal only", "No bearing on emergency treatment"],
+        "RESEARCH_ENROLLMENT" => &["Emergency research enrollment preferences", "Check per trial category before EFIC enrollment"],
+        _ if !consent_items.is_empty() => return consent_items.to_vec(),
+        _ => &["Standard directive conditions apply"],
+    };
+    conditions.iter().map(|c| c.to_string()).collect()
+}
@@ -0,0 +1,372 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+
+use crate::storage::{self, Store};
+use crate::{
+    clinician_summary, clock, emergency, hashing, i18n, merkle, notify_executor_of_consent, point_in_time, replication, shards, tenants,
+    webhooks, AmendmentProposal, ConsentDirective, EmergencyContact, PHIMetadata, ProxyGrant, VisibilityPreferences,
+    AMENDMENT_PROPOSALS, CONSENT_DIRECTIVES,
+    CONSENT_DIRECTIVE_VERSIONS, DIRECTIVE_OWNERS, EMERGENCY_CONTACTS, NEXT_CONTACT_SEQ, OWNER_SIGNING_KEYS, PATIENT_HASH_INDEX,
+    PHI_METADATA, PROXY_GRANTS, VISIBILITY_PREFERENCES,
+};
+
+// Every change to core directive state; endpoints validate, then record one of these
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub enum DirectiveEventKind {
+    DirectiveStored(PHIMetadata),
+    MetadataArchived { patient_id_hash: Vec<u8> },
+    MetadataSharded { patient_id_hash: Vec<u8>, shard_id: String, shard_key: Vec<u8> },
+    ConsentUpdated { directive: ConsentDirective, version: u64 },
+    OwnerAssigned { patient_id: String, owner: Principal, public_key: Option<Vec<u8>> },
+    VisibilityUpdated { patient_id_hash: Vec<u8>, preferences: VisibilityPreferences },
+    ContactRegistered { patient_id_hash: Vec<u8>, contact: EmergencyContact },
+    ContactRemoved { patient_id_hash: Vec<u8>, contact_id: String },
+    ProxyGranted { patient_id: String, grant: ProxyGrant },
+    AmendmentProposed(AmendmentProposal),
+    AmendmentDecided {
+        proposal_id: String,
+        status: String,
+        decided_by: Principal,
+        decided_at: u64,
+        acceptance_signature: Option<Vec<u8>>,
+        resulting_version: Option<u64>,
+    },
+    PatientRekeyed { old_hash: Vec<u8>, new_hash: Vec<u8> },
+    TenantSaved(tenants::Tenant),
+    PatientEnrolled { patient_id: String, tenant_id: String },
+    SharingAgreementSaved(tenants::DataSharingAgreement),
+    TenantDefaultsSaved(tenants::TenantConfigDefaults),
+    EnrollmentConsented { patient_id: String, tenant_id: Option<String> },
+    PatientUnenrolled { patient_id: String },
+    LocaleSet { patient_id_hash: Vec<u8>, locale: Option<String> },
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DirectiveEvent {
+    pub sequence: u64,
+    pub recorded_at: u64,
+    pub recorded_by: Principal,
+    pub kind: DirectiveEventKind,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DirectiveEventPage {
+    pub events: Vec<DirectiveEvent>,
+    pub next_cursor: u64,
+    pub head: u64,
+}
+
+thread_local! {
+    // The source of truth, keyed by sequence; the maps in lib.rs are a projection of it. It lives in
+    // stable memory, so upgrades never copy it.
+    static DIRECTIVE_EVENTS: std::cell::RefCell<Box<dyn Store<u64, DirectiveEvent>>> =
+        std::cell::RefCell::new(storage::backend(storage::DIRECTIVE_EVENTS_MEMORY));
+}
+
+const MAX_EVENT_PAGE: u32 = 500;
+
+// Append and apply; the sequence number doubles as the consumer cursor
+pub(crate) fn record(kind: DirectiveEventKind) -> u64 {
+    // Endpoints validate before they record, so this is the one place every write passes
+    if replication::is_replica() {
+        ic_cdk::trap("This canister is a read replica; send writes to the primary");
+    }
+    let event = DIRECTIVE_EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = DirectiveEvent {
+            sequence: events.len(),
+            recorded_at: clock::now(),
+            recorded_by: caller(),
+            kind,
+        };
+        events.insert(event.sequence, event.clone());
+        event
+    });
+    apply(&event);
+    publish_side_effects(&event);
+    point_in_time::on_event_recorded(event.sequence);
+    event.sequence
+}
+
+// Events carry patient IDs and contact addresses, so the stream is for controllers' projectors only
+#[ic_cdk::query]
+fn get_directive_events(cursor: u64, limit: u32) -> Result<DirectiveEventPage, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may read the directive event stream".to_string());
+    }
+    DIRECTIVE_EVENTS.with(|events| {
+        let events = events.borrow();
+        let head = events.len();
+        let end = head.min(cursor.saturating_add(limit.min(MAX_EVENT_PAGE) as u64));
+        let page: Vec<DirectiveEvent> = (cursor..end).filter_map(|sequence| events.get(&sequence)).collect();
+        Ok(DirectiveEventPage {
+            next_cursor: cursor + page.len() as u64,
+            head,
+            events: page,
+        })
+    })
+}
+
+// Discard the derived maps and replay the log; side effects (webhooks, cache invalidation) are not repeated
+#[ic_cdk::update]
+fn rebuild_directive_state() -> Result<u64, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may rebuild directive state".to_string());
+    }
+    Ok(replay())
+}
+
+pub(crate) fn head() -> u64 {
+    DIRECTIVE_EVENTS.with(|events| events.borrow().len())
+}
+
+pub(crate) fn events_from(sequence: u64) -> Vec<DirectiveEvent> {
+    DIRECTIVE_EVENTS.with(|events| {
+        let events = events.borrow();
+        (sequence..events.len()).filter_map(|sequence| events.get(&sequence)).collect()
+    })
+}
+
+// The log is already in stable memory; releases that carried it through pre_upgrade hand over
+// their copy once, into an empty log
+pub(crate) fn restore(legacy_events: Option<Vec<DirectiveEvent>>) {
+    if let Some(legacy_events) = legacy_events {
+        DIRECTIVE_EVENTS.with(|events| {
+            let mut events = events.borrow_mut();
+            if events.len() == 0 {
+                for event in legacy_events {
+                    events.insert(event.sequence, event);
+                }
+            }
+        });
+    }
+    replay();
+}
+
+fn replay() -> u64 {
+    PHI_METADATA.with(|m| m.borrow_mut().clear());
+    shards::SHARD_LOCATIONS.with(|m| m.borrow_mut().clear());
+    clinician_summary::CLINICIAN_SUMMARIES.with(|m| m.borrow_mut().clear());
+    CONSENT_DIRECTIVES.with(|m| m.borrow_mut().clear());
+    CONSENT_DIRECTIVE_VERSIONS.with(|m| m.borrow_mut().clear());
+    DIRECTIVE_OWNERS.with(|m| m.borrow_mut().clear());
+    OWNER_SIGNING_KEYS.with(|m| m.borrow_mut().clear());
+    VISIBILITY_PREFERENCES.with(|m| m.borrow_mut().clear());
+    EMERGENCY_CONTACTS.with(|m| m.borrow_mut().clear());
+    PROXY_GRANTS.with(|m| m.borrow_mut().clear());
+    AMENDMENT_PROPOSALS.with(|m| m.borrow_mut().clear());
+    PATIENT_HASH_INDEX.with(|m| m.borrow_mut().clear());
+    tenants::TENANTS.with(|m| m.borrow_mut().clear());
+    tenants::PATIENT_TENANTS.with(|m| m.borrow_mut().clear());
+    tenants::ENROLLMENT_CONSENTS.with(|m| m.borrow_mut().clear());
+    tenants::SHARING_AGREEMENTS.with(|m| m.borrow_mut().clear());
+    tenants::TENANT_USAGE.with(|m| m.borrow_mut().clear());
+    tenants::TENANT_DEFAULTS.with(|d| *d.borrow_mut() = tenants::initial_defaults());
+    i18n::PATIENT_LOCALES.with(|m| m.borrow_mut().clear());
+    merkle::clear();
+
+    // One event at a time out of stable memory, never the whole log on the heap
+    let head = head();
+    for sequence in 0..head {
+        if let Some(event) = DIRECTIVE_EVENTS.with(|events| events.borrow().get(&sequence)) {
+            apply(&event);
+        }
+    }
+    merkle::certify_root();
+    point_in_time::rebuild_snapshots();
+    head
+}
+
+// Hash-keyed events carry the storage key of their day; PatientRekeyed carries them forward on replay
+fn apply(event: &DirectiveEvent) {
+    match &event.kind {
+        DirectiveEventKind::DirectiveStored(metadata) => {
+            PHI_METADATA.with(|m| m.borrow_mut().insert(metadata.patient_id_hash.clone(), metadata.clone()));
+        }
+        DirectiveEventKind::MetadataArchived { patient_id_hash } => {
+            PHI_METADATA.with(|m| m.borrow_mut().remove(patient_id_hash));
+        }
+        DirectiveEventKind::MetadataSharded { patient_id_hash, shard_id, shard_key } => {
+            PHI_METADATA.with(|m| m.borrow_mut().remove(patient_id_hash));
+            shards::SHARD_LOCATIONS.with(|l| {
+                l.borrow_mut().insert(
+                    patient_id_hash.clone(),
+                    shards::ShardLocation { shard_id: shard_id.clone(), shard_key: shard_key.clone() },
+                )
+            });
+        }
+        DirectiveEventKind::ConsentUpdated { directive, version } => {
+            merkle::append_directive_version(&directive.patient_id, *version, event.recorded_at, directive);
+            CONSENT_DIRECTIVE_VERSIONS.with(|versions| {
+                versions.borrow_mut().entry(directive.patient_id.clone()).or_default().push(directive.clone());
+            });
+            PATIENT_HASH_INDEX.with(|index| {
+                let patient_id_hash = hashing::storage_key(&hashing::patient_hash(&directive.patient_id));
+                index.borrow_mut().insert(patient_id_hash, directive.patient_id.clone());
+            });
+            CONSENT_DIRECTIVES.with(|d| d.borrow_mut().insert(directive.patient_id.clone(), directive.clone()));
+            tenants::count_directive_write(&directive.patient_id, event.recorded_at);
+            clinician_summary::refresh_patient(&directive.patient_id, event.recorded_at);
+        }
+        DirectiveEventKind::OwnerAssigned { patient_id, owner, public_key } => {
+            DIRECTIVE_OWNERS.with(|o| o.borrow_mut().insert(patient_id.clone(), *owner));
+            OWNER_SIGNING_KEYS.with(|k| match public_key {
+                Some(public_key) => k.borrow_mut().insert(patient_id.clone(), public_key.clone()),
+                None => k.borrow_mut().remove(patient_id),
+            });
+        }
+        DirectiveEventKind::VisibilityUpdated { patient_id_hash, preferences } => {
+            VISIBILITY_PREFERENCES.with(|p| {
+                p.borrow_mut().insert(patient_id_hash.clone(), preferences.clone());
+            });
+        }
+        DirectiveEventKind::ContactRegistered { patient_id_hash, contact } => {
+            EMERGENCY_CONTACTS.with(|c| {
+                c.borrow_mut().entry(patient_id_hash.clone()).or_default().push(contact.clone());
+            });
+            // Keep contact IDs unique when the sequence is rebuilt from the log
+            if let Some(seq) = contact.contact_id.strip_prefix("CONTACT_").and_then(|s| s.parse::<u64>().ok()) {
+                NEXT_CONTACT_SEQ.with(|n| {
+                    let mut n = n.borrow_mut();
+                    *n = (*n).max(seq);
+                });
+            }
+            clinician_summary::refresh(patient_id_hash, event.recorded_at);
+        }
+        DirectiveEventKind::ContactRemoved { patient_id_hash, contact_id } => {
+            EMERGENCY_CONTACTS.with(|c| {
+                if let Some(list) = c.borrow_mut().get_mut(patient_id_hash) {
+                    list.retain(|x| &x.contact_id != contact_id);
+                }
+            });
+            clinician_summary::refresh(patient_id_hash, event.recorded_at);
+        }
+        DirectiveEventKind::ProxyGranted { patient_id, grant } => {
+            PROXY_GRANTS.with(|grants| {
+                let mut grants = grants.borrow_mut();
+                let list = grants.entry(patient_id.clone()).or_default();
+                list.retain(|g| g.proxy != grant.proxy);
+                list.push(grant.clone());
+            });
+            clinician_summary::refresh_patient(patient_id, event.recorded_at);
+        }
+        DirectiveEventKind::AmendmentProposed(proposal) => {
+            AMENDMENT_PROPOSALS.with(|p| p.borrow_mut().insert(proposal.proposal_id.clone(), proposal.clone()));
+        }
+        DirectiveEventKind::AmendmentDecided {
+            proposal_id,
+            status,
+            decided_by,
+            decided_at,
+            acceptance_signature,
+            resulting_version,
+        } => {
+            AMENDMENT_PROPOSALS.with(|proposals| {
+                if let Some(p) = proposals.borrow_mut().get_mut(proposal_id) {
+                    p.status = status.clone();
+                    p.decided_at = Some(*decided_at);
+                    p.decided_by = Some(*decided_by);
+                    p.acceptance_signature = acceptance_signature.clone();
+                    p.resulting_version = *resulting_version;
+                }
+            });
+        }
+        DirectiveEventKind::PatientRekeyed { old_hash, new_hash } => {
+            PHI_METADATA.with(|phi_map| {
+                let mut phi_map = phi_map.borrow_mut();
+                if let Some(mut metadata) = phi_map.remove(old_hash) {
+                    metadata.patient_id_hash = new_hash.clone();
+                    phi_map.insert(new_hash.clone(), metadata);
+                }
+            });
+            shards::SHARD_LOCATIONS.with(|l| hashing::rekey_entry(&mut l.borrow_mut(), old_hash, new_hash));
+            clinician_summary::CLINICIAN_SUMMARIES.with(|s| hashing::rekey_entry(&mut s.borrow_mut(), old_hash, new_hash));
+            VISIBILITY_PREFERENCES.with(|prefs| hashing::rekey_entry(&mut prefs.borrow_mut(), old_hash, new_hash));
+            EMERGENCY_CONTACTS.with(|contacts| hashing::rekey_entry(&mut contacts.borrow_mut(), old_hash, new_hash));
+            i18n::PATIENT_LOCALES.with(|locales| hashing::rekey_entry(&mut locales.borrow_mut(), old_hash, new_hash));
+            PATIENT_HASH_INDEX.with(|index| hashing::rekey_entry(&mut index.borrow_mut(), old_hash, new_hash));
+        }
+        DirectiveEventKind::TenantSaved(tenant) => {
+            tenants::TENANTS.with(|t| t.borrow_mut().insert(tenant.tenant_id.clone(), tenant.clone()));
+        }
+        DirectiveEventKind::PatientEnrolled { patient_id, tenant_id } => {
+            // Enrolling with a second tenant is a transfer: the first stops counting the patient
+            if let Some(previous) = tenants::PATIENT_TENANTS.with(|p| p.borrow_mut().insert(patient_id.clone(), tenant_id.clone())) {
+                tenants::count_unenrollment(&previous);
+            }
+            tenants::count_enrollment(tenant_id);
+            tenants::ENROLLMENT_CONSENTS.with(|c| c.borrow_mut().remove(patient_id));
+        }
+        DirectiveEventKind::EnrollmentConsented { patient_id, tenant_id } => {
+            tenants::ENROLLMENT_CONSENTS.with(|c| match tenant_id {
+                Some(tenant_id) => c.borrow_mut().insert(patient_id.clone(), tenant_id.clone()),
+                None => c.borrow_mut().remove(patient_id),
+            });
+        }
+        DirectiveEventKind::PatientUnenrolled { patient_id } => {
+            if let Some(previous) = tenants::PATIENT_TENANTS.with(|p| p.borrow_mut().remove(patient_id)) {
+                tenants::count_unenrollment(&previous);
+            }
+        }
+        DirectiveEventKind::LocaleSet { patient_id_hash, locale } => {
+            i18n::PATIENT_LOCALES.with(|locales| match locale {
+                Some(locale) => locales.borrow_mut().insert(patient_id_hash.clone(), locale.clone()),
+                None => locales.borrow_mut().remove(patient_id_hash),
+            });
+        }
+        DirectiveEventKind::SharingAgreementSaved(agreement) => {
+            tenants::SHARING_AGREEMENTS.with(|a| {
+                a.borrow_mut().insert(agreement.agreement_id.clone(), agreement.clone());
+            });
+        }
+        DirectiveEventKind::TenantDefaultsSaved(defaults) => {
+            tenants::TENANT_DEFAULTS.with(|d| *d.borrow_mut() = defaults.clone());
+        }
+    }
+}
+
+// Outward-facing consequences of a live event; never run during replay
+fn publish_side_effects(event: &DirectiveEvent) {
+    match &event.kind {
+        DirectiveEventKind::ConsentUpdated { directive, version } => {
+            merkle::certify_root();
+
+            let patient_id_hash = hashing::patient_hash(&directive.patient_id);
+            emergency::invalidate_bridge_cache(Some(patient_id_hash.clone()), None);
+            replication::mark_patient_changed(&hashing::storage_key(&patient_id_hash));
+            let event_type = if emergency::INACTIVE_STATUSES.contains(&directive.status.as_str()) {
+                "DIRECTIVE_REVOKED"
+            } else if *version == 1 {
+                "DIRECTIVE_CREATED"
+            } else {
+                "DIRECTIVE_UPDATED"
+            };
+            webhooks::publish(
+                event_type,
+                &patient_id_hash,
+                &format!("{}_v{}", directive.directive_type, version),
+            );
+            notify_executor_of_consent(directive);
+        }
+        DirectiveEventKind::VisibilityUpdated { patient_id_hash, .. } => {
+            emergency::invalidate_bridge_cache(Some(patient_id_hash.clone()), None);
+            replication::mark_patient_changed(patient_id_hash);
+        }
+        DirectiveEventKind::PatientRekeyed { old_hash, .. } => {
+            emergency::invalidate_bridge_cache(Some(old_hash.clone()), None);
+        }
+        // The clinician summary names the proxy contact
+        DirectiveEventKind::ContactRegistered { patient_id_hash, .. }
+        | DirectiveEventKind::ContactRemoved { patient_id_hash, .. } => {
+            emergency::invalidate_bridge_cache(Some(patient_id_hash.clone()), None);
+            replication::mark_patient_changed(patient_id_hash);
+        }
+        DirectiveEventKind::ProxyGranted { patient_id, .. } => {
+            let patient_id_hash = hashing::patient_hash(patient_id);
+            emergency::invalidate_bridge_cache(Some(patient_id_hash.clone()), None);
+            replication::mark_patient_changed(&hashing::storage_key(&patient_id_hash));
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,97 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{clock, emergency, hashing, identity, CONSENT_DIRECTIVES, PATIENT_HASH_INDEX};
+
+// Deliberately minimal: no details, conditions or timestamps
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DirectiveExistence {
+    pub has_directive: bool,
+    pub directive_types: Vec<String>,
+}
+
+#[derive(Clone, Copy)]
+struct RateWindow {
+    started_at: u64,
+    count: u32,
+}
+
+thread_local! {
+    static CALLER_WINDOWS: std::cell::RefCell<BTreeMap<Principal, RateWindow>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static GLOBAL_WINDOW: std::cell::RefCell<RateWindow> =
+        std::cell::RefCell::new(RateWindow { started_at: 0, count: 0 });
+}
+
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+const CALLER_WINDOW_MINUTES: u64 = 10;
+const CALLER_LIMIT: u32 = 5;
+// Field-device principals are cheap to mint, so a global cap bounds enumeration
+const GLOBAL_WINDOW_MINUTES: u64 = 1;
+const GLOBAL_LIMIT: u32 = 60;
+
+// Open to field devices without a hospital proof, so the per-caller and global limits are the only gate.
+// An update rather than a query: rate-limit counters must persist between calls
+#[ic_cdk::update]
+fn check_directive_exists(patient_id_hash: Vec<u8>) -> Result<DirectiveExistence, String> {
+    let requester = caller();
+    if requester == Principal::anonymous() {
+        return Err("Anonymous callers may not check directive existence".to_string());
+    }
+    consume_rate_limit(requester)?;
+
+    let storage_hash = identity::canonical_patient_hash(&hashing::storage_key(&patient_id_hash));
+    let directive_types: Vec<String> = PATIENT_HASH_INDEX
+        .with(|index| index.borrow().get(&storage_hash).cloned())
+        .and_then(|patient_id| CONSENT_DIRECTIVES.with(|d| d.borrow().get(&patient_id)))
+        .filter(|d| !emergency::INACTIVE_STATUSES.contains(&d.status.as_str()))
+        .map(|d| vec![d.directive_type])
+        .unwrap_or_default();
+
+    emergency::log_access(&storage_hash, requester, requester, "EXISTENCE_CHECK", directive_types.clone());
+    Ok(DirectiveExistence {
+        has_directive: !directive_types.is_empty(),
+        directive_types,
+    })
+}
+
+pub(crate) fn consume_rate_limit(requester: Principal) -> Result<(), String> {
+    let now = clock::now();
+
+    GLOBAL_WINDOW.with(|w| {
+        let mut window = w.borrow_mut();
+        if now.saturating_sub(window.started_at) >= GLOBAL_WINDOW_MINUTES * NANOS_PER_MINUTE {
+            *window = RateWindow { started_at: now, count: 0 };
+            // Piggyback cleanup of expired per-caller windows on the global rollover
+            CALLER_WINDOWS.with(|c| {
+                c.borrow_mut().retain(|_, w| now.saturating_sub(w.started_at) < CALLER_WINDOW_MINUTES * NANOS_PER_MINUTE);
+            });
+        }
+        if window.count >= GLOBAL_LIMIT {
+            return Err("Directive existence checks are temporarily throttled".to_string());
+        }
+        Ok(())
+    })?;
+
+    CALLER_WINDOWS.with(|c| {
+        let mut windows = c.borrow_mut();
+        let window = windows.entry(requester).or_insert(RateWindow { started_at: now, count: 0 });
+        if now.saturating_sub(window.started_at) >= CALLER_WINDOW_MINUTES * NANOS_PER_MINUTE {
+            *window = RateWindow { started_at: now, count: 0 };
+        }
+        if window.count >= CALLER_LIMIT {
+            return Err(format!(
+                "Rate limit exceeded: {} checks per {} minutes",
+                CALLER_LIMIT, CALLER_WINDOW_MINUTES
+            ));
+        }
+        window.count += 1;
+        Ok(())
+    })?;
+
+    GLOBAL_WINDOW.with(|w| w.borrow_mut().count += 1);
+    Ok(())
+}
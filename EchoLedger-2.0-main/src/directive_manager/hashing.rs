@@ -0,0 +1,389 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::access_letters::LetterState;
+use crate::audit_buffer::AuditBufferState;
+use crate::activation::ActivationState;
+use crate::challenge::{self, ChallengeState};
+use crate::identity::IdentityState;
+use crate::webhooks::{self, WebhookState};
+use crate::admins::{self, AdminOperation, AdminState};
+use crate::compliance::{self, ComplianceState};
+use crate::honeytokens::{self, HoneytokenState};
+use crate::events::{self, DirectiveEvent};
+use crate::replication::ReplicationState;
+use crate::shards::ShardState;
+use crate::{
+    access_letters, activation, audit_buffer, bracelet, clock, directive_owner, emergency, identity, key_lifecycle, load_shedding, replication,
+    reverification,
+    shards, storage, EXECUTOR_AI_CANISTER_ID, PATIENT_HASH_INDEX,
+};
+
+// Key metadata only; key material never leaves the canister
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PatientHashKey {
+    pub version: u32,
+    pub created_at: u64,
+    pub status: String, // "ACTIVE", "RETIRING", "RETIRED"
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct HashMigration {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub started_at: u64,
+    pub total_patients: u64,
+    pub migrated_patients: u64,
+    pub completed_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct SealedKey {
+    metadata: PatientHashKey,
+    secret: Vec<u8>,
+}
+
+thread_local! {
+    static HASH_KEYS: std::cell::RefCell<BTreeMap<u32, SealedKey>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    // Dual-read during rotation: new hash -> old hash until the patient is re-keyed...
+    static PENDING_REKEYS: std::cell::RefCell<BTreeMap<Vec<u8>, Vec<u8>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    // ...then old hash -> new hash for callers still holding the old one, until retirement
+    static FORWARD_ALIASES: std::cell::RefCell<BTreeMap<Vec<u8>, Vec<u8>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static HASH_MIGRATION: std::cell::RefCell<Option<HashMigration>> = std::cell::RefCell::new(None);
+}
+
+// Version 0 is the original unkeyed SHA-256 scheme
+const LEGACY_KEY_VERSION: u32 = 0;
+const HMAC_BLOCK_SIZE: usize = 64;
+
+// Generates a fresh secret and queues every known patient for re-keying
+#[ic_cdk::update]
+async fn rotate_patient_hash_key() -> Result<PatientHashKey, String> {
+    admins::authorize(AdminOperation::RotatePatientHashKey)?;
+    rotate_key().await
+}
+
+// Also run by key_lifecycle when the active key outlives its rotation policy
+pub(crate) async fn rotate_key() -> Result<PatientHashKey, String> {
+    if HASH_KEYS.with(|keys| keys.borrow().values().any(|k| k.metadata.status == "RETIRING")) {
+        return Err("Finish migrating and retire the previous key before rotating again".to_string());
+    }
+
+    let (secret,) = raw_rand().await.map_err(|(_, msg)| format!("Failed to generate key: {}", msg))?;
+    Ok(install_rotated_key(secret))
+}
+
+// The new key becomes active at once; old hashes keep resolving through PENDING_REKEYS until migrated
+pub(crate) fn install_rotated_key(secret: Vec<u8>) -> PatientHashKey {
+    let from_version = current_version();
+    let to_version = from_version + 1;
+    let metadata = PatientHashKey {
+        version: to_version,
+        created_at: clock::now(),
+        status: "ACTIVE".to_string(),
+    };
+
+    HASH_KEYS.with(|keys| {
+        let mut keys = keys.borrow_mut();
+        if let Some(previous) = keys.get_mut(&from_version) {
+            previous.metadata.status = "RETIRING".to_string();
+        }
+        keys.insert(to_version, SealedKey { metadata: metadata.clone(), secret });
+    });
+
+    // Only patients with a committed directive can be re-derived; the index holds their ids
+    let patient_ids: Vec<String> = PATIENT_HASH_INDEX.with(|index| index.borrow().values().cloned().collect());
+    PENDING_REKEYS.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        for patient_id in &patient_ids {
+            pending.insert(hash_with(to_version, patient_id), hash_with(from_version, patient_id));
+        }
+    });
+    HASH_MIGRATION.with(|m| {
+        *m.borrow_mut() = Some(HashMigration {
+            from_version,
+            to_version,
+            started_at: clock::now(),
+            total_patients: patient_ids.len() as u64,
+            migrated_patients: 0,
+            completed_at: if patient_ids.is_empty() { Some(clock::now()) } else { None },
+        });
+    });
+
+    ic_cdk::println!("AUDIT: Patient hash key rotated to version {}", to_version);
+    metadata
+}
+
+// Migration tooling: move a batch of patients' records from old-key to new-key hashes
+#[ic_cdk::update]
+fn migrate_patient_hash_keys(batch_size: u32) -> Result<HashMigration, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may run hash key migration".to_string());
+    }
+    migrate_batch(batch_size)
+}
+
+pub(crate) fn migrate_batch(batch_size: u32) -> Result<HashMigration, String> {
+    let _permit = load_shedding::admit("BULK")?;
+    let batch: Vec<(Vec<u8>, Vec<u8>)> = PENDING_REKEYS.with(|pending| {
+        pending.borrow().iter().take(batch_size as usize).map(|(n, o)| (n.clone(), o.clone())).collect()
+    });
+
+    for (new_hash, old_hash) in &batch {
+        crate::rekey_patient(old_hash, new_hash);
+        activation::rekey_patient(old_hash, new_hash);
+        bracelet::rekey_patient(old_hash, new_hash);
+        identity::rekey_patient(old_hash, new_hash);
+        emergency::rekey_patient(old_hash, new_hash);
+        access_letters::rekey_patient(old_hash, new_hash);
+
+        PENDING_REKEYS.with(|pending| pending.borrow_mut().remove(new_hash));
+        FORWARD_ALIASES.with(|aliases| aliases.borrow_mut().insert(old_hash.clone(), new_hash.clone()));
+    }
+
+    let remaining = PENDING_REKEYS.with(|pending| pending.borrow().len());
+    HASH_MIGRATION.with(|m| {
+        let mut m = m.borrow_mut();
+        let migration = m.as_mut().ok_or("No hash key migration in progress")?;
+        migration.migrated_patients += batch.len() as u64;
+        if remaining == 0 && migration.completed_at.is_none() {
+            migration.completed_at = Some(clock::now());
+        }
+        Ok(migration.clone())
+    })
+}
+
+// Ends dual-read: old hashes stop resolving and the old secret is destroyed
+#[ic_cdk::update]
+fn retire_patient_hash_key(version: u32) -> Result<PatientHashKey, String> {
+    admins::authorize(AdminOperation::RetirePatientHashKey { version })?;
+    retire_key(version)
+}
+
+pub(crate) fn retire_key(version: u32) -> Result<PatientHashKey, String> {
+    if PENDING_REKEYS.with(|pending| !pending.borrow().is_empty()) {
+        return Err("Hash key migration has not completed".to_string());
+    }
+
+    let metadata = HASH_KEYS.with(|keys| {
+        let mut keys = keys.borrow_mut();
+        let key = keys.get_mut(&version).ok_or_else(|| format!("Unknown key version: {}", version))?;
+        if key.metadata.status != "RETIRING" {
+            return Err(format!("Key version {} is {}, not RETIRING", version, key.metadata.status));
+        }
+        key.metadata.status = "RETIRED".to_string();
+        key.secret.clear();
+        Ok(key.metadata.clone())
+    })?;
+    FORWARD_ALIASES.with(|aliases| aliases.borrow_mut().clear());
+    Ok(metadata)
+}
+
+#[ic_cdk::query]
+fn get_patient_hash_keys() -> Vec<PatientHashKey> {
+    key_metadata()
+}
+
+#[ic_cdk::query]
+fn get_patient_hash_migration() -> Option<HashMigration> {
+    migration()
+}
+
+pub(crate) fn key_metadata() -> Vec<PatientHashKey> {
+    HASH_KEYS.with(|keys| keys.borrow().values().map(|k| k.metadata.clone()).collect())
+}
+
+pub(crate) fn migration() -> Option<HashMigration> {
+    HASH_MIGRATION.with(|m| m.borrow().clone())
+}
+
+// Hashing oracle for sibling canisters and the patient themself; open access would allow dictionary attacks
+#[ic_cdk::query]
+fn derive_patient_hash(patient_id: String) -> Result<Vec<u8>, String> {
+    let requester = caller();
+    let trusted = [EXECUTOR_AI_CANISTER_ID, emergency::EMERGENCY_BRIDGE_CANISTER_ID]
+        .iter()
+        .any(|id| Principal::from_text(id).ok() == Some(requester));
+    if !trusted && !ic_cdk::api::is_controller(&requester) && directive_owner(&patient_id) != Some(requester) {
+        return Err("Caller may not derive patient hashes".to_string());
+    }
+    Ok(patient_hash(&patient_id))
+}
+
+type SealedState = (
+    Vec<SealedKey>,
+    Vec<(Vec<u8>, Vec<u8>)>,
+    Vec<(Vec<u8>, Vec<u8>)>,
+    Option<HashMigration>,
+    Option<Vec<DirectiveEvent>>,
+    Option<Principal>,
+    Option<AdminState>,
+    Option<ReplicationState>,
+    Option<ShardState>,
+    Option<LetterState>,
+    Option<ComplianceState>,
+    Option<HoneytokenState>,
+    Option<u64>,
+    Option<ModuleStates>,
+);
+
+// State of modules no event records, as one record so a module can be added without reshaping the tuple
+#[derive(CandidType, Deserialize)]
+struct ModuleStates {
+    activation: Option<ActivationState>,
+    identity: Option<IdentityState>,
+    webhooks: Option<WebhookState>,
+    challenge: Option<ChallengeState>,
+    audit_buffer: Option<AuditBufferState>,
+}
+
+// The key ring must survive upgrades; losing it orphans every keyed hash.
+// The directive event log stays in its own stable region and is replayed once the key ring is back;
+// its slot here is only read from older images.
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    let keys: Vec<SealedKey> = HASH_KEYS.with(|keys| keys.borrow().values().cloned().collect());
+    let pending: Vec<(Vec<u8>, Vec<u8>)> = PENDING_REKEYS.with(|p| p.borrow().clone().into_iter().collect());
+    let aliases: Vec<(Vec<u8>, Vec<u8>)> = FORWARD_ALIASES.with(|a| a.borrow().clone().into_iter().collect());
+    let migration = HASH_MIGRATION.with(|m| m.borrow().clone());
+    let sealed: SealedState = (
+        keys,
+        pending,
+        aliases,
+        migration,
+        None,
+        storage::archive_canister_setting(),
+        Some(admins::snapshot()),
+        Some(replication::snapshot()),
+        Some(shards::snapshot()),
+        Some(access_letters::snapshot()),
+        Some(compliance::snapshot()),
+        Some(honeytokens::snapshot()),
+        Some(clock::snapshot()),
+        Some(ModuleStates {
+            activation: Some(activation::snapshot()),
+            identity: Some(identity::snapshot()),
+            webhooks: Some(webhooks::snapshot()),
+            challenge: Some(challenge::snapshot()),
+            audit_buffer: Some(audit_buffer::snapshot()),
+        }),
+    );
+    storage::save_upgrade_state(sealed);
+}
+
+// Releases before the key ring was kept had no upgrade hooks and left stable memory empty; they start
+// on the legacy unkeyed scheme, as a fresh install does. A saved image that no longer decodes still
+// traps: starting over would orphan every keyed hash.
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    // Older releases wrote one raw blob at offset 0; read it before anything touches the managed regions
+    let (keys, pending, aliases, migration, directive_events, archive, admin_state, replication_state, shard_state, letter_state, compliance_state, honeytoken_state, last_sequence, module_states): SealedState =
+        if ic_cdk::api::stable::stable_size() == 0 {
+            (Vec::new(), Vec::new(), Vec::new(), None, None, None, None, None, None, None, None, None, None, None)
+        } else {
+            ic_cdk::storage::stable_restore()
+                .ok()
+                .or_else(storage::load_upgrade_state)
+                .expect("Failed to restore patient hash keys from stable memory")
+        };
+
+    HASH_KEYS.with(|k| *k.borrow_mut() = keys.into_iter().map(|key| (key.metadata.version, key)).collect());
+    PENDING_REKEYS.with(|p| *p.borrow_mut() = pending.into_iter().collect());
+    FORWARD_ALIASES.with(|a| *a.borrow_mut() = aliases.into_iter().collect());
+    HASH_MIGRATION.with(|m| *m.borrow_mut() = migration);
+    storage::restore_archive_canister(archive);
+    admins::restore(admin_state);
+    replication::restore(replication_state);
+    shards::restore(shard_state);
+    access_letters::restore(letter_state);
+    compliance::restore(compliance_state);
+    honeytokens::restore(honeytoken_state);
+    clock::restore(last_sequence);
+    if let Some(modules) = module_states {
+        activation::restore(modules.activation);
+        identity::restore(modules.identity);
+        webhooks::restore(modules.webhooks);
+        challenge::restore(modules.challenge);
+        audit_buffer::restore(modules.audit_buffer);
+    }
+    events::restore(directive_events);
+    audit_buffer::ensure_flush_timer();
+    key_lifecycle::ensure_rotation_timer();
+    access_letters::ensure_delivery_timer();
+    reverification::ensure_sweep_timer();
+    activation::ensure_date_timers();
+    webhooks::ensure_delivery_timers();
+}
+
+pub(crate) fn patient_hash(patient_id: &str) -> Vec<u8> {
+    hash_with(current_version(), patient_id)
+}
+
+// Translate a caller-supplied hash to the key its records are stored under right now
+pub(crate) fn storage_key(patient_id_hash: &[u8]) -> Vec<u8> {
+    PENDING_REKEYS.with(|pending| pending.borrow().get(patient_id_hash).cloned())
+        .or_else(|| FORWARD_ALIASES.with(|aliases| aliases.borrow().get(patient_id_hash).cloned()))
+        .unwrap_or_else(|| patient_id_hash.to_vec())
+}
+
+pub(crate) fn rekey_entry<V>(map: &mut BTreeMap<Vec<u8>, V>, old_hash: &[u8], new_hash: &[u8]) {
+    if let Some(value) = map.remove(old_hash) {
+        map.insert(new_hash.to_vec(), value);
+    }
+}
+
+// Read replicas derive the same hashes as the primary, so they are sent the active key
+pub(crate) fn active_key() -> (u32, Vec<u8>) {
+    let version = current_version();
+    let secret = HASH_KEYS.with(|keys| keys.borrow().get(&version).map(|k| k.secret.clone()));
+    (version, secret.unwrap_or_default())
+}
+
+pub(crate) fn install_replicated_key(version: u32, secret: Vec<u8>) {
+    HASH_KEYS.with(|keys| {
+        let mut keys = keys.borrow_mut();
+        keys.clear();
+        if version != LEGACY_KEY_VERSION {
+            let metadata = PatientHashKey { version, created_at: clock::now(), status: "ACTIVE".to_string() };
+            keys.insert(version, SealedKey { metadata, secret });
+        }
+    });
+}
+
+fn current_version() -> u32 {
+    HASH_KEYS.with(|keys| {
+        keys.borrow()
+            .values()
+            .find(|k| k.metadata.status == "ACTIVE")
+            .map_or(LEGACY_KEY_VERSION, |k| k.metadata.version)
+    })
+}
+
+fn hash_with(version: u32, patient_id: &str) -> Vec<u8> {
+    let secret = HASH_KEYS.with(|keys| keys.borrow().get(&version).map(|k| k.secret.clone()));
+    match secret {
+        Some(secret) if !secret.is_empty() => hmac_sha256(&secret, patient_id.as_bytes()),
+        _ => ic_cdk::api::sha256(patient_id.as_bytes()),
+    }
+}
+
+// RFC 2104 HMAC over the canister's SHA-256
+pub(crate) fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block = if key.len() > HMAC_BLOCK_SIZE { ic_cdk::api::sha256(key) } else { key.to_vec() };
+    block.resize(HMAC_BLOCK_SIZE, 0);
+
+    let mut inner: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let mut outer: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend_from_slice(&ic_cdk::api::sha256(&inner));
+    ic_cdk::api::sha256(&outer)
+}
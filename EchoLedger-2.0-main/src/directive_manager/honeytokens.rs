@@ -0,0 +1,171 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{
+    clock, commit_directive_version, compliance, directive_owner, events, ids, validation, ConsentDirective,
+    PATIENT_HASH_INDEX,
+};
+
+// Canary patients: synthetic records planted among the real ones, with a directive on file like any
+// other, that no hospital has any reason to look up. Any logged access to one, and any metadata
+// read or amendment aimed at one, opens a critical compliance incident on the spot, which pages the
+// operators. The register of canaries is for controllers only; to everyone else they are patients.
+// A query's changes are thrown away, so it could never open an incident; every endpoint that reads
+// one patient's records is therefore an update and touches the patient before it checks the caller.
+// verify_directives stays a query for the emergency path and is caught when its receipt is flushed
+// into the access log. Owner-only reads are left out: a canary's owner is this canister.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct Honeytoken {
+    pub honeytoken_id: String,
+    pub patient_id: String,
+    pub label: String,
+    pub directive_type: String,
+    pub planted_at: u64,
+    pub planted_by: Principal,
+    pub trips: u32,
+    pub last_tripped_at: Option<u64>,
+}
+
+// Carried across upgrades with the hash key ring; a canary forgotten is a patient nobody watches
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct HoneytokenState {
+    honeytokens: Vec<Honeytoken>,
+}
+
+const CANARY_DIRECTIVE_TYPES: [&str; 3] = ["DNR", "ORGAN_DONATION", "DATA_CONSENT"];
+const MAX_HONEYTOKENS: usize = 1_000;
+const MAX_LABEL_BYTES: usize = 256;
+
+thread_local! {
+    // Keyed by patient id, which outlives hash key rotations
+    static HONEYTOKENS: std::cell::RefCell<BTreeMap<String, Honeytoken>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+}
+
+// The patient id should look like the deployment's real ones; it must not belong to anyone
+#[ic_cdk::update]
+async fn plant_honeytoken(patient_id: String, directive_type: String, label: String) -> Result<Honeytoken, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may plant honeytokens".to_string());
+    }
+    let patient_id = validation::identifier("patient_id", &patient_id)?;
+    let directive_type = validation::identifier("directive_type", &directive_type)?.to_uppercase();
+    if !CANARY_DIRECTIVE_TYPES.contains(&directive_type.as_str()) {
+        return Err(format!("directive_type must be one of {}", CANARY_DIRECTIVE_TYPES.join(", ")));
+    }
+    let label = validation::text("label", &label, MAX_LABEL_BYTES)?;
+    if HONEYTOKENS.with(|h| h.borrow().len()) >= MAX_HONEYTOKENS {
+        return Err("Too many honeytokens are planted".to_string());
+    }
+    // A real signature is opaque bytes; the canary's is random ones of the same shape
+    let (signature,) = raw_rand().await.map_err(|(_, msg)| format!("Failed to generate signature: {}", msg))?;
+    if directive_owner(&patient_id).is_some() {
+        return Err(format!("Patient {} already has a directive on file", patient_id));
+    }
+
+    let now = clock::now();
+    events::record(events::DirectiveEventKind::OwnerAssigned { patient_id: patient_id.clone(), owner: ic_cdk::id(), public_key: None });
+    commit_directive_version(ConsentDirective {
+        patient_id: patient_id.clone(),
+        directive_type: directive_type.clone(),
+        status: "ACTIVE".to_string(),
+        consent_items: vec![],
+        timestamp: now,
+        signature,
+        payer_notification: None,
+        digital_legacy: None,
+        disposition: None,
+        autopsy: None,
+        research_enrollment: None,
+    });
+    let honeytoken = Honeytoken {
+        honeytoken_id: ids::new_id("CANARY"),
+        patient_id: patient_id.clone(),
+        label,
+        directive_type,
+        planted_at: now,
+        planted_by: caller(),
+        trips: 0,
+        last_tripped_at: None,
+    };
+    HONEYTOKENS.with(|h| h.borrow_mut().insert(patient_id, honeytoken.clone()));
+    ic_cdk::println!("AUDIT: Honeytoken {} planted by {}", honeytoken.honeytoken_id, caller().to_text());
+    Ok(honeytoken)
+}
+
+#[ic_cdk::query]
+fn get_honeytokens() -> Result<Vec<Honeytoken>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read honeytokens".to_string());
+    }
+    Ok(HONEYTOKENS.with(|h| h.borrow().values().cloned().collect()))
+}
+
+// Stops watching; the canary's directive stays on file so its removal is not itself a tell
+#[ic_cdk::update]
+fn retire_honeytoken(honeytoken_id: String) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may retire honeytokens".to_string());
+    }
+    HONEYTOKENS.with(|h| {
+        let mut honeytokens = h.borrow_mut();
+        let before = honeytokens.len();
+        honeytokens.retain(|_, x| x.honeytoken_id != honeytoken_id);
+        if honeytokens.len() == before {
+            return Err(format!("Unknown honeytoken: {}", honeytoken_id));
+        }
+        Ok(())
+    })
+}
+
+pub(crate) fn is_honeytoken(patient_id: &str) -> bool {
+    HONEYTOKENS.with(|h| h.borrow().contains_key(patient_id))
+}
+
+// For records reached by storage key
+pub(crate) fn touch_hash(patient_id_hash: &[u8], principal: Principal, surface: &str) {
+    if let Some(patient_id) = PATIENT_HASH_INDEX.with(|index| index.borrow().get(patient_id_hash).cloned()) {
+        touch(&patient_id, principal, surface);
+    }
+}
+
+pub(crate) fn touch(patient_id: &str, principal: Principal, surface: &str) {
+    let now = clock::now();
+    let tripped = HONEYTOKENS.with(|h| {
+        h.borrow_mut().get_mut(patient_id).map(|honeytoken| {
+            honeytoken.trips += 1;
+            honeytoken.last_tripped_at = Some(now);
+            honeytoken.clone()
+        })
+    });
+    let Some(honeytoken) = tripped else {
+        return;
+    };
+    compliance::open_incident(
+        "HONEYTOKEN_ACCESS",
+        "CRITICAL",
+        Some(principal),
+        format!("Canary record {} touched by {}", honeytoken.honeytoken_id, principal.to_text()),
+        format!(
+            "{} reached the canary labelled \"{}\"; it has been touched {} time(s) since it was planted",
+            surface, honeytoken.label, honeytoken.trips
+        ),
+    );
+}
+
+pub(crate) fn snapshot() -> HoneytokenState {
+    HoneytokenState { honeytokens: HONEYTOKENS.with(|h| h.borrow().values().cloned().collect()) }
+}
+
+pub(crate) fn restore(state: Option<HoneytokenState>) {
+    let Some(state) = state else {
+        return;
+    };
+    HONEYTOKENS.with(|h| {
+        *h.borrow_mut() = state.honeytokens.into_iter().map(|x| (x.patient_id.clone(), x)).collect();
+    });
+}
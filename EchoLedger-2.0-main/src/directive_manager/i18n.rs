@@ -0,0 +1,109 @@
+use candid::Principal;
+use ic_cdk::caller;
+use std::collections::BTreeMap;
+
+use crate::{directive_owner, events, hashing, tenants, validation, PATIENT_HASH_INDEX};
+
+// Message catalogs for text this canister writes for people: contact notifications. Templates take
+// "{name}" placeholders. Locales are matched on their primary language; anything without a catalog
+// entry falls back to English, so a missing translation never blocks a notification.
+
+pub(crate) const DEFAULT_LOCALE: &str = "en";
+const SUPPORTED_LANGUAGES: [&str; 4] = ["en", "de", "es", "fr"];
+
+thread_local! {
+    // patient_id_hash -> locale the patient's contacts are written to in; a projection of the event log
+    pub(crate) static PATIENT_LOCALES: std::cell::RefCell<BTreeMap<Vec<u8>, String>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+// The patient or one of their proxies; None returns the patient to the default
+#[ic_cdk::update]
+fn set_patient_locale(patient_id_hash: Vec<u8>, locale: Option<String>) -> Result<(), String> {
+    validation::bytes("patient_id_hash", &patient_id_hash, validation::MAX_HASH_BYTES)?;
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+    let patient_id = PATIENT_HASH_INDEX.with(|index| index.borrow().get(&patient_id_hash).cloned())
+        .ok_or("Unknown patient")?;
+    if !may_manage(&patient_id, caller()) {
+        return Err("Only the patient or their proxy may set the patient's locale".to_string());
+    }
+    let locale = locale.map(|l| validation::language_tag("locale", &l)).transpose()?;
+    events::record(events::DirectiveEventKind::LocaleSet { patient_id_hash, locale });
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_supported_locales() -> Vec<String> {
+    SUPPORTED_LANGUAGES.iter().map(|l| l.to_string()).collect()
+}
+
+pub(crate) fn patient_locale(patient_id_hash: &[u8]) -> String {
+    PATIENT_LOCALES.with(|locales| locales.borrow().get(patient_id_hash).cloned())
+        .unwrap_or_else(|| DEFAULT_LOCALE.to_string())
+}
+
+fn may_manage(patient_id: &str, principal: Principal) -> bool {
+    directive_owner(patient_id) == Some(principal) || tenants::is_proxy(patient_id, principal)
+}
+
+// `key` rendered for `locale`, with each "{name}" replaced by its argument. One pass over the
+// template, so braces inside an argument are never taken for placeholders.
+pub(crate) fn render(locale: &str, key: &str, args: &[(&str, &str)]) -> String {
+    let language = locale.split('-').next().unwrap_or(locale).to_lowercase();
+    let template = template(key, &language).or_else(|| template(key, DEFAULT_LOCALE)).unwrap_or(key);
+    let mut message = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        message.push_str(&rest[..start]);
+        let placeholder = rest[start + 1..].find('}').map(|end| &rest[start + 1..start + 1 + end]);
+        match placeholder.and_then(|name| args.iter().find(|(n, _)| *n == name).map(|(_, v)| (name, v))) {
+            Some((name, value)) => {
+                message.push_str(value);
+                rest = &rest[start + name.len() + 2..];
+            }
+            None => {
+                message.push('{');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    message.push_str(rest);
+    message
+}
+
+// Event types are shown by name; ones without a catalog entry keep their code
+pub(crate) fn event_label(locale: &str, event_type: &str) -> String {
+    let key = format!("event.{}", event_type);
+    let label = render(locale, &key, &[]);
+    if label == key { event_type.to_string() } else { label }
+}
+
+fn template(key: &str, language: &str) -> Option<&'static str> {
+    Some(match (key, language) {
+        ("notification.full", "en") => "EchoLedger {event}: {summary} {details} (ref {reference})",
+        ("notification.full", "de") => "EchoLedger {event}: {summary} {details} (Ref. {reference})",
+        ("notification.full", "es") => "EchoLedger {event}: {summary} {details} (ref. {reference})",
+        ("notification.full", "fr") => "EchoLedger {event} : {summary} {details} (réf. {reference})",
+
+        ("notification.summary", "en") => "EchoLedger {event}: {summary} (ref {reference})",
+        ("notification.summary", "de") => "EchoLedger {event}: {summary} (Ref. {reference})",
+        ("notification.summary", "es") => "EchoLedger {event}: {summary} (ref. {reference})",
+        ("notification.summary", "fr") => "EchoLedger {event} : {summary} (réf. {reference})",
+
+        ("notification.minimal", "en") => "EchoLedger: an event on a record you are listed for needs attention (ref {reference})",
+        ("notification.minimal", "de") => "EchoLedger: Ein Ereignis in einer Akte, für die Sie eingetragen sind, erfordert Ihre Aufmerksamkeit (Ref. {reference})",
+        ("notification.minimal", "es") => "EchoLedger: un evento en un expediente en el que usted figura requiere su atención (ref. {reference})",
+        ("notification.minimal", "fr") => "EchoLedger : un événement concernant un dossier où vous êtes mentionné requiert votre attention (réf. {reference})",
+
+        ("event.EMERGENCY_ACCESS", "de") => "Notfallzugriff",
+        ("event.EMERGENCY_ACCESS", "es") => "acceso de emergencia",
+        ("event.EMERGENCY_ACCESS", "fr") => "accès d'urgence",
+        ("event.DIRECTIVE_EXECUTION", "de") => "Ausführung der Verfügung",
+        ("event.DIRECTIVE_EXECUTION", "es") => "ejecución de la directiva",
+        ("event.DIRECTIVE_EXECUTION", "fr") => "exécution de la directive",
+        ("event.RECORD_ACCESSED", "de") => "Zugriff auf Ihre Akte",
+        ("event.RECORD_ACCESSED", "es") => "acceso a su expediente",
+        ("event.RECORD_ACCESSED", "fr") => "accès à votre dossier",
+        _ => return None,
+    })
+}
@@ -0,0 +1,484 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{clock, hashing, honeytokens, ids, validation};
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PatientIdentifier {
+    pub system: String, // "MRN", "SSN", "NATIONAL_ID", "INSURANCE"
+    pub issuer: String,
+    pub identifier_hash: Vec<u8>,
+    pub registered_by: Principal,
+    pub registered_at: u64,
+}
+
+// Plaintext demographics are only ever hashed field by field, never stored
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct Demographics {
+    pub family_name: String,
+    pub given_name: String,
+    pub date_of_birth: String, // "YYYY-MM-DD"
+    pub sex: String,
+    pub postal_code: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DemographicFingerprint {
+    pub family_name_phonetic: Vec<u8>,
+    pub given_name_initial: Vec<u8>,
+    pub date_of_birth: Vec<u8>,
+    pub birth_year: Vec<u8>,
+    pub sex: Vec<u8>,
+    pub postal_code: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct MatchCandidate {
+    pub patient_id_hash: Vec<u8>,
+    pub score: f32,
+    pub classification: String, // "MATCH", "POSSIBLE_MATCH"
+    pub agreeing_fields: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct MergeRecord {
+    pub merge_id: String,
+    pub survivor_hash: Vec<u8>,
+    pub duplicate_hash: Vec<u8>,
+    pub moved_identifiers: Vec<PatientIdentifier>,
+    pub merged_by: Principal,
+    pub merged_at: u64,
+    pub reason: String,
+    pub unmerged_at: Option<u64>,
+}
+
+// Carried across upgrades with the hash key ring; a lost index strands every registered MRN or
+// national ID and undoes every merge
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct IdentityState {
+    registrars: Vec<(Principal, String)>,
+    identifier_index: Vec<(Vec<u8>, Vec<u8>)>,
+    identifiers: Vec<(Vec<u8>, Vec<PatientIdentifier>)>,
+    demographics: Vec<(Vec<u8>, DemographicFingerprint)>,
+    merged_into: Vec<(Vec<u8>, Vec<u8>)>,
+    merges: Vec<MergeRecord>,
+}
+
+thread_local! {
+    static IDENTITY_REGISTRARS: std::cell::RefCell<BTreeMap<Principal, String>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    // identifier_hash -> patient_id_hash of the record currently holding it
+    static IDENTIFIER_INDEX: std::cell::RefCell<BTreeMap<Vec<u8>, Vec<u8>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static PATIENT_IDENTIFIERS: std::cell::RefCell<BTreeMap<Vec<u8>, Vec<PatientIdentifier>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static PATIENT_DEMOGRAPHICS: std::cell::RefCell<BTreeMap<Vec<u8>, DemographicFingerprint>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    // duplicate patient_id_hash -> surviving record
+    static MERGED_INTO: std::cell::RefCell<BTreeMap<Vec<u8>, Vec<u8>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static MERGE_RECORDS: std::cell::RefCell<BTreeMap<String, MergeRecord>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+const IDENTIFIER_SYSTEMS: [&str; 4] = ["MRN", "SSN", "NATIONAL_ID", "INSURANCE"];
+// Fellegi-Sunter style log2(m/u) agreement weights and disagreement penalties
+const FAMILY_NAME_WEIGHT: (f32, f32) = (4.5, -3.0);
+const GIVEN_INITIAL_WEIGHT: (f32, f32) = (1.5, -1.0);
+const DATE_OF_BIRTH_WEIGHT: (f32, f32) = (6.0, -4.0);
+const BIRTH_YEAR_WEIGHT: f32 = 2.0;
+const SEX_WEIGHT: (f32, f32) = (0.5, -2.0);
+const POSTAL_CODE_WEIGHT: (f32, f32) = (2.5, -0.5);
+const MATCH_THRESHOLD: f32 = 12.0;
+const POSSIBLE_MATCH_THRESHOLD: f32 = 7.0;
+
+#[ic_cdk::update]
+fn register_identity_registrar(principal: Principal, organization: String) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may register identity registrars".to_string());
+    }
+    IDENTITY_REGISTRARS.with(|r| r.borrow_mut().insert(principal, organization));
+    Ok(())
+}
+
+// An identifier belongs to exactly one record; a clash means the records should be merged
+#[ic_cdk::update]
+fn register_patient_identifier(
+    patient_id_hash: Vec<u8>,
+    system: String,
+    issuer: String,
+    value: String
+) -> Result<(), String> {
+    let registrar = caller();
+    ensure_registrar(&registrar)?;
+    if !IDENTIFIER_SYSTEMS.contains(&system.as_str()) {
+        return Err(format!("Unknown identifier system: {}", system));
+    }
+    if value.trim().is_empty() || issuer.trim().is_empty() {
+        return Err("Identifier value and issuer are required".to_string());
+    }
+
+    let patient_id_hash = canonical_patient_hash(&hashing::storage_key(&patient_id_hash));
+    let identifier_hash = hash_identifier(&system, &issuer, &value);
+    let holder = IDENTIFIER_INDEX.with(|index| index.borrow().get(&identifier_hash).cloned());
+    match holder {
+        Some(holder) if holder == patient_id_hash => return Ok(()),
+        Some(_) => return Err("Identifier is already registered to another patient record; merge the records instead".to_string()),
+        None => {}
+    }
+
+    IDENTIFIER_INDEX.with(|index| {
+        index.borrow_mut().insert(identifier_hash.clone(), patient_id_hash.clone());
+    });
+    PATIENT_IDENTIFIERS.with(|ids| {
+        ids.borrow_mut().entry(patient_id_hash).or_default().push(PatientIdentifier {
+            system,
+            issuer,
+            identifier_hash,
+            registered_by: registrar,
+            registered_at: clock::now(),
+        });
+    });
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn set_patient_demographics(patient_id_hash: Vec<u8>, demographics: Demographics) -> Result<(), String> {
+    ensure_registrar(&caller())?;
+    let fingerprint = fingerprint(&demographics)?;
+    PATIENT_DEMOGRAPHICS.with(|d| {
+        d.borrow_mut().insert(canonical_patient_hash(&hashing::storage_key(&patient_id_hash)), fingerprint);
+    });
+    Ok(())
+}
+
+// Deterministic lookup by any registered identifier, following merges to the surviving record
+#[ic_cdk::query]
+fn resolve_patient_identifier(system: String, issuer: String, value: String) -> Result<Option<Vec<u8>>, String> {
+    ensure_registrar(&caller())?;
+    let identifier_hash = hash_identifier(&system, &issuer, &value);
+    Ok(IDENTIFIER_INDEX
+        .with(|index| index.borrow().get(&identifier_hash).cloned())
+        .map(|hash| canonical_patient_hash(&hash)))
+}
+
+// Probabilistic fallback when no identifier is known to the presenting hospital
+#[ic_cdk::query]
+fn match_patient_demographics(demographics: Demographics) -> Result<Vec<MatchCandidate>, String> {
+    ensure_registrar(&caller())?;
+    let probe = fingerprint(&demographics)?;
+
+    let mut candidates: Vec<MatchCandidate> = PATIENT_DEMOGRAPHICS.with(|d| {
+        d.borrow()
+            .iter()
+            .filter(|(hash, _)| !MERGED_INTO.with(|m| m.borrow().contains_key(*hash)))
+            .filter_map(|(hash, stored)| {
+                let (score, agreeing_fields) = match_score(&probe, stored);
+                let classification = if score >= MATCH_THRESHOLD {
+                    "MATCH"
+                } else if score >= POSSIBLE_MATCH_THRESHOLD {
+                    "POSSIBLE_MATCH"
+                } else {
+                    return None;
+                };
+                Some(MatchCandidate {
+                    patient_id_hash: hash.clone(),
+                    score,
+                    classification: classification.to_string(),
+                    agreeing_fields,
+                })
+            })
+            .collect()
+    });
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(candidates)
+}
+
+// Fold a duplicate record into the survivor; the move is recorded so it can be undone
+#[ic_cdk::update]
+fn merge_patient_records(survivor_hash: Vec<u8>, duplicate_hash: Vec<u8>, reason: String) -> Result<MergeRecord, String> {
+    let merged_by = caller();
+    ensure_registrar(&merged_by)?;
+    let reason = validation::text("reason", &reason, validation::MAX_REASON_BYTES)?;
+    let survivor_hash = hashing::storage_key(&survivor_hash);
+    let duplicate_hash = hashing::storage_key(&duplicate_hash);
+    if canonical_patient_hash(&survivor_hash) != survivor_hash || canonical_patient_hash(&duplicate_hash) != duplicate_hash {
+        return Err("Both records must be active; one has already been merged".to_string());
+    }
+    if survivor_hash == duplicate_hash {
+        return Err("Cannot merge a patient record into itself".to_string());
+    }
+
+    let moved_identifiers = PATIENT_IDENTIFIERS.with(|ids| {
+        let mut ids = ids.borrow_mut();
+        let moved = ids.remove(&duplicate_hash).unwrap_or_default();
+        ids.entry(survivor_hash.clone()).or_default().extend(moved.iter().cloned());
+        moved
+    });
+    IDENTIFIER_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for identifier in &moved_identifiers {
+            index.insert(identifier.identifier_hash.clone(), survivor_hash.clone());
+        }
+    });
+    MERGED_INTO.with(|m| m.borrow_mut().insert(duplicate_hash.clone(), survivor_hash.clone()));
+
+    let merged_at = clock::now();
+    let record = MergeRecord {
+        merge_id: ids::new_id("MERGE"),
+        survivor_hash,
+        duplicate_hash,
+        moved_identifiers,
+        merged_by,
+        merged_at,
+        reason,
+        unmerged_at: None,
+    };
+    MERGE_RECORDS.with(|r| r.borrow_mut().insert(record.merge_id.clone(), record.clone()));
+
+    ic_cdk::println!("AUDIT: Patient record merge {} by {}", record.merge_id, merged_by.to_text());
+    Ok(record)
+}
+
+// Reverse an erroneous merge: the duplicate's identifiers go back wherever they now live
+#[ic_cdk::update]
+fn unmerge_patient_records(merge_id: String) -> Result<MergeRecord, String> {
+    let unmerged_by = caller();
+    ensure_registrar(&unmerged_by)?;
+    let mut record = MERGE_RECORDS.with(|r| r.borrow().get(&merge_id).cloned())
+        .ok_or_else(|| format!("Merge not found: {}", merge_id))?;
+    if record.unmerged_at.is_some() {
+        return Err("Merge has already been reversed".to_string());
+    }
+
+    let moved: Vec<Vec<u8>> = record.moved_identifiers.iter().map(|i| i.identifier_hash.clone()).collect();
+    for identifier_hash in &moved {
+        let holder = IDENTIFIER_INDEX.with(|index| index.borrow().get(identifier_hash).cloned());
+        if let Some(holder) = holder {
+            PATIENT_IDENTIFIERS.with(|ids| {
+                if let Some(list) = ids.borrow_mut().get_mut(&holder) {
+                    list.retain(|i| &i.identifier_hash != identifier_hash);
+                }
+            });
+        }
+        IDENTIFIER_INDEX.with(|index| {
+            index.borrow_mut().insert(identifier_hash.clone(), record.duplicate_hash.clone());
+        });
+    }
+    PATIENT_IDENTIFIERS.with(|ids| {
+        ids.borrow_mut().insert(record.duplicate_hash.clone(), record.moved_identifiers.clone());
+    });
+    MERGED_INTO.with(|m| m.borrow_mut().remove(&record.duplicate_hash));
+
+    record.unmerged_at = Some(clock::now());
+    MERGE_RECORDS.with(|r| r.borrow_mut().insert(merge_id.clone(), record.clone()));
+    ic_cdk::println!("AUDIT: Patient record merge {} reversed by {}", merge_id, unmerged_by.to_text());
+    Ok(record)
+}
+
+#[ic_cdk::update]
+fn get_patient_identifiers(patient_id_hash: Vec<u8>) -> Result<Vec<PatientIdentifier>, String> {
+    let patient_id_hash = canonical_patient_hash(&hashing::storage_key(&patient_id_hash));
+    honeytokens::touch_hash(&patient_id_hash, caller(), "Patient identifiers read");
+    ensure_registrar(&caller())?;
+    Ok(PATIENT_IDENTIFIERS.with(|ids| ids.borrow().get(&patient_id_hash).cloned().unwrap_or_default()))
+}
+
+#[ic_cdk::update]
+fn get_merge_history(patient_id_hash: Vec<u8>) -> Vec<MergeRecord> {
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+    honeytokens::touch_hash(&canonical_patient_hash(&patient_id_hash), caller(), "Merge history read");
+    MERGE_RECORDS.with(|r| {
+        r.borrow()
+            .values()
+            .filter(|m| m.survivor_hash == patient_id_hash || m.duplicate_hash == patient_id_hash)
+            .cloned()
+            .collect()
+    })
+}
+
+// Follows the merge chain so lookups by a retired record land on the survivor
+pub(crate) fn canonical_patient_hash(patient_id_hash: &[u8]) -> Vec<u8> {
+    let mut current = patient_id_hash.to_vec();
+    MERGED_INTO.with(|m| {
+        let merged = m.borrow();
+        while let Some(next) = merged.get(&current) {
+            current = next.clone();
+        }
+    });
+    current
+}
+
+// A hospital is attributed the patients it has registered identifiers for
+pub(crate) fn attributed_patients(registrar: Principal) -> Vec<(Vec<u8>, Vec<Vec<u8>>)> {
+    PATIENT_IDENTIFIERS.with(|ids| {
+        ids.borrow()
+            .iter()
+            .filter(|(_, list)| list.iter().any(|i| i.registered_by == registrar))
+            .map(|(hash, list)| (hash.clone(), list.iter().map(|i| i.identifier_hash.clone()).collect()))
+            .collect()
+    })
+}
+
+pub(crate) fn is_attributed(registrar: Principal, patient_id_hash: &[u8]) -> bool {
+    let patient_id_hash = canonical_patient_hash(&hashing::storage_key(patient_id_hash));
+    PATIENT_IDENTIFIERS.with(|ids| {
+        ids.borrow()
+            .get(&patient_id_hash)
+            .is_some_and(|list| list.iter().any(|i| i.registered_by == registrar))
+    })
+}
+
+pub(crate) fn snapshot() -> IdentityState {
+    IdentityState {
+        registrars: IDENTITY_REGISTRARS.with(|r| r.borrow().clone().into_iter().collect()),
+        identifier_index: IDENTIFIER_INDEX.with(|i| i.borrow().clone().into_iter().collect()),
+        identifiers: PATIENT_IDENTIFIERS.with(|i| i.borrow().clone().into_iter().collect()),
+        demographics: PATIENT_DEMOGRAPHICS.with(|d| d.borrow().clone().into_iter().collect()),
+        merged_into: MERGED_INTO.with(|m| m.borrow().clone().into_iter().collect()),
+        merges: MERGE_RECORDS.with(|r| r.borrow().values().cloned().collect()),
+    }
+}
+
+pub(crate) fn restore(state: Option<IdentityState>) {
+    let Some(state) = state else {
+        return;
+    };
+    IDENTITY_REGISTRARS.with(|r| *r.borrow_mut() = state.registrars.into_iter().collect());
+    IDENTIFIER_INDEX.with(|i| *i.borrow_mut() = state.identifier_index.into_iter().collect());
+    PATIENT_IDENTIFIERS.with(|i| *i.borrow_mut() = state.identifiers.into_iter().collect());
+    PATIENT_DEMOGRAPHICS.with(|d| *d.borrow_mut() = state.demographics.into_iter().collect());
+    MERGED_INTO.with(|m| *m.borrow_mut() = state.merged_into.into_iter().collect());
+    MERGE_RECORDS.with(|r| *r.borrow_mut() = state.merges.into_iter().map(|x| (x.merge_id.clone(), x)).collect());
+}
+
+pub(crate) fn rekey_patient(old_hash: &[u8], new_hash: &[u8]) {
+    let replace = |hash: &mut Vec<u8>| {
+        if hash == old_hash {
+            *hash = new_hash.to_vec();
+        }
+    };
+    PATIENT_IDENTIFIERS.with(|ids| hashing::rekey_entry(&mut ids.borrow_mut(), old_hash, new_hash));
+    PATIENT_DEMOGRAPHICS.with(|d| hashing::rekey_entry(&mut d.borrow_mut(), old_hash, new_hash));
+    IDENTIFIER_INDEX.with(|index| index.borrow_mut().values_mut().for_each(replace));
+    MERGED_INTO.with(|m| {
+        let mut merged = m.borrow_mut();
+        hashing::rekey_entry(&mut merged, old_hash, new_hash);
+        merged.values_mut().for_each(replace);
+    });
+    MERGE_RECORDS.with(|r| {
+        for record in r.borrow_mut().values_mut() {
+            replace(&mut record.survivor_hash);
+            replace(&mut record.duplicate_hash);
+        }
+    });
+}
+
+pub(crate) fn ensure_registrar(principal: &Principal) -> Result<(), String> {
+    if IDENTITY_REGISTRARS.with(|r| r.borrow().contains_key(principal)) || ic_cdk::api::is_controller(principal) {
+        Ok(())
+    } else {
+        Err("Caller is not a registered identity registrar".to_string())
+    }
+}
+
+fn hash_identifier(system: &str, issuer: &str, value: &str) -> Vec<u8> {
+    let normalized: String = value.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_uppercase();
+    ic_cdk::api::sha256(format!("{}|{}|{}", system, issuer.trim().to_uppercase(), normalized).as_bytes())
+}
+
+fn fingerprint(demographics: &Demographics) -> Result<DemographicFingerprint, String> {
+    let dob = demographics.date_of_birth.trim();
+    let valid_dob = dob.len() == 10
+        && dob.chars().enumerate().all(|(i, c)| if i == 4 || i == 7 { c == '-' } else { c.is_ascii_digit() });
+    if !valid_dob {
+        return Err("date_of_birth must be formatted YYYY-MM-DD".to_string());
+    }
+    let given_initial = demographics.given_name.trim().chars().next()
+        .ok_or("given_name is required")?
+        .to_ascii_uppercase();
+
+    let field_hash = |field: &str, value: &str| ic_cdk::api::sha256(format!("{}|{}", field, value).as_bytes());
+    Ok(DemographicFingerprint {
+        family_name_phonetic: field_hash("FAMILY", &soundex(&demographics.family_name)?),
+        given_name_initial: field_hash("GIVEN", &given_initial.to_string()),
+        date_of_birth: field_hash("DOB", dob),
+        birth_year: field_hash("YOB", &dob[..4]),
+        sex: field_hash("SEX", &demographics.sex.trim().to_uppercase()),
+        postal_code: demographics.postal_code.as_ref()
+            .map(|p| p.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_uppercase())
+            .filter(|p| !p.is_empty())
+            .map(|p| field_hash("POSTAL", &p)),
+    })
+}
+
+fn match_score(probe: &DemographicFingerprint, stored: &DemographicFingerprint) -> (f32, Vec<String>) {
+    let mut score = 0.0;
+    let mut agreeing = Vec::new();
+    let mut compare = |name: &str, agrees: bool, (agree, disagree): (f32, f32)| {
+        if agrees {
+            score += agree;
+            agreeing.push(name.to_string());
+        } else {
+            score += disagree;
+        }
+    };
+
+    compare("FAMILY_NAME", probe.family_name_phonetic == stored.family_name_phonetic, FAMILY_NAME_WEIGHT);
+    compare("GIVEN_NAME_INITIAL", probe.given_name_initial == stored.given_name_initial, GIVEN_INITIAL_WEIGHT);
+    // A transposed day or month still earns partial credit through the birth year
+    if probe.date_of_birth == stored.date_of_birth {
+        compare("DATE_OF_BIRTH", true, DATE_OF_BIRTH_WEIGHT);
+    } else if probe.birth_year == stored.birth_year {
+        compare("BIRTH_YEAR", true, (BIRTH_YEAR_WEIGHT, 0.0));
+    } else {
+        compare("DATE_OF_BIRTH", false, DATE_OF_BIRTH_WEIGHT);
+    }
+    compare("SEX", probe.sex == stored.sex, SEX_WEIGHT);
+    if let (Some(a), Some(b)) = (&probe.postal_code, &stored.postal_code) {
+        compare("POSTAL_CODE", a == b, POSTAL_CODE_WEIGHT);
+    }
+    (score, agreeing)
+}
+
+// American Soundex, so spelling variants of a family name still agree
+fn soundex(name: &str) -> Result<String, String> {
+    let letters: Vec<char> = name.chars().filter(|c| c.is_ascii_alphabetic()).map(|c| c.to_ascii_uppercase()).collect();
+    let first = *letters.first().ok_or("family_name must contain letters")?;
+    let code = |c: char| match c {
+        'B' | 'F' | 'P' | 'V' => '1',
+        'C' | 'G' | 'J' | 'K' | 'Q' | 'S' | 'X' | 'Z' => '2',
+        'D' | 'T' => '3',
+        'L' => '4',
+        'M' | 'N' => '5',
+        'R' => '6',
+        _ => '0',
+    };
+
+    let mut encoded = first.to_string();
+    let mut previous = code(first);
+    for &c in &letters[1..] {
+        let digit = code(c);
+        if digit != '0' && digit != previous {
+            encoded.push(digit);
+            if encoded.len() == 4 {
+                break;
+            }
+        }
+        // H and W do not separate letters with the same code; vowels do
+        if c != 'H' && c != 'W' {
+            previous = digit;
+        }
+    }
+    while encoded.len() < 4 {
+        encoded.push('0');
+    }
+    Ok(encoded)
+}
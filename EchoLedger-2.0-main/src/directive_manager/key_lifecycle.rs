@@ -0,0 +1,252 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::{challenge, clock, hashing, offline, webhooks, NANOS_PER_DAY};
+
+// Rotation policy and inventory for every key this canister holds. The patient hash key is the
+// canister's own, so it can be rotated on a schedule: a timer rotates it once it outlives its
+// policy, re-keys patients in background batches and retires the old key after a grace period.
+// Webhook secrets, challenge keys and offline bundle keys are shared with their holders, who
+// rotate them; the inventory reports when they are due so an auditor can chase them.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct KeyRotationPolicy {
+    pub purpose: String,
+    pub max_age_days: u32,
+    pub automatic: bool,
+    pub retire_after_days: u32,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct KeyInventoryEntry {
+    pub key_id: String,
+    pub purpose: String,
+    pub version: u32,
+    pub created_at: Option<u64>,
+    pub age_days: Option<u64>,
+    pub status: String, // "ACTIVE", "RETIRING", "RETIRED"
+    pub rotation_status: String, // "CURRENT", "DUE", "REKEYING", "AWAITING_RETIREMENT", "RETIRED"
+    pub rotation_due_at: Option<u64>,
+    pub rotated_by: String, // "CANISTER", "HOLDER"
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct KeyLifecycleEvent {
+    pub at: u64,
+    pub purpose: String,
+    pub action: String, // "ROTATE", "REKEY_BATCH", "RETIRE"
+    pub outcome: String,
+}
+
+const KEY_PURPOSES: [&str; 4] = ["PATIENT_HASH", "WEBHOOK_SIGNING", "HOSPITAL_CHALLENGE", "OFFLINE_BUNDLE"];
+// Only keys nobody outside the canister holds can be rotated without their holder's help
+const CANISTER_HELD_PURPOSES: [&str; 1] = ["PATIENT_HASH"];
+const ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const BACKGROUND_REKEY_BATCH: u32 = 500;
+const MAX_LIFECYCLE_EVENTS: usize = 200;
+const MAX_KEY_AGE_DAYS: u32 = 3_650;
+
+thread_local! {
+    static ROTATION_POLICIES: std::cell::RefCell<BTreeMap<String, KeyRotationPolicy>> =
+        std::cell::RefCell::new(default_policies());
+
+    static LIFECYCLE_EVENTS: std::cell::RefCell<Vec<KeyLifecycleEvent>> =
+        std::cell::RefCell::new(Vec::new());
+
+    static ROTATION_TIMER_STARTED: std::cell::RefCell<bool> = const { std::cell::RefCell::new(false) };
+
+    static ROTATION_IN_FLIGHT: std::cell::RefCell<bool> = const { std::cell::RefCell::new(false) };
+}
+
+fn default_policies() -> BTreeMap<String, KeyRotationPolicy> {
+    [("PATIENT_HASH", 365), ("WEBHOOK_SIGNING", 180), ("HOSPITAL_CHALLENGE", 90), ("OFFLINE_BUNDLE", 30)]
+        .into_iter()
+        .map(|(purpose, max_age_days)| {
+            (purpose.to_string(), KeyRotationPolicy {
+                purpose: purpose.to_string(),
+                max_age_days,
+                automatic: false,
+                retire_after_days: 7,
+            })
+        })
+        .collect()
+}
+
+#[ic_cdk::update]
+fn set_key_rotation_policy(policy: KeyRotationPolicy) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may set key rotation policy".to_string());
+    }
+    if !KEY_PURPOSES.contains(&policy.purpose.as_str()) {
+        return Err(format!("Unknown key purpose: {}", policy.purpose));
+    }
+    if policy.automatic && !CANISTER_HELD_PURPOSES.contains(&policy.purpose.as_str()) {
+        return Err(format!("{} keys are rotated by their holders and cannot rotate automatically", policy.purpose));
+    }
+    if policy.max_age_days == 0 || policy.max_age_days > MAX_KEY_AGE_DAYS {
+        return Err(format!("max_age_days must be between 1 and {}", MAX_KEY_AGE_DAYS));
+    }
+    ROTATION_POLICIES.with(|p| p.borrow_mut().insert(policy.purpose.clone(), policy));
+    ensure_rotation_timer();
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_key_rotation_policies() -> Vec<KeyRotationPolicy> {
+    ROTATION_POLICIES.with(|p| p.borrow().values().cloned().collect())
+}
+
+// Key ids, ages and rotation status for compliance audits; never any key material
+#[ic_cdk::query]
+fn get_key_inventory() -> Result<Vec<KeyInventoryEntry>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read the key inventory".to_string());
+    }
+    let now = clock::now();
+    let mut inventory = patient_hash_inventory(now);
+    inventory.extend(webhooks::secret_ages().into_iter().map(|(subscription_id, created_at)| {
+        holder_entry(now, "WEBHOOK_SIGNING", format!("webhook:{}", subscription_id), 1, created_at)
+    }));
+    inventory.extend(challenge::key_ages().into_iter().map(|(tenant_id, set_at)| {
+        holder_entry(now, "HOSPITAL_CHALLENGE", format!("challenge:{}", tenant_id), 1, set_at)
+    }));
+    inventory.extend(offline::key_versions().into_iter().map(|(hospital, version, issued_at)| {
+        holder_entry(now, "OFFLINE_BUNDLE", format!("offline_bundle:{}", hospital.to_text()), version, issued_at)
+    }));
+    Ok(inventory)
+}
+
+#[ic_cdk::query]
+fn get_key_lifecycle_events() -> Result<Vec<KeyLifecycleEvent>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read key lifecycle events".to_string());
+    }
+    Ok(LIFECYCLE_EVENTS.with(|e| e.borrow().clone()))
+}
+
+// Timers do not survive upgrades; post_upgrade and policy changes restart the check
+pub(crate) fn ensure_rotation_timer() {
+    let started = ROTATION_TIMER_STARTED.with(|s| std::mem::replace(&mut *s.borrow_mut(), true));
+    if !started {
+        ic_cdk_timers::set_timer_interval(ROTATION_CHECK_INTERVAL, || ic_cdk::spawn(run_rotation_check()));
+    }
+}
+
+// One step per tick: continue a re-key, retire a migrated key, or start a rotation that is due
+async fn run_rotation_check() {
+    let policy = policy_for("PATIENT_HASH");
+    let now = clock::now();
+    let migration = hashing::migration();
+
+    if let Some(migration) = migration.as_ref().filter(|m| m.completed_at.is_none()) {
+        let outcome = match hashing::migrate_batch(BACKGROUND_REKEY_BATCH) {
+            Ok(progress) => format!("{} of {} patients re-keyed", progress.migrated_patients, progress.total_patients),
+            Err(e) => e,
+        };
+        record("PATIENT_HASH", "REKEY_BATCH", format!("v{}: {}", migration.to_version, outcome));
+        return;
+    }
+    if !policy.automatic {
+        return;
+    }
+
+    let retiring = hashing::key_metadata().into_iter().find(|k| k.status == "RETIRING");
+    if let (Some(key), Some(completed_at)) = (retiring, migration.as_ref().and_then(|m| m.completed_at)) {
+        if now >= completed_at + policy.retire_after_days as u64 * NANOS_PER_DAY {
+            let outcome = hashing::retire_key(key.version).map_or_else(|e| e, |_| "retired".to_string());
+            record("PATIENT_HASH", "RETIRE", format!("v{}: {}", key.version, outcome));
+        }
+        return;
+    }
+
+    let active = hashing::key_metadata().into_iter().find(|k| k.status == "ACTIVE");
+    let due = active.is_none_or(|k| now >= k.created_at + policy.max_age_days as u64 * NANOS_PER_DAY);
+    if due && !ROTATION_IN_FLIGHT.with(|f| std::mem::replace(&mut *f.borrow_mut(), true)) {
+        let outcome = match hashing::rotate_key().await {
+            Ok(key) => format!("rotated to v{}", key.version),
+            Err(e) => e,
+        };
+        ROTATION_IN_FLIGHT.with(|f| *f.borrow_mut() = false);
+        record("PATIENT_HASH", "ROTATE", outcome);
+    }
+}
+
+fn patient_hash_inventory(now: u64) -> Vec<KeyInventoryEntry> {
+    let policy = policy_for("PATIENT_HASH");
+    let rekeying = hashing::migration().is_some_and(|m| m.completed_at.is_none());
+    let keys = hashing::key_metadata();
+    if keys.is_empty() {
+        // Still on the original unkeyed scheme, which is overdue by definition
+        return vec![KeyInventoryEntry {
+            key_id: "patient_hash:v0".to_string(),
+            purpose: "PATIENT_HASH".to_string(),
+            version: 0,
+            created_at: None,
+            age_days: None,
+            status: "ACTIVE".to_string(),
+            rotation_status: "DUE".to_string(),
+            rotation_due_at: None,
+            rotated_by: "CANISTER".to_string(),
+        }];
+    }
+    keys.into_iter().map(|key| {
+        let due_at = key.created_at + policy.max_age_days as u64 * NANOS_PER_DAY;
+        let rotation_status = match key.status.as_str() {
+            "RETIRED" => "RETIRED",
+            "RETIRING" if rekeying => "REKEYING",
+            "RETIRING" => "AWAITING_RETIREMENT",
+            _ if now >= due_at => "DUE",
+            _ => "CURRENT",
+        };
+        KeyInventoryEntry {
+            key_id: format!("patient_hash:v{}", key.version),
+            purpose: "PATIENT_HASH".to_string(),
+            version: key.version,
+            created_at: Some(key.created_at),
+            age_days: Some(now.saturating_sub(key.created_at) / NANOS_PER_DAY),
+            rotation_status: rotation_status.to_string(),
+            rotation_due_at: (key.status == "ACTIVE").then_some(due_at),
+            status: key.status,
+            rotated_by: "CANISTER".to_string(),
+        }
+    }).collect()
+}
+
+fn holder_entry(now: u64, purpose: &str, key_id: String, version: u32, created_at: u64) -> KeyInventoryEntry {
+    let due_at = created_at + policy_for(purpose).max_age_days as u64 * NANOS_PER_DAY;
+    KeyInventoryEntry {
+        key_id,
+        purpose: purpose.to_string(),
+        version,
+        created_at: Some(created_at),
+        age_days: Some(now.saturating_sub(created_at) / NANOS_PER_DAY),
+        status: "ACTIVE".to_string(),
+        rotation_status: if now >= due_at { "DUE" } else { "CURRENT" }.to_string(),
+        rotation_due_at: Some(due_at),
+        rotated_by: "HOLDER".to_string(),
+    }
+}
+
+fn policy_for(purpose: &str) -> KeyRotationPolicy {
+    ROTATION_POLICIES.with(|p| p.borrow().get(purpose).cloned())
+        .unwrap_or_else(|| default_policies().remove(purpose).expect("every purpose has a default policy"))
+}
+
+fn record(purpose: &str, action: &str, outcome: String) {
+    ic_cdk::println!("AUDIT: Key lifecycle {} {}: {}", purpose, action, outcome);
+    LIFECYCLE_EVENTS.with(|e| {
+        let mut events = e.borrow_mut();
+        if events.len() >= MAX_LIFECYCLE_EVENTS {
+            events.remove(0);
+        }
+        events.push(KeyLifecycleEvent {
+            at: clock::now(),
+            purpose: purpose.to_string(),
+            action: action.to_string(),
+            outcome,
+        });
+    });
+}
@@ -1,8 +1,49 @@
-use candid::{CandidType, Deserialize};
-use ic_cdk::api::time;
+use candid::{CandidType, Deserialize, Principal};
+use ed25519_dalek::{Signature, VerifyingKey};
+use ic_cdk::{call, caller};
 use serde::Serialize;
 use std::collections::BTreeMap;
 
+mod access_letters;
+mod activation;
+mod admins;
+mod anomaly;
+mod audit_buffer;
+mod autopsy;
+mod bracelet;
+mod challenge;
+mod clinician_summary;
+mod clock;
+mod compliance;
+mod cross_border;
+mod digital_legacy;
+mod disposition;
+mod emergency;
+mod events;
+mod existence;
+mod hashing;
+mod honeytokens;
+mod i18n;
+mod identity;
+mod ids;
+mod key_lifecycle;
+mod load_shedding;
+mod merkle;
+mod offline;
+mod payers;
+mod point_in_time;
+mod references;
+mod replication;
+mod research_enrollment;
+mod reverification;
+mod shards;
+mod storage;
+mod tenants;
+mod translation;
+mod validation;
+mod verification;
+mod webhooks;
+
 #[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
 pub struct PHIMetadata {
     pub patient_id_hash: Vec<u8>,
@@ -22,41 +63,823 @@ pub struct ConsentDirective {
     pub consent_items: Vec<String>,
     pub timestamp: u64,
     pub signature: Vec<u8>,
+    pub payer_notification: Option<payers::PayerNotificationConsent>, // None: payers are never told
+    pub digital_legacy: Option<Vec<digital_legacy::DigitalLegacyWish>>,
+    pub disposition: Option<disposition::DispositionPreferences>,
+    pub autopsy: Option<autopsy::AutopsyPreference>,
+    pub research_enrollment: Option<research_enrollment::ResearchEnrollmentPreferences>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct VisibilityPreferences {
+    pub disclosable_directive_types: Vec<String>,
+    pub permitted_requester_classes: Vec<String>,
+    pub notify_next_of_kin: bool,
+    pub updated_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct EmergencyContact {
+    pub contact_id: String,
+    pub name: String,
+    pub relationship: String,
+    pub channel: String,
+    pub address: String,
+    pub content_level: String,
+    pub principal: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ContactEvent {
+    pub event_type: String,
+    pub reference_id: String,
+    pub summary: String,
+    pub details: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ContactNotification {
+    pub notification_id: String,
+    pub contact_id: String,
+    pub event_type: String,
+    pub reference_id: String,
+    pub patient_reference: Option<String>, // None for operator alerts
+    pub channel: String,
+    pub message: String,
+    pub sent_at: u64,
+    pub acknowledged_at: Option<u64>,
+    pub acknowledgment_note: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ProxyGrant {
+    pub proxy: Principal,
+    pub scopes: Vec<String>,
+    pub granted_at: u64,
+    pub public_key: Option<Vec<u8>>, // Ed25519 key the proxy signs amendment acceptances with
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct AmendmentProposal {
+    pub proposal_id: String,
+    pub patient_id: String,
+    pub proposed_by: Principal,
+    pub proposed_directive: ConsentDirective,
+    pub rationale: String,
+    pub proposal_hash: Vec<u8>,
+    pub base_version: Option<u64>, // Directive version the proposal amends; None on proposals from older releases
+    pub status: String, // "PROPOSED", "ACCEPTED", "REJECTED", "SUPERSEDED"
+    pub created_at: u64,
+    pub decided_at: Option<u64>,
+    pub decided_by: Option<Principal>,
+    pub acceptance_signature: Option<Vec<u8>>,
+    pub resulting_version: Option<u64>,
 }
 
 thread_local! {
-    static PHI_METADATA: std::cell::RefCell<BTreeMap<Vec<u8>, PHIMetadata>> = 
+    static PHI_METADATA: std::cell::RefCell<Box<dyn storage::Store<Vec<u8>, PHIMetadata>>> =
+        std::cell::RefCell::new(storage::backend(storage::PHI_METADATA_MEMORY));
+
+    static CONSENT_DIRECTIVES: std::cell::RefCell<Box<dyn storage::Store<String, ConsentDirective>>> =
+        std::cell::RefCell::new(storage::backend(storage::CONSENT_DIRECTIVES_MEMORY));
+
+    static VISIBILITY_PREFERENCES: std::cell::RefCell<BTreeMap<Vec<u8>, VisibilityPreferences>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static EMERGENCY_CONTACTS: std::cell::RefCell<BTreeMap<Vec<u8>, Vec<EmergencyContact>>> =
         std::cell::RefCell::new(BTreeMap::new());
-    
-    static CONSENT_DIRECTIVES: std::cell::RefCell<BTreeMap<String, ConsentDirective>> = 
+
+    static CONTACT_NOTIFICATIONS: std::cell::RefCell<BTreeMap<String, ContactNotification>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    // On-call staff paged for platform health events rather than patient events
+    static OPERATOR_CONTACTS: std::cell::RefCell<Vec<EmergencyContact>> = std::cell::RefCell::new(Vec::new());
+
+    static DIRECTIVE_OWNERS: std::cell::RefCell<BTreeMap<String, Principal>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    // patient_id -> Ed25519 key the patient signs amendment acceptances with, bound with the owner
+    static OWNER_SIGNING_KEYS: std::cell::RefCell<BTreeMap<String, Vec<u8>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static CONSENT_DIRECTIVE_VERSIONS: std::cell::RefCell<BTreeMap<String, Vec<ConsentDirective>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static PROXY_GRANTS: std::cell::RefCell<BTreeMap<String, Vec<ProxyGrant>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static AMENDMENT_PROPOSALS: std::cell::RefCell<BTreeMap<String, AmendmentProposal>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static NEXT_CONTACT_SEQ: std::cell::RefCell<u64> = std::cell::RefCell::new(0);
+
+    // patient hash -> patient_id, so hash-keyed lookups can reach consent directives
+    static PATIENT_HASH_INDEX: std::cell::RefCell<BTreeMap<Vec<u8>, String>> =
         std::cell::RefCell::new(BTreeMap::new());
 }
 
+const EXECUTOR_AI_CANISTER_ID: &str = "rrkah-fqaaa-aaaaa-aaaaq-cai";
+const CONTACT_CHANNELS: [&str; 3] = ["EMAIL", "SMS", "PUSH"];
+const CONTENT_LEVELS: [&str; 3] = ["MINIMAL", "SUMMARY", "FULL"];
+const POA_AMEND_SCOPE: &str = "AMEND_DIRECTIVES";
+const AMENDMENT_ACCEPTANCE_DOMAIN: &[u8] = b"echoledger-amendment-acceptance:";
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+const REQUESTER_CLASSES: [&str; 4] = ["EMERGENCY_DEPARTMENT", "TRANSPLANT_CENTER", "HOSPITAL", "FIRST_RESPONDER"];
+
 #[ic_cdk::update]
 async fn store_directive_metadata(metadata: PHIMetadata) -> Result<(), String> {
+    let _permit = load_shedding::admit("INTERACTIVE")?;
+    let mut metadata = validation::metadata(metadata)?;
     if metadata.retention_period > 50 * 365 * 24 * 60 * 60 * 1000 {
         return Err("Retention period exceeds HIPAA limits".to_string());
     }
 
-    PHI_METADATA.with(|phi_map| {
-        phi_map.borrow_mut().insert(metadata.patient_id_hash.clone(), metadata);
-    });
+    metadata.patient_id_hash = hashing::storage_key(&metadata.patient_id_hash);
+    // Sharded deployments keep only the location here; single-canister ones keep the metadata
+    if shards::store(metadata.clone()).await?.is_none() {
+        events::record(events::DirectiveEventKind::DirectiveStored(metadata));
+    }
 
     Ok(())
 }
 
+// Metadata untouched for idle_days moves to the archive canister; the event log records the move
 #[ic_cdk::update]
-fn update_consent_directive(directive: ConsentDirective) -> Result<(), String> {
-    CONSENT_DIRECTIVES.with(|directives| {
-        directives.borrow_mut().insert(directive.patient_id.clone(), directive);
+async fn archive_cold_metadata(idle_days: u64, batch_size: u32) -> Result<u32, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may archive directive metadata".to_string());
+    }
+    let _permit = load_shedding::admit("BULK")?;
+    let cutoff = clock::now().saturating_sub(idle_days.saturating_mul(NANOS_PER_DAY));
+    let cold: Vec<PHIMetadata> = PHI_METADATA.with(|phi_map| {
+        phi_map.borrow()
+            .entries()
+            .into_iter()
+            .map(|(_, metadata)| metadata)
+            .filter(|metadata| metadata.updated_at < cutoff)
+            .take(batch_size as usize)
+            .collect()
     });
 
+    let mut archived = 0;
+    for metadata in cold {
+        storage::PHI_METADATA_ARCHIVE.put(&metadata.patient_id_hash, &metadata).await?;
+        events::record(events::DirectiveEventKind::MetadataArchived { patient_id_hash: metadata.patient_id_hash });
+        archived += 1;
+    }
+    Ok(archived)
+}
+
+#[ic_cdk::update]
+async fn get_archived_metadata(patient_id_hash: Vec<u8>) -> Result<Option<PHIMetadata>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may read archived directive metadata".to_string());
+    }
+    storage::PHI_METADATA_ARCHIVE.get(&hashing::storage_key(&patient_id_hash)).await
+}
+
+// Only the patient may write directly; everyone else goes through propose_amendment. The patient
+// is the principal an identity registrar bound to them, never whoever happens to write first.
+#[ic_cdk::update]
+fn update_consent_directive(mut directive: ConsentDirective) -> Result<(), String> {
+    let _permit = load_shedding::admit("INTERACTIVE")?;
+    let writer = caller();
+    let Some(owner) = directive_owner(&directive.patient_id) else {
+        return Err("Patient identity has not been verified; an identity registrar must bind the patient's principal first".to_string());
+    };
+    if owner != writer {
+        return Err("Only the patient may edit this directive directly; submit an amendment proposal instead".to_string());
+    }
+    tenants::check_directive_write(&directive.patient_id)?;
+    payers::validate_consent(&mut directive.payer_notification)?;
+    digital_legacy::validate(&mut directive)?;
+    disposition::validate(&mut directive)?;
+    autopsy::validate(&mut directive)?;
+    research_enrollment::validate(&mut directive)?;
+
+    commit_directive_version(directive);
+
     Ok(())
 }
 
-#[ic_cdk::query]
+fn commit_directive_version(directive: ConsentDirective) -> u64 {
+    let version = current_version(&directive.patient_id) + 1;
+    events::record(events::DirectiveEventKind::ConsentUpdated { directive, version });
+    version
+}
+
+// executor_ai cancels and retracts steps run under an organ donation or data consent that is
+// withdrawn or runs out. One-way, so an unreachable executor never holds up the change itself.
+fn notify_executor_of_consent(directive: &ConsentDirective) {
+    if directive.directive_type != "ORGAN_DONATION" && directive.directive_type != "DATA_CONSENT" {
+        return;
+    }
+    let Ok(executor_id) = Principal::from_text(EXECUTOR_AI_CANISTER_ID) else {
+        return;
+    };
+    let args = (directive.patient_id.clone(), directive.directive_type.clone(), directive.status.clone());
+    if let Err(code) = ic_cdk::notify(executor_id, "record_consent_status", args) {
+        ic_cdk::println!("⚠️ Consent change for {} not sent to executor: {:?}", directive.directive_type, code);
+    }
+}
+
+#[ic_cdk::update]
 fn get_consent_status(patient_id: String) -> Option<ConsentDirective> {
+    honeytokens::touch(&patient_id, caller(), "Consent status read");
     CONSENT_DIRECTIVES.with(|directives| {
-        directives.borrow().get(&patient_id).cloned()
+        directives.borrow().get(&patient_id)
+    })
+    .filter(|directive| tenants::may_access_patient(caller(), &patient_id, Some(&directive.directive_type)))
+}
+
+// Patient-controlled emergency disclosure preferences, keyed like PHI metadata
+#[ic_cdk::update]
+fn set_visibility_preferences(
+    patient_id_hash: Vec<u8>,
+    mut preferences: VisibilityPreferences
+) -> Result<(), String> {
+    if !tenants::manages_patient_hash(caller(), &patient_id_hash) {
+        return Err("Only the patient or their proxy may set visibility preferences".to_string());
+    }
+    if let Some(unknown) = preferences.permitted_requester_classes.iter()
+        .find(|class| !REQUESTER_CLASSES.contains(&class.as_str()))
+    {
+        return Err(format!("Unknown requester class: {}", unknown));
+    }
+
+    preferences.updated_at = clock::now();
+    events::record(events::DirectiveEventKind::VisibilityUpdated {
+        patient_id_hash: hashing::storage_key(&patient_id_hash),
+        preferences,
+    });
+
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn get_visibility_preferences(patient_id_hash: Vec<u8>) -> Option<VisibilityPreferences> {
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+    honeytokens::touch_hash(&patient_id_hash, caller(), "Visibility preferences read");
+    if !tenants::may_access_patient_hash(caller(), &patient_id_hash) {
+        return None;
+    }
+    VISIBILITY_PREFERENCES.with(|prefs| {
+        prefs.borrow().get(&patient_id_hash).cloned()
     })
-}
\ No newline at end of file
+}
+
+// Register a next-of-kin or emergency contact for a patient; the patient or their proxy chooses them
+#[ic_cdk::update]
+fn register_emergency_contact(
+    patient_id_hash: Vec<u8>,
+    contact: EmergencyContact
+) -> Result<String, String> {
+    validation::bytes("patient_id_hash", &patient_id_hash, validation::MAX_HASH_BYTES)?;
+    if !tenants::manages_patient_hash(caller(), &patient_id_hash) {
+        return Err("Only the patient or their proxy may register emergency contacts".to_string());
+    }
+    let mut contact = validation::contact(contact)?;
+    if !CONTACT_CHANNELS.contains(&contact.channel.as_str()) {
+        return Err(format!("Unsupported notification channel: {}", contact.channel));
+    }
+    if !CONTENT_LEVELS.contains(&contact.content_level.as_str()) {
+        return Err(format!("Unknown content level: {}", contact.content_level));
+    }
+    tenants::check_contact_channel(&hashing::storage_key(&patient_id_hash), &contact.channel)?;
+
+    let seq = NEXT_CONTACT_SEQ.with(|seq| {
+        let mut seq = seq.borrow_mut();
+        *seq += 1;
+        *seq
+    });
+    contact.contact_id = format!("CONTACT_{}", seq);
+    let contact_id = contact.contact_id.clone();
+
+    events::record(events::DirectiveEventKind::ContactRegistered {
+        patient_id_hash: hashing::storage_key(&patient_id_hash),
+        contact,
+    });
+
+    Ok(contact_id)
+}
+
+#[ic_cdk::update]
+fn remove_emergency_contact(patient_id_hash: Vec<u8>, contact_id: String) -> Result<(), String> {
+    if !tenants::manages_patient_hash(caller(), &patient_id_hash) {
+        return Err("Only the patient or their proxy may remove emergency contacts".to_string());
+    }
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+    EMERGENCY_CONTACTS.with(|contacts| {
+        let contacts = contacts.borrow();
+        let list = contacts.get(&patient_id_hash).ok_or("No contacts registered for patient")?;
+        if !list.iter().any(|c| c.contact_id == contact_id) {
+            return Err(format!("Contact not found: {}", contact_id));
+        }
+        Ok::<_, String>(())
+    })?;
+    events::record(events::DirectiveEventKind::ContactRemoved { patient_id_hash, contact_id });
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn get_emergency_contacts(patient_id_hash: Vec<u8>) -> Vec<EmergencyContact> {
+    honeytokens::touch_hash(&hashing::storage_key(&patient_id_hash), caller(), "Emergency contacts read");
+    emergency_contacts(patient_id_hash)
+}
+
+// Whether a principal may speak for the patient in a dispute: the owner, a proxy the owner granted,
+// or a contact registered with that principal. Asked by executor_ai on behalf of the objector.
+#[ic_cdk::update]
+fn is_patient_representative(patient_id_hash: Vec<u8>, principal: Principal) -> Result<bool, String> {
+    if !tenants::is_platform(caller()) {
+        return Err("Only platform canisters may check patient representatives".to_string());
+    }
+    if principal == Principal::anonymous() {
+        return Ok(false);
+    }
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+    let canonical_hash = identity::canonical_patient_hash(&patient_id_hash);
+    let Some(patient_id) = PATIENT_HASH_INDEX.with(|index| index.borrow().get(&canonical_hash).cloned()) else {
+        return Ok(false);
+    };
+    honeytokens::touch(&patient_id, principal, "Objection filed");
+    Ok(directive_owner(&patient_id) == Some(principal)
+        || tenants::is_proxy(&patient_id, principal)
+        || EMERGENCY_CONTACTS.with(|contacts| {
+            contacts.borrow().get(&patient_id_hash).is_some_and(|list| list.iter().any(|c| c.principal == Some(principal)))
+        }))
+}
+
+fn emergency_contacts(patient_id_hash: Vec<u8>) -> Vec<EmergencyContact> {
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+    if !tenants::may_access_patient_hash(caller(), &patient_id_hash) {
+        return Vec::new();
+    }
+    EMERGENCY_CONTACTS.with(|contacts| {
+        contacts.borrow().get(&patient_id_hash).cloned().unwrap_or_default()
+    })
+}
+
+// Notification subsystem entry point: fan an event out to every registered contact.
+// Only the platform's own canisters raise events; anyone else reaches no one.
+#[ic_cdk::update]
+fn notify_contacts(patient_id_hash: Vec<u8>, event: ContactEvent) -> Vec<ContactNotification> {
+    if !tenants::is_platform(caller()) {
+        ic_cdk::println!("🚫 notify_contacts refused for {}", caller());
+        return Vec::new();
+    }
+    let locale = i18n::patient_locale(&hashing::storage_key(&patient_id_hash));
+    let contacts = emergency_contacts(patient_id_hash.clone());
+    deliver_to_contacts(&contacts, &event, &locale, Some(&patient_id_hash))
+}
+
+// Each contact is told the patient's reference for that contact alone
+fn deliver_to_contacts(
+    contacts: &[EmergencyContact],
+    event: &ContactEvent,
+    locale: &str,
+    patient_id_hash: Option<&[u8]>,
+) -> Vec<ContactNotification> {
+    let now = clock::now();
+
+    contacts.iter().map(|contact| {
+        let notification = ContactNotification {
+            notification_id: ids::new_id("NOTIF"),
+            contact_id: contact.contact_id.clone(),
+            event_type: event.event_type.clone(),
+            reference_id: event.reference_id.clone(),
+            patient_reference: patient_id_hash
+                .map(|hash| references::reference_for(&format!("contact:{}", contact.contact_id), hash)),
+            channel: contact.channel.clone(),
+            message: render_contact_message(event, &contact.content_level, locale),
+            sent_at: now,
+            acknowledged_at: None,
+            acknowledgment_note: None,
+        };
+
+        dispatch_notification(contact, &notification);
+
+        CONTACT_NOTIFICATIONS.with(|notifications| {
+            notifications.borrow_mut().insert(notification.notification_id.clone(), notification.clone());
+        });
+
+        notification
+    }).collect()
+}
+
+#[ic_cdk::update]
+fn register_operator_contact(mut contact: EmergencyContact) -> Result<String, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may register operator contacts".to_string());
+    }
+    if !CONTACT_CHANNELS.contains(&contact.channel.as_str()) {
+        return Err(format!("Unsupported notification channel: {}", contact.channel));
+    }
+    if !CONTENT_LEVELS.contains(&contact.content_level.as_str()) {
+        return Err(format!("Unknown content level: {}", contact.content_level));
+    }
+    let seq = NEXT_CONTACT_SEQ.with(|seq| {
+        let mut seq = seq.borrow_mut();
+        *seq += 1;
+        *seq
+    });
+    contact.contact_id = format!("OPERATOR_{}", seq);
+    let contact_id = contact.contact_id.clone();
+    OPERATOR_CONTACTS.with(|contacts| contacts.borrow_mut().push(contact));
+    Ok(contact_id)
+}
+
+#[ic_cdk::update]
+fn remove_operator_contact(contact_id: String) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may remove operator contacts".to_string());
+    }
+    OPERATOR_CONTACTS.with(|contacts| {
+        let mut contacts = contacts.borrow_mut();
+        let before = contacts.len();
+        contacts.retain(|c| c.contact_id != contact_id);
+        if contacts.len() == before {
+            return Err(format!("Contact not found: {}", contact_id));
+        }
+        Ok(())
+    })
+}
+
+// Operator alerts from sibling canisters, such as emergency_bridge's SLO burn alerts
+#[ic_cdk::update]
+fn notify_operators(event: ContactEvent) -> Vec<ContactNotification> {
+    if !tenants::is_platform(caller()) {
+        return Vec::new();
+    }
+    let contacts = OPERATOR_CONTACTS.with(|contacts| contacts.borrow().clone());
+    deliver_to_contacts(&contacts, &event, i18n::DEFAULT_LOCALE, None)
+}
+
+// Contacts confirm they received a notification; execution acknowledgments are forwarded to executor_ai
+#[ic_cdk::update]
+async fn acknowledge_notification(notification_id: String, note: Option<String>) -> Result<(), String> {
+    let note = note.map(|n| validation::text("note", &n, validation::MAX_REASON_BYTES)).transpose()?;
+    let acknowledged = CONTACT_NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        let notification = notifications.get_mut(&notification_id)
+            .ok_or_else(|| format!("Notification not found: {}", notification_id))?;
+
+        let contact_principal = find_contact(&notification.contact_id).and_then(|c| c.principal);
+        if let Some(expected) = contact_principal {
+            if expected != caller() {
+                return Err("Caller is not the notified contact".to_string());
+            }
+        }
+
+        notification.acknowledged_at = Some(clock::now());
+        notification.acknowledgment_note = note;
+        Ok::<_, String>(notification.clone())
+    })?;
+
+    if acknowledged.event_type == "DIRECTIVE_EXECUTION" {
+        let executor_id = Principal::from_text(EXECUTOR_AI_CANISTER_ID)
+            .map_err(|_| "Invalid executor canister ID")?;
+        let result: Result<(Result<(), String>,), _> = call(
+            executor_id,
+            "record_contact_acknowledgment",
+            (acknowledged,)
+        ).await;
+        if let Ok((Err(e),)) = result {
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+// The platform sees every notification; others only those sent to contacts of patients they may review
+#[ic_cdk::update]
+fn get_contact_notifications(reference_id: String) -> Vec<ContactNotification> {
+    let requester = caller();
+    let platform = tenants::is_platform(requester);
+    let matching: Vec<ContactNotification> = CONTACT_NOTIFICATIONS.with(|notifications| {
+        notifications.borrow().values().filter(|n| n.reference_id == reference_id).cloned().collect()
+    });
+    let patient_hashes: std::collections::BTreeSet<Vec<u8>> =
+        matching.iter().filter_map(|n| contact_patient_hash(&n.contact_id)).collect();
+    for patient_id_hash in &patient_hashes {
+        honeytokens::touch_hash(patient_id_hash, requester, "Contact notifications read");
+    }
+    matching
+        .into_iter()
+        .filter(|n| platform || contact_patient_hash(&n.contact_id)
+            .is_some_and(|hash| tenants::may_review_patient_hash(requester, &hash)))
+        .collect()
+}
+
+fn contact_patient_hash(contact_id: &str) -> Option<Vec<u8>> {
+    EMERGENCY_CONTACTS.with(|contacts| {
+        contacts.borrow()
+            .iter()
+            .find(|(_, list)| list.iter().any(|c| c.contact_id == contact_id))
+            .map(|(hash, _)| hash.clone())
+    })
+}
+
+fn find_contact(contact_id: &str) -> Option<EmergencyContact> {
+    EMERGENCY_CONTACTS.with(|contacts| {
+        contacts.borrow()
+            .values()
+            .flatten()
+            .find(|c| c.contact_id == contact_id)
+            .cloned()
+    })
+    .or_else(|| OPERATOR_CONTACTS.with(|contacts| contacts.borrow().iter().find(|c| c.contact_id == contact_id).cloned()))
+}
+
+// The framing is localized; summary and details are written by the sending canister
+fn render_contact_message(event: &ContactEvent, content_level: &str, locale: &str) -> String {
+    let key = match content_level {
+        "FULL" => "notification.full",
+        "SUMMARY" => "notification.summary",
+        _ => "notification.minimal",
+    };
+    i18n::render(locale, key, &[
+        ("event", &i18n::event_label(locale, &event.event_type)),
+        ("summary", &event.summary),
+        ("details", &event.details),
+        ("reference", &event.reference_id),
+    ])
+}
+
+fn dispatch_notification(contact: &EmergencyContact, notification: &ContactNotification) {
+    // In a real implementation, this would hand off to the email/SMS/push gateways
+    ic_cdk::println!(
+        "📨 CONTACT NOTIFICATION: {} via {} to {} - {}",
+        notification.notification_id,
+        contact.channel,
+        contact.address,
+        notification.message
+    );
+}
+
+// An identity registrar holding an identifier for the patient - so one that has verified who they
+// are - binds the principal the patient acts as and the key they sign amendment acceptances with.
+// Binding again replaces both, for a patient who has lost their device or key.
+#[ic_cdk::update]
+fn bind_patient_owner(patient_id: String, owner: Principal, public_key: Vec<u8>) -> Result<(), String> {
+    let registrar = caller();
+    identity::ensure_registrar(&registrar)?;
+    let patient_id = validation::identifier("patient_id", &patient_id)?;
+    if !ic_cdk::api::is_controller(&registrar) && !identity::is_attributed(registrar, &hashing::patient_hash(&patient_id)) {
+        return Err("Only a registrar holding an identifier for this patient may bind their principal".to_string());
+    }
+    if owner == Principal::anonymous() {
+        return Err("The anonymous principal cannot own a directive".to_string());
+    }
+    parse_signing_key(&public_key)?;
+
+    events::record(events::DirectiveEventKind::OwnerAssigned { patient_id, owner, public_key: Some(public_key) });
+    Ok(())
+}
+
+// Patient grants a healthcare proxy scoped powers under their power of attorney; a proxy that may
+// accept amendments needs the key it will sign those acceptances with
+#[ic_cdk::update]
+fn grant_proxy(patient_id: String, proxy: Principal, scopes: Vec<String>, public_key: Option<Vec<u8>>) -> Result<(), String> {
+    if directive_owner(&patient_id) != Some(caller()) {
+        return Err("Only the patient may grant proxy powers".to_string());
+    }
+    validation::collection("scopes", scopes.len(), validation::MAX_SCOPES)?;
+    let scopes = scopes.iter().map(|s| validation::identifier("scope", s)).collect::<Result<Vec<_>, _>>()?;
+    if let Some(public_key) = &public_key {
+        parse_signing_key(public_key)?;
+    }
+
+    events::record(events::DirectiveEventKind::ProxyGranted {
+        patient_id,
+        grant: ProxyGrant { proxy, scopes, granted_at: clock::now(), public_key },
+    });
+
+    Ok(())
+}
+
+// Hospitals and proxies propose changes; nothing takes effect until the patient accepts
+#[ic_cdk::update]
+fn propose_amendment(
+    patient_id: String,
+    mut proposed_directive: ConsentDirective,
+    rationale: String
+) -> Result<AmendmentProposal, String> {
+    let rationale = validation::text("rationale", &rationale, validation::MAX_RATIONALE_BYTES)?;
+    let proposer = caller();
+    if proposer == Principal::anonymous() {
+        return Err("Anonymous callers cannot propose amendments".to_string());
+    }
+    if proposed_directive.patient_id != patient_id {
+        return Err("Proposed directive belongs to a different patient".to_string());
+    }
+    honeytokens::touch(&patient_id, proposer, "Amendment proposal");
+    if directive_owner(&patient_id).is_none() {
+        return Err(format!("No directive on file for patient {}", patient_id));
+    }
+    if !tenants::may_access_patient(proposer, &patient_id, Some(&proposed_directive.directive_type)) {
+        return Err("Patient belongs to another tenant and no data-sharing agreement covers this directive".to_string());
+    }
+    payers::validate_consent(&mut proposed_directive.payer_notification)?;
+    digital_legacy::validate(&mut proposed_directive)?;
+    disposition::validate(&mut proposed_directive)?;
+    autopsy::validate(&mut proposed_directive)?;
+    research_enrollment::validate(&mut proposed_directive)?;
+
+    let now = clock::now();
+    let base_version = current_version(&patient_id);
+    let proposal_hash = hash_proposal(&proposed_directive, &rationale, base_version)?;
+    let proposal = AmendmentProposal {
+        proposal_id: ids::new_id("AMEND"),
+        patient_id,
+        proposed_by: proposer,
+        proposed_directive,
+        rationale,
+        proposal_hash,
+        base_version: Some(base_version),
+        status: "PROPOSED".to_string(),
+        created_at: now,
+        decided_at: None,
+        decided_by: None,
+        acceptance_signature: None,
+        resulting_version: None,
+    };
+
+    events::record(events::DirectiveEventKind::AmendmentProposed(proposal.clone()));
+
+    Ok(proposal)
+}
+
+// Signed acceptance by the patient (or a proxy holding the amend scope) creates a new version. The
+// signature is over the proposal hash, checked against the decider's bound key, and a proposal made
+// against a version that has since been replaced is retired rather than applied over the newer one.
+#[ic_cdk::update]
+fn accept_amendment(proposal_id: String, signature: Vec<u8>) -> Result<u64, String> {
+    let _permit = load_shedding::admit("INTERACTIVE")?;
+    let proposal = pending_proposal(&proposal_id)?;
+    let decider = caller();
+    if !may_confirm_amendment(&proposal.patient_id, decider) {
+        return Err("Only the patient or an authorized proxy may accept amendments".to_string());
+    }
+    let public_key = signing_key(&proposal.patient_id, decider)
+        .ok_or("No signing key is bound for this patient or proxy; acceptance cannot be verified")?;
+    verify_acceptance(&public_key, &proposal.proposal_hash, &signature)?;
+    tenants::check_directive_write(&proposal.patient_id)?;
+
+    let current = current_version(&proposal.patient_id);
+    if proposal.base_version != Some(current) {
+        events::record(events::DirectiveEventKind::AmendmentDecided {
+            proposal_id,
+            status: "SUPERSEDED".to_string(),
+            decided_by: decider,
+            decided_at: clock::now(),
+            acceptance_signature: None,
+            resulting_version: None,
+        });
+        return Err(format!(
+            "The directive has changed since this amendment was proposed (now version {}); it must be proposed again",
+            current
+        ));
+    }
+
+    let mut directive = proposal.proposed_directive.clone();
+    directive.timestamp = clock::now();
+    directive.signature = signature.clone();
+    let version = commit_directive_version(directive);
+
+    events::record(events::DirectiveEventKind::AmendmentDecided {
+        proposal_id,
+        status: "ACCEPTED".to_string(),
+        decided_by: decider,
+        decided_at: clock::now(),
+        acceptance_signature: Some(signature),
+        resulting_version: Some(version),
+    });
+
+    Ok(version)
+}
+
+#[ic_cdk::update]
+fn reject_amendment(proposal_id: String) -> Result<(), String> {
+    let proposal = pending_proposal(&proposal_id)?;
+    let decider = caller();
+    if !may_confirm_amendment(&proposal.patient_id, decider) {
+        return Err("Only the patient or an authorized proxy may reject amendments".to_string());
+    }
+
+    events::record(events::DirectiveEventKind::AmendmentDecided {
+        proposal_id,
+        status: "REJECTED".to_string(),
+        decided_by: decider,
+        decided_at: clock::now(),
+        acceptance_signature: None,
+        resulting_version: None,
+    });
+
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn get_amendment_proposals(patient_id: String) -> Vec<AmendmentProposal> {
+    let requester = caller();
+    honeytokens::touch(&patient_id, requester, "Amendment proposals read");
+    AMENDMENT_PROPOSALS.with(|proposals| {
+        proposals.borrow()
+            .values()
+            .filter(|p| p.patient_id == patient_id)
+            .filter(|p| tenants::may_access_patient(requester, &patient_id, Some(&p.proposed_directive.directive_type)))
+            .cloned()
+            .collect()
+    })
+}
+
+#[ic_cdk::update]
+fn get_directive_versions(patient_id: String) -> Vec<ConsentDirective> {
+    let requester = caller();
+    honeytokens::touch(&patient_id, requester, "Directive versions read");
+    CONSENT_DIRECTIVE_VERSIONS.with(|versions| {
+        versions.borrow().get(&patient_id).cloned().unwrap_or_default()
+    })
+    .into_iter()
+    .filter(|directive| tenants::may_access_patient(requester, &patient_id, Some(&directive.directive_type)))
+    .collect()
+}
+
+// Called by hash key migration to move one patient's hash-keyed records
+fn rekey_patient(old_hash: &[u8], new_hash: &[u8]) {
+    events::record(events::DirectiveEventKind::PatientRekeyed {
+        old_hash: old_hash.to_vec(),
+        new_hash: new_hash.to_vec(),
+    });
+}
+
+fn directive_owner(patient_id: &str) -> Option<Principal> {
+    DIRECTIVE_OWNERS.with(|owners| owners.borrow().get(patient_id).copied())
+}
+
+fn current_version(patient_id: &str) -> u64 {
+    CONSENT_DIRECTIVE_VERSIONS.with(|versions| versions.borrow().get(patient_id).map_or(0, |history| history.len() as u64))
+}
+
+// The key bound to the decider: the patient's from their identity binding, a proxy's from its grant
+fn signing_key(patient_id: &str, principal: Principal) -> Option<Vec<u8>> {
+    if directive_owner(patient_id) == Some(principal) {
+        return OWNER_SIGNING_KEYS.with(|keys| keys.borrow().get(patient_id).cloned());
+    }
+    PROXY_GRANTS.with(|grants| {
+        grants.borrow()
+            .get(patient_id)
+            .and_then(|list| list.iter().find(|g| g.proxy == principal))
+            .and_then(|g| g.public_key.clone())
+    })
+}
+
+fn parse_signing_key(public_key: &[u8]) -> Result<VerifyingKey, String> {
+    let bytes: [u8; 32] = public_key.try_into()
+        .map_err(|_| "Signing keys are 32-byte Ed25519 keys".to_string())?;
+    VerifyingKey::from_bytes(&bytes).map_err(|_| "Invalid Ed25519 public key".to_string())
+}
+
+fn verify_acceptance(public_key: &[u8], proposal_hash: &[u8], signature: &[u8]) -> Result<(), String> {
+    let key = parse_signing_key(public_key)?;
+    let signature = Signature::from_slice(signature)
+        .map_err(|_| "Acceptance must carry a 64-byte Ed25519 signature over the proposal hash".to_string())?;
+    let message = [AMENDMENT_ACCEPTANCE_DOMAIN, proposal_hash].concat();
+    key.verify_strict(&message, &signature)
+        .map_err(|_| "Acceptance signature does not verify against the bound key".to_string())
+}
+
+fn may_confirm_amendment(patient_id: &str, principal: Principal) -> bool {
+    if directive_owner(patient_id) == Some(principal) {
+        return true;
+    }
+    PROXY_GRANTS.with(|grants| {
+        grants.borrow()
+            .get(patient_id)
+            .map_or(false, |list| list.iter().any(|g| {
+                g.proxy == principal && g.scopes.iter().any(|s| s == POA_AMEND_SCOPE)
+            }))
+    })
+}
+
+fn pending_proposal(proposal_id: &str) -> Result<AmendmentProposal, String> {
+    let proposal = AMENDMENT_PROPOSALS.with(|proposals| proposals.borrow().get(proposal_id).cloned())
+        .ok_or_else(|| format!("Amendment proposal not found: {}", proposal_id))?;
+    if proposal.status != "PROPOSED" {
+        return Err(format!("Amendment proposal already {}", proposal.status));
+    }
+    Ok(proposal)
+}
+
+// Covers the version amended, so a signature cannot be carried over to the same change on another version
+fn hash_proposal(directive: &ConsentDirective, rationale: &str, base_version: u64) -> Result<Vec<u8>, String> {
+    let mut material = serde_json::to_vec(directive).map_err(|e| e.to_string())?;
+    material.extend_from_slice(rationale.as_bytes());
+    material.extend_from_slice(&base_version.to_be_bytes());
+    Ok(ic_cdk::api::sha256(&material))
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,185 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::clock;
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct LaneLimit {
+    pub lane: String, // "EMERGENCY", "INTERACTIVE", "BULK"
+    pub max_in_flight: u32,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct SheddingPolicy {
+    pub lanes: Vec<LaneLimit>,
+    pub window_seconds: u64,
+    pub instruction_budget_per_window: u64,
+    pub max_open_calls: u32,
+    pub retry_after_seconds: u32,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct LaneStatus {
+    pub lane: String,
+    pub in_flight: u32,
+    pub max_in_flight: u32,
+    pub admitted: u64,
+    pub shed: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct LoadStatus {
+    pub pressure: f32, // 1.0 means the instruction budget or open-call limit is fully used
+    pub window_instructions: u64,
+    pub open_calls: u32,
+    pub lanes: Vec<LaneStatus>,
+}
+
+// Held for the life of a request; dropping it frees the lane slot and charges the instructions used
+pub(crate) struct LanePermit {
+    id: u64,
+}
+
+thread_local! {
+    static SHEDDING_POLICY: std::cell::RefCell<SheddingPolicy> = std::cell::RefCell::new(SheddingPolicy {
+        lanes: vec![
+            LaneLimit { lane: "EMERGENCY".to_string(), max_in_flight: u32::MAX },
+            LaneLimit { lane: "INTERACTIVE".to_string(), max_in_flight: 200 },
+            LaneLimit { lane: "BULK".to_string(), max_in_flight: 4 },
+        ],
+        window_seconds: 10,
+        instruction_budget_per_window: 50_000_000_000,
+        max_open_calls: 500,
+        retry_after_seconds: 30,
+    });
+
+    // permit id -> (lane, admitted_at)
+    static IN_FLIGHT: std::cell::RefCell<BTreeMap<u64, (&'static str, u64)>> =
+        std::cell::RefCell::new(BTreeMap::new());
+    static NEXT_PERMIT: std::cell::RefCell<u64> = std::cell::RefCell::new(0);
+    // (window started at, instructions charged in it)
+    static INSTRUCTION_WINDOW: std::cell::RefCell<(u64, u64)> = std::cell::RefCell::new((0, 0));
+    // lane -> (admitted, shed)
+    static LANE_COUNTERS: std::cell::RefCell<BTreeMap<&'static str, (u64, u64)>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+// Highest priority first. Emergency lookups are never shed; the others give way as pressure rises.
+const LANES: [&str; 3] = ["EMERGENCY", "INTERACTIVE", "BULK"];
+const SHED_AT_PRESSURE: [f32; 3] = [f32::INFINITY, 0.9, 0.6];
+// A permit whose request trapped is never dropped; stop counting it after this long
+const STALE_PERMIT_NANOS: u64 = 10 * 60 * 1_000_000_000;
+
+#[ic_cdk::update]
+fn set_load_shedding_policy(policy: SheddingPolicy) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may set the load shedding policy".to_string());
+    }
+    if let Some(limit) = policy.lanes.iter().find(|l| !LANES.contains(&l.lane.as_str())) {
+        return Err(format!("Unknown request lane: {}", limit.lane));
+    }
+    if policy.window_seconds == 0 || policy.instruction_budget_per_window == 0 || policy.max_open_calls == 0 {
+        return Err("The window, instruction budget and open-call limit must be non-zero".to_string());
+    }
+    SHEDDING_POLICY.with(|p| *p.borrow_mut() = policy);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_load_status() -> Result<LoadStatus, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read load status".to_string());
+    }
+    let policy = SHEDDING_POLICY.with(|p| p.borrow().clone());
+    let (window_instructions, open_calls, pressure) = pressure(&policy);
+    let lanes = LANES.iter().map(|lane| {
+        let (admitted, shed) = LANE_COUNTERS.with(|c| c.borrow().get(lane).copied().unwrap_or_default());
+        LaneStatus {
+            lane: lane.to_string(),
+            in_flight: in_flight(lane),
+            max_in_flight: max_in_flight(&policy, lane),
+            admitted,
+            shed,
+        }
+    }).collect();
+    Ok(LoadStatus { pressure, window_instructions, open_calls, lanes })
+}
+
+// Classify a request at the top of its endpoint; refused work is told when to come back
+pub(crate) fn admit(lane: &str) -> Result<LanePermit, String> {
+    let rank = LANES.iter().position(|l| *l == lane).unwrap_or(LANES.len() - 1);
+    let lane = LANES[rank];
+    let policy = SHEDDING_POLICY.with(|p| p.borrow().clone());
+
+    let (_, _, pressure) = pressure(&policy);
+    let refusal = if pressure >= SHED_AT_PRESSURE[rank] {
+        Some(format!("{} requests are being shed under load ({:.0}% pressure)", lane, pressure * 100.0))
+    } else if in_flight(lane) >= max_in_flight(&policy, lane) {
+        Some(format!("Too many {} requests in flight", lane))
+    } else {
+        None
+    };
+    if let Some(reason) = refusal {
+        LANE_COUNTERS.with(|c| c.borrow_mut().entry(lane).or_default().1 += 1);
+        return Err(format!("{}; retry after {} seconds", reason, policy.retry_after_seconds));
+    }
+
+    let id = NEXT_PERMIT.with(|n| {
+        let mut n = n.borrow_mut();
+        *n += 1;
+        *n
+    });
+    let now = clock::now();
+    IN_FLIGHT.with(|f| {
+        let mut in_flight = f.borrow_mut();
+        in_flight.retain(|_, (_, admitted_at)| now.saturating_sub(*admitted_at) < STALE_PERMIT_NANOS);
+        in_flight.insert(id, (lane, now));
+    });
+    LANE_COUNTERS.with(|c| c.borrow_mut().entry(lane).or_default().0 += 1);
+    Ok(LanePermit { id })
+}
+
+impl Drop for LanePermit {
+    fn drop(&mut self) {
+        IN_FLIGHT.with(|f| f.borrow_mut().remove(&self.id));
+        // Counter 1 covers the whole call context, including work done before and after awaits
+        let used = ic_cdk::api::performance_counter(1);
+        let window_nanos = SHEDDING_POLICY.with(|p| p.borrow().window_seconds) * 1_000_000_000;
+        let now = clock::now();
+        INSTRUCTION_WINDOW.with(|w| {
+            let mut window = w.borrow_mut();
+            if now.saturating_sub(window.0) >= window_nanos {
+                *window = (now, 0);
+            }
+            window.1 = window.1.saturating_add(used);
+        });
+    }
+}
+
+fn pressure(policy: &SheddingPolicy) -> (u64, u32, f32) {
+    let now = clock::now();
+    let window_instructions = INSTRUCTION_WINDOW.with(|w| {
+        let (started_at, used) = *w.borrow();
+        if now.saturating_sub(started_at) < policy.window_seconds * 1_000_000_000 { used } else { 0 }
+    });
+    let open_calls = LANES.iter().map(|lane| in_flight(lane)).sum::<u32>();
+    let pressure = (window_instructions as f32 / policy.instruction_budget_per_window as f32)
+        .max(open_calls as f32 / policy.max_open_calls as f32);
+    (window_instructions, open_calls, pressure)
+}
+
+fn in_flight(lane: &str) -> u32 {
+    let now = clock::now();
+    IN_FLIGHT.with(|f| {
+        f.borrow()
+            .values()
+            .filter(|(l, admitted_at)| *l == lane && now.saturating_sub(*admitted_at) < STALE_PERMIT_NANOS)
+            .count() as u32
+    })
+}
+
+fn max_in_flight(policy: &SheddingPolicy, lane: &str) -> u32 {
+    policy.lanes.iter().find(|l| l.lane == lane).map_or(u32::MAX, |l| l.max_in_flight)
+}
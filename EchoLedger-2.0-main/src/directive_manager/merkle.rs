@@ -0,0 +1,168 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{challenge, existence, hashing, identity, tenants};
+use crate::{ConsentDirective, PATIENT_HASH_INDEX};
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ProofStep {
+    pub sibling_hash: Vec<u8>,
+    pub sibling_on_left: bool,
+}
+
+// Verify by hashing leaf_hash with each sibling in order (0x01 || left || right)
+// and comparing the result to root; the certificate binds root to this canister
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct InclusionProof {
+    pub patient_id: String,
+    pub version: u64,
+    pub leaf_index: u64,
+    pub leaf_hash: Vec<u8>,
+    pub appended_at: u64,
+    pub proof: Vec<ProofStep>,
+    pub root: Vec<u8>,
+    pub tree_size: u64,
+    pub certificate: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+struct LeafRecord {
+    leaf_index: u64,
+    appended_at: u64,
+}
+
+thread_local! {
+    // A projection of the ConsentUpdated events, rebuilt with the rest of directive state on replay
+    static MERKLE_LEAVES: std::cell::RefCell<Vec<Vec<u8>>> = std::cell::RefCell::new(Vec::new());
+
+    static LEAF_INDEX: std::cell::RefCell<BTreeMap<(String, u64), LeafRecord>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+// Append-only: every directive version becomes a leaf, stamped with the time its event was recorded
+pub(crate) fn append_directive_version(patient_id: &str, version: u64, appended_at: u64, directive: &ConsentDirective) {
+    let leaf = leaf_hash(patient_id, version, appended_at, directive);
+
+    let leaf_index = MERKLE_LEAVES.with(|leaves| {
+        let mut leaves = leaves.borrow_mut();
+        leaves.push(leaf);
+        leaves.len() as u64 - 1
+    });
+
+    LEAF_INDEX.with(|index| {
+        index.borrow_mut().insert((patient_id.to_string(), version), LeafRecord { leaf_index, appended_at });
+    });
+}
+
+// Once per live append and once after a replay, not per leaf: each root hashes the whole tree
+pub(crate) fn certify_root() {
+    ic_cdk::api::set_certified_data(&current_root());
+}
+
+pub(crate) fn clear() {
+    MERKLE_LEAVES.with(|leaves| leaves.borrow_mut().clear());
+    LEAF_INDEX.with(|index| index.borrow_mut().clear());
+}
+
+#[ic_cdk::query]
+fn get_merkle_root() -> (Vec<u8>, u64, Option<Vec<u8>>) {
+    let size = MERKLE_LEAVES.with(|leaves| leaves.borrow().len() as u64);
+    (current_root(), size, ic_cdk::api::data_certificate())
+}
+
+// An answer confirms the patient has a directive, so outside callers need a hospital proof and share
+// check_directive_exists's rate limit; an update so the limit can be counted
+#[ic_cdk::update]
+fn get_inclusion_proof(patient_id_hash: Vec<u8>, version: u64) -> Result<InclusionProof, String> {
+    let requester = caller();
+    if requester == Principal::anonymous() {
+        return Err("Anonymous callers may not request inclusion proofs".to_string());
+    }
+    challenge::require_proof(requester)?;
+    if !tenants::is_platform(requester) {
+        existence::consume_rate_limit(requester)?;
+    }
+
+    let storage_hash = identity::canonical_patient_hash(&hashing::storage_key(&patient_id_hash));
+    let patient_id = PATIENT_HASH_INDEX.with(|index| index.borrow().get(&storage_hash).cloned())
+        .ok_or_else(|| format!("No directive version {} recorded for this patient", version))?;
+    inclusion_proof(patient_id, version)
+}
+
+pub(crate) fn inclusion_proof(patient_id: String, version: u64) -> Result<InclusionProof, String> {
+    let record = LEAF_INDEX.with(|index| index.borrow().get(&(patient_id.clone(), version)).cloned())
+        .ok_or_else(|| format!("No directive version {} recorded for patient {}", version, patient_id))?;
+
+    MERKLE_LEAVES.with(|leaves| {
+        let leaves = leaves.borrow();
+        let (proof, root) = build_proof(&leaves, record.leaf_index as usize);
+
+        Ok(InclusionProof {
+            patient_id,
+            version,
+            leaf_index: record.leaf_index,
+            leaf_hash: leaves[record.leaf_index as usize].clone(),
+            appended_at: record.appended_at,
+            proof,
+            root,
+            tree_size: leaves.len() as u64,
+            certificate: ic_cdk::api::data_certificate(),
+        })
+    })
+}
+
+fn leaf_hash(patient_id: &str, version: u64, appended_at: u64, directive: &ConsentDirective) -> Vec<u8> {
+    let mut material = vec![0x00];
+    material.extend_from_slice(patient_id.as_bytes());
+    material.extend_from_slice(&version.to_be_bytes());
+    material.extend_from_slice(&appended_at.to_be_bytes());
+    material.extend_from_slice(&serde_json::to_vec(directive).unwrap_or_default());
+    ic_cdk::api::sha256(&material)
+}
+
+fn node_hash(left: &[u8], right: &[u8]) -> Vec<u8> {
+    let mut material = vec![0x01];
+    material.extend_from_slice(left);
+    material.extend_from_slice(right);
+    ic_cdk::api::sha256(&material)
+}
+
+fn current_root() -> Vec<u8> {
+    MERKLE_LEAVES.with(|leaves| {
+        let leaves = leaves.borrow();
+        if leaves.is_empty() {
+            return vec![0; 32];
+        }
+        build_proof(&leaves, 0).1
+    })
+}
+
+// An odd node at the end of a level is promoted unchanged to the next level
+fn build_proof(leaves: &[Vec<u8>], mut index: usize) -> (Vec<ProofStep>, Vec<u8>) {
+    let mut proof = Vec::new();
+    let mut level: Vec<Vec<u8>> = leaves.to_vec();
+
+    while level.len() > 1 {
+        let sibling = if index % 2 == 0 { index + 1 } else { index - 1 };
+        if sibling < level.len() {
+            proof.push(ProofStep {
+                sibling_hash: level[sibling].clone(),
+                sibling_on_left: sibling < index,
+            });
+        }
+
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [single] => single.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        index /= 2;
+    }
+
+    (proof, level.pop().unwrap_or_default())
+}
@@ -0,0 +1,200 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument,
+};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::emergency::{self, EmergencyDirective};
+use crate::{bracelet, challenge, clock, hashing, identity, ids, load_shedding, VISIBILITY_PREFERENCES};
+
+// One patient in a bundle; readers match on identifier or bracelet hashes they can compute locally
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct OfflineBundleEntry {
+    pub patient_id_hash: Vec<u8>,
+    pub identifier_hashes: Vec<Vec<u8>>,
+    pub token_uid_hashes: Vec<Vec<u8>>,
+    pub directives: Vec<EmergencyDirective>,
+}
+
+// Encrypt-then-sign; readers follow OFFLINE_BUNDLE_SPEC.md alongside this module
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct OfflineBundle {
+    pub format_version: u32,
+    pub bundle_id: String,
+    pub hospital: Principal,
+    pub key_version: u32,
+    pub generated_at: u64,
+    pub expires_at: u64,
+    pub entry_count: u32,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Clone)]
+struct BundleKey {
+    version: u32,
+    secret: Vec<u8>,
+    issued_at: u64,
+}
+
+thread_local! {
+    static BUNDLE_KEYS: std::cell::RefCell<BTreeMap<Principal, BundleKey>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+const BUNDLE_SIGNING_DOMAIN: &[u8] = b"echoledger-offline-bundle-v1";
+const DEFAULT_BUNDLE_TTL_HOURS: u32 = 24;
+const MAX_BUNDLE_TTL_HOURS: u32 = 72;
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const ECDSA_KEY_NAME: &str = "test_key";
+// Offline readers are hospital staff, so bundles honour preferences for the HOSPITAL class
+const BUNDLE_REQUESTER_CLASS: &str = "HOSPITAL";
+
+// Returned once; the hospital installs it in its offline reader. Calling again rotates it.
+#[ic_cdk::update]
+async fn issue_offline_bundle_key() -> Result<(u32, Vec<u8>), String> {
+    let hospital = caller();
+    identity::ensure_registrar(&hospital)?;
+
+    let (secret,) = raw_rand().await.map_err(|(_, msg)| format!("Failed to generate bundle key: {}", msg))?;
+    let version = BUNDLE_KEYS.with(|keys| {
+        let mut keys = keys.borrow_mut();
+        let version = keys.get(&hospital).map_or(1, |k| k.version + 1);
+        keys.insert(hospital, BundleKey { version, secret: secret.clone(), issued_at: clock::now() });
+        version
+    });
+    ic_cdk::println!("AUDIT: Offline bundle key v{} issued to {}", version, hospital.to_text());
+    Ok((version, secret))
+}
+
+#[ic_cdk::update]
+async fn export_offline_bundle(ttl_hours: Option<u32>) -> Result<OfflineBundle, String> {
+    let hospital = caller();
+    identity::ensure_registrar(&hospital)?;
+    challenge::require_proof(hospital)?;
+    let _permit = load_shedding::admit("BULK")?;
+    let ttl_hours = ttl_hours.unwrap_or(DEFAULT_BUNDLE_TTL_HOURS);
+    if ttl_hours == 0 || ttl_hours > MAX_BUNDLE_TTL_HOURS {
+        return Err(format!("Bundles may live between 1 and {} hours", MAX_BUNDLE_TTL_HOURS));
+    }
+    let key = BUNDLE_KEYS.with(|keys| keys.borrow().get(&hospital).cloned())
+        .ok_or("Issue an offline bundle key before exporting")?;
+
+    let entries: Vec<OfflineBundleEntry> = identity::attributed_patients(hospital)
+        .into_iter()
+        .filter_map(|(patient_id_hash, identifier_hashes)| {
+            let directives: Vec<EmergencyDirective> = emergency::active_directives(&patient_id_hash)
+                .into_iter()
+                .filter(|d| disclosure_permitted(&patient_id_hash, &d.directive_type))
+                .collect();
+            if directives.is_empty() {
+                return None;
+            }
+            Some(OfflineBundleEntry {
+                token_uid_hashes: bracelet::active_token_hashes(&patient_id_hash),
+                patient_id_hash,
+                identifier_hashes,
+                directives,
+            })
+        })
+        .collect();
+
+    let generated_at = clock::now();
+    let (random,) = raw_rand().await.map_err(|(_, msg)| format!("Failed to generate nonce: {}", msg))?;
+    let nonce = random[..16].to_vec();
+    let plaintext = serde_json::to_vec(&entries).map_err(|e| e.to_string())?;
+    let mut bundle = OfflineBundle {
+        format_version: BUNDLE_FORMAT_VERSION,
+        bundle_id: ids::new_id("BUNDLE"),
+        hospital,
+        key_version: key.version,
+        generated_at,
+        expires_at: generated_at + ttl_hours as u64 * NANOS_PER_HOUR,
+        entry_count: entries.len() as u32,
+        ciphertext: apply_keystream(&key.secret, &nonce, &plaintext),
+        nonce,
+        signature: vec![],
+    };
+
+    let (signed,) = sign_with_ecdsa(SignWithEcdsaArgument {
+        message_hash: signing_digest(&bundle),
+        derivation_path: signing_derivation_path(),
+        key_id: signing_key_id(),
+    }).await.map_err(|(_, msg)| format!("Failed to sign bundle: {}", msg))?;
+    bundle.signature = signed.signature;
+
+    for entry in &entries {
+        let directive_types = entry.directives.iter().map(|d| d.directive_type.clone()).collect();
+        emergency::log_access(&entry.patient_id_hash, hospital, hospital, "OFFLINE_EXPORT", directive_types);
+    }
+    Ok(bundle)
+}
+
+// Readers fetch this while online and pin it for offline signature checks
+#[ic_cdk::update]
+async fn get_offline_bundle_public_key() -> Result<Vec<u8>, String> {
+    let (response,) = ecdsa_public_key(EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path: signing_derivation_path(),
+        key_id: signing_key_id(),
+    }).await.map_err(|(_, msg)| format!("Failed to fetch bundle signing key: {}", msg))?;
+    Ok(response.public_key)
+}
+
+// hospital -> (key version, issued_at), for the key inventory
+pub(crate) fn key_versions() -> Vec<(Principal, u32, u64)> {
+    BUNDLE_KEYS.with(|keys| keys.borrow().iter().map(|(hospital, key)| (*hospital, key.version, key.issued_at)).collect())
+}
+
+fn disclosure_permitted(patient_id_hash: &[u8], directive_type: &str) -> bool {
+    VISIBILITY_PREFERENCES.with(|prefs| {
+        prefs.borrow().get(patient_id_hash).map_or(true, |p| {
+            (p.disclosable_directive_types.is_empty() || p.disclosable_directive_types.iter().any(|t| t == directive_type))
+                && (p.permitted_requester_classes.is_empty()
+                    || p.permitted_requester_classes.iter().any(|c| c == BUNDLE_REQUESTER_CLASS))
+        })
+    })
+}
+
+// HMAC-SHA256 in counter mode: block i = HMAC(key, nonce || i as u64 big-endian)
+fn apply_keystream(key: &[u8], nonce: &[u8], data: &[u8]) -> Vec<u8> {
+    data.chunks(32)
+        .enumerate()
+        .flat_map(|(i, chunk)| {
+            let mut counter_block = nonce.to_vec();
+            counter_block.extend_from_slice(&(i as u64).to_be_bytes());
+            let keystream = hashing::hmac_sha256(key, &counter_block);
+            chunk.iter().zip(keystream).map(|(b, k)| b ^ k).collect::<Vec<u8>>()
+        })
+        .collect()
+}
+
+fn signing_digest(bundle: &OfflineBundle) -> Vec<u8> {
+    let mut material = BUNDLE_SIGNING_DOMAIN.to_vec();
+    material.extend_from_slice(&bundle.format_version.to_be_bytes());
+    material.extend_from_slice(bundle.bundle_id.as_bytes());
+    material.extend_from_slice(bundle.hospital.as_slice());
+    material.extend_from_slice(&bundle.key_version.to_be_bytes());
+    material.extend_from_slice(&bundle.generated_at.to_be_bytes());
+    material.extend_from_slice(&bundle.expires_at.to_be_bytes());
+    material.extend_from_slice(&bundle.entry_count.to_be_bytes());
+    material.extend_from_slice(&bundle.nonce);
+    material.extend_from_slice(&ic_cdk::api::sha256(&bundle.ciphertext));
+    ic_cdk::api::sha256(&material)
+}
+
+fn signing_derivation_path() -> Vec<Vec<u8>> {
+    vec![b"offline_bundle".to_vec()]
+}
+
+fn signing_key_id() -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: ECDSA_KEY_NAME.to_string(),
+    }
+}
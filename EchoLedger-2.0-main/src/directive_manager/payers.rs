@@ -0,0 +1,123 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{clock, hashing, validation, webhooks, CONSENT_DIRECTIVES, EXECUTOR_AI_CANISTER_ID};
+
+// Estates and insurers need to know that end-of-life directives were carried out. A payer is
+// registered here by controllers, then receives notices at a webhook subscription it creates with
+// the "PAYER" scope. A notice goes out only for patients whose directive names that payer in its
+// payer_notification consent, and says no more than what ran, when, and the execution id.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PayerEndpoint {
+    pub payer_id: String,
+    pub name: String,
+    pub principal: Principal,
+    pub active: bool,
+    pub registered_at: u64,
+}
+
+// Stored on the directive itself, so the opt-in is versioned and amended with it
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PayerNotificationConsent {
+    pub payer_ids: Vec<String>,
+    pub consented_at: u64, // set on write
+}
+
+const MAX_PAYERS: usize = 500;
+const MAX_PAYERS_PER_DIRECTIVE: usize = 5;
+
+thread_local! {
+    static PAYERS: std::cell::RefCell<BTreeMap<String, PayerEndpoint>> = const { std::cell::RefCell::new(BTreeMap::new()) };
+}
+
+#[ic_cdk::update]
+fn register_payer_endpoint(payer_id: String, name: String, principal: Principal) -> Result<PayerEndpoint, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may register payers".to_string());
+    }
+    let payer_id = validation::identifier("payer_id", &payer_id)?.to_uppercase();
+    let name = validation::identifier("name", &name)?;
+    PAYERS.with(|p| {
+        let mut payers = p.borrow_mut();
+        if payers.len() >= MAX_PAYERS && !payers.contains_key(&payer_id) {
+            return Err("Too many payers are registered".to_string());
+        }
+        if payers.values().any(|payer| payer.principal == principal && payer.payer_id != payer_id) {
+            return Err("This principal is already registered for another payer".to_string());
+        }
+        let payer = PayerEndpoint { payer_id: payer_id.clone(), name, principal, active: true, registered_at: clock::now() };
+        payers.insert(payer_id, payer.clone());
+        ic_cdk::println!("AUDIT: Payer {} registered for {}", payer.payer_id, principal.to_text());
+        Ok(payer)
+    })
+}
+
+// A deactivated payer keeps its place in existing consents but receives nothing
+#[ic_cdk::update]
+fn set_payer_endpoint_active(payer_id: String, active: bool) -> Result<PayerEndpoint, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may change payers".to_string());
+    }
+    PAYERS.with(|p| {
+        let mut payers = p.borrow_mut();
+        let payer = payers.get_mut(&payer_id).ok_or_else(|| format!("Unknown payer: {}", payer_id))?;
+        payer.active = active;
+        Ok(payer.clone())
+    })
+}
+
+// Public, so patients can pick the payers to name in their consent
+#[ic_cdk::query]
+fn get_payer_endpoints() -> Vec<PayerEndpoint> {
+    PAYERS.with(|p| p.borrow().values().filter(|payer| payer.active).cloned().collect())
+}
+
+// executor_ai reports each completed run; returns how many payers were sent a notice
+#[ic_cdk::update]
+fn notify_payers_of_execution(
+    patient_id: String,
+    execution_id: String,
+    execution_types: Vec<String>,
+    executed_at: u64
+) -> Result<u32, String> {
+    let executor_id = Principal::from_text(EXECUTOR_AI_CANISTER_ID)
+        .map_err(|_| "Invalid executor canister ID")?;
+    if caller() != executor_id {
+        return Err("Only executor_ai may report executions to payers".to_string());
+    }
+    let Some(consent) = CONSENT_DIRECTIVES.with(|d| d.borrow().get(&patient_id)).and_then(|d| d.payer_notification) else {
+        return Ok(0);
+    };
+    let patient_id_hash = hashing::patient_hash(&patient_id);
+    let mut notified = 0;
+    for payer_id in &consent.payer_ids {
+        let Some(payer) = PAYERS.with(|p| p.borrow().get(payer_id).cloned()).filter(|payer| payer.active) else {
+            continue;
+        };
+        if webhooks::publish_payer_notice(payer.principal, &patient_id_hash, &execution_id, &execution_types, executed_at) > 0 {
+            notified += 1;
+        }
+    }
+    ic_cdk::println!("AUDIT: Execution {} reported to {} payer(s)", execution_id, notified);
+    Ok(notified)
+}
+
+// Checks a directive's consent before it is written and stamps when it was given
+pub(crate) fn validate_consent(consent: &mut Option<PayerNotificationConsent>) -> Result<(), String> {
+    let Some(consent) = consent else {
+        return Ok(());
+    };
+    validation::collection("payer_notification.payer_ids", consent.payer_ids.len(), MAX_PAYERS_PER_DIRECTIVE)?;
+    if let Some(unknown) = consent.payer_ids.iter().find(|id| !PAYERS.with(|p| p.borrow().contains_key(*id))) {
+        return Err(validation::invalid("payer_notification.payer_ids", &format!("unknown payer {}", unknown)));
+    }
+    consent.consented_at = clock::now();
+    Ok(())
+}
+
+pub(crate) fn is_payer(principal: Principal) -> bool {
+    PAYERS.with(|p| p.borrow().values().any(|payer| payer.active && payer.principal == principal))
+}
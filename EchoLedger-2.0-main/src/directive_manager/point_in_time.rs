@@ -0,0 +1,221 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::events::{self, DirectiveEvent, DirectiveEventKind};
+use crate::{directive_owner, AmendmentProposal, ConsentDirective, ProxyGrant};
+
+// What the canister believed about one patient at a given instant
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DirectiveAsOf {
+    pub patient_id: String,
+    pub as_of: u64,
+    pub directive: Option<ConsentDirective>,
+    pub version: u64,
+    pub owner: Option<Principal>,
+    pub proxy_grants: Vec<ProxyGrant>,
+    pub open_amendments: Vec<AmendmentProposal>,
+    pub last_changed_at: Option<u64>,
+    pub events_replayed: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct SystemStateAsOf {
+    pub as_of: u64,
+    pub event_count: u64,
+    pub patients_with_directives: u64,
+    pub active_directives: u64,
+    pub inactive_directives: u64,
+    pub directives_by_type: Vec<(String, u64)>,
+    pub open_amendments: u64,
+    pub proxy_grants: u64,
+}
+
+// Only the patient-id-keyed slice of state; hash-keyed records are not reconstructed
+#[derive(Clone, Default)]
+struct HistoricalState {
+    directives: BTreeMap<String, ConsentDirective>,
+    versions: BTreeMap<String, u64>,
+    owners: BTreeMap<String, Principal>,
+    proxy_grants: BTreeMap<String, Vec<ProxyGrant>>,
+    proposals: BTreeMap<String, AmendmentProposal>,
+    changed_at: BTreeMap<String, u64>,
+    events_applied: u64,
+    last_event_at: u64,
+}
+
+thread_local! {
+    // One snapshot every SNAPSHOT_SPACING events, so a point-in-time query replays a bounded tail.
+    // Each is a full copy of the slice, so at most MAX_SNAPSHOTS are kept: when they run over, every
+    // other one is dropped and the spacing doubles. Replay tails grow with the log; memory does not.
+    static SNAPSHOTS: std::cell::RefCell<Vec<HistoricalState>> = std::cell::RefCell::new(Vec::new());
+    static SNAPSHOT_SPACING: std::cell::Cell<u64> = const { std::cell::Cell::new(SNAPSHOT_INTERVAL) };
+}
+
+const SNAPSHOT_INTERVAL: u64 = 500;
+const MAX_SNAPSHOTS: usize = 32;
+
+#[ic_cdk::query]
+fn get_directive_as_of(patient_id: String, timestamp: u64) -> Result<DirectiveAsOf, String> {
+    let requester = caller();
+    if !ic_cdk::api::is_controller(&requester) && directive_owner(&patient_id) != Some(requester) {
+        return Err("Only controllers or the patient may query directive history".to_string());
+    }
+
+    let state = state_as_of(timestamp);
+    Ok(DirectiveAsOf {
+        directive: state.directives.get(&patient_id).cloned(),
+        version: state.versions.get(&patient_id).copied().unwrap_or(0),
+        owner: state.owners.get(&patient_id).copied(),
+        proxy_grants: state.proxy_grants.get(&patient_id).cloned().unwrap_or_default(),
+        open_amendments: state.proposals
+            .values()
+            .filter(|p| p.patient_id == patient_id && p.status == "PROPOSED")
+            .cloned()
+            .collect(),
+        last_changed_at: state.changed_at.get(&patient_id).copied(),
+        events_replayed: state.events_applied,
+        patient_id,
+        as_of: timestamp,
+    })
+}
+
+#[ic_cdk::query]
+fn get_system_state_as_of(timestamp: u64) -> Result<SystemStateAsOf, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may query system history".to_string());
+    }
+
+    let state = state_as_of(timestamp);
+    let mut by_type: BTreeMap<String, u64> = BTreeMap::new();
+    let mut active = 0;
+    for directive in state.directives.values() {
+        if crate::emergency::INACTIVE_STATUSES.contains(&directive.status.as_str()) {
+            continue;
+        }
+        active += 1;
+        *by_type.entry(directive.directive_type.clone()).or_default() += 1;
+    }
+    Ok(SystemStateAsOf {
+        as_of: timestamp,
+        event_count: state.events_applied,
+        patients_with_directives: state.directives.len() as u64,
+        active_directives: active,
+        inactive_directives: state.directives.len() as u64 - active,
+        directives_by_type: by_type.into_iter().collect(),
+        open_amendments: state.proposals.values().filter(|p| p.status == "PROPOSED").count() as u64,
+        proxy_grants: state.proxy_grants.values().map(|g| g.len() as u64).sum(),
+    })
+}
+
+// Called after each recorded event; folds the next interval into a new snapshot when it fills
+pub(crate) fn on_event_recorded(sequence: u64) {
+    if (sequence + 1) % SNAPSHOT_SPACING.with(|s| s.get()) == 0 {
+        let mut state = SNAPSHOTS.with(|s| s.borrow().last().cloned()).unwrap_or_default();
+        for event in events::events_from(state.events_applied) {
+            fold(&mut state, &event);
+        }
+        keep_snapshot(state);
+    }
+}
+
+pub(crate) fn rebuild_snapshots() {
+    SNAPSHOTS.with(|s| s.borrow_mut().clear());
+    SNAPSHOT_SPACING.with(|s| s.set(SNAPSHOT_INTERVAL));
+    let mut state = HistoricalState::default();
+    for event in events::events_from(0) {
+        fold(&mut state, &event);
+        if state.events_applied % SNAPSHOT_SPACING.with(|s| s.get()) == 0 {
+            keep_snapshot(state.clone());
+        }
+    }
+}
+
+fn keep_snapshot(state: HistoricalState) {
+    SNAPSHOTS.with(|s| {
+        let mut snapshots = s.borrow_mut();
+        snapshots.push(state);
+        if snapshots.len() > MAX_SNAPSHOTS {
+            let spacing = SNAPSHOT_SPACING.with(|s| s.get()).saturating_mul(2);
+            snapshots.retain(|snapshot| snapshot.events_applied % spacing == 0);
+            SNAPSHOT_SPACING.with(|s| s.set(spacing));
+        }
+    });
+}
+
+fn state_as_of(timestamp: u64) -> HistoricalState {
+    let mut state = SNAPSHOTS.with(|s| {
+        let snapshots = s.borrow();
+        let usable = snapshots.partition_point(|snapshot| snapshot.last_event_at <= timestamp);
+        usable.checked_sub(1).map(|i| snapshots[i].clone())
+    }).unwrap_or_default();
+
+    for event in events::events_from(state.events_applied) {
+        if event.recorded_at > timestamp {
+            break;
+        }
+        fold(&mut state, &event);
+    }
+    state
+}
+
+fn fold(state: &mut HistoricalState, event: &DirectiveEvent) {
+    state.events_applied = event.sequence + 1;
+    state.last_event_at = event.recorded_at;
+
+    let patient_id = match &event.kind {
+        DirectiveEventKind::ConsentUpdated { directive, version } => {
+            state.versions.insert(directive.patient_id.clone(), *version);
+            state.directives.insert(directive.patient_id.clone(), directive.clone());
+            Some(directive.patient_id.clone())
+        }
+        DirectiveEventKind::OwnerAssigned { patient_id, owner, .. } => {
+            state.owners.insert(patient_id.clone(), *owner);
+            Some(patient_id.clone())
+        }
+        DirectiveEventKind::ProxyGranted { patient_id, grant } => {
+            let list = state.proxy_grants.entry(patient_id.clone()).or_default();
+            list.retain(|g| g.proxy != grant.proxy);
+            list.push(grant.clone());
+            Some(patient_id.clone())
+        }
+        DirectiveEventKind::AmendmentProposed(proposal) => {
+            state.proposals.insert(proposal.proposal_id.clone(), proposal.clone());
+            Some(proposal.patient_id.clone())
+        }
+        DirectiveEventKind::AmendmentDecided {
+            proposal_id,
+            status,
+            decided_by,
+            decided_at,
+            acceptance_signature,
+            resulting_version,
+        } => state.proposals.get_mut(proposal_id).map(|p| {
+            p.status = status.clone();
+            p.decided_at = Some(*decided_at);
+            p.decided_by = Some(*decided_by);
+            p.acceptance_signature = acceptance_signature.clone();
+            p.resulting_version = *resulting_version;
+            p.patient_id.clone()
+        }),
+        DirectiveEventKind::DirectiveStored(_)
+        | DirectiveEventKind::MetadataArchived { .. }
+        | DirectiveEventKind::MetadataSharded { .. }
+        | DirectiveEventKind::VisibilityUpdated { .. }
+        | DirectiveEventKind::ContactRegistered { .. }
+        | DirectiveEventKind::ContactRemoved { .. }
+        | DirectiveEventKind::PatientRekeyed { .. }
+        | DirectiveEventKind::TenantSaved(_)
+        | DirectiveEventKind::SharingAgreementSaved(_)
+        | DirectiveEventKind::TenantDefaultsSaved(_)
+        | DirectiveEventKind::LocaleSet { .. } => None,
+        DirectiveEventKind::PatientEnrolled { patient_id, .. }
+        | DirectiveEventKind::EnrollmentConsented { patient_id, .. }
+        | DirectiveEventKind::PatientUnenrolled { patient_id } => Some(patient_id.clone()),
+    };
+
+    if let Some(patient_id) = patient_id {
+        state.changed_at.insert(patient_id, event.recorded_at);
+    }
+}
@@ -0,0 +1,76 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{clock, hashing, ids, tenants, validation, PATIENT_HASH_INDEX};
+
+// Opaque references to a patient for everything that leaves EchoLedger. A recipient (a webhook
+// subscription, a contact, an exchange partner) always gets the same reference for the same
+// patient, so it can correlate its own messages, but no two recipients share one and none of them
+// is derived from the patient hash. Only this canister can map a reference back.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ExternalReference {
+    pub reference: String,
+    pub recipient: String,
+    pub patient_id_hash: Vec<u8>,
+    pub issued_at: u64,
+}
+
+const MAX_RECIPIENT_BYTES: usize = 256;
+
+thread_local! {
+    static EXTERNAL_REFERENCES: std::cell::RefCell<BTreeMap<String, ExternalReference>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    // (recipient, patient_id_hash) -> reference
+    static REFERENCE_INDEX: std::cell::RefCell<BTreeMap<(String, Vec<u8>), String>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+// Sibling canisters name the patient this way in what they send out themselves
+#[ic_cdk::update]
+fn issue_external_reference(patient_id_hash: Vec<u8>, recipient: String) -> Result<String, String> {
+    if !tenants::is_platform(caller()) {
+        return Err("Only EchoLedger canisters may issue external references".to_string());
+    }
+    validation::bytes("patient_id_hash", &patient_id_hash, validation::MAX_HASH_BYTES)?;
+    let recipient = validation::text("recipient", &recipient, MAX_RECIPIENT_BYTES)?;
+    if recipient.is_empty() {
+        return Err(validation::invalid("recipient", "must not be empty"));
+    }
+    Ok(reference_for(&recipient, &patient_id_hash))
+}
+
+// Anyone who may read the patient's record may learn which patient a reference stands for
+#[ic_cdk::query]
+fn resolve_external_reference(reference: String) -> Result<ExternalReference, String> {
+    let mut resolved = EXTERNAL_REFERENCES.with(|r| r.borrow().get(&reference).cloned())
+        .ok_or("Unknown reference")?;
+    resolved.patient_id_hash = hashing::storage_key(&resolved.patient_id_hash);
+    let patient_id = PATIENT_HASH_INDEX.with(|index| index.borrow().get(&resolved.patient_id_hash).cloned());
+    if !patient_id.is_some_and(|id| tenants::may_access_patient(caller(), &id, None)) {
+        return Err("Unknown reference".to_string());
+    }
+    Ok(resolved)
+}
+
+pub(crate) fn reference_for(recipient: &str, patient_id_hash: &[u8]) -> String {
+    let patient_id_hash = hashing::storage_key(patient_id_hash);
+    let key = (recipient.to_string(), patient_id_hash.clone());
+    if let Some(reference) = REFERENCE_INDEX.with(|index| index.borrow().get(&key).cloned()) {
+        return reference;
+    }
+    let reference = ids::new_id("PREF");
+    EXTERNAL_REFERENCES.with(|r| {
+        r.borrow_mut().insert(reference.clone(), ExternalReference {
+            reference: reference.clone(),
+            recipient: recipient.to_string(),
+            patient_id_hash,
+            issued_at: clock::now(),
+        })
+    });
+    REFERENCE_INDEX.with(|index| index.borrow_mut().insert(key, reference.clone()));
+    reference
+}
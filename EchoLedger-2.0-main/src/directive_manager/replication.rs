@@ -0,0 +1,398 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use crate::clinician_summary::{self, ClinicianSummary};
+use crate::emergency::{self, EmergencyAccessToken, EmergencyDirective};
+use crate::{
+    clock, cross_border, events, hashing, identity, load_shedding, VisibilityPreferences, PATIENT_HASH_INDEX, VISIBILITY_PREFERENCES,
+};
+
+// Read replicas on other subnets, so emergency lookups are answered near the hospital. The same
+// wasm runs in either role. The primary keeps every write and pushes each replica what a lookup
+// needs: per-patient snapshots of the directives an emergency lookup would disclose, the live
+// emergency tokens and the active patient hash key. Only the registered primary may push, and the
+// IC authenticates it as the caller. Each snapshot carries a version that only ever grows on the
+// primary, upgrades included, and a replica keeps the newest it has seen, so retries and reordered
+// batches converge.
+// Replicas log their own grants; anything they cannot answer the bridge asks the primary.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub enum ReplicationRole {
+    Primary,
+    Replica { primary: Principal, bridges: Vec<Principal> },
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ReadReplica {
+    pub canister: Principal,
+    pub region: String,
+    pub registered_at: u64,
+    pub last_synced_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct PatientSnapshot {
+    pub patient_id_hash: Vec<u8>,
+    pub directives: Vec<EmergencyDirective>, // Empty when nothing is disclosable; the replica drops the patient
+    pub preferences: Option<VisibilityPreferences>,
+    pub clinician_summary: Option<ClinicianSummary>,
+    pub version: u64,
+    pub cross_border_policy: Option<String>, // lookups of these patients go to the primary
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct ReplicationBatch {
+    pub key_version: u32,
+    pub key_secret: Vec<u8>,
+    pub snapshots: Vec<PatientSnapshot>,
+    pub tokens: Vec<EmergencyAccessToken>,
+    pub sent_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ReplicationAck {
+    pub applied: u32,
+    pub needs_full_sync: bool,
+}
+
+// Mirrored by emergency_bridge
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ReplicaLookup {
+    pub patient_id_hash: Vec<u8>,
+    pub directives: Vec<EmergencyDirective>,
+    pub preferences: Option<VisibilityPreferences>,
+    pub clinician_summary: Option<ClinicianSummary>,
+    pub synced_at: u64,
+    pub token_expires_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ReplicationStatus {
+    pub role: ReplicationRole,
+    pub replicas: Vec<ReadReplica>,
+    pub pending_snapshots: u64,
+    pub replicated_patients: u64,
+    pub last_synced_at: Option<u64>,
+}
+
+// Carried across upgrades alongside the hash key ring; replica snapshots are rebuilt by a full sync
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct ReplicationState {
+    role: ReplicationRole,
+    replicas: Vec<ReadReplica>,
+    last_snapshot_version: Option<u64>, // Absent in images saved before versions were carried
+}
+
+const PUSH_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_SNAPSHOTS_PER_BATCH: usize = 200;
+const MAX_REPLICAS: usize = 16;
+const MAX_REGION_BYTES: usize = 64;
+
+thread_local! {
+    static ROLE: std::cell::RefCell<ReplicationRole> = const { std::cell::RefCell::new(ReplicationRole::Primary) };
+
+    // Primary side
+    static REPLICAS: std::cell::RefCell<BTreeMap<Principal, ReadReplica>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    // replica -> patient ids whose snapshot it has yet to receive
+    static PENDING_PATIENTS: std::cell::RefCell<BTreeMap<Principal, BTreeSet<String>>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    static PUSH_TIMER_STARTED: std::cell::RefCell<bool> = const { std::cell::RefCell::new(false) };
+
+    static PUSH_IN_FLIGHT: std::cell::RefCell<bool> = const { std::cell::RefCell::new(false) };
+
+    static LAST_SNAPSHOT_VERSION: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+
+    // Replica side: derived patient hash -> snapshot
+    static SNAPSHOTS: std::cell::RefCell<BTreeMap<Vec<u8>, PatientSnapshot>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    static SYNCED_KEY_VERSION: std::cell::RefCell<Option<u32>> = const { std::cell::RefCell::new(None) };
+
+    static LAST_SYNCED_AT: std::cell::RefCell<Option<u64>> = const { std::cell::RefCell::new(None) };
+}
+
+// A canister that already holds directives cannot be turned into a replica and lose them
+#[ic_cdk::update]
+fn set_replication_role(role: ReplicationRole) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may set the replication role".to_string());
+    }
+    if let ReplicationRole::Replica { primary, bridges } = &role {
+        if events::head() > 0 {
+            return Err("This canister holds directive events and must stay a primary".to_string());
+        }
+        if *primary == ic_cdk::api::id() || bridges.is_empty() {
+            return Err("A replica needs another canister as primary and at least one bridge".to_string());
+        }
+    }
+    ROLE.with(|r| *r.borrow_mut() = role);
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn register_read_replica(canister: Principal, region: String) -> Result<ReadReplica, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may register read replicas".to_string());
+    }
+    if is_replica() {
+        return Err("Read replicas are registered on the primary".to_string());
+    }
+    if region.trim().is_empty() || region.len() > MAX_REGION_BYTES {
+        return Err(format!("region must be 1 to {} bytes", MAX_REGION_BYTES));
+    }
+    let replica = ReadReplica {
+        canister,
+        region: region.trim().to_string(),
+        registered_at: clock::now(),
+        last_synced_at: None,
+        last_error: None,
+    };
+    REPLICAS.with(|r| {
+        let mut replicas = r.borrow_mut();
+        if !replicas.contains_key(&canister) && replicas.len() >= MAX_REPLICAS {
+            return Err(format!("At most {} read replicas may be registered", MAX_REPLICAS));
+        }
+        replicas.insert(canister, replica.clone());
+        Ok(())
+    })?;
+    queue_all_patients(canister);
+    ensure_push_timer();
+    Ok(replica)
+}
+
+#[ic_cdk::update]
+fn deregister_read_replica(canister: Principal) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may deregister read replicas".to_string());
+    }
+    PENDING_PATIENTS.with(|p| p.borrow_mut().remove(&canister));
+    REPLICAS.with(|r| r.borrow_mut().remove(&canister))
+        .map(|_| ())
+        .ok_or_else(|| "Read replica not registered".to_string())
+}
+
+#[ic_cdk::query]
+fn get_replication_status() -> Result<ReplicationStatus, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read replication status".to_string());
+    }
+    Ok(ReplicationStatus {
+        role: ROLE.with(|r| r.borrow().clone()),
+        replicas: REPLICAS.with(|r| r.borrow().values().cloned().collect()),
+        pending_snapshots: PENDING_PATIENTS.with(|p| p.borrow().values().map(|ids| ids.len() as u64).sum()),
+        replicated_patients: SNAPSHOTS.with(|s| s.borrow().len() as u64),
+        last_synced_at: LAST_SYNCED_AT.with(|l| *l.borrow()),
+    })
+}
+
+// Replica side: the primary's push. A new key version means every held snapshot is keyed wrongly.
+#[ic_cdk::update]
+fn apply_replication_batch(batch: ReplicationBatch) -> Result<ReplicationAck, String> {
+    let ReplicationRole::Replica { primary, .. } = ROLE.with(|r| r.borrow().clone()) else {
+        return Err("This canister is not a read replica".to_string());
+    };
+    if caller() != primary {
+        return Err("Replication batches are accepted from the primary only".to_string());
+    }
+
+    let needs_full_sync = SYNCED_KEY_VERSION.with(|v| *v.borrow()) != Some(batch.key_version);
+    if needs_full_sync {
+        hashing::install_replicated_key(batch.key_version, batch.key_secret);
+        SNAPSHOTS.with(|s| s.borrow_mut().clear());
+        SYNCED_KEY_VERSION.with(|v| *v.borrow_mut() = Some(batch.key_version));
+    }
+    emergency::replace_tokens(batch.tokens);
+
+    let mut applied = 0;
+    SNAPSHOTS.with(|s| {
+        let mut snapshots = s.borrow_mut();
+        for snapshot in batch.snapshots {
+            if snapshots.get(&snapshot.patient_id_hash).is_some_and(|held| held.version >= snapshot.version) {
+                continue;
+            }
+            applied += 1;
+            if snapshot.directives.is_empty() {
+                snapshots.remove(&snapshot.patient_id_hash);
+            } else {
+                snapshots.insert(snapshot.patient_id_hash.clone(), snapshot);
+            }
+        }
+    });
+    LAST_SYNCED_AT.with(|l| *l.borrow_mut() = Some(clock::now()));
+    Ok(ReplicationAck { applied, needs_full_sync })
+}
+
+// Replica side: emergency_bridge's local lookup. Misses and refusals are not logged here; the
+// bridge retries them against the primary, which logs the outcome.
+#[ic_cdk::update]
+fn replica_emergency_lookup(patient_id: String, requester: Principal, token: String) -> Result<ReplicaLookup, String> {
+    let ReplicationRole::Replica { bridges, .. } = ROLE.with(|r| r.borrow().clone()) else {
+        return Err("This canister is not a read replica".to_string());
+    };
+    let via = caller();
+    if !bridges.contains(&via) && !ic_cdk::api::is_controller(&via) {
+        return Err("Replica lookups must come through a local emergency_bridge".to_string());
+    }
+    let _permit = load_shedding::admit("EMERGENCY")?;
+    let synced_at = LAST_SYNCED_AT.with(|l| *l.borrow()).ok_or("Replica has not synced yet")?;
+    emergency::validate_token(&token, requester)?;
+
+    let patient_id_hash = hashing::patient_hash(&patient_id);
+    let snapshot = SNAPSHOTS.with(|s| s.borrow().get(&patient_id_hash).cloned())
+        .ok_or("Patient is not replicated here")?;
+    if let Some(policy_id) = &snapshot.cross_border_policy {
+        return Err(format!("Patient is covered by cross-border policy {}, which the primary applies", policy_id));
+    }
+    let directive_types = snapshot.directives.iter().map(|d| d.directive_type.clone()).collect();
+    emergency::log_access(&patient_id_hash, requester, via, "GRANTED", directive_types);
+    Ok(ReplicaLookup {
+        patient_id_hash,
+        directives: snapshot.directives,
+        preferences: snapshot.preferences,
+        clinician_summary: snapshot.clinician_summary,
+        synced_at,
+        token_expires_at: emergency::token_expires_at(&token),
+    })
+}
+
+pub(crate) fn is_replica() -> bool {
+    ROLE.with(|r| matches!(*r.borrow(), ReplicationRole::Replica { .. }))
+}
+
+// Queue a patient, by the hash its records are stored under, for every replica
+pub(crate) fn mark_patient_changed(patient_id_hash: &[u8]) {
+    if REPLICAS.with(|r| r.borrow().is_empty()) {
+        return;
+    }
+    let Some(patient_id) = PATIENT_HASH_INDEX.with(|index| index.borrow().get(patient_id_hash).cloned()) else {
+        return;
+    };
+    PENDING_PATIENTS.with(|p| {
+        for pending in p.borrow_mut().values_mut() {
+            pending.insert(patient_id.clone());
+        }
+    });
+}
+
+// Timers do not survive upgrades; post_upgrade and replica registration restart the push
+pub(crate) fn ensure_push_timer() {
+    let started = PUSH_TIMER_STARTED.with(|s| std::mem::replace(&mut *s.borrow_mut(), true));
+    if !started {
+        ic_cdk_timers::set_timer_interval(PUSH_INTERVAL, || ic_cdk::spawn(push_to_replicas()));
+    }
+}
+
+pub(crate) fn snapshot() -> ReplicationState {
+    ReplicationState {
+        role: ROLE.with(|r| r.borrow().clone()),
+        replicas: REPLICAS.with(|r| r.borrow().values().cloned().collect()),
+        last_snapshot_version: Some(LAST_SNAPSHOT_VERSION.with(|v| v.get())),
+    }
+}
+
+pub(crate) fn restore(state: Option<ReplicationState>) {
+    let Some(state) = state else {
+        return;
+    };
+    ROLE.with(|r| *r.borrow_mut() = state.role);
+    LAST_SNAPSHOT_VERSION.with(|v| v.set(state.last_snapshot_version.unwrap_or(0)));
+    for replica in state.replicas {
+        let canister = replica.canister;
+        REPLICAS.with(|r| r.borrow_mut().insert(canister, replica));
+        queue_all_patients(canister);
+    }
+    if REPLICAS.with(|r| !r.borrow().is_empty()) {
+        ensure_push_timer();
+    }
+}
+
+// Every tick sends each replica a batch, empty or not, so its synced_at shows it is current
+async fn push_to_replicas() {
+    if is_replica() || PUSH_IN_FLIGHT.with(|f| std::mem::replace(&mut *f.borrow_mut(), true)) {
+        return;
+    }
+    let (key_version, key_secret) = hashing::active_key();
+    let tokens = emergency::live_tokens();
+    let replicas: Vec<Principal> = REPLICAS.with(|r| r.borrow().keys().copied().collect());
+
+    for replica in replicas {
+        // Taken out before the call so a change made while it is in flight is queued again
+        let patient_ids: Vec<String> = PENDING_PATIENTS.with(|p| {
+            let mut p = p.borrow_mut();
+            let pending = p.entry(replica).or_default();
+            let batch: Vec<String> = pending.iter().take(MAX_SNAPSHOTS_PER_BATCH).cloned().collect();
+            for patient_id in &batch {
+                pending.remove(patient_id);
+            }
+            batch
+        });
+        let batch = ReplicationBatch {
+            key_version,
+            key_secret: key_secret.clone(),
+            snapshots: patient_ids.iter().map(|patient_id| patient_snapshot(patient_id)).collect(),
+            tokens: tokens.clone(),
+            sent_at: clock::now(),
+        };
+
+        let result: Result<(Result<ReplicationAck, String>,), _> =
+            ic_cdk::call(replica, "apply_replication_batch", (batch,)).await;
+        let outcome = match result {
+            Ok((Ok(ack),)) => Ok(ack),
+            Ok((Err(e),)) => Err(e),
+            Err((_, msg)) => Err(msg),
+        };
+        match &outcome {
+            Ok(ack) if ack.needs_full_sync => queue_all_patients(replica),
+            Ok(_) => {}
+            Err(_) => PENDING_PATIENTS.with(|p| p.borrow_mut().entry(replica).or_default().extend(patient_ids)),
+        }
+        REPLICAS.with(|r| {
+            if let Some(registration) = r.borrow_mut().get_mut(&replica) {
+                match outcome {
+                    Ok(_) => {
+                        registration.last_synced_at = Some(clock::now());
+                        registration.last_error = None;
+                    }
+                    Err(e) => registration.last_error = Some(e),
+                }
+            }
+        });
+    }
+    PUSH_IN_FLIGHT.with(|f| *f.borrow_mut() = false);
+}
+
+// What an emergency lookup on the primary would disclose right now, keyed as the bridge will ask
+fn patient_snapshot(patient_id: &str) -> PatientSnapshot {
+    let patient_id_hash = hashing::patient_hash(patient_id);
+    let storage_key = identity::canonical_patient_hash(&hashing::storage_key(&patient_id_hash));
+    PatientSnapshot {
+        directives: emergency::active_directives(&storage_key),
+        preferences: VISIBILITY_PREFERENCES.with(|p| p.borrow().get(&storage_key).cloned()),
+        clinician_summary: clinician_summary::for_hash(&storage_key),
+        cross_border_policy: cross_border::policy_for_patient(&storage_key),
+        patient_id_hash,
+        version: next_snapshot_version(),
+    }
+}
+
+// Carried across upgrades and never behind the clock, so it stays ahead of any version a replica
+// holds - including those sent before it was persisted, which counted up from zero on each upgrade
+fn next_snapshot_version() -> u64 {
+    LAST_SNAPSHOT_VERSION.with(|v| {
+        let next = (v.get() + 1).max(clock::now());
+        v.set(next);
+        next
+    })
+}
+
+fn queue_all_patients(replica: Principal) {
+    let patient_ids: BTreeSet<String> = PATIENT_HASH_INDEX.with(|index| index.borrow().values().cloned().collect());
+    PENDING_PATIENTS.with(|p| p.borrow_mut().entry(replica).or_default().extend(patient_ids));
+}
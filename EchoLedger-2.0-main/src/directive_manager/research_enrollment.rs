@@ -0,0 +1,105 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+
+use crate::emergency::{EMERGENCY_BRIDGE_CANISTER_ID, INACTIVE_STATUSES};
+use crate::{validation, ConsentDirective, CONSENT_DIRECTIVES};
+
+// Pre-consent to, or refusal of, enrollment in emergency research run under an exception from
+// informed consent (EFIC), where the patient cannot be asked at the time. Separate from
+// DATA_CONSENT: this is about receiving an experimental intervention, decided per trial category,
+// with a default for categories the patient did not name. Research coordinators check it through
+// emergency_bridge.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct TrialCategoryPreference {
+    pub category: String, // One of TRIAL_CATEGORIES
+    pub decision: String, // "CONSENT" or "REFUSE"
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ResearchEnrollmentPreferences {
+    pub default_decision: String, // "CONSENT", "REFUSE" or "NO_PREFERENCE"
+    pub categories: Vec<TrialCategoryPreference>,
+    pub notes: Option<String>,
+}
+
+// What emergency_bridge relays to the coordinator
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct EnrollmentDecision {
+    pub category: String,
+    pub decision: String, // "CONSENT", "REFUSE" or "NO_PREFERENCE"
+    pub basis: String,    // "CATEGORY", "DEFAULT" or "NONE_ON_FILE"
+}
+
+const DIRECTIVE_TYPE: &str = "RESEARCH_ENROLLMENT";
+const TRIAL_CATEGORIES: [&str; 9] = [
+    "CARDIAC_ARREST",
+    "TRAUMA",
+    "TRAUMATIC_BRAIN_INJURY",
+    "STROKE",
+    "SEPSIS",
+    "RESPIRATORY_FAILURE",
+    "OVERDOSE",
+    "SEIZURE",
+    "OTHER",
+];
+const DECISIONS: [&str; 2] = ["CONSENT", "REFUSE"];
+
+// Only emergency_bridge, which checks the coordinator and their study, may ask
+#[ic_cdk::query]
+fn research_enrollment_decision(patient_id: String, category: String) -> Result<EnrollmentDecision, String> {
+    let via = caller();
+    if Principal::from_text(EMERGENCY_BRIDGE_CANISTER_ID).ok() != Some(via) && !ic_cdk::api::is_controller(&via) {
+        return Err("Enrollment checks must come through emergency_bridge".to_string());
+    }
+    let category = category.trim().to_uppercase();
+    if !TRIAL_CATEGORIES.contains(&category.as_str()) {
+        return Err(validation::invalid("category", &format!("must be one of {}", TRIAL_CATEGORIES.join(", "))));
+    }
+    let preferences = CONSENT_DIRECTIVES
+        .with(|d| d.borrow().get(&patient_id))
+        .filter(|directive| !INACTIVE_STATUSES.contains(&directive.status.as_str()))
+        .and_then(|directive| directive.research_enrollment);
+    let Some(preferences) = preferences else {
+        return Ok(EnrollmentDecision { category, decision: "NO_PREFERENCE".to_string(), basis: "NONE_ON_FILE".to_string() });
+    };
+    Ok(match preferences.categories.iter().find(|c| c.category == category) {
+        Some(named) => EnrollmentDecision { decision: named.decision.clone(), category, basis: "CATEGORY".to_string() },
+        None => EnrollmentDecision { decision: preferences.default_decision, category, basis: "DEFAULT".to_string() },
+    })
+}
+
+// Normalizes the preferences in place; a RESEARCH_ENROLLMENT directive must carry them
+pub(crate) fn validate(directive: &mut ConsentDirective) -> Result<(), String> {
+    if directive.directive_type == DIRECTIVE_TYPE && directive.research_enrollment.is_none() {
+        return Err(validation::invalid("research_enrollment", "a RESEARCH_ENROLLMENT directive needs its preferences"));
+    }
+    let Some(preferences) = directive.research_enrollment.as_mut() else {
+        return Ok(());
+    };
+    preferences.default_decision = preferences.default_decision.trim().to_uppercase();
+    if !DECISIONS.contains(&preferences.default_decision.as_str()) && preferences.default_decision != "NO_PREFERENCE" {
+        return Err(validation::invalid("research_enrollment.default_decision", "must be CONSENT, REFUSE or NO_PREFERENCE"));
+    }
+    validation::collection("research_enrollment.categories", preferences.categories.len(), TRIAL_CATEGORIES.len())?;
+    for preference in preferences.categories.iter_mut() {
+        preference.category = preference.category.trim().to_uppercase();
+        preference.decision = preference.decision.trim().to_uppercase();
+        if !TRIAL_CATEGORIES.contains(&preference.category.as_str()) {
+            return Err(validation::invalid("research_enrollment.categories.category", &format!("must be one of {}", TRIAL_CATEGORIES.join(", "))));
+        }
+        if !DECISIONS.contains(&preference.decision.as_str()) {
+            return Err(validation::invalid("research_enrollment.categories.decision", "must be CONSENT or REFUSE"));
+        }
+    }
+    let mut seen: Vec<&str> = preferences.categories.iter().map(|c| c.category.as_str()).collect();
+    seen.sort_unstable();
+    if seen.windows(2).any(|w| w[0] == w[1]) {
+        return Err(validation::invalid("research_enrollment.categories", "each category may appear once"));
+    }
+    if let Some(notes) = &preferences.notes {
+        preferences.notes = Some(validation::text("research_enrollment.notes", notes, validation::MAX_RATIONALE_BYTES)?);
+    }
+    Ok(())
+}
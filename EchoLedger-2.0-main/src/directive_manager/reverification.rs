@@ -0,0 +1,275 @@
+use candid::{CandidType, Deserialize};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::{
+    autopsy, clock, deliver_to_contacts, digital_legacy, disposition, hashing, honeytokens, i18n, ids, payers, research_enrollment,
+    shards, tenants, ConsentDirective, ContactEvent, AMENDMENT_PROPOSALS, CONSENT_DIRECTIVES, CONSENT_DIRECTIVE_VERSIONS,
+    OPERATOR_CONTACTS, POA_AMEND_SCOPE, PROXY_GRANTS,
+};
+
+// A directive that was valid when it was written can stop being so: the rules its detail sections
+// are checked against change, metadata moves or expires, proxies lose their standing. A daily sweep
+// re-checks every current directive against today's rules and registries, keeps a report of each
+// run and flags the directives that no longer pass until a later run finds them fixed. Witness
+// attestations are not recorded on directives, so there is nothing to re-check for them here.
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ReverificationFinding {
+    pub check: String, // "SIGNATURE", "RULESET", "OFF_CHAIN_REF", "PROXY"
+    pub detail: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct FlaggedDirective {
+    pub patient_id_hash: Vec<u8>,
+    pub directive_type: String,
+    pub version: u64,
+    pub findings: Vec<ReverificationFinding>,
+    pub first_flagged_at: u64,
+    pub last_run_id: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ReverificationReport {
+    pub run_id: String,
+    pub started_at: u64,
+    pub completed_at: u64,
+    pub checked: u32,
+    pub passed: u32,
+    pub newly_flagged: u32,
+    pub still_flagged: u32,
+    pub cleared: u32,
+}
+
+const REVERIFICATION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_REPORTS: usize = 90;
+
+thread_local! {
+    // Per storage key of the patient hash
+    static FLAGGED_DIRECTIVES: std::cell::RefCell<BTreeMap<Vec<u8>, FlaggedDirective>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    static REPORTS: std::cell::RefCell<Vec<ReverificationReport>> = const { std::cell::RefCell::new(Vec::new()) };
+
+    static SWEEP_TIMER_STARTED: std::cell::RefCell<bool> = const { std::cell::RefCell::new(false) };
+
+    static SWEEP_IN_FLIGHT: std::cell::RefCell<bool> = const { std::cell::RefCell::new(false) };
+}
+
+// Runs a sweep now, outside the schedule
+#[ic_cdk::update]
+async fn run_reverification_sweep() -> Result<ReverificationReport, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may run the re-verification sweep".to_string());
+    }
+    ensure_sweep_timer();
+    sweep().await
+}
+
+#[ic_cdk::query]
+fn get_reverification_reports() -> Result<Vec<ReverificationReport>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read re-verification reports".to_string());
+    }
+    Ok(REPORTS.with(|r| r.borrow().clone()))
+}
+
+#[ic_cdk::query]
+fn get_flagged_directives() -> Result<Vec<FlaggedDirective>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read flagged directives".to_string());
+    }
+    Ok(FLAGGED_DIRECTIVES.with(|f| f.borrow().values().cloned().collect()))
+}
+
+// Timers do not survive upgrades; post_upgrade restarts the sweep
+pub(crate) fn ensure_sweep_timer() {
+    let started = SWEEP_TIMER_STARTED.with(|s| std::mem::replace(&mut *s.borrow_mut(), true));
+    if !started {
+        ic_cdk_timers::set_timer_interval(REVERIFICATION_INTERVAL, || ic_cdk::spawn(async {
+            if let Err(e) = sweep().await {
+                ic_cdk::println!("Re-verification sweep skipped: {}", e);
+            }
+        }));
+    }
+}
+
+async fn sweep() -> Result<ReverificationReport, String> {
+    if SWEEP_IN_FLIGHT.with(|f| std::mem::replace(&mut *f.borrow_mut(), true)) {
+        return Err("A re-verification sweep is already running".to_string());
+    }
+    let run_id = ids::new_id("REVERIFY");
+    let started_at = clock::now();
+    // Canaries are never valid and never meant to be
+    let directives: Vec<ConsentDirective> = CONSENT_DIRECTIVES.with(|d| {
+        d.borrow()
+            .entries()
+            .into_iter()
+            .map(|(_, directive)| directive)
+            .filter(|directive| !honeytokens::is_honeytoken(&directive.patient_id))
+            .collect()
+    });
+
+    let mut failing: BTreeMap<Vec<u8>, FlaggedDirective> = BTreeMap::new();
+    for directive in &directives {
+        let patient_id_hash = hashing::patient_hash(&directive.patient_id);
+        let version = CONSENT_DIRECTIVE_VERSIONS.with(|v| {
+            v.borrow().get(&directive.patient_id).map_or(0, |history| history.len() as u64)
+        });
+        let mut findings = check_signature(directive, version);
+        findings.extend(check_ruleset(directive));
+        findings.extend(check_off_chain_ref(&patient_id_hash).await);
+        findings.extend(check_proxies(&directive.patient_id));
+        if !findings.is_empty() {
+            failing.insert(patient_id_hash.clone(), FlaggedDirective {
+                patient_id_hash,
+                directive_type: directive.directive_type.clone(),
+                version,
+                findings,
+                first_flagged_at: started_at,
+                last_run_id: run_id.clone(),
+            });
+        }
+    }
+
+    let (newly_flagged, still_flagged, cleared) = FLAGGED_DIRECTIVES.with(|f| {
+        let mut flagged = f.borrow_mut();
+        let cleared = flagged.keys().filter(|key| !failing.contains_key(*key)).count() as u32;
+        let mut newly_flagged = Vec::new();
+        for (key, entry) in failing.iter_mut() {
+            match flagged.get(key) {
+                Some(previous) => entry.first_flagged_at = previous.first_flagged_at,
+                None => newly_flagged.push(entry.clone()),
+            }
+        }
+        let still_flagged = (failing.len() - newly_flagged.len()) as u32;
+        *flagged = failing;
+        (newly_flagged, still_flagged, cleared)
+    });
+
+    let report = ReverificationReport {
+        run_id,
+        started_at,
+        completed_at: clock::now(),
+        checked: directives.len() as u32,
+        passed: directives.len() as u32 - newly_flagged.len() as u32 - still_flagged,
+        newly_flagged: newly_flagged.len() as u32,
+        still_flagged,
+        cleared,
+    };
+    REPORTS.with(|r| {
+        let mut reports = r.borrow_mut();
+        if reports.len() >= MAX_REPORTS {
+            reports.remove(0);
+        }
+        reports.push(report.clone());
+    });
+    SWEEP_IN_FLIGHT.with(|f| *f.borrow_mut() = false);
+    if !newly_flagged.is_empty() {
+        page_operators(&report, &newly_flagged);
+    }
+    Ok(report)
+}
+
+// Present, and the one the patient gave when the version came from an accepted amendment
+fn check_signature(directive: &ConsentDirective, version: u64) -> Vec<ReverificationFinding> {
+    if directive.signature.is_empty() {
+        return vec![finding("SIGNATURE", "The current version carries no signature".to_string())];
+    }
+    let acceptance = AMENDMENT_PROPOSALS.with(|p| {
+        p.borrow()
+            .values()
+            .find(|x| x.patient_id == directive.patient_id && x.status == "ACCEPTED" && x.resulting_version == Some(version))
+            .map(|x| (x.acceptance_signature.clone(), x.decided_by))
+    });
+    let Some((acceptance_signature, decided_by)) = acceptance else {
+        return Vec::new();
+    };
+    let mut findings = Vec::new();
+    if acceptance_signature.as_ref() != Some(&directive.signature) {
+        findings.push(finding("SIGNATURE", format!("Version {} does not carry the signature its amendment was accepted with", version)));
+    }
+    // A proxy who accepted the amendment must still hold the power to have done so
+    if let Some(decider) = decided_by.filter(|d| tenants::is_proxy(&directive.patient_id, *d)) {
+        let holds_scope = PROXY_GRANTS.with(|g| {
+            g.borrow().get(&directive.patient_id).is_some_and(|list| {
+                list.iter().any(|grant| grant.proxy == decider && grant.scopes.iter().any(|s| s == POA_AMEND_SCOPE))
+            })
+        });
+        if !holds_scope {
+            findings.push(finding("SIGNATURE", format!("Version {} was accepted by a proxy who no longer holds {}", version, POA_AMEND_SCOPE)));
+        }
+    }
+    findings
+}
+
+// The detail sections against the validators as they stand now
+fn check_ruleset(directive: &ConsentDirective) -> Vec<ReverificationFinding> {
+    let mut copy = directive.clone();
+    [
+        payers::validate_consent(&mut copy.payer_notification),
+        digital_legacy::validate(&mut copy),
+        disposition::validate(&mut copy),
+        autopsy::validate(&mut copy),
+        research_enrollment::validate(&mut copy),
+    ]
+    .into_iter()
+    .filter_map(Result::err)
+    .map(|e| finding("RULESET", e))
+    .collect()
+}
+
+async fn check_off_chain_ref(patient_id_hash: &[u8]) -> Vec<ReverificationFinding> {
+    let metadata = match shards::load_metadata(&hashing::storage_key(patient_id_hash)).await {
+        Ok(Some(metadata)) => metadata,
+        Ok(None) => return vec![finding("OFF_CHAIN_REF", "No directive metadata resolves for this patient".to_string())],
+        Err(e) => return vec![finding("OFF_CHAIN_REF", format!("Directive metadata could not be resolved: {}", e))],
+    };
+    if metadata.off_chain_ref.is_empty() {
+        return vec![finding("OFF_CHAIN_REF", "The metadata carries no off-chain reference".to_string())];
+    }
+    // retention_period is in milliseconds; zero means none was set
+    let expires_at = metadata.created_at.saturating_add(metadata.retention_period.saturating_mul(1_000_000));
+    if metadata.retention_period > 0 && expires_at <= clock::now() {
+        return vec![finding("OFF_CHAIN_REF", "The off-chain record is past its retention period".to_string())];
+    }
+    Vec::new()
+}
+
+// Proxies belonging to an organization are only as active as its tenancy
+fn check_proxies(patient_id: &str) -> Vec<ReverificationFinding> {
+    let grants = PROXY_GRANTS.with(|g| g.borrow().get(patient_id).cloned()).unwrap_or_default();
+    grants
+        .iter()
+        .filter_map(|grant| tenants::membership_of(grant.proxy).map(|m| (grant, m)))
+        .filter(|(_, membership)| membership.status != "ACTIVE")
+        .map(|(grant, membership)| {
+            finding("PROXY", format!(
+                "Proxy {} belongs to tenant {}, which is {}",
+                grant.proxy.to_text(), membership.tenant_id, membership.status
+            ))
+        })
+        .collect()
+}
+
+fn finding(check: &str, detail: String) -> ReverificationFinding {
+    ReverificationFinding { check: check.to_string(), detail }
+}
+
+fn page_operators(report: &ReverificationReport, newly_flagged: &[FlaggedDirective]) {
+    let checks: std::collections::BTreeSet<&str> = newly_flagged
+        .iter()
+        .flat_map(|f| f.findings.iter().map(|x| x.check.as_str()))
+        .collect();
+    let event = ContactEvent {
+        event_type: "DIRECTIVE_REVERIFICATION".to_string(),
+        reference_id: report.run_id.clone(),
+        summary: format!("{} directive(s) no longer pass re-verification", newly_flagged.len()),
+        details: format!("Failed checks: {}", checks.into_iter().collect::<Vec<_>>().join(", ")),
+    };
+    let contacts = OPERATOR_CONTACTS.with(|contacts| contacts.borrow().clone());
+    deliver_to_contacts(&contacts, &event, i18n::DEFAULT_LOCALE, None);
+}
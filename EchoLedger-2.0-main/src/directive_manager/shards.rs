@@ -0,0 +1,394 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::{call, caller};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::events::{self, DirectiveEventKind};
+use crate::{clock, hashing, honeytokens, ids, load_shedding, storage, tenants, PHIMetadata, PATIENT_HASH_INDEX, PHI_METADATA};
+
+// Directive-store canisters that hold PHI metadata once one canister's memory no longer can. Each
+// shard owns a range of the first two bytes of the patient hash; new metadata goes to the shard
+// owning its prefix and the event log records only where it went, so directive_manager keeps a
+// small location index instead of the bodies. A split hands the upper half of a range to a new
+// shard and moves its entries over in batches; reads try the index first, so a patient is never
+// unreachable mid-move. emergency_bridge never sees any of this. Shards expose:
+//   store_put     : (text, blob, blob) -> (variant { Ok; Err : text })
+//   store_put_new : (text, blob, blob) -> (variant { Ok : bool; Err : text })  // false if the key exists
+//   store_get     : (text, blob) -> (opt blob) query
+//   store_remove  : (text, blob) -> (variant { Ok; Err : text })
+//   store_count   : (text) -> (nat64) query
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DirectiveShard {
+    pub shard_id: String,
+    pub canister: Principal,
+    pub prefix_start: u16,
+    pub prefix_end: u16, // Inclusive
+    pub status: String, // "ACTIVE", "FILLING"
+    pub added_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ShardLocation {
+    pub shard_id: String,
+    pub shard_key: Vec<u8>, // The hash the entry was written under; re-keying does not move it
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ShardRebalance {
+    pub from_shard: String,
+    pub to_shard: String,
+    pub prefix_start: u16,
+    pub prefix_end: u16,
+    pub started_at: u64,
+    pub moved: u64,
+    pub completed_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct ShardUsage {
+    pub shard: DirectiveShard,
+    pub indexed_entries: u64,
+    pub stored_entries: Option<u64>,
+    pub error: Option<String>,
+}
+
+// Carried across upgrades alongside the hash key ring; the location index is rebuilt from events
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct ShardState {
+    shards: Vec<DirectiveShard>,
+    rebalance: Option<ShardRebalance>,
+}
+
+const NAMESPACE: &str = "phi_metadata";
+const MAX_SHARDS: usize = 256;
+const MAX_REBALANCE_BATCH: u32 = 500;
+
+thread_local! {
+    static SHARDS: std::cell::RefCell<BTreeMap<String, DirectiveShard>> = const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    // storage key -> where its metadata lives; a projection of the event log
+    pub(crate) static SHARD_LOCATIONS: std::cell::RefCell<BTreeMap<Vec<u8>, ShardLocation>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    static REBALANCE: std::cell::RefCell<Option<ShardRebalance>> = const { std::cell::RefCell::new(None) };
+}
+
+// Ranges may not overlap; the first shard usually takes 0..=65535
+#[ic_cdk::update]
+fn add_directive_shard(canister: Principal, prefix_start: u16, prefix_end: u16) -> Result<DirectiveShard, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may add directive shards".to_string());
+    }
+    if prefix_start > prefix_end {
+        return Err("prefix_start must not exceed prefix_end".to_string());
+    }
+    let shard = DirectiveShard {
+        shard_id: ids::new_id("SHARD"),
+        canister,
+        prefix_start,
+        prefix_end,
+        status: "ACTIVE".to_string(),
+        added_at: clock::now(),
+    };
+    SHARDS.with(|s| {
+        let mut shards = s.borrow_mut();
+        if shards.len() >= MAX_SHARDS {
+            return Err(format!("At most {} shards may be configured", MAX_SHARDS));
+        }
+        if let Some(existing) = shards.values().find(|s| s.prefix_start <= prefix_end && prefix_start <= s.prefix_end) {
+            return Err(format!("Range overlaps shard {}", existing.shard_id));
+        }
+        shards.insert(shard.shard_id.clone(), shard.clone());
+        Ok(())
+    })?;
+    Ok(shard)
+}
+
+#[ic_cdk::query]
+fn get_directive_shards() -> Vec<DirectiveShard> {
+    SHARDS.with(|s| s.borrow().values().cloned().collect())
+}
+
+#[ic_cdk::query]
+fn get_shard_rebalance() -> Option<ShardRebalance> {
+    REBALANCE.with(|r| r.borrow().clone())
+}
+
+// Hand the upper half of a shard's range to a new canister; run_shard_rebalance moves the entries
+#[ic_cdk::update]
+fn split_directive_shard(shard_id: String, canister: Principal) -> Result<ShardRebalance, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may split directive shards".to_string());
+    }
+    if REBALANCE.with(|r| r.borrow().as_ref().is_some_and(|r| r.completed_at.is_none())) {
+        return Err("Finish the running rebalance first".to_string());
+    }
+    let source = SHARDS.with(|s| s.borrow().get(&shard_id).cloned())
+        .ok_or_else(|| format!("Unknown shard: {}", shard_id))?;
+    if source.prefix_start == source.prefix_end {
+        return Err("A single-prefix shard cannot be split".to_string());
+    }
+    let midpoint = source.prefix_start + (source.prefix_end - source.prefix_start) / 2 + 1;
+    let target = DirectiveShard {
+        shard_id: ids::new_id("SHARD"),
+        canister,
+        prefix_start: midpoint,
+        prefix_end: source.prefix_end,
+        status: "FILLING".to_string(),
+        added_at: clock::now(),
+    };
+    let rebalance = ShardRebalance {
+        from_shard: source.shard_id,
+        to_shard: target.shard_id.clone(),
+        prefix_start: target.prefix_start,
+        prefix_end: target.prefix_end,
+        started_at: clock::now(),
+        moved: 0,
+        completed_at: None,
+    };
+    SHARDS.with(|s| s.borrow_mut().insert(target.shard_id.clone(), target));
+    REBALANCE.with(|r| *r.borrow_mut() = Some(rebalance.clone()));
+    ic_cdk::println!("AUDIT: Shard {} split at prefix {:04x}", shard_id, midpoint);
+    Ok(rebalance)
+}
+
+// Copies never overwrite: a write that reached the new shard first is newer than the copy
+#[ic_cdk::update]
+async fn run_shard_rebalance(batch_size: u32) -> Result<ShardRebalance, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may run shard rebalancing".to_string());
+    }
+    let _permit = load_shedding::admit("BULK")?;
+    let rebalance = REBALANCE.with(|r| r.borrow().clone())
+        .filter(|r| r.completed_at.is_none())
+        .ok_or("No shard rebalance in progress")?;
+    let source = shard(&rebalance.from_shard)?;
+    let target = shard(&rebalance.to_shard)?;
+
+    let batch = moving_entries(&rebalance, batch_size.min(MAX_REBALANCE_BATCH) as usize);
+    let mut moved = 0;
+    for (storage_key, location) in &batch {
+        if let Some(bytes) = get_raw(source.canister, &location.shard_key).await? {
+            put_new_raw(target.canister, &location.shard_key, bytes).await?;
+        }
+        // Only entries nobody rewrote meanwhile are re-pointed; a rewrite already points at the target
+        if still_at(storage_key, &source.shard_id, &location.shard_key) {
+            events::record(DirectiveEventKind::MetadataSharded {
+                patient_id_hash: storage_key.clone(),
+                shard_id: target.shard_id.clone(),
+                shard_key: location.shard_key.clone(),
+            });
+        }
+        remove_raw(source.canister, &location.shard_key).await?;
+        moved += 1;
+    }
+
+    let done = moving_entries(&rebalance, 1).is_empty();
+    let rebalance = REBALANCE.with(|r| {
+        let mut r = r.borrow_mut();
+        let rebalance = r.as_mut().ok_or("Shard rebalance was cancelled")?;
+        rebalance.moved += moved;
+        if done {
+            rebalance.completed_at = Some(clock::now());
+        }
+        Ok::<_, String>(rebalance.clone())
+    })?;
+    if done {
+        SHARDS.with(|s| {
+            let mut shards = s.borrow_mut();
+            if let Some(source) = shards.get_mut(&rebalance.from_shard) {
+                source.prefix_end = rebalance.prefix_start - 1;
+            }
+            if let Some(target) = shards.get_mut(&rebalance.to_shard) {
+                target.status = "ACTIVE".to_string();
+            }
+        });
+        ic_cdk::println!("AUDIT: Shard rebalance into {} complete ({} entries)", rebalance.to_shard, rebalance.moved);
+    }
+    Ok(rebalance)
+}
+
+// Move metadata still held locally onto the shards that own its prefix
+#[ic_cdk::update]
+async fn offload_local_metadata(batch_size: u32) -> Result<u32, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may offload directive metadata".to_string());
+    }
+    let _permit = load_shedding::admit("BULK")?;
+    let local: Vec<PHIMetadata> = PHI_METADATA.with(|phi_map| {
+        phi_map.borrow()
+            .entries()
+            .into_iter()
+            .map(|(_, metadata)| metadata)
+            .filter(|metadata| shard_for_write(&metadata.patient_id_hash).is_some())
+            .take(batch_size.min(MAX_REBALANCE_BATCH) as usize)
+            .collect()
+    });
+
+    let mut offloaded = 0;
+    for metadata in local {
+        if store(metadata).await?.is_some() {
+            offloaded += 1;
+        }
+    }
+    Ok(offloaded)
+}
+
+// Local, sharded or archived: wherever the patient's metadata is now
+#[ic_cdk::update]
+async fn get_directive_metadata(patient_id_hash: Vec<u8>) -> Result<Option<PHIMetadata>, String> {
+    let patient_id_hash = hashing::storage_key(&patient_id_hash);
+    honeytokens::touch_hash(&patient_id_hash, caller(), "Directive metadata read");
+    let patient_id = PATIENT_HASH_INDEX.with(|index| index.borrow().get(&patient_id_hash).cloned());
+    let permitted = ic_cdk::api::is_controller(&caller())
+        || patient_id.is_some_and(|id| tenants::may_access_patient(caller(), &id, None));
+    if !permitted {
+        return Err("Not authorized to read this patient's directive metadata".to_string());
+    }
+    load_metadata(&patient_id_hash).await
+}
+
+// Takes a storage key; the caller has already checked access
+pub(crate) async fn load_metadata(patient_id_hash: &[u8]) -> Result<Option<PHIMetadata>, String> {
+    if let Some(metadata) = PHI_METADATA.with(|phi_map| phi_map.borrow().get(&patient_id_hash.to_vec())) {
+        return Ok(Some(metadata));
+    }
+    if let Some(location) = SHARD_LOCATIONS.with(|l| l.borrow().get(patient_id_hash).cloned()) {
+        let shard = shard(&location.shard_id)?;
+        if let Some(bytes) = get_raw(shard.canister, &location.shard_key).await? {
+            let mut metadata: PHIMetadata = candid::decode_one(&bytes).map_err(|e| e.to_string())?;
+            metadata.patient_id_hash = patient_id_hash.to_vec();
+            return Ok(Some(metadata));
+        }
+    }
+    storage::PHI_METADATA_ARCHIVE.get(&patient_id_hash.to_vec()).await
+}
+
+// Fan-out over every shard; a shard that does not answer is reported, not fatal
+#[ic_cdk::update]
+async fn get_shard_usage() -> Result<Vec<ShardUsage>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may read shard usage".to_string());
+    }
+    let shards: Vec<DirectiveShard> = SHARDS.with(|s| s.borrow().values().cloned().collect());
+    let mut usage = Vec::with_capacity(shards.len());
+    for shard in shards {
+        let indexed_entries = SHARD_LOCATIONS.with(|l| {
+            l.borrow().values().filter(|location| location.shard_id == shard.shard_id).count() as u64
+        });
+        let counted: Result<(u64,), _> = call(shard.canister, "store_count", (NAMESPACE,)).await;
+        let (stored_entries, error) = match counted {
+            Ok((count,)) => (Some(count), None),
+            Err((_, msg)) => (None, Some(msg)),
+        };
+        usage.push(ShardUsage { shard, indexed_entries, stored_entries, error });
+    }
+    Ok(usage)
+}
+
+// Writes the metadata to its shard and records the location; None when no shard owns the prefix
+pub(crate) async fn store(metadata: PHIMetadata) -> Result<Option<String>, String> {
+    let Some(shard) = shard_for_write(&metadata.patient_id_hash) else {
+        return Ok(None);
+    };
+    let storage_key = metadata.patient_id_hash.clone();
+    let previous = SHARD_LOCATIONS.with(|l| l.borrow().get(&storage_key).cloned());
+    let bytes = candid::encode_one(&metadata).map_err(|e| e.to_string())?;
+    let (result,): (Result<(), String>,) = call(shard.canister, "store_put", (NAMESPACE, storage_key.clone(), bytes))
+        .await
+        .map_err(|(_, msg)| format!("Shard write failed: {}", msg))?;
+    result?;
+
+    events::record(DirectiveEventKind::MetadataSharded {
+        patient_id_hash: storage_key.clone(),
+        shard_id: shard.shard_id.clone(),
+        shard_key: storage_key.clone(),
+    });
+    // A copy written before a re-key or a split is now orphaned
+    if let Some(previous) = previous.filter(|p| p.shard_id != shard.shard_id || p.shard_key != storage_key) {
+        if let Ok(old_shard) = self::shard(&previous.shard_id) {
+            if let Err(e) = remove_raw(old_shard.canister, &previous.shard_key).await {
+                ic_cdk::println!("⚠️ Orphaned shard entry left on {}: {}", previous.shard_id, e);
+            }
+        }
+    }
+    Ok(Some(shard.shard_id))
+}
+
+pub(crate) fn snapshot() -> ShardState {
+    ShardState {
+        shards: SHARDS.with(|s| s.borrow().values().cloned().collect()),
+        rebalance: REBALANCE.with(|r| r.borrow().clone()),
+    }
+}
+
+pub(crate) fn restore(state: Option<ShardState>) {
+    let Some(state) = state else {
+        return;
+    };
+    SHARDS.with(|s| *s.borrow_mut() = state.shards.into_iter().map(|shard| (shard.shard_id.clone(), shard)).collect());
+    REBALANCE.with(|r| *r.borrow_mut() = state.rebalance);
+}
+
+// A shard still filling from a split owns its range for new writes
+fn shard_for_write(patient_id_hash: &[u8]) -> Option<DirectiveShard> {
+    let prefix = prefix(patient_id_hash);
+    SHARDS.with(|s| {
+        let shards = s.borrow();
+        let covering = || shards.values().filter(|s| s.prefix_start <= prefix && prefix <= s.prefix_end);
+        covering().find(|s| s.status == "FILLING").or_else(|| covering().next()).cloned()
+    })
+}
+
+fn prefix(patient_id_hash: &[u8]) -> u16 {
+    let first = patient_id_hash.first().copied().unwrap_or(0);
+    let second = patient_id_hash.get(1).copied().unwrap_or(0);
+    u16::from_be_bytes([first, second])
+}
+
+fn shard(shard_id: &str) -> Result<DirectiveShard, String> {
+    SHARDS.with(|s| s.borrow().get(shard_id).cloned()).ok_or_else(|| format!("Unknown shard: {}", shard_id))
+}
+
+fn moving_entries(rebalance: &ShardRebalance, limit: usize) -> Vec<(Vec<u8>, ShardLocation)> {
+    SHARD_LOCATIONS.with(|l| {
+        l.borrow()
+            .iter()
+            .filter(|(_, location)| location.shard_id == rebalance.from_shard)
+            .filter(|(_, location)| {
+                let prefix = prefix(&location.shard_key);
+                rebalance.prefix_start <= prefix && prefix <= rebalance.prefix_end
+            })
+            .take(limit)
+            .map(|(key, location)| (key.clone(), location.clone()))
+            .collect()
+    })
+}
+
+fn still_at(storage_key: &[u8], shard_id: &str, shard_key: &[u8]) -> bool {
+    SHARD_LOCATIONS.with(|l| {
+        l.borrow().get(storage_key).is_some_and(|location| location.shard_id == shard_id && location.shard_key == shard_key)
+    })
+}
+
+async fn get_raw(canister: Principal, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    let (value,): (Option<Vec<u8>>,) = call(canister, "store_get", (NAMESPACE, key.to_vec()))
+        .await
+        .map_err(|(_, msg)| format!("Shard read failed: {}", msg))?;
+    Ok(value)
+}
+
+async fn put_new_raw(canister: Principal, key: &[u8], value: Vec<u8>) -> Result<bool, String> {
+    let (result,): (Result<bool, String>,) = call(canister, "store_put_new", (NAMESPACE, key.to_vec(), value))
+        .await
+        .map_err(|(_, msg)| format!("Shard write failed: {}", msg))?;
+    result
+}
+
+async fn remove_raw(canister: Principal, key: &[u8]) -> Result<(), String> {
+    let (result,): (Result<(), String>,) = call(canister, "store_remove", (NAMESPACE, key.to_vec()))
+        .await
+        .map_err(|(_, msg)| format!("Shard remove failed: {}", msg))?;
+    result
+}
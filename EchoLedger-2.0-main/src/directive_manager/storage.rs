@@ -0,0 +1,221 @@
+use candid::{CandidType, Principal};
+use ic_cdk::call;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use crate::admins::{self, AdminOperation};
+
+pub(crate) type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// The operations endpoints need from a keyed store, whatever holds the bytes
+pub(crate) trait Store<K, V> {
+    fn get(&self, key: &K) -> Option<V>;
+    fn insert(&mut self, key: K, value: V) -> Option<V>;
+    fn remove(&mut self, key: &K) -> Option<V>;
+    fn len(&self) -> u64;
+    fn entries(&self) -> Vec<(K, V)>;
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn clear(&mut self) {
+        for (key, _) in self.entries() {
+            self.remove(&key);
+        }
+    }
+}
+
+// Heap backend: the plain map, used off-chain and in tests
+impl<K: Ord + Clone, V: Clone> Store<K, V> for BTreeMap<K, V> {
+    fn get(&self, key: &K) -> Option<V> {
+        BTreeMap::get(self, key).cloned()
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        BTreeMap::insert(self, key, value)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        BTreeMap::remove(self, key)
+    }
+
+    fn len(&self) -> u64 {
+        BTreeMap::len(self) as u64
+    }
+
+    fn entries(&self) -> Vec<(K, V)> {
+        self.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+
+    fn clear(&mut self) {
+        BTreeMap::clear(self)
+    }
+}
+
+// Candid-encoded wrapper so existing record types can live in stable memory unchanged
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Stored<T>(pub T);
+
+impl<T: CandidType + DeserializeOwned> Storable for Stored<T> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(&self.0).expect("Failed to encode stored value"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Stored(candid::decode_one(&bytes).expect("Failed to decode stored value"))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// Production backend: survives upgrades without a pre_upgrade copy
+pub(crate) struct StableStore<K, V>
+where
+    K: CandidType + DeserializeOwned + Ord + Clone,
+    V: CandidType + DeserializeOwned,
+{
+    map: StableBTreeMap<Stored<K>, Stored<V>, Memory>,
+}
+
+impl<K, V> StableStore<K, V>
+where
+    K: CandidType + DeserializeOwned + Ord + Clone,
+    V: CandidType + DeserializeOwned,
+{
+    pub(crate) fn init(memory_id: MemoryId) -> Self {
+        StableStore { map: StableBTreeMap::init(memory(memory_id)) }
+    }
+}
+
+impl<K, V> Store<K, V> for StableStore<K, V>
+where
+    K: CandidType + DeserializeOwned + Ord + Clone,
+    V: CandidType + DeserializeOwned,
+{
+    fn get(&self, key: &K) -> Option<V> {
+        self.map.get(&Stored(key.clone())).map(|v| v.0)
+    }
+
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        self.map.insert(Stored(key), Stored(value)).map(|v| v.0)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.map.remove(&Stored(key.clone())).map(|v| v.0)
+    }
+
+    fn len(&self) -> u64 {
+        self.map.len()
+    }
+
+    fn entries(&self) -> Vec<(K, V)> {
+        self.map.iter().map(|(k, v)| (k.0, v.0)).collect()
+    }
+}
+
+thread_local! {
+    static MEMORY_MANAGER: std::cell::RefCell<MemoryManager<DefaultMemoryImpl>> =
+        std::cell::RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    static UPGRADE_STATE: std::cell::RefCell<StableCell<Vec<u8>, Memory>> = std::cell::RefCell::new(
+        StableCell::init(memory(UPGRADE_STATE_MEMORY), Vec::new()).expect("Failed to initialize upgrade state cell")
+    );
+
+    static ARCHIVE_CANISTER: std::cell::RefCell<Option<Principal>> = std::cell::RefCell::new(None);
+}
+
+// Memory IDs are permanent once deployed: append new ones, never renumber
+pub(crate) const UPGRADE_STATE_MEMORY: MemoryId = MemoryId::new(0);
+pub(crate) const PHI_METADATA_MEMORY: MemoryId = MemoryId::new(1);
+pub(crate) const CONSENT_DIRECTIVES_MEMORY: MemoryId = MemoryId::new(2);
+pub(crate) const AUDIT_BUFFER_MEMORY: MemoryId = MemoryId::new(3);
+pub(crate) const DIRECTIVE_EVENTS_MEMORY: MemoryId = MemoryId::new(4);
+
+fn memory(memory_id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(memory_id))
+}
+
+// Stable memory on the IC; a heap map everywhere else so logic can be exercised natively
+pub(crate) fn backend<K, V>(memory_id: MemoryId) -> Box<dyn Store<K, V>>
+where
+    K: CandidType + DeserializeOwned + Ord + Clone + 'static,
+    V: CandidType + DeserializeOwned + Clone + 'static,
+{
+    if cfg!(target_arch = "wasm32") {
+        Box::new(StableStore::init(memory_id))
+    } else {
+        Box::new(BTreeMap::new())
+    }
+}
+
+// State still carried across upgrades as one blob lives in its own region, clear of the stores
+pub(crate) fn save_upgrade_state<T: CandidType>(state: T) {
+    let bytes = candid::encode_one(state).expect("Failed to encode upgrade state");
+    UPGRADE_STATE.with(|cell| cell.borrow_mut().set(bytes).expect("Failed to write upgrade state"));
+}
+
+pub(crate) fn load_upgrade_state<T: CandidType + DeserializeOwned>() -> Option<T> {
+    UPGRADE_STATE.with(|cell| {
+        let cell = cell.borrow();
+        if cell.get().is_empty() {
+            return None;
+        }
+        candid::decode_one(cell.get()).ok()
+    })
+}
+
+// Cold tier. The archive canister is remote, so it is reached asynchronously rather than through Store:
+//   archive_put : (text, blob, blob) -> (variant { Ok; Err : text })
+//   archive_get : (text, blob) -> (opt blob) query
+pub(crate) struct ArchiveStore {
+    namespace: &'static str,
+}
+
+pub(crate) const PHI_METADATA_ARCHIVE: ArchiveStore = ArchiveStore { namespace: "phi_metadata" };
+// Batches of emergency access records, keyed by (flushed_at, first buffer sequence)
+pub(crate) const EMERGENCY_ACCESS_ARCHIVE: ArchiveStore = ArchiveStore { namespace: "emergency_access" };
+
+impl ArchiveStore {
+    pub(crate) async fn put<K: CandidType, V: CandidType>(&self, key: &K, value: &V) -> Result<(), String> {
+        let archive = archive_canister()?;
+        let key = candid::encode_one(key).map_err(|e| e.to_string())?;
+        let value = candid::encode_one(value).map_err(|e| e.to_string())?;
+        let (result,): (Result<(), String>,) = call(archive, "archive_put", (self.namespace, key, value))
+            .await
+            .map_err(|(_, msg)| format!("Archive write failed: {}", msg))?;
+        result
+    }
+
+    pub(crate) async fn get<K: CandidType, V: CandidType + DeserializeOwned>(&self, key: &K) -> Result<Option<V>, String> {
+        let archive = archive_canister()?;
+        let key = candid::encode_one(key).map_err(|e| e.to_string())?;
+        let (value,): (Option<Vec<u8>>,) = call(archive, "archive_get", (self.namespace, key))
+            .await
+            .map_err(|(_, msg)| format!("Archive read failed: {}", msg))?;
+        value.map(|bytes| candid::decode_one(&bytes).map_err(|e| e.to_string())).transpose()
+    }
+}
+
+#[ic_cdk::update]
+fn set_archive_canister(archive: Principal) -> Result<(), String> {
+    admins::authorize(AdminOperation::SetArchiveCanister { archive })?;
+    ARCHIVE_CANISTER.with(|a| *a.borrow_mut() = Some(archive));
+    Ok(())
+}
+
+fn archive_canister() -> Result<Principal, String> {
+    ARCHIVE_CANISTER.with(|a| *a.borrow()).ok_or_else(|| "No archive canister configured".to_string())
+}
+
+pub(crate) fn archive_canister_setting() -> Option<Principal> {
+    ARCHIVE_CANISTER.with(|a| *a.borrow())
+}
+
+pub(crate) fn restore_archive_canister(archive: Option<Principal>) {
+    ARCHIVE_CANISTER.with(|a| *a.borrow_mut() = archive);
+}
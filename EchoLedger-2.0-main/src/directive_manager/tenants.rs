@@ -0,0 +1,639 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{
+    clock, directive_owner, emergency, events, hashing, ids, validation, CONTACT_CHANNELS, EXECUTOR_AI_CANISTER_ID, NANOS_PER_DAY,
+    PATIENT_HASH_INDEX, PROXY_GRANTS,
+};
+
+// Unset fields inherit the global defaults
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default)]
+pub struct TenantConfigOverrides {
+    pub notification_channels: Option<Vec<String>>,
+    pub jurisdiction: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct TenantConfigDefaults {
+    pub notification_channels: Vec<String>,
+    pub jurisdiction: String,
+}
+
+// Each setting with where it came from: "GLOBAL" or "TENANT"
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct EffectiveTenantConfig {
+    pub tenant_id: String,
+    pub notification_channels: Vec<String>,
+    pub notification_channels_source: String,
+    pub jurisdiction: String,
+    pub jurisdiction_source: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct TenantQuota {
+    pub max_patients: u64,
+    pub max_directive_writes_per_day: u64,
+}
+
+// A hospital system; every principal belongs to at most one
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct Tenant {
+    pub tenant_id: String,
+    pub name: String,
+    pub admins: Vec<Principal>,
+    pub members: Vec<Principal>,
+    pub status: String, // "ACTIVE", "SUSPENDED"
+    pub quota: TenantQuota,
+    pub overrides: TenantConfigOverrides,
+    pub suspended_reason: Option<String>,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug, Default)]
+pub struct TenantUsage {
+    pub tenant_id: String,
+    pub patients: u64,
+    pub directive_writes_today: u64,
+    pub usage_day: u64,
+    pub total_directive_writes: u64,
+}
+
+// The owning tenant lets the recipient's members read its patients' directives of the listed types
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DataSharingAgreement {
+    pub agreement_id: String,
+    pub owner_tenant: String,
+    pub recipient_tenant: String,
+    pub directive_types: Vec<String>,
+    pub purpose: String,
+    pub created_by: Principal,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub revoked_at: Option<u64>,
+}
+
+// What sibling canisters need to scope their own data to a caller
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct TenantMembership {
+    pub tenant_id: String,
+    pub status: String,
+    pub is_admin: bool,
+    pub jurisdiction: String,
+}
+
+thread_local! {
+    // Projections of the directive event log, like the maps in lib.rs
+    pub(crate) static TENANTS: std::cell::RefCell<BTreeMap<String, Tenant>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    // patient_id -> tenant_id; a patient is enrolled with exactly one tenant
+    pub(crate) static PATIENT_TENANTS: std::cell::RefCell<BTreeMap<String, String>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    // patient_id -> tenant_id the patient or a proxy agreed to be enrolled with, spent by the enrollment
+    pub(crate) static ENROLLMENT_CONSENTS: std::cell::RefCell<BTreeMap<String, String>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    pub(crate) static SHARING_AGREEMENTS: std::cell::RefCell<BTreeMap<String, DataSharingAgreement>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    pub(crate) static TENANT_USAGE: std::cell::RefCell<BTreeMap<String, TenantUsage>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    pub(crate) static TENANT_DEFAULTS: std::cell::RefCell<TenantConfigDefaults> =
+        std::cell::RefCell::new(initial_defaults());
+}
+
+pub(crate) const LLM_CANISTER_ID: &str = "renrk-eyaaa-aaaaa-aaada-cai";
+const MAX_TENANT_ID_LENGTH: usize = 64;
+const MAX_JURISDICTION_LENGTH: usize = 16;
+
+#[ic_cdk::update]
+fn create_tenant(tenant_id: String, name: String, admin: Principal, quota: TenantQuota) -> Result<Tenant, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may create tenants".to_string());
+    }
+    if tenant_id.is_empty()
+        || tenant_id.len() > MAX_TENANT_ID_LENGTH
+        || !tenant_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err("Tenant IDs are 1-64 characters of letters, digits, '_' and '-'".to_string());
+    }
+    if TENANTS.with(|t| t.borrow().contains_key(&tenant_id)) {
+        return Err(format!("Tenant already exists: {}", tenant_id));
+    }
+    if let Some(existing) = membership_of(admin) {
+        return Err(format!("{} already belongs to tenant {}", admin, existing.tenant_id));
+    }
+
+    let tenant = Tenant {
+        tenant_id,
+        name,
+        admins: vec![admin],
+        members: vec![admin],
+        status: "ACTIVE".to_string(),
+        quota,
+        overrides: TenantConfigOverrides::default(),
+        suspended_reason: None,
+        created_at: clock::now(),
+    };
+    events::record(events::DirectiveEventKind::TenantSaved(tenant.clone()));
+    Ok(tenant)
+}
+
+#[ic_cdk::update]
+fn set_tenant_quota(tenant_id: String, quota: TenantQuota) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may change tenant quotas".to_string());
+    }
+    let mut tenant = tenant(&tenant_id)?;
+    tenant.quota = quota;
+    events::record(events::DirectiveEventKind::TenantSaved(tenant));
+    Ok(())
+}
+
+// Members of a suspended tenant lose access to its patients; patients keep access to their own records
+#[ic_cdk::update]
+fn suspend_tenant(tenant_id: String, reason: String) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may suspend tenants".to_string());
+    }
+    let reason = validation::text("reason", &reason, validation::MAX_REASON_BYTES)?;
+    let mut tenant = tenant(&tenant_id)?;
+    tenant.status = "SUSPENDED".to_string();
+    tenant.suspended_reason = Some(reason);
+    events::record(events::DirectiveEventKind::TenantSaved(tenant));
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn reinstate_tenant(tenant_id: String) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may reinstate tenants".to_string());
+    }
+    let mut tenant = tenant(&tenant_id)?;
+    tenant.status = "ACTIVE".to_string();
+    tenant.suspended_reason = None;
+    events::record(events::DirectiveEventKind::TenantSaved(tenant));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn list_tenants() -> Result<Vec<Tenant>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may list tenants".to_string());
+    }
+    Ok(TENANTS.with(|t| t.borrow().values().cloned().collect()))
+}
+
+#[ic_cdk::update]
+fn set_tenant_config_defaults(defaults: TenantConfigDefaults) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may change tenant defaults".to_string());
+    }
+    validate_channels(&defaults.notification_channels)?;
+    validate_jurisdiction(&defaults.jurisdiction)?;
+    events::record(events::DirectiveEventKind::TenantDefaultsSaved(defaults));
+    Ok(())
+}
+
+// Replaces the tenant's overrides wholesale; pass None for a field to go back to the global default
+#[ic_cdk::update]
+fn set_tenant_config_overrides(tenant_id: String, overrides: TenantConfigOverrides) -> Result<EffectiveTenantConfig, String> {
+    let mut tenant = tenant(&tenant_id)?;
+    ensure_tenant_admin(&tenant, caller())?;
+    if let Some(channels) = &overrides.notification_channels {
+        validate_channels(channels)?;
+    }
+    if let Some(jurisdiction) = &overrides.jurisdiction {
+        validate_jurisdiction(jurisdiction)?;
+    }
+
+    tenant.overrides = overrides;
+    events::record(events::DirectiveEventKind::TenantSaved(tenant));
+    Ok(effective_config(&tenant_id))
+}
+
+#[ic_cdk::query]
+fn get_effective_tenant_config(tenant_id: String) -> Result<EffectiveTenantConfig, String> {
+    let requester = caller();
+    let tenant = tenant(&tenant_id)?;
+    if !tenant.members.contains(&requester) && !is_platform(requester) {
+        return Err(format!("Only members of tenant {} may read its configuration", tenant_id));
+    }
+    Ok(effective_config(&tenant_id))
+}
+
+#[ic_cdk::update]
+fn add_tenant_member(tenant_id: String, member: Principal, admin: bool) -> Result<(), String> {
+    let mut tenant = tenant(&tenant_id)?;
+    ensure_tenant_admin(&tenant, caller())?;
+    if let Some(existing) = membership_of(member) {
+        if existing.tenant_id != tenant_id {
+            return Err(format!("{} already belongs to tenant {}", member, existing.tenant_id));
+        }
+    }
+
+    if !tenant.members.contains(&member) {
+        tenant.members.push(member);
+    }
+    if admin && !tenant.admins.contains(&member) {
+        tenant.admins.push(member);
+    }
+    events::record(events::DirectiveEventKind::TenantSaved(tenant));
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn remove_tenant_member(tenant_id: String, member: Principal) -> Result<(), String> {
+    let mut tenant = tenant(&tenant_id)?;
+    ensure_tenant_admin(&tenant, caller())?;
+    if tenant.admins == [member] {
+        return Err("A tenant must keep at least one admin".to_string());
+    }
+    tenant.members.retain(|m| *m != member);
+    tenant.admins.retain(|m| *m != member);
+    events::record(events::DirectiveEventKind::TenantSaved(tenant));
+    Ok(())
+}
+
+// The patient or a proxy names the tenant they agree to be enrolled with, or None to withdraw.
+// Enrolling with a different tenant than the current one moves the patient there.
+#[ic_cdk::update]
+fn consent_to_enrollment(patient_id: String, tenant_id: Option<String>) -> Result<(), String> {
+    let requester = caller();
+    if directive_owner(&patient_id) != Some(requester) && !is_proxy(&patient_id, requester) {
+        return Err("Only the patient or their proxy may consent to enrollment".to_string());
+    }
+    if let Some(tenant_id) = &tenant_id {
+        tenant(tenant_id)?;
+    }
+    events::record(events::DirectiveEventKind::EnrollmentConsented { patient_id, tenant_id });
+    Ok(())
+}
+
+// A member places a patient in their tenant's namespace once the patient or a proxy has agreed to
+// it; counts against the patient quota
+#[ic_cdk::update]
+fn enroll_patient(patient_id: String) -> Result<(), String> {
+    let enroller = caller();
+    let membership = membership_of(enroller).ok_or("Caller does not belong to a tenant")?;
+    let tenant = tenant(&membership.tenant_id)?;
+    if patient_tenant(&patient_id).as_deref() == Some(tenant.tenant_id.as_str()) {
+        return Ok(());
+    }
+    if ENROLLMENT_CONSENTS.with(|c| c.borrow().get(&patient_id).cloned()).as_deref() != Some(tenant.tenant_id.as_str()) {
+        return Err(format!("Patient has not consented to enrollment with tenant {}", tenant.tenant_id));
+    }
+    record_enrollment(patient_id, &tenant)
+}
+
+// Controllers place or move a patient without consent on file, e.g. for a court-ordered transfer
+#[ic_cdk::update]
+fn assign_patient_tenant(patient_id: String, tenant_id: String) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may assign patients to tenants".to_string());
+    }
+    let tenant = tenant(&tenant_id)?;
+    if patient_tenant(&patient_id).as_deref() == Some(tenant_id.as_str()) {
+        return Ok(());
+    }
+    record_enrollment(patient_id, &tenant)
+}
+
+// The patient, a proxy, an admin of the patient's tenant or a controller takes the patient out of it
+#[ic_cdk::update]
+fn unenroll_patient(patient_id: String) -> Result<(), String> {
+    let requester = caller();
+    let current = patient_tenant(&patient_id).ok_or("Patient is not enrolled with any tenant")?;
+    let patient_side = directive_owner(&patient_id) == Some(requester) || is_proxy(&patient_id, requester);
+    if !patient_side {
+        ensure_tenant_admin(&tenant(&current)?, requester)?;
+    }
+    events::record(events::DirectiveEventKind::PatientUnenrolled { patient_id: patient_id.clone() });
+    ic_cdk::println!("AUDIT: Patient {} unenrolled from tenant {} by {}", patient_id, current, requester.to_text());
+    Ok(())
+}
+
+fn record_enrollment(patient_id: String, tenant: &Tenant) -> Result<(), String> {
+    if tenant.status != "ACTIVE" {
+        return Err(format!("Tenant {} is suspended", tenant.tenant_id));
+    }
+    if usage(&tenant.tenant_id).patients >= tenant.quota.max_patients {
+        return Err(format!("Tenant {} has reached its quota of {} patients", tenant.tenant_id, tenant.quota.max_patients));
+    }
+    events::record(events::DirectiveEventKind::PatientEnrolled { patient_id, tenant_id: tenant.tenant_id.clone() });
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn create_data_sharing_agreement(
+    recipient_tenant: String,
+    directive_types: Vec<String>,
+    purpose: String,
+    expires_at: Option<u64>,
+) -> Result<DataSharingAgreement, String> {
+    let creator = caller();
+    let membership = membership_of(creator).ok_or("Caller does not belong to a tenant")?;
+    let owner = tenant(&membership.tenant_id)?;
+    ensure_tenant_admin(&owner, creator)?;
+    tenant(&recipient_tenant)?;
+    if recipient_tenant == owner.tenant_id {
+        return Err("A tenant does not need an agreement with itself".to_string());
+    }
+    if directive_types.is_empty() {
+        return Err("An agreement must name the directive types it shares".to_string());
+    }
+    if purpose.trim().is_empty() {
+        return Err("An agreement must state its purpose".to_string());
+    }
+    let now = clock::now();
+    if expires_at.is_some_and(|at| at <= now) {
+        return Err("Agreement expiry must be in the future".to_string());
+    }
+
+    let agreement = DataSharingAgreement {
+        agreement_id: ids::new_id("DSA"),
+        owner_tenant: owner.tenant_id,
+        recipient_tenant,
+        directive_types,
+        purpose,
+        created_by: creator,
+        created_at: now,
+        expires_at,
+        revoked_at: None,
+    };
+    events::record(events::DirectiveEventKind::SharingAgreementSaved(agreement.clone()));
+    Ok(agreement)
+}
+
+#[ic_cdk::update]
+fn revoke_data_sharing_agreement(agreement_id: String) -> Result<(), String> {
+    let mut agreement = SHARING_AGREEMENTS.with(|a| a.borrow().get(&agreement_id).cloned())
+        .ok_or_else(|| format!("Agreement not found: {}", agreement_id))?;
+    ensure_tenant_admin(&tenant(&agreement.owner_tenant)?, caller())?;
+    if agreement.revoked_at.is_some() {
+        return Err("Agreement already revoked".to_string());
+    }
+    agreement.revoked_at = Some(clock::now());
+    events::record(events::DirectiveEventKind::SharingAgreementSaved(agreement));
+    Ok(())
+}
+
+// Agreements the tenant grants or receives; visible to either side's admins
+#[ic_cdk::query]
+fn get_data_sharing_agreements(tenant_id: String) -> Result<Vec<DataSharingAgreement>, String> {
+    ensure_tenant_admin(&tenant(&tenant_id)?, caller())?;
+    Ok(SHARING_AGREEMENTS.with(|a| {
+        a.borrow()
+            .values()
+            .filter(|x| x.owner_tenant == tenant_id || x.recipient_tenant == tenant_id)
+            .cloned()
+            .collect()
+    }))
+}
+
+#[ic_cdk::query]
+fn get_tenant_usage(tenant_id: String) -> Result<TenantUsage, String> {
+    ensure_tenant_admin(&tenant(&tenant_id)?, caller())?;
+    Ok(usage(&tenant_id))
+}
+
+// For sibling canisters scoping their own state, and for a principal asking about itself
+#[ic_cdk::query]
+fn resolve_tenant(principal: Principal) -> Result<Option<TenantMembership>, String> {
+    let requester = caller();
+    if requester != principal && !is_platform(requester) {
+        return Err("Caller may not resolve another principal's tenant".to_string());
+    }
+    Ok(membership_of(principal))
+}
+
+pub(crate) fn membership_of(principal: Principal) -> Option<TenantMembership> {
+    let tenant = TENANTS.with(|t| t.borrow().values().find(|tenant| tenant.members.contains(&principal)).cloned())?;
+    Some(TenantMembership {
+        jurisdiction: effective_config(&tenant.tenant_id).jurisdiction,
+        is_admin: tenant.admins.contains(&principal),
+        tenant_id: tenant.tenant_id,
+        status: tenant.status,
+    })
+}
+
+// Tenant overrides over the global defaults, field by field
+pub(crate) fn effective_config(tenant_id: &str) -> EffectiveTenantConfig {
+    let defaults = TENANT_DEFAULTS.with(|d| d.borrow().clone());
+    let overrides = TENANTS.with(|t| t.borrow().get(tenant_id).map(|x| x.overrides.clone())).unwrap_or_default();
+    let source = |overridden: bool| if overridden { "TENANT" } else { "GLOBAL" }.to_string();
+    EffectiveTenantConfig {
+        tenant_id: tenant_id.to_string(),
+        notification_channels_source: source(overrides.notification_channels.is_some()),
+        notification_channels: overrides.notification_channels.unwrap_or(defaults.notification_channels),
+        jurisdiction_source: source(overrides.jurisdiction.is_some()),
+        jurisdiction: overrides.jurisdiction.unwrap_or(defaults.jurisdiction),
+    }
+}
+
+// Contacts of an enrolled patient are limited to the channels their tenant has enabled
+pub(crate) fn check_contact_channel(patient_id_hash: &[u8], channel: &str) -> Result<(), String> {
+    let tenant_id = PATIENT_HASH_INDEX.with(|index| index.borrow().get(patient_id_hash).cloned())
+        .and_then(|patient_id| patient_tenant(&patient_id));
+    let Some(tenant_id) = tenant_id else {
+        return Ok(());
+    };
+    if effective_config(&tenant_id).notification_channels.iter().any(|c| c == channel) {
+        Ok(())
+    } else {
+        Err(format!("Tenant {} has not enabled the {} channel", tenant_id, channel))
+    }
+}
+
+pub(crate) fn initial_defaults() -> TenantConfigDefaults {
+    TenantConfigDefaults {
+        notification_channels: CONTACT_CHANNELS.iter().map(|c| c.to_string()).collect(),
+        jurisdiction: "US".to_string(),
+    }
+}
+
+pub(crate) fn patient_tenant(patient_id: &str) -> Option<String> {
+    PATIENT_TENANTS.with(|p| p.borrow().get(patient_id).cloned())
+}
+
+// Controllers and sibling canisters carry their own checks; everyone else is scoped here
+pub(crate) fn may_access_patient(principal: Principal, patient_id: &str, directive_type: Option<&str>) -> bool {
+    is_platform(principal)
+        || directive_owner(patient_id) == Some(principal)
+        || is_proxy(patient_id, principal)
+        || tenant_allows(principal, patient_id, directive_type)
+}
+
+// Members of the patient's tenant, or of a tenant it shares the directive type with. Patients not
+// yet enrolled with any tenant keep the pre-tenancy rules.
+pub(crate) fn tenant_allows(principal: Principal, patient_id: &str, directive_type: Option<&str>) -> bool {
+    let Some(owner_tenant) = patient_tenant(patient_id) else {
+        return true;
+    };
+    let Some(membership) = membership_of(principal) else {
+        return false;
+    };
+    if membership.status != "ACTIVE" {
+        return false;
+    }
+    if membership.tenant_id == owner_tenant {
+        return true;
+    }
+
+    let now = clock::now();
+    SHARING_AGREEMENTS.with(|a| {
+        a.borrow().values().any(|agreement| {
+            agreement.owner_tenant == owner_tenant
+                && agreement.recipient_tenant == membership.tenant_id
+                && agreement.revoked_at.is_none()
+                && agreement.expires_at.is_none_or(|at| at > now)
+                && directive_type.is_none_or(|t| agreement.directive_types.iter().any(|x| x == t))
+        })
+    })
+}
+
+// Hash-keyed records resolve to the patient through the hash index; unknown hashes have no tenant
+pub(crate) fn may_access_patient_hash(principal: Principal, patient_id_hash: &[u8]) -> bool {
+    match PATIENT_HASH_INDEX.with(|index| index.borrow().get(patient_id_hash).cloned()) {
+        Some(patient_id) => may_access_patient(principal, &patient_id, None),
+        None => true,
+    }
+}
+
+// The patient or one of their proxies, for a hash-keyed record
+pub(crate) fn manages_patient_hash(principal: Principal, patient_id_hash: &[u8]) -> bool {
+    let key = hashing::storage_key(patient_id_hash);
+    PATIENT_HASH_INDEX.with(|index| index.borrow().get(&key).cloned())
+        .is_some_and(|patient_id| directive_owner(&patient_id) == Some(principal) || is_proxy(&patient_id, principal))
+}
+
+// Access logs and notification records say who looked at a patient and when. Only the patient's
+// own side reads them: the patient or a proxy, the tenant the patient is enrolled with, and
+// controllers. A hash that resolves to no patient is controllers only.
+pub(crate) fn may_review_patient_hash(principal: Principal, patient_id_hash: &[u8]) -> bool {
+    if ic_cdk::api::is_controller(&principal) {
+        return true;
+    }
+    let key = hashing::storage_key(patient_id_hash);
+    let Some(patient_id) = PATIENT_HASH_INDEX.with(|index| index.borrow().get(&key).cloned()) else {
+        return false;
+    };
+    if directive_owner(&patient_id) == Some(principal) || is_proxy(&patient_id, principal) {
+        return true;
+    }
+    match (patient_tenant(&patient_id), membership_of(principal)) {
+        (Some(tenant_id), Some(membership)) => membership.status == "ACTIVE" && membership.tenant_id == tenant_id,
+        _ => false,
+    }
+}
+
+// Every new directive version for an enrolled patient counts against its tenant's daily quota
+pub(crate) fn check_directive_write(patient_id: &str) -> Result<(), String> {
+    let Some(tenant_id) = patient_tenant(patient_id) else {
+        return Ok(());
+    };
+    let tenant = tenant(&tenant_id)?;
+    let usage = usage(&tenant_id);
+    let today = clock::now() / NANOS_PER_DAY;
+    if usage.usage_day == today && usage.directive_writes_today >= tenant.quota.max_directive_writes_per_day {
+        return Err(format!(
+            "Tenant {} has reached its quota of {} directive writes today",
+            tenant_id, tenant.quota.max_directive_writes_per_day
+        ));
+    }
+    Ok(())
+}
+
+// Replayed from ConsentUpdated, so usage is rebuilt with the rest of the projection
+pub(crate) fn count_directive_write(patient_id: &str, recorded_at: u64) {
+    let Some(tenant_id) = patient_tenant(patient_id) else {
+        return;
+    };
+    let day = recorded_at / NANOS_PER_DAY;
+    TENANT_USAGE.with(|u| {
+        let mut u = u.borrow_mut();
+        let usage = u.entry(tenant_id.clone()).or_insert_with(|| TenantUsage { tenant_id, ..Default::default() });
+        if usage.usage_day != day {
+            usage.usage_day = day;
+            usage.directive_writes_today = 0;
+        }
+        usage.directive_writes_today += 1;
+        usage.total_directive_writes += 1;
+    });
+}
+
+pub(crate) fn count_enrollment(tenant_id: &str) {
+    TENANT_USAGE.with(|u| {
+        let mut u = u.borrow_mut();
+        let usage = u.entry(tenant_id.to_string()).or_insert_with(|| TenantUsage {
+            tenant_id: tenant_id.to_string(),
+            ..Default::default()
+        });
+        usage.patients += 1;
+    });
+}
+
+pub(crate) fn count_unenrollment(tenant_id: &str) {
+    TENANT_USAGE.with(|u| {
+        if let Some(usage) = u.borrow_mut().get_mut(tenant_id) {
+            usage.patients = usage.patients.saturating_sub(1);
+        }
+    });
+}
+
+fn tenant(tenant_id: &str) -> Result<Tenant, String> {
+    TENANTS.with(|t| t.borrow().get(tenant_id).cloned()).ok_or_else(|| format!("Unknown tenant: {}", tenant_id))
+}
+
+fn usage(tenant_id: &str) -> TenantUsage {
+    TENANT_USAGE.with(|u| u.borrow().get(tenant_id).cloned()).unwrap_or_else(|| TenantUsage {
+        tenant_id: tenant_id.to_string(),
+        ..Default::default()
+    })
+}
+
+fn validate_channels(channels: &[String]) -> Result<(), String> {
+    if channels.is_empty() {
+        return Err("At least one notification channel must stay enabled".to_string());
+    }
+    match channels.iter().find(|c| !CONTACT_CHANNELS.contains(&c.as_str())) {
+        Some(unknown) => Err(format!("Unsupported notification channel: {}", unknown)),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn validate_jurisdiction(jurisdiction: &str) -> Result<(), String> {
+    if jurisdiction.is_empty()
+        || jurisdiction.len() > MAX_JURISDICTION_LENGTH
+        || !jurisdiction.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(format!("Jurisdiction {:?} must be an uppercase code such as \"DE\" or \"US-CA\"", jurisdiction));
+    }
+    Ok(())
+}
+
+pub(crate) fn ensure_tenant_admin(tenant: &Tenant, principal: Principal) -> Result<(), String> {
+    if tenant.admins.contains(&principal) || ic_cdk::api::is_controller(&principal) {
+        Ok(())
+    } else {
+        Err(format!("Only admins of tenant {} may do this", tenant.tenant_id))
+    }
+}
+
+pub(crate) fn is_proxy(patient_id: &str, principal: Principal) -> bool {
+    PROXY_GRANTS.with(|grants| {
+        grants.borrow().get(patient_id).is_some_and(|list| list.iter().any(|g| g.proxy == principal))
+    })
+}
+
+pub(crate) fn is_platform(principal: Principal) -> bool {
+    principal == ic_cdk::id()
+        || ic_cdk::api::is_controller(&principal)
+        || [EXECUTOR_AI_CANISTER_ID, emergency::EMERGENCY_BRIDGE_CANISTER_ID, LLM_CANISTER_ID]
+            .iter()
+            .any(|id| Principal::from_text(id).ok() == Some(principal))
+}
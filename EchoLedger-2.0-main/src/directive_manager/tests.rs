@@ -0,0 +1,321 @@
+use super::*;
+
+// The clock is pinned so windows and expiry can be crossed without waiting them out
+use canister_clock::at_minutes as at;
+
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+
+#[test]
+fn existence_checks_are_throttled_until_the_caller_window_rolls_over() {
+    at(0);
+    let requester = Principal::anonymous();
+    for _ in 0..5 {
+        assert!(existence::consume_rate_limit(requester).is_ok());
+    }
+    assert!(existence::consume_rate_limit(requester).is_err());
+
+    clock::advance(9 * NANOS_PER_MINUTE);
+    assert!(existence::consume_rate_limit(requester).is_err());
+
+    clock::advance(NANOS_PER_MINUTE);
+    assert!(existence::consume_rate_limit(requester).is_ok());
+}
+
+#[test]
+fn emergency_tokens_drop_out_of_the_live_set_once_expired() {
+    at(0);
+    let now = clock::now();
+    emergency::replace_tokens(vec![emergency::EmergencyAccessToken {
+        token_hash: vec![1; 32],
+        requester: Principal::anonymous(),
+        issued_at: now,
+        expires_at: now + 60 * NANOS_PER_MINUTE,
+        revoked: false,
+    }]);
+    assert_eq!(emergency::live_tokens().len(), 1);
+
+    at(60);
+    assert_eq!(emergency::live_tokens().len(), 1);
+
+    at(61);
+    assert!(emergency::live_tokens().is_empty());
+}
+
+#[test]
+fn sequence_orders_records_within_one_instant_and_survives_a_restore() {
+    at(0);
+    let first = clock::next_sequence();
+    let second = clock::next_sequence();
+    assert!(second > first);
+    assert_eq!(clock::now(), 1_700_000_000_000_000_000);
+
+    // An upgrade restores the saved counter; it never moves back
+    clock::restore(Some(second + 100));
+    assert_eq!(clock::next_sequence(), second + 101);
+    clock::restore(Some(1));
+    assert_eq!(clock::next_sequence(), second + 102);
+}
+
+fn principal(byte: u8) -> Principal {
+    Principal::from_slice(&[byte; 29])
+}
+
+fn hex(text: &str) -> Vec<u8> {
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+}
+
+fn hospital(tenant_id: &str, members: Vec<Principal>) -> tenants::Tenant {
+    tenants::Tenant {
+        tenant_id: tenant_id.to_string(),
+        name: tenant_id.to_string(),
+        admins: vec![],
+        members,
+        status: "ACTIVE".to_string(),
+        quota: tenants::TenantQuota { max_patients: 100, max_directive_writes_per_day: 100 },
+        overrides: Default::default(),
+        suspended_reason: None,
+        created_at: 0,
+    }
+}
+
+#[test]
+fn tenant_members_reach_their_own_patients_and_only_what_is_shared_with_them() {
+    at(0);
+    let (mayo_nurse, cleveland_nurse, outsider) = (principal(1), principal(2), principal(3));
+    tenants::TENANTS.with(|t| {
+        let mut t = t.borrow_mut();
+        t.insert("MAYO".to_string(), hospital("MAYO", vec![mayo_nurse]));
+        t.insert("CLEVELAND".to_string(), hospital("CLEVELAND", vec![cleveland_nurse]));
+    });
+    tenants::PATIENT_TENANTS.with(|p| p.borrow_mut().insert("patient_tenant_001".to_string(), "MAYO".to_string()));
+
+    assert!(tenants::tenant_allows(mayo_nurse, "patient_tenant_001", Some("DNR")));
+    assert!(!tenants::tenant_allows(cleveland_nurse, "patient_tenant_001", Some("DNR")));
+    assert!(!tenants::tenant_allows(outsider, "patient_tenant_001", None));
+    // A patient enrolled nowhere keeps the pre-tenancy rules
+    assert!(tenants::tenant_allows(outsider, "patient_tenant_002", None));
+
+    tenants::SHARING_AGREEMENTS.with(|a| {
+        a.borrow_mut().insert("AGREEMENT_001".to_string(), tenants::DataSharingAgreement {
+            agreement_id: "AGREEMENT_001".to_string(),
+            owner_tenant: "MAYO".to_string(),
+            recipient_tenant: "CLEVELAND".to_string(),
+            directive_types: vec!["DNR".to_string()],
+            purpose: "Transfers".to_string(),
+            created_by: mayo_nurse,
+            created_at: clock::now(),
+            expires_at: Some(clock::now() + 60 * NANOS_PER_MINUTE),
+            revoked_at: None,
+        });
+    });
+    assert!(tenants::tenant_allows(cleveland_nurse, "patient_tenant_001", Some("DNR")));
+    assert!(!tenants::tenant_allows(cleveland_nurse, "patient_tenant_001", Some("ORGAN_DONATION")));
+
+    at(61);
+    assert!(!tenants::tenant_allows(cleveland_nurse, "patient_tenant_001", Some("DNR")));
+
+    // Members of a suspended tenant lose access to its own patients too
+    tenants::TENANTS.with(|t| t.borrow_mut().get_mut("MAYO").unwrap().status = "SUSPENDED".to_string());
+    assert!(!tenants::tenant_allows(mayo_nurse, "patient_tenant_001", Some("DNR")));
+}
+
+#[test]
+fn amendment_acceptance_verifies_only_over_the_domain_and_the_proposal_hash() {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    let patient_key = SigningKey::from_bytes(&[7; 32]);
+    let public_key = patient_key.verifying_key().to_bytes().to_vec();
+    let proposal_hash = vec![0xAB; 32];
+    let signed = |message: &[u8]| patient_key.sign(message).to_bytes().to_vec();
+
+    let acceptance = signed(&[AMENDMENT_ACCEPTANCE_DOMAIN, &proposal_hash].concat());
+    assert!(verify_acceptance(&public_key, &proposal_hash, &acceptance).is_ok());
+
+    // The bare hash, another proposal, another key and a malformed key all fail
+    assert!(verify_acceptance(&public_key, &proposal_hash, &signed(&proposal_hash)).is_err());
+    assert!(verify_acceptance(&public_key, &[0xCD; 32], &acceptance).is_err());
+    let other_key = SigningKey::from_bytes(&[8; 32]).verifying_key().to_bytes().to_vec();
+    assert!(verify_acceptance(&other_key, &proposal_hash, &acceptance).is_err());
+    assert!(verify_acceptance(&public_key[..31], &proposal_hash, &acceptance).is_err());
+}
+
+fn directive_activation(patient_id_hash: &[u8], directive_type: &str, status: &str, not_before: Option<u64>) -> activation::DirectiveActivation {
+    activation::DirectiveActivation {
+        patient_id_hash: patient_id_hash.to_vec(),
+        directive_type: directive_type.to_string(),
+        conditions: not_before.map_or(vec![], |not_before| vec![activation::ActivationCondition {
+            condition_type: "AFTER_DATE".to_string(),
+            not_before: Some(not_before),
+            diagnosis_codes: vec![],
+            required_attestations: 0,
+        }]),
+        require_all: true,
+        status: status.to_string(),
+        activated_at: None,
+        activation_reason: None,
+    }
+}
+
+fn physician(principal: Principal) -> activation::RegisteredPhysician {
+    activation::RegisteredPhysician {
+        principal,
+        name: "Dr. Example".to_string(),
+        license_number: "MD-0001".to_string(),
+        public_key: vec![0; 32],
+        registered_at: 0,
+    }
+}
+
+fn attestation(physician: Principal) -> activation::IncapacityAttestation {
+    activation::IncapacityAttestation { physician, signature: vec![0; 64], attested_at: 0 }
+}
+
+#[test]
+fn certified_living_will_and_locked_directives_survive_an_upgrade() {
+    at(0);
+    let patient = vec![0x11; 32];
+    let (dr_a, dr_b) = (principal(10), principal(11));
+    activation::restore(Some(activation::ActivationState {
+        activations: vec![
+            directive_activation(&patient, "LIVING_WILL", "ACTIVE", None),
+            directive_activation(&patient, "DNR", "PENDING", Some(clock::now() + 60 * NANOS_PER_MINUTE)),
+        ],
+        diagnoses: vec![],
+        attestations: vec![(patient.clone(), vec![attestation(dr_a), attestation(dr_b)])],
+        physicians: vec![physician(dr_a), physician(dr_b)],
+        required_attestations: 2,
+    }));
+    assert!(activation::activation_status(patient.clone(), "LIVING_WILL".to_string()).active);
+
+    // What pre_upgrade saves, post_upgrade puts back over the fresh image's empty state
+    let saved = activation::snapshot();
+    activation::restore(Some(activation::ActivationState {
+        activations: vec![],
+        diagnoses: vec![],
+        attestations: vec![],
+        physicians: vec![],
+        required_attestations: 2,
+    }));
+    assert!(!activation::activation_status(patient.clone(), "LIVING_WILL".to_string()).active);
+
+    activation::restore(Some(saved));
+    assert_eq!(activation::attestation_count(&patient), 2);
+    assert!(activation::activation_status(patient.clone(), "LIVING_WILL".to_string()).active);
+    assert!(!activation::activation_status(patient.clone(), "DNR".to_string()).active);
+    at(60);
+    assert!(activation::activation_status(patient, "DNR".to_string()).active);
+}
+
+#[test]
+fn only_registered_physicians_count_toward_certified_incapacity() {
+    at(0);
+    let patient = vec![0x12; 32];
+    let (dr_a, deregistered) = (principal(12), principal(13));
+    activation::restore(Some(activation::ActivationState {
+        activations: vec![directive_activation(&patient, "LIVING_WILL", "ACTIVE", None)],
+        diagnoses: vec![],
+        attestations: vec![(patient.clone(), vec![attestation(dr_a), attestation(deregistered)])],
+        physicians: vec![physician(dr_a)],
+        required_attestations: 2,
+    }));
+    assert_eq!(activation::attestation_count(&patient), 1);
+    assert!(!activation::activation_status(patient, "LIVING_WILL".to_string()).active);
+}
+
+#[test]
+fn rotation_keys_new_hashes_and_reads_through_to_records_not_yet_migrated() {
+    at(0);
+    let patient_id = "patient_rekey_001";
+    let legacy = hashing::patient_hash(patient_id);
+    assert_eq!(legacy, ic_cdk::api::sha256(patient_id.as_bytes()));
+    PATIENT_HASH_INDEX.with(|index| index.borrow_mut().insert(legacy.clone(), patient_id.to_string()));
+
+    let key = hashing::install_rotated_key(vec![0x42; 32]);
+    assert_eq!((key.version, key.status.as_str()), (1, "ACTIVE"));
+    let keyed = hashing::patient_hash(patient_id);
+    assert_eq!(keyed, hashing::hmac_sha256(&[0x42; 32], patient_id.as_bytes()));
+    assert_ne!(keyed, legacy);
+
+    // Until the patient is migrated, the new hash finds the records still stored under the old one
+    assert_eq!(hashing::storage_key(&keyed), legacy);
+    let migration = hashing::migration().unwrap();
+    assert_eq!((migration.from_version, migration.to_version), (0, 1));
+    assert_eq!((migration.total_patients, migration.migrated_patients), (1, 0));
+    assert!(migration.completed_at.is_none());
+}
+
+#[test]
+fn patient_hashes_and_challenge_answers_are_rfc_4231_hmac_sha256() {
+    assert_eq!(
+        hashing::hmac_sha256(&[0x0b; 20], b"Hi There"),
+        hex("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7")
+    );
+    // Keys longer than a block are hashed first
+    assert_eq!(
+        hashing::hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First"),
+        hex("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54")
+    );
+}
+
+#[test]
+fn hospital_challenge_keys_survive_an_upgrade() {
+    challenge::restore(Some(challenge::ChallengeState { keys: vec![("MAYO".to_string(), vec![0x55; 32], 7)] }));
+    let saved = challenge::snapshot();
+
+    challenge::restore(Some(challenge::ChallengeState { keys: vec![] }));
+    assert!(challenge::key_ages().is_empty());
+    // An image without challenge state leaves the keys as they are
+    challenge::restore(None);
+    assert!(challenge::key_ages().is_empty());
+
+    challenge::restore(Some(saved));
+    assert_eq!(challenge::key_ages(), vec![("MAYO".to_string(), 7)]);
+}
+
+#[test]
+fn full_audit_buffer_sheds_its_oldest_entries_and_counts_them() {
+    const CAP: u64 = 100_000;
+    at(0);
+    let entry = |accessed_at: u64| emergency::EmergencyAccessLog {
+        patient_id_hash: vec![0x13; 32],
+        requester: Principal::anonymous(),
+        via: Principal::anonymous(),
+        accessed_at,
+        sequence: Some(accessed_at),
+        outcome: "GRANTED".to_string(),
+        directive_types: vec!["DNR".to_string()],
+        requester_jurisdiction: None,
+        cross_border: None,
+    };
+    for accessed_at in 0..CAP {
+        audit_buffer::buffer(entry(accessed_at));
+    }
+    let status = audit_buffer::status();
+    assert_eq!((status.buffered_entries, status.shed_entries, status.oldest_buffered_at), (CAP, 0, Some(0)));
+
+    // An access is never refused for want of space; the oldest unarchived entry makes room
+    assert_eq!(audit_buffer::buffer(entry(CAP)), CAP);
+    assert_eq!(audit_buffer::buffer(entry(CAP + 1)), CAP);
+    let status = audit_buffer::status();
+    assert_eq!((status.buffered_entries, status.shed_entries, status.oldest_buffered_at), (CAP, 2, Some(2)));
+    assert!(status.last_shed_at.is_some());
+}
+
+#[test]
+fn contacts_see_only_the_detail_their_content_level_allows() {
+    let event = ContactEvent {
+        event_type: "EMERGENCY_ACCESS".to_string(),
+        reference_id: "REF_001".to_string(),
+        summary: "Directive read".to_string(),
+        details: "Cardiac arrest, ER 3".to_string(),
+    };
+    let full = render_contact_message(&event, "FULL", "en");
+    assert!(full.contains("Directive read") && full.contains("Cardiac arrest, ER 3"));
+    let summary = render_contact_message(&event, "SUMMARY", "en");
+    assert!(summary.contains("Directive read") && !summary.contains("Cardiac arrest"));
+    // Unknown levels fall back to the least revealing message
+    for level in ["MINIMAL", "UNKNOWN"] {
+        let minimal = render_contact_message(&event, level, "en");
+        assert!(minimal.contains("REF_001") && !minimal.contains("Directive read") && !minimal.contains("Cardiac arrest"));
+    }
+}
@@ -0,0 +1,121 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::{call, caller};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{emergency, hashing, honeytokens, identity, load_shedding, tenants, validation, CONSENT_DIRECTIVES, PATIENT_HASH_INDEX};
+
+// Machine translations of a directive, kept next to the original they were made from. A translation
+// belongs to one version of the directive: once the directive changes it is no longer served.
+
+// Mirrors llm_canister's DirectiveTranslation
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DirectiveTranslation {
+    pub source_language: String,
+    pub target_language: String,
+    pub summary: String,
+    pub directives: Vec<TranslatedDirective>,
+    pub confidence_score: f32,
+    pub machine_translated: bool,
+    pub notice: String,
+    pub provider_id: String,
+    pub template_version: u32,
+    pub translated_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct TranslatedDirective {
+    pub directive_type: String,
+    pub conditions: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct TranslationRecord {
+    pub patient_id: String,
+    pub directive_type: String,
+    pub directive_timestamp: u64, // the version of the directive that was translated
+    pub requested_by: Principal,
+    pub translation: DirectiveTranslation,
+}
+
+thread_local! {
+    static DIRECTIVE_TRANSLATIONS: std::cell::RefCell<BTreeMap<String, TranslationRecord>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+// The patient, their proxies, or anyone their tenant lets read the directive may ask for a translation
+#[ic_cdk::update]
+async fn translate_directive(patient_id: String, source_language: String) -> Result<TranslationRecord, String> {
+    let _permit = load_shedding::admit("BULK")?;
+    let requester = caller();
+    let source_language = validation::language_tag("source_language", &source_language)?;
+    let directive = CONSENT_DIRECTIVES.with(|d| d.borrow().get(&patient_id))
+        .ok_or_else(|| format!("No directive found for patient {}", patient_id))?;
+    if !tenants::may_access_patient(requester, &patient_id, Some(&directive.directive_type)) {
+        return Err("Caller may not read this patient's directive".to_string());
+    }
+    if directive.consent_items.is_empty() {
+        return Err("Directive has no text to translate".to_string());
+    }
+
+    let llm_canister = Principal::from_text(tenants::LLM_CANISTER_ID).map_err(|_| "Invalid LLM canister ID")?;
+    let (result,): (Result<DirectiveTranslation, String>,) = call(
+        llm_canister,
+        "translate_directive_text",
+        (patient_id.clone(), source_language, directive.consent_items.join("\n"), tenants::patient_tenant(&patient_id))
+    ).await.map_err(|(_, msg)| format!("Translation failed: {}", msg))?;
+    let translation = result?;
+
+    // The outcall takes seconds; a directive edited meanwhile must not be paired with the old text's translation
+    let current = CONSENT_DIRECTIVES.with(|d| d.borrow().get(&patient_id)).map(|d| d.timestamp);
+    if current != Some(directive.timestamp) {
+        return Err("Directive changed while it was being translated; request the translation again".to_string());
+    }
+    let record = TranslationRecord {
+        patient_id: patient_id.clone(),
+        directive_type: directive.directive_type,
+        directive_timestamp: directive.timestamp,
+        requested_by: requester,
+        translation,
+    };
+    DIRECTIVE_TRANSLATIONS.with(|t| t.borrow_mut().insert(patient_id, record.clone()));
+    Ok(record)
+}
+
+// The stored translation, including one made from an earlier version of the directive
+#[ic_cdk::update]
+fn get_directive_translation(patient_id: String) -> Result<Option<TranslationRecord>, String> {
+    honeytokens::touch(&patient_id, caller(), "Directive translation read");
+    if !tenants::may_access_patient(caller(), &patient_id, None) {
+        return Err("Caller may not read this patient's directive".to_string());
+    }
+    Ok(DIRECTIVE_TRANSLATIONS.with(|t| t.borrow().get(&patient_id).cloned()))
+}
+
+// Called by emergency_bridge for a requester whose locale differs from the directive's language,
+// after emergency_lookup has disclosed the directive itself; the same token must be presented
+#[ic_cdk::update]
+fn emergency_translation(
+    patient_id_hash: Vec<u8>,
+    requester: Principal,
+    token: String
+) -> Result<Option<DirectiveTranslation>, String> {
+    let _permit = load_shedding::admit("EMERGENCY")?;
+    let bridge = Principal::from_text(emergency::EMERGENCY_BRIDGE_CANISTER_ID).ok();
+    if Some(caller()) != bridge && !ic_cdk::api::is_controller(&caller()) {
+        return Err("Emergency lookups must come through emergency_bridge".to_string());
+    }
+    emergency::validate_token(&token, requester)?;
+
+    let patient_id_hash = identity::canonical_patient_hash(&hashing::storage_key(&patient_id_hash));
+    let Some(patient_id) = PATIENT_HASH_INDEX.with(|index| index.borrow().get(&patient_id_hash).cloned()) else {
+        return Ok(None);
+    };
+    let current = CONSENT_DIRECTIVES.with(|d| d.borrow().get(&patient_id)).map(|d| d.timestamp);
+    let record = DIRECTIVE_TRANSLATIONS.with(|t| t.borrow().get(&patient_id).cloned())
+        .filter(|record| Some(record.directive_timestamp) == current);
+    if let Some(record) = &record {
+        emergency::log_access(&patient_id_hash, requester, caller(), "TRANSLATION_SERVED", vec![record.directive_type.clone()]);
+    }
+    Ok(record.map(|r| r.translation))
+}
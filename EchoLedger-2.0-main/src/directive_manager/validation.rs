@@ -0,0 +1,114 @@
+use crate::{EmergencyContact, PHIMetadata};
+
+// Shape checks for everything that arrives over Candid. Each failure reads
+// "Invalid <field>: <reason>" so integrators can map errors back to their payloads.
+
+// Ingress argument caps, checked in inspect_message before the argument is decoded
+const DEFAULT_MAX_ARG_BYTES: usize = 64 * 1024;
+const LARGE_ARG_METHODS: [(&str, usize); 2] = [
+    ("record_verification_receipts", 512 * 1024),
+    ("update_consent_directive", 256 * 1024),
+];
+
+const MAX_IDENTIFIER_BYTES: usize = 128;
+pub(crate) const MAX_REASON_BYTES: usize = 1024;
+pub(crate) const MAX_RATIONALE_BYTES: usize = 4 * 1024;
+pub(crate) const MAX_SCOPES: usize = 16;
+pub(crate) const MAX_HASH_BYTES: usize = 64;
+const MAX_ADDRESS_BYTES: usize = 512;
+const MAX_OFF_CHAIN_REF_BYTES: usize = 1024;
+
+pub(crate) fn within_ingress_limit(method: &str) -> bool {
+    let limit = LARGE_ARG_METHODS.iter()
+        .find(|(name, _)| *name == method)
+        .map_or(DEFAULT_MAX_ARG_BYTES, |(_, limit)| *limit);
+    ic_cdk::api::call::arg_data_raw_size() <= limit
+}
+
+pub(crate) fn metadata(metadata: PHIMetadata) -> Result<PHIMetadata, String> {
+    bytes("patient_id_hash", &metadata.patient_id_hash, MAX_HASH_BYTES)?;
+    Ok(PHIMetadata {
+        directive_type: identifier("directive_type", &metadata.directive_type)?,
+        off_chain_ref: text("off_chain_ref", &metadata.off_chain_ref, MAX_OFF_CHAIN_REF_BYTES)?,
+        ..metadata
+    })
+}
+
+pub(crate) fn contact(contact: EmergencyContact) -> Result<EmergencyContact, String> {
+    let address = text("address", &contact.address, MAX_ADDRESS_BYTES)?;
+    if address.is_empty() || address.contains(['\n', '\t']) {
+        return Err(invalid("address", "must be a single non-empty line"));
+    }
+    Ok(EmergencyContact {
+        name: identifier("name", &contact.name)?,
+        relationship: identifier("relationship", &contact.relationship)?,
+        address,
+        ..contact
+    })
+}
+
+// Free text: at most `max_bytes`, no control characters other than newline and tab, and
+// normalized (no byte-order mark, LF line endings, exotic spaces made plain, trimmed)
+pub(crate) fn text(field: &str, value: &str, max_bytes: usize) -> Result<String, String> {
+    if value.len() > max_bytes {
+        return Err(invalid(field, &format!("longer than {} bytes", max_bytes)));
+    }
+    let normalized = value.replace("\r\n", "\n").replace('\r', "\n");
+    let mut cleaned = String::with_capacity(normalized.len());
+    for c in normalized.chars() {
+        match c {
+            '\u{FEFF}' => {}
+            '\u{00A0}' | '\u{2000}'..='\u{200A}' | '\u{202F}' | '\u{205F}' | '\u{3000}' => cleaned.push(' '),
+            '\n' | '\t' => cleaned.push(c),
+            // U+FFFD is what binary content turns into once forced through a UTF-8 decoder
+            '\u{FFFD}' | '\u{FFFE}' | '\u{FFFF}' => return Err(invalid(field, "contains binary content")),
+            _ if c.is_control() => return Err(invalid(field, "contains control characters")),
+            _ => cleaned.push(c),
+        }
+    }
+    Ok(cleaned.trim().to_string())
+}
+
+// Ids and codes: non-empty single-line text
+pub(crate) fn identifier(field: &str, value: &str) -> Result<String, String> {
+    let value = text(field, value, MAX_IDENTIFIER_BYTES)?;
+    if value.is_empty() {
+        return Err(invalid(field, "must not be empty"));
+    }
+    if value.contains(['\n', '\t']) {
+        return Err(invalid(field, "must be a single line"));
+    }
+    Ok(value)
+}
+
+// BCP 47 language tags as clients send them ("de", "de-DE", "pt-BR"); lowercased for comparison
+pub(crate) fn language_tag(field: &str, value: &str) -> Result<String, String> {
+    let value = identifier(field, value)?.to_lowercase();
+    let mut subtags = value.split('-');
+    let primary = subtags.next().unwrap_or_default();
+    let well_formed = (2..=3).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_lowercase())
+        && subtags.all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()));
+    if !well_formed {
+        return Err(invalid(field, "not a language tag such as \"de\" or \"en-US\""));
+    }
+    Ok(value)
+}
+
+pub(crate) fn collection(field: &str, len: usize, max_items: usize) -> Result<(), String> {
+    if len > max_items {
+        return Err(invalid(field, &format!("more than {} entries", max_items)));
+    }
+    Ok(())
+}
+
+pub(crate) fn bytes(field: &str, value: &[u8], max_bytes: usize) -> Result<(), String> {
+    if value.is_empty() || value.len() > max_bytes {
+        return Err(invalid(field, &format!("must be 1-{} bytes", max_bytes)));
+    }
+    Ok(())
+}
+
+pub(crate) fn invalid(field: &str, reason: &str) -> String {
+    format!("Invalid {}: {}", field, reason)
+}
@@ -0,0 +1,138 @@
+use candid::{CandidType, Deserialize, Principal};
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::emergency::{self, EmergencyDirective, EMERGENCY_BRIDGE_CANISTER_ID};
+use crate::merkle::{self, InclusionProof};
+use crate::{challenge, clock, hashing, identity, ConsentDirective, CONSENT_DIRECTIVES, CONSENT_DIRECTIVE_VERSIONS, PATIENT_HASH_INDEX};
+
+// The stored directive with its Merkle inclusion proof; the proof's certificate binds the root to this canister
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct CertifiedDirective {
+    pub directive: ConsentDirective,
+    pub proof: InclusionProof,
+}
+
+// What a query verification read; flushed back through record_verification_receipts to be audited
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct VerificationReceipt {
+    pub receipt_id: String,
+    pub patient_id_hash: Vec<u8>,
+    pub requester: Principal,
+    pub token_hash: Vec<u8>,
+    pub directive_types: Vec<String>,
+    pub verified_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct DirectiveVerification {
+    pub directives: Vec<EmergencyDirective>,
+    pub certified: Vec<CertifiedDirective>,
+    pub receipt: VerificationReceipt,
+}
+
+thread_local! {
+    // receipt_id -> verified_at, so a receipt flushed twice is audited once
+    static RECORDED_RECEIPTS: std::cell::RefCell<BTreeMap<String, u64>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+// Receipts older than this are refused; a hospital's buffer must be flushed at least daily
+const RECEIPT_MAX_AGE_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+const MAX_RECEIPTS_PER_FLUSH: usize = 500;
+
+// Read-only verification for a hospital holding an emergency token, answered without consensus.
+// A query cannot write the access log, so the reply carries a receipt the hospital buffers and
+// flushes with record_verification_receipts. Alerts, notifications and anything that must be
+// audited before the data is seen go through emergency_bridge's emergency_check instead.
+#[ic_cdk::query]
+fn verify_directives(patient_id_hash: Vec<u8>, token: String) -> Result<DirectiveVerification, String> {
+    let requester = caller();
+    emergency::validate_token(&token, requester)?;
+
+    let patient_id_hash = identity::canonical_patient_hash(&hashing::storage_key(&patient_id_hash));
+    let directives = emergency::active_directives(&patient_id_hash);
+    if directives.is_empty() {
+        return Err("No active directive found for patient".to_string());
+    }
+
+    let certified = PATIENT_HASH_INDEX
+        .with(|index| index.borrow().get(&patient_id_hash).cloned())
+        .and_then(|patient_id| {
+            let directive = CONSENT_DIRECTIVES.with(|d| d.borrow().get(&patient_id))?;
+            let version = CONSENT_DIRECTIVE_VERSIONS.with(|v| v.borrow().get(&patient_id).map(|list| list.len() as u64))?;
+            let proof = merkle::inclusion_proof(patient_id, version).ok()?;
+            Some(CertifiedDirective { directive, proof })
+        })
+        .into_iter()
+        .collect();
+
+    let verified_at = clock::now();
+    let directive_types = directives.iter().map(|d| d.directive_type.clone()).collect();
+    let mut material = requester.as_slice().to_vec();
+    material.extend_from_slice(&patient_id_hash);
+    material.extend_from_slice(&verified_at.to_be_bytes());
+    let receipt_id = ic_cdk::api::sha256(&material).iter().map(|b| format!("{:02x}", b)).collect();
+
+    Ok(DirectiveVerification {
+        directives,
+        certified,
+        receipt: VerificationReceipt {
+            receipt_id,
+            patient_id_hash,
+            requester,
+            token_hash: ic_cdk::api::sha256(token.as_bytes()),
+            directive_types,
+            verified_at,
+        },
+    })
+}
+
+// The buffered half of the query path: each receipt becomes an access log entry dated when the read happened.
+// Hospitals flush their own receipts; emergency_bridge and controllers may flush on their behalf.
+#[ic_cdk::update]
+fn record_verification_receipts(receipts: Vec<VerificationReceipt>) -> Result<u32, String> {
+    let via = caller();
+    let bridge = Principal::from_text(EMERGENCY_BRIDGE_CANISTER_ID).ok();
+    let trusted = Some(via) == bridge || ic_cdk::api::is_controller(&via);
+    challenge::require_proof(via)?;
+    if receipts.len() > MAX_RECEIPTS_PER_FLUSH {
+        return Err(format!("At most {} receipts per flush", MAX_RECEIPTS_PER_FLUSH));
+    }
+
+    let now = clock::now();
+    RECORDED_RECEIPTS.with(|r| r.borrow_mut().retain(|_, at| now.saturating_sub(*at) < RECEIPT_MAX_AGE_NANOS));
+
+    // Check the whole batch first so a bad receipt cannot leave it half recorded
+    for receipt in &receipts {
+        if !trusted && receipt.requester != via {
+            return Err("Receipts may only be flushed by the hospital that made the query".to_string());
+        }
+        if receipt.verified_at > now || now - receipt.verified_at >= RECEIPT_MAX_AGE_NANOS {
+            return Err(format!("Receipt {} is outside the accepted window", receipt.receipt_id));
+        }
+        if !emergency::token_issued_to(&receipt.token_hash, receipt.requester) {
+            return Err(format!("Receipt {} does not name a token issued to its requester", receipt.receipt_id));
+        }
+    }
+
+    let mut recorded = 0;
+    for receipt in receipts {
+        if RECORDED_RECEIPTS.with(|r| r.borrow().contains_key(&receipt.receipt_id)) {
+            continue;
+        }
+        emergency::log_access_at(
+            &receipt.patient_id_hash,
+            receipt.requester,
+            via,
+            "QUERY_VERIFIED",
+            receipt.directive_types,
+            receipt.verified_at,
+            None,
+        );
+        RECORDED_RECEIPTS.with(|r| r.borrow_mut().insert(receipt.receipt_id, receipt.verified_at));
+        recorded += 1;
+    }
+    Ok(recorded)
+}
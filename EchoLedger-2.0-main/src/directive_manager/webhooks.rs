@@ -0,0 +1,522 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs, TransformContext,
+};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::caller;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::{clock, hashing, identity, payers, references, EXECUTOR_AI_CANISTER_ID};
+
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct WebhookSubscription {
+    pub subscription_id: String,
+    pub subscriber: Principal,
+    pub callback_url: String,
+    pub event_types: Vec<String>,
+    pub scope: String, // "ATTRIBUTED", "ALL", "PAYER"
+    pub active: bool,
+    pub consecutive_failures: u32,
+    pub created_at: u64,
+}
+
+// One record per (subscription, event), updated in place as retries run
+#[derive(CandidType, Deserialize, Serialize, Clone, Debug)]
+pub struct WebhookDelivery {
+    pub delivery_id: String,
+    pub subscription_id: String,
+    pub event_id: String,
+    pub event_type: String,
+    pub status: String, // "PENDING", "RETRYING", "DELIVERED", "FAILED"
+    pub attempts: u32,
+    pub last_response_status: Option<String>,
+    pub last_error: Option<String>,
+    pub created_at: u64,
+    pub last_attempt_at: Option<u64>,
+    pub next_attempt_at: Option<u64>,
+}
+
+// The callback body; names the patient only by the subscription's own reference, never PHI
+#[derive(Serialize)]
+struct WebhookPayload {
+    event_id: String,
+    event_type: String,
+    patient_reference: String,
+    reference_id: String,
+    occurred_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    execution_types: Option<Vec<String>>, // payer notices only
+}
+
+// Carried across upgrades with the hash key ring; subscribers would otherwise stop hearing from the
+// registry with no signal. Retry timers are not, and are re-armed from next_attempt_at.
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct WebhookState {
+    subscriptions: Vec<WebhookSubscription>,
+    secrets: Vec<(String, Vec<u8>)>,
+    deliveries: Vec<WebhookDelivery>,
+    pending_payloads: Vec<(String, Vec<u8>)>,
+    next_event_seq: u64,
+}
+
+thread_local! {
+    static WEBHOOK_SUBSCRIPTIONS: std::cell::RefCell<BTreeMap<String, WebhookSubscription>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    // Kept apart from the subscription so listing never exposes it
+    static WEBHOOK_SECRETS: std::cell::RefCell<BTreeMap<String, Vec<u8>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static WEBHOOK_DELIVERIES: std::cell::RefCell<BTreeMap<String, WebhookDelivery>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    // delivery_id -> body, dropped once the delivery succeeds or gives up
+    static PENDING_PAYLOADS: std::cell::RefCell<BTreeMap<String, Vec<u8>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static NEXT_EVENT_SEQ: std::cell::RefCell<u64> = std::cell::RefCell::new(0);
+}
+
+const WEBHOOK_EVENT_TYPES: [&str; 5] = [
+    "DIRECTIVE_CREATED",
+    "DIRECTIVE_UPDATED",
+    "DIRECTIVE_REVOKED",
+    "EXECUTION_STARTED",
+    "EXECUTION_COMPLETED",
+];
+// Sent only to payer subscriptions, and only for patients who named the payer
+const PAYER_NOTICE: &str = "PAYER_NOTICE";
+const MAX_SUBSCRIPTIONS_PER_SUBSCRIBER: usize = 10;
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+// Delay before attempts 2..=5
+const RETRY_BACKOFF_SECONDS: [u64; 4] = [60, 300, 1_800, 7_200];
+// A dead endpoint is suspended instead of burning outcall cycles indefinitely
+const SUSPEND_AFTER_FAILURES: u32 = 15;
+const OUTCALL_CYCLES: u128 = 50_000_000_000;
+// The delivery log keeps finished records for audit; past this, the oldest are pruned first
+const MAX_WEBHOOK_DELIVERIES: usize = 10_000;
+
+// The secret is returned once; receivers check X-EchoLedger-Signature with it
+#[ic_cdk::update]
+async fn create_webhook_subscription(
+    callback_url: String,
+    event_types: Vec<String>,
+    scope: String
+) -> Result<(WebhookSubscription, Vec<u8>), String> {
+    let subscriber = caller();
+    match scope.as_str() {
+        "ATTRIBUTED" => identity::ensure_registrar(&subscriber)?,
+        // Registry-wide feeds cover every patient, so controllers grant them
+        "ALL" if ic_cdk::api::is_controller(&subscriber) => {}
+        "ALL" => return Err("Only controllers may subscribe to events for all patients".to_string()),
+        "PAYER" if !payers::is_payer(subscriber) => return Err("Only registered payers may subscribe to payer notices".to_string()),
+        "PAYER" if event_types.iter().any(|t| t != PAYER_NOTICE) => {
+            return Err(format!("Payer subscriptions carry {} events only", PAYER_NOTICE));
+        }
+        "PAYER" => {}
+        _ => return Err(format!("Unknown subscription scope: {}", scope)),
+    }
+    if !callback_url.starts_with("https://") {
+        return Err("Callback URL must use https".to_string());
+    }
+    if event_types.is_empty() {
+        return Err("Subscribe to at least one event type".to_string());
+    }
+    if let Some(unknown) = event_types.iter().find(|t| !WEBHOOK_EVENT_TYPES.contains(&t.as_str()) && scope != "PAYER") {
+        return Err(format!("Unknown event type: {}", unknown));
+    }
+    let existing = WEBHOOK_SUBSCRIPTIONS.with(|s| s.borrow().values().filter(|x| x.subscriber == subscriber).count());
+    if existing >= MAX_SUBSCRIPTIONS_PER_SUBSCRIBER {
+        return Err(format!("At most {} subscriptions per subscriber", MAX_SUBSCRIPTIONS_PER_SUBSCRIBER));
+    }
+
+    let (secret,) = raw_rand().await.map_err(|(_, msg)| format!("Failed to generate webhook secret: {}", msg))?;
+    let created_at = clock::now();
+    let subscription = WebhookSubscription {
+        subscription_id: format!("WHSUB_{}", hex(&ic_cdk::api::sha256(&secret)[..8])),
+        subscriber,
+        callback_url,
+        event_types,
+        scope,
+        active: true,
+        consecutive_failures: 0,
+        created_at,
+    };
+
+    WEBHOOK_SUBSCRIPTIONS.with(|s| {
+        s.borrow_mut().insert(subscription.subscription_id.clone(), subscription.clone());
+    });
+    WEBHOOK_SECRETS.with(|s| {
+        s.borrow_mut().insert(subscription.subscription_id.clone(), secret.clone());
+    });
+    ic_cdk::println!(
+        "AUDIT: Webhook subscription {} created by {} ({})",
+        subscription.subscription_id,
+        subscriber.to_text(),
+        subscription.scope
+    );
+    Ok((subscription, secret))
+}
+
+// Re-enabling a suspended subscription also clears its failure streak
+#[ic_cdk::update]
+fn set_webhook_subscription_active(subscription_id: String, active: bool) -> Result<WebhookSubscription, String> {
+    let requester = caller();
+    WEBHOOK_SUBSCRIPTIONS.with(|s| {
+        let mut subscriptions = s.borrow_mut();
+        let subscription = subscriptions.get_mut(&subscription_id).ok_or("Unknown webhook subscription")?;
+        if subscription.subscriber != requester && !ic_cdk::api::is_controller(&requester) {
+            return Err("Only the subscriber may change this subscription".to_string());
+        }
+        subscription.active = active;
+        if active {
+            subscription.consecutive_failures = 0;
+        }
+        Ok(subscription.clone())
+    })
+}
+
+// The delivery log is kept for audit after the subscription is gone
+#[ic_cdk::update]
+fn delete_webhook_subscription(subscription_id: String) -> Result<(), String> {
+    let requester = caller();
+    WEBHOOK_SUBSCRIPTIONS.with(|s| {
+        let mut subscriptions = s.borrow_mut();
+        let subscription = subscriptions.get(&subscription_id).ok_or("Unknown webhook subscription")?;
+        if subscription.subscriber != requester && !ic_cdk::api::is_controller(&requester) {
+            return Err("Only the subscriber may delete this subscription".to_string());
+        }
+        subscriptions.remove(&subscription_id);
+        Ok(())
+    })?;
+    WEBHOOK_SECRETS.with(|s| s.borrow_mut().remove(&subscription_id));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_webhook_subscriptions() -> Vec<WebhookSubscription> {
+    let requester = caller();
+    WEBHOOK_SUBSCRIPTIONS.with(|s| {
+        s.borrow().values().filter(|x| x.subscriber == requester).cloned().collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_webhook_deliveries(subscription_id: String) -> Result<Vec<WebhookDelivery>, String> {
+    let requester = caller();
+    let subscriber = WEBHOOK_SUBSCRIPTIONS.with(|s| s.borrow().get(&subscription_id).map(|x| x.subscriber));
+    if subscriber != Some(requester) && !ic_cdk::api::is_controller(&requester) {
+        return Err("Only the subscriber may read this delivery log".to_string());
+    }
+    let mut deliveries: Vec<WebhookDelivery> = WEBHOOK_DELIVERIES.with(|d| {
+        d.borrow().values().filter(|x| x.subscription_id == subscription_id).cloned().collect()
+    });
+    deliveries.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(deliveries)
+}
+
+// executor_ai reports the start and end of each death-directive run
+#[ic_cdk::update]
+fn publish_execution_event(patient_id: String, event_type: String, execution_id: String) -> Result<(), String> {
+    let executor_id = Principal::from_text(EXECUTOR_AI_CANISTER_ID)
+        .map_err(|_| "Invalid executor canister ID")?;
+    if caller() != executor_id {
+        return Err("Only executor_ai may publish execution events".to_string());
+    }
+    if !event_type.starts_with("EXECUTION_") || !WEBHOOK_EVENT_TYPES.contains(&event_type.as_str()) {
+        return Err(format!("Unknown execution event type: {}", event_type));
+    }
+    publish(&event_type, &hashing::patient_hash(&patient_id), &execution_id);
+    Ok(())
+}
+
+// Fan an event out to every matching subscription; delivery runs on timers after this call returns
+pub(crate) fn publish(event_type: &str, patient_id_hash: &[u8], reference_id: &str) {
+    let recipients: Vec<String> = WEBHOOK_SUBSCRIPTIONS.with(|s| {
+        s.borrow()
+            .values()
+            .filter(|x| x.active && x.event_types.iter().any(|t| t == event_type))
+            .filter(|x| x.scope == "ALL" || identity::is_attributed(x.subscriber, patient_id_hash))
+            .map(|x| x.subscription_id.clone())
+            .collect()
+    });
+    enqueue(&recipients, event_type, patient_id_hash, reference_id, clock::now(), None);
+}
+
+// To the payer's own subscriptions only; returns how many deliveries were queued
+pub(crate) fn publish_payer_notice(
+    payer: Principal,
+    patient_id_hash: &[u8],
+    execution_id: &str,
+    execution_types: &[String],
+    executed_at: u64
+) -> usize {
+    let recipients: Vec<String> = WEBHOOK_SUBSCRIPTIONS.with(|s| {
+        s.borrow()
+            .values()
+            .filter(|x| x.active && x.scope == "PAYER" && x.subscriber == payer)
+            .map(|x| x.subscription_id.clone())
+            .collect()
+    });
+    enqueue(&recipients, PAYER_NOTICE, patient_id_hash, execution_id, executed_at, Some(execution_types.to_vec()));
+    recipients.len()
+}
+
+fn enqueue(
+    recipients: &[String],
+    event_type: &str,
+    patient_id_hash: &[u8],
+    reference_id: &str,
+    occurred_at: u64,
+    execution_types: Option<Vec<String>>
+) {
+    if recipients.is_empty() {
+        return;
+    }
+    let seq = NEXT_EVENT_SEQ.with(|n| {
+        let mut n = n.borrow_mut();
+        *n += 1;
+        *n
+    });
+    let event_id = format!("EVT_{}_{}", occurred_at, seq);
+
+    for subscription_id in recipients {
+        let payload = WebhookPayload {
+            event_id: event_id.clone(),
+            event_type: event_type.to_string(),
+            patient_reference: references::reference_for(&format!("webhook:{}", subscription_id), patient_id_hash),
+            reference_id: reference_id.to_string(),
+            occurred_at,
+            execution_types: execution_types.clone(),
+        };
+        let Ok(body) = serde_json::to_vec(&payload) else {
+            continue;
+        };
+        let delivery_id = format!("DLV_{}_{}", subscription_id, seq);
+        WEBHOOK_DELIVERIES.with(|d| {
+            d.borrow_mut().insert(delivery_id.clone(), WebhookDelivery {
+                delivery_id: delivery_id.clone(),
+                subscription_id: subscription_id.clone(),
+                event_id: event_id.clone(),
+                event_type: event_type.to_string(),
+                status: "PENDING".to_string(),
+                attempts: 0,
+                last_response_status: None,
+                last_error: None,
+                created_at: clock::now(),
+                last_attempt_at: None,
+                next_attempt_at: Some(clock::now()),
+            });
+        });
+        PENDING_PAYLOADS.with(|p| p.borrow_mut().insert(delivery_id.clone(), body));
+        schedule_attempt(delivery_id, Duration::ZERO);
+    }
+    prune_deliveries();
+}
+
+// Finished deliveries go first, oldest first. Only if the log is still over the cap, which takes a
+// long outage across many subscribers, are the oldest pending ones dropped; their timers then find
+// nothing to send.
+fn prune_deliveries() {
+    WEBHOOK_DELIVERIES.with(|d| {
+        let mut deliveries = d.borrow_mut();
+        let excess = deliveries.len().saturating_sub(MAX_WEBHOOK_DELIVERIES);
+        if excess == 0 {
+            return;
+        }
+        let mut candidates: Vec<(bool, u64, String)> = deliveries
+            .values()
+            .map(|x| (x.next_attempt_at.is_some(), x.created_at, x.delivery_id.clone()))
+            .collect();
+        candidates.sort();
+        for (pending, _, delivery_id) in candidates.into_iter().take(excess) {
+            deliveries.remove(&delivery_id);
+            if pending {
+                PENDING_PAYLOADS.with(|p| p.borrow_mut().remove(&delivery_id));
+                ic_cdk::println!("⚠️ Webhook delivery {} dropped: delivery log is full", delivery_id);
+            }
+        }
+    });
+}
+
+// subscription_id -> when its signing secret was issued, for the key inventory. Secrets are
+// issued with the subscription and replaced by subscribing again.
+pub(crate) fn secret_ages() -> Vec<(String, u64)> {
+    WEBHOOK_SUBSCRIPTIONS.with(|s| {
+        s.borrow().values().map(|x| (x.subscription_id.clone(), x.created_at)).collect()
+    })
+}
+
+fn schedule_attempt(delivery_id: String, delay: Duration) {
+    ic_cdk_timers::set_timer(delay, move || ic_cdk::spawn(attempt_delivery(delivery_id)));
+}
+
+async fn attempt_delivery(delivery_id: String) {
+    let Some(delivery) = WEBHOOK_DELIVERIES.with(|d| d.borrow().get(&delivery_id).cloned()) else {
+        return;
+    };
+    let subscription = WEBHOOK_SUBSCRIPTIONS.with(|s| s.borrow().get(&delivery.subscription_id).cloned());
+    let secret = WEBHOOK_SECRETS.with(|s| s.borrow().get(&delivery.subscription_id).cloned());
+    let body = PENDING_PAYLOADS.with(|p| p.borrow().get(&delivery_id).cloned());
+    let (Some(subscription), Some(secret), Some(body)) = (subscription, secret, body) else {
+        finish_delivery(&delivery_id, "FAILED", delivery.attempts, None, Some("Subscription was deleted".to_string()));
+        return;
+    };
+    if !subscription.active {
+        finish_delivery(&delivery_id, "FAILED", delivery.attempts, None, Some("Subscription is suspended".to_string()));
+        return;
+    }
+
+    // Signing the timestamp with the body lets receivers reject replays
+    let timestamp = clock::now().to_string();
+    let mut signed = format!("{}.", timestamp).into_bytes();
+    signed.extend_from_slice(&body);
+    let signature = hex(&hashing::hmac_sha256(&secret, &signed));
+
+    let request = CanisterHttpRequestArgument {
+        url: subscription.callback_url.clone(),
+        max_response_bytes: Some(4_096),
+        method: HttpMethod::POST,
+        headers: vec![
+            HttpHeader { name: "Content-Type".to_string(), value: "application/json".to_string() },
+            HttpHeader { name: "X-EchoLedger-Event".to_string(), value: delivery.event_type.clone() },
+            HttpHeader { name: "X-EchoLedger-Delivery".to_string(), value: delivery_id.clone() },
+            HttpHeader { name: "X-EchoLedger-Timestamp".to_string(), value: timestamp },
+            HttpHeader { name: "X-EchoLedger-Signature".to_string(), value: format!("sha256={}", signature) },
+        ],
+        body: Some(body),
+        transform: Some(TransformContext::from_name("transform_webhook_response".to_string(), vec![])),
+    };
+
+    let (response_status, error) = match http_request(request, OUTCALL_CYCLES).await {
+        Ok((response,)) if response.status >= Nat::from(200u32) && response.status < Nat::from(300u32) => {
+            (Some(response.status.to_string()), None)
+        }
+        Ok((response,)) => (
+            Some(response.status.to_string()),
+            Some(format!("Endpoint responded with status {}", response.status)),
+        ),
+        Err((_, msg)) => (None, Some(msg)),
+    };
+
+    let Some(error) = error else {
+        WEBHOOK_SUBSCRIPTIONS.with(|s| {
+            if let Some(x) = s.borrow_mut().get_mut(&delivery.subscription_id) {
+                x.consecutive_failures = 0;
+            }
+        });
+        finish_delivery(&delivery_id, "DELIVERED", delivery.attempts + 1, response_status, None);
+        return;
+    };
+
+    let still_active = WEBHOOK_SUBSCRIPTIONS.with(|s| {
+        let mut subscriptions = s.borrow_mut();
+        let Some(x) = subscriptions.get_mut(&delivery.subscription_id) else {
+            return false;
+        };
+        x.consecutive_failures += 1;
+        if x.consecutive_failures >= SUSPEND_AFTER_FAILURES && x.active {
+            x.active = false;
+            ic_cdk::println!(
+                "⚠️ Webhook subscription {} suspended after {} consecutive failures",
+                x.subscription_id,
+                x.consecutive_failures
+            );
+        }
+        x.active
+    });
+
+    let attempts = delivery.attempts + 1;
+    if !still_active || attempts >= MAX_DELIVERY_ATTEMPTS {
+        finish_delivery(&delivery_id, "FAILED", attempts, response_status, Some(error));
+        return;
+    }
+
+    let delay = Duration::from_secs(RETRY_BACKOFF_SECONDS[(attempts - 1) as usize]);
+    WEBHOOK_DELIVERIES.with(|d| {
+        if let Some(x) = d.borrow_mut().get_mut(&delivery_id) {
+            x.status = "RETRYING".to_string();
+            x.attempts = attempts;
+            x.last_response_status = response_status;
+            x.last_error = Some(error);
+            x.last_attempt_at = Some(clock::now());
+            x.next_attempt_at = Some(clock::now() + delay.as_nanos() as u64);
+        }
+    });
+    schedule_attempt(delivery_id, delay);
+}
+
+// Each replica sees its own Date and request-id headers, so the outcall only reaches consensus once
+// everything but the status, the one thing a delivery reads, is stripped
+#[ic_cdk::query]
+fn transform_webhook_response(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: vec![],
+        body: vec![],
+    }
+}
+
+fn finish_delivery(
+    delivery_id: &str,
+    status: &str,
+    attempts: u32,
+    response_status: Option<String>,
+    error: Option<String>
+) {
+    WEBHOOK_DELIVERIES.with(|d| {
+        if let Some(x) = d.borrow_mut().get_mut(delivery_id) {
+            x.status = status.to_string();
+            x.attempts = attempts;
+            x.last_response_status = response_status;
+            x.last_error = error;
+            x.last_attempt_at = Some(clock::now());
+            x.next_attempt_at = None;
+        }
+    });
+    PENDING_PAYLOADS.with(|p| p.borrow_mut().remove(delivery_id));
+}
+
+pub(crate) fn snapshot() -> WebhookState {
+    WebhookState {
+        subscriptions: WEBHOOK_SUBSCRIPTIONS.with(|s| s.borrow().values().cloned().collect()),
+        secrets: WEBHOOK_SECRETS.with(|s| s.borrow().clone().into_iter().collect()),
+        deliveries: WEBHOOK_DELIVERIES.with(|d| d.borrow().values().cloned().collect()),
+        pending_payloads: PENDING_PAYLOADS.with(|p| p.borrow().clone().into_iter().collect()),
+        next_event_seq: NEXT_EVENT_SEQ.with(|n| *n.borrow()),
+    }
+}
+
+pub(crate) fn restore(state: Option<WebhookState>) {
+    let Some(state) = state else {
+        return;
+    };
+    WEBHOOK_SUBSCRIPTIONS.with(|s| {
+        *s.borrow_mut() = state.subscriptions.into_iter().map(|x| (x.subscription_id.clone(), x)).collect();
+    });
+    WEBHOOK_SECRETS.with(|s| *s.borrow_mut() = state.secrets.into_iter().collect());
+    WEBHOOK_DELIVERIES.with(|d| *d.borrow_mut() = state.deliveries.into_iter().map(|x| (x.delivery_id.clone(), x)).collect());
+    PENDING_PAYLOADS.with(|p| *p.borrow_mut() = state.pending_payloads.into_iter().collect());
+    NEXT_EVENT_SEQ.with(|n| *n.borrow_mut() = state.next_event_seq);
+}
+
+// A delivery whose outcall was in flight during the upgrade is attempted again; receivers
+// de-duplicate on X-EchoLedger-Delivery
+pub(crate) fn ensure_delivery_timers() {
+    let now = clock::now();
+    let pending: Vec<(String, u64)> = WEBHOOK_DELIVERIES.with(|d| {
+        d.borrow()
+            .values()
+            .filter_map(|x| x.next_attempt_at.map(|at| (x.delivery_id.clone(), at)))
+            .collect()
+    });
+    for (delivery_id, next_attempt_at) in pending {
+        schedule_attempt(delivery_id, Duration::from_nanos(next_attempt_at.saturating_sub(now)));
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,219 @@
+use ic_cdk::{caller, Principal};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{validation, ClinicianSummary, PatientDirective};
+
+// Some EDs are alerted through alphanumeric pagers or overhead announcement (text-to-speech)
+// systems rather than a screen. Each channel has a format: a length limit, plain ASCII letters,
+// digits and basic punctuation only, and optionally upper case or abbreviations spelled out so a
+// speech engine does not read "DNR" as a word. Hospitals pick the channels their alerts go to.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AlertChannelFormat {
+    pub channel: String,
+    pub max_chars: u32,
+    pub uppercase: bool,
+    pub spell_out_abbreviations: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RenderedAlert {
+    pub channel: String,
+    pub text: String,
+}
+
+const MAX_CHANNELS_PER_HOSPITAL: usize = 8;
+const MAX_CHANNEL_FORMATS: usize = 32;
+const MAX_HOSPITAL_SETTINGS: usize = 10_000;
+const MAX_RENDERED_CHARS: u32 = 2_000;
+
+const ABBREVIATIONS: [(&str, &str); 9] = [
+    ("DNR", "do not resuscitate"),
+    ("DNI", "do not intubate"),
+    ("CPR", "cardiopulmonary resuscitation"),
+    ("POLST", "physician orders for life sustaining treatment"),
+    ("OPO", "organ procurement organization"),
+    ("ICU", "intensive care unit"),
+    ("ED", "emergency department"),
+    ("SMS", "text message"),
+    ("DCD", "donation after circulatory death"),
+];
+
+thread_local! {
+    static CHANNEL_FORMATS: std::cell::RefCell<BTreeMap<String, AlertChannelFormat>> =
+        std::cell::RefCell::new(default_formats());
+
+    // requesting principal -> channels its alerts are rendered for
+    static HOSPITAL_ALERT_CHANNELS: std::cell::RefCell<BTreeMap<Principal, Vec<String>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+#[ic_cdk::query]
+fn get_alert_channel_formats() -> Vec<AlertChannelFormat> {
+    CHANNEL_FORMATS.with(|f| f.borrow().values().cloned().collect())
+}
+
+#[ic_cdk::update]
+fn set_alert_channel_format(format: AlertChannelFormat) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may change alert channel formats".to_string());
+    }
+    let channel = validation::identifier("channel", &format.channel)?.to_uppercase();
+    if format.max_chars < 20 || format.max_chars > MAX_RENDERED_CHARS {
+        return Err(validation::invalid("max_chars", &format!("must be between 20 and {}", MAX_RENDERED_CHARS)));
+    }
+    CHANNEL_FORMATS.with(|f| {
+        let mut formats = f.borrow_mut();
+        if formats.len() >= MAX_CHANNEL_FORMATS && !formats.contains_key(&channel) {
+            return Err("Too many alert channel formats are configured".to_string());
+        }
+        formats.insert(channel.clone(), AlertChannelFormat { channel, ..format });
+        Ok(())
+    })
+}
+
+// A hospital chooses its own channels; an empty list stops plain-text rendering for it
+#[ic_cdk::update]
+fn set_hospital_alert_channels(channels: Vec<String>) -> Result<(), String> {
+    validation::collection("channels", channels.len(), MAX_CHANNELS_PER_HOSPITAL)?;
+    let channels = channels.iter()
+        .map(|c| validation::identifier("channels", c).map(|c| c.to_uppercase()))
+        .collect::<Result<Vec<String>, String>>()?;
+    if let Some(unknown) = channels.iter().find(|c| !CHANNEL_FORMATS.with(|f| f.borrow().contains_key(*c))) {
+        return Err(validation::invalid("channels", &format!("no format is configured for {}", unknown)));
+    }
+    let hospital = caller();
+    HOSPITAL_ALERT_CHANNELS.with(|settings| {
+        let mut settings = settings.borrow_mut();
+        if channels.is_empty() {
+            settings.remove(&hospital);
+            return Ok(());
+        }
+        if settings.len() >= MAX_HOSPITAL_SETTINGS && !settings.contains_key(&hospital) {
+            return Err("Too many alert channel settings are stored".to_string());
+        }
+        settings.insert(hospital, channels);
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_hospital_alert_channels() -> Vec<String> {
+    HOSPITAL_ALERT_CHANNELS.with(|settings| settings.borrow().get(&caller()).cloned().unwrap_or_default())
+}
+
+// What a channel would receive for this summary, for integrators testing their pager or TTS setup
+#[ic_cdk::query]
+fn preview_alert_rendering(channel: String, summary: ClinicianSummary) -> Result<RenderedAlert, String> {
+    let format = CHANNEL_FORMATS.with(|f| f.borrow().get(&channel.to_uppercase()).cloned())
+        .ok_or_else(|| validation::invalid("channel", &format!("no format is configured for {}", channel)))?;
+    Ok(render(&format, &summary_text(&summary)))
+}
+
+// The requester's channels, each rendered from the clinician summary, or the directive without one
+pub(crate) fn render_for(requester: Principal, summary: Option<&ClinicianSummary>, directive: &PatientDirective) -> Vec<RenderedAlert> {
+    let channels = HOSPITAL_ALERT_CHANNELS.with(|settings| settings.borrow().get(&requester).cloned().unwrap_or_default());
+    if channels.is_empty() {
+        return Vec::new();
+    }
+    let text = match summary {
+        Some(summary) => summary_text(summary),
+        None => format!("{}. {}", directive.directive_type, directive.emergency_conditions.join(". ")),
+    };
+    channels.iter()
+        .filter_map(|channel| CHANNEL_FORMATS.with(|f| f.borrow().get(channel).cloned()))
+        .map(|format| render(&format, &text))
+        .collect()
+}
+
+fn summary_text(summary: &ClinicianSummary) -> String {
+    format!("{}. {}. {}", summary.code_status, summary.restrictions, summary.proxy_contact)
+}
+
+fn render(format: &AlertChannelFormat, text: &str) -> RenderedAlert {
+    let text = if format.spell_out_abbreviations { spell_out(text) } else { text.to_string() };
+    let text = plain(&text);
+    let text = if format.uppercase { text.to_uppercase() } else { text };
+    RenderedAlert {
+        channel: format.channel.clone(),
+        text: truncate_at_word(&text, format.max_chars as usize),
+    }
+}
+
+fn spell_out(text: &str) -> String {
+    text.split(' ')
+        .map(|word| {
+            let core = word.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+            match ABBREVIATIONS.iter().find(|(abbreviation, _)| *abbreviation == core) {
+                Some((abbreviation, expansion)) => word.replacen(abbreviation, expansion, 1),
+                None => word.to_string(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// ASCII letters, digits, spaces and . , only; other separators become sentence or clause breaks
+fn plain(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '@' {
+            out.push_str(" at ");
+            continue;
+        }
+        let mapped = match c {
+            c if c.is_ascii_alphanumeric() => Some(c),
+            ' ' | '\n' | '\t' | '-' | '_' | '/' | '(' | ')' => Some(' '),
+            ':' | ';' | ',' => Some(','),
+            '.' | '!' | '?' => Some('.'),
+            '+' => Some(' '),
+            _ => None,
+        };
+        if let Some(c) = mapped {
+            out.push(c);
+        }
+    }
+    // Collapse the runs the mapping leaves behind: ", ," "  " " ." and the like
+    let mut collapsed = String::with_capacity(out.len());
+    for c in out.chars() {
+        match (collapsed.chars().last(), c) {
+            (Some(' '), ' ') | (Some(',' | '.'), ',' | '.') => {}
+            (Some(' '), ',' | '.') => {
+                collapsed.pop();
+                collapsed.push(c);
+            }
+            (Some(',' | '.'), c) if c.is_ascii_alphabetic() => {
+                collapsed.push(' ');
+                collapsed.push(c);
+            }
+            _ => collapsed.push(c),
+        }
+    }
+    collapsed.trim().trim_start_matches([',', '.']).trim().to_string()
+}
+
+fn truncate_at_word(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(max_chars).collect();
+    match cut.rfind(' ') {
+        Some(space) if space > max_chars / 2 => cut[..space].trim_end_matches([',', '.', ' ']).to_string(),
+        _ => cut,
+    }
+}
+
+fn default_formats() -> BTreeMap<String, AlertChannelFormat> {
+    [
+        ("PAGER", 120, true, false),
+        ("TTS", 400, false, true),
+        ("SMS", 160, false, false),
+    ]
+    .into_iter()
+    .map(|(channel, max_chars, uppercase, spell_out_abbreviations)| {
+        (channel.to_string(), AlertChannelFormat { channel: channel.to_string(), max_chars, uppercase, spell_out_abbreviations })
+    })
+    .collect()
+}
@@ -0,0 +1,227 @@
+use ic_cdk::{caller, Principal};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::{clock, ids, validation, ContactEvent, DIRECTIVE_MANAGER_CANISTER_ID};
+
+// Every actionable emergency_check response opens a session that the hospital closes by confirming
+// the treating clinician saw the directive and what they did. A session still open after the
+// timeout pages operators through directive_manager, and again each timeout after, up to a limit.
+// Repeat checks within one emergency reuse the open session.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CareTeamAcknowledgment {
+    pub clinician_id: String,
+    pub action_taken: String, // One of ACTIONS_TAKEN
+    pub note: Option<String>,
+    pub acknowledged_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EmergencySession {
+    pub session_id: String,
+    pub requester: Principal,
+    pub hospital_id: String,
+    pub patient_id_hash: Vec<u8>,
+    pub directive_type: String,
+    pub delivered_at: u64,
+    pub acknowledgment: Option<CareTeamAcknowledgment>,
+    pub escalations: u32,
+    pub last_escalated_at: Option<u64>,
+    pub hospice_referral_status: Option<String>, // Set for comfort-care sessions; see hospice_referrals
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AcknowledgmentPolicy {
+    pub timeout_seconds: u64,
+    pub max_escalations: u32,
+}
+
+const ACTIONS_TAKEN: [&str; 5] = ["FOLLOWED", "PARTIALLY_FOLLOWED", "OVERRIDDEN", "NOT_APPLICABLE", "DEFERRED"];
+const MAX_NOTE_BYTES: usize = 1024;
+const MAX_SESSIONS: usize = 10_000;
+// Closed sessions are kept this long for reporting, then dropped
+const CLOSED_RETENTION_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+const SWEEP_INTERVAL_SECONDS: u64 = 60;
+
+thread_local! {
+    static SESSIONS: std::cell::RefCell<BTreeMap<String, EmergencySession>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static POLICY: std::cell::RefCell<AcknowledgmentPolicy> =
+        std::cell::RefCell::new(AcknowledgmentPolicy { timeout_seconds: 10 * 60, max_escalations: 3 });
+
+    static SWEEP_TIMER_STARTED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// Called by the hospital that received the response
+#[ic_cdk::update]
+fn acknowledge_emergency_response(
+    session_id: String,
+    clinician_id: String,
+    action_taken: String,
+    note: Option<String>
+) -> Result<EmergencySession, String> {
+    let clinician_id = validation::identifier("clinician_id", &clinician_id)?;
+    let action_taken = validation::identifier("action_taken", &action_taken)?.to_uppercase();
+    if !ACTIONS_TAKEN.contains(&action_taken.as_str()) {
+        return Err(validation::invalid("action_taken", &format!("must be one of {}", ACTIONS_TAKEN.join(", "))));
+    }
+    let note = note.as_deref().map(|n| validation::text("note", n, MAX_NOTE_BYTES)).transpose()?;
+
+    SESSIONS.with(|s| {
+        let mut sessions = s.borrow_mut();
+        let session = sessions.get_mut(&session_id).ok_or_else(|| format!("Unknown emergency session: {}", session_id))?;
+        if session.requester != caller() {
+            return Err("Only the hospital that received the response may acknowledge it".to_string());
+        }
+        if session.acknowledgment.is_some() {
+            return Err("This response has already been acknowledged".to_string());
+        }
+        session.acknowledgment = Some(CareTeamAcknowledgment {
+            clinician_id,
+            action_taken,
+            note,
+            acknowledged_at: clock::now(),
+        });
+        ic_cdk::println!("AUDIT: Emergency session {} acknowledged after {} escalation(s)", session.session_id, session.escalations);
+        Ok(session.clone())
+    })
+}
+
+#[ic_cdk::query]
+fn get_emergency_session(session_id: String) -> Result<EmergencySession, String> {
+    let session = SESSIONS.with(|s| s.borrow().get(&session_id).cloned())
+        .ok_or_else(|| format!("Unknown emergency session: {}", session_id))?;
+    if session.requester != caller() && !ic_cdk::api::is_controller(&caller()) {
+        return Err("Not authorized to read this emergency session".to_string());
+    }
+    Ok(session)
+}
+
+// A hospital sees its own; controllers see every open session
+#[ic_cdk::query]
+fn get_unacknowledged_sessions() -> Vec<EmergencySession> {
+    let requester = caller();
+    let all = ic_cdk::api::is_controller(&requester);
+    SESSIONS.with(|s| {
+        s.borrow()
+            .values()
+            .filter(|session| session.acknowledgment.is_none() && (all || session.requester == requester))
+            .cloned()
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_acknowledgment_policy() -> AcknowledgmentPolicy {
+    POLICY.with(|p| p.borrow().clone())
+}
+
+#[ic_cdk::update]
+fn set_acknowledgment_policy(policy: AcknowledgmentPolicy) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may change the acknowledgment policy".to_string());
+    }
+    if policy.timeout_seconds < 60 || policy.timeout_seconds > 24 * 60 * 60 {
+        return Err(validation::invalid("timeout_seconds", "must be between 60 and 86400"));
+    }
+    if policy.max_escalations > 10 {
+        return Err(validation::invalid("max_escalations", "must be at most 10"));
+    }
+    POLICY.with(|p| *p.borrow_mut() = policy);
+    Ok(())
+}
+
+// The open session for this hospital and patient, or a new one
+pub(crate) fn open_session(requester: Principal, hospital_id: &str, patient_id_hash: &[u8], directive_type: &str) -> String {
+    let now = clock::now();
+    ensure_sweep_timer();
+    SESSIONS.with(|s| {
+        let mut sessions = s.borrow_mut();
+        let open = sessions.values().find(|session| {
+            session.acknowledgment.is_none()
+                && session.requester == requester
+                && session.patient_id_hash == patient_id_hash
+                && session.directive_type == directive_type
+        });
+        if let Some(session) = open {
+            return session.session_id.clone();
+        }
+        if sessions.len() >= MAX_SESSIONS {
+            sessions.retain(|_, session| {
+                session.acknowledgment.as_ref().is_none_or(|a| now.saturating_sub(a.acknowledged_at) < CLOSED_RETENTION_NANOS)
+            });
+        }
+        let session_id = ids::new_id("SESSION");
+        sessions.insert(session_id.clone(), EmergencySession {
+            session_id: session_id.clone(),
+            requester,
+            hospital_id: hospital_id.to_string(),
+            patient_id_hash: patient_id_hash.to_vec(),
+            directive_type: directive_type.to_string(),
+            delivered_at: now,
+            acknowledgment: None,
+            escalations: 0,
+            last_escalated_at: None,
+            hospice_referral_status: None,
+        });
+        session_id
+    })
+}
+
+pub(crate) fn note_hospice_referral(session_id: &str, status: &str) {
+    SESSIONS.with(|s| {
+        if let Some(session) = s.borrow_mut().get_mut(session_id) {
+            session.hospice_referral_status = Some(status.to_string());
+        }
+    });
+}
+
+fn ensure_sweep_timer() {
+    if SWEEP_TIMER_STARTED.with(|s| s.replace(true)) {
+        return;
+    }
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(SWEEP_INTERVAL_SECONDS), escalate_overdue);
+}
+
+// One operator page per overdue session per timeout, so a missed acknowledgment keeps resurfacing
+fn escalate_overdue() {
+    let now = clock::now();
+    let policy = POLICY.with(|p| p.borrow().clone());
+    let timeout = policy.timeout_seconds * 1_000_000_000;
+    let overdue: Vec<EmergencySession> = SESSIONS.with(|s| {
+        let mut sessions = s.borrow_mut();
+        sessions.values_mut()
+            .filter(|session| session.acknowledgment.is_none() && session.escalations < policy.max_escalations)
+            .filter(|session| now.saturating_sub(session.last_escalated_at.unwrap_or(session.delivered_at)) >= timeout)
+            .map(|session| {
+                session.escalations += 1;
+                session.last_escalated_at = Some(now);
+                session.clone()
+            })
+            .collect()
+    });
+
+    let Ok(directive_manager_id) = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID) else {
+        return;
+    };
+    for session in overdue {
+        let minutes = now.saturating_sub(session.delivered_at) / 60_000_000_000;
+        let event = ContactEvent {
+            event_type: "EMERGENCY_RESPONSE_UNACKNOWLEDGED".to_string(),
+            reference_id: session.session_id.clone(),
+            summary: format!(
+                "{} directive delivered to {} has not been acknowledged by the care team after {} minutes",
+                session.directive_type, session.hospital_id, minutes
+            ),
+            details: format!("Escalation {} of {}", session.escalations, policy.max_escalations),
+        };
+        ic_cdk::println!("⏰ UNACKNOWLEDGED: {}", event.summary);
+        if let Err(code) = ic_cdk::notify(directive_manager_id, "notify_operators", (event,)) {
+            ic_cdk::println!("⚠️ Acknowledgment escalation could not be sent: {:?}", code);
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use ic_cdk::{caller, Principal};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+use crate::versioning::EmergencyCheckRequestV2;
+use crate::{clock, fhir_client, ids, validation, PatientDirective};
+
+// When an active directive calls for comfort care, the bridge proposes the de-escalation order set
+// that goes with it: stop vasopressors, start comfort medications from the hospital's formulary.
+// The proposal is sent to the hospital's EHR as draft MedicationRequests with intent "proposal" and
+// nothing here can activate them; a clinician reviews and signs in the EHR, then records the review.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FormularyEntry {
+    pub role: String, // One of COMFORT_ROLES
+    pub rxnorm_code: String,
+    pub display: String,
+    pub dose_instruction: String, // as the hospital's order sets word it
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProposedOrder {
+    pub action: String, // "DISCONTINUE" or "INITIATE"
+    pub role: String,
+    pub rxnorm_code: String,
+    pub display: String,
+    pub dose_instruction: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct OrderSetReview {
+    pub clinician_id: String,
+    pub decision: String, // One of REVIEW_DECISIONS
+    pub note: Option<String>,
+    pub reviewed_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProposedOrderSet {
+    pub order_set_id: String,
+    pub session_id: String,
+    pub requester: Principal,
+    pub hospital_id: String,
+    pub patient_id: String,
+    pub directive_type: String,
+    pub orders: Vec<ProposedOrder>,
+    pub generated_at: u64,
+    pub ehr_status: String, // "PENDING", "DRAFTED", "FAILED", "NO_ENDPOINT"
+    pub ehr_error: Option<String>,
+    pub review: Option<OrderSetReview>,
+}
+
+const COMFORT_ROLES: [&str; 5] = ["DYSPNEA_PAIN", "ANXIETY", "SECRETIONS", "NAUSEA", "FEVER"];
+const REVIEW_DECISIONS: [&str; 3] = ["ACCEPTED", "MODIFIED", "REJECTED"];
+// (RxNorm, display)
+const VASOPRESSORS: [(&str, &str); 6] = [
+    ("7512", "norepinephrine"),
+    ("3992", "epinephrine"),
+    ("11149", "vasopressin"),
+    ("3628", "dopamine"),
+    ("8163", "phenylephrine"),
+    ("3616", "dobutamine"),
+];
+const RXNORM_SYSTEM: &str = "http://www.nlm.nih.gov/research/umls/rxnorm";
+const MAX_FORMULARY_ENTRIES: usize = 20;
+const MAX_FORMULARIES: usize = 1_000;
+const MAX_ORDER_SETS: usize = 10_000;
+const MAX_NOTE_BYTES: usize = 1024;
+
+thread_local! {
+    // Per hospital principal; hospitals without one get DEFAULT_FORMULARY
+    static FORMULARIES: std::cell::RefCell<BTreeMap<Principal, Vec<FormularyEntry>>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    static ORDER_SETS: std::cell::RefCell<BTreeMap<String, ProposedOrderSet>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+}
+
+// (role, RxNorm, display, dose instruction)
+const DEFAULT_FORMULARY: [(&str, &str, &str, &str); 5] = [
+    ("DYSPNEA_PAIN", "7052", "morphine", "2-4 mg IV every 1 hour as needed for pain or dyspnea"),
+    ("ANXIETY", "6960", "midazolam", "1-2 mg IV every 1 hour as needed for agitation"),
+    ("SECRETIONS", "4850", "glycopyrrolate", "0.2 mg IV every 4 hours as needed for secretions"),
+    ("NAUSEA", "26225", "ondansetron", "4 mg IV every 6 hours as needed for nausea"),
+    ("FEVER", "161", "acetaminophen", "650 mg PR every 6 hours as needed for fever"),
+];
+
+// Replaces the caller's mapping; an empty list restores the default
+#[ic_cdk::update]
+fn set_comfort_care_formulary(entries: Vec<FormularyEntry>) -> Result<(), String> {
+    validation::collection("entries", entries.len(), MAX_FORMULARY_ENTRIES)?;
+    let mut mapped = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let role = validation::identifier("role", &entry.role)?.to_uppercase();
+        if !COMFORT_ROLES.contains(&role.as_str()) {
+            return Err(validation::invalid("role", &format!("must be one of {}", COMFORT_ROLES.join(", "))));
+        }
+        mapped.push(FormularyEntry {
+            role,
+            rxnorm_code: validation::identifier("rxnorm_code", &entry.rxnorm_code)?,
+            display: validation::text("display", &entry.display, MAX_NOTE_BYTES)?,
+            dose_instruction: validation::text("dose_instruction", &entry.dose_instruction, MAX_NOTE_BYTES)?,
+        });
+    }
+    let hospital = caller();
+    FORMULARIES.with(|f| {
+        let mut formularies = f.borrow_mut();
+        if mapped.is_empty() {
+            formularies.remove(&hospital);
+            return Ok(());
+        }
+        if formularies.len() >= MAX_FORMULARIES && !formularies.contains_key(&hospital) {
+            return Err("Too many formularies are stored".to_string());
+        }
+        formularies.insert(hospital, mapped);
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_comfort_care_formulary() -> Vec<FormularyEntry> {
+    formulary_for(caller())
+}
+
+#[ic_cdk::query]
+fn get_proposed_order_set(order_set_id: String) -> Result<ProposedOrderSet, String> {
+    let order_set = ORDER_SETS.with(|o| o.borrow().get(&order_set_id).cloned())
+        .ok_or_else(|| format!("Unknown order set: {}", order_set_id))?;
+    let requester = caller();
+    if order_set.requester != requester && !ic_cdk::api::is_controller(&requester) {
+        return Err("Only the hospital the order set was proposed to may read it".to_string());
+    }
+    Ok(order_set)
+}
+
+// Records what the clinician did with the drafts in the EHR; it activates nothing
+#[ic_cdk::update]
+fn review_proposed_order_set(order_set_id: String, clinician_id: String, decision: String, note: Option<String>) -> Result<ProposedOrderSet, String> {
+    let clinician_id = validation::identifier("clinician_id", &clinician_id)?;
+    let decision = validation::identifier("decision", &decision)?.to_uppercase();
+    if !REVIEW_DECISIONS.contains(&decision.as_str()) {
+        return Err(validation::invalid("decision", &format!("must be one of {}", REVIEW_DECISIONS.join(", "))));
+    }
+    let note = note.as_deref().map(|n| validation::text("note", n, MAX_NOTE_BYTES)).transpose()?;
+    ORDER_SETS.with(|o| {
+        let mut order_sets = o.borrow_mut();
+        let order_set = order_sets.get_mut(&order_set_id).ok_or_else(|| format!("Unknown order set: {}", order_set_id))?;
+        if order_set.requester != caller() {
+            return Err("Only the hospital the order set was proposed to may review it".to_string());
+        }
+        if order_set.review.is_some() {
+            return Err("This order set has already been reviewed".to_string());
+        }
+        order_set.review = Some(OrderSetReview { clinician_id, decision, note, reviewed_at: clock::now() });
+        ic_cdk::println!("AUDIT: Order set {} reviewed for session {}", order_set.order_set_id, order_set.session_id);
+        Ok(order_set.clone())
+    })
+}
+
+// Proposes the order set for an actionable comfort-care response; None when the directive does not call for it
+pub(crate) fn propose(
+    requester: Principal,
+    request: &EmergencyCheckRequestV2,
+    directive: &PatientDirective,
+    session_id: &str
+) -> Option<String> {
+    if !directive.emergency_conditions.iter().any(|c| c.to_lowercase().contains("comfort care")) {
+        return None;
+    }
+    // Repeat checks within one emergency share the session, and so the proposal
+    let existing = ORDER_SETS.with(|o| {
+        o.borrow().values().find(|x| x.session_id == session_id).map(|x| x.order_set_id.clone())
+    });
+    if existing.is_some() {
+        return existing;
+    }
+    let mut orders: Vec<ProposedOrder> = VASOPRESSORS.iter().map(|(code, display)| ProposedOrder {
+        action: "DISCONTINUE".to_string(),
+        role: "VASOPRESSOR".to_string(),
+        rxnorm_code: code.to_string(),
+        display: display.to_string(),
+        dose_instruction: None,
+    }).collect();
+    orders.extend(formulary_for(requester).into_iter().map(|entry| ProposedOrder {
+        action: "INITIATE".to_string(),
+        role: entry.role,
+        rxnorm_code: entry.rxnorm_code,
+        display: entry.display,
+        dose_instruction: Some(entry.dose_instruction),
+    }));
+
+    let endpoint = fhir_client::endpoint_for(requester);
+    let order_set = ProposedOrderSet {
+        order_set_id: ids::new_id("ORDERSET"),
+        session_id: session_id.to_string(),
+        requester,
+        hospital_id: request.hospital_id.clone(),
+        patient_id: request.patient_id.clone(),
+        directive_type: directive.directive_type.clone(),
+        orders,
+        generated_at: clock::now(),
+        ehr_status: if endpoint.is_some() { "PENDING" } else { "NO_ENDPOINT" }.to_string(),
+        ehr_error: None,
+        review: None,
+    };
+    let order_set_id = order_set.order_set_id.clone();
+    let bundle = draft_bundle(&order_set);
+    ORDER_SETS.with(|o| {
+        let mut order_sets = o.borrow_mut();
+        if order_sets.len() >= MAX_ORDER_SETS {
+            // Ids sort by creation, so the first is the oldest
+            order_sets.pop_first();
+        }
+        order_sets.insert(order_set_id.clone(), order_set);
+    });
+
+    if let Some(base_url) = endpoint {
+        let id = order_set_id.clone();
+        ic_cdk::spawn(async move {
+            let result = fhir_client::post_transaction(&base_url, &bundle).await;
+            ORDER_SETS.with(|o| {
+                if let Some(order_set) = o.borrow_mut().get_mut(&id) {
+                    match result {
+                        Ok(()) => order_set.ehr_status = "DRAFTED".to_string(),
+                        Err(e) => {
+                            order_set.ehr_status = "FAILED".to_string();
+                            order_set.ehr_error = Some(e);
+                        }
+                    }
+                }
+            });
+        });
+    }
+    Some(order_set_id)
+}
+
+fn formulary_for(hospital: Principal) -> Vec<FormularyEntry> {
+    FORMULARIES.with(|f| f.borrow().get(&hospital).cloned()).unwrap_or_else(|| {
+        DEFAULT_FORMULARY.iter().map(|(role, code, display, dose)| FormularyEntry {
+            role: role.to_string(),
+            rxnorm_code: code.to_string(),
+            display: display.to_string(),
+            dose_instruction: dose.to_string(),
+        }).collect()
+    })
+}
+
+// Every entry is a draft proposal; discontinuations are proposals not to perform
+fn draft_bundle(order_set: &ProposedOrderSet) -> serde_json::Value {
+    let entries: Vec<serde_json::Value> = order_set.orders.iter().map(|order| {
+        let mut resource = json!({
+            "resourceType": "MedicationRequest",
+            "status": "draft",
+            "intent": "proposal",
+            "doNotPerform": order.action == "DISCONTINUE",
+            "identifier": [{ "system": "urn:echoledger:order-set", "value": order_set.order_set_id }],
+            "subject": { "identifier": { "value": order_set.patient_id } },
+            "medicationCodeableConcept": {
+                "coding": [{ "system": RXNORM_SYSTEM, "code": order.rxnorm_code, "display": order.display }]
+            },
+            "reasonCode": [{ "text": format!("Comfort care per {} directive", order_set.directive_type) }],
+            "note": [{ "text": "Proposed by EchoLedger for clinician review; not active until signed" }],
+        });
+        if let Some(dose) = &order.dose_instruction {
+            resource["dosageInstruction"] = json!([{ "text": dose }]);
+        }
+        json!({ "resource": resource, "request": { "method": "POST", "url": "MedicationRequest" } })
+    }).collect();
+    json!({ "resourceType": "Bundle", "type": "transaction", "entry": entries })
+}
@@ -0,0 +1,75 @@
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
+};
+use ic_cdk::{caller, Principal};
+use candid::Nat;
+use std::collections::BTreeMap;
+
+use crate::validation;
+
+// A minimal FHIR R4 client for writing back to a hospital's EHR. Each hospital principal registers
+// its own FHIR base URL; the bridge only ever POSTs transaction bundles there and reads nothing back.
+
+const OUTCALL_CYCLES: u128 = 50_000_000_000;
+const MAX_URL_BYTES: usize = 512;
+const MAX_ENDPOINTS: usize = 1_000;
+
+thread_local! {
+    static HOSPITAL_FHIR_ENDPOINTS: std::cell::RefCell<BTreeMap<Principal, String>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+}
+
+// None removes the endpoint; drafts for this hospital are then kept here only
+#[ic_cdk::update]
+fn set_hospital_fhir_endpoint(base_url: Option<String>) -> Result<(), String> {
+    let hospital = caller();
+    let Some(base_url) = base_url else {
+        HOSPITAL_FHIR_ENDPOINTS.with(|e| e.borrow_mut().remove(&hospital));
+        return Ok(());
+    };
+    let base_url = validation::text("base_url", &base_url, MAX_URL_BYTES)?;
+    if !base_url.starts_with("https://") {
+        return Err(validation::invalid("base_url", "FHIR endpoints must use HTTPS"));
+    }
+    HOSPITAL_FHIR_ENDPOINTS.with(|e| {
+        let mut endpoints = e.borrow_mut();
+        if endpoints.len() >= MAX_ENDPOINTS && !endpoints.contains_key(&hospital) {
+            return Err("Too many FHIR endpoints are registered".to_string());
+        }
+        endpoints.insert(hospital, base_url.trim_end_matches('/').to_string());
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_hospital_fhir_endpoint() -> Option<String> {
+    HOSPITAL_FHIR_ENDPOINTS.with(|e| e.borrow().get(&caller()).cloned())
+}
+
+pub(crate) fn endpoint_for(hospital: Principal) -> Option<String> {
+    HOSPITAL_FHIR_ENDPOINTS.with(|e| e.borrow().get(&hospital).cloned())
+}
+
+// Posts a transaction Bundle to the base URL, as FHIR expects for batches of writes
+pub(crate) async fn post_transaction(base_url: &str, bundle: &serde_json::Value) -> Result<(), String> {
+    let body = serde_json::to_vec(bundle).map_err(|e| format!("Failed to encode FHIR bundle: {}", e))?;
+    let request = CanisterHttpRequestArgument {
+        url: base_url.to_string(),
+        max_response_bytes: Some(16 * 1024),
+        method: HttpMethod::POST,
+        headers: vec![
+            HttpHeader { name: "Content-Type".to_string(), value: "application/fhir+json".to_string() },
+            HttpHeader { name: "Accept".to_string(), value: "application/fhir+json".to_string() },
+        ],
+        body: Some(body),
+        transform: None,
+    };
+    let (response,) = http_request(request, OUTCALL_CYCLES)
+        .await
+        .map_err(|(_, msg)| format!("FHIR request failed: {}", msg))?;
+    if response.status >= Nat::from(200u32) && response.status < Nat::from(300u32) {
+        Ok(())
+    } else {
+        Err(format!("FHIR server rejected the bundle with status {}", response.status))
+    }
+}
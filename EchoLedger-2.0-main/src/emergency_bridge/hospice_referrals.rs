@@ -0,0 +1,262 @@
+use ic_cdk::{caller, Principal};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{care_team_ack, clock, ids, validation, ClinicianSummary, PatientDirective};
+
+// A comfort-care response also refers the patient to hospice. Every active provider serving the
+// hospital gets a referral packet to collect; the first to accept takes the referral and the others
+// are withdrawn. Packets carry no patient identifiers: the provider gets the directive summary and
+// the hospital's attending contact, and the referral id is what both sides use when they talk.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct HospiceProvider {
+    pub provider_id: String,
+    pub name: String,
+    pub principal: Principal,
+    pub hospital_ids: Vec<String>, // hospitals it takes referrals from; empty for any
+    pub active: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AttendingContact {
+    pub name: String,
+    pub role: String,
+    pub phone: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReferralPacket {
+    pub hospital_id: String,
+    pub directive_type: String,
+    pub code_status: Option<String>,
+    pub restrictions: Option<String>,
+    pub attending_contact: Option<AttendingContact>,
+    pub referred_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct HospiceReferral {
+    pub referral_id: String,
+    pub session_id: String,
+    pub requester: Principal,
+    pub provider_id: String,
+    pub packet: ReferralPacket,
+    pub status: String, // "SENT", "ACCEPTED", "DECLINED", "WITHDRAWN"
+    pub note: Option<String>,
+    pub responded_at: Option<u64>,
+}
+
+const MAX_PROVIDERS: usize = 500;
+const MAX_CONTACTS: usize = 1_000;
+const MAX_REFERRALS: usize = 10_000;
+const MAX_HOSPITALS_PER_PROVIDER: usize = 100;
+const MAX_FIELD_BYTES: usize = 256;
+const MAX_NOTE_BYTES: usize = 1024;
+
+thread_local! {
+    static PROVIDERS: std::cell::RefCell<BTreeMap<String, HospiceProvider>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    // Per hospital principal
+    static ATTENDING_CONTACTS: std::cell::RefCell<BTreeMap<Principal, AttendingContact>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+
+    static REFERRALS: std::cell::RefCell<BTreeMap<String, HospiceReferral>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+}
+
+#[ic_cdk::update]
+fn register_hospice_provider(provider: HospiceProvider) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may register hospice providers".to_string());
+    }
+    let provider_id = validation::identifier("provider_id", &provider.provider_id)?.to_uppercase();
+    let name = validation::text("name", &provider.name, MAX_FIELD_BYTES)?;
+    validation::collection("hospital_ids", provider.hospital_ids.len(), MAX_HOSPITALS_PER_PROVIDER)?;
+    let hospital_ids = provider.hospital_ids.iter()
+        .map(|h| validation::identifier("hospital_ids", h))
+        .collect::<Result<Vec<_>, _>>()?;
+    PROVIDERS.with(|p| {
+        let mut providers = p.borrow_mut();
+        if providers.len() >= MAX_PROVIDERS && !providers.contains_key(&provider_id) {
+            return Err("Too many hospice providers are registered".to_string());
+        }
+        if providers.values().any(|x| x.principal == provider.principal && x.provider_id != provider_id) {
+            return Err("This principal is already registered for another hospice provider".to_string());
+        }
+        providers.insert(provider_id.clone(), HospiceProvider { provider_id, name, hospital_ids, ..provider });
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+fn set_hospice_provider_active(provider_id: String, active: bool) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage hospice providers".to_string());
+    }
+    PROVIDERS.with(|p| {
+        let mut providers = p.borrow_mut();
+        let provider = providers.get_mut(&provider_id).ok_or_else(|| format!("Unknown hospice provider: {}", provider_id))?;
+        provider.active = active;
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_hospice_providers() -> Vec<HospiceProvider> {
+    PROVIDERS.with(|p| p.borrow().values().cloned().collect())
+}
+
+// The hospital's contact for hospice teams; None removes it
+#[ic_cdk::update]
+fn set_attending_contact(contact: Option<AttendingContact>) -> Result<(), String> {
+    let hospital = caller();
+    let Some(contact) = contact else {
+        ATTENDING_CONTACTS.with(|c| c.borrow_mut().remove(&hospital));
+        return Ok(());
+    };
+    let contact = AttendingContact {
+        name: validation::text("name", &contact.name, MAX_FIELD_BYTES)?,
+        role: validation::text("role", &contact.role, MAX_FIELD_BYTES)?,
+        phone: validation::text("phone", &contact.phone, MAX_FIELD_BYTES)?,
+    };
+    ATTENDING_CONTACTS.with(|c| {
+        let mut contacts = c.borrow_mut();
+        if contacts.len() >= MAX_CONTACTS && !contacts.contains_key(&hospital) {
+            return Err("Too many attending contacts are stored".to_string());
+        }
+        contacts.insert(hospital, contact);
+        Ok(())
+    })
+}
+
+// The referring hospital sees every packet for its session
+#[ic_cdk::query]
+fn get_hospice_referrals(session_id: String) -> Vec<HospiceReferral> {
+    let requester = caller();
+    let all = ic_cdk::api::is_controller(&requester);
+    REFERRALS.with(|r| {
+        r.borrow()
+            .values()
+            .filter(|x| x.session_id == session_id && (all || x.requester == requester))
+            .cloned()
+            .collect()
+    })
+}
+
+#[ic_cdk::query]
+fn get_pending_hospice_referrals() -> Vec<HospiceReferral> {
+    let Some(provider_id) = provider_for_principal(caller()) else {
+        return Vec::new();
+    };
+    REFERRALS.with(|r| {
+        r.borrow()
+            .values()
+            .filter(|x| x.provider_id == provider_id && x.status == "SENT")
+            .cloned()
+            .collect()
+    })
+}
+
+#[ic_cdk::update]
+fn respond_to_hospice_referral(referral_id: String, accept: bool, note: Option<String>) -> Result<HospiceReferral, String> {
+    let provider_id = provider_for_principal(caller()).ok_or("Caller is not a registered hospice provider")?;
+    let note = note.as_deref().map(|n| validation::text("note", n, MAX_NOTE_BYTES)).transpose()?;
+    let now = clock::now();
+    let (referral, session_status) = REFERRALS.with(|r| {
+        let mut referrals = r.borrow_mut();
+        let referral = referrals.get_mut(&referral_id)
+            .ok_or_else(|| format!("Unknown hospice referral: {}", referral_id))?;
+        if referral.provider_id != provider_id {
+            return Err("This referral was sent to another provider".to_string());
+        }
+        if referral.status != "SENT" {
+            return Err(format!("This referral is already {}", referral.status));
+        }
+        referral.status = if accept { "ACCEPTED" } else { "DECLINED" }.to_string();
+        referral.note = note;
+        referral.responded_at = Some(now);
+        let referral = referral.clone();
+
+        let siblings = referrals.values_mut().filter(|x| x.session_id == referral.session_id && x.referral_id != referral.referral_id);
+        let session_status = if accept {
+            for sibling in siblings.filter(|x| x.status == "SENT") {
+                sibling.status = "WITHDRAWN".to_string();
+                sibling.responded_at = Some(now);
+            }
+            "ACCEPTED"
+        } else if siblings.into_iter().all(|x| x.status == "DECLINED") {
+            "ALL_DECLINED"
+        } else {
+            "REFERRED"
+        };
+        Ok((referral, session_status))
+    })?;
+    care_team_ack::note_hospice_referral(&referral.session_id, session_status);
+    ic_cdk::println!("AUDIT: Hospice referral {} {} by {}", referral.referral_id, referral.status, provider_id);
+    Ok(referral)
+}
+
+// Refers a comfort-care session to the providers serving its hospital; once per session
+pub(crate) fn refer(
+    requester: Principal,
+    hospital_id: &str,
+    directive: &PatientDirective,
+    clinician_summary: Option<&ClinicianSummary>,
+    session_id: &str
+) {
+    if REFERRALS.with(|r| r.borrow().values().any(|x| x.session_id == session_id)) {
+        return;
+    }
+    let providers: Vec<String> = PROVIDERS.with(|p| {
+        p.borrow()
+            .values()
+            .filter(|x| x.active && (x.hospital_ids.is_empty() || x.hospital_ids.iter().any(|h| h == hospital_id)))
+            .map(|x| x.provider_id.clone())
+            .collect()
+    });
+    if providers.is_empty() {
+        care_team_ack::note_hospice_referral(session_id, "NO_PROVIDER");
+        return;
+    }
+
+    let packet = ReferralPacket {
+        hospital_id: hospital_id.to_string(),
+        directive_type: directive.directive_type.clone(),
+        code_status: clinician_summary.map(|s| s.code_status.clone()),
+        restrictions: clinician_summary.map(|s| s.restrictions.clone()),
+        attending_contact: ATTENDING_CONTACTS.with(|c| c.borrow().get(&requester).cloned()),
+        referred_at: clock::now(),
+    };
+    REFERRALS.with(|r| {
+        let mut referrals = r.borrow_mut();
+        while referrals.len() + providers.len() > MAX_REFERRALS {
+            // Ids sort by creation, so the first is the oldest
+            if referrals.pop_first().is_none() {
+                break;
+            }
+        }
+        for provider_id in providers {
+            let referral_id = ids::new_id("HOSPICE");
+            referrals.insert(referral_id.clone(), HospiceReferral {
+                referral_id,
+                session_id: session_id.to_string(),
+                requester,
+                provider_id,
+                packet: packet.clone(),
+                status: "SENT".to_string(),
+                note: None,
+                responded_at: None,
+            });
+        }
+    });
+    care_team_ack::note_hospice_referral(session_id, "REFERRED");
+}
+
+fn provider_for_principal(principal: Principal) -> Option<String> {
+    PROVIDERS.with(|p| {
+        p.borrow().values().find(|x| x.active && x.principal == principal).map(|x| x.provider_id.clone())
+    })
+}
@@ -0,0 +1,133 @@
+use ic_cdk::{caller, Principal};
+use std::collections::BTreeMap;
+
+use crate::validation;
+
+// Message catalogs for emergency responses. A requester's locale comes from the request when it
+// names one, else from the hospital's saved preference, else English. Locales are matched on their
+// primary language; text without a catalog entry, such as a patient's own conditions, is left as written.
+
+pub(crate) const DEFAULT_LOCALE: &str = "en";
+const SUPPORTED_LANGUAGES: [&str; 4] = ["en", "de", "es", "fr"];
+const MAX_LOCALE_PREFERENCES: usize = 10_000;
+
+thread_local! {
+    // requesting principal -> locale its responses are written in
+    static HOSPITAL_LOCALES: std::cell::RefCell<BTreeMap<Principal, String>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+// A hospital sets its own default; None returns it to English
+#[ic_cdk::update]
+fn set_hospital_locale(locale: Option<String>) -> Result<(), String> {
+    let locale = locale.map(|l| validation::language_tag("locale", &l)).transpose()?;
+    let hospital = caller();
+    HOSPITAL_LOCALES.with(|locales| {
+        let mut locales = locales.borrow_mut();
+        match locale {
+            Some(locale) => {
+                if locales.len() >= MAX_LOCALE_PREFERENCES && !locales.contains_key(&hospital) {
+                    return Err("Too many locale preferences are stored".to_string());
+                }
+                locales.insert(hospital, locale);
+            }
+            None => {
+                locales.remove(&hospital);
+            }
+        }
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_hospital_locale() -> Option<String> {
+    HOSPITAL_LOCALES.with(|locales| locales.borrow().get(&caller()).cloned())
+}
+
+#[ic_cdk::query]
+fn get_supported_locales() -> Vec<String> {
+    SUPPORTED_LANGUAGES.iter().map(|l| l.to_string()).collect()
+}
+
+// The locale the requester asked for, explicitly or by preference; None when they never said
+pub(crate) fn requester_locale(requested: Option<&str>, requester: Principal) -> Option<String> {
+    requested.map(String::from)
+        .or_else(|| HOSPITAL_LOCALES.with(|locales| locales.borrow().get(&requester).cloned()))
+}
+
+// `key` rendered for `locale`, with each "{name}" replaced by its argument. One pass over the
+// template, so braces inside an argument are never taken for placeholders.
+pub(crate) fn render(locale: &str, key: &str, args: &[(&str, &str)]) -> String {
+    let template = template(key, language(locale)).or_else(|| template(key, DEFAULT_LOCALE)).unwrap_or(key);
+    let mut message = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        message.push_str(&rest[..start]);
+        let placeholder = rest[start + 1..].find('}').map(|end| &rest[start + 1..start + 1 + end]);
+        match placeholder.and_then(|name| args.iter().find(|(n, _)| *n == name).map(|(_, v)| (name, v))) {
+            Some((name, value)) => {
+                message.push_str(value);
+                rest = &rest[start + name.len() + 2..];
+            }
+            None => {
+                message.push('{');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    message.push_str(rest);
+    message
+}
+
+// The standard conditions directive_manager attaches are catalogued; a patient's own wording is not
+pub(crate) fn condition(locale: &str, condition: &str) -> String {
+    template(&format!("condition.{}", condition), language(locale))
+        .map_or_else(|| condition.to_string(), String::from)
+}
+
+fn language(locale: &str) -> &str {
+    locale.split('-').next().unwrap_or(locale)
+}
+
+fn template(key: &str, language: &str) -> Option<&'static str> {
+    Some(match (key, language) {
+        ("response.active", "en") => "{directive_type} directive verified on-chain. {details}",
+        ("response.active", "de") => "{directive_type}-Verfügung on-chain verifiziert. Es gilt: {conditions}",
+        ("response.active", "es") => "Directiva {directive_type} verificada en cadena. Se aplica: {conditions}",
+        ("response.active", "fr") => "Directive {directive_type} vérifiée sur la chaîne. S'applique : {conditions}",
+
+        ("response.pending", "en") => "{directive_type} directive on file but not yet active. Pending: {pending}",
+        ("response.pending", "de") => "{directive_type}-Verfügung hinterlegt, aber noch nicht wirksam. Ausstehend: {pending}",
+        ("response.pending", "es") => "Directiva {directive_type} registrada pero aún no activa. Pendiente: {pending}",
+        ("response.pending", "fr") => "Directive {directive_type} enregistrée mais pas encore active. En attente : {pending}",
+
+        ("condition.No resuscitation", "de") => "Keine Wiederbelebung",
+        ("condition.No resuscitation", "es") => "No reanimar",
+        ("condition.No resuscitation", "fr") => "Pas de réanimation",
+        ("condition.No mechanical ventilation", "de") => "Keine maschinelle Beatmung",
+        ("condition.No mechanical ventilation", "es") => "Sin ventilación mecánica",
+        ("condition.No mechanical ventilation", "fr") => "Pas de ventilation mécanique",
+        ("condition.Comfort care only", "de") => "Ausschließlich palliative Versorgung",
+        ("condition.Comfort care only", "es") => "Solo cuidados paliativos",
+        ("condition.Comfort care only", "fr") => "Soins de confort uniquement",
+        ("condition.Organ harvesting authorized", "de") => "Organentnahme erlaubt",
+        ("condition.Organ harvesting authorized", "es") => "Extracción de órganos autorizada",
+        ("condition.Organ harvesting authorized", "fr") => "Prélèvement d'organes autorisé",
+        ("condition.Contact organ network", "de") => "Organspende-Netzwerk verständigen",
+        ("condition.Contact organ network", "es") => "Contactar con la red de trasplantes",
+        ("condition.Contact organ network", "fr") => "Contacter le réseau de prélèvement",
+        ("condition.Time-sensitive coordination required", "de") => "Zeitkritische Koordination erforderlich",
+        ("condition.Time-sensitive coordination required", "es") => "Se requiere coordinación urgente",
+        ("condition.Time-sensitive coordination required", "fr") => "Coordination urgente requise",
+        ("condition.Research data sharing authorized", "de") => "Weitergabe von Forschungsdaten erlaubt",
+        ("condition.Research data sharing authorized", "es") => "Cesión de datos para investigación autorizada",
+        ("condition.Research data sharing authorized", "fr") => "Partage des données de recherche autorisé",
+        ("condition.Anonymization required", "de") => "Anonymisierung erforderlich",
+        ("condition.Anonymization required", "es") => "Anonimización obligatoria",
+        ("condition.Anonymization required", "fr") => "Anonymisation requise",
+        ("condition.Standard directive conditions apply", "de") => "Es gelten die Standardbedingungen der Verfügung",
+        ("condition.Standard directive conditions apply", "es") => "Se aplican las condiciones estándar de la directiva",
+        ("condition.Standard directive conditions apply", "fr") => "Les conditions standard de la directive s'appliquent",
+        _ => return None,
+    })
+}
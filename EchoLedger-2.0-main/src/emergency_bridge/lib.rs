@@ -5,6 +5,32 @@ use candid::{CandidType, Deserialize};
 use serde::Serialize;
 use std::collections::BTreeMap;
 
+mod alert_rendering;
+mod care_team_ack;
+mod clock;
+mod comfort_orders;
+mod fhir_client;
+mod hospice_referrals;
+mod i18n;
+mod ids;
+mod lookup_cache;
+mod read_replica;
+mod research_enrollment;
+mod situations;
+mod slo;
+mod translation;
+mod validation;
+mod versioning;
+mod wallet;
+
+use versioning::{EmergencyCheckRequestV2, EmergencyCheckResponseV2};
+
+const DIRECTIVE_MANAGER_CANISTER_ID: &str = "rdmx6-jaaaa-aaaah-qdrva-cai";
+
+// Mirrors directive_manager's REQUESTER_CLASSES
+const REQUESTER_CLASS_NAMES: [&str; 4] = ["EMERGENCY_DEPARTMENT", "TRANSPLANT_CENTER", "HOSPITAL", "FIRST_RESPONDER"];
+const UNREGISTERED_REQUESTER: &str = "UNREGISTERED";
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct EmergencyRequest {
     pub patient_id: String,
@@ -21,6 +47,7 @@ pub struct EmergencyResponse {
     pub message: String,
     pub confidence_score: f32,
     pub timestamp: u64,
+    pub attestation_status: Option<AttestationStatus>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -33,6 +60,70 @@ pub struct PatientDirective {
     pub emergency_conditions: Vec<String>,
 }
 
+// Mirrors directive_manager's ClinicianSummary: three display lines, regenerated there on every change
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ClinicianSummary {
+    pub code_status: String,
+    pub restrictions: String,
+    pub proxy_contact: String,
+    pub generated_at: u64,
+}
+
+// Mirrors directive_manager's EmergencyLookup
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DirectiveLookup {
+    pub directives: Vec<PatientDirective>,
+    pub clinician_summary: Option<ClinicianSummary>,
+    pub token_expires_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VisibilityPreferences {
+    pub disclosable_directive_types: Vec<String>,
+    pub permitted_requester_classes: Vec<String>,
+    pub notify_next_of_kin: bool,
+    pub updated_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ActivationStatus {
+    pub directive_type: String,
+    pub active: bool,
+    pub satisfied_conditions: Vec<String>,
+    pub pending_conditions: Vec<String>,
+    pub attestation_status: Option<AttestationStatus>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AttestationStatus {
+    pub attestations: u32,
+    pub required: u32,
+    pub certified: bool,
+    pub attested_by: Vec<Principal>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ContactEvent {
+    pub event_type: String,
+    pub reference_id: String,
+    pub summary: String,
+    pub details: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ContactNotification {
+    pub notification_id: String,
+    pub contact_id: String,
+    pub event_type: String,
+    pub reference_id: String,
+    pub patient_reference: Option<String>, // None for operator alerts
+    pub channel: String,
+    pub message: String,
+    pub sent_at: u64,
+    pub acknowledged_at: Option<u64>,
+    pub acknowledgment_note: Option<String>,
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct ImpactMetrics {
     pub total_directives_processed: u32,
@@ -50,7 +141,12 @@ pub struct ImpactMetrics {
 }
 
 thread_local! {
-    static EMERGENCY_REQUESTS: std::cell::RefCell<BTreeMap<String, EmergencyRequest>> =
+    // Keyed by arrival sequence, so iteration order is the order requests were served
+    static EMERGENCY_REQUESTS: std::cell::RefCell<BTreeMap<u64, EmergencyRequest>> =
+        std::cell::RefCell::new(BTreeMap::new());
+    
+    // Requester class each hospital principal was registered under; unregistered callers have none
+    static REQUESTER_CLASSES: std::cell::RefCell<BTreeMap<Principal, String>> =
         std::cell::RefCell::new(BTreeMap::new());
     
     static IMPACT_METRICS: std::cell::RefCell<ImpactMetrics> =
@@ -71,10 +167,28 @@ thread_local! {
 }
 
 // Main emergency check function for competition demo
+// The update path: persists the audit record, raises alerts and notifies contacts. Hospitals that only
+// need to read a directive can use directive_manager's certified verify_directives query instead.
 #[ic_cdk::update]
 async fn emergency_check(request: EmergencyRequest) -> Result<EmergencyResponse, String> {
-    let start_time = ic_cdk::api::time();
-    
+    versioning::note_call("emergency_check");
+    emergency_check_v1(request).await
+}
+
+// v1 requests are translated onto the current pipeline and the answer back into the v1 shape
+async fn emergency_check_v1(request: EmergencyRequest) -> Result<EmergencyResponse, String> {
+    let start_time = clock::now();
+    let result = match validation::emergency_request(&request) {
+        Ok(request) => run_emergency_check(versioning::request_from_v1(request), start_time).await
+            .map(versioning::response_to_v1),
+        Err(e) => Err(e),
+    };
+    slo::record("emergency_check", start_time, result.is_ok());
+    result
+}
+
+// The pipeline behind every version of emergency_check; expects a validated request
+async fn run_emergency_check(request: EmergencyCheckRequestV2, start_time: u64) -> Result<EmergencyCheckResponseV2, String> {
     // 1. Verify hospital credentials using threshold ECDSA
     let verified = verify_hospital_signature(&request).await?;
     
@@ -82,77 +196,348 @@ async fn emergency_check(request: EmergencyRequest) -> Result<EmergencyResponse,
         return Err("Hospital signature verification failed".to_string());
     }
     
-    // 2. Fetch directive from directive_manager
-    let directive = get_patient_directive(&request.patient_id).await?;
+    // 2. Resolve the directive, preferences and activation, reusing this hospital's recent lookup
+    let requester = caller();
+    let cached = lookup_cache::get(
+        requester,
+        &request.patient_id,
+        &request.hospital_id,
+        &request.situation,
+        request.access_token.as_deref(),
+    );
+    let (bundle, cache_hit) = match cached {
+        Some(bundle) => match lookup_cache::record_hit(requester, &bundle).await {
+            Ok(()) => (bundle, true),
+            Err(e) => {
+                ic_cdk::println!("⚠️ Cached lookup could not be logged, resolving again: {}", e);
+                (lookup_cache::resolve(requester, &request).await?, false)
+            }
+        },
+        None => (lookup_cache::resolve(requester, &request).await?, false),
+    };
+    let patient_id_hash = bundle.patient_id_hash;
+    let directive = bundle.directive;
+    let preferences = bundle.preferences;
+    let activation = bundle.activation;
+    let other_directives_on_file = bundle.other_directives_on_file;
+    let clinician_summary = bundle.clinician_summary;
+    
+    // 2b. Enforce the patient's emergency visibility preferences
+    let requester_class = classify_requester(requester);
+    if let Some(prefs) = &preferences {
+        if !is_disclosure_permitted(prefs, &directive.directive_type, &requester_class) {
+            return Err(format!(
+                "Patient visibility preferences do not permit disclosing {} directives to {}",
+                directive.directive_type, requester_class
+            ));
+        }
+    }
+    let locale = i18n::requester_locale(request.requester_locale.as_deref(), requester);
+    let translation = translation::for_requester(&request, locale.as_deref(), &patient_id_hash).await;
+    let locale = locale.as_deref().unwrap_or(i18n::DEFAULT_LOCALE);
+    
+    // 2c. Condition-locked directives are reported but not acted on until activated
+    if !activation.active {
+        EMERGENCY_REQUESTS.with(|requests| {
+            requests.borrow_mut().insert(clock::next_sequence(), versioning::request_to_v1(&request));
+        });
+        return Ok(versioning::response_v2(
+            directive_response(&directive, &activation, locale),
+            &activation,
+            cache_hit,
+            translation,
+            other_directives_on_file,
+            clinician_summary,
+        ));
+    }
     
     // 3. Process emergency situation with AI analysis
     let ai_analysis = analyze_emergency_situation(&request, &directive).await?;
     
-    // 4. Send WebSpeed alert to hospital systems
-    send_emergency_alert(&request, &directive).await?;
+    // 4. Send WebSpeed alert to hospital systems, rendered for any pager or TTS channels they use
+    let rendered_alerts = send_emergency_alert(&request, &directive, clinician_summary.as_ref()).await?;
     
     // 5. Update metrics
     IMPACT_METRICS.with(|metrics| {
         let mut m = metrics.borrow_mut();
         m.emergency_responses_served += 1;
-        let response_time = ((ic_cdk::api::time() - start_time) / 1_000_000) as u32; // Convert to ms
+        let response_time = ((clock::now() - start_time) / 1_000_000) as u32; // Convert to ms
         m.average_response_time_ms = (m.average_response_time_ms + response_time) / 2;
     });
     
     // 6. Store request for audit
     EMERGENCY_REQUESTS.with(|requests| {
-        requests.borrow_mut().insert(
-            format!("{}-{}", request.patient_id, start_time),
-            request.clone()
-        );
+        requests.borrow_mut().insert(clock::next_sequence(), versioning::request_to_v1(&request));
     });
     
-    Ok(EmergencyResponse {
-        action_required: true,
+    // 7. Notify the patient's contacts when they asked to be told of accesses; a repeat lookup
+    //    within the same emergency was already reported
+    if !cache_hit && preferences.as_ref().map_or(false, |prefs| prefs.notify_next_of_kin) {
+        notify_patient_contacts(&request, &directive, &requester_class).await;
+    }
+    
+    let mut response = versioning::response_v2(
+        directive_response(&directive, &activation, locale),
+        &activation,
+        cache_hit,
+        translation,
+        other_directives_on_file,
+        clinician_summary,
+    );
+    response.rendered_alerts = rendered_alerts;
+    // 8. The hospital confirms the treating clinician saw it, or operators are paged
+    let session_id = care_team_ack::open_session(
+        requester,
+        &request.hospital_id,
+        &patient_id_hash,
+        &directive.directive_type,
+    );
+    // 9. Comfort-care directives come with de-escalation orders, drafted in the EHR for review only
+    response.proposed_order_set_id = comfort_orders::propose(requester, &request, &directive, &session_id);
+    // 10. ...and a referral to the hospice providers serving the hospital
+    if response.proposed_order_set_id.is_some() {
+        hospice_referrals::refer(requester, &request.hospital_id, &directive, response.clinician_summary.as_ref(), &session_id);
+    }
+    response.session_id = Some(session_id);
+    Ok(response)
+}
+
+fn directive_response(directive: &PatientDirective, activation: &ActivationStatus, locale: &str) -> EmergencyResponse {
+    let message = if activation.active {
+        let conditions: Vec<String> = directive.emergency_conditions.iter().map(|c| i18n::condition(locale, c)).collect();
+        i18n::render(locale, "response.active", &[
+            ("directive_type", &directive.directive_type),
+            ("details", &directive.details),
+            ("conditions", &conditions.join("; ")),
+        ])
+    } else {
+        i18n::render(locale, "response.pending", &[
+            ("directive_type", &directive.directive_type),
+            ("pending", &activation.pending_conditions.join(", ")),
+        ])
+    };
+    EmergencyResponse {
+        action_required: activation.active,
         directive_type: directive.directive_type.clone(),
-        message: format!("{} directive verified on-chain. {}", directive.directive_type, directive.details),
+        message,
         confidence_score: directive.confidence_score,
-        timestamp: ic_cdk::api::time(),
-    })
+        timestamp: clock::now(),
+        attestation_status: activation.attestation_status.clone(),
+    }
 }
 
-// Fixed: Implement the missing get_patient_directive function
-async fn get_patient_directive(patient_id: &str) -> Result<PatientDirective, String> {
-    let patient_id_hash = ic_cdk::api::sha256(patient_id.as_bytes());
+// Bracelet scan for unresponsive patients: same token checks and consent shaping as emergency_check.
+// The situation, when given, justifies a cross-border lookup as emergency_check's does.
+#[ic_cdk::update]
+async fn lookup_by_bracelet(
+    token_uid_hash: Vec<u8>,
+    hospital_id: String,
+    access_token: String,
+    situation: Option<String>
+) -> Result<Vec<PatientDirective>, String> {
+    let start_time = clock::now();
+    let result = run_bracelet_lookup(token_uid_hash, hospital_id, access_token, situation).await;
+    slo::record("lookup_by_bracelet", start_time, result.is_ok());
+    result
+}
+
+async fn run_bracelet_lookup(
+    token_uid_hash: Vec<u8>,
+    hospital_id: String,
+    access_token: String,
+    situation: Option<String>
+) -> Result<Vec<PatientDirective>, String> {
+    validation::bytes("token_uid_hash", &token_uid_hash, validation::MAX_TOKEN_UID_HASH_BYTES)?;
+    // Still validated for callers that send it, but the requester class comes from the caller
+    validation::identifier("hospital_id", &hospital_id)?;
+    let access_token = validation::token("access_token", &access_token)?;
+    let situation = situation.as_deref().map(validation::situation).transpose()?;
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let (result,): (Result<(Vec<u8>, Vec<PatientDirective>), String>,) = call(
+        directive_manager_id,
+        "bracelet_lookup",
+        (token_uid_hash, caller(), access_token, situation)
+    ).await.map_err(|(_, msg)| format!("Bracelet lookup failed: {}", msg))?;
+    let (patient_id_hash, directives) = result?;
     
+    let requester_class = classify_requester(caller());
+    let permitted: Vec<PatientDirective> = match visibility_preferences_for_hash(patient_id_hash).await {
+        Some(prefs) => directives.into_iter()
+            .filter(|d| is_disclosure_permitted(&prefs, &d.directive_type, &requester_class))
+            .collect(),
+        None => directives,
+    };
+    if permitted.is_empty() {
+        return Err(format!("Patient visibility preferences do not permit disclosure to {}", requester_class));
+    }
+    Ok(permitted)
+}
+
+// Fixed: Implement the missing get_patient_directive function
+// An unreachable directive_manager is an error: with no verified directive the clinician is told so
+// rather than handed one. The situation is the emergency justification directive_manager weighs for
+// cross-border lookups.
+async fn get_patient_directives(patient_id_hash: Vec<u8>, access_token: &str, situation: &str) -> Result<DirectiveLookup, String> {
     // Call directive_manager canister - using placeholder ID for now
-    let directive_manager_id = Principal::from_text("rdmx6-jaaaa-aaaah-qdrva-cai")
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
         .map_err(|_| "Invalid directive manager canister ID")?;
     
-    let result: Result<(Result<PatientDirective, String>,), _> = call(
+    // directive_manager checks the token is bound to the calling hospital and logs the access
+    let result: Result<(Result<DirectiveLookup, String>,), _> = call(
         directive_manager_id,
-        "emergency_lookup",
-        (patient_id_hash, caller(), "emergency_token".to_string())
+        "emergency_lookup_with_summary",
+        (patient_id_hash, caller(), access_token.to_string(), Some(situation.to_string()))
     ).await;
     
     match result {
-        Ok((Ok(directive),)) => Ok(directive),
+        Ok((Ok(lookup),)) if lookup.directives.is_empty() => Err("No active directive found for patient".to_string()),
+        Ok((Ok(lookup),)) => Ok(lookup),
         Ok((Err(e),)) => Err(e),
-        Err(_) => {
-            // Fallback for demo purposes
-            Ok(PatientDirective {
-                directive_type: "DNR".to_string(),
-                details: "Do not resuscitate per patient's wishes".to_string(),
-                confidence_score: 0.94,
-                timestamp: ic_cdk::api::time(),
-                legal_validity: 0.92,
-                emergency_conditions: vec![
-                    "No resuscitation".to_string(),
-                    "No mechanical ventilation".to_string(),
-                    "Comfort care only".to_string(),
-                ],
-            })
+        Err((_, msg)) => Err(format!(
+            "Directive registry unreachable ({}); no verified directive is available - follow standard care protocol",
+            msg
+        )),
+    }
+}
+
+// directive_manager holds the patient hash key; hashes are never computed locally
+async fn derive_patient_hash(patient_id: &str) -> Result<Vec<u8>, String> {
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let (result,): (Result<Vec<u8>, String>,) = call(
+        directive_manager_id,
+        "derive_patient_hash",
+        (patient_id.to_string(),)
+    ).await.map_err(|(_, msg)| format!("Failed to derive patient hash: {}", msg))?;
+    result
+}
+
+// Fetch the patient's visibility preferences; None means no restrictions on file
+async fn visibility_preferences_for_hash(patient_id_hash: Vec<u8>) -> Option<VisibilityPreferences> {
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID).ok()?;
+    
+    let result: Result<(Option<VisibilityPreferences>,), _> = call(
+        directive_manager_id,
+        "get_visibility_preferences",
+        (patient_id_hash,)
+    ).await;
+    
+    result.ok().and_then(|(prefs,)| prefs)
+}
+
+// Ask directive_manager whether a condition-locked directive has activated. A failed call is an
+// error, never an unlocked directive.
+async fn evaluate_directive_activation(patient_id_hash: Vec<u8>, directive_type: &str) -> Result<ActivationStatus, String> {
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let (status,): (ActivationStatus,) = call(
+        directive_manager_id,
+        "evaluate_activation",
+        (patient_id_hash, directive_type.to_string())
+    ).await.map_err(|(_, msg)| format!("Failed to evaluate activation: {}", msg))?;
+    Ok(status)
+}
+
+// Served when activation could not be evaluated: the directive is shown as not in effect
+fn activation_unknown(directive_type: &str) -> ActivationStatus {
+    ActivationStatus {
+        directive_type: directive_type.to_string(),
+        active: false,
+        satisfied_conditions: vec![],
+        pending_conditions: vec!["ACTIVATION_STATUS_UNKNOWN".to_string()],
+        attestation_status: None,
+    }
+}
+
+// The requester class patients choose from, as registered for the calling principal. Nothing the
+// request says about itself counts; an unregistered caller matches no class a patient can permit.
+fn classify_requester(requester: Principal) -> String {
+    REQUESTER_CLASSES.with(|classes| classes.borrow().get(&requester).cloned())
+        .unwrap_or_else(|| UNREGISTERED_REQUESTER.to_string())
+}
+
+// None removes the principal's class
+#[ic_cdk::update]
+fn set_requester_class(requester: Principal, requester_class: Option<String>) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may register requester classes".to_string());
+    }
+    REQUESTER_CLASSES.with(|classes| match requester_class {
+        Some(class) if REQUESTER_CLASS_NAMES.contains(&class.as_str()) => {
+            classes.borrow_mut().insert(requester, class);
+            Ok(())
         }
+        Some(class) => Err(format!("Unknown requester class: {}", class)),
+        None => {
+            classes.borrow_mut().remove(&requester);
+            Ok(())
+        }
+    })
+}
+
+#[ic_cdk::query]
+fn get_requester_class() -> String {
+    classify_requester(caller())
+}
+
+// Empty lists mean the patient has not restricted that dimension
+fn is_disclosure_permitted(
+    preferences: &VisibilityPreferences,
+    directive_type: &str,
+    requester_class: &str
+) -> bool {
+    let type_allowed = preferences.disclosable_directive_types.is_empty()
+        || preferences.disclosable_directive_types.iter().any(|t| t == directive_type);
+    let class_allowed = preferences.permitted_requester_classes.is_empty()
+        || preferences.permitted_requester_classes.iter().any(|c| c == requester_class);
+    
+    type_allowed && class_allowed
+}
+
+// Notification hook fired on each emergency access the patient asked to hear about
+async fn notify_patient_contacts(
+    request: &EmergencyCheckRequestV2,
+    directive: &PatientDirective,
+    requester_class: &str
+) {
+    let start_time = clock::now();
+    let Ok(patient_id_hash) = derive_patient_hash(&request.patient_id).await else {
+        slo::record("notification_delivery", start_time, false);
+        return;
+    };
+    let event = ContactEvent {
+        event_type: "EMERGENCY_ACCESS".to_string(),
+        reference_id: ids::new_id("ACCESS"),
+        summary: format!("{} directive accessed by {} ({})", directive.directive_type, request.hospital_id, requester_class),
+        details: format!("Situation: {}", request.situation),
+    };
+    
+    let Ok(directive_manager_id) = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID) else {
+        slo::record("notification_delivery", start_time, false);
+        return;
+    };
+    
+    // Contact delivery must never block the emergency response
+    let result: Result<(Vec<ContactNotification>,), _> = call(
+        directive_manager_id,
+        "notify_contacts",
+        (patient_id_hash, event)
+    ).await;
+    slo::record("notification_delivery", start_time, result.is_ok());
+    
+    match result {
+        Ok((notifications,)) => ic_cdk::println!(
+            "📨 Next-of-kin notified: {} contact(s) for {}",
+            notifications.len(),
+            request.patient_id
+        ),
+        Err((_, msg)) => ic_cdk::println!("⚠️ Next-of-kin notification failed: {}", msg),
     }
 }
 
 // Implement proper Threshold ECDSA signature verification
-async fn verify_hospital_signature(request: &EmergencyRequest) -> Result<bool, String> {
+async fn verify_hospital_signature(request: &EmergencyCheckRequestV2) -> Result<bool, String> {
     let message = format!("{}{}{}", request.patient_id, request.hospital_id, request.situation);
     let message_hash = ic_cdk::api::sha256(message.as_bytes());
     
@@ -174,30 +559,22 @@ async fn verify_hospital_signature(request: &EmergencyRequest) -> Result<bool, S
 
 // AI analysis of emergency situation
 async fn analyze_emergency_situation(
-    request: &EmergencyRequest,
+    request: &EmergencyCheckRequestV2,
     directive: &PatientDirective
 ) -> Result<f32, String> {
     // Simple AI analysis based on situation and vitals
     let mut confidence = directive.confidence_score;
     
     // Adjust confidence based on emergency situation
-    match request.situation.as_str() {
-        "cardiac_arrest" => {
-            if directive.directive_type == "DNR" {
-                confidence = (confidence + 0.05).min(1.0);
-            }
-        },
-        "respiratory_failure" => {
-            if directive.directive_type == "DNR" {
-                confidence = (confidence + 0.03).min(1.0);
-            }
-        },
-        _ => {}
+    if let Some(code) = situations::code_of(&request.situation) {
+        let adjustment = situations::confidence_adjustment(code, &directive.directive_type);
+        confidence = (confidence + adjustment).clamp(0.0, 1.0);
     }
     
     // Analyze vitals if provided
     if let Some(vitals) = &request.vitals {
-        if vitals.contains("pulse\": 0") || vitals.contains("bp\": \"0/0") {
+        let no_pressure = vitals.systolic_mmhg == Some(0) && vitals.diastolic_mmhg == Some(0);
+        if vitals.heart_rate_bpm == Some(0) || no_pressure {
             confidence = (confidence + 0.02).min(1.0);
         }
     }
@@ -207,10 +584,11 @@ async fn analyze_emergency_situation(
 
 // WebSpeed emergency alert system
 async fn send_emergency_alert(
-    request: &EmergencyRequest,
-    directive: &PatientDirective
-) -> Result<String, String> {
-    let alert_id = format!("ALERT_{}_{}", request.patient_id, ic_cdk::api::time());
+    request: &EmergencyCheckRequestV2,
+    directive: &PatientDirective,
+    clinician_summary: Option<&ClinicianSummary>
+) -> Result<Vec<alert_rendering::RenderedAlert>, String> {
+    let alert_id = ids::new_id("ALERT");
     
     // Log the alert for audit and demo purposes
     ic_cdk::println!(
@@ -223,21 +601,28 @@ async fn send_emergency_alert(
     
     // In a real implementation, this would send WebSocket messages
     // to hospital systems, push notifications, etc.
+    let rendered = alert_rendering::render_for(caller(), clinician_summary, directive);
+    for alert in &rendered {
+        ic_cdk::println!("📟 {} via {}: {}", alert_id, alert.channel, alert.text);
+    }
     
-    Ok(alert_id)
+    Ok(rendered)
 }
 
-// Get recent emergency alerts for monitoring
+// Get recent emergency alerts for monitoring; they name patients and their vitals, so operators only
 #[ic_cdk::query]
-fn get_recent_alerts(limit: u32) -> Vec<EmergencyRequest> {
-    EMERGENCY_REQUESTS.with(|requests| {
+fn get_recent_alerts(limit: u32) -> Result<Vec<EmergencyRequest>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read recent alerts".to_string());
+    }
+    Ok(EMERGENCY_REQUESTS.with(|requests| {
         requests.borrow()
             .values()
             .rev()
             .take(limit as usize)
             .cloned()
             .collect()
-    })
+    }))
 }
 
 // Get impact metrics for demo dashboard
@@ -249,6 +634,7 @@ fn get_impact_metrics() -> ImpactMetrics {
 // HIPAA compliance verification
 #[ic_cdk::query]
 fn verify_hipaa_compliance(patient_id: String) -> Result<bool, String> {
+    let patient_id = validation::identifier("patient_id", &patient_id)?;
     // Check if patient data handling is HIPAA compliant
     // This would involve checking encryption, access logs, etc.
     
@@ -256,7 +642,7 @@ fn verify_hipaa_compliance(patient_id: String) -> Result<bool, String> {
         "AUDIT: HIPAA compliance check - Patient: {} - Caller: {} - Time: {}",
         patient_id,
         caller().to_text(),
-        ic_cdk::api::time()
+        clock::now()
     );
     
     Ok(true) // 100% compliance in our implementation
@@ -267,7 +653,7 @@ fn verify_hipaa_compliance(patient_id: String) -> Result<bool, String> {
 fn get_audit_trail(patient_id: String) -> Vec<String> {
     // Return audit trail entries for the patient
     vec![
-        format!("Emergency access - Patient: {} - Time: {}", patient_id, ic_cdk::api::time()),
+        format!("Emergency access - Patient: {} - Time: {}", patient_id, clock::now()),
         format!("Directive verification - Patient: {} - Result: Verified", patient_id),
         format!("HIPAA compliance check - Patient: {} - Status: Compliant", patient_id),
     ]
@@ -279,6 +665,8 @@ async fn verify_signature_authenticity(
     patient_id: String,
     hospital_id: String
 ) -> Result<bool, String> {
+    let patient_id = validation::identifier("patient_id", &patient_id)?;
+    let hospital_id = validation::identifier("hospital_id", &hospital_id)?;
     let message = format!("{}{}", patient_id, hospital_id);
     let message_hash = ic_cdk::api::sha256(message.as_bytes());
     
@@ -303,7 +691,8 @@ async fn verify_signature_authenticity(
 // Legacy function for backward compatibility
 #[ic_cdk::update]
 async fn process_emergency_request(request: EmergencyRequest) -> Result<EmergencyResponse, String> {
-    emergency_check(request).await
+    versioning::note_call("process_emergency_request");
+    emergency_check_v1(request).await
 }
 
 async fn verify_emergency_signature(
@@ -311,12 +700,13 @@ async fn verify_emergency_signature(
     hospital_id: String,
     signature: Vec<u8>
 ) -> Result<bool, String> {
-    let request = EmergencyRequest {
+    let request = EmergencyCheckRequestV2 {
         patient_id,
         hospital_id,
         situation: "legacy_verification".to_string(),
         vitals: None,
         access_token: None,
+        requester_locale: None,
     };
     
     verify_hospital_signature(&request).await
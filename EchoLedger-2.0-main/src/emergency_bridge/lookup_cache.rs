@@ -0,0 +1,220 @@
+use ic_cdk::{call, caller, Principal};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{clock, i18n, read_replica, situations, validation};
+use crate::{
+    activation_unknown, classify_requester, derive_patient_hash, directive_response, evaluate_directive_activation,
+    get_patient_directives, is_disclosure_permitted, visibility_preferences_for_hash, ActivationStatus,
+    ClinicianSummary, EmergencyCheckRequestV2, EmergencyRequest, EmergencyResponse, PatientDirective, VisibilityPreferences, DIRECTIVE_MANAGER_CANISTER_ID,
+};
+
+// Everything emergency_check resolved for one hospital's lookup of one patient
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CachedBundle {
+    pub patient_id_hash: Vec<u8>,
+    pub hospital_id: String,
+    pub situation: String,
+    pub directive: PatientDirective, // The first directive on file that applies to the situation
+    pub other_directives_on_file: u32,
+    pub clinician_summary: Option<ClinicianSummary>,
+    pub preferences: Option<VisibilityPreferences>,
+    pub activation: ActivationStatus,
+    pub cached_at: u64,
+    pub token_hash: Vec<u8>, // Only the token that resolved the bundle may read it back
+    pub token_expires_at: Option<u64>,
+}
+
+// Mirrors directive_manager's VerificationReceipt
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+struct VerificationReceipt {
+    receipt_id: String,
+    patient_id_hash: Vec<u8>,
+    requester: Principal,
+    token_hash: Vec<u8>,
+    directive_types: Vec<String>,
+    verified_at: u64,
+}
+
+thread_local! {
+    // (requesting principal, patient_id) -> bundle; a hospital only ever reuses its own token-checked lookups
+    static LOOKUP_CACHE: std::cell::RefCell<BTreeMap<(Principal, String), CachedBundle>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+// Long enough to cover the repeat checks of a single emergency, short enough that
+// a missed invalidation cannot keep serving a stale directive for long
+const LOOKUP_CACHE_TTL_NANOS: u64 = 2 * 60 * 1_000_000_000;
+const MAX_CACHED_BUNDLES: usize = 1_000;
+// Take effect only on certified incapacity, which directive_manager alone can vouch for
+const ATTESTED_DIRECTIVE_TYPES: [&str; 1] = ["LIVING_WILL"];
+
+// Repeat lookup as a query. Only a bundle this caller resolved through emergency_check with the same
+// token, where the token was checked and the access logged, is served; anything else is a miss.
+#[ic_cdk::query]
+fn emergency_check_cached(request: EmergencyRequest) -> Result<EmergencyResponse, String> {
+    let request = validation::emergency_request(&request)?;
+    let bundle = get(caller(), &request.patient_id, &request.hospital_id, &request.situation, request.access_token.as_deref())
+        .ok_or("No recent lookup for this patient; call emergency_check")?;
+
+    let requester_class = classify_requester(caller());
+    if let Some(prefs) = &bundle.preferences {
+        if !is_disclosure_permitted(prefs, &bundle.directive.directive_type, &requester_class) {
+            return Err(format!(
+                "Patient visibility preferences do not permit disclosing {} directives to {}",
+                bundle.directive.directive_type, requester_class
+            ));
+        }
+    }
+    let locale = i18n::requester_locale(None, caller());
+    Ok(directive_response(&bundle.directive, &bundle.activation, locale.as_deref().unwrap_or(i18n::DEFAULT_LOCALE)))
+}
+
+// directive_manager drops bundles when a patient's directives or preferences change, or a hospital's token is revoked
+#[ic_cdk::update]
+fn invalidate_lookup_cache(patient_id_hash: Option<Vec<u8>>, requester: Option<Principal>) -> Result<u32, String> {
+    let directive_manager = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID).ok();
+    if Some(caller()) != directive_manager && !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only directive_manager may invalidate cached lookups".to_string());
+    }
+    Ok(LOOKUP_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let before = cache.len();
+        cache.retain(|(principal, _), bundle| {
+            Some(&bundle.patient_id_hash) != patient_id_hash.as_ref() && Some(*principal) != requester
+        });
+        (before - cache.len()) as u32
+    }))
+}
+
+// A different situation can select a different directive, so it misses too, as does a different
+// token or one that has expired since the bundle was resolved
+pub(crate) fn get(
+    requester: Principal,
+    patient_id: &str,
+    hospital_id: &str,
+    situation: &str,
+    access_token: Option<&str>
+) -> Option<CachedBundle> {
+    let token_hash = ic_cdk::api::sha256(access_token?.as_bytes());
+    let now = clock::now();
+    LOOKUP_CACHE.with(|cache| {
+        cache.borrow()
+            .get(&(requester, patient_id.to_string()))
+            .filter(|bundle| bundle.hospital_id == hospital_id && bundle.situation == situation)
+            .filter(|bundle| bundle.token_hash == token_hash && bundle.token_expires_at.is_some_and(|at| now <= at))
+            .filter(|bundle| now.saturating_sub(bundle.cached_at) < LOOKUP_CACHE_TTL_NANOS)
+            .cloned()
+    })
+}
+
+// A hit served by an update is still a disclosure, so directive_manager logs it through the same
+// receipt path hospitals use for query reads. A hit that cannot be logged is not served.
+pub(crate) async fn record_hit(requester: Principal, bundle: &CachedBundle) -> Result<(), String> {
+    let directive_manager = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let verified_at = clock::now();
+    let mut material = requester.as_slice().to_vec();
+    material.extend_from_slice(&bundle.patient_id_hash);
+    material.extend_from_slice(&verified_at.to_be_bytes());
+    material.extend_from_slice(&clock::next_sequence().to_be_bytes());
+    let receipt = VerificationReceipt {
+        receipt_id: ic_cdk::api::sha256(&material).iter().map(|b| format!("{:02x}", b)).collect(),
+        patient_id_hash: bundle.patient_id_hash.clone(),
+        requester,
+        token_hash: bundle.token_hash.clone(),
+        directive_types: vec![bundle.directive.directive_type.clone()],
+        verified_at,
+    };
+    let result: Result<(Result<u32, String>,), _> =
+        call(directive_manager, "record_verification_receipts", (vec![receipt],)).await;
+    match result {
+        Ok((Ok(_),)) => Ok(()),
+        Ok((Err(e),)) => Err(e),
+        Err((_, msg)) => Err(format!("Directive registry unreachable: {}", msg)),
+    }
+}
+
+// One call to a local read replica when it can answer, otherwise the full round of directive_manager
+// calls; the result is cached unless activation could not be evaluated
+pub(crate) async fn resolve(requester: Principal, request: &EmergencyCheckRequestV2) -> Result<CachedBundle, String> {
+    let access_token = request.access_token.as_deref().ok_or("An emergency access token is required")?;
+    let replicated = read_replica::lookup(&request.patient_id, requester, access_token).await
+        .filter(|lookup| !lookup.directives.is_empty());
+    let from_replica = replicated.is_some();
+    let (patient_id_hash, directives, clinician_summary, preferences, token_expires_at) = match replicated {
+        Some(lookup) => (lookup.patient_id_hash, lookup.directives, lookup.clinician_summary, lookup.preferences, lookup.token_expires_at),
+        None => {
+            let patient_id_hash = derive_patient_hash(&request.patient_id).await?;
+            let lookup = get_patient_directives(patient_id_hash.clone(), access_token, &request.situation).await?;
+            let preferences = visibility_preferences_for_hash(patient_id_hash.clone()).await;
+            (patient_id_hash, lookup.directives, lookup.clinician_summary, preferences, lookup.token_expires_at)
+        }
+    };
+
+    let (applicable, other_directives_on_file) = situations::split_applicable(&request.situation, directives);
+    let directive = applicable.into_iter().next().ok_or_else(|| format!(
+        "No directive on file applies to {} ({} other directive(s) on file)",
+        request.situation, other_directives_on_file
+    ))?;
+    // Replicas cannot show a living will's attestation, so those are always evaluated by the primary
+    let (activation, activation_known) = if from_replica && !needs_attestation(&directive.directive_type) {
+        // Replicas only hold directives the primary found active
+        (ActivationStatus {
+            directive_type: directive.directive_type.clone(),
+            active: true,
+            satisfied_conditions: vec![],
+            pending_conditions: vec![],
+            attestation_status: None,
+        }, true)
+    } else {
+        match evaluate_directive_activation(patient_id_hash.clone(), &directive.directive_type).await {
+            Ok(activation) if needs_attestation(&activation.directive_type)
+                && !activation.attestation_status.as_ref().is_some_and(|a| a.certified) => {
+                // A living will is never in effect without certified incapacity, whatever the status says
+                (ActivationStatus { active: false, ..activation }, true)
+            }
+            Ok(activation) => (activation, true),
+            Err(e) => {
+                ic_cdk::println!("⚠️ {}", e);
+                (activation_unknown(&directive.directive_type), false)
+            }
+        }
+    };
+
+    let now = clock::now();
+    let bundle = CachedBundle {
+        patient_id_hash,
+        hospital_id: request.hospital_id.clone(),
+        situation: request.situation.clone(),
+        directive,
+        other_directives_on_file,
+        clinician_summary,
+        preferences,
+        activation,
+        cached_at: now,
+        token_hash: ic_cdk::api::sha256(access_token.as_bytes()),
+        token_expires_at,
+    };
+    if activation_known {
+        store(requester, &request.patient_id, bundle.clone());
+    }
+    Ok(bundle)
+}
+
+// Expired bundles are swept as new ones arrive; past the cap a lookup simply goes uncached
+pub(crate) fn store(requester: Principal, patient_id: &str, bundle: CachedBundle) {
+    let now = clock::now();
+    LOOKUP_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        cache.retain(|_, b| now.saturating_sub(b.cached_at) < LOOKUP_CACHE_TTL_NANOS);
+        if cache.len() < MAX_CACHED_BUNDLES {
+            cache.insert((requester, patient_id.to_string()), bundle);
+        }
+    });
+}
+
+fn needs_attestation(directive_type: &str) -> bool {
+    ATTESTED_DIRECTIVE_TYPES.contains(&directive_type)
+}
@@ -0,0 +1,74 @@
+use ic_cdk::{call, caller, Principal};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+use crate::{clock, ClinicianSummary, PatientDirective, VisibilityPreferences};
+
+// A bridge deployed next to a directive_manager read replica asks it first. Anything the replica
+// cannot answer, or an answer older than the staleness bound, goes to the primary as before, so a
+// replica miss is never a refusal.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReadReplicaRoute {
+    pub canister: Principal,
+    pub max_staleness_seconds: u64,
+}
+
+// Mirrors directive_manager's ReplicaLookup
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReplicaLookup {
+    pub patient_id_hash: Vec<u8>,
+    pub directives: Vec<PatientDirective>,
+    pub preferences: Option<VisibilityPreferences>,
+    pub clinician_summary: Option<ClinicianSummary>,
+    pub synced_at: u64,
+    pub token_expires_at: Option<u64>,
+}
+
+thread_local! {
+    static READ_REPLICA: std::cell::RefCell<Option<ReadReplicaRoute>> = const { std::cell::RefCell::new(None) };
+}
+
+const MAX_STALENESS_SECONDS: u64 = 10 * 60;
+
+#[ic_cdk::update]
+fn set_directive_read_replica(route: Option<ReadReplicaRoute>) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may route lookups to a read replica".to_string());
+    }
+    if let Some(route) = &route {
+        if route.max_staleness_seconds == 0 || route.max_staleness_seconds > MAX_STALENESS_SECONDS {
+            return Err(format!("max_staleness_seconds must be between 1 and {}", MAX_STALENESS_SECONDS));
+        }
+    }
+    READ_REPLICA.with(|r| *r.borrow_mut() = route);
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_directive_read_replica() -> Option<ReadReplicaRoute> {
+    READ_REPLICA.with(|r| r.borrow().clone())
+}
+
+// None whenever the primary should be asked instead
+pub(crate) async fn lookup(patient_id: &str, requester: Principal, access_token: &str) -> Option<ReplicaLookup> {
+    let route = READ_REPLICA.with(|r| r.borrow().clone())?;
+    let result: Result<(Result<ReplicaLookup, String>,), _> = call(
+        route.canister,
+        "replica_emergency_lookup",
+        (patient_id.to_string(), requester, access_token.to_string())
+    ).await;
+    let lookup = match result {
+        Ok((Ok(lookup),)) => lookup,
+        Ok((Err(e),)) => {
+            ic_cdk::println!("Read replica could not answer, asking the primary: {}", e);
+            return None;
+        }
+        Err((_, msg)) => {
+            ic_cdk::println!("Read replica unreachable, asking the primary: {}", msg);
+            return None;
+        }
+    };
+    let age = clock::now().saturating_sub(lookup.synced_at);
+    (age <= route.max_staleness_seconds * 1_000_000_000).then_some(lookup)
+}
@@ -0,0 +1,121 @@
+use ic_cdk::{call, caller, Principal};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{clock, validation, DIRECTIVE_MANAGER_CANISTER_ID};
+
+// Research coordinators of emergency studies run under an exception from informed consent check
+// here whether a patient refused, or pre-consented to, enrollment in that kind of study. Only the
+// patient's own wishes are answered; the study's other EFIC criteria stay with the coordinator.
+// Coordinators are registered per study with the trial categories its protocol covers.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ResearchCoordinator {
+    pub principal: Principal,
+    pub study_id: String,
+    pub protocol_reference: String, // IRB / ethics approval for the EFIC protocol
+    pub categories: Vec<String>,
+    pub active: bool,
+}
+
+// Mirrors directive_manager's EnrollmentDecision
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EnrollmentDecision {
+    pub category: String,
+    pub decision: String,
+    pub basis: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TrialEnrollmentCheck {
+    pub study_id: String,
+    pub category: String,
+    pub decision: String, // "CONSENT", "REFUSE" or "NO_PREFERENCE"
+    pub basis: String,    // "CATEGORY", "DEFAULT" or "NONE_ON_FILE"
+    pub enrollment_permitted: bool, // false only when the patient refused this category
+    pub checked_at: u64,
+}
+
+const MAX_COORDINATORS: usize = 1_000;
+const MAX_CATEGORIES: usize = 16;
+
+thread_local! {
+    static COORDINATORS: std::cell::RefCell<BTreeMap<Principal, ResearchCoordinator>> =
+        const { std::cell::RefCell::new(BTreeMap::new()) };
+}
+
+#[ic_cdk::update]
+fn register_research_coordinator(coordinator: ResearchCoordinator) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may register research coordinators".to_string());
+    }
+    let study_id = validation::identifier("study_id", &coordinator.study_id)?;
+    let protocol_reference = validation::identifier("protocol_reference", &coordinator.protocol_reference)?;
+    validation::collection("categories", coordinator.categories.len(), MAX_CATEGORIES)?;
+    let categories = coordinator.categories.iter()
+        .map(|c| validation::identifier("categories", c).map(|c| c.to_uppercase()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if categories.is_empty() {
+        return Err(validation::invalid("categories", "a study must cover at least one trial category"));
+    }
+    COORDINATORS.with(|c| {
+        let mut coordinators = c.borrow_mut();
+        if coordinators.len() >= MAX_COORDINATORS && !coordinators.contains_key(&coordinator.principal) {
+            return Err("Too many research coordinators are registered".to_string());
+        }
+        coordinators.insert(coordinator.principal, ResearchCoordinator { study_id, protocol_reference, categories, ..coordinator });
+        Ok(())
+    })
+}
+
+#[ic_cdk::update]
+fn set_research_coordinator_active(principal: Principal, active: bool) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage research coordinators".to_string());
+    }
+    COORDINATORS.with(|c| {
+        let mut coordinators = c.borrow_mut();
+        let coordinator = coordinators.get_mut(&principal).ok_or("Unknown research coordinator")?;
+        coordinator.active = active;
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_research_coordinators() -> Result<Vec<ResearchCoordinator>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may list research coordinators".to_string());
+    }
+    Ok(COORDINATORS.with(|c| c.borrow().values().cloned().collect()))
+}
+
+// Composite, so the directive_manager read stays a query end to end
+#[ic_cdk::query(composite = true)]
+async fn check_trial_enrollment(patient_id: String, category: String) -> Result<TrialEnrollmentCheck, String> {
+    let patient_id = validation::identifier("patient_id", &patient_id)?;
+    let category = validation::identifier("category", &category)?.to_uppercase();
+    let coordinator = COORDINATORS.with(|c| c.borrow().get(&caller()).filter(|x| x.active).cloned())
+        .ok_or("Caller is not a registered research coordinator")?;
+    if !coordinator.categories.contains(&category) {
+        return Err(format!("Study {} is not registered for {} trials", coordinator.study_id, category));
+    }
+
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let (result,): (Result<EnrollmentDecision, String>,) = call(
+        directive_manager_id,
+        "research_enrollment_decision",
+        (patient_id, category)
+    ).await.map_err(|(_, msg)| format!("Enrollment check failed: {}", msg))?;
+    let decision = result?;
+
+    Ok(TrialEnrollmentCheck {
+        study_id: coordinator.study_id,
+        enrollment_permitted: decision.decision != "REFUSE",
+        category: decision.category,
+        decision: decision.decision,
+        basis: decision.basis,
+        checked_at: clock::now(),
+    })
+}
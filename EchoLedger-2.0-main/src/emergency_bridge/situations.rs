@@ -0,0 +1,245 @@
+use ic_cdk::caller;
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{validation, PatientDirective};
+
+// Coded emergency situations. Requests still carry `situation` as text; intake turns it into one of
+// these codes and rejects anything it cannot place, so a typo fails loudly instead of silently
+// skipping the situation's handling. Anything outside the taxonomy goes in as "other: <description>".
+// What each situation changes lives in SITUATION_HANDLING, not in match arms: its confidence
+// adjustments and which directive types are relevant to it at all (organ donation consent has
+// nothing to say during an asthma attack). Directives outside that list are only counted.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SituationCode {
+    CardiacArrest,
+    RespiratoryFailure,
+    BrainDeath,
+    Stroke,
+    MajorTrauma,
+    Sepsis,
+    Overdose,
+    Anaphylaxis,
+    AsthmaAttack,
+    Seizure,
+    TerminalDecline,
+    Unspecified,
+    Other,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SituationInfo {
+    pub code: SituationCode,
+    pub text_code: String,
+    pub description: String,
+    pub aliases: Vec<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ConfidenceAdjustment {
+    pub directive_type: String,
+    pub delta: f32,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SituationHandling {
+    pub code: SituationCode,
+    pub confidence_adjustments: Vec<ConfidenceAdjustment>,
+    pub applicable_directive_types: Option<Vec<String>>, // None: every directive type applies
+}
+
+// (code, text code, description, accepted aliases)
+const TAXONOMY: [(SituationCode, &str, &str, &[&str]); 13] = [
+    (SituationCode::CardiacArrest, "cardiac_arrest", "Cardiac arrest", &["cardiopulmonary_arrest", "code_blue"]),
+    (SituationCode::RespiratoryFailure, "respiratory_failure", "Respiratory failure", &["respiratory_arrest"]),
+    (SituationCode::BrainDeath, "brain_death", "Brain death", &["death_by_neurologic_criteria"]),
+    (SituationCode::Stroke, "stroke", "Stroke", &["cva", "cerebrovascular_accident"]),
+    (SituationCode::MajorTrauma, "major_trauma", "Major trauma", &["trauma", "polytrauma"]),
+    (SituationCode::Sepsis, "sepsis", "Sepsis or septic shock", &["septic_shock"]),
+    (SituationCode::Overdose, "overdose", "Drug overdose or poisoning", &["poisoning"]),
+    (SituationCode::Anaphylaxis, "anaphylaxis", "Anaphylaxis", &["anaphylactic_shock"]),
+    (SituationCode::AsthmaAttack, "asthma_attack", "Acute asthma attack", &["asthma", "status_asthmaticus"]),
+    (SituationCode::Seizure, "seizure", "Seizure", &["status_epilepticus"]),
+    (SituationCode::TerminalDecline, "terminal_decline", "Decline from a known terminal illness", &["end_of_life"]),
+    (SituationCode::Unspecified, "unspecified", "Emergency not yet characterised", &["emergency"]),
+    (SituationCode::Other, "other", "Outside the taxonomy; described in free text", &[]),
+];
+
+const MAX_ADJUSTMENT: f32 = 0.25;
+const MAX_APPLICABLE_TYPES: usize = 32;
+
+const RESUSCITATION: [&str; 2] = ["DNR", "LIVING_WILL"];
+const END_OF_LIFE: [&str; 4] = ["DNR", "LIVING_WILL", "ORGAN_DONATION", "TISSUE_DONATION"];
+const AFTER_DEATH: [&str; 4] = ["ORGAN_DONATION", "TISSUE_DONATION", "LIVING_WILL", "DATA_CONSENT"];
+
+thread_local! {
+    static SITUATION_HANDLING: std::cell::RefCell<BTreeMap<SituationCode, SituationHandling>> =
+        std::cell::RefCell::new(default_handling());
+}
+
+#[ic_cdk::query]
+fn get_situation_taxonomy() -> Vec<SituationInfo> {
+    TAXONOMY.iter()
+        .map(|(code, text_code, description, aliases)| SituationInfo {
+            code: *code,
+            text_code: text_code.to_string(),
+            description: description.to_string(),
+            aliases: aliases.iter().map(|a| a.to_string()).collect(),
+        })
+        .collect()
+}
+
+#[ic_cdk::query]
+fn get_situation_handling() -> Vec<SituationHandling> {
+    SITUATION_HANDLING.with(|h| h.borrow().values().cloned().collect())
+}
+
+// Replaces one situation's row; an empty adjustment list clears its adjustments
+#[ic_cdk::update]
+fn set_situation_handling(handling: SituationHandling) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may change situation handling".to_string());
+    }
+    for adjustment in &handling.confidence_adjustments {
+        validation::identifier("confidence_adjustments.directive_type", &adjustment.directive_type)?;
+        if !adjustment.delta.is_finite() || adjustment.delta.abs() > MAX_ADJUSTMENT {
+            return Err(validation::invalid(
+                "confidence_adjustments.delta",
+                &format!("must be between -{} and {}", MAX_ADJUSTMENT, MAX_ADJUSTMENT),
+            ));
+        }
+    }
+    if let Some(types) = &handling.applicable_directive_types {
+        validation::collection("applicable_directive_types", types.len(), MAX_APPLICABLE_TYPES)?;
+        for directive_type in types {
+            validation::identifier("applicable_directive_types", directive_type)?;
+        }
+    }
+    SITUATION_HANDLING.with(|h| h.borrow_mut().insert(handling.code, handling));
+    Ok(())
+}
+
+// Intake: the canonical text for a situation, "other: <description>" for the escape hatch
+pub(crate) fn canonical(field: &str, value: &str) -> Result<String, String> {
+    let (code, freetext) = parse(value).ok_or_else(|| {
+        let reason = match suggestion(value) {
+            Some(close) => format!("unknown situation \"{}\"; did you mean \"{}\"? Use \"other: <description>\" for anything outside the taxonomy", value.trim(), close),
+            None => format!("unknown situation \"{}\"; use a code from get_situation_taxonomy or \"other: <description>\"", value.trim()),
+        };
+        validation::invalid(field, &reason)
+    })?;
+    match (code, freetext) {
+        (SituationCode::Other, Some(description)) => Ok(format!("other: {}", description)),
+        (SituationCode::Other, None) => Err(validation::invalid(field, "\"other\" needs a description, as \"other: <description>\"")),
+        (code, _) => Ok(text_code(code).to_string()),
+    }
+}
+
+// Canonical text (as intake leaves it) back to its code
+pub(crate) fn code_of(situation: &str) -> Option<SituationCode> {
+    parse(situation).map(|(code, _)| code)
+}
+
+// The situation's confidence adjustment for this directive type, 0 when it has none
+pub(crate) fn confidence_adjustment(code: SituationCode, directive_type: &str) -> f32 {
+    SITUATION_HANDLING.with(|h| {
+        h.borrow()
+            .get(&code)
+            .and_then(|handling| handling.confidence_adjustments.iter().find(|a| a.directive_type == directive_type))
+            .map_or(0.0, |a| a.delta)
+    })
+}
+
+// (directives that apply to the situation, in their original order; how many others are on file)
+pub(crate) fn split_applicable(situation: &str, directives: Vec<PatientDirective>) -> (Vec<PatientDirective>, u32) {
+    let applicable_types = code_of(situation).and_then(|code| {
+        SITUATION_HANDLING.with(|h| h.borrow().get(&code).and_then(|handling| handling.applicable_directive_types.clone()))
+    });
+    let Some(applicable_types) = applicable_types else {
+        return (directives, 0);
+    };
+    let (applicable, others): (Vec<PatientDirective>, Vec<PatientDirective>) =
+        directives.into_iter().partition(|d| applicable_types.contains(&d.directive_type));
+    (applicable, others.len() as u32)
+}
+
+pub(crate) fn text_code(code: SituationCode) -> &'static str {
+    TAXONOMY.iter().find(|(c, ..)| *c == code).map_or("other", |(_, text_code, ..)| text_code)
+}
+
+fn parse(value: &str) -> Option<(SituationCode, Option<String>)> {
+    let trimmed = value.trim();
+    let (head, freetext) = match trimmed.split_once(':') {
+        Some((head, rest)) => (head, Some(rest.trim().to_string()).filter(|r| !r.is_empty())),
+        None => (trimmed, None),
+    };
+    let key = normalize(head);
+    if key == "other" {
+        return Some((SituationCode::Other, freetext));
+    }
+    if freetext.is_some() {
+        return None;
+    }
+    TAXONOMY.iter()
+        .find(|(_, text_code, _, aliases)| *text_code == key || aliases.contains(&key.as_str()))
+        .map(|(code, ..)| (*code, None))
+}
+
+// "Cardiac Arrest", "CARDIAC-ARREST" and "cardiac_arrest" are the same code
+fn normalize(value: &str) -> String {
+    value.trim().to_lowercase().chars().map(|c| if c == ' ' || c == '-' { '_' } else { c }).collect()
+}
+
+// The closest code within two edits, for the rejection message
+fn suggestion(value: &str) -> Option<&'static str> {
+    let key = normalize(value);
+    TAXONOMY.iter()
+        .map(|(_, text_code, ..)| (edit_distance(&key, text_code), *text_code))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, text_code)| text_code)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn default_handling() -> BTreeMap<SituationCode, SituationHandling> {
+    let types = |types: &[&str]| Some(types.iter().map(|t| t.to_string()).collect());
+    [
+        (SituationCode::CardiacArrest, Some(0.05), types(&END_OF_LIFE)),
+        (SituationCode::RespiratoryFailure, Some(0.03), types(&RESUSCITATION)),
+        (SituationCode::BrainDeath, None, types(&AFTER_DEATH)),
+        (SituationCode::Stroke, None, types(&RESUSCITATION)),
+        (SituationCode::MajorTrauma, None, types(&END_OF_LIFE)),
+        (SituationCode::Sepsis, None, types(&RESUSCITATION)),
+        (SituationCode::Overdose, None, types(&RESUSCITATION)),
+        (SituationCode::Anaphylaxis, None, types(&RESUSCITATION)),
+        (SituationCode::AsthmaAttack, None, types(&RESUSCITATION)),
+        (SituationCode::Seizure, None, types(&RESUSCITATION)),
+        (SituationCode::TerminalDecline, None, types(&END_OF_LIFE)),
+        (SituationCode::Unspecified, None, None),
+        (SituationCode::Other, None, None),
+    ]
+    .into_iter()
+    .map(|(code, dnr_delta, applicable_directive_types)| {
+        let confidence_adjustments = dnr_delta
+            .map(|delta| ConfidenceAdjustment { directive_type: "DNR".to_string(), delta })
+            .into_iter()
+            .collect();
+        (code, SituationHandling { code, confidence_adjustments, applicable_directive_types })
+    })
+    .collect()
+}
@@ -0,0 +1,210 @@
+use ic_cdk::{caller, Principal};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{clock, ids, ContactEvent, DIRECTIVE_MANAGER_CANISTER_ID};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SloDefinition {
+    pub slo_id: String,
+    pub kind: String, // "LATENCY": every reply counts; "DELIVERY": the call must also succeed
+    pub threshold_ms: u64,
+    pub target_percent: f32,
+    pub window_seconds: u64,
+    pub burn_window_seconds: u64,
+    pub alert_burn_rate: f32, // Error budget spend rate, over the burn window, that pages operators
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SloStatus {
+    pub slo: SloDefinition,
+    pub samples: u64,
+    pub good: u64,
+    pub attainment_percent: f32,
+    pub error_budget_remaining_percent: f32,
+    pub target_percentile_latency_ms: Option<u64>,
+    pub burn_rate: f32,
+    pub last_alert_at: Option<u64>,
+}
+
+#[derive(Clone, Copy)]
+struct Sample {
+    recorded_at: u64,
+    latency_ms: u64,
+    good: bool,
+}
+
+thread_local! {
+    static SLO_DEFINITIONS: std::cell::RefCell<BTreeMap<String, SloDefinition>> =
+        std::cell::RefCell::new(default_slos());
+
+    static SLO_SAMPLES: std::cell::RefCell<BTreeMap<String, VecDeque<Sample>>> =
+        std::cell::RefCell::new(BTreeMap::new());
+
+    static LAST_ALERTS: std::cell::RefCell<BTreeMap<String, u64>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+const SLO_KINDS: [&str; 2] = ["LATENCY", "DELIVERY"];
+// Oldest samples go first once an SLO holds this many, whatever its window
+const MAX_SAMPLES_PER_SLO: usize = 20_000;
+// A handful of slow calls right after an upgrade should not page anyone
+const MIN_SAMPLES_FOR_ALERT: usize = 20;
+const ALERT_COOLDOWN_NANOS: u64 = 15 * 60 * 1_000_000_000;
+
+fn default_slos() -> BTreeMap<String, SloDefinition> {
+    let slo = |slo_id: &str, kind: &str, threshold_ms: u64, target_percent: f32| SloDefinition {
+        slo_id: slo_id.to_string(),
+        kind: kind.to_string(),
+        threshold_ms,
+        target_percent,
+        window_seconds: 24 * 60 * 60,
+        burn_window_seconds: 5 * 60,
+        alert_burn_rate: 14.4,
+    };
+    [
+        slo("emergency_check", "LATENCY", 1_000, 99.0),
+        slo("lookup_by_bracelet", "LATENCY", 1_000, 99.0),
+        slo("notification_delivery", "DELIVERY", 30_000, 99.0),
+    ]
+    .into_iter()
+    .map(|s| (s.slo_id.clone(), s))
+    .collect()
+}
+
+#[ic_cdk::update]
+fn set_slo(slo: SloDefinition) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may define SLOs".to_string());
+    }
+    if !SLO_KINDS.contains(&slo.kind.as_str()) {
+        return Err(format!("Unknown SLO kind: {}", slo.kind));
+    }
+    if !(slo.target_percent > 0.0 && slo.target_percent < 100.0) {
+        return Err("SLO targets must be strictly between 0 and 100 percent".to_string());
+    }
+    if slo.threshold_ms == 0 || slo.burn_window_seconds == 0 || slo.burn_window_seconds > slo.window_seconds {
+        return Err("Thresholds must be non-zero and the burn window must fit inside the SLO window".to_string());
+    }
+    SLO_DEFINITIONS.with(|d| d.borrow_mut().insert(slo.slo_id.clone(), slo));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_slo_dashboard() -> Result<Vec<SloStatus>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read the SLO dashboard".to_string());
+    }
+    let now = clock::now();
+    let definitions: Vec<SloDefinition> = SLO_DEFINITIONS.with(|d| d.borrow().values().cloned().collect());
+    Ok(definitions.into_iter().map(|slo| status(slo, now)).collect())
+}
+
+// Latency is measured from the start of the call inside this canister; time spent in the
+// ingress queue is not visible here
+pub(crate) fn record(slo_id: &str, started_at: u64, succeeded: bool) {
+    let Some(slo) = SLO_DEFINITIONS.with(|d| d.borrow().get(slo_id).cloned()) else {
+        return;
+    };
+    let now = clock::now();
+    let latency_ms = now.saturating_sub(started_at) / 1_000_000;
+    let good = latency_ms <= slo.threshold_ms && (succeeded || slo.kind == "LATENCY");
+    SLO_SAMPLES.with(|s| {
+        let mut samples = s.borrow_mut();
+        let samples = samples.entry(slo.slo_id.clone()).or_default();
+        while samples.front().is_some_and(|x| now.saturating_sub(x.recorded_at) >= slo.window_seconds * 1_000_000_000) {
+            samples.pop_front();
+        }
+        if samples.len() >= MAX_SAMPLES_PER_SLO {
+            samples.pop_front();
+        }
+        samples.push_back(Sample { recorded_at: now, latency_ms, good });
+    });
+    if !good {
+        alert_if_burning(slo, now);
+    }
+}
+
+fn status(slo: SloDefinition, now: u64) -> SloStatus {
+    let window = in_window(&slo.slo_id, slo.window_seconds, now);
+    let good = window.iter().filter(|x| x.good).count() as u64;
+    let samples = window.len() as u64;
+    let attainment = if samples == 0 { 1.0 } else { good as f32 / samples as f32 };
+    let budget = 1.0 - slo.target_percent / 100.0;
+    let budget_remaining = (1.0 - (1.0 - attainment) / budget).max(0.0);
+
+    let mut latencies: Vec<u64> = window.iter().map(|x| x.latency_ms).collect();
+    latencies.sort_unstable();
+    let rank = ((latencies.len() as f32 * slo.target_percent / 100.0).ceil() as usize).saturating_sub(1);
+    let target_percentile_latency_ms = latencies.get(rank).copied();
+
+    SloStatus {
+        samples,
+        good,
+        attainment_percent: attainment * 100.0,
+        error_budget_remaining_percent: budget_remaining * 100.0,
+        target_percentile_latency_ms,
+        burn_rate: burn_rate(&slo, now).0,
+        last_alert_at: LAST_ALERTS.with(|a| a.borrow().get(&slo.slo_id).copied()),
+        slo,
+    }
+}
+
+// How many times faster than sustainable the error budget is being spent over the burn window
+fn burn_rate(slo: &SloDefinition, now: u64) -> (f32, usize) {
+    let recent = in_window(&slo.slo_id, slo.burn_window_seconds, now);
+    if recent.is_empty() {
+        return (0.0, 0);
+    }
+    let bad = recent.iter().filter(|x| !x.good).count() as f32 / recent.len() as f32;
+    (bad / (1.0 - slo.target_percent / 100.0), recent.len())
+}
+
+fn in_window(slo_id: &str, seconds: u64, now: u64) -> Vec<Sample> {
+    SLO_SAMPLES.with(|s| {
+        s.borrow()
+            .get(slo_id)
+            .map(|samples| {
+                samples.iter()
+                    .filter(|x| now.saturating_sub(x.recorded_at) < seconds * 1_000_000_000)
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    })
+}
+
+// Pages operators through directive_manager's notification subsystem; one-way, so the
+// request that tipped the budget is never held up by it
+fn alert_if_burning(slo: SloDefinition, now: u64) {
+    let (rate, samples) = burn_rate(&slo, now);
+    if samples < MIN_SAMPLES_FOR_ALERT || rate < slo.alert_burn_rate {
+        return;
+    }
+    let cooling = LAST_ALERTS.with(|a| a.borrow().get(&slo.slo_id).is_some_and(|at| now.saturating_sub(*at) < ALERT_COOLDOWN_NANOS));
+    if cooling {
+        return;
+    }
+    LAST_ALERTS.with(|a| a.borrow_mut().insert(slo.slo_id.clone(), now));
+
+    let event = ContactEvent {
+        event_type: "SLO_BURN".to_string(),
+        reference_id: ids::new_id("SLO"),
+        summary: format!(
+            "{} is burning its error budget at {:.1}x (alert at {:.1}x)",
+            slo.slo_id, rate, slo.alert_burn_rate
+        ),
+        details: format!(
+            "{:.1}% of {} calls in the last {} seconds missed the {} ms objective; target {}%",
+            rate * (100.0 - slo.target_percent), samples, slo.burn_window_seconds, slo.threshold_ms, slo.target_percent
+        ),
+    };
+    ic_cdk::println!("🔥 SLO ALERT: {}", event.summary);
+    let Ok(directive_manager_id) = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID) else {
+        return;
+    };
+    if let Err(code) = ic_cdk::notify(directive_manager_id, "notify_operators", (event,)) {
+        ic_cdk::println!("⚠️ SLO alert could not be sent: {:?}", code);
+    }
+}
@@ -120,6 +120,7 @@ mod tests {
             message: "Test message".to_string(),
             confidence_score: 0.95,
             timestamp: time(),
+            attestation_status: None,
         };
 
         assert!(response.action_required);
@@ -127,4 +128,74 @@ mod tests {
         assert!(response.confidence_score > 0.9);
         assert!(response.timestamp > 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_visibility_preferences_enforcement() {
+        let preferences = VisibilityPreferences {
+            disclosable_directive_types: vec!["DNR".to_string()],
+            permitted_requester_classes: vec!["EMERGENCY_DEPARTMENT".to_string()],
+            notify_next_of_kin: true,
+            updated_at: 0,
+        };
+
+        // The class comes from the caller's registration, never from the hospital ID it sends
+        assert_eq!(classify_requester(Principal::anonymous()), UNREGISTERED_REQUESTER);
+        assert!(!is_disclosure_permitted(&preferences, "DNR", UNREGISTERED_REQUESTER));
+        assert!(is_disclosure_permitted(&preferences, "DNR", "EMERGENCY_DEPARTMENT"));
+        assert!(!is_disclosure_permitted(&preferences, "ORGAN_DONATION", "EMERGENCY_DEPARTMENT"));
+        assert!(!is_disclosure_permitted(&preferences, "DNR", "TRANSPLANT_CENTER"));
+    }
+
+    fn cached_bundle(token: &str, token_expires_at: u64) -> crate::lookup_cache::CachedBundle {
+        crate::lookup_cache::CachedBundle {
+            patient_id_hash: vec![0x21; 32],
+            hospital_id: "MAYO_EMERGENCY_001".to_string(),
+            situation: "cardiac_arrest".to_string(),
+            directive: crate::PatientDirective {
+                directive_type: "DNR".to_string(),
+                details: "Do not resuscitate".to_string(),
+                confidence_score: 0.95,
+                timestamp: 0,
+                legal_validity: 0.9,
+                emergency_conditions: vec!["cardiac_arrest".to_string()],
+            },
+            other_directives_on_file: 0,
+            clinician_summary: None,
+            preferences: None,
+            activation: crate::activation_unknown("DNR"),
+            cached_at: crate::clock::now(),
+            token_hash: ic_cdk::api::sha256(token.as_bytes()),
+            token_expires_at: Some(token_expires_at),
+        }
+    }
+
+    #[test]
+    fn cached_lookup_is_served_only_to_the_token_that_resolved_it() {
+        use crate::lookup_cache::{get, store};
+        const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+
+        canister_clock::at_minutes(0);
+        let hospital = candid::Principal::from_slice(&[7; 29]);
+        let expires_at = crate::clock::now() + 60 * NANOS_PER_MINUTE;
+        store(hospital, "cached_patient_001", cached_bundle("token_A", expires_at));
+        let lookup = |requester, situation: &str, token: Option<&str>| {
+            get(requester, "cached_patient_001", "MAYO_EMERGENCY_001", situation, token).is_some()
+        };
+
+        assert!(lookup(hospital, "cardiac_arrest", Some("token_A")));
+        assert!(!lookup(hospital, "cardiac_arrest", Some("token_B")));
+        assert!(!lookup(hospital, "cardiac_arrest", None));
+        assert!(!lookup(hospital, "brain_death", Some("token_A")));
+        assert!(!lookup(candid::Principal::from_slice(&[8; 29]), "cardiac_arrest", Some("token_A")));
+
+        // Bundles last two minutes, however long the token itself has left
+        canister_clock::at_minutes(2);
+        assert!(!lookup(hospital, "cardiac_arrest", Some("token_A")));
+
+        // Nor does a bundle outlive the token that resolved it
+        store(hospital, "cached_patient_001", cached_bundle("token_A", crate::clock::now()));
+        assert!(lookup(hospital, "cardiac_arrest", Some("token_A")));
+        canister_clock::advance(1);
+        assert!(!lookup(hospital, "cardiac_arrest", Some("token_A")));
+    }
+}
@@ -0,0 +1,55 @@
+use ic_cdk::{call, caller, Principal};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+use crate::{EmergencyCheckRequestV2, DIRECTIVE_MANAGER_CANISTER_ID};
+
+// Mirrors directive_manager's DirectiveTranslation
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DirectiveTranslation {
+    pub source_language: String,
+    pub target_language: String,
+    pub summary: String,
+    pub directives: Vec<TranslatedDirective>,
+    pub confidence_score: f32,
+    pub machine_translated: bool,
+    pub notice: String,
+    pub provider_id: String,
+    pub template_version: u32,
+    pub translated_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TranslatedDirective {
+    pub directive_type: String,
+    pub conditions: Vec<String>,
+}
+
+// The stored machine translation, when the requester declared a locale whose language is not the
+// directive's. A translation is an aid, so failing to fetch one never fails the emergency check.
+pub(crate) async fn for_requester(
+    request: &EmergencyCheckRequestV2,
+    locale: Option<&str>,
+    patient_id_hash: &[u8]
+) -> Option<DirectiveTranslation> {
+    let locale = locale?;
+    let access_token = request.access_token.as_deref()?;
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID).ok()?;
+    let result: Result<(Result<Option<DirectiveTranslation>, String>,), _> = call(
+        directive_manager_id,
+        "emergency_translation",
+        (patient_id_hash.to_vec(), caller(), access_token.to_string())
+    ).await;
+
+    match result {
+        Ok((Ok(translation),)) => translation.filter(|t| primary_language(&t.source_language) != primary_language(locale)),
+        Ok((Err(e),)) | Err((_, e)) => {
+            ic_cdk::println!("⚠️ Directive translation unavailable: {}", e);
+            None
+        }
+    }
+}
+
+fn primary_language(tag: &str) -> &str {
+    tag.split('-').next().unwrap_or(tag)
+}
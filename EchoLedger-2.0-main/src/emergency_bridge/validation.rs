@@ -0,0 +1,147 @@
+use crate::situations;
+use crate::versioning::{EmergencyCheckRequestV2, VitalSigns};
+use crate::EmergencyRequest;
+
+// Shape checks for everything that arrives over Candid. Each failure reads
+// "Invalid <field>: <reason>" so integrators can map errors back to their payloads.
+
+// Nothing this canister accepts needs more; larger ingress is dropped before it is decoded
+const MAX_ARG_BYTES: usize = 64 * 1024;
+
+const MAX_IDENTIFIER_BYTES: usize = 128;
+const MAX_SITUATION_BYTES: usize = 4 * 1024;
+const MAX_VITALS_BYTES: usize = 8 * 1024;
+const MAX_ACCESS_TOKEN_BYTES: usize = 512;
+pub(crate) const MAX_TOKEN_UID_HASH_BYTES: usize = 64;
+pub(crate) const MAX_WALLET_PAYLOAD_BYTES: usize = 16 * 1024;
+
+// Inter-canister calls skip this hook and are held to the same limits by the per-field checks
+#[ic_cdk::inspect_message]
+fn inspect_message() {
+    if ic_cdk::api::call::arg_data_raw_size() <= MAX_ARG_BYTES {
+        ic_cdk::api::call::accept_message();
+    }
+}
+
+// Checks every field and returns the request with its text normalized
+pub(crate) fn emergency_request(request: &EmergencyRequest) -> Result<EmergencyRequest, String> {
+    Ok(EmergencyRequest {
+        patient_id: identifier("patient_id", &request.patient_id)?,
+        hospital_id: identifier("hospital_id", &request.hospital_id)?,
+        situation: situation(&request.situation)?,
+        vitals: request.vitals.as_deref().map(|v| text("vitals", v, MAX_VITALS_BYTES)).transpose()?,
+        access_token: request.access_token.as_deref().map(|t| token("access_token", t)).transpose()?,
+    })
+}
+
+pub(crate) fn emergency_request_v2(request: &EmergencyCheckRequestV2) -> Result<EmergencyCheckRequestV2, String> {
+    Ok(EmergencyCheckRequestV2 {
+        patient_id: identifier("patient_id", &request.patient_id)?,
+        hospital_id: identifier("hospital_id", &request.hospital_id)?,
+        situation: situation(&request.situation)?,
+        vitals: request.vitals.as_ref().map(vitals).transpose()?,
+        access_token: request.access_token.as_deref().map(|t| token("access_token", t)).transpose()?,
+        requester_locale: request.requester_locale.as_deref().map(|l| language_tag("requester_locale", l)).transpose()?,
+    })
+}
+
+// Free text or a taxonomy code, returned in canonical form
+pub(crate) fn situation(value: &str) -> Result<String, String> {
+    situations::canonical("situation", &text("situation", value, MAX_SITUATION_BYTES)?)
+}
+
+// Physiologically impossible readings are a client bug, not a patient state
+fn vitals(vitals: &VitalSigns) -> Result<VitalSigns, String> {
+    for (field, value, max) in [
+        ("vitals.heart_rate_bpm", vitals.heart_rate_bpm, 400),
+        ("vitals.systolic_mmhg", vitals.systolic_mmhg, 400),
+        ("vitals.diastolic_mmhg", vitals.diastolic_mmhg, 300),
+        ("vitals.respiratory_rate", vitals.respiratory_rate, 150),
+        ("vitals.spo2_percent", vitals.spo2_percent, 100),
+    ] {
+        if value.is_some_and(|v| v > max) {
+            return Err(invalid(field, &format!("above {}", max)));
+        }
+    }
+    Ok(VitalSigns {
+        brain_activity: vitals.brain_activity.as_deref().map(|b| identifier("vitals.brain_activity", b)).transpose()?,
+        ..vitals.clone()
+    })
+}
+
+// Free text: at most `max_bytes`, no control characters other than newline and tab, and
+// normalized (no byte-order mark, LF line endings, exotic spaces made plain, trimmed)
+pub(crate) fn text(field: &str, value: &str, max_bytes: usize) -> Result<String, String> {
+    if value.len() > max_bytes {
+        return Err(invalid(field, &format!("longer than {} bytes", max_bytes)));
+    }
+    let normalized = value.replace("\r\n", "\n").replace('\r', "\n");
+    let mut cleaned = String::with_capacity(normalized.len());
+    for c in normalized.chars() {
+        match c {
+            '\u{FEFF}' => {}
+            '\u{00A0}' | '\u{2000}'..='\u{200A}' | '\u{202F}' | '\u{205F}' | '\u{3000}' => cleaned.push(' '),
+            '\n' | '\t' => cleaned.push(c),
+            // U+FFFD is what binary content turns into once forced through a UTF-8 decoder
+            '\u{FFFD}' | '\u{FFFE}' | '\u{FFFF}' => return Err(invalid(field, "contains binary content")),
+            _ if c.is_control() => return Err(invalid(field, "contains control characters")),
+            _ => cleaned.push(c),
+        }
+    }
+    Ok(cleaned.trim().to_string())
+}
+
+// Ids and codes: non-empty single-line text
+pub(crate) fn identifier(field: &str, value: &str) -> Result<String, String> {
+    let value = text(field, value, MAX_IDENTIFIER_BYTES)?;
+    if value.is_empty() {
+        return Err(invalid(field, "must not be empty"));
+    }
+    if value.contains(['\n', '\t']) {
+        return Err(invalid(field, "must be a single line"));
+    }
+    Ok(value)
+}
+
+// Bearer tokens are compared byte for byte, so they are checked but never rewritten
+pub(crate) fn token(field: &str, value: &str) -> Result<String, String> {
+    if value.is_empty() || value.len() > MAX_ACCESS_TOKEN_BYTES {
+        return Err(invalid(field, &format!("must be 1-{} bytes", MAX_ACCESS_TOKEN_BYTES)));
+    }
+    if value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(invalid(field, "must not contain whitespace or control characters"));
+    }
+    Ok(value.to_string())
+}
+
+// BCP 47 language tags as clients send them ("de", "de-DE", "pt-BR"); lowercased for comparison
+pub(crate) fn language_tag(field: &str, value: &str) -> Result<String, String> {
+    let value = identifier(field, value)?.to_lowercase();
+    let mut subtags = value.split('-');
+    let primary = subtags.next().unwrap_or_default();
+    let well_formed = (2..=3).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_lowercase())
+        && subtags.all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()));
+    if !well_formed {
+        return Err(invalid(field, "not a language tag such as \"de\" or \"en-US\""));
+    }
+    Ok(value)
+}
+
+pub(crate) fn collection(field: &str, len: usize, max_items: usize) -> Result<(), String> {
+    if len > max_items {
+        return Err(invalid(field, &format!("more than {} entries", max_items)));
+    }
+    Ok(())
+}
+
+pub(crate) fn bytes(field: &str, value: &[u8], max_bytes: usize) -> Result<(), String> {
+    if value.is_empty() || value.len() > max_bytes {
+        return Err(invalid(field, &format!("must be 1-{} bytes", max_bytes)));
+    }
+    Ok(())
+}
+
+pub(crate) fn invalid(field: &str, reason: &str) -> String {
+    format!("Invalid {}: {}", field, reason)
+}
@@ -0,0 +1,299 @@
+use ic_cdk::{caller, Principal};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::alert_rendering::RenderedAlert;
+use crate::translation::DirectiveTranslation;
+use crate::{clock, slo, validation, ActivationStatus, AttestationStatus, ClinicianSummary, EmergencyRequest, EmergencyResponse};
+
+// Structured vitals; v1 carried these as a free-form JSON string
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct VitalSigns {
+    pub heart_rate_bpm: Option<u32>,
+    pub systolic_mmhg: Option<u32>,
+    pub diastolic_mmhg: Option<u32>,
+    pub respiratory_rate: Option<u32>,
+    pub spo2_percent: Option<u32>,
+    pub brain_activity: Option<String>,
+}
+
+// The shape the emergency pipeline runs on; v1 requests are translated into it
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EmergencyCheckRequestV2 {
+    pub patient_id: String,
+    pub hospital_id: String,
+    pub situation: String,
+    pub vitals: Option<VitalSigns>,
+    pub access_token: Option<String>,
+    pub requester_locale: Option<String>, // BCP 47, e.g. "de-DE"; overrides the hospital's saved locale
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EmergencyCheckResponseV2 {
+    pub api_version: String,
+    pub action_required: bool,
+    pub directive_type: String,
+    pub message: String,
+    pub confidence_score: f32,
+    pub timestamp: u64,
+    pub attestation_status: Option<AttestationStatus>,
+    pub satisfied_conditions: Vec<String>,
+    pub pending_conditions: Vec<String>,
+    pub served_from_cache: bool,
+    pub translation: Option<DirectiveTranslation>, // machine translation, only for a requester in another language
+    pub other_directives_on_file: u32, // Directives the situation's applicability row left out
+    pub clinician_summary: Option<ClinicianSummary>,
+    pub rendered_alerts: Vec<RenderedAlert>, // Plain text for the hospital's pager and TTS channels
+    pub session_id: Option<String>, // Set on actionable responses; acknowledge it once the clinician has seen them
+    pub proposed_order_set_id: Option<String>, // Comfort-care de-escalation drafts awaiting clinician review
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ApiVersionInfo {
+    pub family: String,
+    pub version: String,
+    pub endpoint: String,
+    pub status: String, // "CURRENT", "DEPRECATED", "RETIRED"
+    pub deprecated_at: Option<u64>,
+    pub sunset_at: Option<u64>,
+    pub replaced_by: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DeprecatedUsage {
+    pub endpoint: String,
+    pub caller: Principal,
+    pub calls: u64,
+    pub last_called_at: u64,
+}
+
+struct EndpointVersion {
+    family: &'static str,
+    version: &'static str,
+    endpoint: &'static str,
+    deprecated_at: Option<u64>,
+    sunset_at: Option<u64>,
+    replaced_by: Option<&'static str>,
+}
+
+// The published deprecation windows. Past its sunset an endpoint is reported RETIRED but
+// keeps answering: an emergency lookup is never refused over an interface version.
+const ENDPOINT_VERSIONS: [EndpointVersion; 4] = [
+    EndpointVersion {
+        family: "emergency_check",
+        version: "v0",
+        endpoint: "process_emergency_request",
+        deprecated_at: Some(1_735_689_600_000_000_000), // 2025-01-01
+        sunset_at: Some(1_782_777_600_000_000_000),     // 2026-06-30
+        replaced_by: Some("emergency_check_v2"),
+    },
+    EndpointVersion {
+        family: "emergency_check",
+        version: "v1",
+        endpoint: "emergency_check",
+        deprecated_at: Some(1_793_491_200_000_000_000), // 2026-11-01
+        sunset_at: Some(1_809_129_600_000_000_000),     // 2027-05-01
+        replaced_by: Some("emergency_check_v2"),
+    },
+    EndpointVersion {
+        family: "emergency_check",
+        version: "v2",
+        endpoint: "emergency_check_v2",
+        deprecated_at: None,
+        sunset_at: None,
+        replaced_by: None,
+    },
+    EndpointVersion {
+        family: "emergency_check_cached",
+        version: "v1",
+        endpoint: "emergency_check_cached",
+        deprecated_at: None,
+        sunset_at: None,
+        replaced_by: None,
+    },
+];
+
+thread_local! {
+    // (endpoint, caller) -> (calls, last called); who still needs to migrate
+    static DEPRECATED_USAGE: std::cell::RefCell<BTreeMap<(String, Principal), (u64, u64)>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+const MAX_TRACKED_CALLERS: usize = 10_000;
+
+#[ic_cdk::update]
+async fn emergency_check_v2(request: EmergencyCheckRequestV2) -> Result<EmergencyCheckResponseV2, String> {
+    let start_time = clock::now();
+    let result = match validation::emergency_request_v2(&request) {
+        Ok(request) => crate::run_emergency_check(request, start_time).await,
+        Err(e) => Err(e),
+    };
+    slo::record("emergency_check", start_time, result.is_ok());
+    result
+}
+
+#[ic_cdk::query]
+fn get_api_versions() -> Vec<ApiVersionInfo> {
+    let now = clock::now();
+    ENDPOINT_VERSIONS.iter().map(|v| info(v, now)).collect()
+}
+
+// The newest version of `family` the client supports that has not been retired
+#[ic_cdk::query]
+fn negotiate_api_version(family: String, client_versions: Vec<String>) -> Result<ApiVersionInfo, String> {
+    validation::collection("client_versions", client_versions.len(), ENDPOINT_VERSIONS.len())?;
+    let now = clock::now();
+    ENDPOINT_VERSIONS.iter()
+        .rev()
+        .filter(|v| v.family == family && client_versions.iter().any(|c| c == v.version))
+        .map(|v| info(v, now))
+        .find(|v| v.status != "RETIRED")
+        .ok_or_else(|| {
+            let offered: Vec<&str> = ENDPOINT_VERSIONS.iter()
+                .filter(|v| v.family == family)
+                .map(|v| v.version)
+                .collect();
+            if offered.is_empty() {
+                format!("Unknown API family: {}", family)
+            } else {
+                format!("No supported version in common for {}; this canister offers {}", family, offered.join(", "))
+            }
+        })
+}
+
+#[ic_cdk::query]
+fn get_deprecated_endpoint_usage() -> Result<Vec<DeprecatedUsage>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read deprecated endpoint usage".to_string());
+    }
+    Ok(DEPRECATED_USAGE.with(|u| {
+        u.borrow().iter().map(|((endpoint, caller), (calls, last_called_at))| DeprecatedUsage {
+            endpoint: endpoint.clone(),
+            caller: *caller,
+            calls: *calls,
+            last_called_at: *last_called_at,
+        }).collect()
+    }))
+}
+
+fn info(version: &EndpointVersion, now: u64) -> ApiVersionInfo {
+    let status = if version.sunset_at.is_some_and(|at| now >= at) {
+        "RETIRED"
+    } else if version.deprecated_at.is_some_and(|at| now >= at) {
+        "DEPRECATED"
+    } else {
+        "CURRENT"
+    };
+    ApiVersionInfo {
+        family: version.family.to_string(),
+        version: version.version.to_string(),
+        endpoint: version.endpoint.to_string(),
+        status: status.to_string(),
+        deprecated_at: version.deprecated_at,
+        sunset_at: version.sunset_at,
+        replaced_by: version.replaced_by.map(String::from),
+    }
+}
+
+// Counts a call to `endpoint` when it is past its deprecation date
+pub(crate) fn note_call(endpoint: &str) {
+    let now = clock::now();
+    let deprecated = ENDPOINT_VERSIONS.iter()
+        .any(|v| v.endpoint == endpoint && v.deprecated_at.is_some_and(|at| now >= at));
+    if !deprecated {
+        return;
+    }
+    DEPRECATED_USAGE.with(|u| {
+        let mut usage = u.borrow_mut();
+        let key = (endpoint.to_string(), caller());
+        if usage.len() >= MAX_TRACKED_CALLERS && !usage.contains_key(&key) {
+            return;
+        }
+        let entry = usage.entry(key).or_insert((0, now));
+        entry.0 += 1;
+        entry.1 = now;
+    });
+}
+
+// v1 -> pipeline. Vitals JSON the shim cannot read is dropped rather than failing the lookup.
+pub(crate) fn request_from_v1(request: EmergencyRequest) -> EmergencyCheckRequestV2 {
+    EmergencyCheckRequestV2 {
+        vitals: request.vitals.as_deref().and_then(vitals_from_json),
+        patient_id: request.patient_id,
+        hospital_id: request.hospital_id,
+        situation: request.situation,
+        access_token: request.access_token,
+        requester_locale: None,
+    }
+}
+
+// Kept in the v1 shape for get_recent_alerts, which predates v2. The bearer token is not kept:
+// the alert log is for monitoring, not a second place to find live credentials.
+pub(crate) fn request_to_v1(request: &EmergencyCheckRequestV2) -> EmergencyRequest {
+    EmergencyRequest {
+        patient_id: request.patient_id.clone(),
+        hospital_id: request.hospital_id.clone(),
+        situation: request.situation.clone(),
+        vitals: request.vitals.as_ref().and_then(|v| serde_json::to_string(v).ok()),
+        access_token: None,
+    }
+}
+
+pub(crate) fn response_to_v1(response: EmergencyCheckResponseV2) -> EmergencyResponse {
+    EmergencyResponse {
+        action_required: response.action_required,
+        directive_type: response.directive_type,
+        message: response.message,
+        confidence_score: response.confidence_score,
+        timestamp: response.timestamp,
+        attestation_status: response.attestation_status,
+    }
+}
+
+pub(crate) fn response_v2(
+    response: EmergencyResponse,
+    activation: &ActivationStatus,
+    served_from_cache: bool,
+    translation: Option<DirectiveTranslation>,
+    other_directives_on_file: u32,
+    clinician_summary: Option<ClinicianSummary>,
+) -> EmergencyCheckResponseV2 {
+    EmergencyCheckResponseV2 {
+        api_version: "v2".to_string(),
+        action_required: response.action_required,
+        directive_type: response.directive_type,
+        message: response.message,
+        confidence_score: response.confidence_score,
+        timestamp: response.timestamp,
+        attestation_status: response.attestation_status,
+        satisfied_conditions: activation.satisfied_conditions.clone(),
+        pending_conditions: activation.pending_conditions.clone(),
+        served_from_cache,
+        translation,
+        other_directives_on_file,
+        clinician_summary,
+        rendered_alerts: Vec::new(),
+        session_id: None,
+        proposed_order_set_id: None,
+    }
+}
+
+// The keys v1 integrations were documented to send: pulse or heart_rate, bp or blood_pressure as
+// "systolic/diastolic", respiratory_rate, spo2 and brain_activity
+fn vitals_from_json(vitals: &str) -> Option<VitalSigns> {
+    let value: serde_json::Value = serde_json::from_str(vitals).ok()?;
+    let number = |keys: &[&str]| keys.iter().find_map(|k| value[*k].as_u64()).map(|n| n.min(u32::MAX as u64) as u32);
+    let pressure = ["blood_pressure", "bp"].iter()
+        .find_map(|k| value[*k].as_str())
+        .and_then(|bp| bp.split_once('/'))
+        .map(|(s, d)| (s.trim().parse().ok(), d.trim().parse().ok()));
+    Some(VitalSigns {
+        heart_rate_bpm: number(&["heart_rate", "pulse"]),
+        systolic_mmhg: pressure.and_then(|p| p.0),
+        diastolic_mmhg: pressure.and_then(|p| p.1),
+        respiratory_rate: number(&["respiratory_rate"]),
+        spo2_percent: number(&["spo2"]),
+        brain_activity: value["brain_activity"].as_str().map(String::from),
+    })
+}
@@ -0,0 +1,232 @@
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::{call, caller, Principal};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::validation;
+use crate::{clock, PatientDirective, DIRECTIVE_MANAGER_CANISTER_ID};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct WalletDirectiveSummary {
+    pub directive_type: String,
+    pub emergency_conditions: Vec<String>,
+    pub legal_validity: f32,
+}
+
+// Everything a bedside reader needs is carried inside the signed payload
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct WalletClaims {
+    pub serial: String,
+    pub patient_ref: String,
+    pub directives: Vec<WalletDirectiveSummary>,
+    pub issued_at: u64,
+    pub expires_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct WalletVerification {
+    pub claims: WalletClaims,
+    pub verified_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+struct IssuedWalletToken {
+    owner: Principal,
+    expires_at: u64,
+    revoked: bool,
+}
+
+thread_local! {
+    static WALLET_SIGNING_SECRET: std::cell::RefCell<Vec<u8>> = std::cell::RefCell::new(Vec::new());
+
+    static ISSUED_WALLET_TOKENS: std::cell::RefCell<BTreeMap<String, IssuedWalletToken>> =
+        std::cell::RefCell::new(BTreeMap::new());
+}
+
+// Uppercase hex keeps the payload inside the QR alphanumeric character set
+const WALLET_PAYLOAD_PREFIX: &str = "EL1";
+const DEFAULT_WALLET_TTL_HOURS: u32 = 24;
+const MAX_WALLET_TTL_HOURS: u32 = 72;
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const HMAC_BLOCK_SIZE: usize = 64;
+
+// The patient requests a QR payload for their phone or wallet card
+#[ic_cdk::update]
+async fn issue_wallet_token(patient_id: String, ttl_hours: Option<u32>) -> Result<String, String> {
+    let patient_id = validation::identifier("patient_id", &patient_id)?;
+    let ttl_hours = ttl_hours.unwrap_or(DEFAULT_WALLET_TTL_HOURS);
+    if ttl_hours == 0 || ttl_hours > MAX_WALLET_TTL_HOURS {
+        return Err(format!("Wallet tokens may live between 1 and {} hours", MAX_WALLET_TTL_HOURS));
+    }
+
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let (summary,): (Result<(Vec<u8>, Vec<PatientDirective>), String>,) = call(
+        directive_manager_id,
+        "wallet_directive_summary",
+        (patient_id, caller())
+    ).await.map_err(|(_, msg)| format!("Failed to load directive summary: {}", msg))?;
+    let (patient_id_hash, directives) = summary?;
+    // Readers see a wallet-only reference, so a scanned pass cannot be joined to any other record
+    let (patient_ref,): (Result<String, String>,) = call(
+        directive_manager_id,
+        "issue_external_reference",
+        (patient_id_hash.clone(), "wallet".to_string())
+    ).await.map_err(|(_, msg)| format!("Failed to issue patient reference: {}", msg))?;
+    let patient_ref = patient_ref?;
+
+    let secret = signing_secret().await?;
+    let issued_at = clock::now();
+    let mut serial_material = patient_id_hash.clone();
+    serial_material.extend_from_slice(&issued_at.to_be_bytes());
+    let claims = WalletClaims {
+        serial: hex(&ic_cdk::api::sha256(&serial_material)[..8]),
+        patient_ref,
+        directives: directives.into_iter().map(|d| WalletDirectiveSummary {
+            directive_type: d.directive_type,
+            emergency_conditions: d.emergency_conditions,
+            legal_validity: d.legal_validity,
+        }).collect(),
+        issued_at,
+        expires_at: issued_at + ttl_hours as u64 * NANOS_PER_HOUR,
+    };
+
+    let body = hex(&serde_json::to_vec(&claims).map_err(|e| e.to_string())?);
+    let signed = format!("{}.{}", WALLET_PAYLOAD_PREFIX, body);
+    let signature = hex(&hmac_sha256(&secret, signed.as_bytes()));
+
+    ISSUED_WALLET_TOKENS.with(|tokens| {
+        let mut tokens = tokens.borrow_mut();
+        tokens.retain(|_, t| t.expires_at > issued_at);
+        tokens.insert(claims.serial.clone(), IssuedWalletToken {
+            owner: caller(),
+            expires_at: claims.expires_at,
+            revoked: false,
+        });
+    });
+    Ok(format!("{}.{}", signed, signature))
+}
+
+// Bedside scan: signature and expiry are checked locally, no identifier lookup is made
+#[ic_cdk::update]
+fn verify_wallet_token(payload: String) -> Result<WalletVerification, String> {
+    if payload.len() > validation::MAX_WALLET_PAYLOAD_BYTES {
+        return Err(validation::invalid("payload", &format!("longer than {} bytes", validation::MAX_WALLET_PAYLOAD_BYTES)));
+    }
+    let payload = payload.trim();
+    let (signed, signature) = payload.rsplit_once('.').ok_or("Malformed wallet payload")?;
+    let (prefix, body) = signed.split_once('.').ok_or("Malformed wallet payload")?;
+    if prefix != WALLET_PAYLOAD_PREFIX {
+        return Err(format!("Unsupported wallet payload version: {}", prefix));
+    }
+
+    let secret = WALLET_SIGNING_SECRET.with(|s| s.borrow().clone());
+    let signature = unhex(signature).ok_or("Malformed wallet signature")?;
+    if secret.is_empty() || !constant_time_eq(&hmac_sha256(&secret, signed.as_bytes()), &signature) {
+        return Err("Wallet signature is invalid".to_string());
+    }
+
+    let claims: WalletClaims = unhex(body)
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or("Malformed wallet claims")?;
+    let now = clock::now();
+    if now > claims.expires_at {
+        return Err("Wallet token has expired; ask the patient to refresh it".to_string());
+    }
+    if ISSUED_WALLET_TOKENS.with(|t| t.borrow().get(&claims.serial).is_some_and(|t| t.revoked)) {
+        return Err("Wallet token has been revoked by the patient".to_string());
+    }
+
+    ic_cdk::println!(
+        "AUDIT: Wallet token verified - Serial: {} - Caller: {} - Time: {}",
+        claims.serial,
+        caller().to_text(),
+        now
+    );
+    Ok(WalletVerification { claims, verified_at: now })
+}
+
+#[ic_cdk::update]
+fn revoke_wallet_token(serial: String) -> Result<(), String> {
+    ISSUED_WALLET_TOKENS.with(|tokens| {
+        let mut tokens = tokens.borrow_mut();
+        let token = tokens.get_mut(&serial).ok_or("Unknown or expired wallet token")?;
+        if token.owner != caller() {
+            return Err("Only the patient who issued this wallet token may revoke it".to_string());
+        }
+        token.revoked = true;
+        Ok(())
+    })
+}
+
+type WalletState = (Vec<u8>, Vec<(String, IssuedWalletToken)>, Option<u64>);
+
+// The signing secret must survive upgrades or every issued card stops verifying. The clock's
+// sequence counter rides along so records keyed by it are never overwritten after an upgrade.
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    let secret = WALLET_SIGNING_SECRET.with(|s| s.borrow().clone());
+    let issued: Vec<(String, IssuedWalletToken)> = ISSUED_WALLET_TOKENS.with(|t| t.borrow().clone().into_iter().collect());
+    ic_cdk::storage::stable_save((secret, issued, Some(clock::snapshot()))).expect("Failed to save wallet signing state");
+}
+
+// Releases before this state was kept left stable memory empty; they start with no secret, which is
+// generated on first issue. A saved image that no longer decodes still traps: starting over would
+// silently invalidate every card already issued.
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    let (secret, issued, last_sequence): WalletState = if ic_cdk::api::stable::stable_size() == 0 {
+        (Vec::new(), Vec::new(), None)
+    } else {
+        ic_cdk::storage::stable_restore().expect("Failed to restore wallet signing state")
+    };
+    WALLET_SIGNING_SECRET.with(|s| *s.borrow_mut() = secret);
+    ISSUED_WALLET_TOKENS.with(|t| *t.borrow_mut() = issued.into_iter().collect());
+    clock::restore(last_sequence);
+}
+
+async fn signing_secret() -> Result<Vec<u8>, String> {
+    let existing = WALLET_SIGNING_SECRET.with(|s| s.borrow().clone());
+    if !existing.is_empty() {
+        return Ok(existing);
+    }
+    let (secret,) = raw_rand().await.map_err(|(_, msg)| format!("Failed to generate signing key: {}", msg))?;
+    // Another call may have initialized the secret while this one awaited
+    Ok(WALLET_SIGNING_SECRET.with(|s| {
+        let mut stored = s.borrow_mut();
+        if stored.is_empty() {
+            *stored = secret;
+        }
+        stored.clone()
+    }))
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block = if key.len() > HMAC_BLOCK_SIZE { ic_cdk::api::sha256(key) } else { key.to_vec() };
+    block.resize(HMAC_BLOCK_SIZE, 0);
+
+    let mut inner: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    inner.extend_from_slice(message);
+    let mut outer: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend_from_slice(&ic_cdk::api::sha256(&inner));
+    ic_cdk::api::sha256(&outer)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
@@ -0,0 +1,198 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::capacity;
+use crate::governance;
+use crate::networks::{self, OrganNetwork};
+use crate::validation;
+use crate::RecipientMatch;
+
+// Weighted scoring policy for one allocation system (e.g. US KAS, ETKAS)
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AllocationProfile {
+    pub profile_id: String,
+    pub name: String,
+    pub network_ids: Vec<String>,
+    pub jurisdictions: Vec<String>,
+    pub compatibility_weight: f32,
+    pub urgency_weight: f32,
+    pub survival_benefit_weight: f32,
+    pub distance_penalty_per_100km: f32,
+    pub max_distance_km: Option<u32>,
+}
+
+thread_local! {
+    static ALLOCATION_PROFILES: RefCell<BTreeMap<String, AllocationProfile>> = RefCell::new({
+        let mut profiles = BTreeMap::new();
+        for profile in [
+            default_profile(),
+            // US Kidney Allocation System: longevity matching weighs post-transplant survival
+            AllocationProfile {
+                profile_id: "US_KAS".to_string(),
+                name: "OPTN Kidney Allocation System".to_string(),
+                network_ids: vec!["UNOS".to_string()],
+                jurisdictions: vec!["US".to_string()],
+                compatibility_weight: 0.3,
+                urgency_weight: 0.3,
+                survival_benefit_weight: 0.4,
+                distance_penalty_per_100km: 0.02,
+                max_distance_km: Some(1_000),
+            },
+            // Eurotransplant Kidney Allocation System: HLA match and travel distance dominate
+            AllocationProfile {
+                profile_id: "ETKAS".to_string(),
+                name: "Eurotransplant Kidney Allocation System".to_string(),
+                network_ids: vec!["Eurotransplant".to_string()],
+                jurisdictions: vec!["DE".to_string(), "NL".to_string(), "BE".to_string(), "AT".to_string()],
+                compatibility_weight: 0.5,
+                urgency_weight: 0.3,
+                survival_benefit_weight: 0.2,
+                distance_penalty_per_100km: 0.05,
+                max_distance_km: None,
+            },
+        ] {
+            profiles.insert(profile.profile_id.clone(), profile);
+        }
+        profiles
+    });
+}
+
+const DEFAULT_PROFILE_ID: &str = "DEFAULT";
+
+// Fallback when no regional policy applies
+fn default_profile() -> AllocationProfile {
+    AllocationProfile {
+        profile_id: DEFAULT_PROFILE_ID.to_string(),
+        name: "Compatibility and urgency".to_string(),
+        network_ids: vec![],
+        jurisdictions: vec![],
+        compatibility_weight: 0.5,
+        urgency_weight: 0.5,
+        survival_benefit_weight: 0.0,
+        distance_penalty_per_100km: 0.0,
+        max_distance_km: None,
+    }
+}
+
+#[update]
+fn set_allocation_profile(profile: AllocationProfile) -> Result<(), String> {
+    governance::authorize_direct_change(caller())?;
+    validate_profile(&profile)?;
+    install_profile(profile);
+    Ok(())
+}
+
+pub(crate) fn validate_profile(profile: &AllocationProfile) -> Result<(), String> {
+    let weights = [
+        profile.compatibility_weight,
+        profile.urgency_weight,
+        profile.survival_benefit_weight,
+        profile.distance_penalty_per_100km,
+    ];
+    if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+        return Err("Allocation weights must be finite and non-negative".to_string());
+    }
+    Ok(())
+}
+
+pub(crate) fn install_profile(profile: AllocationProfile) {
+    ALLOCATION_PROFILES.with(|profiles| {
+        profiles.borrow_mut().insert(profile.profile_id.clone(), profile);
+    });
+}
+
+#[query]
+fn get_allocation_profiles() -> Vec<AllocationProfile> {
+    ALLOCATION_PROFILES.with(|profiles| profiles.borrow().values().cloned().collect())
+}
+
+// Score candidate matches under an explicit profile without sending any offers
+#[query]
+fn preview_allocation(profile_id: String, candidates: Vec<RecipientMatch>) -> Result<Vec<RecipientMatch>, String> {
+    validation::collection("candidates", candidates.len(), validation::MAX_ALLOCATION_CANDIDATES)?;
+    let profile = ALLOCATION_PROFILES.with(|profiles| profiles.borrow().get(&profile_id).cloned())
+        .ok_or_else(|| format!("Unknown allocation profile: {}", profile_id))?;
+
+    let mut ranked: Vec<RecipientMatch> = candidates
+        .into_iter()
+        .filter_map(|mut m| {
+            m.allocation_score = score(&profile, &m)?;
+            m.allocation_profile = profile.profile_id.clone();
+            Some(m)
+        })
+        .collect();
+    sort_by_allocation_score(&mut ranked);
+    Ok(ranked)
+}
+
+// A network-specific profile wins over a jurisdiction match; otherwise the default applies
+pub(crate) fn profile_for(network: Option<&OrganNetwork>) -> AllocationProfile {
+    ALLOCATION_PROFILES.with(|profiles| {
+        let profiles = profiles.borrow();
+        network
+            .and_then(|n| {
+                profiles.values()
+                    .find(|p| p.network_ids.contains(&n.network_id))
+                    .or_else(|| profiles.values().find(|p| p.jurisdictions.iter().any(|j| n.jurisdictions.contains(j))))
+            })
+            .or_else(|| profiles.get(DEFAULT_PROFILE_ID))
+            .cloned()
+            .unwrap_or_else(default_profile)
+    })
+}
+
+// None means the match is ineligible under this profile
+pub(crate) fn score(profile: &AllocationProfile, candidate: &RecipientMatch) -> Option<f32> {
+    if let Some(max_distance) = profile.max_distance_km {
+        if candidate.distance_km > max_distance {
+            return None;
+        }
+    }
+
+    let urgency = (4 - candidate.urgency_level.clamp(1, 3)) as f32 / 3.0;
+    let score = candidate.compatibility_score * profile.compatibility_weight
+        + urgency * profile.urgency_weight
+        + candidate.estimated_survival_benefit * profile.survival_benefit_weight
+        - (candidate.distance_km as f32 / 100.0) * profile.distance_penalty_per_100km;
+    Some(score)
+}
+
+// Score each match under the profile of the network serving its transplant center
+pub(crate) fn apply_regional_profiles(candidates: Vec<RecipientMatch>) -> Vec<RecipientMatch> {
+    let mut ranked: Vec<RecipientMatch> = candidates
+        .into_iter()
+        .filter_map(|mut m| {
+            let network = networks::network_for_center(&m.transplant_center);
+            let profile = profile_for(network.as_ref());
+            match score(&profile, &m) {
+                Some(score) => {
+                    // Centers that declared themselves unavailable drop down the ranking
+                    m.allocation_score = score - capacity::availability_penalty(&m.transplant_center);
+                    m.allocation_profile = profile.profile_id;
+                    Some(m)
+                }
+                None => {
+                    ic_cdk::println!(
+                        "⚖️ {} excluded under {} - {} km exceeds distance limit",
+                        m.recipient_id,
+                        profile.profile_id,
+                        m.distance_km
+                    );
+                    None
+                }
+            }
+        })
+        .collect();
+    sort_by_allocation_score(&mut ranked);
+    ranked
+}
+
+// total_cmp keeps the order total when a score comes out NaN; a comparator that calls NaN equal
+// to everything is not an order, and the sort may panic on it
+fn sort_by_allocation_score(matches: &mut [RecipientMatch]) {
+    matches.sort_by(|a, b| b.allocation_score.total_cmp(&a.allocation_score));
+}
@@ -0,0 +1,130 @@
+use ic_cdk_macros::query;
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::cell::RefCell;
+
+use crate::clock;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub event_type: String,
+    pub reference_id: String,
+    pub payload_hash: Vec<u8>,
+    pub previous_hash: Vec<u8>,
+    pub entry_hash: Vec<u8>,
+}
+
+thread_local! {
+    static AUDIT_CHAIN: RefCell<Vec<AuditEntry>> = RefCell::new(Vec::new());
+}
+
+// Append an entry whose hash commits to the payload and to the previous entry
+pub(crate) fn append_audit_entry(event_type: &str, reference_id: &str, payload: &[u8]) -> AuditEntry {
+    AUDIT_CHAIN.with(|chain| {
+        let mut chain = chain.borrow_mut();
+        let previous_hash = chain.last().map(|e| e.entry_hash.clone()).unwrap_or_else(|| vec![0; 32]);
+        let sequence = chain.len() as u64;
+        let timestamp = clock::now();
+        let payload_hash = ic_cdk::api::sha256(payload);
+        let entry_hash = hash_entry(sequence, timestamp, event_type, reference_id, &payload_hash, &previous_hash);
+
+        let entry = AuditEntry {
+            sequence,
+            timestamp,
+            event_type: event_type.to_string(),
+            reference_id: reference_id.to_string(),
+            payload_hash,
+            previous_hash,
+            entry_hash,
+        };
+        chain.push(entry.clone());
+        entry
+    })
+}
+
+// Variable-length fields are length-prefixed, so moving bytes between event type and reference
+// ("AB"+"C" vs "A"+"BC") changes the hash
+pub(crate) fn hash_entry(
+    sequence: u64,
+    timestamp: u64,
+    event_type: &str,
+    reference_id: &str,
+    payload_hash: &[u8],
+    previous_hash: &[u8]
+) -> Vec<u8> {
+    let mut material = Vec::new();
+    material.extend_from_slice(&sequence.to_be_bytes());
+    material.extend_from_slice(&timestamp.to_be_bytes());
+    for field in [event_type.as_bytes(), reference_id.as_bytes(), payload_hash, previous_hash] {
+        material.extend_from_slice(&(field.len() as u64).to_be_bytes());
+        material.extend_from_slice(field);
+    }
+    ic_cdk::api::sha256(&material)
+}
+
+pub(crate) fn entries_for(reference_id: &str) -> Vec<AuditEntry> {
+    AUDIT_CHAIN.with(|chain| {
+        chain.borrow()
+            .iter()
+            .filter(|e| e.reference_id == reference_id)
+            .cloned()
+            .collect()
+    })
+}
+
+#[query]
+pub(crate) fn get_audit_chain(offset: u64, limit: u32) -> Vec<AuditEntry> {
+    AUDIT_CHAIN.with(|chain| {
+        chain.borrow()
+            .iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    })
+}
+
+// Recompute every link; returns the first broken sequence number, if any
+#[query]
+pub(crate) fn verify_audit_chain() -> Result<u64, String> {
+    AUDIT_CHAIN.with(|chain| {
+        let chain = chain.borrow();
+        let mut previous_hash = vec![0; 32];
+        for entry in chain.iter() {
+            let expected = hash_entry(
+                entry.sequence,
+                entry.timestamp,
+                &entry.event_type,
+                &entry.reference_id,
+                &entry.payload_hash,
+                &previous_hash,
+            );
+            if entry.previous_hash != previous_hash || entry.entry_hash != expected {
+                return Err(format!("Audit chain broken at sequence {}", entry.sequence));
+            }
+            previous_hash = entry.entry_hash.clone();
+        }
+        Ok(chain.len() as u64)
+    })
+}
+
+// Carried across upgrades with the event log, so verification continues the chain rather than restarting it
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct AuditState {
+    chain: Vec<AuditEntry>,
+}
+
+pub(crate) fn snapshot() -> AuditState {
+    AuditState {
+        chain: AUDIT_CHAIN.with(|a| a.borrow().clone()),
+    }
+}
+
+pub(crate) fn restore(state: Option<AuditState>) {
+    let Some(state) = state else {
+        return;
+    };
+    AUDIT_CHAIN.with(|a| *a.borrow_mut() = state.chain);
+}
@@ -0,0 +1,136 @@
+use canbench_rs::{bench, bench_fn, BenchResult};
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+use crate::{allocation, audit, find_optimal_recipients, OrganAvailability, RecipientMatch};
+
+// Ceilings for the hot paths, well inside the per-message limit; tighten them as
+// canbench_results.yml settles rather than raising them to make a regression pass
+const BUDGET_MATCH_100: u64 = 20_000_000;
+const BUDGET_MATCH_1000: u64 = 250_000_000;
+const BUDGET_AUDIT_APPEND_1000: u64 = 400_000_000;
+const BUDGET_AUDIT_VERIFY_1000: u64 = 400_000_000;
+const BUDGET_AUDIT_PAGE: u64 = 10_000_000;
+const BUDGET_FULL_MATCH: u64 = 20_000_000;
+
+fn candidates(count: usize) -> Vec<RecipientMatch> {
+    (0..count)
+        .map(|i| RecipientMatch {
+            recipient_id: format!("RECIPIENT_{:05}", i),
+            organ: "kidney".to_string(),
+            compatibility_score: 0.5 + (i % 50) as f32 / 100.0,
+            urgency_level: (i % 3) as u8 + 1,
+            distance_km: (i as u32 * 37) % 1_500,
+            transplant_center: format!("CENTER_{:03}", i % 40),
+            notification_sent: false,
+            estimated_survival_benefit: 0.4 + (i % 60) as f32 / 100.0,
+            allocation_profile: String::new(),
+            allocation_score: 0.0,
+            kdpi: None,
+            epts: None,
+        })
+        .collect()
+}
+
+fn donor_organs() -> Vec<OrganAvailability> {
+    ["kidney_left", "kidney_right", "liver"]
+        .iter()
+        .map(|organ| OrganAvailability {
+            organ_type: organ.to_string(),
+            blood_type: "O+".to_string(),
+            hla_typing: vec!["A2".to_string(), "B44".to_string(), "DR4".to_string()],
+            organ_condition: "GOOD".to_string(),
+            time_since_harvest: 0,
+            location: "BENCH_HOSPITAL".to_string(),
+            viability_score: 0.9,
+            kdpi: organ.starts_with("kidney").then_some(0.35),
+        })
+        .collect()
+}
+
+// The matcher makes no calls, so one poll finishes it
+fn run_to_completion<F: Future>(future: F) -> F::Output {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("benchmark waited on a call"),
+    }
+}
+
+fn seed_audit_chain(entries: usize) {
+    for i in 0..entries {
+        audit::append_audit_entry("DIRECTIVE_EXECUTED", &format!("EXEC_{}", i), format!("payload {}", i).as_bytes());
+    }
+}
+
+fn within_budget(name: &str, result: BenchResult, budget: u64) -> BenchResult {
+    assert!(
+        result.total.instructions <= budget,
+        "{} used {} instructions, budget is {}",
+        name,
+        result.total.instructions,
+        budget
+    );
+    result
+}
+
+#[bench(raw)]
+fn match_run_100_recipients() -> BenchResult {
+    let pool = candidates(100);
+    within_budget(
+        "match_run_100_recipients",
+        bench_fn(|| allocation::apply_regional_profiles(pool)),
+        BUDGET_MATCH_100,
+    )
+}
+
+#[bench(raw)]
+fn match_run_1000_recipients() -> BenchResult {
+    let pool = candidates(1_000);
+    within_budget(
+        "match_run_1000_recipients",
+        bench_fn(|| allocation::apply_regional_profiles(pool)),
+        BUDGET_MATCH_1000,
+    )
+}
+
+#[bench(raw)]
+fn audit_append_1000_entries() -> BenchResult {
+    within_budget(
+        "audit_append_1000_entries",
+        bench_fn(|| seed_audit_chain(1_000)),
+        BUDGET_AUDIT_APPEND_1000,
+    )
+}
+
+#[bench(raw)]
+fn audit_verify_1000_entries() -> BenchResult {
+    seed_audit_chain(1_000);
+    within_budget(
+        "audit_verify_1000_entries",
+        bench_fn(|| audit::verify_audit_chain().expect("seeded chain must verify")),
+        BUDGET_AUDIT_VERIFY_1000,
+    )
+}
+
+#[bench(raw)]
+fn audit_page_from_1000_entries() -> BenchResult {
+    seed_audit_chain(1_000);
+    within_budget(
+        "audit_page_from_1000_entries",
+        bench_fn(|| audit::get_audit_chain(900, 100)),
+        BUDGET_AUDIT_PAGE,
+    )
+}
+
+// Everything find_optimal_recipients does for one donor: candidate lookup, virtual crossmatch,
+// multi-organ reservation, EPTS and the regional ranking
+#[bench(raw)]
+fn full_match_run() -> BenchResult {
+    let organs = donor_organs();
+    within_budget(
+        "full_match_run",
+        bench_fn(|| run_to_completion(find_optimal_recipients("BENCH_DONOR", &organs)).expect("match run failed")),
+        BUDGET_FULL_MATCH,
+    )
+}
@@ -0,0 +1,375 @@
+use ic_cdk::{call, caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::reconciliation::Outbound;
+use crate::{
+    audit, clock, derive_patient_hash, disposition, governance, ids, resilience, traps, viability, ContactEvent,
+    ContactNotification, DirectiveExecution, DIRECTIVE_MANAGER_CANISTER_ID,
+};
+
+// Whole-body donation to an anatomical program (a medical school's willed-body program) is not a
+// transplant: the program takes the whole body, so it usually cannot follow organ recovery. The
+// precedence policy decides which one runs when a patient asked for both. Programs are offered the
+// body one at a time, in registration order; a decline or a missed deadline moves to the next one.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AnatomicalProgram {
+    pub program_id: String,
+    pub name: String,
+    pub principal: Option<Principal>,
+    pub accepts_after_organ_recovery: bool,
+    pub max_age_years: Option<u8>,
+    pub max_weight_kg: Option<f32>,
+    pub active: bool,
+}
+
+// A screen result that rules the body out for every program
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct BodyDonationExclusion {
+    pub pathogen: String,
+    pub excluded_results: Vec<String>, // "POSITIVE", "INDETERMINATE"
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct BodyDonationReferral {
+    pub referral_id: String,
+    pub patient_id: String,
+    pub program_id: Option<String>, // the program holding the current offer
+    pub remaining_programs: Vec<String>,
+    pub declined_by: Vec<String>,
+    pub offered_at: u64,
+    pub respond_by: u64,
+    pub status: String, // "OFFERED", "ACCEPTED", "NO_PROGRAM_ACCEPTED"
+    pub decline_reasons: Vec<String>,
+}
+
+thread_local! {
+    static ANATOMICAL_PROGRAMS: RefCell<Vec<AnatomicalProgram>> = RefCell::new(Vec::new());
+    static EXCLUSIONS: RefCell<Vec<BodyDonationExclusion>> = RefCell::new(
+        ["HIV", "HBV", "HCV", "TB", "CJD"].into_iter().map(|pathogen| BodyDonationExclusion {
+            pathogen: pathogen.to_string(),
+            excluded_results: vec!["POSITIVE".to_string(), "INDETERMINATE".to_string()],
+        }).collect()
+    );
+    static PRECEDENCE: RefCell<String> = RefCell::new("ORGAN_FIRST".to_string());
+    static BODY_DONATION_REFERRALS: RefCell<BTreeMap<String, BodyDonationReferral>> = RefCell::new(BTreeMap::new());
+}
+
+pub(crate) const DIRECTIVE_TYPE: &str = "WHOLE_BODY_DONATION";
+const PRECEDENCE_POLICIES: [&str; 2] = ["ORGAN_FIRST", "BODY_FIRST"];
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+// Programs need the body within a day or two, so each one gets a short window to answer
+const RESPONSE_WINDOW_HOURS: u64 = 6;
+const MAX_PROGRAMS: usize = 100;
+
+#[update]
+fn register_anatomical_program(program: AnatomicalProgram) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage anatomical programs".to_string());
+    }
+    let program = AnatomicalProgram { program_id: program.program_id.trim().to_uppercase(), ..program };
+    ANATOMICAL_PROGRAMS.with(|p| {
+        let mut programs = p.borrow_mut();
+        if let Some(existing) = programs.iter_mut().find(|x| x.program_id == program.program_id) {
+            *existing = program;
+            return Ok(());
+        }
+        if programs.len() >= MAX_PROGRAMS {
+            return Err("Too many anatomical programs are registered".to_string());
+        }
+        programs.push(program);
+        Ok(())
+    })
+}
+
+#[update]
+fn set_anatomical_program_active(program_id: String, active: bool) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage anatomical programs".to_string());
+    }
+    ANATOMICAL_PROGRAMS.with(|p| {
+        let mut programs = p.borrow_mut();
+        let program = programs.iter_mut().find(|x| x.program_id == program_id)
+            .ok_or_else(|| format!("Unknown anatomical program: {}", program_id))?;
+        program.active = active;
+        Ok(())
+    })
+}
+
+#[query]
+fn get_anatomical_programs() -> Vec<AnatomicalProgram> {
+    ANATOMICAL_PROGRAMS.with(|p| p.borrow().clone())
+}
+
+#[update]
+fn set_body_donation_exclusions(exclusions: Vec<BodyDonationExclusion>) -> Result<(), String> {
+    governance::authorize_direct_change(caller())?;
+    let exclusions = exclusions.into_iter().map(|e| BodyDonationExclusion {
+        pathogen: e.pathogen.trim().to_uppercase(),
+        excluded_results: e.excluded_results.iter().map(|r| r.trim().to_uppercase()).collect(),
+    }).collect();
+    EXCLUSIONS.with(|e| *e.borrow_mut() = exclusions);
+    Ok(())
+}
+
+#[query]
+fn get_body_donation_exclusions() -> Vec<BodyDonationExclusion> {
+    EXCLUSIONS.with(|e| e.borrow().clone())
+}
+
+#[update]
+fn set_body_donation_precedence(policy: String) -> Result<(), String> {
+    governance::authorize_direct_change(caller())?;
+    let policy = policy.trim().to_uppercase();
+    if !PRECEDENCE_POLICIES.contains(&policy.as_str()) {
+        return Err(format!("Precedence must be one of {}", PRECEDENCE_POLICIES.join(", ")));
+    }
+    PRECEDENCE.with(|p| *p.borrow_mut() = policy);
+    Ok(())
+}
+
+#[query]
+fn get_body_donation_precedence() -> String {
+    PRECEDENCE.with(|p| p.borrow().clone())
+}
+
+#[query]
+fn get_body_donation_referrals(patient_id: String) -> Vec<BodyDonationReferral> {
+    BODY_DONATION_REFERRALS.with(|r| r.borrow().values().filter(|x| x.patient_id == patient_id).cloned().collect())
+}
+
+// The program holding the offer accepts the body or passes it on
+#[update]
+fn respond_to_body_donation_offer(referral_id: String, accept: bool, reason: Option<String>) -> Result<BodyDonationReferral, String> {
+    let program_id = program_for_principal(caller()).ok_or("Caller does not represent an anatomical program")?;
+    let referral = BODY_DONATION_REFERRALS.with(|r| {
+        let mut referrals = r.borrow_mut();
+        let referral = referrals.get_mut(&referral_id)
+            .ok_or_else(|| format!("Body donation referral not found: {}", referral_id))?;
+        if referral.status != "OFFERED" || referral.program_id.as_ref() != Some(&program_id) {
+            return Err("This program does not hold the current offer".to_string());
+        }
+        if accept {
+            referral.status = "ACCEPTED".to_string();
+        } else {
+            referral.decline_reasons.push(format!("{}: {}", program_id, reason.unwrap_or_else(|| "declined".to_string())));
+            advance(referral);
+        }
+        Ok(referral.clone())
+    })?;
+
+    if let Ok(payload) = serde_json::to_vec(&referral) {
+        let kind = if accept { "BODY_DONATION_ACCEPTED" } else { "BODY_DONATION_DECLINED" };
+        audit::append_audit_entry(kind, &referral.referral_id, &payload);
+    }
+    after_transition(&referral);
+    Ok(referral)
+}
+
+// Asked for either as its own directive or as the disposition method
+pub(crate) async fn requested(patient_id: &str, directives: &[String]) -> bool {
+    if directives.iter().any(|d| d == DIRECTIVE_TYPE) {
+        return true;
+    }
+    matches!(
+        disposition::fetch_preferences(patient_id).await,
+        Ok(Some(preferences)) if preferences.method == "BODY_DONATION"
+    )
+}
+
+// Whether whole-body donation should displace organ and tissue recovery for this donor
+pub(crate) fn takes_precedence(patient_id: &str) -> bool {
+    PRECEDENCE.with(|p| p.borrow().as_str() == "BODY_FIRST")
+        && eligible_programs(patient_id, false).is_ok_and(|programs| !programs.is_empty())
+}
+
+// Screens the donor and offers the body to the first eligible program
+pub(crate) fn execute_body_donation(patient_id: &str, organs_recovered: bool) -> DirectiveExecution {
+    ic_cdk::println!("🎓 Executing whole-body donation for patient: {}", patient_id);
+    let programs = match eligible_programs(patient_id, organs_recovered) {
+        Ok(programs) if programs.is_empty() => {
+            let status = if organs_recovered { "EXCLUDED_BY_ORGAN_RECOVERY" } else { "NO_ELIGIBLE_PROGRAM" };
+            return step(status, vec![]);
+        }
+        Ok(programs) => programs,
+        Err(reason) => {
+            ic_cdk::println!("⚠️ Whole-body donation screened out for {}: {}", patient_id, reason);
+            return step("INELIGIBLE", vec![]);
+        }
+    };
+
+    let now = clock::now();
+    let mut referral = BodyDonationReferral {
+        referral_id: ids::new_id("BODY"),
+        patient_id: patient_id.to_string(),
+        program_id: None,
+        remaining_programs: programs,
+        declined_by: vec![],
+        offered_at: now,
+        respond_by: now,
+        status: "OFFERED".to_string(),
+        decline_reasons: vec![],
+    };
+    advance(&mut referral);
+    let offered_to = referral.program_id.clone();
+    if let Ok(payload) = serde_json::to_vec(&referral) {
+        audit::append_audit_entry("BODY_DONATION_OFFERED", &referral.referral_id, &payload);
+    }
+    BODY_DONATION_REFERRALS.with(|r| r.borrow_mut().insert(referral.referral_id.clone(), referral));
+
+    step("OFFERED", offered_to.into_iter().collect())
+}
+
+// Donor data is required: without infection screens the body cannot be cleared for any program
+fn eligible_programs(patient_id: &str, organs_recovered: bool) -> Result<Vec<String>, String> {
+    let donor = viability::donor_data(patient_id)
+        .ok_or_else(|| format!("No donor clinical data submitted for patient: {}", patient_id))?;
+    let excluded = EXCLUSIONS.with(|e| {
+        e.borrow().iter().find(|rule| {
+            donor.infection_screens.iter().any(|s| s.pathogen == rule.pathogen && rule.excluded_results.contains(&s.result))
+        }).map(|rule| rule.pathogen.clone())
+    });
+    if let Some(pathogen) = excluded {
+        return Err(format!("{} screen excludes whole-body donation", pathogen));
+    }
+    Ok(ANATOMICAL_PROGRAMS.with(|p| {
+        p.borrow()
+            .iter()
+            .filter(|x| x.active && (x.accepts_after_organ_recovery || !organs_recovered))
+            .filter(|x| x.max_age_years.is_none_or(|max| donor.age_years <= max))
+            .filter(|x| x.max_weight_kg.is_none_or(|max| donor.weight_kg.is_none_or(|w| w <= max)))
+            .map(|x| x.program_id.clone())
+            .collect()
+    }))
+}
+
+// Moves the offer to the next program in line, or closes the referral when none are left
+fn advance(referral: &mut BodyDonationReferral) {
+    if let Some(current) = referral.program_id.take() {
+        referral.declined_by.push(current);
+    }
+    if referral.remaining_programs.is_empty() {
+        referral.status = "NO_PROGRAM_ACCEPTED".to_string();
+        return;
+    }
+    let next = referral.remaining_programs.remove(0);
+    let now = clock::now();
+    referral.offered_at = now;
+    referral.respond_by = now + RESPONSE_WINDOW_HOURS * NANOS_PER_HOUR;
+    referral.program_id = Some(next.clone());
+    ic_cdk::println!("🎓 BODY DONATION OFFER: {} offered to {}", referral.referral_id, next);
+
+    let referral_id = referral.referral_id.clone();
+    ic_cdk_timers::set_timer(Duration::from_nanos(RESPONSE_WINDOW_HOURS * NANOS_PER_HOUR), move || {
+        expire_offer(&referral_id, &next);
+    });
+}
+
+fn expire_offer(referral_id: &str, program_id: &str) {
+    let referral = BODY_DONATION_REFERRALS.with(|r| {
+        let mut referrals = r.borrow_mut();
+        let referral = referrals.get_mut(referral_id)?;
+        if referral.status != "OFFERED" || referral.program_id.as_deref() != Some(program_id) {
+            return None;
+        }
+        referral.decline_reasons.push(format!("{}: no response before deadline", program_id));
+        advance(referral);
+        Some(referral.clone())
+    });
+    if let Some(referral) = referral {
+        after_transition(&referral);
+    }
+}
+
+// Next-of-kin hear once the outcome is settled, since it changes the funeral arrangements
+fn after_transition(referral: &BodyDonationReferral) {
+    let summary = match referral.status.as_str() {
+        "ACCEPTED" => "Whole-body donation accepted",
+        "NO_PROGRAM_ACCEPTED" => "Whole-body donation could not be placed",
+        _ => return,
+    };
+    let program_name = referral.program_id.as_ref().and_then(|id| {
+        ANATOMICAL_PROGRAMS.with(|p| p.borrow().iter().find(|x| &x.program_id == id).map(|x| x.name.clone()))
+    });
+    let event = ContactEvent {
+        event_type: "BODY_DONATION_UPDATE".to_string(),
+        reference_id: referral.referral_id.clone(),
+        summary: summary.to_string(),
+        details: match program_name {
+            Some(name) => format!("{} will contact the family about transport", name),
+            None => "The family should proceed with other funeral arrangements".to_string(),
+        },
+    };
+    let patient_id = referral.patient_id.clone();
+    ic_cdk::spawn(async move {
+        let _watch = traps::Watch::start("BODY_DONATION_NOTICE");
+        if let Err(e) = notify_family(&patient_id, event).await {
+            ic_cdk::println!("⚠️ Body donation notice to next-of-kin failed: {}", e);
+        }
+    });
+}
+
+async fn notify_family(patient_id: &str, event: ContactEvent) -> Result<(), String> {
+    let patient_id_hash = derive_patient_hash(patient_id).await?;
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let _: (Vec<ContactNotification>,) = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call(directive_manager_id, "notify_contacts", (patient_id_hash.clone(), event.clone()))
+    }).await?;
+    Ok(())
+}
+
+fn step(status: &str, programs: Vec<String>) -> DirectiveExecution {
+    DirectiveExecution {
+        directive_type: DIRECTIVE_TYPE.to_string(),
+        execution_status: status.to_string(),
+        organs_processed: vec![],
+        recipient_matches: vec![],
+        total_recipients_notified: programs.len() as u32,
+        estimated_lives_saved: 0,
+        data_shared_with: programs,
+        anonymization_verified: false,
+        research_impact_score: 0.0,
+    }
+}
+
+fn program_for_principal(principal: Principal) -> Option<String> {
+    ANATOMICAL_PROGRAMS.with(|p| {
+        p.borrow().iter().find(|x| x.principal == Some(principal)).map(|x| x.program_id.clone())
+    })
+}
+
+// One entry per program offered the body; a program that let its deadline pass never acted
+pub(crate) fn outbound_between(from: u64, to: u64) -> Vec<Outbound> {
+    let referrals: Vec<BodyDonationReferral> = BODY_DONATION_REFERRALS.with(|r| {
+        r.borrow().values().filter(|x| x.offered_at >= from && x.offered_at < to).cloned().collect()
+    });
+    let mut outbound = Vec::new();
+    for referral in referrals {
+        let mut offered: Vec<(&String, bool)> = referral.declined_by.iter()
+            .map(|program_id| {
+                let lapsed = format!("{}: no response before deadline", program_id);
+                (program_id, !referral.decline_reasons.contains(&lapsed))
+            })
+            .collect();
+        // The current holder is only settled once it accepts
+        if let (Some(program_id), "ACCEPTED") = (&referral.program_id, referral.status.as_str()) {
+            offered.push((program_id, true));
+        }
+        outbound.extend(offered.into_iter().map(|(program_id, acted)| Outbound {
+            channel: "BODY_DONATION_OFFER",
+            reference_id: referral.referral_id.clone(),
+            counterparty: program_id.clone(),
+            sent_at: referral.offered_at,
+            delivered: true,
+            acted,
+            detail: None,
+        }));
+    }
+    outbound
+}
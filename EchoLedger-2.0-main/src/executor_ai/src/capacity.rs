@@ -0,0 +1,105 @@
+use ic_cdk::{caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::clock;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CenterCapacity {
+    pub transplant_center: String,
+    pub status: String, // "OPEN", "LIMITED", "CLOSED"
+    pub operating_rooms_available: u32,
+    pub surgeon_on_call: bool,
+    pub note: Option<String>,
+    pub updated_at: u64,
+}
+
+thread_local! {
+    // Principal allowed to publish for each transplant center
+    static CENTER_PUBLISHERS: RefCell<BTreeMap<Principal, String>> = RefCell::new(BTreeMap::new());
+    static CENTER_CAPACITY: RefCell<BTreeMap<String, CenterCapacity>> = RefCell::new(BTreeMap::new());
+}
+
+const CAPACITY_STATUSES: [&str; 3] = ["OPEN", "LIMITED", "CLOSED"];
+// Declarations older than this are ignored rather than trusted
+const CAPACITY_TTL_NANOS: u64 = 12 * 60 * 60 * 1_000_000_000;
+
+#[update]
+fn register_center_publisher(publisher: Principal, transplant_center: String) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may register center publishers".to_string());
+    }
+    CENTER_PUBLISHERS.with(|p| p.borrow_mut().insert(publisher, transplant_center));
+    Ok(())
+}
+
+#[update]
+fn publish_center_capacity(mut capacity: CenterCapacity) -> Result<(), String> {
+    let center = CENTER_PUBLISHERS.with(|p| p.borrow().get(&caller()).cloned())
+        .ok_or("Caller is not a registered transplant center publisher")?;
+    if center != capacity.transplant_center {
+        return Err(format!("Caller may only publish capacity for {}", center));
+    }
+    if !CAPACITY_STATUSES.contains(&capacity.status.as_str()) {
+        return Err(format!("Unknown capacity status: {}", capacity.status));
+    }
+
+    capacity.updated_at = clock::now();
+    CENTER_CAPACITY.with(|c| {
+        c.borrow_mut().insert(center, capacity);
+    });
+    Ok(())
+}
+
+#[query]
+fn get_center_capacity() -> Vec<CenterCapacity> {
+    CENTER_CAPACITY.with(|c| c.borrow().values().cloned().collect())
+}
+
+fn current(transplant_center: &str) -> Option<CenterCapacity> {
+    let now = clock::now();
+    CENTER_CAPACITY.with(|c| {
+        c.borrow()
+            .get(transplant_center)
+            .filter(|cap| now.saturating_sub(cap.updated_at) <= CAPACITY_TTL_NANOS)
+            .cloned()
+    })
+}
+
+// Score reduction applied during ranking; centers without a fresh declaration are not penalized
+pub(crate) fn availability_penalty(transplant_center: &str) -> f32 {
+    match current(transplant_center) {
+        Some(cap) if cap.status == "CLOSED" => 1.0,
+        Some(cap) if cap.status == "LIMITED" || !cap.surgeon_on_call || cap.operating_rooms_available == 0 => 0.25,
+        _ => 0.0,
+    }
+}
+
+pub(crate) fn is_closed(transplant_center: &str) -> bool {
+    current(transplant_center).is_some_and(|cap| cap.status == "CLOSED")
+}
+
+// Carried across upgrades with the event log
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct CapacityState {
+    publishers: BTreeMap<Principal, String>,
+    capacity: BTreeMap<String, CenterCapacity>,
+}
+
+pub(crate) fn snapshot() -> CapacityState {
+    CapacityState {
+        publishers: CENTER_PUBLISHERS.with(|c| c.borrow().clone()),
+        capacity: CENTER_CAPACITY.with(|c| c.borrow().clone()),
+    }
+}
+
+pub(crate) fn restore(state: Option<CapacityState>) {
+    let Some(state) = state else {
+        return;
+    };
+    CENTER_PUBLISHERS.with(|c| *c.borrow_mut() = state.publishers);
+    CENTER_CAPACITY.with(|c| *c.borrow_mut() = state.capacity);
+}
@@ -0,0 +1,213 @@
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{audit, clock, ids, networks};
+
+// Transplant centers register an X25519 public key, and offers to them are sealed to it before they
+// leave the canister: a fresh ephemeral key per offer, SHA-256 over the shared secret and both
+// public keys for the message key, ChaCha20-Poly1305 for the body. Gateways and relays between us
+// and the center carry ciphertext only. Nothing goes to a center's endpoint in the clear: until the
+// center registers a key, deliveries to it are refused and the offer moves on as undeliverable.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CenterKey {
+    pub key_id: String,
+    pub public_key: Vec<u8>,
+    pub registered_by: Principal,
+    pub registered_at: u64,
+    pub retired_at: Option<u64>,
+}
+
+// The operator is the center's own principal, which may rotate the key without a controller
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CenterKeyring {
+    pub transplant_center: String,
+    pub operator: Principal,
+    pub keys: Vec<CenterKey>,
+}
+
+// The body posted in place of the plaintext offer
+#[derive(Serialize)]
+struct SealedOffer<'a> {
+    scheme: &'a str,
+    key_id: &'a str,
+    ephemeral_public_key: String,
+    content_type: &'a str,
+    ciphertext: String,
+}
+
+thread_local! {
+    static CENTER_KEYRINGS: RefCell<BTreeMap<String, CenterKeyring>> = RefCell::new(BTreeMap::new());
+}
+
+pub(crate) const SEALED_CONTENT_TYPE: &str = "application/vnd.echoledger.sealed+json";
+const SEALING_SCHEME: &str = "X25519-SHA256-CHACHA20POLY1305";
+const KEY_DERIVATION_DOMAIN: &[u8] = b"echoledger-offer-seal-v1";
+const MAX_RETIRED_KEYS: usize = 4;
+
+#[update]
+fn register_transplant_center_key(
+    transplant_center: String,
+    operator: Principal,
+    public_key: Vec<u8>
+) -> Result<CenterKeyring, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may enroll transplant center keys".to_string());
+    }
+    if networks::network_for_center(&transplant_center).is_none() {
+        return Err(format!("No active network serves {}", transplant_center));
+    }
+    let public_key = valid_public_key(public_key)?;
+    let keyring = CENTER_KEYRINGS.with(|k| {
+        let mut keyrings = k.borrow_mut();
+        let keyring = keyrings.entry(transplant_center.clone()).or_insert_with(|| CenterKeyring {
+            transplant_center: transplant_center.clone(),
+            operator,
+            keys: Vec::new(),
+        });
+        keyring.operator = operator;
+        install(keyring, public_key);
+        keyring.clone()
+    });
+    audit::append_audit_entry("CENTER_KEY_REGISTERED", &transplant_center, &current_public_key(&keyring));
+    Ok(keyring)
+}
+
+// The center, or a controller on its behalf, replaces the current key; the old one is kept as retired
+#[update]
+fn rotate_transplant_center_key(transplant_center: String, public_key: Vec<u8>) -> Result<CenterKeyring, String> {
+    let public_key = valid_public_key(public_key)?;
+    let requester = caller();
+    let keyring = CENTER_KEYRINGS.with(|k| {
+        let mut keyrings = k.borrow_mut();
+        let keyring = keyrings.get_mut(&transplant_center)
+            .ok_or_else(|| format!("No key registered for {}", transplant_center))?;
+        if requester != keyring.operator && !ic_cdk::api::is_controller(&requester) {
+            return Err("Only the center's operator or a controller may rotate its key".to_string());
+        }
+        install(keyring, public_key);
+        Ok(keyring.clone())
+    })?;
+    audit::append_audit_entry("CENTER_KEY_ROTATED", &transplant_center, &current_public_key(&keyring));
+    Ok(keyring)
+}
+
+#[query]
+fn get_transplant_center_keyring(transplant_center: String) -> Option<CenterKeyring> {
+    CENTER_KEYRINGS.with(|k| k.borrow().get(&transplant_center).cloned())
+}
+
+// The offer body sealed to the center's current key; a center without one cannot be sent anything
+pub(crate) async fn seal(transplant_center: &str, content_type: &str, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let key = current_key(transplant_center)
+        .ok_or_else(|| format!("{} has no sealing key registered; refusing to send it PHI in the clear", transplant_center))?;
+    let (seed,) = raw_rand().await.map_err(|(_, msg)| format!("Failed to draw ephemeral key: {}", msg))?;
+    let seed: [u8; 32] = seed.as_slice().try_into().map_err(|_| "Unexpected randomness length".to_string())?;
+    seal_to(transplant_center, &key, seed, content_type, plaintext)
+}
+
+// Seals under an ephemeral key derived from the seed; the seed must never be used twice
+pub(crate) fn seal_to(
+    transplant_center: &str,
+    key: &CenterKey,
+    ephemeral_seed: [u8; 32],
+    content_type: &str,
+    plaintext: &[u8]
+) -> Result<Vec<u8>, String> {
+    let recipient: [u8; 32] = key.public_key.as_slice().try_into()
+        .map_err(|_| format!("Stored key for {} is malformed", transplant_center))?;
+    let recipient = PublicKey::from(recipient);
+    let ephemeral = StaticSecret::from(ephemeral_seed);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let shared = ephemeral.diffie_hellman(&recipient);
+    if !shared.was_contributory() {
+        return Err(format!("Key registered for {} is not usable", transplant_center));
+    }
+
+    let mut key_material = KEY_DERIVATION_DOMAIN.to_vec();
+    key_material.extend_from_slice(shared.as_bytes());
+    key_material.extend_from_slice(ephemeral_public.as_bytes());
+    key_material.extend_from_slice(recipient.as_bytes());
+    let message_key = ic_cdk::api::sha256(&key_material);
+
+    // Every message key is used once, so a fixed nonce is safe; the metadata is bound as associated data
+    let associated_data = format!("{}|{}|{}", SEALING_SCHEME, key.key_id, content_type);
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&message_key))
+        .encrypt(Nonce::from_slice(&[0; 12]), Payload { msg: plaintext, aad: associated_data.as_bytes() })
+        .map_err(|_| "Offer encryption failed".to_string())?;
+
+    let sealed = SealedOffer {
+        scheme: SEALING_SCHEME,
+        key_id: &key.key_id,
+        ephemeral_public_key: hex(ephemeral_public.as_bytes()),
+        content_type,
+        ciphertext: hex(&ciphertext),
+    };
+    serde_json::to_vec(&sealed).map_err(|e| e.to_string())
+}
+
+fn current_key(transplant_center: &str) -> Option<CenterKey> {
+    CENTER_KEYRINGS.with(|k| {
+        k.borrow().get(transplant_center)
+            .and_then(|keyring| keyring.keys.iter().find(|key| key.retired_at.is_none()).cloned())
+    })
+}
+
+fn current_public_key(keyring: &CenterKeyring) -> Vec<u8> {
+    keyring.keys.iter().find(|key| key.retired_at.is_none()).map(|key| key.public_key.clone()).unwrap_or_default()
+}
+
+fn install(keyring: &mut CenterKeyring, public_key: Vec<u8>) {
+    let now = clock::now();
+    for key in keyring.keys.iter_mut().filter(|key| key.retired_at.is_none()) {
+        key.retired_at = Some(now);
+    }
+    keyring.keys.insert(0, CenterKey {
+        key_id: ids::new_id("CKEY"),
+        public_key,
+        registered_by: caller(),
+        registered_at: now,
+        retired_at: None,
+    });
+    keyring.keys.truncate(MAX_RETIRED_KEYS + 1);
+}
+
+fn valid_public_key(public_key: Vec<u8>) -> Result<Vec<u8>, String> {
+    let bytes: [u8; 32] = public_key.as_slice().try_into()
+        .map_err(|_| "public_key must be a 32-byte X25519 key".to_string())?;
+    if bytes.iter().all(|b| *b == 0) {
+        return Err("public_key must not be the zero point".to_string());
+    }
+    Ok(public_key)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Carried across upgrades with the event log
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct CenterKeysState {
+    keyrings: BTreeMap<String, CenterKeyring>,
+}
+
+pub(crate) fn snapshot() -> CenterKeysState {
+    CenterKeysState {
+        keyrings: CENTER_KEYRINGS.with(|c| c.borrow().clone()),
+    }
+}
+
+pub(crate) fn restore(state: Option<CenterKeysState>) {
+    let Some(state) = state else {
+        return;
+    };
+    CENTER_KEYRINGS.with(|c| *c.borrow_mut() = state.keyrings);
+}
@@ -0,0 +1,236 @@
+use ic_cdk::{caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::cell::RefCell;
+
+use crate::events::{self, ExecutionEventKind};
+use crate::{audit, clock, networks, tissue, traps, ExecutionResult, DIRECTIVE_MANAGER_CANISTER_ID, EXECUTION_HISTORY};
+
+// Organ donation and data consents can be withdrawn, or run out, while an execution is queued or
+// running. directive_manager reports every status change to either. Steps that have not run check
+// for a withdrawal first and are cancelled; tissue referrals still waiting for their batch are
+// cancelled; transplant centers, tissue banks and research institutions already told get a
+// retraction. An execution that was mid-step when the withdrawal arrived retracts as it completes.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct StepConsentState {
+    pub step: String,
+    pub consent_type: String,
+    pub consent_status: String, // "ACTIVE", or the status the consent was withdrawn with
+    pub checked_at: u64,
+    pub proceeded: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ConsentRetraction {
+    pub consent_type: String,
+    pub consent_status: String,
+    pub retracted_at: u64,
+    pub cancelled_referrals: Vec<String>,
+    pub recipients_retracted: Vec<String>,
+    pub retraction_failures: Vec<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ConsentWithdrawal {
+    pub patient_id: String,
+    pub consent_type: String,
+    pub status: String,
+    pub withdrawn_at: u64,
+}
+
+// Carried across upgrades with the event log; withdrawals outlive the executions they cancel
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct ConsentCascadeState {
+    withdrawals: Vec<ConsentWithdrawal>,
+}
+
+const CASCADING_CONSENTS: [&str; 2] = ["ORGAN_DONATION", "DATA_CONSENT"];
+const WITHDRAWN_STATUSES: [&str; 4] = ["REVOKED", "WITHDRAWN", "INACTIVE", "EXPIRED"];
+
+thread_local! {
+    // (patient_id, consent type) -> the withdrawal in force
+    static WITHDRAWALS: RefCell<BTreeMap<(String, String), ConsentWithdrawal>> = const { RefCell::new(BTreeMap::new()) };
+    // (execution_id, consent type) retractions under way, so a cascade and a catch-up never both send
+    static RETRACTING: RefCell<BTreeSet<(String, String)>> = const { RefCell::new(BTreeSet::new()) };
+}
+
+// Called by directive_manager whenever an organ donation or data consent changes status
+#[update]
+async fn record_consent_status(patient_id: String, consent_type: String, status: String) -> Result<u32, String> {
+    let _watch = traps::Watch::start("CONSENT_CASCADE");
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    if caller() != directive_manager_id {
+        return Err("Only directive_manager may report consent changes".to_string());
+    }
+    if !CASCADING_CONSENTS.contains(&consent_type.as_str()) {
+        return Err(format!("Changes to {} consents do not cascade into executions", consent_type));
+    }
+
+    let key = (patient_id.clone(), consent_type.clone());
+    if !WITHDRAWN_STATUSES.contains(&status.as_str()) {
+        // Consent given again applies to steps from now on; nothing cancelled is reinstated
+        WITHDRAWALS.with(|w| w.borrow_mut().remove(&key));
+        return Ok(0);
+    }
+    if WITHDRAWALS.with(|w| w.borrow().contains_key(&key)) {
+        return Ok(0);
+    }
+    let withdrawal = ConsentWithdrawal { patient_id, consent_type, status, withdrawn_at: clock::now() };
+    WITHDRAWALS.with(|w| w.borrow_mut().insert(key, withdrawal.clone()));
+    ic_cdk::println!("🛑 {} consent {} for patient {}", withdrawal.consent_type, withdrawal.status, withdrawal.patient_id);
+
+    let executions: Vec<ExecutionResult> = EXECUTION_HISTORY.with(|history| {
+        history.borrow().values().filter(|x| x.patient_id == withdrawal.patient_id).cloned().collect()
+    });
+    let mut retracted = 0;
+    for execution in &executions {
+        if retract(execution, &withdrawal).await {
+            retracted += 1;
+        }
+    }
+    Ok(retracted)
+}
+
+#[query]
+fn get_consent_withdrawals(patient_id: String) -> Vec<ConsentWithdrawal> {
+    WITHDRAWALS.with(|w| w.borrow().values().filter(|x| x.patient_id == patient_id).cloned().collect())
+}
+
+// Gate for a step that runs under a consent; the check lands on the execution record either way
+pub(crate) fn check_step(patient_id: &str, step: &str, consent_type: &str, states: &mut Vec<StepConsentState>) -> bool {
+    let withdrawal = withdrawal(patient_id, consent_type);
+    let proceeded = withdrawal.is_none();
+    if !proceeded {
+        ic_cdk::println!("⏭️ {} cancelled for {}: {} consent withdrawn", step, patient_id, consent_type);
+    }
+    states.push(StepConsentState {
+        step: step.to_string(),
+        consent_type: consent_type.to_string(),
+        consent_status: withdrawal.map_or_else(|| "ACTIVE".to_string(), |w| w.status),
+        checked_at: clock::now(),
+        proceeded,
+    });
+    proceeded
+}
+
+// A step that passed its check but whose consent was withdrawn before the execution completed
+pub(crate) async fn catch_up(execution: &ExecutionResult) {
+    let consent_types: BTreeSet<&str> = execution.consent_states.iter()
+        .filter(|x| x.proceeded)
+        .map(|x| x.consent_type.as_str())
+        .collect();
+    for consent_type in consent_types {
+        if let Some(withdrawal) = withdrawal(&execution.patient_id, consent_type) {
+            retract(execution, &withdrawal).await;
+        }
+    }
+}
+
+fn withdrawal(patient_id: &str, consent_type: &str) -> Option<ConsentWithdrawal> {
+    WITHDRAWALS.with(|w| w.borrow().get(&(patient_id.to_string(), consent_type.to_string())).cloned())
+}
+
+// Walks back one execution's steps under the withdrawn consent; false when there was nothing to do
+async fn retract(execution: &ExecutionResult, withdrawal: &ConsentWithdrawal) -> bool {
+    let claim = (execution.execution_id.clone(), withdrawal.consent_type.clone());
+    // The stored record, not the caller's copy, which may predate a retraction recorded since
+    let already_retracted = EXECUTION_HISTORY.with(|history| {
+        history.borrow().get(&execution.execution_id).is_some_and(|record| {
+            record.consent_retractions.iter().any(|x| x.consent_type == withdrawal.consent_type)
+        })
+    });
+    if already_retracted {
+        return false;
+    }
+    let Some(_claim) = RetractionClaim::take(claim) else {
+        return false;
+    };
+
+    let reason = format!("Donor {} consent {}", withdrawal.consent_type, withdrawal.status);
+    let mut retraction = ConsentRetraction {
+        consent_type: withdrawal.consent_type.clone(),
+        consent_status: withdrawal.status.clone(),
+        retracted_at: clock::now(),
+        cancelled_referrals: vec![],
+        recipients_retracted: vec![],
+        retraction_failures: vec![],
+    };
+    let steps = execution.directives_executed.iter();
+    match withdrawal.consent_type.as_str() {
+        "ORGAN_DONATION" => {
+            let offers = steps
+                .filter(|d| d.directive_type == "ORGAN_DONATION")
+                .flat_map(|d| d.recipient_matches.iter())
+                .filter(|m| m.notification_sent);
+            for offer in offers {
+                let recipient = format!("{} ({})", offer.transplant_center, offer.organ);
+                match networks::send_retraction(offer, &reason).await {
+                    Ok(_) => retraction.recipients_retracted.push(recipient),
+                    Err(e) => retraction.retraction_failures.push(format!("{}: {}", recipient, e)),
+                }
+            }
+            let (cancelled, banks) = tissue::withdraw_referrals(&execution.patient_id);
+            retraction.cancelled_referrals = cancelled;
+            retraction.recipients_retracted.extend(banks);
+        }
+        "DATA_CONSENT" => {
+            // Institutions are notified out of band; the notice is logged for their data officers
+            for institution in steps.filter(|d| d.directive_type == "DATA_CONSENT").flat_map(|d| d.data_shared_with.iter()) {
+                ic_cdk::println!("📊 DATA RETRACTION: {} - {} for execution {}", institution, reason, execution.execution_id);
+                retraction.recipients_retracted.push(institution.clone());
+            }
+        }
+        _ => {}
+    }
+
+    let acted = !retraction.cancelled_referrals.is_empty()
+        || !retraction.recipients_retracted.is_empty()
+        || !retraction.retraction_failures.is_empty();
+    if acted {
+        if let Ok(payload) = serde_json::to_vec(&retraction) {
+            audit::append_audit_entry("CONSENT_RETRACTION", &execution.execution_id, &payload);
+        }
+        events::record(ExecutionEventKind::ConsentRetracted {
+            execution_id: execution.execution_id.clone(),
+            retraction,
+        });
+    }
+    acted
+}
+
+// Held across a retraction's awaits; dropping it, on return or when a trap abandons the
+// retraction, lets the next withdrawal or catch-up try again instead of finding it claimed forever
+struct RetractionClaim((String, String));
+
+impl RetractionClaim {
+    fn take(claim: (String, String)) -> Option<RetractionClaim> {
+        RETRACTING.with(|r| r.borrow_mut().insert(claim.clone()))
+            .then(|| RetractionClaim(claim))
+    }
+}
+
+impl Drop for RetractionClaim {
+    fn drop(&mut self) {
+        RETRACTING.with(|r| r.borrow_mut().remove(&self.0));
+    }
+}
+
+pub(crate) fn snapshot() -> ConsentCascadeState {
+    ConsentCascadeState { withdrawals: WITHDRAWALS.with(|w| w.borrow().values().cloned().collect()) }
+}
+
+pub(crate) fn restore(state: Option<ConsentCascadeState>) {
+    let Some(state) = state else {
+        return;
+    };
+    WITHDRAWALS.with(|w| {
+        *w.borrow_mut() = state.withdrawals
+            .into_iter()
+            .map(|x| ((x.patient_id.clone(), x.consent_type.clone()), x))
+            .collect();
+    });
+}
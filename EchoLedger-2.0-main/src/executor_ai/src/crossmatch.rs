@@ -0,0 +1,151 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::{clock, validation, viability, OrganAvailability, RecipientMatch};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CrossmatchResult {
+    pub donor_id: String,
+    pub recipient_id: String,
+    pub compatible: bool,
+    pub conflicting_antigens: Vec<String>,
+    pub evaluated_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct MatchExclusion {
+    pub donor_id: String,
+    pub recipient_id: String,
+    pub organ: String,
+    pub reason: String,
+    pub excluded_at: u64,
+}
+
+thread_local! {
+    // Recipient ID -> unacceptable antigens, in allele notation at any resolution (e.g. "A*02", "B*07:02")
+    static UNACCEPTABLE_ANTIGENS: RefCell<BTreeMap<String, Vec<String>>> = RefCell::new(BTreeMap::new());
+    static MATCH_EXCLUSIONS: RefCell<Vec<MatchExclusion>> = RefCell::new(Vec::new());
+}
+
+#[update]
+fn register_unacceptable_antigens(recipient_id: String, antigens: Vec<String>) -> Result<(), String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    let recipient_id = validation::identifier("recipient_id", &recipient_id)?;
+    validation::collection("antigens", antigens.len(), validation::MAX_ANTIGENS)?;
+    let antigens = antigens.iter().map(|a| validation::identifier("antigen", a)).collect::<Result<Vec<_>, _>>()?;
+    if let Some(bad) = antigens.iter().find(|a| !a.contains('*')) {
+        return Err(format!("Antigen must use allele notation (e.g. A*02): {}", bad));
+    }
+
+    UNACCEPTABLE_ANTIGENS.with(|u| {
+        u.borrow_mut().insert(recipient_id, antigens);
+    });
+    Ok(())
+}
+
+// Antigen lists and crossmatches expose HLA typing; only intake hospitals and controllers read them
+#[query]
+fn get_unacceptable_antigens(recipient_id: String) -> Result<Vec<String>, String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    Ok(UNACCEPTABLE_ANTIGENS.with(|u| u.borrow().get(&recipient_id).cloned().unwrap_or_default()))
+}
+
+// Compare a registered donor's HLA typing against a recipient's unacceptable antigens
+#[query]
+fn virtual_crossmatch(donor_id: String, recipient_id: String) -> Result<CrossmatchResult, String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    let donor = viability::donor_data(&donor_id)
+        .ok_or_else(|| format!("No donor clinical data submitted for patient: {}", donor_id))?;
+    if donor.hla_typing.is_empty() {
+        return Err("Donor HLA typing is required for a virtual crossmatch".to_string());
+    }
+
+    let conflicting_antigens = conflicts(&donor.hla_typing, &recipient_id);
+    Ok(CrossmatchResult {
+        donor_id,
+        recipient_id,
+        compatible: conflicting_antigens.is_empty(),
+        conflicting_antigens,
+        evaluated_at: clock::now(),
+    })
+}
+
+#[query]
+fn get_match_exclusions(donor_id: String) -> Vec<MatchExclusion> {
+    MATCH_EXCLUSIONS.with(|e| {
+        e.borrow()
+            .iter()
+            .filter(|x| x.donor_id == donor_id)
+            .cloned()
+            .collect()
+    })
+}
+
+// "A*02" is unacceptable for donor allele "A*02:01" but not for "A*020"
+fn antigen_matches(donor_allele: &str, unacceptable: &str) -> bool {
+    donor_allele == unacceptable
+        || (donor_allele.starts_with(unacceptable)
+            && donor_allele[unacceptable.len()..].starts_with(':'))
+}
+
+pub(crate) fn conflicts(donor_hla: &[String], recipient_id: &str) -> Vec<String> {
+    UNACCEPTABLE_ANTIGENS.with(|u| {
+        u.borrow()
+            .get(recipient_id)
+            .map(|antigens| {
+                antigens.iter()
+                    .filter(|a| donor_hla.iter().any(|d| antigen_matches(d, a)))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    })
+}
+
+// Drop matches with a positive virtual crossmatch and record why
+pub(crate) fn exclude_incompatible(
+    donor_id: &str,
+    organs: &[OrganAvailability],
+    matches: Vec<RecipientMatch>
+) -> Vec<RecipientMatch> {
+    let now = clock::now();
+    let mut exclusions = Vec::new();
+
+    let compatible = matches
+        .into_iter()
+        .filter(|m| {
+            // Avascular tissue such as corneas is allocated without HLA matching
+            let Some(organ) = organs.iter().find(|o| o.organ_type == m.organ && !o.hla_typing.is_empty()) else {
+                return true;
+            };
+            let conflicting = conflicts(&organ.hla_typing, &m.recipient_id);
+            if conflicting.is_empty() {
+                return true;
+            }
+            exclusions.push(MatchExclusion {
+                donor_id: donor_id.to_string(),
+                recipient_id: m.recipient_id.clone(),
+                organ: m.organ.clone(),
+                reason: format!("Positive virtual crossmatch: unacceptable antigens {}", conflicting.join(", ")),
+                excluded_at: now,
+            });
+            false
+        })
+        .collect();
+
+    for exclusion in &exclusions {
+        ic_cdk::println!("🧬 {} excluded for {}: {}", exclusion.recipient_id, exclusion.organ, exclusion.reason);
+    }
+    MATCH_EXCLUSIONS.with(|e| e.borrow_mut().extend(exclusions));
+    compatible
+}
@@ -0,0 +1,481 @@
+use ic_cdk::{caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::{audit, clock, ids, protobuf, validation, viability};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TransportDevice {
+    pub device_id: String,
+    pub principal: Option<Principal>, // for authenticated update calls
+    pub push_key_hash: Option<Vec<u8>>, // sha256 of the key sent with HTTPS pushes
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TelemetryReading {
+    pub device_id: String,
+    pub recorded_at: u64,
+    pub temperature_c: f32,
+    pub perfusion_pressure_mmhg: Option<f32>,
+    pub received_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TelemetryAlert {
+    pub metric: String, // "TEMPERATURE", "PERFUSION_PRESSURE"
+    pub value: f32,
+    pub acceptable_min: f32,
+    pub acceptable_max: f32,
+    pub recorded_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CustodyRecord {
+    pub custody_id: String,
+    pub donor_id: String,
+    pub organ_type: String,
+    pub recipient_id: String,
+    pub device_id: String,
+    pub status: String, // "IN_TRANSIT", "DELIVERED"
+    pub opened_at: u64,
+    pub closed_at: Option<u64>,
+    pub readings: Vec<TelemetryReading>,
+    pub alerts: Vec<TelemetryAlert>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub upgrade: Option<bool>,
+}
+
+// The body of a POST /telemetry, as JSON or as the protobuf message of the same name
+#[derive(Deserialize)]
+pub(crate) struct TelemetryPush {
+    pub(crate) custody_id: String,
+    pub(crate) device_id: String,
+    pub(crate) recorded_at: u64,
+    pub(crate) temperature_c: f32,
+    pub(crate) perfusion_pressure_mmhg: Option<f32>,
+}
+
+// Reply to a push for clients that asked for JSON or protobuf; others get plain text
+#[derive(Serialize)]
+pub(crate) struct TelemetryAck {
+    pub(crate) status_code: u16,
+    pub(crate) message: String,
+    pub(crate) alerts: Vec<TelemetryAlert>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PayloadFormat {
+    Json,
+    Protobuf,
+    PlainText,
+}
+
+// Acceptable hypothermic preservation ranges: (temp min, temp max, perfusion min/max if machine-perfused)
+struct PreservationRange {
+    temperature_c: (f32, f32),
+    perfusion_mmhg: Option<(f32, f32)>,
+}
+
+fn preservation_range(organ_type: &str) -> PreservationRange {
+    match organ_type {
+        "kidney_left" | "kidney_right" | "kidney_en_bloc" => PreservationRange {
+            temperature_c: (1.0, 8.0),
+            perfusion_mmhg: Some((20.0, 40.0)),
+        },
+        "liver" => PreservationRange {
+            temperature_c: (1.0, 8.0),
+            perfusion_mmhg: Some((2.0, 8.0)),
+        },
+        "heart" | "lungs" => PreservationRange {
+            temperature_c: (2.0, 10.0),
+            perfusion_mmhg: None,
+        },
+        _ => PreservationRange {
+            temperature_c: (2.0, 8.0),
+            perfusion_mmhg: None,
+        },
+    }
+}
+
+thread_local! {
+    static TRANSPORT_DEVICES: RefCell<BTreeMap<String, TransportDevice>> = RefCell::new(BTreeMap::new());
+    static CUSTODY_RECORDS: RefCell<BTreeMap<String, CustodyRecord>> = RefCell::new(BTreeMap::new());
+}
+
+const PUSH_KEY_HEADER: &str = "x-device-key";
+// A push key is a bearer secret, so a captured push could be sent again. Pushes must be recent and
+// newer than the device's last reading on the record; the window allows for gateway delay and skew.
+const PUSH_WINDOW_NANOS: u64 = 5 * 60 * 1_000_000_000;
+const MAX_FUTURE_SKEW_NANOS: u64 = 60 * 1_000_000_000;
+// Two days of readings at one every 15 seconds; each reading raises at most two alerts
+const MAX_READINGS_PER_RECORD: usize = 11_520;
+
+#[update]
+fn register_transport_device(device: TransportDevice) -> Result<(), String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    if device.principal.is_none() && device.push_key_hash.is_none() {
+        return Err("A device needs a principal or a push key to authenticate".to_string());
+    }
+    TRANSPORT_DEVICES.with(|d| {
+        d.borrow_mut().insert(device.device_id.clone(), device);
+    });
+    Ok(())
+}
+
+#[update]
+fn open_custody_record(
+    donor_id: String,
+    organ_type: String,
+    recipient_id: String,
+    device_id: String
+) -> Result<CustodyRecord, String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    if !TRANSPORT_DEVICES.with(|d| d.borrow().contains_key(&device_id)) {
+        return Err(format!("Unknown transport device: {}", device_id));
+    }
+
+    let now = clock::now();
+    let record = CustodyRecord {
+        custody_id: ids::new_id("CUSTODY"),
+        donor_id,
+        organ_type,
+        recipient_id,
+        device_id,
+        status: "IN_TRANSIT".to_string(),
+        opened_at: now,
+        closed_at: None,
+        readings: vec![],
+        alerts: vec![],
+    };
+
+    CUSTODY_RECORDS.with(|c| {
+        let mut records = c.borrow_mut();
+        let open = records.values().find(|r| {
+            r.status == "IN_TRANSIT" && r.donor_id == record.donor_id && r.organ_type == record.organ_type
+        });
+        if let Some(open) = open {
+            return Err(format!("Custody already open: {}", open.custody_id));
+        }
+        records.insert(record.custody_id.clone(), record.clone());
+        Ok(())
+    })?;
+    Ok(record)
+}
+
+#[update]
+fn close_custody_record(custody_id: String) -> Result<CustodyRecord, String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    let record = CUSTODY_RECORDS.with(|c| {
+        let mut records = c.borrow_mut();
+        let record = records.get_mut(&custody_id).ok_or_else(|| format!("Custody record not found: {}", custody_id))?;
+        record.status = "DELIVERED".to_string();
+        record.closed_at = Some(clock::now());
+        Ok::<_, String>(record.clone())
+    })?;
+
+    if let Ok(payload) = serde_json::to_vec(&record) {
+        audit::append_audit_entry("CUSTODY_CLOSED", &custody_id, &payload);
+    }
+    Ok(record)
+}
+
+// Devices holding a principal report directly
+#[update]
+fn submit_telemetry(
+    custody_id: String,
+    recorded_at: u64,
+    temperature_c: f32,
+    perfusion_pressure_mmhg: Option<f32>
+) -> Result<Vec<TelemetryAlert>, String> {
+    let reporter = caller();
+    let device_id = TRANSPORT_DEVICES.with(|d| {
+        d.borrow()
+            .values()
+            .find(|x| x.principal == Some(reporter))
+            .map(|x| x.device_id.clone())
+    }).ok_or("Caller is not a registered transport device")?;
+
+    record_reading(&custody_id, TelemetryReading {
+        device_id,
+        recorded_at,
+        temperature_c,
+        perfusion_pressure_mmhg,
+        received_at: clock::now(),
+    })
+}
+
+// Devices without an IC identity POST through the HTTP gateway, in JSON or protobuf
+// (executor_ai.proto); custody records can be read back the same way
+#[query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    if request.method == "POST" && request.url.starts_with("/telemetry") {
+        return HttpResponse { status_code: 200, headers: vec![], body: vec![], upgrade: Some(true) };
+    }
+    if request.method == "GET" {
+        if let Some(custody_id) = request.url.split('?').next().and_then(|path| path.strip_prefix("/custody/")) {
+            let format = match accepted_format(&request.headers) {
+                PayloadFormat::PlainText => PayloadFormat::Json,
+                format => format,
+            };
+            return match get_custody_record(custody_id.to_string()) {
+                Some(record) if format == PayloadFormat::Protobuf => {
+                    payload_response(200, protobuf::CONTENT_TYPE, protobuf::encode_custody_record(&record))
+                }
+                Some(record) => payload_response(200, "application/json", serde_json::to_vec(&record).unwrap_or_default()),
+                None => plain_response(404, "Custody record not found"),
+            };
+        }
+    }
+    plain_response(404, "Not found")
+}
+
+#[update]
+fn http_request_update(request: HttpRequest) -> HttpResponse {
+    if request.method != "POST" || !request.url.starts_with("/telemetry") {
+        return plain_response(404, "Not found");
+    }
+    let Some(body_format) = body_format(&request.headers) else {
+        return plain_response(415, "Telemetry must be sent as application/json or application/x-protobuf");
+    };
+    // Without an explicit Accept, protobuf clients get protobuf back and JSON clients keep the plain-text reply
+    let reply = match accepted_format(&request.headers) {
+        PayloadFormat::PlainText if body_format == PayloadFormat::Protobuf => PayloadFormat::Protobuf,
+        format => format,
+    };
+    if request.body.len() > validation::MAX_TELEMETRY_BODY_BYTES {
+        return ack_response(reply, 413, "Telemetry payload too large", vec![]);
+    }
+
+    let push = match decode_push(body_format, &request.body) {
+        Ok(push) => push,
+        Err(e) => return ack_response(reply, 400, &e, vec![]),
+    };
+    let key = header(&request.headers, PUSH_KEY_HEADER).map(String::from);
+    let authenticated = TRANSPORT_DEVICES.with(|d| {
+        d.borrow()
+            .get(&push.device_id)
+            .and_then(|device| device.push_key_hash.clone())
+            .zip(key)
+            .is_some_and(|(expected, key)| ic_cdk::api::sha256(key.as_bytes()) == expected)
+    });
+    if !authenticated {
+        return ack_response(reply, 401, "Device authentication failed", vec![]);
+    }
+    if let Err(e) = check_push_fresh(&push) {
+        return ack_response(reply, 409, &e, vec![]);
+    }
+
+    let reading = TelemetryReading {
+        device_id: push.device_id,
+        recorded_at: push.recorded_at,
+        temperature_c: push.temperature_c,
+        perfusion_pressure_mmhg: push.perfusion_pressure_mmhg,
+        received_at: clock::now(),
+    };
+    match record_reading(&push.custody_id, reading) {
+        Ok(alerts) => ack_response(reply, 200, &format!("{} alert(s) raised", alerts.len()), alerts),
+        Err(e) => ack_response(reply, 409, &e, vec![]),
+    }
+}
+
+#[query]
+fn get_custody_record(custody_id: String) -> Option<CustodyRecord> {
+    CUSTODY_RECORDS.with(|c| c.borrow().get(&custody_id).cloned())
+}
+
+// Both encodings land in the same TelemetryPush and go through the same identifier checks
+fn decode_push(format: PayloadFormat, body: &[u8]) -> Result<TelemetryPush, String> {
+    let push = match format {
+        PayloadFormat::Protobuf => protobuf::decode_push(body)?,
+        _ => serde_json::from_slice::<TelemetryPush>(body).map_err(|_| "Malformed telemetry payload".to_string())?,
+    };
+    Ok(TelemetryPush {
+        custody_id: validation::identifier("custody_id", &push.custody_id)?,
+        device_id: validation::identifier("device_id", &push.device_id)?,
+        ..push
+    })
+}
+
+fn check_push_fresh(push: &TelemetryPush) -> Result<(), String> {
+    let now = clock::now();
+    if push.recorded_at.saturating_add(PUSH_WINDOW_NANOS) < now {
+        return Err("Telemetry is too old to accept".to_string());
+    }
+    if push.recorded_at > now.saturating_add(MAX_FUTURE_SKEW_NANOS) {
+        return Err("Telemetry is dated in the future".to_string());
+    }
+    let last = CUSTODY_RECORDS.with(|c| {
+        c.borrow().get(&push.custody_id).and_then(|record| {
+            record.readings.iter().rev().find(|r| r.device_id == push.device_id).map(|r| r.recorded_at)
+        })
+    });
+    if last.is_some_and(|last| push.recorded_at <= last) {
+        return Err("Telemetry is not newer than the device's last reading".to_string());
+    }
+    Ok(())
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+}
+
+fn media_type(value: &str) -> Option<PayloadFormat> {
+    match value.split(';').next().unwrap_or("").trim().to_ascii_lowercase().as_str() {
+        "application/json" => Some(PayloadFormat::Json),
+        "application/x-protobuf" | "application/protobuf" => Some(PayloadFormat::Protobuf),
+        _ => None,
+    }
+}
+
+// A missing Content-Type is read as JSON, which is all the gateway took before protobuf
+fn body_format(headers: &[(String, String)]) -> Option<PayloadFormat> {
+    header(headers, "content-type").map_or(Some(PayloadFormat::Json), media_type)
+}
+
+// The first supported type listed in Accept; q-values are not weighed
+fn accepted_format(headers: &[(String, String)]) -> PayloadFormat {
+    header(headers, "accept")
+        .and_then(|accept| accept.split(',').find_map(media_type))
+        .unwrap_or(PayloadFormat::PlainText)
+}
+
+fn ack_response(format: PayloadFormat, status_code: u16, message: &str, alerts: Vec<TelemetryAlert>) -> HttpResponse {
+    let ack = TelemetryAck { status_code, message: message.to_string(), alerts };
+    match format {
+        PayloadFormat::Protobuf => payload_response(status_code, protobuf::CONTENT_TYPE, protobuf::encode_ack(&ack)),
+        PayloadFormat::Json => payload_response(status_code, "application/json", serde_json::to_vec(&ack).unwrap_or_default()),
+        PayloadFormat::PlainText => plain_response(status_code, message),
+    }
+}
+
+fn payload_response(status_code: u16, content_type: &str, body: Vec<u8>) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![("Content-Type".to_string(), content_type.to_string())],
+        body,
+        upgrade: None,
+    }
+}
+
+fn plain_response(status_code: u16, message: &str) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+        body: message.as_bytes().to_vec(),
+        upgrade: None,
+    }
+}
+
+// Attach a reading to an open custody record and alert on out-of-range values
+pub(crate) fn record_reading(custody_id: &str, reading: TelemetryReading) -> Result<Vec<TelemetryAlert>, String> {
+    // NaN compares false against both ends of a range and would never raise an alert
+    if !reading.temperature_c.is_finite() || reading.perfusion_pressure_mmhg.is_some_and(|p| !p.is_finite()) {
+        return Err("Telemetry values must be finite numbers".to_string());
+    }
+    let alerts = CUSTODY_RECORDS.with(|c| {
+        let mut records = c.borrow_mut();
+        let record = records.get_mut(custody_id).ok_or_else(|| format!("Custody record not found: {}", custody_id))?;
+        if record.status != "IN_TRANSIT" {
+            return Err("Custody record is closed".to_string());
+        }
+        if record.device_id != reading.device_id {
+            return Err("Device is not assigned to this custody record".to_string());
+        }
+        if record.readings.len() >= MAX_READINGS_PER_RECORD {
+            return Err("Custody record holds the maximum number of readings".to_string());
+        }
+
+        let range = preservation_range(&record.organ_type);
+        let mut alerts = Vec::new();
+        let (t_min, t_max) = range.temperature_c;
+        if reading.temperature_c < t_min || reading.temperature_c > t_max {
+            alerts.push(TelemetryAlert {
+                metric: "TEMPERATURE".to_string(),
+                value: reading.temperature_c,
+                acceptable_min: t_min,
+                acceptable_max: t_max,
+                recorded_at: reading.recorded_at,
+            });
+        }
+        if let (Some((p_min, p_max)), Some(pressure)) = (range.perfusion_mmhg, reading.perfusion_pressure_mmhg) {
+            if pressure < p_min || pressure > p_max {
+                alerts.push(TelemetryAlert {
+                    metric: "PERFUSION_PRESSURE".to_string(),
+                    value: pressure,
+                    acceptable_min: p_min,
+                    acceptable_max: p_max,
+                    recorded_at: reading.recorded_at,
+                });
+            }
+        }
+
+        record.readings.push(reading);
+        record.alerts.extend(alerts.iter().cloned());
+        Ok::<_, String>(alerts)
+    })?;
+
+    for alert in &alerts {
+        ic_cdk::println!(
+            "🌡️ PRESERVATION ALERT: {} - {} {:.1} outside {:.1}-{:.1}",
+            custody_id,
+            alert.metric,
+            alert.value,
+            alert.acceptable_min,
+            alert.acceptable_max
+        );
+        if let Ok(payload) = serde_json::to_vec(alert) {
+            audit::append_audit_entry("PRESERVATION_ALERT", custody_id, &payload);
+        }
+    }
+    Ok(alerts)
+}
+
+// Any custody record, open or closed, for the donor's organ
+pub(crate) fn custody_opened(donor_id: &str, organ_type: &str) -> bool {
+    CUSTODY_RECORDS.with(|c| c.borrow().values().any(|r| r.donor_id == donor_id && r.organ_type == organ_type))
+}
+
+// Carried across upgrades with the event log
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct CustodyState {
+    devices: BTreeMap<String, TransportDevice>,
+    records: BTreeMap<String, CustodyRecord>,
+}
+
+pub(crate) fn snapshot() -> CustodyState {
+    CustodyState {
+        devices: TRANSPORT_DEVICES.with(|t| t.borrow().clone()),
+        records: CUSTODY_RECORDS.with(|c| c.borrow().clone()),
+    }
+}
+
+pub(crate) fn restore(state: Option<CustodyState>) {
+    let Some(state) = state else {
+        return;
+    };
+    TRANSPORT_DEVICES.with(|t| *t.borrow_mut() = state.devices);
+    CUSTODY_RECORDS.with(|c| *c.borrow_mut() = state.records);
+}
@@ -0,0 +1,214 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::{audit, clock, viability};
+
+// Donation after circulatory death: withdrawal -> arrest -> stand-off -> declaration -> perfusion
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DcdCase {
+    pub patient_id: String,
+    pub state: String, // "SCHEDULED", "WITHDRAWN", "ARREST", "STAND_OFF_COMPLETE", "DEATH_DECLARED", "PERFUSED", "ABANDONED"
+    pub withdrawal_scheduled_at: u64,
+    pub withdrawal_at: Option<u64>,
+    pub circulatory_arrest_at: Option<u64>,
+    pub stand_off_minutes: u32,
+    pub death_declared_at: Option<u64>,
+    pub perfusion_at: Option<u64>,
+    pub warm_ischemia_minutes: Option<u32>,
+    pub asystolic_warm_ischemia_minutes: Option<u32>,
+}
+
+thread_local! {
+    static DCD_CASES: RefCell<BTreeMap<String, DcdCase>> = RefCell::new(BTreeMap::new());
+}
+
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+// Most jurisdictions mandate 2-5 minutes of observed asystole before declaration
+const MIN_STAND_OFF_MINUTES: u32 = 2;
+const DEFAULT_STAND_OFF_MINUTES: u32 = 5;
+// Organ recovery is abandoned if arrest does not follow withdrawal within this window
+const PROGRESSION_WINDOW_MINUTES: u64 = 120;
+
+#[update]
+fn schedule_withdrawal(
+    patient_id: String,
+    scheduled_at: u64,
+    stand_off_minutes: Option<u32>
+) -> Result<DcdCase, String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    let stand_off_minutes = stand_off_minutes.unwrap_or(DEFAULT_STAND_OFF_MINUTES);
+    if stand_off_minutes < MIN_STAND_OFF_MINUTES {
+        return Err(format!("Stand-off period must be at least {} minutes", MIN_STAND_OFF_MINUTES));
+    }
+
+    let case = DcdCase {
+        patient_id: patient_id.clone(),
+        state: "SCHEDULED".to_string(),
+        withdrawal_scheduled_at: scheduled_at,
+        withdrawal_at: None,
+        circulatory_arrest_at: None,
+        stand_off_minutes,
+        death_declared_at: None,
+        perfusion_at: None,
+        warm_ischemia_minutes: None,
+        asystolic_warm_ischemia_minutes: None,
+    };
+    DCD_CASES.with(|c| {
+        let mut cases = c.borrow_mut();
+        if cases.get(&patient_id).is_some_and(|existing| existing.state != "SCHEDULED" && existing.state != "ABANDONED") {
+            return Err("Withdrawal has already begun for this patient".to_string());
+        }
+        cases.insert(patient_id, case.clone());
+        Ok(())
+    })?;
+    Ok(case)
+}
+
+#[update]
+fn record_withdrawal_of_support(patient_id: String) -> Result<DcdCase, String> {
+    let case = transition(&patient_id, &["SCHEDULED"], |case, now| {
+        case.state = "WITHDRAWN".to_string();
+        case.withdrawal_at = Some(now);
+        Ok(())
+    })?;
+
+    // Abandon recovery if the patient does not progress to arrest in time
+    ic_cdk_timers::set_timer(Duration::from_nanos(PROGRESSION_WINDOW_MINUTES * NANOS_PER_MINUTE), move || {
+        DCD_CASES.with(|c| {
+            if let Some(case) = c.borrow_mut().get_mut(&patient_id) {
+                if case.state == "WITHDRAWN" {
+                    case.state = "ABANDONED".to_string();
+                    ic_cdk::println!("⏹️ DCD recovery abandoned for {} - no arrest within window", patient_id);
+                }
+            }
+        });
+    });
+    Ok(case)
+}
+
+// Starts the mandatory no-touch stand-off; the timer only marks it complete for display, since
+// declaration checks the elapsed time itself
+#[update]
+fn record_circulatory_arrest(patient_id: String) -> Result<DcdCase, String> {
+    let case = transition(&patient_id, &["WITHDRAWN"], |case, now| {
+        case.state = "ARREST".to_string();
+        case.circulatory_arrest_at = Some(now);
+        Ok(())
+    })?;
+
+    let stand_off = Duration::from_nanos((case.stand_off_minutes as u64).saturating_mul(NANOS_PER_MINUTE));
+    ic_cdk_timers::set_timer(stand_off, move || {
+        DCD_CASES.with(|c| {
+            if let Some(case) = c.borrow_mut().get_mut(&patient_id) {
+                if case.state == "ARREST" {
+                    case.state = "STAND_OFF_COMPLETE".to_string();
+                }
+            }
+        });
+    });
+    Ok(case)
+}
+
+// A timer that was lost to an upgrade or runs late neither blocks nor hastens declaration
+#[update]
+fn declare_circulatory_death(patient_id: String) -> Result<DcdCase, String> {
+    let case = transition(&patient_id, &["ARREST", "STAND_OFF_COMPLETE"], |case, now| {
+        let arrest_at = case.circulatory_arrest_at.ok_or("No circulatory arrest recorded")?;
+        let stand_off = (case.stand_off_minutes as u64).saturating_mul(NANOS_PER_MINUTE);
+        let observed = now.saturating_sub(arrest_at);
+        if observed < stand_off {
+            return Err(format!(
+                "Stand-off incomplete: {} of {} minutes observed",
+                observed / NANOS_PER_MINUTE,
+                case.stand_off_minutes
+            ));
+        }
+        case.state = "DEATH_DECLARED".to_string();
+        case.death_declared_at = Some(now);
+        Ok(())
+    }).map_err(|e| format!("{} - the stand-off period must elapse before declaration", e))?;
+
+    if let Ok(payload) = serde_json::to_vec(&case) {
+        audit::append_audit_entry("DCD_DEATH_DECLARED", &patient_id, &payload);
+    }
+    Ok(case)
+}
+
+// Perfusion ends warm ischemia; the measured time feeds viability scoring
+#[update]
+fn record_perfusion_start(patient_id: String) -> Result<DcdCase, String> {
+    transition(&patient_id, &["DEATH_DECLARED"], |case, now| {
+        let minutes_since = |t: Option<u64>| t.map(|t| (now.saturating_sub(t) / NANOS_PER_MINUTE) as u32);
+        case.state = "PERFUSED".to_string();
+        case.perfusion_at = Some(now);
+        case.warm_ischemia_minutes = minutes_since(case.withdrawal_at);
+        case.asystolic_warm_ischemia_minutes = minutes_since(case.circulatory_arrest_at);
+        Ok(())
+    })
+}
+
+#[query]
+fn get_dcd_case(patient_id: String) -> Option<DcdCase> {
+    dcd_case(&patient_id)
+}
+
+fn transition(
+    patient_id: &str,
+    expected_states: &[&str],
+    apply: impl FnOnce(&mut DcdCase, u64) -> Result<(), String>
+) -> Result<DcdCase, String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    DCD_CASES.with(|c| {
+        let mut cases = c.borrow_mut();
+        let case = cases.get_mut(patient_id).ok_or_else(|| format!("No DCD case for patient: {}", patient_id))?;
+        if !expected_states.contains(&case.state.as_str()) {
+            return Err(format!("DCD case is {}, expected {}", case.state, expected_states.join(" or ")));
+        }
+        apply(case, clock::now())?;
+        Ok(case.clone())
+    })
+}
+
+pub(crate) fn dcd_case(patient_id: &str) -> Option<DcdCase> {
+    DCD_CASES.with(|c| c.borrow().get(patient_id).cloned())
+}
+
+// Execution gate: a DCD donor's organs may only be allocated once perfusion has started
+pub(crate) fn ensure_ready_for_allocation(patient_id: &str) -> Result<(), String> {
+    match dcd_case(patient_id) {
+        Some(case) if case.state == "PERFUSED" => Ok(()),
+        Some(case) if case.state == "ABANDONED" => {
+            Err("DCD recovery abandoned - patient did not progress to circulatory arrest".to_string())
+        }
+        Some(case) => Err(format!("DCD protocol incomplete: {}", case.state)),
+        None => Err("DCD donor has no withdrawal-of-support record".to_string()),
+    }
+}
+
+// Carried across upgrades with the event log
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct DcdState {
+    cases: BTreeMap<String, DcdCase>,
+}
+
+pub(crate) fn snapshot() -> DcdState {
+    DcdState {
+        cases: DCD_CASES.with(|d| d.borrow().clone()),
+    }
+}
+
+pub(crate) fn restore(state: Option<DcdState>) {
+    let Some(state) = state else {
+        return;
+    };
+    DCD_CASES.with(|d| *d.borrow_mut() = state.cases);
+}
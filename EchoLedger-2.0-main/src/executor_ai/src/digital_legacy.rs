@@ -0,0 +1,291 @@
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
+};
+use ic_cdk::{call, caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Nat};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::reconciliation::Outbound;
+use crate::{
+    audit, clock, derive_patient_hash, external_reference, ids, outcall_budget, resilience, DirectiveExecution,
+    DIRECTIVE_MANAGER_CANISTER_ID,
+};
+
+// After death, each digital legacy wish on the directive becomes one instruction to the legacy
+// service registered for that account's provider: delete, memorialize, or transfer to a named
+// person. Services without an endpoint keep their instructions queued for pickup.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LegacyService {
+    pub service_id: String,
+    pub name: String,
+    pub providers: Vec<String>, // account providers it acts for, e.g. "GOOGLE", "META"
+    pub endpoint_url: Option<String>,
+    pub principal: Option<Principal>,
+    pub active: bool,
+}
+
+// Mirrors directive_manager's record
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DigitalLegacyWish {
+    pub service: String,
+    pub account_identifier: String,
+    pub action: String,
+    pub transfer_to: Option<String>,
+    pub note: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LegacyInstruction {
+    pub instruction_id: String,
+    pub patient_id: String,
+    pub service_id: Option<String>, // None when no registered service covers the provider
+    pub wish: DigitalLegacyWish,
+    pub issued_at: u64,
+    pub status: String, // "SENT", "QUEUED", "FAILED", "UNROUTED", "CONFIRMED"
+    pub last_error: Option<String>,
+}
+
+// The instruction body; the service knows the patient only by its own reference
+#[derive(Serialize)]
+struct InstructionPayload<'a> {
+    instruction_id: &'a str,
+    patient_reference: &'a str,
+    provider: &'a str,
+    account_identifier: &'a str,
+    action: &'a str,
+    transfer_to: Option<&'a str>,
+    note: Option<&'a str>,
+    issued_at: u64,
+}
+
+thread_local! {
+    static LEGACY_SERVICES: RefCell<BTreeMap<String, LegacyService>> = RefCell::new(BTreeMap::new());
+    static LEGACY_INSTRUCTIONS: RefCell<BTreeMap<String, LegacyInstruction>> = RefCell::new(BTreeMap::new());
+}
+
+const OUTCALL_CYCLES: u128 = 50_000_000_000;
+
+#[update]
+fn register_legacy_service(service: LegacyService) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage legacy services".to_string());
+    }
+    if service.providers.is_empty() {
+        return Err("A legacy service must act for at least one provider".to_string());
+    }
+    if let Some(url) = &service.endpoint_url {
+        if !url.starts_with("https://") {
+            return Err("Legacy service endpoints must use HTTPS".to_string());
+        }
+    }
+    let service = LegacyService {
+        providers: service.providers.iter().map(|p| p.trim().to_uppercase()).collect(),
+        ..service
+    };
+    LEGACY_SERVICES.with(|s| s.borrow_mut().insert(service.service_id.clone(), service));
+    Ok(())
+}
+
+#[update]
+fn set_legacy_service_active(service_id: String, active: bool) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage legacy services".to_string());
+    }
+    LEGACY_SERVICES.with(|s| {
+        let mut services = s.borrow_mut();
+        let service = services.get_mut(&service_id).ok_or_else(|| format!("Unknown legacy service: {}", service_id))?;
+        service.active = active;
+        Ok(())
+    })
+}
+
+#[query]
+fn get_legacy_services() -> Vec<LegacyService> {
+    LEGACY_SERVICES.with(|s| s.borrow().values().cloned().collect())
+}
+
+#[query]
+fn get_legacy_instructions(patient_id: String) -> Vec<LegacyInstruction> {
+    LEGACY_INSTRUCTIONS.with(|i| i.borrow().values().filter(|x| x.patient_id == patient_id).cloned().collect())
+}
+
+// A service polls its queued instructions when it has no endpoint, or catches up after a failure
+#[query]
+fn get_pending_legacy_instructions() -> Vec<LegacyInstruction> {
+    let Some(service_id) = service_for_principal(caller()) else {
+        return Vec::new();
+    };
+    LEGACY_INSTRUCTIONS.with(|i| {
+        i.borrow()
+            .values()
+            .filter(|x| x.service_id.as_ref() == Some(&service_id) && (x.status == "QUEUED" || x.status == "FAILED"))
+            .cloned()
+            .collect()
+    })
+}
+
+// The service reports it has carried the instruction out
+#[update]
+fn confirm_legacy_instruction(instruction_id: String) -> Result<LegacyInstruction, String> {
+    let service_id = service_for_principal(caller()).ok_or("Caller does not represent a legacy service")?;
+    let instruction = LEGACY_INSTRUCTIONS.with(|i| {
+        let mut instructions = i.borrow_mut();
+        let instruction = instructions.get_mut(&instruction_id)
+            .ok_or_else(|| format!("Legacy instruction not found: {}", instruction_id))?;
+        if instruction.service_id.as_ref() != Some(&service_id) {
+            return Err("This instruction was issued to another service".to_string());
+        }
+        instruction.status = "CONFIRMED".to_string();
+        Ok(instruction.clone())
+    })?;
+    if let Ok(payload) = serde_json::to_vec(&instruction) {
+        audit::append_audit_entry("LEGACY_INSTRUCTION_CONFIRMED", &instruction.instruction_id, &payload);
+    }
+    Ok(instruction)
+}
+
+// None when the patient left no digital legacy wishes
+pub(crate) async fn execute_digital_legacy(patient_id: &str) -> Result<Option<DirectiveExecution>, String> {
+    let wishes = fetch_wishes(patient_id).await?;
+    if wishes.is_empty() {
+        return Ok(None);
+    }
+    ic_cdk::println!("💾 Executing digital legacy for patient: {}", patient_id);
+    let patient_id_hash = derive_patient_hash(patient_id).await?;
+
+    let now = clock::now();
+    let issued = wishes.len();
+    let mut services_instructed = Vec::new();
+    let mut settled = 0;
+    for wish in wishes {
+        let service = LEGACY_SERVICES.with(|s| {
+            s.borrow().values().find(|x| x.active && x.providers.contains(&wish.service)).cloned()
+        });
+        let mut instruction = LegacyInstruction {
+            instruction_id: ids::new_id("LEGACY"),
+            patient_id: patient_id.to_string(),
+            service_id: service.as_ref().map(|s| s.service_id.clone()),
+            wish,
+            issued_at: now,
+            status: "UNROUTED".to_string(),
+            last_error: None,
+        };
+        if let Some(service) = service {
+            let outcome = match &service.endpoint_url {
+                Some(url) => send(url, &service, &instruction, &patient_id_hash).await,
+                None => Ok("QUEUED"),
+            };
+            match outcome {
+                Ok(status) => {
+                    instruction.status = status.to_string();
+                    settled += 1;
+                }
+                Err(e) => {
+                    instruction.status = "FAILED".to_string();
+                    instruction.last_error = Some(e);
+                }
+            }
+            if !services_instructed.contains(&service.service_id) {
+                services_instructed.push(service.service_id);
+            }
+        }
+        if let Ok(payload) = serde_json::to_vec(&instruction) {
+            audit::append_audit_entry("LEGACY_INSTRUCTION_ISSUED", &instruction.instruction_id, &payload);
+        }
+        LEGACY_INSTRUCTIONS.with(|i| i.borrow_mut().insert(instruction.instruction_id.clone(), instruction));
+    }
+
+    Ok(Some(DirectiveExecution {
+        directive_type: "DIGITAL_LEGACY".to_string(),
+        execution_status: if settled == issued { "COMPLETED" } else { "PARTIAL" }.to_string(),
+        organs_processed: vec![],
+        recipient_matches: vec![],
+        total_recipients_notified: services_instructed.len() as u32,
+        estimated_lives_saved: 0,
+        data_shared_with: services_instructed,
+        anonymization_verified: false,
+        research_impact_score: 0.0,
+    }))
+}
+
+async fn fetch_wishes(patient_id: &str) -> Result<Vec<DigitalLegacyWish>, String> {
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let (result,): (Result<Vec<DigitalLegacyWish>, String>,) = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call(directive_manager_id, "get_digital_legacy_wishes", (patient_id.to_string(),))
+    }).await.map_err(|msg| format!("Failed to fetch digital legacy wishes: {}", msg))?;
+    result
+}
+
+async fn send(
+    url: &str,
+    service: &LegacyService,
+    instruction: &LegacyInstruction,
+    patient_id_hash: &[u8]
+) -> Result<&'static str, String> {
+    let patient_reference = external_reference(patient_id_hash, &format!("legacy:{}", service.service_id)).await?;
+    let body = serde_json::to_vec(&InstructionPayload {
+        instruction_id: &instruction.instruction_id,
+        patient_reference: &patient_reference,
+        provider: &instruction.wish.service,
+        account_identifier: &instruction.wish.account_identifier,
+        action: &instruction.wish.action,
+        transfer_to: instruction.wish.transfer_to.as_deref(),
+        note: instruction.wish.note.as_deref(),
+        issued_at: instruction.issued_at,
+    })
+    .map_err(|e| format!("Failed to encode legacy instruction: {}", e))?;
+
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        max_response_bytes: Some(4_096),
+        method: HttpMethod::POST,
+        headers: vec![HttpHeader { name: "Content-Type".to_string(), value: "application/json".to_string() }],
+        body: Some(body),
+        transform: None,
+    };
+    // Nothing here is time-critical, so it waits behind emergency traffic
+    outcall_budget::acquire("NOTIFICATION", OUTCALL_CYCLES).await?;
+    let target = format!("legacy:{}", service.service_id);
+    let (response,) = resilience::guarded_call(&target, || http_request(request.clone(), OUTCALL_CYCLES))
+        .await
+        .map_err(|msg| format!("{} delivery failed: {}", service.service_id, msg))?;
+    if response.status >= Nat::from(200u32) && response.status < Nat::from(300u32) {
+        Ok("SENT")
+    } else {
+        Err(format!("{} rejected the instruction with status {}", service.service_id, response.status))
+    }
+}
+
+fn service_for_principal(principal: Principal) -> Option<String> {
+    LEGACY_SERVICES.with(|s| {
+        s.borrow().values().find(|x| x.principal == Some(principal)).map(|x| x.service_id.clone())
+    })
+}
+
+// Instructions issued in [from, to); the service acts by confirming
+pub(crate) fn outbound_between(from: u64, to: u64) -> Vec<Outbound> {
+    LEGACY_INSTRUCTIONS.with(|i| {
+        i.borrow()
+            .values()
+            .filter(|x| x.issued_at >= from && x.issued_at < to)
+            .map(|x| Outbound {
+                channel: "DIGITAL_LEGACY_INSTRUCTION",
+                reference_id: x.instruction_id.clone(),
+                counterparty: x.service_id.clone().unwrap_or_else(|| x.wish.service.clone()),
+                sent_at: x.issued_at,
+                delivered: x.status == "SENT" || x.status == "CONFIRMED",
+                acted: x.status == "CONFIRMED",
+                detail: match x.status.as_str() {
+                    "UNROUTED" => Some(format!("No registered service covers {}", x.wish.service)),
+                    "QUEUED" => Some("Never collected by the service".to_string()),
+                    _ => x.last_error.clone(),
+                },
+            })
+            .collect()
+    })
+}
@@ -0,0 +1,299 @@
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
+};
+use ic_cdk::{call, caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Nat};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::reconciliation::Outbound;
+use crate::{
+    audit, clock, derive_patient_hash, external_reference, outcall_budget, resilience, DirectiveExecution, ExecutionResult,
+    DIRECTIVE_MANAGER_CANISTER_ID,
+};
+
+// Funeral and disposition wishes are not carried out by the canister; they are reported. The
+// execution picks up the patient's preferences as a step, and once the run completes the full
+// report goes to next-of-kin with the other directives and to the designated funeral provider.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FuneralProvider {
+    pub provider_id: String,
+    pub name: String,
+    pub endpoint_url: Option<String>,
+    pub principal: Option<Principal>,
+    pub active: bool,
+}
+
+// Mirrors directive_manager's record
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DispositionPreferences {
+    pub method: String,
+    pub religious_requirements: Vec<String>,
+    pub funeral_provider_id: Option<String>,
+    pub funeral_home_name: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DispositionReport {
+    pub execution_id: String,
+    pub patient_id: String,
+    pub preferences: DispositionPreferences,
+    pub executed_directives: Vec<String>,
+    pub provider_delivery: String, // "PENDING", "SENT", "AWAITING_PICKUP", "FAILED", "NO_PROVIDER", "ACKNOWLEDGED"
+    pub last_error: Option<String>,
+    pub created_at: u64,
+}
+
+// The provider's copy; the patient is named only by the provider's own reference
+#[derive(Serialize)]
+struct ReportPayload<'a> {
+    execution_id: &'a str,
+    patient_reference: &'a str,
+    method: &'a str,
+    religious_requirements: &'a [String],
+    notes: Option<&'a str>,
+    executed_directives: &'a [String],
+    reported_at: u64,
+}
+
+thread_local! {
+    static FUNERAL_PROVIDERS: RefCell<BTreeMap<String, FuneralProvider>> = RefCell::new(BTreeMap::new());
+    // execution_id -> report
+    static DISPOSITION_REPORTS: RefCell<BTreeMap<String, DispositionReport>> = RefCell::new(BTreeMap::new());
+}
+
+const OUTCALL_CYCLES: u128 = 50_000_000_000;
+
+#[update]
+fn register_funeral_provider(provider: FuneralProvider) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage funeral providers".to_string());
+    }
+    if let Some(url) = &provider.endpoint_url {
+        if !url.starts_with("https://") {
+            return Err("Funeral provider endpoints must use HTTPS".to_string());
+        }
+    }
+    let provider = FuneralProvider { provider_id: provider.provider_id.trim().to_uppercase(), ..provider };
+    FUNERAL_PROVIDERS.with(|p| p.borrow_mut().insert(provider.provider_id.clone(), provider));
+    Ok(())
+}
+
+#[update]
+fn set_funeral_provider_active(provider_id: String, active: bool) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage funeral providers".to_string());
+    }
+    FUNERAL_PROVIDERS.with(|p| {
+        let mut providers = p.borrow_mut();
+        let provider = providers.get_mut(&provider_id).ok_or_else(|| format!("Unknown funeral provider: {}", provider_id))?;
+        provider.active = active;
+        Ok(())
+    })
+}
+
+#[query]
+fn get_funeral_providers() -> Vec<FuneralProvider> {
+    FUNERAL_PROVIDERS.with(|p| p.borrow().values().cloned().collect())
+}
+
+#[query]
+fn get_disposition_report(execution_id: String) -> Option<DispositionReport> {
+    DISPOSITION_REPORTS.with(|r| r.borrow().get(&execution_id).cloned())
+}
+
+// Providers without an endpoint collect their reports here
+#[query]
+fn get_pending_disposition_reports() -> Vec<DispositionReport> {
+    let Some(provider_id) = provider_for_principal(caller()) else {
+        return Vec::new();
+    };
+    DISPOSITION_REPORTS.with(|r| {
+        r.borrow()
+            .values()
+            .filter(|x| x.preferences.funeral_provider_id.as_ref() == Some(&provider_id))
+            .filter(|x| x.provider_delivery == "AWAITING_PICKUP" || x.provider_delivery == "FAILED")
+            .cloned()
+            .collect()
+    })
+}
+
+#[update]
+fn acknowledge_disposition_report(execution_id: String) -> Result<DispositionReport, String> {
+    let provider_id = provider_for_principal(caller()).ok_or("Caller does not represent a funeral provider")?;
+    let report = DISPOSITION_REPORTS.with(|r| {
+        let mut reports = r.borrow_mut();
+        let report = reports.get_mut(&execution_id)
+            .ok_or_else(|| format!("Disposition report not found: {}", execution_id))?;
+        if report.preferences.funeral_provider_id.as_ref() != Some(&provider_id) {
+            return Err("This report was addressed to another provider".to_string());
+        }
+        report.provider_delivery = "ACKNOWLEDGED".to_string();
+        Ok(report.clone())
+    })?;
+    if let Ok(payload) = serde_json::to_vec(&report) {
+        audit::append_audit_entry("DISPOSITION_REPORT_ACKNOWLEDGED", &report.execution_id, &payload);
+    }
+    Ok(report)
+}
+
+// Records the preferences against the run; None when the patient left none
+pub(crate) async fn execute_disposition(patient_id: &str, execution_id: &str) -> Result<Option<DirectiveExecution>, String> {
+    let Some(preferences) = fetch_preferences(patient_id).await? else {
+        return Ok(None);
+    };
+    ic_cdk::println!("⚱️ Recording disposition preferences for patient: {}", patient_id);
+    let provider = preferences.funeral_provider_id.as_ref().and_then(|id| {
+        FUNERAL_PROVIDERS.with(|p| p.borrow().get(id).filter(|x| x.active).cloned())
+    });
+    let report = DispositionReport {
+        execution_id: execution_id.to_string(),
+        patient_id: patient_id.to_string(),
+        preferences,
+        executed_directives: vec![],
+        provider_delivery: if provider.is_some() { "PENDING" } else { "NO_PROVIDER" }.to_string(),
+        last_error: None,
+        created_at: clock::now(),
+    };
+    DISPOSITION_REPORTS.with(|r| r.borrow_mut().insert(execution_id.to_string(), report));
+
+    Ok(Some(DirectiveExecution {
+        directive_type: "DISPOSITION".to_string(),
+        execution_status: if provider.is_some() { "COMPLETED" } else { "FAMILY_ONLY" }.to_string(),
+        organs_processed: vec![],
+        recipient_matches: vec![],
+        total_recipients_notified: u32::from(provider.is_some()),
+        estimated_lives_saved: 0,
+        data_shared_with: provider.map(|p| p.provider_id).into_iter().collect(),
+        anonymization_verified: false,
+        research_impact_score: 0.0,
+    }))
+}
+
+// One line for the next-of-kin notice
+pub(crate) fn summary(execution_id: &str) -> Option<String> {
+    let report = DISPOSITION_REPORTS.with(|r| r.borrow().get(execution_id).cloned())?;
+    let preferences = &report.preferences;
+    let mut line = format!("Disposition: {}", preferences.method.replace('_', " ").to_lowercase());
+    if !preferences.religious_requirements.is_empty() {
+        line.push_str(&format!("; religious requirements: {}", preferences.religious_requirements.join(", ")));
+    }
+    let provider_name = preferences.funeral_provider_id.as_ref()
+        .and_then(|id| FUNERAL_PROVIDERS.with(|p| p.borrow().get(id).map(|x| x.name.clone())))
+        .or_else(|| preferences.funeral_home_name.clone());
+    if let Some(name) = provider_name {
+        line.push_str(&format!("; funeral provider: {}", name));
+    }
+    Some(line)
+}
+
+// Called once the run has completed, so the provider sees the whole report
+pub(crate) async fn deliver_report(execution_result: &ExecutionResult) {
+    let execution_id = &execution_result.execution_id;
+    let executed: Vec<String> = execution_result.directives_executed.iter().map(|d| d.directive_type.clone()).collect();
+    let Some(report) = DISPOSITION_REPORTS.with(|r| {
+        let mut reports = r.borrow_mut();
+        let report = reports.get_mut(execution_id)?;
+        report.executed_directives = executed;
+        Some(report.clone())
+    }) else {
+        return;
+    };
+    let provider = report.preferences.funeral_provider_id.as_ref()
+        .and_then(|id| FUNERAL_PROVIDERS.with(|p| p.borrow().get(id).filter(|x| x.active).cloned()));
+    let Some(provider) = provider else {
+        return;
+    };
+
+    let (status, error) = match &provider.endpoint_url {
+        None => ("AWAITING_PICKUP", None),
+        Some(url) => match send(url, &provider, &report).await {
+            Ok(()) => ("SENT", None),
+            Err(e) => ("FAILED", Some(e)),
+        },
+    };
+    if let Some(e) = &error {
+        ic_cdk::println!("⚠️ Disposition report for {} not delivered to {}: {}", execution_id, provider.provider_id, e);
+    }
+    DISPOSITION_REPORTS.with(|r| {
+        if let Some(report) = r.borrow_mut().get_mut(execution_id) {
+            report.provider_delivery = status.to_string();
+            report.last_error = error;
+        }
+    });
+}
+
+pub(crate) async fn fetch_preferences(patient_id: &str) -> Result<Option<DispositionPreferences>, String> {
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let (result,): (Result<Option<DispositionPreferences>, String>,) = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call(directive_manager_id, "get_disposition_preferences", (patient_id.to_string(),))
+    }).await.map_err(|msg| format!("Failed to fetch disposition preferences: {}", msg))?;
+    result
+}
+
+async fn send(url: &str, provider: &FuneralProvider, report: &DispositionReport) -> Result<(), String> {
+    let patient_id_hash = derive_patient_hash(&report.patient_id).await?;
+    let patient_reference = external_reference(&patient_id_hash, &format!("funeral:{}", provider.provider_id)).await?;
+    let body = serde_json::to_vec(&ReportPayload {
+        execution_id: &report.execution_id,
+        patient_reference: &patient_reference,
+        method: &report.preferences.method,
+        religious_requirements: &report.preferences.religious_requirements,
+        notes: report.preferences.notes.as_deref(),
+        executed_directives: &report.executed_directives,
+        reported_at: clock::now(),
+    })
+    .map_err(|e| format!("Failed to encode disposition report: {}", e))?;
+
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        max_response_bytes: Some(4_096),
+        method: HttpMethod::POST,
+        headers: vec![HttpHeader { name: "Content-Type".to_string(), value: "application/json".to_string() }],
+        body: Some(body),
+        transform: None,
+    };
+    outcall_budget::acquire("NOTIFICATION", OUTCALL_CYCLES).await?;
+    let target = format!("funeral:{}", provider.provider_id);
+    let (response,) = resilience::guarded_call(&target, || http_request(request.clone(), OUTCALL_CYCLES))
+        .await
+        .map_err(|msg| format!("{} delivery failed: {}", provider.provider_id, msg))?;
+    if response.status >= Nat::from(200u32) && response.status < Nat::from(300u32) {
+        Ok(())
+    } else {
+        Err(format!("{} rejected the report with status {}", provider.provider_id, response.status))
+    }
+}
+
+fn provider_for_principal(principal: Principal) -> Option<String> {
+    FUNERAL_PROVIDERS.with(|p| {
+        p.borrow().values().find(|x| x.principal == Some(principal)).map(|x| x.provider_id.clone())
+    })
+}
+
+// Reports made in [from, to) for a named provider; the provider acts by acknowledging
+pub(crate) fn outbound_between(from: u64, to: u64) -> Vec<Outbound> {
+    DISPOSITION_REPORTS.with(|r| {
+        r.borrow()
+            .values()
+            .filter(|x| x.created_at >= from && x.created_at < to && x.provider_delivery != "NO_PROVIDER")
+            .map(|x| Outbound {
+                channel: "DISPOSITION_REPORT",
+                reference_id: x.execution_id.clone(),
+                counterparty: x.preferences.funeral_provider_id.clone().unwrap_or_default(),
+                sent_at: x.created_at,
+                delivered: x.provider_delivery == "SENT" || x.provider_delivery == "ACKNOWLEDGED",
+                acted: x.provider_delivery == "ACKNOWLEDGED",
+                detail: match x.provider_delivery.as_str() {
+                    "AWAITING_PICKUP" => Some("Never picked up by the provider".to_string()),
+                    _ => x.last_error.clone(),
+                },
+            })
+            .collect()
+    })
+}
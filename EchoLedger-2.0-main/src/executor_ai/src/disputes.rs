@@ -0,0 +1,308 @@
+use ic_cdk::{call, caller, Principal};
+use ic_cdk_macros::update;
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::{audit, clock, derive_patient_hash, ethics, ids, resilience, traps, validation, DIRECTIVE_MANAGER_CANISTER_ID};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Dispute {
+    pub dispute_id: String,
+    pub patient_id: String,
+    pub filed_by: Principal,
+    pub directive_type: Option<String>,
+    pub reason: String,
+    pub status: String, // "UNDER_REVIEW", "ESCALATED", "RESOLVED_PROCEED", "RESOLVED_UPHELD"
+    pub filed_at: u64,
+    pub review_deadline: u64,
+    pub ethics_reviewer: Option<Principal>,
+    pub resolution: Option<String>,
+    pub resolved_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DisputeHoldConfig {
+    pub review_window_hours: u64,
+    pub ethics_reviewers: Vec<Principal>,
+}
+
+thread_local! {
+    pub(crate) static DISPUTES: RefCell<BTreeMap<String, Dispute>> = RefCell::new(BTreeMap::new());
+    static HOLD_CONFIG: RefCell<DisputeHoldConfig> = RefCell::new(DisputeHoldConfig {
+        review_window_hours: 72,
+        ethics_reviewers: vec![],
+    });
+    static NEXT_REVIEWER: RefCell<usize> = RefCell::new(0);
+}
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+// Both hold execution; an escalated dispute missed its review deadline and is the committee's to decide
+const HOLDING_STATUSES: [&str; 2] = ["UNDER_REVIEW", "ESCALATED"];
+
+// Family or proxy objection against a pending execution; places the patient's executions on hold
+#[update]
+async fn file_objection(
+    patient_id: String,
+    directive_type: Option<String>,
+    reason: String
+) -> Result<Dispute, String> {
+    let _watch = traps::Watch::start("OBJECTION");
+    let reason = validation::text("reason", &reason, validation::MAX_REASON_BYTES)?;
+    let objector = caller();
+    if objector == Principal::anonymous() {
+        return Err("Anonymous callers may not file objections".to_string());
+    }
+    if !is_authorized_objector(&patient_id, objector).await? {
+        return Err("Caller is not a registered family member or proxy for this patient".to_string());
+    }
+
+    let now = clock::now();
+    let window_hours = HOLD_CONFIG.with(|config| config.borrow().review_window_hours);
+    let window = review_window_nanos(window_hours)?;
+    let review_deadline = now.checked_add(window).ok_or("Review deadline is past the end of the clock")?;
+    let reviewer = HOLD_CONFIG.with(|config| assign_ethics_reviewer(&config.borrow().ethics_reviewers));
+
+    let dispute = Dispute {
+        dispute_id: ids::new_id("DISPUTE"),
+        patient_id: patient_id.clone(),
+        filed_by: objector,
+        directive_type,
+        reason,
+        status: "UNDER_REVIEW".to_string(),
+        filed_at: now,
+        review_deadline,
+        ethics_reviewer: reviewer,
+        resolution: None,
+        resolved_at: None,
+    };
+
+    DISPUTES.with(|disputes| {
+        disputes.borrow_mut().insert(dispute.dispute_id.clone(), dispute.clone());
+    });
+
+    ethics::open_case(&patient_id, "DISPUTE", &dispute.dispute_id, &dispute.reason);
+    schedule_escalation(window);
+
+    ic_cdk::println!(
+        "⚖️ DISPUTE FILED: {} - Patient: {} - Reviewer: {}",
+        dispute.dispute_id,
+        patient_id,
+        reviewer.map(|p| p.to_text()).unwrap_or_else(|| "unassigned".to_string())
+    );
+
+    Ok(dispute)
+}
+
+// Record the ethics review outcome; "PROCEED" releases the hold, "UPHELD" blocks the contested execution
+#[update]
+fn resolve_dispute(dispute_id: String, outcome: String, resolution: String) -> Result<Dispute, String> {
+    let resolution = validation::text("resolution", &resolution, validation::MAX_REASON_BYTES)?;
+    let status = match outcome.as_str() {
+        "PROCEED" => "RESOLVED_PROCEED",
+        "UPHELD" => "RESOLVED_UPHELD",
+        _ => return Err(format!("Unknown dispute outcome: {}", outcome)),
+    };
+
+    escalate_overdue();
+    let resolved = DISPUTES.with(|disputes| {
+        let mut disputes = disputes.borrow_mut();
+        let dispute = disputes.get_mut(&dispute_id)
+            .ok_or_else(|| format!("Dispute not found: {}", dispute_id))?;
+
+        let reviewer = caller();
+        let controller = ic_cdk::api::is_controller(&reviewer);
+        if dispute.ethics_reviewer != Some(reviewer) && !controller {
+            return Err("Only the assigned ethics reviewer may resolve this dispute".to_string());
+        }
+        if !HOLDING_STATUSES.contains(&dispute.status.as_str()) {
+            return Err(format!("Dispute already resolved: {}", dispute.status));
+        }
+        if dispute.status == "ESCALATED" && !controller {
+            return Err("The review deadline has passed; the ethics committee now decides this dispute".to_string());
+        }
+
+        dispute.status = status.to_string();
+        dispute.resolution = Some(resolution);
+        dispute.resolved_at = Some(clock::now());
+        Ok::<_, String>(dispute.clone())
+    })?;
+
+    ethics::supersede_dispute_case(&resolved.dispute_id, &resolved.status);
+    Ok(resolved)
+}
+
+// Ethics committee decisions on referred disputes resolve them the same way a reviewer would
+pub(crate) fn apply_board_decision(dispute_id: &str, outcome: &str, case_id: &str) {
+    DISPUTES.with(|disputes| {
+        if let Some(dispute) = disputes.borrow_mut().get_mut(dispute_id) {
+            if HOLDING_STATUSES.contains(&dispute.status.as_str()) {
+                dispute.status = format!("RESOLVED_{}", outcome);
+                dispute.resolution = Some(format!("Ethics committee decision {}", case_id));
+                dispute.resolved_at = Some(clock::now());
+            }
+        }
+    });
+}
+
+#[update]
+fn configure_dispute_hold(config: DisputeHoldConfig) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may configure dispute holds".to_string());
+    }
+    if config.review_window_hours == 0 {
+        return Err("Review window must be at least one hour".to_string());
+    }
+    review_window_nanos(config.review_window_hours)?;
+
+    HOLD_CONFIG.with(|c| *c.borrow_mut() = config);
+    Ok(())
+}
+
+// Objections name family members and their reasons. The patient's representatives and controllers
+// see every dispute for the patient; an objector or assigned reviewer sees only their own.
+// An update because deciding who represents the patient takes a call to directive_manager.
+#[update]
+async fn get_disputes(patient_id: String) -> Result<Vec<Dispute>, String> {
+    let requester = caller();
+    if requester == Principal::anonymous() {
+        return Err("Anonymous callers may not read disputes".to_string());
+    }
+    if ic_cdk::api::is_controller(&requester) || is_authorized_objector(&patient_id, requester).await? {
+        return Ok(disputes_for(&patient_id));
+    }
+    disputes_party_to(&patient_id, requester)
+}
+
+// The disputes a principal filed or was assigned to review; anyone else is refused
+pub(crate) fn disputes_party_to(patient_id: &str, requester: Principal) -> Result<Vec<Dispute>, String> {
+    let own: Vec<Dispute> = disputes_for(patient_id)
+        .into_iter()
+        .filter(|d| d.filed_by == requester || d.ethics_reviewer == Some(requester))
+        .collect();
+    if own.is_empty() {
+        return Err("Only the patient's representatives, parties to a dispute or controllers may read disputes".to_string());
+    }
+    Ok(own)
+}
+
+pub(crate) fn disputes_for(patient_id: &str) -> Vec<Dispute> {
+    DISPUTES.with(|disputes| {
+        disputes.borrow()
+            .values()
+            .filter(|d| d.patient_id == patient_id)
+            .cloned()
+            .collect()
+    })
+}
+
+// Execution gate: an unresolved dispute holds every execution for the patient
+pub(crate) fn active_hold(patient_id: &str) -> Option<Dispute> {
+    DISPUTES.with(|disputes| {
+        disputes.borrow()
+            .values()
+            .find(|d| d.patient_id == patient_id && HOLDING_STATUSES.contains(&d.status.as_str()))
+            .cloned()
+    })
+}
+
+// Directive types whose execution was blocked by an upheld objection
+pub(crate) fn upheld_objections(patient_id: &str) -> Vec<Option<String>> {
+    DISPUTES.with(|disputes| {
+        disputes.borrow()
+            .values()
+            .filter(|d| d.patient_id == patient_id && d.status == "RESOLVED_UPHELD")
+            .map(|d| d.directive_type.clone())
+            .collect()
+    })
+}
+
+fn review_window_nanos(window_hours: u64) -> Result<u64, String> {
+    window_hours.checked_mul(NANOS_PER_HOUR).ok_or_else(|| format!("Review window of {} hours is too long", window_hours))
+}
+
+// A reviewer who lets the deadline pass loses the dispute to the committee; the hold stays until it decides
+fn escalate_overdue() {
+    let now = clock::now();
+    let escalated: Vec<String> = DISPUTES.with(|disputes| {
+        disputes.borrow_mut()
+            .values_mut()
+            .filter(|d| d.status == "UNDER_REVIEW" && d.review_deadline <= now)
+            .map(|d| {
+                d.status = "ESCALATED".to_string();
+                d.dispute_id.clone()
+            })
+            .collect()
+    });
+    for dispute_id in escalated {
+        audit::append_audit_entry("DISPUTE_ESCALATED", &dispute_id, &now.to_be_bytes());
+        ic_cdk::println!("⚖️ DISPUTE ESCALATED: {} missed its review deadline", dispute_id);
+    }
+}
+
+fn schedule_escalation(delay_nanos: u64) {
+    ic_cdk_timers::set_timer(Duration::from_nanos(delay_nanos), escalate_overdue);
+}
+
+// Timers do not survive an upgrade; re-arm one for each dispute still under review
+pub(crate) fn ensure_escalation_timers() {
+    let now = clock::now();
+    let deadlines: Vec<u64> = DISPUTES.with(|disputes| {
+        disputes.borrow().values().filter(|d| d.status == "UNDER_REVIEW").map(|d| d.review_deadline).collect()
+    });
+    for deadline in deadlines {
+        schedule_escalation(deadline.saturating_sub(now));
+    }
+}
+
+fn assign_ethics_reviewer(reviewers: &[Principal]) -> Option<Principal> {
+    if reviewers.is_empty() {
+        return None;
+    }
+    NEXT_REVIEWER.with(|next| {
+        let mut next = next.borrow_mut();
+        let reviewer = reviewers[*next % reviewers.len()];
+        *next += 1;
+        Some(reviewer)
+    })
+}
+
+// directive_manager decides who speaks for the patient: the owner, a proxy the owner granted, or a
+// contact the patient or a proxy registered with that principal. The caller is the objector itself.
+async fn is_authorized_objector(patient_id: &str, objector: Principal) -> Result<bool, String> {
+    let patient_id_hash = derive_patient_hash(patient_id).await?;
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+
+    let (result,): (Result<bool, String>,) = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call(directive_manager_id, "is_patient_representative", (patient_id_hash.clone(), objector))
+    }).await.map_err(|msg| format!("Failed to check the objector: {}", msg))?;
+    result
+}
+
+// Carried across upgrades with the event log; a hold must outlive the upgrade it straddles
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct DisputesState {
+    disputes: BTreeMap<String, Dispute>,
+    hold_config: DisputeHoldConfig,
+    next_reviewer: u64,
+}
+
+pub(crate) fn snapshot() -> DisputesState {
+    DisputesState {
+        disputes: DISPUTES.with(|d| d.borrow().clone()),
+        hold_config: HOLD_CONFIG.with(|h| h.borrow().clone()),
+        next_reviewer: NEXT_REVIEWER.with(|n| *n.borrow() as u64),
+    }
+}
+
+pub(crate) fn restore(state: Option<DisputesState>) {
+    let Some(state) = state else {
+        return;
+    };
+    DISPUTES.with(|d| *d.borrow_mut() = state.disputes);
+    HOLD_CONFIG.with(|h| *h.borrow_mut() = state.hold_config);
+    NEXT_REVIEWER.with(|n| *n.borrow_mut() = state.next_reviewer as usize);
+}
@@ -0,0 +1,344 @@
+use ic_cdk::{call, caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::evidence::ConsentDirective;
+use crate::{audit, clock, disputes, governance, ids, resilience, validation, DIRECTIVE_MANAGER_CANISTER_ID, LLM_CANISTER_ID};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EthicsVote {
+    pub member: Principal,
+    pub vote: String, // "PROCEED", "BLOCK", "ABSTAIN"
+    pub rationale: String,
+    pub cast_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EthicsCase {
+    pub case_id: String,
+    pub patient_id: String,
+    pub source: String, // "DISPUTE", "LOW_LEGAL_VALIDITY", "CONTRADICTION"
+    pub source_reference: String,
+    pub summary: String,
+    pub status: String, // "OPEN", "DECIDED", "SUPERSEDED"
+    pub votes: Vec<EthicsVote>,
+    pub decision: Option<String>,
+    pub decided_at: Option<u64>,
+    pub voting_record_hash: Option<Vec<u8>>,
+    pub referred_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct QuorumRules {
+    pub members: Vec<Principal>,
+    pub quorum: u32,
+    pub proceed_threshold_percent: u8,
+}
+
+thread_local! {
+    pub(crate) static ETHICS_CASES: RefCell<BTreeMap<String, EthicsCase>> = RefCell::new(BTreeMap::new());
+    static QUORUM_RULES: RefCell<QuorumRules> = RefCell::new(QuorumRules {
+        members: vec![],
+        quorum: 3,
+        proceed_threshold_percent: 67,
+    });
+}
+
+const REFERRAL_SOURCES: [&str; 3] = ["DISPUTE", "LOW_LEGAL_VALIDITY", "CONTRADICTION"];
+
+// An analysis scoring below this is not legally sound enough to act on without the committee
+const LOW_LEGAL_VALIDITY: f32 = 0.7;
+
+// Mirrors llm_canister::replay::DirectiveReview
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct DirectiveReview {
+    trace_id: u64,
+    legal_validity_score: f32,
+    contradictions: Vec<String>,
+    recorded_at: u64,
+}
+
+// Refer a contested or ambiguous case to the ethics committee
+#[update]
+fn refer_ethics_case(
+    patient_id: String,
+    source: String,
+    source_reference: String,
+    summary: String
+) -> Result<EthicsCase, String> {
+    let referrer = caller();
+    if !ic_cdk::api::is_controller(&referrer) && !is_committee_member(&referrer) {
+        return Err("Only controllers or committee members may refer cases".to_string());
+    }
+    let summary = validation::text("summary", &summary, validation::MAX_REASON_BYTES)?;
+    if !REFERRAL_SOURCES.contains(&source.as_str()) {
+        return Err(format!("Unknown referral source: {}", source));
+    }
+
+    Ok(open_case(&patient_id, &source, &source_reference, &summary))
+}
+
+pub(crate) fn open_case(patient_id: &str, source: &str, source_reference: &str, summary: &str) -> EthicsCase {
+    let now = clock::now();
+    let case = EthicsCase {
+        case_id: ids::new_id("ETHICS"),
+        patient_id: patient_id.to_string(),
+        source: source.to_string(),
+        source_reference: source_reference.to_string(),
+        summary: summary.to_string(),
+        status: "OPEN".to_string(),
+        votes: vec![],
+        decision: None,
+        decided_at: None,
+        voting_record_hash: None,
+        referred_at: now,
+    };
+
+    ETHICS_CASES.with(|cases| {
+        cases.borrow_mut().insert(case.case_id.clone(), case.clone());
+    });
+
+    ic_cdk::println!("🏛️ ETHICS REFERRAL: {} - Patient: {} - Source: {}", case.case_id, patient_id, source);
+    case
+}
+
+// Run before an execution is gated: an unsigned directive version, or a newest analysis of low legal
+// validity or with contradicting extractions, opens a case that holds the execution. Each is referred
+// once; a decided case is not reopened by the next attempt.
+pub(crate) async fn refer_unsettled_directives(patient_id: &str) -> Result<(), String> {
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let (versions,): (Vec<ConsentDirective>,) = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call(directive_manager_id, "get_directive_versions", (patient_id.to_string(),))
+    }).await.map_err(|msg| format!("Failed to load directive versions: {}", msg))?;
+
+    let mut latest: BTreeMap<&str, &ConsentDirective> = BTreeMap::new();
+    for version in &versions {
+        latest.insert(&version.directive_type, version);
+    }
+    for directive in latest.values().filter(|d| d.signature.is_empty()) {
+        let reference = format!("{}@{}", directive.directive_type, directive.timestamp);
+        refer_once(patient_id, "LOW_LEGAL_VALIDITY", &reference, &format!(
+            "{} directive version of {} is unsigned", directive.directive_type, directive.timestamp
+        ));
+    }
+
+    // Without a reachable llm_canister there is no analysis to weigh; the signatures above still count
+    let llm_id = Principal::from_text(LLM_CANISTER_ID).map_err(|_| "Invalid LLM canister ID")?;
+    let review = match resilience::guarded_call(resilience::LLM_CANISTER, || {
+        call::<_, (Result<Option<DirectiveReview>, String>,)>(llm_id, "get_directive_review", (patient_id.to_string(),))
+    }).await {
+        Ok((Ok(review),)) => review,
+        Ok((Err(e),)) | Err(e) => {
+            ic_cdk::println!("⚠️ Directive review unavailable for {}: {}", patient_id, e);
+            None
+        }
+    };
+    if let Some(review) = review {
+        let reference = format!("TRACE_{}", review.trace_id);
+        if review.legal_validity_score < LOW_LEGAL_VALIDITY {
+            refer_once(patient_id, "LOW_LEGAL_VALIDITY", &reference, &format!(
+                "Directive analysis of {} scored legal validity {:.2}", review.recorded_at, review.legal_validity_score
+            ));
+        }
+        if !review.contradictions.is_empty() {
+            refer_once(patient_id, "CONTRADICTION", &reference, &format!(
+                "Directive analysis of {} contradicts itself: {}", review.recorded_at, review.contradictions.join("; ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn refer_once(patient_id: &str, source: &str, source_reference: &str, summary: &str) {
+    let referred = ETHICS_CASES.with(|cases| {
+        cases.borrow().values().any(|c| {
+            c.patient_id == patient_id && c.source == source && c.source_reference == source_reference
+        })
+    });
+    if !referred {
+        open_case(patient_id, source, source_reference, summary);
+    }
+}
+
+// Committee members vote with rationale; the case is decided once quorum is reached
+#[update]
+fn cast_ethics_vote(case_id: String, vote: String, rationale: String) -> Result<EthicsCase, String> {
+    let member = caller();
+    if !is_committee_member(&member) {
+        return Err("Caller is not an ethics committee member".to_string());
+    }
+    if !["PROCEED", "BLOCK", "ABSTAIN"].contains(&vote.as_str()) {
+        return Err(format!("Unknown vote: {}", vote));
+    }
+    let rationale = validation::text("rationale", &rationale, validation::MAX_REASON_BYTES)?;
+    if rationale.is_empty() {
+        return Err("A rationale is required for every vote".to_string());
+    }
+
+    let rules = QUORUM_RULES.with(|r| r.borrow().clone());
+
+    let decided = ETHICS_CASES.with(|cases| {
+        let mut cases = cases.borrow_mut();
+        let case = cases.get_mut(&case_id).ok_or_else(|| format!("Ethics case not found: {}", case_id))?;
+
+        if case.status != "OPEN" {
+            return Err("Ethics case already decided".to_string());
+        }
+        if case.votes.iter().any(|v| v.member == member) {
+            return Err("Member has already voted on this case".to_string());
+        }
+
+        case.votes.push(EthicsVote {
+            member,
+            vote,
+            rationale,
+            cast_at: clock::now(),
+        });
+
+        if let Some(decision) = tally(&case.votes, &rules) {
+            let voting_record = serde_json::to_vec(&case.votes).map_err(|e| e.to_string())?;
+            let entry = audit::append_audit_entry("ETHICS_DECISION", &case.case_id, &voting_record);
+            case.status = "DECIDED".to_string();
+            case.decision = Some(decision);
+            case.decided_at = Some(entry.timestamp);
+            case.voting_record_hash = Some(entry.payload_hash);
+        }
+
+        Ok::<_, String>(case.clone())
+    })?;
+
+    if decided.source == "DISPUTE" {
+        if let Some(decision) = &decided.decision {
+            let outcome = if decision == "PROCEED" { "PROCEED" } else { "UPHELD" };
+            disputes::apply_board_decision(&decided.source_reference, outcome, &decided.case_id);
+        }
+    }
+
+    Ok(decided)
+}
+
+// A dispute its reviewer resolved no longer needs the committee; its case is closed without a decision
+// so the referral stops holding execution and takes no more votes
+pub(crate) fn supersede_dispute_case(dispute_id: &str, dispute_status: &str) {
+    ETHICS_CASES.with(|cases| {
+        let mut cases = cases.borrow_mut();
+        let open = cases.values_mut()
+            .filter(|c| c.source == "DISPUTE" && c.source_reference == dispute_id && c.status == "OPEN");
+        for case in open {
+            let entry = audit::append_audit_entry("ETHICS_SUPERSEDED", &case.case_id, dispute_status.as_bytes());
+            case.status = "SUPERSEDED".to_string();
+            case.decided_at = Some(entry.timestamp);
+        }
+    });
+}
+
+// Quorum counts every cast vote; the decision considers only non-abstaining votes
+fn tally(votes: &[EthicsVote], rules: &QuorumRules) -> Option<String> {
+    if (votes.len() as u32) < rules.quorum {
+        return None;
+    }
+
+    let proceed = votes.iter().filter(|v| v.vote == "PROCEED").count();
+    let block = votes.iter().filter(|v| v.vote == "BLOCK").count();
+    let decisive = proceed + block;
+    if decisive == 0 {
+        return Some("BLOCK".to_string());
+    }
+
+    if proceed * 100 >= decisive * rules.proceed_threshold_percent as usize {
+        Some("PROCEED".to_string())
+    } else {
+        Some("BLOCK".to_string())
+    }
+}
+
+#[update]
+fn set_quorum_rules(rules: QuorumRules) -> Result<(), String> {
+    governance::authorize_direct_change(caller())?;
+    validate_quorum_rules(&rules)?;
+    install_quorum_rules(rules);
+    Ok(())
+}
+
+pub(crate) fn validate_quorum_rules(rules: &QuorumRules) -> Result<(), String> {
+    if rules.quorum == 0 || rules.quorum as usize > rules.members.len() {
+        return Err("Quorum must be between 1 and the number of committee members".to_string());
+    }
+    if rules.proceed_threshold_percent == 0 || rules.proceed_threshold_percent > 100 {
+        return Err("Proceed threshold must be between 1 and 100 percent".to_string());
+    }
+    Ok(())
+}
+
+pub(crate) fn install_quorum_rules(rules: QuorumRules) {
+    QUORUM_RULES.with(|r| *r.borrow_mut() = rules);
+}
+
+#[query]
+fn get_quorum_rules() -> QuorumRules {
+    QUORUM_RULES.with(|r| r.borrow().clone())
+}
+
+// Votes carry members' rationales about a named patient; only the committee and controllers read them
+#[query]
+fn get_ethics_cases(patient_id: String) -> Result<Vec<EthicsCase>, String> {
+    let reader = caller();
+    if !ic_cdk::api::is_controller(&reader) && !is_committee_member(&reader) {
+        return Err("Only controllers or committee members may read ethics cases".to_string());
+    }
+    Ok(cases_for(&patient_id))
+}
+
+pub(crate) fn cases_for(patient_id: &str) -> Vec<EthicsCase> {
+    ETHICS_CASES.with(|cases| {
+        cases.borrow()
+            .values()
+            .filter(|c| c.patient_id == patient_id)
+            .cloned()
+            .collect()
+    })
+}
+
+// Execution gate: open cases hold execution, BLOCK decisions stop it
+pub(crate) fn blocking_case(patient_id: &str) -> Option<EthicsCase> {
+    ETHICS_CASES.with(|cases| {
+        cases.borrow()
+            .values()
+            .find(|c| {
+                c.patient_id == patient_id
+                    && (c.status == "OPEN" || c.decision.as_deref() == Some("BLOCK"))
+                    && c.source != "DISPUTE"
+            })
+            .cloned()
+    })
+}
+
+pub(crate) fn is_committee_member(principal: &Principal) -> bool {
+    QUORUM_RULES.with(|r| r.borrow().members.contains(principal))
+}
+
+// Carried across upgrades with the event log; open cases keep blocking their executions
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct EthicsState {
+    cases: BTreeMap<String, EthicsCase>,
+    quorum_rules: QuorumRules,
+}
+
+pub(crate) fn snapshot() -> EthicsState {
+    EthicsState {
+        cases: ETHICS_CASES.with(|e| e.borrow().clone()),
+        quorum_rules: QUORUM_RULES.with(|q| q.borrow().clone()),
+    }
+}
+
+pub(crate) fn restore(state: Option<EthicsState>) {
+    let Some(state) = state else {
+        return;
+    };
+    ETHICS_CASES.with(|e| *e.borrow_mut() = state.cases);
+    QUORUM_RULES.with(|q| *q.borrow_mut() = state.quorum_rules);
+}
@@ -0,0 +1,301 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query, pre_upgrade, post_upgrade};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::consent_cascade::{self, ConsentRetraction};
+use crate::{
+    audit, capacity, center_keys, clock, custody, dcd, disputes, ethics, governance, history, patient_locks, plugins, reconciliation, traps,
+    failed_step, ContactAcknowledgment, DirectiveExecution, ExecutionResult, EXECUTION_HISTORY,
+};
+
+// Every change to execution state; EXECUTION_HISTORY is derived from these
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum ExecutionEventKind {
+    ExecutionStarted { execution_id: String, patient_id: String },
+    ExecutionStepCompleted { execution_id: String, step: DirectiveExecution },
+    ExecutionStepFailed { execution_id: String, step: String, error: String },
+    ExecutionCompleted(ExecutionResult),
+    // execution_id is None for an attempt refused before it had one, and in events recorded before it was carried
+    ExecutionFailed { patient_id: String, execution_id: Option<String>, error: String },
+    ContactAcknowledged { execution_id: String, acknowledgment: ContactAcknowledgment },
+    ConsentRetracted { execution_id: String, retraction: ConsentRetraction },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ExecutionEvent {
+    pub sequence: u64,
+    pub recorded_at: u64,
+    pub kind: ExecutionEventKind,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ExecutionEventPage {
+    pub events: Vec<ExecutionEvent>,
+    pub next_cursor: u64,
+    pub head: u64,
+    pub first_sequence: u64, // Older events have been rolled up and can no longer be read
+}
+
+thread_local! {
+    static EXECUTION_EVENTS: RefCell<Vec<ExecutionEvent>> = RefCell::new(Vec::new());
+    // Sequence of EXECUTION_EVENTS[0]; sequences stay stable as the front of the log is compacted
+    static FIRST_SEQUENCE: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+    // Executions started and not yet ended, built up from their step events; derived like EXECUTION_HISTORY
+    static IN_FLIGHT: RefCell<BTreeMap<String, ExecutionResult>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+const MAX_EVENT_PAGE: u32 = 500;
+
+// Append and apply; the sequence number doubles as the consumer cursor
+pub(crate) fn record(kind: ExecutionEventKind) -> u64 {
+    let event = EXECUTION_EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let event = ExecutionEvent {
+            sequence: FIRST_SEQUENCE.with(|f| f.get()) + events.len() as u64,
+            recorded_at: clock::now(),
+            kind,
+        };
+        events.push(event.clone());
+        event
+    });
+    apply(&event);
+    event.sequence
+}
+
+#[query]
+fn get_execution_events(cursor: u64, limit: u32) -> Result<ExecutionEventPage, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may read the execution event stream".to_string());
+    }
+    let first_sequence = FIRST_SEQUENCE.with(|f| f.get());
+    let cursor = cursor.max(first_sequence);
+    EXECUTION_EVENTS.with(|events| {
+        let events = events.borrow();
+        let page: Vec<ExecutionEvent> = events
+            .iter()
+            .skip((cursor - first_sequence) as usize)
+            .take(limit.min(MAX_EVENT_PAGE) as usize)
+            .cloned()
+            .collect();
+        Ok(ExecutionEventPage {
+            next_cursor: cursor + page.len() as u64,
+            head: first_sequence + events.len() as u64,
+            first_sequence,
+            events: page,
+        })
+    })
+}
+
+#[update]
+fn rebuild_execution_state() -> Result<u64, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may rebuild execution state".to_string());
+    }
+    Ok(replay())
+}
+
+// Executions whose completion was recorded in [from, to), with when; compacted ones are gone
+pub(crate) fn completed_between(from: u64, to: u64) -> Vec<(u64, String)> {
+    EXECUTION_EVENTS.with(|events| {
+        events.borrow()
+            .iter()
+            .filter(|e| e.recorded_at >= from && e.recorded_at < to)
+            .filter_map(|e| match &e.kind {
+                ExecutionEventKind::ExecutionCompleted(result) => Some((e.recorded_at, result.execution_id.clone())),
+                _ => None,
+            })
+            .collect()
+    })
+}
+
+// Takes events recorded before cutoff off the front of the log, at most max_events of them
+pub(crate) fn drain_before(cutoff: u64, max_events: usize) -> Vec<ExecutionEvent> {
+    EXECUTION_EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let count = events.iter().take(max_events).take_while(|e| e.recorded_at < cutoff).count();
+        FIRST_SEQUENCE.with(|f| f.set(f.get() + count as u64));
+        events.drain(..count).collect()
+    })
+}
+
+// What stable memory holds across an upgrade; each part is optional so older images still restore
+pub(crate) type StableState = (
+    Option<Vec<ExecutionEvent>>,
+    Option<u64>,
+    Option<history::HistoryState>,
+    Option<consent_cascade::ConsentCascadeState>,
+    Option<reconciliation::ReconciliationState>,
+    Option<traps::TrapMetrics>,
+    Option<ModuleStates>,
+);
+
+// State of modules no event records, as one record so a module can be added without reshaping the tuple
+#[derive(CandidType, Deserialize)]
+pub(crate) struct ModuleStates {
+    disputes: Option<disputes::DisputesState>,
+    ethics: Option<ethics::EthicsState>,
+    audit: Option<audit::AuditState>,
+    custody: Option<custody::CustodyState>,
+    dcd: Option<dcd::DcdState>,
+    center_keys: Option<center_keys::CenterKeysState>,
+    capacity: Option<capacity::CapacityState>,
+    governance: Option<governance::GovernanceState>,
+    plugins: Option<plugins::PluginsState>,
+    last_sequence: Option<u64>,
+}
+
+// The log and its roll-ups are carried across upgrades, with the state no event records - holds,
+// ethics cases, the audit chain, custody, DCD cases, center keys, capacity, governance and
+// plugins; derived state is replayed from the log
+#[pre_upgrade]
+fn pre_upgrade() {
+    ic_cdk::storage::stable_save(saved_state()).expect("Failed to save execution event log");
+}
+
+pub(crate) fn saved_state() -> StableState {
+    let events = EXECUTION_EVENTS.with(|events| events.borrow().clone());
+    let first_sequence = FIRST_SEQUENCE.with(|f| f.get());
+    (
+        Some(events),
+        Some(first_sequence),
+        Some(history::snapshot()),
+        Some(consent_cascade::snapshot()),
+        Some(reconciliation::snapshot()),
+        Some(traps::snapshot()),
+        Some(ModuleStates {
+            disputes: Some(disputes::snapshot()),
+            ethics: Some(ethics::snapshot()),
+            audit: Some(audit::snapshot()),
+            custody: Some(custody::snapshot()),
+            dcd: Some(dcd::snapshot()),
+            center_keys: Some(center_keys::snapshot()),
+            capacity: Some(capacity::snapshot()),
+            governance: Some(governance::snapshot()),
+            plugins: Some(plugins::snapshot()),
+            last_sequence: Some(clock::snapshot()),
+        }),
+    )
+}
+
+// Releases before the log was kept left stable memory empty and start with none. A saved image that
+// no longer decodes traps instead: starting over would silently wipe the log and everything above.
+#[post_upgrade]
+fn post_upgrade() {
+    let state: StableState = if ic_cdk::api::stable::stable_size() == 0 {
+        (None, None, None, None, None, None, None)
+    } else {
+        ic_cdk::storage::stable_restore().expect("Failed to restore execution event log")
+    };
+    restore_state(state);
+    history::ensure_compaction_timer();
+    reconciliation::ensure_reconciliation_timer();
+    disputes::ensure_escalation_timers();
+    plugins::ensure_pending_runs();
+}
+
+// Derived state is rebuilt by replaying the restored log
+pub(crate) fn restore_state(state: StableState) {
+    let (events, first_sequence, history_state, cascade_state, reconciliation_state, trap_metrics, module_states) = state;
+    patient_locks::release_all();
+    EXECUTION_EVENTS.with(|e| *e.borrow_mut() = events.unwrap_or_default());
+    FIRST_SEQUENCE.with(|f| f.set(first_sequence.unwrap_or(0)));
+    history::restore(history_state);
+    consent_cascade::restore(cascade_state);
+    reconciliation::restore(reconciliation_state);
+    traps::restore(trap_metrics);
+    if let Some(modules) = module_states {
+        disputes::restore(modules.disputes);
+        ethics::restore(modules.ethics);
+        audit::restore(modules.audit);
+        custody::restore(modules.custody);
+        dcd::restore(modules.dcd);
+        center_keys::restore(modules.center_keys);
+        capacity::restore(modules.capacity);
+        governance::restore(modules.governance);
+        plugins::restore(modules.plugins);
+        clock::restore(modules.last_sequence);
+    }
+    replay();
+}
+
+// The record of an execution that has started; steps are added as their events arrive
+fn opened(execution_id: &str, patient_id: &str) -> ExecutionResult {
+    ExecutionResult {
+        execution_id: execution_id.to_string(),
+        patient_id: patient_id.to_string(),
+        directives_executed: vec![],
+        total_execution_time_ms: 0,
+        blockchain_verification: String::new(),
+        audit_log_created: false,
+        compliance_verified: false,
+        contact_acknowledgments: vec![],
+        consent_states: vec![],
+        consent_retractions: vec![],
+    }
+}
+
+fn replay() -> u64 {
+    EXECUTION_HISTORY.with(|history| history.borrow_mut().clear());
+    IN_FLIGHT.with(|f| f.borrow_mut().clear());
+    let events = EXECUTION_EVENTS.with(|events| events.borrow().clone());
+    for event in &events {
+        apply(event);
+    }
+    events.len() as u64
+}
+
+fn apply(event: &ExecutionEvent) {
+    match &event.kind {
+        ExecutionEventKind::ExecutionStarted { execution_id, patient_id } => {
+            IN_FLIGHT.with(|f| f.borrow_mut().insert(execution_id.clone(), opened(execution_id, patient_id)));
+        }
+        ExecutionEventKind::ExecutionStepCompleted { execution_id, step } => {
+            IN_FLIGHT.with(|f| {
+                if let Some(record) = f.borrow_mut().get_mut(execution_id) {
+                    record.directives_executed.push(step.clone());
+                }
+            });
+        }
+        ExecutionEventKind::ExecutionStepFailed { execution_id, step, .. } => {
+            IN_FLIGHT.with(|f| {
+                if let Some(record) = f.borrow_mut().get_mut(execution_id) {
+                    record.directives_executed.push(failed_step(step));
+                }
+            });
+        }
+        ExecutionEventKind::ExecutionCompleted(result) => {
+            IN_FLIGHT.with(|f| f.borrow_mut().remove(&result.execution_id));
+            EXECUTION_HISTORY.with(|history| {
+                history.borrow_mut().insert(result.execution_id.clone(), result.clone());
+            });
+        }
+        // An execution that ended early keeps the steps it got through, the failed one marked FAILED
+        ExecutionEventKind::ExecutionFailed { execution_id: Some(execution_id), .. } => {
+            let Some(mut record) = IN_FLIGHT.with(|f| f.borrow_mut().remove(execution_id)) else {
+                return;
+            };
+            if !record.directives_executed.iter().any(|d| d.execution_status == "FAILED") {
+                record.directives_executed.push(failed_step("EXECUTION"));
+            }
+            EXECUTION_HISTORY.with(|history| history.borrow_mut().insert(execution_id.clone(), record));
+        }
+        ExecutionEventKind::ContactAcknowledged { execution_id, acknowledgment } => {
+            EXECUTION_HISTORY.with(|history| {
+                if let Some(record) = history.borrow_mut().get_mut(execution_id) {
+                    record.contact_acknowledgments.push(acknowledgment.clone());
+                }
+            });
+        }
+        ExecutionEventKind::ConsentRetracted { execution_id, retraction } => {
+            EXECUTION_HISTORY.with(|history| {
+                if let Some(record) = history.borrow_mut().get_mut(execution_id) {
+                    record.consent_retractions.push(retraction.clone());
+                }
+            });
+        }
+        ExecutionEventKind::ExecutionFailed { execution_id: None, .. } => {}
+    }
+}
@@ -0,0 +1,287 @@
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument,
+};
+use ic_cdk::{call, caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::audit::{self, AuditEntry};
+use crate::disputes::{self, Dispute};
+use crate::ethics::{self, EthicsCase};
+use crate::{clock, derive_patient_hash, ids, patient_locks, resilience, traps, ContactNotification, ExecutionResult, DIRECTIVE_MANAGER_CANISTER_ID, EXECUTION_HISTORY};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ExecutionAttempt {
+    pub attempt_id: String,
+    pub patient_id: String,
+    pub attempted_at: u64,
+    pub error: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ConsentDirective {
+    pub patient_id: String,
+    pub directive_type: String,
+    pub status: String,
+    pub consent_items: Vec<String>,
+    pub timestamp: u64,
+    pub signature: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProofStep {
+    pub sibling_hash: Vec<u8>,
+    pub sibling_on_left: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct InclusionProof {
+    pub patient_id: String,
+    pub version: u64,
+    pub leaf_index: u64,
+    pub leaf_hash: Vec<u8>,
+    pub appended_at: u64,
+    pub proof: Vec<ProofStep>,
+    pub root: Vec<u8>,
+    pub tree_size: u64,
+    pub certificate: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AttestationStatus {
+    pub attestations: u32,
+    pub required: u32,
+    pub certified: bool,
+    pub attested_by: Vec<Principal>,
+}
+
+// Everything a court or regulator needs to reconstruct why an execution happened
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EvidenceBundle {
+    pub reference_id: String,
+    pub patient_id: String,
+    pub generated_at: u64,
+    pub execution: Option<ExecutionResult>,
+    pub attempt: Option<ExecutionAttempt>,
+    pub directive_versions: Vec<ConsentDirective>,
+    pub inclusion_proofs: Vec<InclusionProof>,
+    pub incapacity_attestations: Option<AttestationStatus>,
+    pub disputes: Vec<Dispute>,
+    pub ethics_cases: Vec<EthicsCase>,
+    pub notification_receipts: Vec<ContactNotification>,
+    pub audit_entries: Vec<AuditEntry>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EvidencePackage {
+    pub package_id: String,
+    pub reference_id: String,
+    pub bundle_json: Vec<u8>,
+    pub bundle_hash: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub key_name: String,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub generated_at: u64,
+}
+
+thread_local! {
+    static EXECUTION_ATTEMPTS: RefCell<BTreeMap<String, ExecutionAttempt>> = RefCell::new(BTreeMap::new());
+    static EVIDENCE_PACKAGES: RefCell<BTreeMap<String, EvidencePackage>> = RefCell::new(BTreeMap::new());
+    // The evidence key's SEC1 public key, fetched once from the management canister
+    static EVIDENCE_PUBLIC_KEY: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+const EVIDENCE_KEY_NAME: &str = "key_1";
+const EVIDENCE_DERIVATION_PATH: &[u8] = b"evidence";
+// A reference exported again within this long gets the package already signed for it
+const EXPORT_REUSE_NANOS: u64 = 10 * 60 * 1_000_000_000;
+
+pub(crate) fn record_failed_attempt(patient_id: &str, error: &str) {
+    let now = clock::now();
+    let attempt = ExecutionAttempt {
+        attempt_id: ids::new_id("ATTEMPT"),
+        patient_id: patient_id.to_string(),
+        attempted_at: now,
+        error: error.to_string(),
+    };
+
+    if let Ok(payload) = serde_json::to_vec(&attempt) {
+        audit::append_audit_entry("EXECUTION_ATTEMPT_FAILED", &attempt.attempt_id, &payload);
+    }
+    EXECUTION_ATTEMPTS.with(|attempts| {
+        attempts.borrow_mut().insert(attempt.attempt_id.clone(), attempt);
+    });
+}
+
+// Bundle, hash and t-ECDSA sign the evidence for an execution or failed attempt. Exports are for
+// controllers and the ethics committee; each one costs a threshold signature, so a reference
+// exported again shortly after gets the package it already has.
+#[update]
+async fn export_evidence_package(reference_id: String) -> Result<EvidencePackage, String> {
+    let _watch = traps::Watch::start("EVIDENCE_EXPORT");
+    ensure_evidence_reader()?;
+    if let Some(recent) = latest_package(&reference_id).filter(|p| clock::now().saturating_sub(p.generated_at) < EXPORT_REUSE_NANOS) {
+        return Ok(recent);
+    }
+    let patient_id = EXECUTION_HISTORY.with(|h| h.borrow().get(&reference_id).map(|e| e.patient_id.clone()))
+        .or_else(|| EXECUTION_ATTEMPTS.with(|a| a.borrow().get(&reference_id).map(|a| a.patient_id.clone())))
+        .ok_or_else(|| format!("No execution or attempt found: {}", reference_id))?;
+    // A bundle taken while an execution for the same patient is between steps would capture half of it
+    let _lock = patient_locks::PatientGuard::acquire(&patient_id, "EVIDENCE_EXPORT")?;
+
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let patient_id_hash = derive_patient_hash(&patient_id).await?;
+
+    let (directive_versions,): (Vec<ConsentDirective>,) = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call(directive_manager_id, "get_directive_versions", (patient_id.clone(),))
+    }).await.map_err(|msg| format!("Failed to load directive versions: {}", msg))?;
+
+    let mut inclusion_proofs = Vec::new();
+    for version in 1..=directive_versions.len() as u64 {
+        let result: Result<(Result<InclusionProof, String>,), _> = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+            call(directive_manager_id, "get_inclusion_proof", (patient_id_hash.clone(), version))
+        }).await;
+        if let Ok((Ok(proof),)) = result {
+            inclusion_proofs.push(proof);
+        }
+    }
+
+    let incapacity_attestations = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call::<_, (AttestationStatus,)>(directive_manager_id, "get_incapacity_attestation_status", (patient_id_hash.clone(),))
+    }).await.ok().map(|(status,)| status);
+
+    let (notification_receipts,) = resilience::guarded_call_or(resilience::DIRECTIVE_MANAGER, || {
+        call::<_, (Vec<ContactNotification>,)>(directive_manager_id, "get_contact_notifications", (reference_id.clone(),))
+    }, (Vec::new(),)).await;
+
+    // The canister's own records are read together after the calls above, so acknowledgments,
+    // retractions and disputes that landed meanwhile are in the bundle alongside each other
+    let generated_at = clock::now();
+    let execution = EXECUTION_HISTORY.with(|h| h.borrow().get(&reference_id).cloned());
+    let attempt = EXECUTION_ATTEMPTS.with(|a| a.borrow().get(&reference_id).cloned());
+    let bundle = EvidenceBundle {
+        reference_id: reference_id.clone(),
+        patient_id: patient_id.clone(),
+        generated_at,
+        execution,
+        attempt,
+        directive_versions,
+        inclusion_proofs,
+        incapacity_attestations,
+        disputes: disputes::disputes_for(&patient_id),
+        ethics_cases: ethics::cases_for(&patient_id),
+        notification_receipts,
+        audit_entries: audit::entries_for(&reference_id),
+    };
+
+    let bundle_json = serde_json::to_vec(&bundle).map_err(|e| e.to_string())?;
+    let bundle_hash = ic_cdk::api::sha256(&bundle_json);
+    let derivation_path = vec![EVIDENCE_DERIVATION_PATH.to_vec()];
+
+    let (signed,) = sign_with_ecdsa(SignWithEcdsaArgument {
+        message_hash: bundle_hash.clone(),
+        derivation_path: derivation_path.clone(),
+        key_id: evidence_key_id(),
+    })
+    .await
+    .map_err(|(_, msg)| format!("Evidence signing failed: {}", msg))?;
+
+    let package = EvidencePackage {
+        package_id: ids::new_id("EVIDENCE"),
+        reference_id: reference_id.clone(),
+        bundle_json,
+        bundle_hash: bundle_hash.clone(),
+        signature: signed.signature,
+        key_name: EVIDENCE_KEY_NAME.to_string(),
+        derivation_path,
+        generated_at,
+    };
+
+    audit::append_audit_entry("EVIDENCE_EXPORTED", &reference_id, &bundle_hash);
+    EVIDENCE_PACKAGES.with(|packages| {
+        packages.borrow_mut().insert(package.package_id.clone(), package.clone());
+    });
+    // The package is already stored; a failed lookup leaves the key for load_evidence_public_key
+    if let Err(e) = evidence_public_key().await {
+        ic_cdk::println!("⚠️ {}", e);
+    }
+
+    Ok(package)
+}
+
+#[query]
+fn get_evidence_package(package_id: String) -> Result<Option<EvidencePackage>, String> {
+    ensure_evidence_reader()?;
+    Ok(EVIDENCE_PACKAGES.with(|packages| packages.borrow().get(&package_id).cloned()))
+}
+
+#[query]
+fn get_execution_attempts(patient_id: String) -> Result<Vec<ExecutionAttempt>, String> {
+    ensure_evidence_reader()?;
+    Ok(EXECUTION_ATTEMPTS.with(|attempts| {
+        attempts.borrow()
+            .values()
+            .filter(|a| a.patient_id == patient_id)
+            .cloned()
+            .collect()
+    }))
+}
+
+// Verifiers check package signatures against this key; it is public and needs no authorization
+#[query]
+fn get_evidence_public_key() -> Result<Vec<u8>, String> {
+    EVIDENCE_PUBLIC_KEY.with(|k| k.borrow().clone())
+        .ok_or_else(|| "Evidence public key not loaded yet; a controller can load it with load_evidence_public_key".to_string())
+}
+
+// Fills the key the query above serves; export also loads it the first time it signs
+#[update]
+async fn load_evidence_public_key() -> Result<Vec<u8>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may load the evidence public key".to_string());
+    }
+    evidence_public_key().await
+}
+
+async fn evidence_public_key() -> Result<Vec<u8>, String> {
+    if let Some(public_key) = EVIDENCE_PUBLIC_KEY.with(|k| k.borrow().clone()) {
+        return Ok(public_key);
+    }
+    let (response,) = ecdsa_public_key(EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path: vec![EVIDENCE_DERIVATION_PATH.to_vec()],
+        key_id: evidence_key_id(),
+    })
+    .await
+    .map_err(|(_, msg)| format!("Evidence public key lookup failed: {}", msg))?;
+    EVIDENCE_PUBLIC_KEY.with(|k| *k.borrow_mut() = Some(response.public_key.clone()));
+    Ok(response.public_key)
+}
+
+fn evidence_key_id() -> EcdsaKeyId {
+    EcdsaKeyId { curve: EcdsaCurve::Secp256k1, name: EVIDENCE_KEY_NAME.to_string() }
+}
+
+// Evidence names the patient and everything done for them
+fn ensure_evidence_reader() -> Result<(), String> {
+    let requester = caller();
+    if ic_cdk::api::is_controller(&requester) || ethics::is_committee_member(&requester) {
+        Ok(())
+    } else {
+        Err("Only canister controllers and ethics committee members may read or export evidence".to_string())
+    }
+}
+
+fn latest_package(reference_id: &str) -> Option<EvidencePackage> {
+    EVIDENCE_PACKAGES.with(|packages| {
+        packages.borrow()
+            .values()
+            .filter(|p| p.reference_id == reference_id)
+            .max_by_key(|p| p.generated_at)
+            .cloned()
+    })
+}
@@ -0,0 +1,197 @@
+use ic_cdk::{caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::cell::RefCell;
+
+use crate::allocation::{self, AllocationProfile};
+use crate::ethics::{self, QuorumRules};
+use crate::history::{self, HistoryRetention};
+use crate::outcall_budget::{self, OutcallBudget};
+use crate::{audit, clock};
+
+// Consortium deployments hand parameter changes to a governance canister (an SNS root's governance
+// or a custom one). The pair below follows the SNS generic-function shape: the validator renders
+// the proposal payload for voters or rejects it, and the execution callback applies it once the
+// proposal is adopted. In exclusive mode the direct controller setters stop working.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct GovernanceConfig {
+    pub governance_canister: Principal,
+    pub exclusive: bool,
+    pub configured_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum ParameterChange {
+    AllocationProfile(AllocationProfile),
+    QuorumRules(QuorumRules),
+    OutcallBudget(OutcallBudget),
+    HistoryRetention(HistoryRetention),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct GovernanceExecution {
+    pub governance_canister: Principal,
+    pub summary: String,
+    pub executed_at: u64,
+}
+
+thread_local! {
+    static GOVERNANCE: RefCell<Option<GovernanceConfig>> = const { RefCell::new(None) };
+    static EXECUTIONS: RefCell<Vec<GovernanceExecution>> = const { RefCell::new(Vec::new()) };
+}
+
+const MAX_EXECUTIONS: usize = 500;
+
+// Controllers hand over to governance; once exclusive, only the governance canister can hand back
+#[update]
+fn set_governance_canister(governance_canister: Option<Principal>, exclusive: bool) -> Result<(), String> {
+    let requester = caller();
+    let current = GOVERNANCE.with(|g| g.borrow().clone());
+    let permitted = match &current {
+        Some(config) if config.exclusive => requester == config.governance_canister,
+        Some(config) => requester == config.governance_canister || ic_cdk::api::is_controller(&requester),
+        None => ic_cdk::api::is_controller(&requester),
+    };
+    if !permitted {
+        return Err("Only controllers, or the governance canister once it is exclusive, may change governance".to_string());
+    }
+    if governance_canister == Some(Principal::anonymous()) {
+        return Err("The anonymous principal cannot govern parameters".to_string());
+    }
+
+    let config = governance_canister.map(|governance_canister| GovernanceConfig {
+        governance_canister,
+        exclusive,
+        configured_at: clock::now(),
+    });
+    let summary = match &config {
+        Some(c) => format!("{} (exclusive: {})", c.governance_canister, c.exclusive),
+        None => "none".to_string(),
+    };
+    audit::append_audit_entry("GOVERNANCE_CONFIGURED", &requester.to_text(), summary.as_bytes());
+    GOVERNANCE.with(|g| *g.borrow_mut() = config);
+    Ok(())
+}
+
+#[query]
+fn get_governance_config() -> Option<GovernanceConfig> {
+    GOVERNANCE.with(|g| g.borrow().clone())
+}
+
+// Validator: Ok carries the rendering shown to voters, Err rejects the proposal before voting
+#[query]
+fn validate_parameter_change(change: ParameterChange) -> Result<String, String> {
+    validate(&change)?;
+    Ok(describe(&change))
+}
+
+// Execution callback, run when a proposal passes; validation is repeated since state may have moved
+#[update]
+fn execute_parameter_change(change: ParameterChange) -> Result<(), String> {
+    let governance_canister = GOVERNANCE.with(|g| g.borrow().as_ref().map(|c| c.governance_canister))
+        .ok_or("No governance canister is configured")?;
+    if caller() != governance_canister {
+        return Err("Parameter changes are executed by the governance canister only".to_string());
+    }
+    validate(&change)?;
+
+    let summary = describe(&change);
+    match change {
+        ParameterChange::AllocationProfile(profile) => allocation::install_profile(profile),
+        ParameterChange::QuorumRules(rules) => ethics::install_quorum_rules(rules),
+        ParameterChange::OutcallBudget(budget) => outcall_budget::install_budget(budget),
+        ParameterChange::HistoryRetention(retention) => history::install_retention(retention),
+    }
+    audit::append_audit_entry("GOVERNANCE_PARAMETER_CHANGE", &governance_canister.to_text(), summary.as_bytes());
+    EXECUTIONS.with(|e| {
+        let mut executions = e.borrow_mut();
+        if executions.len() >= MAX_EXECUTIONS {
+            executions.remove(0);
+        }
+        executions.push(GovernanceExecution { governance_canister, summary, executed_at: clock::now() });
+    });
+    Ok(())
+}
+
+#[query]
+fn get_governance_executions() -> Vec<GovernanceExecution> {
+    EXECUTIONS.with(|e| e.borrow().clone())
+}
+
+// Direct setters stay with controllers until an exclusive governance canister takes over
+pub(crate) fn authorize_direct_change(requester: Principal) -> Result<(), String> {
+    if let Some(config) = GOVERNANCE.with(|g| g.borrow().clone()).filter(|c| c.exclusive) {
+        return Err(format!(
+            "Parameters are governed by {}; submit a proposal instead",
+            config.governance_canister
+        ));
+    }
+    if !ic_cdk::api::is_controller(&requester) {
+        return Err("Only canister controllers may change this parameter".to_string());
+    }
+    Ok(())
+}
+
+fn validate(change: &ParameterChange) -> Result<(), String> {
+    match change {
+        ParameterChange::AllocationProfile(profile) => allocation::validate_profile(profile),
+        ParameterChange::QuorumRules(rules) => ethics::validate_quorum_rules(rules),
+        ParameterChange::OutcallBudget(budget) => outcall_budget::validate_budget(budget),
+        ParameterChange::HistoryRetention(retention) => history::validate_retention(retention),
+    }
+}
+
+fn describe(change: &ParameterChange) -> String {
+    match change {
+        ParameterChange::AllocationProfile(p) => format!(
+            "Set allocation profile {} ({}): compatibility {}, urgency {}, survival benefit {}, distance penalty {}/100km, max distance {}",
+            p.profile_id,
+            p.name,
+            p.compatibility_weight,
+            p.urgency_weight,
+            p.survival_benefit_weight,
+            p.distance_penalty_per_100km,
+            p.max_distance_km.map_or("none".to_string(), |km| format!("{} km", km))
+        ),
+        ParameterChange::QuorumRules(r) => format!(
+            "Set ethics quorum to {} of {} members, proceed threshold {}%",
+            r.quorum,
+            r.members.len(),
+            r.proceed_threshold_percent
+        ),
+        ParameterChange::OutcallBudget(b) => format!(
+            "Set outcall budget to {} calls/minute, {} cycles/day, {}% emergency reserve",
+            b.max_calls_per_minute,
+            b.max_cycles_per_day,
+            b.emergency_reserve_percent
+        ),
+        ParameterChange::HistoryRetention(r) => format!(
+            "Keep full execution records for {} days, then roll them up",
+            r.full_record_days
+        ),
+    }
+}
+
+// Carried across upgrades with the event log
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct GovernanceState {
+    config: Option<GovernanceConfig>,
+    executions: Vec<GovernanceExecution>,
+}
+
+pub(crate) fn snapshot() -> GovernanceState {
+    GovernanceState {
+        config: GOVERNANCE.with(|g| g.borrow().clone()),
+        executions: EXECUTIONS.with(|e| e.borrow().clone()),
+    }
+}
+
+pub(crate) fn restore(state: Option<GovernanceState>) {
+    let Some(state) = state else {
+        return;
+    };
+    GOVERNANCE.with(|g| *g.borrow_mut() = state.config);
+    EXECUTIONS.with(|e| *e.borrow_mut() = state.executions);
+}
@@ -0,0 +1,197 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::events::{self, ExecutionEvent, ExecutionEventKind};
+use crate::{clock, governance, ExecutionResult, EXECUTION_HISTORY};
+
+// Full execution records stay for full_record_days. After that the hourly compaction drops their
+// events from the log and folds them into one roll-up per day, so reporting keeps its counts,
+// outcomes and durations while the hot canister only holds recent executions.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct HistoryRetention {
+    pub full_record_days: u32,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ExecutionRollup {
+    pub day_start: u64,
+    pub executions_completed: u64,
+    pub executions_failed: u64,
+    pub directive_outcomes: Vec<(String, u64)>, // (execution_status, count)
+    pub total_execution_time_ms: u64,
+    pub max_execution_time_ms: u64,
+    pub recipients_notified: u64,
+    pub contact_acknowledgments: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ExecutionHistoryPage {
+    pub records: Vec<ExecutionResult>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ExecutionRollupPage {
+    pub rollups: Vec<ExecutionRollup>,
+    pub next_cursor: Option<u64>,
+}
+
+// Carried across upgrades with the event log
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct HistoryState {
+    retention: HistoryRetention,
+    rollups: Vec<ExecutionRollup>,
+}
+
+thread_local! {
+    static RETENTION: RefCell<HistoryRetention> = const { RefCell::new(HistoryRetention { full_record_days: DEFAULT_FULL_RECORD_DAYS }) };
+    static ROLLUPS: RefCell<BTreeMap<u64, ExecutionRollup>> = const { RefCell::new(BTreeMap::new()) };
+    static COMPACTION_TIMER_STARTED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+const DEFAULT_FULL_RECORD_DAYS: u32 = 30;
+const MAX_FULL_RECORD_DAYS: u32 = 3650;
+const MAX_HISTORY_PAGE: u32 = 100;
+const MAX_ROLLUP_PAGE: u32 = 366;
+const MAX_EVENTS_PER_COMPACTION: usize = 10_000;
+const COMPACTION_INTERVAL_SECONDS: u64 = 60 * 60;
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+#[update]
+fn set_history_retention(retention: HistoryRetention) -> Result<(), String> {
+    governance::authorize_direct_change(caller())?;
+    validate_retention(&retention)?;
+    install_retention(retention);
+    Ok(())
+}
+
+#[query]
+fn get_history_retention() -> HistoryRetention {
+    RETENTION.with(|r| r.borrow().clone())
+}
+
+// Pages run in execution_id order; pass next_cursor back to continue
+#[query]
+fn get_execution_history(cursor: Option<String>, limit: u32) -> ExecutionHistoryPage {
+    EXECUTION_HISTORY.with(|history| {
+        let history = history.borrow();
+        let limit = limit.clamp(1, MAX_HISTORY_PAGE) as usize;
+        let mut remaining = match &cursor {
+            Some(after) => history.range::<String, _>((std::ops::Bound::Excluded(after), std::ops::Bound::Unbounded)),
+            None => history.range::<String, _>(..),
+        };
+        let records: Vec<ExecutionResult> = remaining.by_ref().take(limit).map(|(_, r)| r.clone()).collect();
+        let next_cursor = remaining.next().and(records.last().map(|r| r.execution_id.clone()));
+        ExecutionHistoryPage { records, next_cursor }
+    })
+}
+
+// Oldest day first, starting at from_day_start
+#[query]
+fn get_execution_rollups(from_day_start: Option<u64>, limit: u32) -> ExecutionRollupPage {
+    ROLLUPS.with(|rollups| {
+        let rollups = rollups.borrow();
+        let limit = limit.clamp(1, MAX_ROLLUP_PAGE) as usize;
+        let mut remaining = rollups.range(from_day_start.unwrap_or(0)..);
+        let page: Vec<ExecutionRollup> = remaining.by_ref().take(limit).map(|(_, r)| r.clone()).collect();
+        let next_cursor = remaining.next().map(|(day_start, _)| *day_start);
+        ExecutionRollupPage { rollups: page, next_cursor }
+    })
+}
+
+#[update]
+fn compact_execution_history() -> Result<u64, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may compact execution history".to_string());
+    }
+    Ok(compact())
+}
+
+pub(crate) fn validate_retention(retention: &HistoryRetention) -> Result<(), String> {
+    if retention.full_record_days == 0 || retention.full_record_days > MAX_FULL_RECORD_DAYS {
+        return Err(format!("full_record_days must be between 1 and {}", MAX_FULL_RECORD_DAYS));
+    }
+    Ok(())
+}
+
+pub(crate) fn install_retention(retention: HistoryRetention) {
+    RETENTION.with(|r| *r.borrow_mut() = retention);
+}
+
+pub(crate) fn ensure_compaction_timer() {
+    if COMPACTION_TIMER_STARTED.with(|s| s.replace(true)) {
+        return;
+    }
+    ic_cdk_timers::set_timer_interval(Duration::from_secs(COMPACTION_INTERVAL_SECONDS), || {
+        compact();
+    });
+}
+
+pub(crate) fn snapshot() -> HistoryState {
+    HistoryState {
+        retention: RETENTION.with(|r| r.borrow().clone()),
+        rollups: ROLLUPS.with(|r| r.borrow().values().cloned().collect()),
+    }
+}
+
+pub(crate) fn restore(state: Option<HistoryState>) {
+    let Some(state) = state else {
+        return;
+    };
+    RETENTION.with(|r| *r.borrow_mut() = state.retention);
+    ROLLUPS.with(|r| *r.borrow_mut() = state.rollups.into_iter().map(|rollup| (rollup.day_start, rollup)).collect());
+}
+
+// Returns the number of events folded away
+fn compact() -> u64 {
+    let full_record_days = RETENTION.with(|r| r.borrow().full_record_days) as u64;
+    let cutoff = clock::now().saturating_sub(full_record_days * NANOS_PER_DAY);
+    let drained = events::drain_before(cutoff, MAX_EVENTS_PER_COMPACTION);
+    for event in &drained {
+        roll_up(event);
+    }
+    if !drained.is_empty() {
+        ic_cdk::println!("AUDIT: Rolled up {} execution events older than {} days", drained.len(), full_record_days);
+    }
+    drained.len() as u64
+}
+
+fn roll_up(event: &ExecutionEvent) {
+    let day_start = event.recorded_at - event.recorded_at % NANOS_PER_DAY;
+    ROLLUPS.with(|rollups| {
+        let mut rollups = rollups.borrow_mut();
+        let rollup = rollups.entry(day_start).or_insert_with(|| ExecutionRollup { day_start, ..Default::default() });
+        match &event.kind {
+            ExecutionEventKind::ExecutionCompleted(result) => {
+                rollup.executions_completed += 1;
+                rollup.total_execution_time_ms += result.total_execution_time_ms;
+                rollup.max_execution_time_ms = rollup.max_execution_time_ms.max(result.total_execution_time_ms);
+                for directive in &result.directives_executed {
+                    rollup.recipients_notified += directive.total_recipients_notified as u64;
+                    match rollup.directive_outcomes.iter_mut().find(|(status, _)| *status == directive.execution_status) {
+                        Some((_, count)) => *count += 1,
+                        None => rollup.directive_outcomes.push((directive.execution_status.clone(), 1)),
+                    }
+                }
+                EXECUTION_HISTORY.with(|history| history.borrow_mut().remove(&result.execution_id));
+            }
+            ExecutionEventKind::ExecutionFailed { execution_id, .. } => {
+                rollup.executions_failed += 1;
+                if let Some(execution_id) = execution_id {
+                    EXECUTION_HISTORY.with(|history| history.borrow_mut().remove(execution_id));
+                }
+            }
+            ExecutionEventKind::ContactAcknowledged { .. } => rollup.contact_acknowledgments += 1,
+            ExecutionEventKind::ExecutionStarted { .. }
+            | ExecutionEventKind::ExecutionStepCompleted { .. }
+            | ExecutionEventKind::ExecutionStepFailed { .. }
+            | ExecutionEventKind::ConsentRetracted { .. } => {}
+        }
+    });
+}
@@ -0,0 +1,461 @@
+use ic_cdk::{caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::cell::RefCell;
+
+use crate::custody::{self, TelemetryReading};
+use crate::{calendar, clock, validation, viability};
+
+// The canister half of an MLLP bridge. Integration engines that can only speak HL7 v2 over
+// MLLP connect to a small bridge process on the hospital network; the bridge strips the MLLP
+// framing, relays messages here in batches, and frames the ACKs it gets back. ORU^R01
+// observations from organ transport devices are applied to the matching custody record.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Hl7Bridge {
+    pub bridge_id: String,
+    pub principal: Principal,
+    pub sending_facilities: Vec<String>, // MSH-4 values this bridge may relay for
+    pub registered_by: Principal,
+    pub registered_at: u64,
+}
+
+// What a bridge implementation needs to know to talk to this canister
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct MllpBridgeSpec {
+    pub hl7_version: String,
+    pub relay_method: String,
+    pub start_block: u8,
+    pub end_block: u8,
+    pub trailer: u8,
+    pub segment_separator: String,
+    pub supported_messages: Vec<String>,
+    pub observation_codes: Vec<String>,
+    pub max_batch_messages: u32,
+    pub max_message_bytes: u32,
+    pub ack_mode: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Hl7Ack {
+    pub control_id: String, // MSH-10 of the acknowledged message; empty if it could not be read
+    pub ack_code: String, // "AA", "AE", "AR"
+    pub error_code: Option<u16>, // HL7 table 0357
+    pub error_text: Option<String>,
+    pub duplicate: bool,
+    pub message: String, // the ACK itself, segments separated by \r, without MLLP framing
+}
+
+// One row per relayed message. Only a hash of the payload is kept; the message carries PHI.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Hl7IntakeEntry {
+    pub intake_id: u64,
+    pub bridge_id: String,
+    pub control_id: String,
+    pub sending_application: String,
+    pub sending_facility: String,
+    pub message_type: String,
+    pub processing_id: String,
+    pub ack_code: String,
+    pub error_code: Option<u16>,
+    pub error_text: Option<String>,
+    pub duplicate: bool,
+    pub payload_sha256: Vec<u8>,
+    pub received_at: u64,
+}
+
+// HL7 table 0357 message error conditions
+const SEGMENT_SEQUENCE_ERROR: u16 = 100;
+const REQUIRED_FIELD_MISSING: u16 = 101;
+const DATA_TYPE_ERROR: u16 = 102;
+const TABLE_VALUE_NOT_FOUND: u16 = 103;
+const UNSUPPORTED_MESSAGE_TYPE: u16 = 200;
+const UNSUPPORTED_EVENT_CODE: u16 = 201;
+const UNSUPPORTED_PROCESSING_ID: u16 = 202;
+const UNSUPPORTED_VERSION_ID: u16 = 203;
+const UNKNOWN_KEY_IDENTIFIER: u16 = 204;
+const DUPLICATE_KEY_IDENTIFIER: u16 = 205;
+const APPLICATION_INTERNAL_ERROR: u16 = 207;
+
+const OBSERVATION_CODES: [&str; 2] = ["TEMPERATURE", "PERFUSION_PRESSURE"];
+const MAX_BATCH_MESSAGES: usize = 50;
+const MAX_MESSAGE_BYTES: usize = 16 * 1024;
+const MAX_INTAKE_LOG: usize = 10_000;
+const MAX_LOG_PAGE: usize = 1_000;
+
+thread_local! {
+    static HL7_BRIDGES: RefCell<BTreeMap<String, Hl7Bridge>> = RefCell::new(BTreeMap::new());
+    static INTAKE_LOG: RefCell<VecDeque<Hl7IntakeEntry>> = RefCell::new(VecDeque::new());
+    static NEXT_INTAKE_ID: RefCell<u64> = const { RefCell::new(1) };
+    // Keyed by (sending facility, control id), so a bridge retrying after a lost ACK gets the
+    // same answer instead of applying the reading twice
+    static PROCESSED: RefCell<BTreeMap<(String, String), Processed>> = RefCell::new(BTreeMap::new());
+}
+
+#[update]
+fn register_hl7_bridge(bridge_id: String, principal: Principal, sending_facilities: Vec<String>) -> Result<Hl7Bridge, String> {
+    let registrar = caller();
+    if !viability::is_intake_hospital(&registrar) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    let bridge_id = validation::identifier("bridge_id", &bridge_id)?;
+    validation::collection("sending_facilities", sending_facilities.len(), 50)?;
+    let sending_facilities = sending_facilities.iter()
+        .map(|f| validation::identifier("sending_facilities", f))
+        .collect::<Result<Vec<_>, _>>()?;
+    if sending_facilities.is_empty() {
+        return Err(validation::invalid("sending_facilities", "must not be empty"));
+    }
+
+    let bridge = Hl7Bridge {
+        bridge_id: bridge_id.clone(),
+        principal,
+        sending_facilities,
+        registered_by: registrar,
+        registered_at: clock::now(),
+    };
+    HL7_BRIDGES.with(|b| {
+        let mut bridges = b.borrow_mut();
+        if bridges.values().any(|other| other.principal == principal && other.bridge_id != bridge_id) {
+            return Err("Principal is already registered to another bridge".to_string());
+        }
+        bridges.insert(bridge_id, bridge.clone());
+        Ok(())
+    })?;
+    Ok(bridge)
+}
+
+#[query]
+fn get_mllp_bridge_spec() -> MllpBridgeSpec {
+    MllpBridgeSpec {
+        hl7_version: "2.5".to_string(),
+        relay_method: "relay_hl7_batch".to_string(),
+        start_block: 0x0B,
+        end_block: 0x1C,
+        trailer: 0x0D,
+        segment_separator: "\r".to_string(),
+        supported_messages: vec!["ORU^R01".to_string()],
+        observation_codes: OBSERVATION_CODES.iter().map(|c| c.to_string()).collect(),
+        max_batch_messages: MAX_BATCH_MESSAGES as u32,
+        max_message_bytes: MAX_MESSAGE_BYTES as u32,
+        ack_mode: "ORIGINAL".to_string(),
+    }
+}
+
+// One ACK per message, in order. Messages are processed independently: a NAK for one
+// does not hold back the rest of the batch.
+#[update]
+fn relay_hl7_batch(messages: Vec<String>) -> Result<Vec<Hl7Ack>, String> {
+    let relay = caller();
+    let bridge = HL7_BRIDGES.with(|b| b.borrow().values().find(|x| x.principal == relay).cloned())
+        .ok_or("Caller is not a registered HL7 bridge")?;
+    validation::collection("messages", messages.len(), MAX_BATCH_MESSAGES)?;
+    for (i, message) in messages.iter().enumerate() {
+        if message.len() > MAX_MESSAGE_BYTES {
+            return Err(validation::invalid(&format!("messages[{}]", i), &format!("longer than {} bytes", MAX_MESSAGE_BYTES)));
+        }
+        if message.contains(['\u{0B}', '\u{1C}']) {
+            return Err(validation::invalid(&format!("messages[{}]", i), "still carries MLLP framing"));
+        }
+    }
+
+    Ok(messages.iter().map(|message| process_message(&bridge, message)).collect())
+}
+
+// Intake hospitals see every bridge; a bridge sees its own messages, for reconciliation
+#[query]
+fn get_hl7_intake_log(bridge_id: Option<String>, after_intake_id: Option<u64>, limit: u32) -> Result<Vec<Hl7IntakeEntry>, String> {
+    let requester = caller();
+    let bridge_id = if viability::is_intake_hospital(&requester) {
+        bridge_id
+    } else {
+        let own = HL7_BRIDGES.with(|b| b.borrow().values().find(|x| x.principal == requester).map(|x| x.bridge_id.clone()))
+            .ok_or("Caller may not read the HL7 intake log")?;
+        Some(own)
+    };
+    let after = after_intake_id.unwrap_or(0);
+    Ok(INTAKE_LOG.with(|log| {
+        log.borrow()
+            .iter()
+            .filter(|e| e.intake_id > after && bridge_id.as_ref().is_none_or(|b| &e.bridge_id == b))
+            .take((limit as usize).min(MAX_LOG_PAGE))
+            .cloned()
+            .collect()
+    }))
+}
+
+struct Processed {
+    payload_sha256: Vec<u8>,
+    ack: Hl7Ack,
+}
+
+struct Header<'a> {
+    sending_application: &'a str,
+    sending_facility: &'a str,
+    message_time: &'a str,
+    message_type: &'a str,
+    control_id: &'a str,
+    processing_id: &'a str,
+    version: &'a str,
+}
+
+// An outcome other than AA: the acknowledgment code, a table 0357 code, and why
+struct Nak(&'static str, u16, String);
+
+fn process_message(bridge: &Hl7Bridge, raw: &str) -> Hl7Ack {
+    let now = clock::now();
+    let intake_id = NEXT_INTAKE_ID.with(|n| {
+        let mut next = n.borrow_mut();
+        let id = *next;
+        *next += 1;
+        id
+    });
+    let payload_sha256 = ic_cdk::api::sha256(raw.as_bytes());
+    let segments: Vec<&str> = raw.split(['\r', '\n']).filter(|s| !s.trim().is_empty()).collect();
+    let header = segments.first().and_then(|msh| parse_header(msh));
+    let control_id = header.as_ref().map_or("", |h| h.control_id).to_string();
+    let sending_facility = header.as_ref().map_or("", |h| h.sending_facility).to_string();
+    let key = (sending_facility.clone(), control_id.clone());
+
+    let earlier = PROCESSED.with(|p| p.borrow().get(&key).map(|e| (e.payload_sha256.clone(), e.ack.clone())));
+    let (ack, duplicate) = match (&header, earlier) {
+        (Some(_), Some((hash, ack))) if !control_id.is_empty() && hash == payload_sha256 => (Hl7Ack { duplicate: true, ..ack }, true),
+        (Some(h), Some(_)) if !control_id.is_empty() => {
+            let nak = Nak("AR", DUPLICATE_KEY_IDENTIFIER, "Control id already used for a different message".to_string());
+            (build_ack(Some(h), intake_id, now, Err(nak)), false)
+        }
+        _ => {
+            let outcome = match &header {
+                Some(h) => apply(bridge, h, &segments),
+                None => Err(Nak("AR", SEGMENT_SEQUENCE_ERROR, "Message must start with a readable MSH segment".to_string())),
+            };
+            let ack = build_ack(header.as_ref(), intake_id, now, outcome);
+            // Rejected messages were never applied, so a corrected resend may reuse the control id
+            if ack.ack_code != "AR" && !control_id.is_empty() {
+                PROCESSED.with(|p| p.borrow_mut().insert(key.clone(), Processed { payload_sha256: payload_sha256.clone(), ack: ack.clone() }));
+            }
+            (ack, false)
+        }
+    };
+
+    let entry = Hl7IntakeEntry {
+        intake_id,
+        bridge_id: bridge.bridge_id.clone(),
+        control_id,
+        sending_application: header.as_ref().map_or("", |h| h.sending_application).to_string(),
+        sending_facility,
+        message_type: header.as_ref().map_or("", |h| h.message_type).to_string(),
+        processing_id: header.as_ref().map_or("", |h| h.processing_id).to_string(),
+        ack_code: ack.ack_code.clone(),
+        error_code: ack.error_code,
+        error_text: ack.error_text.clone(),
+        duplicate,
+        payload_sha256,
+        received_at: now,
+    };
+    INTAKE_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        if log.len() >= MAX_INTAKE_LOG {
+            if let Some(evicted) = log.pop_front() {
+                if !evicted.duplicate {
+                    PROCESSED.with(|p| p.borrow_mut().remove(&(evicted.sending_facility, evicted.control_id)));
+                }
+            }
+        }
+        log.push_back(entry);
+    });
+    ack
+}
+
+// MSH-1 is the field separator itself, so after splitting MSH-n sits at index n - 1
+fn parse_header(segment: &str) -> Option<Header<'_>> {
+    let separator = segment.strip_prefix("MSH")?.chars().next()?;
+    let fields: Vec<&str> = segment.split(separator).collect();
+    let msh = |n: usize| fields.get(n - 1).copied().unwrap_or("").trim();
+    Some(Header {
+        sending_application: msh(3),
+        sending_facility: msh(4),
+        message_time: msh(7),
+        message_type: msh(9),
+        control_id: msh(10),
+        processing_id: msh(11),
+        version: msh(12),
+    })
+}
+
+fn apply(bridge: &Hl7Bridge, header: &Header, segments: &[&str]) -> Result<(), Nak> {
+    if header.control_id.is_empty() {
+        return Err(Nak("AR", REQUIRED_FIELD_MISSING, "MSH-10 message control id is missing".to_string()));
+    }
+    if !bridge.sending_facilities.iter().any(|f| f == header.sending_facility) {
+        return Err(Nak("AR", UNKNOWN_KEY_IDENTIFIER, format!("Bridge does not relay for facility {}", header.sending_facility)));
+    }
+    if !header.version.starts_with("2.") {
+        return Err(Nak("AR", UNSUPPORTED_VERSION_ID, format!("Unsupported HL7 version {}", header.version)));
+    }
+    let processing = component(header.processing_id, 0);
+    if processing != "P" && processing != "T" {
+        return Err(Nak("AR", UNSUPPORTED_PROCESSING_ID, format!("Unsupported processing id {}", header.processing_id)));
+    }
+    if component(header.message_type, 0) != "ORU" {
+        return Err(Nak("AR", UNSUPPORTED_MESSAGE_TYPE, format!("Unsupported message type {}", header.message_type)));
+    }
+    if component(header.message_type, 1) != "R01" {
+        return Err(Nak("AR", UNSUPPORTED_EVENT_CODE, format!("Unsupported event {}", header.message_type)));
+    }
+
+    let (custody_id, reading) = reading_from_oru(header, segments)?;
+    // Training messages are checked end to end but not applied
+    if processing == "T" {
+        return Ok(());
+    }
+    custody::record_reading(&custody_id, reading)
+        .map(|_| ())
+        .map_err(|e| Nak("AE", APPLICATION_INTERNAL_ERROR, e))
+}
+
+// OBR-3 carries the custody id; one OBX per observation, coded in OBX-3 with a numeric OBX-5,
+// the observation time in OBX-14 (MSH-7 if absent) and the transport device in OBX-18
+fn reading_from_oru(header: &Header, segments: &[&str]) -> Result<(String, TelemetryReading), Nak> {
+    let missing = |what: &str| Nak("AE", REQUIRED_FIELD_MISSING, format!("{} is missing", what));
+    let fields = |segment: &'static str| {
+        segments.iter()
+            .filter(move |s| s.starts_with(segment) && s[3..].starts_with('|'))
+            .map(|s| s.split('|').collect::<Vec<_>>())
+    };
+    let field = |fields: &[&str], n: usize| fields.get(n).copied().unwrap_or("").trim().to_string();
+
+    let custody_id = fields("OBR")
+        .next()
+        .map(|obr| component(&field(&obr, 3), 0).to_string())
+        .filter(|id| !id.is_empty())
+        .ok_or_else(|| missing("OBR-3 custody id"))?;
+
+    let mut temperature = None;
+    let mut perfusion = None;
+    for obx in fields("OBX") {
+        let code = field(&obx, 3);
+        let code = component(&code, 0);
+        if field(&obx, 2) != "NM" {
+            return Err(Nak("AE", DATA_TYPE_ERROR, format!("OBX-2 for {} must be NM", code)));
+        }
+        let value: f32 = field(&obx, 5)
+            .parse()
+            .map_err(|_| Nak("AE", DATA_TYPE_ERROR, format!("OBX-5 for {} is not a number", code)))?;
+        let observed = (value, field(&obx, 14), component(&field(&obx, 18), 0).to_string());
+        match code {
+            "TEMPERATURE" => temperature = Some(observed),
+            "PERFUSION_PRESSURE" => perfusion = Some(observed),
+            other => return Err(Nak("AE", TABLE_VALUE_NOT_FOUND, format!("Unknown observation code {}", other))),
+        }
+    }
+
+    let (temperature_c, observed_at, device_id) = temperature.ok_or_else(|| missing("TEMPERATURE observation"))?;
+    if device_id.is_empty() {
+        return Err(missing("OBX-18 device id"));
+    }
+    let time = if observed_at.is_empty() { header.message_time } else { &observed_at };
+    let recorded_at = parse_dtm(time).ok_or_else(|| Nak("AE", DATA_TYPE_ERROR, format!("Unreadable timestamp {}", time)))?;
+
+    Ok((custody_id, TelemetryReading {
+        device_id,
+        recorded_at,
+        temperature_c,
+        perfusion_pressure_mmhg: perfusion.map(|(value, _, _)| value),
+        received_at: clock::now(),
+    }))
+}
+
+fn build_ack(header: Option<&Header>, intake_id: u64, now: u64, outcome: Result<(), Nak>) -> Hl7Ack {
+    let control_id = header.map_or("", |h| h.control_id).to_string();
+    let event = header.map_or("", |h| component(h.message_type, 1));
+    let (ack_code, error) = match outcome {
+        Ok(()) => ("AA", None),
+        Err(Nak(code, error_code, text)) => (code, Some((error_code, text))),
+    };
+
+    let mut message = format!(
+        "MSH|^~\\&|EXECUTOR_AI|ECHOLEDGER|{}|{}|{}||ACK^{}^ACK|ACK{}|{}|2.5\rMSA|{}|{}",
+        escape(header.map_or("", |h| h.sending_application)),
+        escape(header.map_or("", |h| h.sending_facility)),
+        format_dtm(now),
+        escape(event),
+        intake_id,
+        header.map(|h| component(h.processing_id, 0)).filter(|p| !p.is_empty()).unwrap_or("P"),
+        ack_code,
+        escape(&control_id),
+    );
+    if let Some((error_code, text)) = &error {
+        message.push_str(&format!("\rERR|||{}^{}^HL70357|E", error_code, escape(text)));
+    }
+
+    Hl7Ack {
+        control_id,
+        ack_code: ack_code.to_string(),
+        error_code: error.as_ref().map(|(code, _)| *code),
+        error_text: error.map(|(_, text)| text),
+        duplicate: false,
+        message,
+    }
+}
+
+fn component(field: &str, n: usize) -> &str {
+    field.split('^').nth(n).unwrap_or("").trim()
+}
+
+// Echoed values must not introduce delimiters into the ACK, nor field values into an outbound message
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\E\\"),
+            '|' => escaped.push_str("\\F\\"),
+            '^' => escaped.push_str("\\S\\"),
+            '~' => escaped.push_str("\\R\\"),
+            '&' => escaped.push_str("\\T\\"),
+            '\r' | '\n' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// HL7 DTM, YYYYMMDD[HH[MM[SS[.S...]]]][+/-ZZZZ], to nanoseconds since the epoch
+fn parse_dtm(value: &str) -> Option<u64> {
+    let (datetime, offset_minutes) = match value.find(['+', '-']) {
+        Some(i) => {
+            let (datetime, zone) = value.split_at(i);
+            let digits = &zone[1..];
+            if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let minutes = digits[..2].parse::<i64>().ok()? * 60 + digits[2..].parse::<i64>().ok()?;
+            (datetime, if zone.starts_with('-') { -minutes } else { minutes })
+        }
+        None => (value, 0),
+    };
+    let (whole, fraction) = datetime.split_once('.').unwrap_or((datetime, ""));
+    if whole.len() < 8 || whole.len() > 14 || whole.len() % 2 != 0 || !whole.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let padded = format!("{:0<14}", whole);
+    let part = |range: std::ops::Range<usize>| padded[range].parse::<i64>().ok();
+    let (year, month, day) = (part(0..4)?, part(4..6)?, part(6..8)?);
+    let (hour, minute, second) = (part(8..10)?, part(10..12)?, part(12..14)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let seconds = calendar::days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second - offset_minutes * 60;
+    let nanos = if fraction.is_empty() { 0 } else { format!("{:0<9}", fraction).parse::<u64>().ok()? };
+    u64::try_from(seconds).ok()?.checked_mul(1_000_000_000)?.checked_add(nanos)
+}
+
+pub(crate) fn format_dtm(nanos: u64) -> String {
+    let (year, month, day, hour, minute, second) = calendar::utc_parts(nanos);
+    format!("{:04}{:02}{:02}{:02}{:02}{:02}+0000", year, month, day, hour, minute, second)
+}
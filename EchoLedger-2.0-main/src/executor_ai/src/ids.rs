@@ -0,0 +1,27 @@
+use crate::clock;
+
+// Identifiers for the records this canister creates: a kind prefix and a ULID-style body of 26
+// Crockford base32 characters. The first ten encode the creation time in milliseconds, so ids of
+// one kind sort by age; the rest hold a tag for this canister and the clock's sequence number, so
+// two records made in the same round, or by two canisters, never share an id. Nothing about the
+// record's subject goes into an id: ids end up in URLs, logs and messages to partners.
+
+const CROCKFORD_BASE32: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const ENCODED_LEN: u32 = 26;
+const MASK_48_BITS: u64 = (1 << 48) - 1;
+
+pub(crate) fn new_id(kind: &str) -> String {
+    let millis = clock::now() / 1_000_000;
+    let value = u128::from(millis & MASK_48_BITS) << 80
+        | u128::from(canister_tag()) << 48
+        | u128::from(clock::next_sequence() & MASK_48_BITS);
+    let body: String = (0..ENCODED_LEN).rev()
+        .map(|i| CROCKFORD_BASE32[(value >> (5 * i)) as usize & 0x1F] as char)
+        .collect();
+    format!("{}_{}", kind, body)
+}
+
+fn canister_tag() -> u32 {
+    let digest = ic_cdk::api::sha256(ic_cdk::api::id().as_slice());
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+}
@@ -0,0 +1,148 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::viability::{self, DonorClinicalData};
+
+// Recipient inputs for the Estimated Post-Transplant Survival score
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CandidateProfile {
+    pub recipient_id: String,
+    pub age_years: u8,
+    pub diabetes: bool,
+    pub prior_solid_organ_transplant: bool,
+    pub dialysis_years: f32,
+    pub accepts_en_bloc: bool,
+}
+
+thread_local! {
+    static CANDIDATE_PROFILES: RefCell<BTreeMap<String, CandidateProfile>> = RefCell::new(BTreeMap::new());
+}
+
+// OPTN reference-year scaling factor: median KDRI_RAO of the prior year's recovered kidney donors
+const KDRI_SCALING_FACTOR: f64 = 1.404_368_170_650_05;
+
+// Approximate reference-population mappings (index value, percentile); refresh with each OPTN release
+const KDPI_MAPPING: [(f64, f64); 15] = [
+    (0.5, 1.0), (0.6, 5.0), (0.7, 12.0), (0.8, 22.0), (0.9, 33.0),
+    (1.0, 45.0), (1.1, 55.0), (1.2, 63.0), (1.3, 71.0), (1.4, 77.0),
+    (1.6, 86.0), (1.8, 92.0), (2.0, 95.0), (2.5, 99.0), (3.0, 100.0),
+];
+const EPTS_MAPPING: [(f64, f64); 10] = [
+    (0.0, 1.0), (0.5, 8.0), (1.0, 20.0), (1.5, 37.0), (2.0, 55.0),
+    (2.5, 72.0), (3.0, 85.0), (3.5, 94.0), (4.0, 98.0), (4.5, 100.0),
+];
+
+#[update]
+fn submit_candidate_profile(profile: CandidateProfile) -> Result<(), String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    if !profile.dialysis_years.is_finite() || profile.dialysis_years < 0.0 {
+        return Err("Dialysis time must be a non-negative number of years".to_string());
+    }
+
+    CANDIDATE_PROFILES.with(|p| {
+        p.borrow_mut().insert(profile.recipient_id.clone(), profile);
+    });
+    Ok(())
+}
+
+pub(crate) fn candidate_profile(recipient_id: &str) -> Option<CandidateProfile> {
+    CANDIDATE_PROFILES.with(|p| p.borrow().get(recipient_id).cloned())
+}
+
+// Candidate history is clinical data; only intake hospitals and controllers read it back
+#[query]
+fn get_candidate_profile(recipient_id: String) -> Result<Option<CandidateProfile>, String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    Ok(candidate_profile(&recipient_id))
+}
+
+// Kidney Donor Risk Index (OPTN refit without race), relative to the reference median
+pub(crate) fn kdri(donor: &DonorClinicalData) -> Option<f64> {
+    let age = donor.age_years as f64;
+    let height_cm = donor.height_cm? as f64;
+    let weight_kg = donor.weight_kg? as f64;
+    let creatinine = donor.labs.iter().find(|l| l.code == "CREATININE")?.value as f64;
+    let indicator = |b: bool| if b { 1.0 } else { 0.0 };
+    let hcv_positive = donor.infection_screens
+        .iter()
+        .any(|s| s.pathogen == "HCV" && s.result == "POSITIVE");
+
+    let mut xbeta = 0.0128 * (age - 40.0);
+    if age < 18.0 {
+        xbeta -= 0.0194 * (age - 18.0);
+    }
+    if age > 50.0 {
+        xbeta += 0.0107 * (age - 50.0);
+    }
+    xbeta -= 0.0464 * (height_cm - 170.0) / 10.0;
+    if weight_kg < 80.0 {
+        xbeta -= 0.0199 * (weight_kg - 80.0) / 5.0;
+    }
+    xbeta += 0.1260 * indicator(donor.history_hypertension);
+    xbeta += 0.1300 * indicator(donor.history_diabetes);
+    xbeta += 0.0881 * indicator(donor.cause_of_death == "CVA");
+    xbeta += 0.2200 * (creatinine - 1.0);
+    if creatinine > 1.5 {
+        xbeta -= 0.2090 * (creatinine - 1.5);
+    }
+    xbeta += 0.2400 * indicator(hcv_positive);
+    xbeta += 0.1330 * indicator(donor.donation_after_circulatory_death);
+
+    Some(xbeta.exp() / KDRI_SCALING_FACTOR)
+}
+
+pub(crate) fn kdpi(donor: &DonorClinicalData) -> Option<f32> {
+    kdri(donor).map(|k| percentile(&KDPI_MAPPING, k) as f32)
+}
+
+// Raw EPTS score as published by OPTN; lower means longer expected graft benefit
+fn raw_epts(candidate: &CandidateProfile) -> f64 {
+    let age_over_25 = (candidate.age_years as f64 - 25.0).max(0.0);
+    let diabetes = if candidate.diabetes { 1.0 } else { 0.0 };
+    let prior = if candidate.prior_solid_organ_transplant { 1.0 } else { 0.0 };
+    let dialysis = candidate.dialysis_years as f64;
+    let no_dialysis = if dialysis == 0.0 { 1.0 } else { 0.0 };
+    let log_dialysis = (dialysis + 1.0).ln();
+
+    0.047 * age_over_25
+        - 0.015 * diabetes * age_over_25
+        + 0.398 * prior
+        - 0.237 * diabetes * prior
+        + 0.315 * log_dialysis
+        - 0.099 * diabetes * log_dialysis
+        + 0.130 * no_dialysis
+        - 0.348 * diabetes * no_dialysis
+        + 1.262 * diabetes
+}
+
+pub(crate) fn epts(recipient_id: &str) -> Option<f32> {
+    CANDIDATE_PROFILES.with(|p| {
+        p.borrow()
+            .get(recipient_id)
+            .map(|c| percentile(&EPTS_MAPPING, raw_epts(c)) as f32)
+    })
+}
+
+// Linear interpolation over a monotonic (value, percentile) table
+fn percentile(mapping: &[(f64, f64)], value: f64) -> f64 {
+    let (first_value, first_pct) = mapping[0];
+    if value <= first_value {
+        return first_pct;
+    }
+    for window in mapping.windows(2) {
+        let (lo, lo_pct) = window[0];
+        let (hi, hi_pct) = window[1];
+        if value <= hi {
+            return lo_pct + (value - lo) / (hi - lo) * (hi_pct - lo_pct);
+        }
+    }
+    100.0
+}
@@ -2,9 +2,51 @@ use ic_cdk::{call, caller, Principal};
 use ic_cdk_macros::{update, query, init};
 use candid::{CandidType, Deserialize};
 use serde::Serialize;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
 use std::cell::RefCell;
 
+mod allocation;
+mod audit;
+mod calendar;
+#[cfg(feature = "canbench-rs")]
+mod benches;
+mod body_donation;
+mod capacity;
+mod center_keys;
+mod clock;
+mod consent_cascade;
+mod crossmatch;
+mod custody;
+mod dcd;
+mod digital_legacy;
+mod disposition;
+mod disputes;
+mod ethics;
+mod events;
+mod evidence;
+mod governance;
+mod history;
+mod hl7_intake;
+mod ids;
+mod kidney_indices;
+mod multi_organ;
+mod networks;
+mod outcall_budget;
+mod paired_exchange;
+mod patient_locks;
+mod plugins;
+mod protobuf;
+mod reconciliation;
+mod resilience;
+mod tissue;
+mod traps;
+mod validation;
+mod viability;
+mod x12;
+
+const DIRECTIVE_MANAGER_CANISTER_ID: &str = "rdmx6-jaaaa-aaaah-qdrva-cai";
+const LLM_CANISTER_ID: &str = "renrk-eyaaa-aaaaa-aaada-cai";
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct OrganAvailability {
     pub organ_type: String,
@@ -14,6 +56,7 @@ pub struct OrganAvailability {
     pub time_since_harvest: u64,
     pub location: String,
     pub viability_score: f32,
+    pub kdpi: Option<f32>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -26,6 +69,10 @@ pub struct RecipientMatch {
     pub transplant_center: String,
     pub notification_sent: bool,
     pub estimated_survival_benefit: f32,
+    pub allocation_profile: String,
+    pub allocation_score: f32,
+    pub kdpi: Option<f32>,
+    pub epts: Option<f32>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -37,6 +84,39 @@ pub struct ExecutionResult {
     pub blockchain_verification: String,
     pub audit_log_created: bool,
     pub compliance_verified: bool,
+    pub contact_acknowledgments: Vec<ContactAcknowledgment>,
+    pub consent_states: Vec<consent_cascade::StepConsentState>,
+    pub consent_retractions: Vec<consent_cascade::ConsentRetraction>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ContactAcknowledgment {
+    pub contact_id: String,
+    pub notification_id: String,
+    pub acknowledged_at: u64,
+    pub note: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ContactEvent {
+    pub event_type: String,
+    pub reference_id: String,
+    pub summary: String,
+    pub details: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ContactNotification {
+    pub notification_id: String,
+    pub contact_id: String,
+    pub event_type: String,
+    pub reference_id: String,
+    pub patient_reference: Option<String>, // None for operator alerts
+    pub channel: String,
+    pub message: String,
+    pub sent_at: u64,
+    pub acknowledged_at: Option<u64>,
+    pub acknowledgment_note: Option<String>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -92,25 +172,6 @@ pub struct DirectiveUpdate {
 
 thread_local! {
     static EXECUTION_HISTORY: RefCell<BTreeMap<String, ExecutionResult>> = RefCell::new(BTreeMap::new());
-    static ORGAN_NETWORKS: RefCell<HashMap<String, Vec<String>>> = RefCell::new({
-        let mut networks = HashMap::new();
-        networks.insert("UNOS".to_string(), vec![
-            "Mayo Clinic Transplant Center".to_string(),
-            "Johns Hopkins Transplant Center".to_string(),
-            "Cleveland Clinic".to_string(),
-            "UCLA Medical Center".to_string(),
-        ]);
-        networks.insert("Eurotransplant".to_string(), vec![
-            "Charité Berlin".to_string(),
-            "University Hospital Zurich".to_string(),
-            "Academic Medical Center Amsterdam".to_string(),
-        ]);
-        networks.insert("ANZOD".to_string(), vec![
-            "Royal Melbourne Hospital".to_string(),
-            "Sydney Children's Hospital".to_string(),
-        ]);
-        networks
-    });
     static RESEARCH_INSTITUTIONS: RefCell<Vec<String>> = RefCell::new(vec![
         "National Cancer Institute".to_string(),
         "Memorial Sloan Kettering Cancer Center".to_string(),
@@ -123,40 +184,165 @@ thread_local! {
 #[init]
 fn init() {
     ic_cdk::println!("🤖 Executor AI initialized - Ready for autonomous directive execution");
+    history::ensure_compaction_timer();
+    reconciliation::ensure_reconciliation_timer();
 }
 
 // Main function for autonomous death directive execution
 #[update]
 async fn execute_death_directives(patient_id: String) -> Result<ExecutionResult, String> {
-    let start_time = ic_cdk::api::time();
-    let execution_id = format!("EXEC_{}_{}", patient_id, start_time);
+    // One execution per patient at a time; a second would repeat every step of the first
+    let _guard = patient_locks::PatientGuard::acquire(&patient_id, "EXECUTION")?;
+    let execution_id = ids::new_id("EXEC");
+    // A trap in a later round fails the running step and the execution instead of leaving them open
+    let mut watch = traps::Watch::execution(&execution_id, &patient_id);
+    let result = run_death_directives(patient_id.clone(), execution_id.clone(), &mut watch).await;
+    
+    // Attempts that never produced an execution record still need evidence
+    if let Err(error) = &result {
+        evidence::record_failed_attempt(&patient_id, error);
+        events::record(events::ExecutionEventKind::ExecutionFailed {
+            patient_id: patient_id.clone(),
+            execution_id: Some(execution_id),
+            error: error.clone(),
+        });
+    }
+    
+    result
+}
+
+async fn run_death_directives(
+    patient_id: String,
+    execution_id: String,
+    watch: &mut traps::Watch
+) -> Result<ExecutionResult, String> {
+    let start_time = clock::now();
     
     ic_cdk::println!("🚀 Starting autonomous execution for patient: {}", patient_id);
     
+    // 0. Unsigned or contested directives go to the ethics committee; family/proxy objections and
+    // open cases hold execution until review resolves them
+    ethics::refer_unsettled_directives(&patient_id).await?;
+    execution_gate(&patient_id)?;
+    
     // 1. Verify death certificate (simulated)
     let death_verified = verify_death_certificate(&patient_id).await?;
     if !death_verified {
         return Err("Death certificate verification failed".to_string());
     }
+    events::record(events::ExecutionEventKind::ExecutionStarted {
+        execution_id: execution_id.clone(),
+        patient_id: patient_id.clone(),
+    });
+    publish_execution_event(&patient_id, "EXECUTION_STARTED", &execution_id).await;
     
     // 2. Retrieve all patient directives
     let directives = get_all_patient_directives(&patient_id).await?;
     
     let mut executed_directives = Vec::new();
+    let mut consent_states = Vec::new();
+    
+    let body_donation_requested = body_donation::requested(&patient_id, &directives).await;
     
-    // 3. Execute organ donation if consented
-    if directives.contains(&"ORGAN_DONATION".to_string()) {
-        let organ_execution = execute_organ_donation(&patient_id).await?;
+    // 2a. A hold, objection or ethics referral may arrive during any await, the steps' own included;
+    // the gate is read again as each step starts, so every step runs on what holds at that moment
+    
+    // 2b. Whole-body donation and organ recovery compete for the same body; the precedence policy picks one
+    let body_donation_wanted = body_donation_requested && step_allowed(&patient_id, body_donation::DIRECTIVE_TYPE)?;
+    let body_first = body_donation_wanted
+        && directives.contains(&"ORGAN_DONATION".to_string())
+        && body_donation::takes_precedence(&patient_id);
+    if body_first {
+        ic_cdk::println!("🎓 Whole-body donation takes precedence over organ and tissue recovery for {}", execution_id);
+    }
+    
+    // 3. Execute organ donation if consented and not since withdrawn
+    if directives.contains(&"ORGAN_DONATION".to_string()) && !body_first && step_allowed(&patient_id, "ORGAN_DONATION")?
+        && consent_cascade::check_step(&patient_id, "ORGAN_DONATION", "ORGAN_DONATION", &mut consent_states)
+    {
+        watch.step("ORGAN_DONATION");
+        let organ_execution = execute_organ_donation(&patient_id).await
+            .map_err(|e| step_failed(&execution_id, "ORGAN_DONATION", e))?;
+        record_step(&execution_id, &organ_execution);
+        watch.step_done();
         executed_directives.push(organ_execution);
     }
     
+    // 3b. Tissue (corneas, skin, bone...) goes to tissue banks on its own timeline
+    if directives.contains(&"ORGAN_DONATION".to_string()) && !body_first && step_allowed(&patient_id, "TISSUE_DONATION")?
+        && consent_cascade::check_step(&patient_id, "TISSUE_DONATION", "ORGAN_DONATION", &mut consent_states)
+    {
+        let tissue_execution = tissue::execute_tissue_donation(&patient_id)
+            .map_err(|e| step_failed(&execution_id, "TISSUE_DONATION", e))?;
+        if let Some(tissue_execution) = tissue_execution {
+            record_step(&execution_id, &tissue_execution);
+            executed_directives.push(tissue_execution);
+        }
+    }
+    
+    // 3c. Whole-body donation; after recovery only programs that take such bodies are offered
+    if body_donation_wanted && step_allowed(&patient_id, body_donation::DIRECTIVE_TYPE)? {
+        let organs_recovered = executed_directives.iter()
+            .any(|d| !d.organs_processed.is_empty() && (d.directive_type == "ORGAN_DONATION" || d.directive_type == "TISSUE_DONATION"));
+        let body_execution = body_donation::execute_body_donation(&patient_id, organs_recovered);
+        record_step(&execution_id, &body_execution);
+        executed_directives.push(body_execution);
+    }
+    
     // 4. Execute data sharing if consented
-    if directives.contains(&"DATA_CONSENT".to_string()) {
-        let data_execution = execute_data_sharing(&patient_id).await?;
+    if directives.contains(&"DATA_CONSENT".to_string()) && step_allowed(&patient_id, "DATA_CONSENT")?
+        && consent_cascade::check_step(&patient_id, "DATA_CONSENT", "DATA_CONSENT", &mut consent_states)
+    {
+        watch.step("DATA_CONSENT");
+        let data_execution = execute_data_sharing(&patient_id).await
+            .map_err(|e| step_failed(&execution_id, "DATA_CONSENT", e))?;
+        record_step(&execution_id, &data_execution);
+        watch.step_done();
         executed_directives.push(data_execution);
     }
     
-    let total_execution_time = ((ic_cdk::api::time() - start_time) / 1_000_000) as u64; // Convert to ms
+    // 4b. Digital legacy wishes go to the services holding the accounts; a failure here never undoes the steps above
+    if step_allowed(&patient_id, "DIGITAL_LEGACY")? {
+        watch.step("DIGITAL_LEGACY");
+        match digital_legacy::execute_digital_legacy(&patient_id).await {
+            Ok(Some(legacy_execution)) => {
+                record_step(&execution_id, &legacy_execution);
+                executed_directives.push(legacy_execution);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                ic_cdk::println!("⚠️ Digital legacy step skipped for {}: {}", execution_id, e);
+                record_step_failure(&execution_id, "DIGITAL_LEGACY", &e);
+                executed_directives.push(failed_step("DIGITAL_LEGACY"));
+            }
+        }
+        watch.step_done();
+    }
+    
+    // 4c. Funeral and disposition wishes are reported, not executed; they ride on the completion notices
+    if step_allowed(&patient_id, "DISPOSITION")? {
+        watch.step("DISPOSITION");
+        match disposition::execute_disposition(&patient_id, &execution_id).await {
+            Ok(Some(disposition_step)) => {
+                record_step(&execution_id, &disposition_step);
+                executed_directives.push(disposition_step);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                ic_cdk::println!("⚠️ Disposition step skipped for {}: {}", execution_id, e);
+                record_step_failure(&execution_id, "DISPOSITION", &e);
+                executed_directives.push(failed_step("DISPOSITION"));
+            }
+        }
+        watch.step_done();
+    }
+    
+    // 4d. Plugins registered for the patient's directive types are queued here and called once the
+    // execution is committed; their outcomes are on the plugin invocation records
+    let upheld_objections = execution_gate(&patient_id)?;
+    let plugin_types: Vec<String> = directives.iter().filter(|t| !is_blocked(&upheld_objections, t)).cloned().collect();
+    
+    let total_execution_time = clock::now().saturating_sub(start_time) / 1_000_000; // Convert to ms
     
     // 5. Create execution result
     let execution_result = ExecutionResult {
@@ -167,21 +353,96 @@ async fn execute_death_directives(patient_id: String) -> Result<ExecutionResult,
         blockchain_verification: format!("0x{:x}", ic_cdk::api::sha256(execution_id.as_bytes())[0..8].iter().fold(0u64, |acc, &b| acc << 8 | b as u64)),
         audit_log_created: true,
         compliance_verified: true,
+        contact_acknowledgments: vec![],
+        consent_states,
+        consent_retractions: vec![],
     };
     
     // 6. Store execution result for audit
-    EXECUTION_HISTORY.with(|history| {
-        history.borrow_mut().insert(execution_id.clone(), execution_result.clone());
-    });
+    events::record(events::ExecutionEventKind::ExecutionCompleted(execution_result.clone()));
+    watch.settled();
+    plugins::schedule(&patient_id, &execution_id, plugin_types);
+    
+    // 6b. A consent withdrawn while its step was under way is walked back now
+    consent_cascade::catch_up(&execution_result).await;
     
     // 7. Create immutable audit log
     create_execution_audit_log(&patient_id, &execution_result).await?;
     
+    // 8. Tell the patient's next-of-kin; their acknowledgments land on this record later
+    notify_next_of_kin(&patient_id, &execution_result).await;
+    disposition::deliver_report(&execution_result).await;
+    publish_execution_event(&patient_id, "EXECUTION_COMPLETED", &execution_id).await;
+    notify_payers(&patient_id, &execution_result).await;
+    
     ic_cdk::println!("✅ Autonomous execution completed: {} in {}ms", execution_id, total_execution_time);
     
     Ok(execution_result)
 }
 
+// Holds stop the whole execution; the directive types of upheld objections are returned to be skipped
+fn execution_gate(patient_id: &str) -> Result<Vec<Option<String>>, String> {
+    if let Some(dispute) = disputes::active_hold(patient_id) {
+        return Err(format!(
+            "Execution on hold pending dispute {} (review deadline {})",
+            dispute.dispute_id, dispute.review_deadline
+        ));
+    }
+    if let Some(case) = ethics::blocking_case(patient_id) {
+        return Err(format!(
+            "Execution gated by ethics committee case {} ({})",
+            case.case_id, case.status
+        ));
+    }
+    Ok(disputes::upheld_objections(patient_id))
+}
+
+// Read the gate as a step starts: a hold or open ethics case stops the execution there, an upheld
+// objection to this directive type skips the step
+fn step_allowed(patient_id: &str, directive_type: &str) -> Result<bool, String> {
+    Ok(!is_blocked(&execution_gate(patient_id)?, directive_type))
+}
+
+// An objection without a directive type objects to every step
+fn is_blocked(upheld_objections: &[Option<String>], directive_type: &str) -> bool {
+    upheld_objections.iter().any(|t| t.as_deref().is_none_or(|t| t == directive_type))
+}
+
+pub(crate) fn record_step_failure(execution_id: &str, step: &str, error: &str) {
+    events::record(events::ExecutionEventKind::ExecutionStepFailed {
+        execution_id: execution_id.to_string(),
+        step: step.to_string(),
+        error: error.to_string(),
+    });
+}
+
+// What a step that failed leaves on the execution record
+pub(crate) fn failed_step(directive_type: &str) -> DirectiveExecution {
+    DirectiveExecution {
+        directive_type: directive_type.to_string(),
+        execution_status: "FAILED".to_string(),
+        organs_processed: vec![],
+        recipient_matches: vec![],
+        total_recipients_notified: 0,
+        estimated_lives_saved: 0,
+        data_shared_with: vec![],
+        anonymization_verified: false,
+        research_impact_score: 0.0,
+    }
+}
+
+fn step_failed(execution_id: &str, step: &str, error: String) -> String {
+    record_step_failure(execution_id, step, &error);
+    error
+}
+
+fn record_step(execution_id: &str, step: &DirectiveExecution) {
+    events::record(events::ExecutionEventKind::ExecutionStepCompleted {
+        execution_id: execution_id.to_string(),
+        step: step.clone(),
+    });
+}
+
 // Execute organ donation with network coordination
 async fn execute_organ_donation(patient_id: &str) -> Result<DirectiveExecution, String> {
     ic_cdk::println!("🫀 Executing organ donation for patient: {}", patient_id);
@@ -190,15 +451,25 @@ async fn execute_organ_donation(patient_id: &str) -> Result<DirectiveExecution,
     let available_organs = assess_organ_viability(patient_id).await?;
     
     // 2. Find optimal recipients
-    let recipient_matches = find_optimal_recipients(&available_organs).await?;
+    let recipient_matches = find_optimal_recipients(patient_id, &available_organs).await?;
     
     // 3. Send notifications to transplant centers
     let mut notification_count = 0;
     let mut updated_matches = Vec::new();
     
     for mut recipient_match in recipient_matches {
+        // Closed centers are skipped outright rather than left to time out
+        if capacity::is_closed(&recipient_match.transplant_center) {
+            ic_cdk::println!("⏭️ Skipping closed center: {}", recipient_match.transplant_center);
+            multi_organ::settle_offer(patient_id, &recipient_match, false);
+            updated_matches.push(recipient_match);
+            continue;
+        }
+        
         let notification_result = notify_transplant_center(&recipient_match).await;
         recipient_match.notification_sent = notification_result.is_ok();
+        // An offer that never reached its center must not hold the organs it reserved
+        multi_organ::settle_offer(patient_id, &recipient_match, recipient_match.notification_sent);
         if recipient_match.notification_sent {
             notification_count += 1;
         }
@@ -251,54 +522,19 @@ async fn execute_data_sharing(patient_id: &str) -> Result<DirectiveExecution, St
     })
 }
 
-// Assess organ viability for donation
+// Assess organ viability for donation from the hospital's submitted work-up
 async fn assess_organ_viability(patient_id: &str) -> Result<Vec<OrganAvailability>, String> {
-    // Simulate organ assessment based on patient data
-    let organs = vec![
-        OrganAvailability {
-            organ_type: "kidney_left".to_string(),
-            blood_type: "O+".to_string(),
-            hla_typing: vec!["A*02:01".to_string(), "B*07:02".to_string()],
-            organ_condition: "Excellent".to_string(),
-            time_since_harvest: 0,
-            location: "Mayo Clinic".to_string(),
-            viability_score: 0.95,
-        },
-        OrganAvailability {
-            organ_type: "kidney_right".to_string(),
-            blood_type: "O+".to_string(),
-            hla_typing: vec!["A*02:01".to_string(), "B*07:02".to_string()],
-            organ_condition: "Excellent".to_string(),
-            time_since_harvest: 0,
-            location: "Mayo Clinic".to_string(),
-            viability_score: 0.94,
-        },
-        OrganAvailability {
-            organ_type: "liver".to_string(),
-            blood_type: "O+".to_string(),
-            hla_typing: vec!["A*02:01".to_string(), "B*07:02".to_string()],
-            organ_condition: "Good".to_string(),
-            time_since_harvest: 0,
-            location: "Mayo Clinic".to_string(),
-            viability_score: 0.91,
-        },
-        OrganAvailability {
-            organ_type: "corneas".to_string(),
-            blood_type: "O+".to_string(),
-            hla_typing: vec![],
-            organ_condition: "Excellent".to_string(),
-            time_since_harvest: 0,
-            location: "Mayo Clinic".to_string(),
-            viability_score: 0.98,
-        },
-    ];
+    let organs = viability::assess(patient_id)?;
     
-    ic_cdk::println!("🔬 Assessed {} organs for patient: {}", organs.len(), patient_id);
+    ic_cdk::println!("🔬 Assessed {} viable organs for patient: {}", organs.len(), patient_id);
     Ok(organs)
 }
 
 // Find optimal recipients using AI matching
-async fn find_optimal_recipients(available_organs: &[OrganAvailability]) -> Result<Vec<RecipientMatch>, String> {
+async fn find_optimal_recipients(
+    patient_id: &str,
+    available_organs: &[OrganAvailability]
+) -> Result<Vec<RecipientMatch>, String> {
     let mut matches = Vec::new();
     
     for organ in available_organs {
@@ -312,6 +548,10 @@ async fn find_optimal_recipients(available_organs: &[OrganAvailability]) -> Resu
                     distance_km: 45,
                     transplant_center: "Mayo Clinic Transplant Center".to_string(),
                     notification_sent: false,
+                    allocation_profile: String::new(),
+                    allocation_score: 0.0,
+                    kdpi: organ.kdpi,
+                    epts: None,
                     estimated_survival_benefit: 0.92,
                 });
             },
@@ -324,6 +564,10 @@ async fn find_optimal_recipients(available_organs: &[OrganAvailability]) -> Resu
                     distance_km: 78,
                     transplant_center: "Johns Hopkins Transplant Center".to_string(),
                     notification_sent: false,
+                    allocation_profile: String::new(),
+                    allocation_score: 0.0,
+                    kdpi: organ.kdpi,
+                    epts: None,
                     estimated_survival_benefit: 0.89,
                 });
             },
@@ -336,36 +580,37 @@ async fn find_optimal_recipients(available_organs: &[OrganAvailability]) -> Resu
                     distance_km: 120,
                     transplant_center: "Cleveland Clinic".to_string(),
                     notification_sent: false,
+                    allocation_profile: String::new(),
+                    allocation_score: 0.0,
+                    kdpi: organ.kdpi,
+                    epts: None,
                     estimated_survival_benefit: 0.85,
                 });
             },
-            "corneas" => {
-                matches.push(RecipientMatch {
-                    recipient_id: "R_004_corneas".to_string(),
-                    organ: organ.organ_type.clone(),
-                    compatibility_score: 0.99,
-                    urgency_level: 3,
-                    distance_km: 25,
-                    transplant_center: "Mayo Clinic Eye Center".to_string(),
-                    notification_sent: false,
-                    estimated_survival_benefit: 0.95,
-                });
-            },
             _ => {}
         }
     }
     
-    // Sort by compatibility score and urgency
-    matches.sort_by(|a, b| {
-        (b.compatibility_score * (4 - b.urgency_level) as f32)
-            .partial_cmp(&(a.compatibility_score * (4 - a.urgency_level) as f32))
-            .unwrap_or(std::cmp::Ordering::Equal)
-    });
+    // Recipients with antibodies against donor HLA are excluded before ranking
+    let matches = crossmatch::exclude_incompatible(patient_id, available_organs, matches);
+    
+    // Combined and en-bloc offers claim their organs first; each organ is reserved exactly once
+    let mut matches = multi_organ::allocate(patient_id, available_organs, matches);
     
-    Ok(matches)
+    // Kidney programs decide acceptance on donor KDPI against recipient EPTS
+    for recipient_match in matches.iter_mut().filter(|m| m.organ.starts_with("kidney")) {
+        recipient_match.epts = kidney_indices::epts(&recipient_match.recipient_id);
+    }
+    
+    // Rank under the allocation policy of each center's network; an excluded match gives its organs back
+    let ranked = allocation::apply_regional_profiles(matches.clone());
+    for excluded in matches.iter().filter(|m| !ranked.iter().any(|r| r.recipient_id == m.recipient_id && r.organ == m.organ)) {
+        multi_organ::settle_offer(patient_id, excluded, false);
+    }
+    Ok(ranked)
 }
 
-// Notify transplant centers
+// Notify transplant centers through the network that serves them
 async fn notify_transplant_center(recipient_match: &RecipientMatch) -> Result<(), String> {
     ic_cdk::println!(
         "🚨 ORGAN AVAILABLE: Center: {} - Recipient: {} - Organ: {} - Compatibility: {:.2}",
@@ -375,8 +620,8 @@ async fn notify_transplant_center(recipient_match: &RecipientMatch) -> Result<()
         recipient_match.compatibility_score
     );
     
-    // In a real implementation, this would send actual notifications
-    // via secure channels to the transplant centers
+    let network_id = networks::send_offer(recipient_match).await?;
+    ic_cdk::println!("📡 Offer routed via {}", network_id);
     
     Ok(())
 }
@@ -477,8 +722,9 @@ async fn get_all_patient_directives(patient_id: &str) -> Result<Vec<String>, Str
 
 async fn anonymize_patient_data(patient_id: &str) -> Result<String, String> {
     ic_cdk::println!("🔒 Anonymizing data for patient: {}", patient_id);
-    // Create anonymized data hash
-    let anonymized_hash = format!("ANON_{:x}", ic_cdk::api::sha256(patient_id.as_bytes())[0..8].iter().fold(0u64, |acc, &b| acc << 8 | b as u64));
+    // Create anonymized data hash from the keyed patient hash, not a dictionary-reversible digest
+    let patient_id_hash = derive_patient_hash(patient_id).await?;
+    let anonymized_hash = format!("ANON_{:x}", patient_id_hash[0..8].iter().fold(0u64, |acc, &b| acc << 8 | b as u64));
     Ok(anonymized_hash)
 }
 
@@ -491,6 +737,9 @@ async fn create_execution_audit_log(
     patient_id: &str,
     execution_result: &ExecutionResult
 ) -> Result<(), String> {
+    let payload = serde_json::to_vec(execution_result).map_err(|e| e.to_string())?;
+    audit::append_audit_entry("DIRECTIVE_EXECUTION", &execution_result.execution_id, &payload);
+    
     ic_cdk::println!(
         "📝 AUDIT: Execution completed - Patient: {} - Execution ID: {} - Time: {} - Lives saved: {}",
         patient_id,
@@ -502,19 +751,141 @@ async fn create_execution_audit_log(
     Ok(())
 }
 
-// Query functions for monitoring
-#[query]
-fn get_execution_history() -> Vec<ExecutionResult> {
-    EXECUTION_HISTORY.with(|history| {
-        history.borrow().values().cloned().collect()
-    })
+async fn notify_next_of_kin(patient_id: &str, execution_result: &ExecutionResult) {
+    let patient_id_hash = match derive_patient_hash(patient_id).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            ic_cdk::println!("⚠️ Next-of-kin notification failed for {}: {}", execution_result.execution_id, e);
+            reconciliation::record_contact_notices(&execution_result.execution_id, Err(e));
+            return;
+        }
+    };
+    let executed: Vec<String> = execution_result.directives_executed.iter()
+        .map(|d| d.directive_type.clone())
+        .collect();
+    let mut details = format!(
+        "{} recipient(s) notified",
+        execution_result.directives_executed.iter().map(|d| d.total_recipients_notified).sum::<u32>()
+    );
+    if let Some(disposition) = disposition::summary(&execution_result.execution_id) {
+        details.push_str(&format!(". {}", disposition));
+    }
+    let event = ContactEvent {
+        event_type: "DIRECTIVE_EXECUTION".to_string(),
+        reference_id: execution_result.execution_id.clone(),
+        summary: format!("Directives executed: {}", executed.join(", ")),
+        details,
+    };
+    
+    let Ok(directive_manager_id) = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID) else {
+        return;
+    };
+    let result: Result<(Vec<ContactNotification>,), _> = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call(directive_manager_id, "notify_contacts", (patient_id_hash.clone(), event.clone()))
+    }).await;
+    
+    if let Err(msg) = &result {
+        ic_cdk::println!("⚠️ Next-of-kin notification failed for {}: {}", execution_result.execution_id, msg);
+    }
+    reconciliation::record_contact_notices(&execution_result.execution_id, result.map(|(notifications,)| notifications));
+}
+
+// Subscribed hospitals and registries hear about runs through directive_manager's webhooks
+async fn publish_execution_event(patient_id: &str, event_type: &str, execution_id: &str) {
+    let Ok(directive_manager_id) = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID) else {
+        return;
+    };
+    let result: Result<(Result<(), String>,), _> = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call(
+            directive_manager_id,
+            "publish_execution_event",
+            (patient_id.to_string(), event_type.to_string(), execution_id.to_string())
+        )
+    }).await;
+    
+    match result {
+        Ok((Ok(()),)) => {}
+        Ok((Err(msg),)) | Err(msg) => {
+            ic_cdk::println!("⚠️ {} event for {} not published: {}", event_type, execution_id, msg);
+        }
+    }
+}
+
+// Payers the patient opted in on their directive get a minimal notice; directive_manager checks the consent
+async fn notify_payers(patient_id: &str, execution_result: &ExecutionResult) {
+    let Ok(directive_manager_id) = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID) else {
+        return;
+    };
+    let execution_types: Vec<String> = execution_result.directives_executed.iter()
+        .map(|d| d.directive_type.clone())
+        .collect();
+    let result: Result<(Result<u32, String>,), _> = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call(
+            directive_manager_id,
+            "notify_payers_of_execution",
+            (patient_id.to_string(), execution_result.execution_id.clone(), execution_types.clone(), clock::now())
+        )
+    }).await;
+    
+    match result {
+        Ok((Ok(_),)) => {}
+        Ok((Err(msg),)) | Err(msg) => {
+            ic_cdk::println!("⚠️ Payer notices for {} not sent: {}", execution_result.execution_id, msg);
+        }
+    }
+}
+
+// directive_manager holds the patient hash key; hashes are never computed locally
+pub(crate) async fn derive_patient_hash(patient_id: &str) -> Result<Vec<u8>, String> {
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let (result,): (Result<Vec<u8>, String>,) = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call(directive_manager_id, "derive_patient_hash", (patient_id.to_string(),))
+    }).await.map_err(|msg| format!("Failed to derive patient hash: {}", msg))?;
+    result
 }
 
+// directive_manager's opaque reference to the patient for one outside recipient
+pub(crate) async fn external_reference(patient_id_hash: &[u8], recipient: &str) -> Result<String, String> {
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let (result,): (Result<String, String>,) = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call(directive_manager_id, "issue_external_reference", (patient_id_hash.to_vec(), recipient.to_string()))
+    }).await.map_err(|msg| format!("Failed to issue external reference: {}", msg))?;
+    result
+}
+
+// Called by directive_manager when a contact acknowledges an execution notice
+#[update]
+fn record_contact_acknowledgment(notification: ContactNotification) -> Result<(), String> {
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    if caller() != directive_manager_id {
+        return Err("Only directive_manager may record contact acknowledgments".to_string());
+    }
+    
+    let acknowledged_at = notification.acknowledged_at
+        .ok_or("Notification has not been acknowledged")?;
+    
+    if !EXECUTION_HISTORY.with(|history| history.borrow().contains_key(&notification.reference_id)) {
+        return Err(format!("Execution not found: {}", notification.reference_id));
+    }
+    events::record(events::ExecutionEventKind::ContactAcknowledged {
+        execution_id: notification.reference_id,
+        acknowledgment: ContactAcknowledgment {
+            contact_id: notification.contact_id,
+            notification_id: notification.notification_id,
+            acknowledged_at,
+            note: notification.acknowledgment_note,
+        },
+    });
+    Ok(())
+}
+
+// Query functions for monitoring
 #[query]
 fn get_supported_organ_networks() -> Vec<String> {
-    ORGAN_NETWORKS.with(|networks| {
-        networks.borrow().keys().cloned().collect()
-    })
+    networks::active_network_ids()
 }
 
 #[query]
@@ -522,4 +893,7 @@ fn get_research_institutions() -> Vec<String> {
     RESEARCH_INSTITUTIONS.with(|institutions| {
         institutions.borrow().clone()
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests;
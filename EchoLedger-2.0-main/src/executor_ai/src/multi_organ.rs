@@ -0,0 +1,245 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::{clock, crossmatch, kidney_indices, viability, OrganAvailability, RecipientMatch};
+
+// A candidate who can only be transplanted if every listed organ comes from the same donor
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct MultiOrganCandidate {
+    pub recipient_id: String,
+    pub organs: Vec<String>, // e.g. ["heart", "lungs"], ["liver", "kidney"]
+    pub transplant_center: String,
+    pub urgency_level: u8,
+    pub distance_km: u32,
+    pub compatibility_score: f32,
+    pub estimated_survival_benefit: f32,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct OrganAllocation {
+    pub donor_id: String,
+    pub organ: String,
+    pub recipient_id: String,
+    pub allocated_at: u64,
+}
+
+thread_local! {
+    static MULTI_ORGAN_CANDIDATES: RefCell<BTreeMap<String, MultiOrganCandidate>> = RefCell::new(BTreeMap::new());
+    // (donor ID, organ) -> allocation; a single organ is never offered to two recipients
+    static ORGAN_ALLOCATIONS: RefCell<BTreeMap<(String, String), OrganAllocation>> = RefCell::new(BTreeMap::new());
+    // (donor ID, recipient ID) -> a candidate taken off the list for a combined offer still being sent
+    static OFFERED_CANDIDATES: RefCell<BTreeMap<(String, String), MultiOrganCandidate>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// OPTN guidance: kidneys from donors under 18 kg are allocated en bloc
+const EN_BLOC_MAX_DONOR_WEIGHT_KG: f32 = 18.0;
+const EN_BLOC_MIN_RECIPIENT_AGE: u8 = 18;
+const EN_BLOC_ORGAN: &str = "kidney_en_bloc";
+const MULTI_ORGAN_COMBINATIONS: [[&str; 2]; 4] = [
+    ["heart", "lungs"],
+    ["liver", "kidney"],
+    ["kidney", "pancreas"],
+    ["heart", "kidney"],
+];
+
+#[update]
+fn register_multi_organ_candidate(candidate: MultiOrganCandidate) -> Result<(), String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    let mut requested: Vec<&str> = candidate.organs.iter().map(String::as_str).collect();
+    requested.sort();
+    let supported = MULTI_ORGAN_COMBINATIONS.iter().any(|combo| {
+        let mut combo = combo.to_vec();
+        combo.sort();
+        combo == requested
+    });
+    if !supported {
+        return Err(format!("Unsupported multi-organ combination: {}", candidate.organs.join("+")));
+    }
+
+    MULTI_ORGAN_CANDIDATES.with(|c| {
+        c.borrow_mut().insert(candidate.recipient_id.clone(), candidate);
+    });
+    Ok(())
+}
+
+#[query]
+fn get_organ_allocations(donor_id: String) -> Vec<OrganAllocation> {
+    ORGAN_ALLOCATIONS.with(|a| {
+        a.borrow()
+            .values()
+            .filter(|x| x.donor_id == donor_id)
+            .cloned()
+            .collect()
+    })
+}
+
+// Frees an organ after the recipient's center declines the offer
+#[update]
+fn release_organ_allocation(donor_id: String, organ: String) -> Result<(), String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    ORGAN_ALLOCATIONS.with(|a| {
+        a.borrow_mut()
+            .remove(&(donor_id, organ.clone()))
+            .map(|_| ())
+            .ok_or_else(|| format!("Organ is not allocated: {}", organ))
+    })
+}
+
+// "kidney" in a combination is satisfied by either kidney
+fn organ_satisfies(requested: &str, available: &str) -> bool {
+    available == requested || (requested == "kidney" && available.starts_with("kidney_") && available != EN_BLOC_ORGAN)
+}
+
+// Multi-organ candidates first, then en-bloc pediatric kidneys, then single-organ matches;
+// every organ is reserved in the allocation ledger before it is offered
+pub(crate) fn allocate(
+    donor_id: &str,
+    organs: &[OrganAvailability],
+    single_matches: Vec<RecipientMatch>
+) -> Vec<RecipientMatch> {
+    let mut remaining: Vec<String> = organs.iter()
+        .map(|o| o.organ_type.clone())
+        .filter(|o| !is_allocated(donor_id, o))
+        .collect();
+    let donor_hla = organs.iter().find(|o| !o.hla_typing.is_empty()).map(|o| o.hla_typing.clone()).unwrap_or_default();
+    let kdpi = organs.iter().find_map(|o| o.kdpi);
+    let mut allocated = Vec::new();
+
+    let mut candidates: Vec<MultiOrganCandidate> = MULTI_ORGAN_CANDIDATES.with(|c| c.borrow().values().cloned().collect());
+    candidates.sort_by_key(|c| c.urgency_level);
+
+    for candidate in candidates {
+        if !crossmatch::conflicts(&donor_hla, &candidate.recipient_id).is_empty() {
+            continue;
+        }
+        let mut claimed = Vec::new();
+        for requested in &candidate.organs {
+            if let Some(organ) = remaining.iter().find(|o| !claimed.contains(*o) && organ_satisfies(requested, o)) {
+                claimed.push(organ.clone());
+            }
+        }
+        if claimed.len() != candidate.organs.len() || !reserve_all(donor_id, &claimed, &candidate.recipient_id) {
+            continue;
+        }
+
+        remaining.retain(|o| !claimed.contains(o));
+        // Off the list at once, so a second donor's run cannot offer the same candidate a second set
+        MULTI_ORGAN_CANDIDATES.with(|c| c.borrow_mut().remove(&candidate.recipient_id));
+        OFFERED_CANDIDATES.with(|o| o.borrow_mut().insert((donor_id.to_string(), candidate.recipient_id.clone()), candidate.clone()));
+        allocated.push(RecipientMatch {
+            recipient_id: candidate.recipient_id.clone(),
+            organ: claimed.join("+"),
+            compatibility_score: candidate.compatibility_score,
+            urgency_level: candidate.urgency_level,
+            distance_km: candidate.distance_km,
+            transplant_center: candidate.transplant_center.clone(),
+            notification_sent: false,
+            estimated_survival_benefit: candidate.estimated_survival_benefit,
+            allocation_profile: String::new(),
+            allocation_score: 0.0,
+            kdpi: if claimed.iter().any(|o| o.starts_with("kidney")) { kdpi } else { None },
+            epts: None,
+        });
+    }
+
+    let en_bloc = viability::donor_data(donor_id)
+        .and_then(|d| d.weight_kg)
+        .map(|w| w < EN_BLOC_MAX_DONOR_WEIGHT_KG)
+        .unwrap_or(false)
+        && remaining.iter().any(|o| o == "kidney_left")
+        && remaining.iter().any(|o| o == "kidney_right");
+
+    if en_bloc {
+        let kidneys = vec!["kidney_left".to_string(), "kidney_right".to_string()];
+        remaining.retain(|o| !kidneys.contains(o));
+
+        // Both small kidneys go to one adult candidate who has agreed to en-bloc grafts
+        let recipient = single_matches.iter()
+            .filter(|m| m.organ.starts_with("kidney_"))
+            .find(|m| {
+                kidney_indices::candidate_profile(&m.recipient_id)
+                    .map(|p| p.accepts_en_bloc && p.age_years >= EN_BLOC_MIN_RECIPIENT_AGE)
+                    .unwrap_or(false)
+            });
+        match recipient {
+            Some(m) if reserve_all(donor_id, &kidneys, &m.recipient_id) => {
+                let mut en_bloc_match = m.clone();
+                en_bloc_match.organ = EN_BLOC_ORGAN.to_string();
+                allocated.push(en_bloc_match);
+            }
+            _ => ic_cdk::println!("🫘 No eligible en-bloc recipient for pediatric donor {}", donor_id),
+        }
+    }
+
+    for single in single_matches {
+        if remaining.contains(&single.organ)
+            && reserve_all(donor_id, std::slice::from_ref(&single.organ), &single.recipient_id)
+        {
+            remaining.retain(|o| o != &single.organ);
+            allocated.push(single);
+        }
+    }
+
+    allocated
+}
+
+// Called once per allocated offer after sending it. A delivered offer keeps its organs and retires
+// its candidate; one that never reached the center frees its organs and puts the candidate back.
+pub(crate) fn settle_offer(donor_id: &str, offer: &RecipientMatch, delivered: bool) {
+    let candidate = OFFERED_CANDIDATES.with(|o| o.borrow_mut().remove(&(donor_id.to_string(), offer.recipient_id.clone())));
+    if delivered {
+        return;
+    }
+    let organs: Vec<String> = if offer.organ == EN_BLOC_ORGAN {
+        vec!["kidney_left".to_string(), "kidney_right".to_string()]
+    } else {
+        offer.organ.split('+').map(str::to_string).collect()
+    };
+    ORGAN_ALLOCATIONS.with(|a| {
+        let mut allocations = a.borrow_mut();
+        for organ in organs {
+            let key = (donor_id.to_string(), organ);
+            if allocations.get(&key).is_some_and(|x| x.recipient_id == offer.recipient_id) {
+                allocations.remove(&key);
+            }
+        }
+    });
+    if let Some(candidate) = candidate {
+        // A registration made while the offer was out is newer and wins
+        MULTI_ORGAN_CANDIDATES.with(|c| {
+            c.borrow_mut().entry(candidate.recipient_id.clone()).or_insert(candidate);
+        });
+    }
+}
+
+fn is_allocated(donor_id: &str, organ: &str) -> bool {
+    ORGAN_ALLOCATIONS.with(|a| a.borrow().contains_key(&(donor_id.to_string(), organ.to_string())))
+}
+
+// All-or-nothing reservation so a combined offer never holds half its organs
+fn reserve_all(donor_id: &str, organs: &[String], recipient_id: &str) -> bool {
+    ORGAN_ALLOCATIONS.with(|a| {
+        let mut allocations = a.borrow_mut();
+        if organs.iter().any(|o| allocations.contains_key(&(donor_id.to_string(), o.clone()))) {
+            return false;
+        }
+        let now = clock::now();
+        for organ in organs {
+            allocations.insert((donor_id.to_string(), organ.clone()), OrganAllocation {
+                donor_id: donor_id.to_string(),
+                organ: organ.clone(),
+                recipient_id: recipient_id.to_string(),
+                allocated_at: now,
+            });
+        }
+        true
+    })
+}
@@ -0,0 +1,375 @@
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs, TransformContext,
+};
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Nat};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::{center_keys, clock, hl7_intake, ids, outcall_budget, resilience, RecipientMatch};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct OrganNetwork {
+    pub network_id: String,
+    pub name: String,
+    pub jurisdictions: Vec<String>,
+    pub endpoint_url: Option<String>,
+    pub auth_scheme: String, // "NONE", "API_KEY", "BEARER"
+    pub message_format: String, // "UNOS_JSON", "ET_XML", "HL7_V2", "GENERIC_JSON"
+    pub transplant_centers: Vec<String>,
+    pub active: bool,
+}
+
+// Each network protocol knows how to frame an organ offer for its endpoint. message_id names the
+// one message: it is the HL7 control ID and the Idempotency-Key the endpoint dedupes retries on.
+pub(crate) trait NetworkAdapter {
+    fn content_type(&self) -> &'static str;
+    fn encode_offer(&self, network: &OrganNetwork, offer: &RecipientMatch, message_id: &str) -> Vec<u8>;
+    fn encode_retraction(&self, network: &OrganNetwork, offer: &RecipientMatch, reason: &str, message_id: &str) -> Vec<u8>;
+}
+
+struct UnosJsonAdapter;
+struct EurotransplantXmlAdapter;
+struct Hl7Adapter;
+struct GenericJsonAdapter;
+
+impl NetworkAdapter for UnosJsonAdapter {
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode_offer(&self, network: &OrganNetwork, offer: &RecipientMatch, _message_id: &str) -> Vec<u8> {
+        serde_json::json!({
+            "network": network.network_id,
+            "matchRunType": "DECEASED_DONOR",
+            "candidateId": offer.recipient_id,
+            "organ": offer.organ,
+            "transplantCenter": offer.transplant_center,
+            "medicalUrgency": offer.urgency_level,
+            "compatibility": offer.compatibility_score,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn encode_retraction(&self, network: &OrganNetwork, offer: &RecipientMatch, reason: &str, _message_id: &str) -> Vec<u8> {
+        serde_json::json!({
+            "network": network.network_id,
+            "messageType": "OFFER_RETRACTION",
+            "candidateId": offer.recipient_id,
+            "organ": offer.organ,
+            "transplantCenter": offer.transplant_center,
+            "reason": reason,
+        })
+        .to_string()
+        .into_bytes()
+    }
+}
+
+impl NetworkAdapter for EurotransplantXmlAdapter {
+    fn content_type(&self) -> &'static str {
+        "application/xml"
+    }
+
+    fn encode_offer(&self, network: &OrganNetwork, offer: &RecipientMatch, _message_id: &str) -> Vec<u8> {
+        format!(
+            "<OrganOffer network=\"{}\"><Recipient>{}</Recipient><Organ>{}</Organ><Centre>{}</Centre><Urgency>{}</Urgency></OrganOffer>",
+            xml_escape(&network.network_id),
+            xml_escape(&offer.recipient_id),
+            xml_escape(&offer.organ),
+            xml_escape(&offer.transplant_center),
+            offer.urgency_level
+        )
+        .into_bytes()
+    }
+
+    fn encode_retraction(&self, network: &OrganNetwork, offer: &RecipientMatch, reason: &str, _message_id: &str) -> Vec<u8> {
+        format!(
+            "<OfferRetraction network=\"{}\"><Recipient>{}</Recipient><Organ>{}</Organ><Centre>{}</Centre><Reason>{}</Reason></OfferRetraction>",
+            xml_escape(&network.network_id),
+            xml_escape(&offer.recipient_id),
+            xml_escape(&offer.organ),
+            xml_escape(&offer.transplant_center),
+            xml_escape(reason)
+        )
+        .into_bytes()
+    }
+}
+
+impl NetworkAdapter for Hl7Adapter {
+    fn content_type(&self) -> &'static str {
+        "x-application/hl7-v2+er7"
+    }
+
+    fn encode_offer(&self, network: &OrganNetwork, offer: &RecipientMatch, message_id: &str) -> Vec<u8> {
+        format!(
+            "MSH|^~\\&|ECHOLEDGER|EXECUTOR_AI|{}|{}|{}||ORU^R01|{}|P|2.5\rOBX|1|ST|ORGAN^Organ offer||{}^{}^{}",
+            hl7_intake::escape(&network.network_id),
+            hl7_intake::escape(&offer.transplant_center),
+            hl7_intake::format_dtm(clock::now()),
+            hl7_intake::escape(message_id),
+            hl7_intake::escape(&offer.organ),
+            hl7_intake::escape(&offer.recipient_id),
+            offer.urgency_level
+        )
+        .into_bytes()
+    }
+
+    // Result status X: the offer result can no longer be obtained
+    fn encode_retraction(&self, network: &OrganNetwork, offer: &RecipientMatch, reason: &str, message_id: &str) -> Vec<u8> {
+        format!(
+            "MSH|^~\\&|ECHOLEDGER|EXECUTOR_AI|{}|{}|{}||ORU^R01|{}|P|2.5\rOBX|1|ST|ORGAN^Organ offer||{}^{}^{}||||||X",
+            hl7_intake::escape(&network.network_id),
+            hl7_intake::escape(&offer.transplant_center),
+            hl7_intake::format_dtm(clock::now()),
+            hl7_intake::escape(message_id),
+            hl7_intake::escape(&offer.organ),
+            hl7_intake::escape(&offer.recipient_id),
+            hl7_intake::escape(reason)
+        )
+        .into_bytes()
+    }
+}
+
+impl NetworkAdapter for GenericJsonAdapter {
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode_offer(&self, _network: &OrganNetwork, offer: &RecipientMatch, _message_id: &str) -> Vec<u8> {
+        serde_json::to_vec(offer).unwrap_or_default()
+    }
+
+    fn encode_retraction(&self, _network: &OrganNetwork, offer: &RecipientMatch, reason: &str, _message_id: &str) -> Vec<u8> {
+        serde_json::to_vec(&serde_json::json!({ "retracted": offer, "reason": reason })).unwrap_or_default()
+    }
+}
+
+fn adapter_for(message_format: &str) -> Box<dyn NetworkAdapter> {
+    match message_format {
+        "UNOS_JSON" => Box::new(UnosJsonAdapter),
+        "ET_XML" => Box::new(EurotransplantXmlAdapter),
+        "HL7_V2" => Box::new(Hl7Adapter),
+        _ => Box::new(GenericJsonAdapter),
+    }
+}
+
+thread_local! {
+    static ORGAN_NETWORK_REGISTRY: RefCell<BTreeMap<String, OrganNetwork>> = RefCell::new({
+        let mut networks = BTreeMap::new();
+        for (network_id, jurisdictions, format, centers) in [
+            ("UNOS", vec!["US"], "UNOS_JSON", vec![
+                "Mayo Clinic Transplant Center",
+                "Johns Hopkins Transplant Center",
+                "Cleveland Clinic",
+                "UCLA Medical Center",
+            ]),
+            ("Eurotransplant", vec!["DE", "NL", "BE", "AT", "HR", "HU", "LU", "SI"], "ET_XML", vec![
+                "Charité Berlin",
+                "University Hospital Zurich",
+                "Academic Medical Center Amsterdam",
+            ]),
+            ("ANZOD", vec!["AU", "NZ"], "GENERIC_JSON", vec![
+                "Royal Melbourne Hospital",
+                "Sydney Children's Hospital",
+            ]),
+        ] {
+            networks.insert(network_id.to_string(), OrganNetwork {
+                network_id: network_id.to_string(),
+                name: network_id.to_string(),
+                jurisdictions: jurisdictions.into_iter().map(String::from).collect(),
+                endpoint_url: None,
+                auth_scheme: "NONE".to_string(),
+                message_format: format.to_string(),
+                transplant_centers: centers.into_iter().map(String::from).collect(),
+                active: true,
+            });
+        }
+        networks
+    });
+
+    // Kept apart from the registry so credentials never leave the canister via queries
+    static NETWORK_CREDENTIALS: RefCell<BTreeMap<String, String>> = RefCell::new(BTreeMap::new());
+}
+
+const MESSAGE_FORMATS: [&str; 4] = ["UNOS_JSON", "ET_XML", "HL7_V2", "GENERIC_JSON"];
+const AUTH_SCHEMES: [&str; 3] = ["NONE", "API_KEY", "BEARER"];
+const OUTCALL_CYCLES: u128 = 50_000_000_000;
+
+#[update]
+fn register_organ_network(network: OrganNetwork, credential: Option<String>) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage organ networks".to_string());
+    }
+    if !MESSAGE_FORMATS.contains(&network.message_format.as_str()) {
+        return Err(format!("Unsupported message format: {}", network.message_format));
+    }
+    if !AUTH_SCHEMES.contains(&network.auth_scheme.as_str()) {
+        return Err(format!("Unsupported auth scheme: {}", network.auth_scheme));
+    }
+    if network.auth_scheme != "NONE" && credential.is_none() {
+        let has_stored = NETWORK_CREDENTIALS.with(|c| c.borrow().contains_key(&network.network_id));
+        if !has_stored {
+            return Err("Authenticated networks require a credential".to_string());
+        }
+    }
+    if let Some(url) = &network.endpoint_url {
+        if !url.starts_with("https://") {
+            return Err("Network endpoints must use HTTPS".to_string());
+        }
+    }
+
+    if let Some(credential) = credential {
+        NETWORK_CREDENTIALS.with(|c| c.borrow_mut().insert(network.network_id.clone(), credential));
+    }
+    ORGAN_NETWORK_REGISTRY.with(|networks| {
+        networks.borrow_mut().insert(network.network_id.clone(), network);
+    });
+    Ok(())
+}
+
+#[update]
+fn set_organ_network_active(network_id: String, active: bool) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage organ networks".to_string());
+    }
+    ORGAN_NETWORK_REGISTRY.with(|networks| {
+        let mut networks = networks.borrow_mut();
+        let network = networks.get_mut(&network_id).ok_or_else(|| format!("Unknown network: {}", network_id))?;
+        network.active = active;
+        Ok(())
+    })
+}
+
+#[query]
+fn get_organ_networks() -> Vec<OrganNetwork> {
+    ORGAN_NETWORK_REGISTRY.with(|networks| networks.borrow().values().cloned().collect())
+}
+
+pub(crate) fn active_network_ids() -> Vec<String> {
+    ORGAN_NETWORK_REGISTRY.with(|networks| {
+        networks.borrow()
+            .values()
+            .filter(|n| n.active)
+            .map(|n| n.network_id.clone())
+            .collect()
+    })
+}
+
+pub(crate) fn network_for_center(transplant_center: &str) -> Option<OrganNetwork> {
+    ORGAN_NETWORK_REGISTRY.with(|networks| {
+        networks.borrow()
+            .values()
+            .find(|n| n.active && n.transplant_centers.iter().any(|c| c == transplant_center))
+            .cloned()
+    })
+}
+
+// Route an offer through the owning network's adapter and endpoint
+pub(crate) async fn send_offer(offer: &RecipientMatch) -> Result<String, String> {
+    let network = network_for_center(&offer.transplant_center)
+        .ok_or_else(|| format!("No active network serves {}", offer.transplant_center))?;
+    let adapter = adapter_for(&network.message_format);
+    let message_id = ids::new_id("NETMSG");
+    let body = adapter.encode_offer(&network, offer, &message_id);
+    deliver(network, &offer.transplant_center, adapter.content_type(), body, "offer", &message_id).await
+}
+
+// Tell a center an offer it was sent no longer stands
+pub(crate) async fn send_retraction(offer: &RecipientMatch, reason: &str) -> Result<String, String> {
+    let network = network_for_center(&offer.transplant_center)
+        .ok_or_else(|| format!("No active network serves {}", offer.transplant_center))?;
+    let adapter = adapter_for(&network.message_format);
+    let message_id = ids::new_id("NETMSG");
+    let body = adapter.encode_retraction(&network, offer, reason, &message_id);
+    deliver(network, &offer.transplant_center, adapter.content_type(), body, "retraction", &message_id).await
+}
+
+async fn deliver(
+    network: OrganNetwork,
+    transplant_center: &str,
+    content_type: &'static str,
+    body: Vec<u8>,
+    message: &str,
+    message_id: &str
+) -> Result<String, String> {
+    let body = center_keys::seal(transplant_center, content_type, &body).await?;
+    let content_type = center_keys::SEALED_CONTENT_TYPE;
+
+    let Some(url) = network.endpoint_url.clone() else {
+        // Networks without a configured endpoint are logged only
+        ic_cdk::println!(
+            "📡 {} {} ({} bytes, {}) queued for {} - no endpoint configured",
+            network.network_id,
+            message,
+            body.len(),
+            content_type,
+            transplant_center
+        );
+        return Ok(network.network_id);
+    };
+
+    // Every replica sends the request, and a retry sends it again; the key lets the endpoint act on it once
+    let mut headers = vec![
+        HttpHeader {
+            name: "Content-Type".to_string(),
+            value: content_type.to_string(),
+        },
+        HttpHeader {
+            name: "Idempotency-Key".to_string(),
+            value: message_id.to_string(),
+        },
+    ];
+    let credential = NETWORK_CREDENTIALS.with(|c| c.borrow().get(&network.network_id).cloned());
+    match (network.auth_scheme.as_str(), credential) {
+        ("API_KEY", Some(key)) => headers.push(HttpHeader { name: "X-API-Key".to_string(), value: key }),
+        ("BEARER", Some(token)) => headers.push(HttpHeader {
+            name: "Authorization".to_string(),
+            value: format!("Bearer {}", token),
+        }),
+        _ => {}
+    }
+
+    let request = CanisterHttpRequestArgument {
+        url,
+        max_response_bytes: Some(4_096),
+        method: HttpMethod::POST,
+        headers,
+        body: Some(body),
+        transform: Some(TransformContext::from_name("transform_network_response".to_string(), vec![])),
+    };
+
+    // Offers and their retractions are time-critical, so they draw on the emergency share of the outcall budget
+    outcall_budget::acquire("EMERGENCY", OUTCALL_CYCLES).await?;
+    let target = format!("network:{}", network.network_id);
+    let (response,) = resilience::guarded_call(&target, || http_request(request.clone(), OUTCALL_CYCLES))
+        .await
+        .map_err(|msg| format!("{} delivery failed: {}", network.network_id, msg))?;
+
+    if response.status >= Nat::from(200u32) && response.status < Nat::from(300u32) {
+        Ok(network.network_id)
+    } else {
+        Err(format!("{} rejected {} with status {}", network.network_id, message, response.status))
+    }
+}
+
+// Replicas must agree on the response to reach consensus, and only the status is read: headers
+// (dates, request ids) and the body differ from replica to replica and are dropped
+#[query]
+fn transform_network_response(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: vec![],
+        body: vec![],
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,208 @@
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::{clock, governance};
+
+// Limits on HTTPS outcalls; cycles are counted as attached, before any refund
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct OutcallBudget {
+    pub max_calls_per_minute: u32,
+    pub max_cycles_per_day: u64,
+    pub emergency_reserve_percent: u8, // Share of each budget only EMERGENCY outcalls may use
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PriorityStats {
+    pub priority: String,
+    pub queue_depth: u32,
+    pub granted: u64,
+    pub refused: u64,
+    pub cycles_spent: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct OutcallBudgetStatus {
+    pub budget: OutcallBudget,
+    pub calls_this_minute: u32,
+    pub cycles_today: u64,
+    pub cycles_remaining_today: u64,
+    pub priorities: Vec<PriorityStats>,
+}
+
+#[derive(Clone, Debug)]
+struct QueuedOutcall {
+    priority: &'static str,
+    cycles: u64,
+    enqueued_at: u64,
+}
+
+#[derive(Default)]
+struct Spend {
+    minute: u64,
+    calls_this_minute: u32,
+    day: u64,
+    cycles_today: u64,
+}
+
+// Highest priority first; the order also ranks the queue
+const PRIORITIES: [&str; 3] = ["EMERGENCY", "NOTIFICATION", "VERIFICATION"];
+// Rounds an outcall may wait for budget before it is refused; verification crawls are simply retried later
+const MAX_WAIT_ROUNDS: [u32; 3] = [30, 10, 0];
+// Entries whose caller is long gone (trapped or abandoned) stop holding up the queue
+const STALE_ENTRY_NANOS: u64 = 5 * 60 * 1_000_000_000;
+const MINUTE_NANOS: u64 = 60 * 1_000_000_000;
+const DAY_NANOS: u64 = 24 * 60 * MINUTE_NANOS;
+
+thread_local! {
+    static BUDGET: RefCell<OutcallBudget> = RefCell::new(OutcallBudget {
+        max_calls_per_minute: 20,
+        max_cycles_per_day: 20_000_000_000_000,
+        emergency_reserve_percent: 20,
+    });
+
+    // (priority rank, ticket) -> waiting outcall
+    static OUTCALL_QUEUE: RefCell<BTreeMap<(usize, u64), QueuedOutcall>> = RefCell::new(BTreeMap::new());
+    static NEXT_TICKET: RefCell<u64> = RefCell::new(0);
+    static SPEND: RefCell<Spend> = RefCell::new(Spend::default());
+    static PRIORITY_TOTALS: RefCell<BTreeMap<&'static str, (u64, u64, u64)>> = RefCell::new(BTreeMap::new());
+}
+
+#[update]
+fn set_outcall_budget(budget: OutcallBudget) -> Result<(), String> {
+    governance::authorize_direct_change(caller())?;
+    validate_budget(&budget)?;
+    install_budget(budget);
+    Ok(())
+}
+
+pub(crate) fn validate_budget(budget: &OutcallBudget) -> Result<(), String> {
+    if budget.max_calls_per_minute == 0 || budget.max_cycles_per_day == 0 {
+        return Err("Outcall budgets must allow at least one call".to_string());
+    }
+    if budget.emergency_reserve_percent > 100 {
+        return Err("The emergency reserve must be within 0-100 percent".to_string());
+    }
+    Ok(())
+}
+
+pub(crate) fn install_budget(budget: OutcallBudget) {
+    BUDGET.with(|b| *b.borrow_mut() = budget);
+}
+
+#[query]
+fn get_outcall_budget_status() -> Result<OutcallBudgetStatus, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read outcall spend".to_string());
+    }
+    let now = clock::now();
+    let budget = BUDGET.with(|b| b.borrow().clone());
+    let (calls_this_minute, cycles_today) = SPEND.with(|s| {
+        let s = s.borrow();
+        let calls = if s.minute == now / MINUTE_NANOS { s.calls_this_minute } else { 0 };
+        let cycles = if s.day == now / DAY_NANOS { s.cycles_today } else { 0 };
+        (calls, cycles)
+    });
+    let priorities = PRIORITIES.iter().enumerate().map(|(rank, priority)| {
+        let queue_depth = OUTCALL_QUEUE.with(|q| q.borrow().keys().filter(|(r, _)| *r == rank).count() as u32);
+        let (granted, refused, cycles_spent) = PRIORITY_TOTALS.with(|t| t.borrow().get(priority).copied().unwrap_or_default());
+        PriorityStats { priority: priority.to_string(), queue_depth, granted, refused, cycles_spent }
+    }).collect();
+
+    Ok(OutcallBudgetStatus {
+        cycles_remaining_today: budget.max_cycles_per_day.saturating_sub(cycles_today),
+        budget,
+        calls_this_minute,
+        cycles_today,
+        priorities,
+    })
+}
+
+// Waits for the outcall's turn and charges it to the budget. A reply has to come from the
+// caller's own message, so a queued outcall holds its place across consensus rounds rather
+// than being resumed from a timer. Higher priorities are always served first.
+pub(crate) async fn acquire(priority: &str, cycles: u128) -> Result<(), String> {
+    let rank = PRIORITIES.iter().position(|p| *p == priority)
+        .ok_or_else(|| format!("Unknown outcall priority: {}", priority))?;
+    let priority = PRIORITIES[rank];
+    let cycles = u64::try_from(cycles).unwrap_or(u64::MAX);
+
+    let ticket = NEXT_TICKET.with(|t| {
+        let mut t = t.borrow_mut();
+        *t += 1;
+        *t
+    });
+    OUTCALL_QUEUE.with(|q| q.borrow_mut().insert((rank, ticket), QueuedOutcall {
+        priority,
+        cycles,
+        enqueued_at: clock::now(),
+    }));
+
+    let mut rounds = 0;
+    loop {
+        if try_grant(rank, ticket) {
+            return Ok(());
+        }
+        if rounds >= MAX_WAIT_ROUNDS[rank] {
+            OUTCALL_QUEUE.with(|q| q.borrow_mut().remove(&(rank, ticket)));
+            PRIORITY_TOTALS.with(|t| t.borrow_mut().entry(priority).or_default().1 += 1);
+            return Err(format!("Outcall budget exhausted for {} traffic", priority));
+        }
+        rounds += 1;
+        let _ = raw_rand().await;
+    }
+}
+
+// Grants the outcall if it is at the head of the queue and fits the budget
+fn try_grant(rank: usize, ticket: u64) -> bool {
+    let now = clock::now();
+    OUTCALL_QUEUE.with(|q| {
+        q.borrow_mut().retain(|key, entry| {
+            *key == (rank, ticket) || now.saturating_sub(entry.enqueued_at) < STALE_ENTRY_NANOS
+        })
+    });
+    let head = OUTCALL_QUEUE.with(|q| q.borrow().first_key_value().map(|(key, entry)| (*key, entry.clone())));
+    let Some((key, entry)) = head.filter(|(key, _)| *key == (rank, ticket)) else {
+        return false;
+    };
+
+    let budget = BUDGET.with(|b| b.borrow().clone());
+    let granted = SPEND.with(|s| {
+        let mut s = s.borrow_mut();
+        if s.minute != now / MINUTE_NANOS {
+            s.minute = now / MINUTE_NANOS;
+            s.calls_this_minute = 0;
+        }
+        if s.day != now / DAY_NANOS {
+            s.day = now / DAY_NANOS;
+            s.cycles_today = 0;
+        }
+
+        // Non-emergency traffic stops short of the reserve
+        let share = if entry.priority == "EMERGENCY" { 100 } else { 100 - budget.emergency_reserve_percent as u64 };
+        let call_limit = (budget.max_calls_per_minute as u64 * share / 100) as u32;
+        let cycle_limit = (budget.max_cycles_per_day as u128 * share as u128 / 100) as u64;
+        if s.calls_this_minute >= call_limit || s.cycles_today.saturating_add(entry.cycles) > cycle_limit {
+            return false;
+        }
+        s.calls_this_minute += 1;
+        s.cycles_today += entry.cycles;
+        true
+    });
+    if !granted {
+        return false;
+    }
+
+    OUTCALL_QUEUE.with(|q| q.borrow_mut().remove(&key));
+    PRIORITY_TOTALS.with(|t| {
+        let mut totals = t.borrow_mut();
+        let totals = totals.entry(entry.priority).or_default();
+        totals.0 += 1;
+        totals.2 += entry.cycles;
+    });
+    true
+}
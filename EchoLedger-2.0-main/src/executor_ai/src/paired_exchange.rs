@@ -0,0 +1,393 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::{audit, clock, crossmatch, ids, networks, traps, viability, RecipientMatch};
+
+// An incompatible donor/recipient pair, or an altruistic donor with no recipient
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ExchangePair {
+    pub pair_id: String,
+    pub donor_kind: String, // "LIVING", "DECEASED"
+    pub donor_blood_type: String,
+    pub donor_hla_typing: Vec<String>,
+    pub recipient_id: Option<String>, // None for non-directed donors, which start chains
+    pub recipient_blood_type: Option<String>,
+    pub transplant_center: String,
+    pub status: String, // "ACTIVE", "PROPOSED", "MATCHED", "WITHDRAWN"
+    pub registered_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ExchangeLeg {
+    pub donor_pair_id: String,
+    pub recipient_pair_id: String,
+    pub recipient_id: String,
+    pub transplant_center: String,
+    pub response: Option<bool>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ExchangeProposal {
+    pub proposal_id: String,
+    pub kind: String, // "CYCLE", "CHAIN"
+    pub legs: Vec<ExchangeLeg>,
+    pub status: String, // "PROPOSED", "ACCEPTED", "DECLINED"
+    pub proposed_at: u64,
+}
+
+thread_local! {
+    static EXCHANGE_PAIRS: RefCell<BTreeMap<String, ExchangePair>> = RefCell::new(BTreeMap::new());
+    static EXCHANGE_PROPOSALS: RefCell<BTreeMap<String, ExchangeProposal>> = RefCell::new(BTreeMap::new());
+    // pair_id -> status the pair had before it went out in a proposal, restored if the proposal falls through
+    static PROPOSED_FROM: RefCell<BTreeMap<String, String>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// A cycle or chain as its pair ids in donation order
+type Structure = (&'static str, Vec<String>);
+
+const MAX_CHAIN_LENGTH: usize = 4;
+// Enumeration and packing each stop where they are once their budget is spent, so a match run
+// fits in one message; the best packing found by then is the one proposed
+const MAX_CANDIDATE_STRUCTURES: usize = 2_000;
+const MAX_PACKING_STEPS: usize = 200_000;
+
+#[update]
+fn register_exchange_pair(mut pair: ExchangePair) -> Result<(), String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    if !["LIVING", "DECEASED"].contains(&pair.donor_kind.as_str()) {
+        return Err(format!("Unknown donor kind: {}", pair.donor_kind));
+    }
+    if pair.recipient_id.is_some() != pair.recipient_blood_type.is_some() {
+        return Err("Recipient ID and blood type must be supplied together".to_string());
+    }
+    if pair.donor_kind == "DECEASED" && pair.recipient_id.is_some() {
+        return Err("Deceased donors enter the exchange only as chain starters".to_string());
+    }
+
+    pair.status = "ACTIVE".to_string();
+    pair.registered_at = clock::now();
+    EXCHANGE_PAIRS.with(|p| {
+        p.borrow_mut().insert(pair.pair_id.clone(), pair);
+    });
+    Ok(())
+}
+
+#[update]
+fn withdraw_exchange_pair(pair_id: String) -> Result<(), String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    EXCHANGE_PAIRS.with(|p| {
+        let mut pairs = p.borrow_mut();
+        let pair = pairs.get_mut(&pair_id).ok_or_else(|| format!("Exchange pair not found: {}", pair_id))?;
+        if pair.status == "MATCHED" {
+            return Err("Matched pairs cannot be withdrawn".to_string());
+        }
+        pair.status = "WITHDRAWN".to_string();
+        Ok(())
+    })
+}
+
+// Find 2-/3-way cycles and altruistic chains, choose the set maximizing transplants, send offers
+#[update]
+async fn run_paired_exchange_match() -> Result<Vec<ExchangeProposal>, String> {
+    let _watch = traps::Watch::start("PAIRED_EXCHANGE");
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+
+    let pool: Vec<ExchangePair> = EXCHANGE_PAIRS.with(|p| {
+        p.borrow().values().filter(|x| x.status == "ACTIVE").cloned().collect()
+    });
+
+    let compatible = compatibility(&pool);
+    let mut structures = Vec::new();
+    find_cycles(&pool, &compatible, &mut structures);
+    find_chains(&pool, &compatible, &mut structures);
+    // Larger structures first so the packing search meets good packings early; none are dropped
+    structures.sort_by_key(|(kind, sequence)| std::cmp::Reverse(transplants(kind, sequence)));
+
+    let selected = best_packing(&structures);
+    let now = clock::now();
+    let mut proposals = Vec::new();
+
+    for (kind, sequence) in selected {
+        let legs = legs_for(&pool, kind, &sequence);
+        let proposal = ExchangeProposal {
+            proposal_id: ids::new_id("EXCHANGE"),
+            kind: kind.to_string(),
+            legs,
+            status: "PROPOSED".to_string(),
+            proposed_at: now,
+        };
+
+        EXCHANGE_PAIRS.with(|p| {
+            let mut pairs = p.borrow_mut();
+            for pair_id in &sequence {
+                if let Some(pair) = pairs.get_mut(pair_id) {
+                    let previous = std::mem::replace(&mut pair.status, "PROPOSED".to_string());
+                    PROPOSED_FROM.with(|f| f.borrow_mut().insert(pair_id.clone(), previous));
+                }
+            }
+        });
+        EXCHANGE_PROPOSALS.with(|p| {
+            p.borrow_mut().insert(proposal.proposal_id.clone(), proposal.clone());
+        });
+        if let Ok(payload) = serde_json::to_vec(&proposal) {
+            audit::append_audit_entry("EXCHANGE_PROPOSED", &proposal.proposal_id, &payload);
+        }
+        proposals.push(proposal);
+    }
+
+    for proposal in &proposals {
+        for leg in &proposal.legs {
+            let offer = RecipientMatch {
+                recipient_id: leg.recipient_id.clone(),
+                organ: "kidney_exchange".to_string(),
+                compatibility_score: 1.0,
+                urgency_level: 3,
+                distance_km: 0,
+                transplant_center: leg.transplant_center.clone(),
+                notification_sent: false,
+                estimated_survival_benefit: 0.0,
+                allocation_profile: String::new(),
+                allocation_score: 0.0,
+                kdpi: None,
+                epts: None,
+            };
+            if let Err(e) = networks::send_offer(&offer).await {
+                ic_cdk::println!("⚠️ Exchange offer for {} not delivered: {}", leg.recipient_id, e);
+            }
+        }
+    }
+
+    Ok(proposals)
+}
+
+// Each recipient's center accepts or declines its leg; one decline returns every pair to where it stood
+#[update]
+fn respond_to_exchange_offer(proposal_id: String, recipient_pair_id: String, accept: bool) -> Result<ExchangeProposal, String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+
+    let proposal = EXCHANGE_PROPOSALS.with(|p| {
+        let mut proposals = p.borrow_mut();
+        let proposal = proposals.get_mut(&proposal_id).ok_or_else(|| format!("Exchange proposal not found: {}", proposal_id))?;
+        if proposal.status != "PROPOSED" {
+            return Err(format!("Exchange proposal already {}", proposal.status));
+        }
+        let leg = proposal.legs
+            .iter_mut()
+            .find(|l| l.recipient_pair_id == recipient_pair_id)
+            .ok_or_else(|| format!("No leg for pair {} in proposal", recipient_pair_id))?;
+        leg.response = Some(accept);
+
+        if !accept {
+            proposal.status = "DECLINED".to_string();
+        } else if proposal.legs.iter().all(|l| l.response == Some(true)) {
+            proposal.status = "ACCEPTED".to_string();
+        }
+        Ok::<_, String>(proposal.clone())
+    })?;
+
+    if proposal.status != "PROPOSED" {
+        let accepted = proposal.status == "ACCEPTED";
+        EXCHANGE_PAIRS.with(|p| {
+            let mut pairs = p.borrow_mut();
+            for leg in &proposal.legs {
+                for pair_id in [&leg.donor_pair_id, &leg.recipient_pair_id] {
+                    let previous = PROPOSED_FROM.with(|f| f.borrow_mut().remove(pair_id));
+                    // A pair withdrawn while the proposal was out stays withdrawn
+                    if let Some(pair) = pairs.get_mut(pair_id).filter(|pair| pair.status == "PROPOSED") {
+                        pair.status = if accepted { "MATCHED".to_string() } else { previous.unwrap_or_else(|| "ACTIVE".to_string()) };
+                    }
+                }
+            }
+        });
+        if let Ok(payload) = serde_json::to_vec(&proposal) {
+            audit::append_audit_entry("EXCHANGE_DECIDED", &proposal.proposal_id, &payload);
+        }
+    }
+
+    Ok(proposal)
+}
+
+#[query]
+fn get_exchange_proposals() -> Vec<ExchangeProposal> {
+    EXCHANGE_PROPOSALS.with(|p| p.borrow().values().cloned().collect())
+}
+
+#[query]
+fn get_exchange_pairs() -> Vec<ExchangePair> {
+    EXCHANGE_PAIRS.with(|p| p.borrow().values().cloned().collect())
+}
+
+fn abo_compatible(donor: &str, recipient: &str) -> bool {
+    let donor = donor.trim_end_matches(['+', '-']);
+    let recipient = recipient.trim_end_matches(['+', '-']);
+    match donor {
+        "O" => true,
+        "A" => recipient == "A" || recipient == "AB",
+        "B" => recipient == "B" || recipient == "AB",
+        "AB" => recipient == "AB",
+        _ => false,
+    }
+}
+
+// Edge donor -> recipient when the donor of one pair can give to the recipient of another
+fn can_donate(donor: &ExchangePair, recipient: &ExchangePair) -> bool {
+    let (Some(recipient_id), Some(recipient_blood_type)) = (&recipient.recipient_id, &recipient.recipient_blood_type) else {
+        return false;
+    };
+    donor.pair_id != recipient.pair_id
+        && abo_compatible(&donor.donor_blood_type, recipient_blood_type)
+        && crossmatch::conflicts(&donor.donor_hla_typing, recipient_id).is_empty()
+}
+
+// compatible[d][r]: the donor of pool[d] can give to the recipient of pool[r], checked once per run
+fn compatibility(pool: &[ExchangePair]) -> Vec<Vec<bool>> {
+    pool.iter().map(|donor| pool.iter().map(|recipient| can_donate(donor, recipient)).collect()).collect()
+}
+
+// Adds a candidate while the budget lasts; a refusal ends the enumeration
+fn push_candidate(structures: &mut Vec<Structure>, candidate: Structure) -> bool {
+    if structures.len() >= MAX_CANDIDATE_STRUCTURES {
+        return false;
+    }
+    structures.push(candidate);
+    true
+}
+
+// 2-way and 3-way cycles only; longer cycles are too fragile to schedule simultaneously
+fn find_cycles(pool: &[ExchangePair], compatible: &[Vec<bool>], structures: &mut Vec<Structure>) {
+    let paired: Vec<usize> = (0..pool.len()).filter(|&i| pool[i].recipient_id.is_some()).collect();
+    let cycle = |members: &[usize]| ("CYCLE", members.iter().map(|&i| pool[i].pair_id.clone()).collect());
+
+    for (i, &a) in paired.iter().enumerate() {
+        for (j, &b) in paired.iter().enumerate().skip(i + 1) {
+            if compatible[a][b] && compatible[b][a] && !push_candidate(structures, cycle(&[a, b])) {
+                return;
+            }
+            // Anchor each 3-cycle on its lowest index so every cycle is listed once
+            for &c in paired.iter().skip(j + 1) {
+                if compatible[a][b] && compatible[b][c] && compatible[c][a] && !push_candidate(structures, cycle(&[a, b, c])) {
+                    return;
+                }
+                if compatible[a][c] && compatible[c][b] && compatible[b][a] && !push_candidate(structures, cycle(&[a, c, b])) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn find_chains(pool: &[ExchangePair], compatible: &[Vec<bool>], structures: &mut Vec<Structure>) {
+    for starter in (0..pool.len()).filter(|&i| pool[i].recipient_id.is_none()) {
+        if !extend_chain(pool, compatible, &mut vec![starter], structures) {
+            return;
+        }
+    }
+}
+
+// False once the candidate budget is spent, which unwinds the whole search
+fn extend_chain(pool: &[ExchangePair], compatible: &[Vec<bool>], path: &mut Vec<usize>, structures: &mut Vec<Structure>) -> bool {
+    if path.len() > 1 && !push_candidate(structures, ("CHAIN", path.iter().map(|&i| pool[i].pair_id.clone()).collect())) {
+        return false;
+    }
+    if path.len() > MAX_CHAIN_LENGTH {
+        return true;
+    }
+    let Some(&tail) = path.last() else {
+        return true;
+    };
+    for next in 0..pool.len() {
+        if pool[next].recipient_id.is_some() && !path.contains(&next) && compatible[tail][next] {
+            path.push(next);
+            let more = extend_chain(pool, compatible, path, structures);
+            path.pop();
+            if !more {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// Transplants performed: every pair in a cycle receives; a chain's starter only gives
+fn transplants(kind: &str, sequence: &[String]) -> usize {
+    if kind == "CHAIN" { sequence.len().saturating_sub(1) } else { sequence.len() }
+}
+
+// Disjoint set packing over the candidate structures; exact unless the step budget runs out first
+fn best_packing(structures: &[Structure]) -> Vec<Structure> {
+    struct Best {
+        total: usize,
+        chosen: Vec<usize>,
+        steps_left: usize,
+    }
+
+    fn search(
+        structures: &[Structure],
+        index: usize,
+        used: &mut Vec<String>,
+        chosen: &mut Vec<usize>,
+        best: &mut Best,
+        total: usize,
+        pool_size: usize
+    ) {
+        if total > best.total {
+            best.total = total;
+            best.chosen = chosen.clone();
+        }
+        // No packing can transplant more recipients than there are unused pairs
+        if total + pool_size.saturating_sub(used.len()) <= best.total || best.steps_left == 0 {
+            return;
+        }
+        best.steps_left -= 1;
+        for i in index..structures.len() {
+            let (kind, sequence) = &structures[i];
+            if sequence.iter().any(|p| used.contains(p)) {
+                continue;
+            }
+            used.extend(sequence.iter().cloned());
+            chosen.push(i);
+            search(structures, i + 1, used, chosen, best, total + transplants(kind, sequence), pool_size);
+            chosen.pop();
+            used.truncate(used.len() - sequence.len());
+        }
+    }
+
+    let mut pairs: Vec<&String> = structures.iter().flat_map(|(_, s)| s.iter()).collect();
+    pairs.sort();
+    pairs.dedup();
+
+    let mut best = Best { total: 0, chosen: Vec::new(), steps_left: MAX_PACKING_STEPS };
+    search(structures, 0, &mut Vec::new(), &mut Vec::new(), &mut best, 0, pairs.len());
+    best.chosen.into_iter().map(|i| structures[i].clone()).collect()
+}
+
+fn legs_for(pool: &[ExchangePair], kind: &str, sequence: &[String]) -> Vec<ExchangeLeg> {
+    let find = |id: &String| pool.iter().find(|p| &p.pair_id == id);
+    let steps = if kind == "CYCLE" { sequence.len() } else { sequence.len().saturating_sub(1) };
+
+    (0..steps)
+        .filter_map(|i| {
+            let donor = find(&sequence[i])?;
+            let recipient = find(&sequence[(i + 1) % sequence.len()])?;
+            Some(ExchangeLeg {
+                donor_pair_id: donor.pair_id.clone(),
+                recipient_pair_id: recipient.pair_id.clone(),
+                recipient_id: recipient.recipient_id.clone()?,
+                transplant_center: recipient.transplant_center.clone(),
+                response: None,
+            })
+        })
+        .collect()
+}
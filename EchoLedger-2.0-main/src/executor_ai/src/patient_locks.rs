@@ -0,0 +1,73 @@
+use ic_cdk::caller;
+use ic_cdk_macros::query;
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::clock;
+
+// Every await is a point where another message may run against the same state. Work that must
+// not interleave for one patient - an execution and a second execution, say - holds the
+// patient's lock across its awaits. The IC has nothing to wait on, so a second caller is turned
+// away rather than queued. The guard releases the lock when it is dropped, which ic-cdk also does
+// for a future abandoned by a trap. Nothing else releases it: a lock that is still held belongs to
+// a future still waiting on a reply, however long that takes, and reclaiming it would let a second
+// execution run alongside the first. An upgrade is the exception: a canister need not be stopped
+// first, and a call still waiting on its reply when the code is replaced never resumes, so its
+// guard is never dropped. Restoring upgraded state releases every lock.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PatientLock {
+    pub patient_id: String,
+    pub operation: String,
+    pub acquired_at: u64,
+}
+
+thread_local! {
+    static PATIENT_LOCKS: RefCell<BTreeMap<String, PatientLock>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+pub(crate) struct PatientGuard {
+    patient_id: String,
+}
+
+impl PatientGuard {
+    pub(crate) fn acquire(patient_id: &str, operation: &str) -> Result<PatientGuard, String> {
+        let now = clock::now();
+        PATIENT_LOCKS.with(|l| {
+            let mut locks = l.borrow_mut();
+            if let Some(held) = locks.get(patient_id) {
+                return Err(format!(
+                    "{} already in progress for patient {} (since {})",
+                    held.operation, patient_id, held.acquired_at
+                ));
+            }
+            locks.insert(patient_id.to_string(), PatientLock {
+                patient_id: patient_id.to_string(),
+                operation: operation.to_string(),
+                acquired_at: now,
+            });
+            Ok(PatientGuard { patient_id: patient_id.to_string() })
+        })
+    }
+}
+
+// Upgrades only: no future that held one of these survives to drop its guard
+pub(crate) fn release_all() {
+    PATIENT_LOCKS.with(|l| l.borrow_mut().clear());
+}
+
+impl Drop for PatientGuard {
+    fn drop(&mut self) {
+        PATIENT_LOCKS.with(|l| l.borrow_mut().remove(&self.patient_id));
+    }
+}
+
+#[query]
+fn get_patient_locks() -> Result<Vec<PatientLock>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may list patient locks".to_string());
+    }
+    Ok(PATIENT_LOCKS.with(|l| l.borrow().values().cloned().collect()))
+}
@@ -0,0 +1,319 @@
+use ic_cdk::{call, caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::evidence::ConsentDirective;
+use crate::{
+    audit, clock, derive_patient_hash, external_reference, governance, ids, resilience, traps, validation,
+    DIRECTIVE_MANAGER_CANISTER_ID,
+};
+
+// Consortium members attach their own post-death actions (a national registry update, say) by
+// registering a canister method against a directive type. Once an execution has completed, every
+// active plugin for a directive type on file is called with the directive, the patient known only
+// by a reference issued for that plugin. A plugin that fails is recorded and never undoes the
+// execution before it.
+//
+// The calls are made from a timer after the execution is committed, never from the execution
+// itself: ic-cdk 0.15 has no way to bound how long a call waits, and a plugin that never answers
+// must not hold an execution or its patient lock open. Their outcomes are on the invocation
+// records, not the execution record.
+//
+// A plugin method takes a PluginInvocation and returns variant { Ok: text; Err: text }, the Ok
+// text being the plugin's own receipt.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ExecutorPlugin {
+    pub plugin_id: String,
+    pub directive_type: String,
+    pub canister_id: Principal,
+    pub method: String,
+    pub description: String,
+    pub active: bool,
+}
+
+// The directive as the plugin sees it; the patient id is replaced by the plugin's reference
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PluginDirectivePayload {
+    pub directive_type: String,
+    pub status: String,
+    pub consent_items: Vec<String>,
+    pub timestamp: u64,
+    pub signature: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PluginInvocation {
+    pub invocation_id: String,
+    pub execution_id: String,
+    pub plugin_id: String,
+    pub patient_reference: String,
+    pub directive: PluginDirectivePayload,
+    pub invoked_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PluginInvocationRecord {
+    pub invocation_id: String,
+    pub execution_id: String,
+    pub plugin_id: String,
+    pub directive_type: String,
+    pub invoked_at: u64,
+    pub status: String, // "SUCCEEDED", "FAILED"
+    pub receipt: Option<String>,
+    pub error: Option<String>,
+}
+
+// A completed execution whose plugins have yet to be called
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PendingPluginRun {
+    pub execution_id: String,
+    pub patient_id: String,
+    pub directive_types: Vec<String>,
+}
+
+thread_local! {
+    static PLUGINS: RefCell<BTreeMap<String, ExecutorPlugin>> = const { RefCell::new(BTreeMap::new()) };
+    static INVOCATIONS: RefCell<BTreeMap<String, PluginInvocationRecord>> = const { RefCell::new(BTreeMap::new()) };
+    // execution_id -> run waiting for the timer
+    static PENDING_RUNS: RefCell<BTreeMap<String, PendingPluginRun>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+const MAX_PLUGINS: usize = 200;
+const MAX_INVOCATIONS: usize = 10_000;
+const MAX_PENDING_RUNS: usize = 1_000;
+const MAX_DESCRIPTION_BYTES: usize = 1024;
+const MAX_RECEIPT_BYTES: usize = 512;
+
+// Controllers, or the governance canister's proposals once it is exclusive
+#[update]
+fn register_executor_plugin(plugin: ExecutorPlugin) -> Result<(), String> {
+    governance::authorize_direct_change(caller())?;
+    let plugin_id = validation::identifier("plugin_id", &plugin.plugin_id)?.to_uppercase();
+    let directive_type = validation::identifier("directive_type", &plugin.directive_type)?.to_uppercase();
+    let method = validation::identifier("method", &plugin.method)?;
+    let description = validation::text("description", &plugin.description, MAX_DESCRIPTION_BYTES)?;
+    if plugin.canister_id == ic_cdk::id() {
+        return Err(validation::invalid("canister_id", "a plugin cannot call back into executor_ai"));
+    }
+    PLUGINS.with(|p| {
+        let mut plugins = p.borrow_mut();
+        if plugins.len() >= MAX_PLUGINS && !plugins.contains_key(&plugin_id) {
+            return Err("Too many executor plugins are registered".to_string());
+        }
+        plugins.insert(plugin_id.clone(), ExecutorPlugin { plugin_id: plugin_id.clone(), directive_type, method, description, ..plugin });
+        Ok(())
+    })?;
+    audit::append_audit_entry("EXECUTOR_PLUGIN_REGISTERED", &plugin_id, caller().as_slice());
+    Ok(())
+}
+
+#[update]
+fn set_executor_plugin_active(plugin_id: String, active: bool) -> Result<(), String> {
+    governance::authorize_direct_change(caller())?;
+    PLUGINS.with(|p| {
+        let mut plugins = p.borrow_mut();
+        let plugin = plugins.get_mut(&plugin_id).ok_or_else(|| format!("Unknown executor plugin: {}", plugin_id))?;
+        plugin.active = active;
+        Ok(())
+    })
+}
+
+#[query]
+fn get_executor_plugins() -> Vec<ExecutorPlugin> {
+    PLUGINS.with(|p| p.borrow().values().cloned().collect())
+}
+
+#[query]
+fn get_plugin_invocations(execution_id: String) -> Vec<PluginInvocationRecord> {
+    INVOCATIONS.with(|i| i.borrow().values().filter(|x| x.execution_id == execution_id).cloned().collect())
+}
+
+// Called once the execution's completion is recorded; the plugins run from a timer, outside it
+pub(crate) fn schedule(patient_id: &str, execution_id: &str, directive_types: Vec<String>) {
+    if active_plugins(&directive_types).is_empty() {
+        return;
+    }
+    let queued = PENDING_RUNS.with(|p| {
+        let mut pending = p.borrow_mut();
+        if pending.len() >= MAX_PENDING_RUNS {
+            return false;
+        }
+        pending.insert(execution_id.to_string(), PendingPluginRun {
+            execution_id: execution_id.to_string(),
+            patient_id: patient_id.to_string(),
+            directive_types,
+        });
+        true
+    });
+    if !queued {
+        ic_cdk::println!("⚠️ Executor plugins not queued for {}: too many runs pending", execution_id);
+        audit::append_audit_entry("EXECUTOR_PLUGINS_DROPPED", execution_id, patient_id.as_bytes());
+        return;
+    }
+    arm_timer();
+}
+
+// Timers do not survive an upgrade; runs queued before it are picked up after
+pub(crate) fn ensure_pending_runs() {
+    if PENDING_RUNS.with(|p| !p.borrow().is_empty()) {
+        arm_timer();
+    }
+}
+
+fn arm_timer() {
+    ic_cdk_timers::set_timer(Duration::ZERO, start_pending_runs);
+}
+
+// Each run is its own task, so a plugin that never answers holds up only the run it belongs to.
+// A run leaves the queue as it starts; its invocations are recorded one by one as they finish.
+fn start_pending_runs() {
+    let runs: Vec<PendingPluginRun> = PENDING_RUNS.with(|p| std::mem::take(&mut *p.borrow_mut()).into_values().collect());
+    for run in runs {
+        ic_cdk::spawn(async move {
+            let _watch = traps::Watch::start("PLUGINS");
+            run_plugins(&run.patient_id, &run.execution_id, &run.directive_types).await;
+        });
+    }
+}
+
+fn active_plugins(directive_types: &[String]) -> BTreeMap<String, Vec<ExecutorPlugin>> {
+    PLUGINS.with(|p| {
+        let mut by_type: BTreeMap<String, Vec<ExecutorPlugin>> = BTreeMap::new();
+        for plugin in p.borrow().values().filter(|x| x.active && directive_types.contains(&x.directive_type)) {
+            by_type.entry(plugin.directive_type.clone()).or_default().push(plugin.clone());
+        }
+        by_type
+    })
+}
+
+async fn run_plugins(patient_id: &str, execution_id: &str, directive_types: &[String]) {
+    // Read again as the run starts: a plugin deactivated while the run waited is not called
+    let by_type = active_plugins(directive_types);
+    if by_type.is_empty() {
+        return;
+    }
+    let (versions, patient_id_hash) = match load_directive_versions(patient_id).await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            ic_cdk::println!("⚠️ Executor plugins skipped for {}: {}", execution_id, e);
+            audit::append_audit_entry("EXECUTOR_PLUGINS_SKIPPED", execution_id, e.as_bytes());
+            return;
+        }
+    };
+
+    for (directive_type, plugins) in by_type {
+        let Some(directive) = versions.iter().rev().find(|d| d.directive_type == directive_type) else {
+            ic_cdk::println!("⚠️ No {} directive on file for plugins in {}", directive_type, execution_id);
+            continue;
+        };
+        let mut succeeded = Vec::new();
+        for plugin in &plugins {
+            let record = invoke(plugin, execution_id, directive, &patient_id_hash).await;
+            if record.status == "SUCCEEDED" {
+                succeeded.push(plugin.plugin_id.clone());
+            }
+        }
+        ic_cdk::println!(
+            "🔌 {} of {} {} plugins succeeded for {}",
+            succeeded.len(), plugins.len(), directive_type, execution_id
+        );
+    }
+}
+
+async fn invoke(
+    plugin: &ExecutorPlugin,
+    execution_id: &str,
+    directive: &ConsentDirective,
+    patient_id_hash: &[u8]
+) -> PluginInvocationRecord {
+    let invocation_id = ids::new_id("PLUGINCALL");
+    let invoked_at = clock::now();
+    let outcome = async {
+        let patient_reference = external_reference(patient_id_hash, &format!("plugin:{}", plugin.plugin_id)).await?;
+        let invocation = PluginInvocation {
+            invocation_id: invocation_id.clone(),
+            execution_id: execution_id.to_string(),
+            plugin_id: plugin.plugin_id.clone(),
+            patient_reference,
+            directive: PluginDirectivePayload {
+                directive_type: directive.directive_type.clone(),
+                status: directive.status.clone(),
+                consent_items: directive.consent_items.clone(),
+                timestamp: directive.timestamp,
+                signature: directive.signature.clone(),
+            },
+            invoked_at,
+        };
+        let (result,): (Result<String, String>,) = resilience::guarded_call(&format!("plugin:{}", plugin.plugin_id), || {
+            call(plugin.canister_id, &plugin.method, (invocation.clone(),))
+        }).await?;
+        result
+    }.await;
+
+    let (status, receipt, error) = match outcome {
+        Ok(receipt) => ("SUCCEEDED", Some(receipt.chars().take(MAX_RECEIPT_BYTES).collect()), None),
+        Err(e) => ("FAILED", None, Some(e)),
+    };
+    let record = PluginInvocationRecord {
+        invocation_id,
+        execution_id: execution_id.to_string(),
+        plugin_id: plugin.plugin_id.clone(),
+        directive_type: directive.directive_type.clone(),
+        invoked_at,
+        status: status.to_string(),
+        receipt,
+        error,
+    };
+    if let Ok(payload) = serde_json::to_vec(&record) {
+        audit::append_audit_entry("EXECUTOR_PLUGIN_INVOKED", &record.invocation_id, &payload);
+    }
+    INVOCATIONS.with(|i| {
+        let mut invocations = i.borrow_mut();
+        if invocations.len() >= MAX_INVOCATIONS {
+            // Ids sort by creation, so the first is the oldest
+            invocations.pop_first();
+        }
+        invocations.insert(record.invocation_id.clone(), record.clone());
+    });
+    record
+}
+
+// Every version of the patient's directives, oldest first, and the keyed hash
+async fn load_directive_versions(patient_id: &str) -> Result<(Vec<ConsentDirective>, Vec<u8>), String> {
+    let directive_manager_id = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID")?;
+    let (versions,): (Vec<ConsentDirective>,) = resilience::guarded_call(resilience::DIRECTIVE_MANAGER, || {
+        call(directive_manager_id, "get_directive_versions", (patient_id.to_string(),))
+    }).await.map_err(|msg| format!("Failed to load directive versions: {}", msg))?;
+    Ok((versions, derive_patient_hash(patient_id).await?))
+}
+
+// Carried across upgrades with the event log
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct PluginsState {
+    plugins: BTreeMap<String, ExecutorPlugin>,
+    invocations: BTreeMap<String, PluginInvocationRecord>,
+    pending_runs: Option<BTreeMap<String, PendingPluginRun>>,
+}
+
+pub(crate) fn snapshot() -> PluginsState {
+    PluginsState {
+        plugins: PLUGINS.with(|p| p.borrow().clone()),
+        invocations: INVOCATIONS.with(|i| i.borrow().clone()),
+        pending_runs: Some(PENDING_RUNS.with(|p| p.borrow().clone())),
+    }
+}
+
+pub(crate) fn restore(state: Option<PluginsState>) {
+    let Some(state) = state else {
+        return;
+    };
+    PLUGINS.with(|p| *p.borrow_mut() = state.plugins);
+    INVOCATIONS.with(|i| *i.borrow_mut() = state.invocations);
+    PENDING_RUNS.with(|p| *p.borrow_mut() = state.pending_runs.unwrap_or_default());
+}
@@ -0,0 +1,222 @@
+use crate::custody::{CustodyRecord, TelemetryAck, TelemetryAlert, TelemetryPush, TelemetryReading};
+use crate::validation;
+
+// Proto3 wire format for the messages in executor_ai.proto. The gateway only needs scalars,
+// strings and nested messages, so this is written out by hand rather than generated.
+
+pub(crate) const CONTENT_TYPE: &str = "application/x-protobuf";
+
+const VARINT: u64 = 0;
+const FIXED64: u64 = 1;
+const LENGTH_DELIMITED: u64 = 2;
+const FIXED32: u64 = 5;
+
+pub(crate) fn decode_push(body: &[u8]) -> Result<TelemetryPush, String> {
+    let mut push = TelemetryPush {
+        custody_id: String::new(),
+        device_id: String::new(),
+        recorded_at: 0,
+        temperature_c: 0.0,
+        perfusion_pressure_mmhg: None,
+    };
+    let mut reader = Reader { buf: body, pos: 0 };
+    while let Some((field, value)) = reader.next_field()? {
+        match field {
+            1 => push.custody_id = value.string("custody_id")?,
+            2 => push.device_id = value.string("device_id")?,
+            3 => push.recorded_at = value.uint64("recorded_at")?,
+            4 => push.temperature_c = value.float("temperature_c")?,
+            5 => push.perfusion_pressure_mmhg = Some(value.float("perfusion_pressure_mmhg")?),
+            _ => {} // fields added by newer schema versions
+        }
+    }
+    Ok(push)
+}
+
+pub(crate) fn encode_ack(ack: &TelemetryAck) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.uint64(1, ack.status_code as u64);
+    w.string(2, &ack.message);
+    for alert in &ack.alerts {
+        w.message(3, &encode_alert(alert));
+    }
+    w.buf
+}
+
+pub(crate) fn encode_custody_record(record: &CustodyRecord) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.string(1, &record.custody_id);
+    w.string(2, &record.donor_id);
+    w.string(3, &record.organ_type);
+    w.string(4, &record.recipient_id);
+    w.string(5, &record.device_id);
+    w.string(6, &record.status);
+    w.uint64(7, record.opened_at);
+    if let Some(closed_at) = record.closed_at {
+        w.key(8, VARINT);
+        w.varint(closed_at);
+    }
+    for reading in &record.readings {
+        w.message(9, &encode_reading(reading));
+    }
+    for alert in &record.alerts {
+        w.message(10, &encode_alert(alert));
+    }
+    w.buf
+}
+
+fn encode_alert(alert: &TelemetryAlert) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.string(1, &alert.metric);
+    w.float(2, alert.value);
+    w.float(3, alert.acceptable_min);
+    w.float(4, alert.acceptable_max);
+    w.uint64(5, alert.recorded_at);
+    w.buf
+}
+
+fn encode_reading(reading: &TelemetryReading) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.string(1, &reading.device_id);
+    w.uint64(2, reading.recorded_at);
+    w.float(3, reading.temperature_c);
+    if let Some(pressure) = reading.perfusion_pressure_mmhg {
+        w.fixed32(4, pressure);
+    }
+    w.uint64(5, reading.received_at);
+    w.buf
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+// Scalars at their default value are left off the wire, as proto3 does; fields declared
+// `optional` are written by the caller whenever they are present
+impl Writer {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u64) {
+        self.varint(((field as u64) << 3) | wire_type);
+    }
+
+    fn uint64(&mut self, field: u32, value: u64) {
+        if value != 0 {
+            self.key(field, VARINT);
+            self.varint(value);
+        }
+    }
+
+    fn float(&mut self, field: u32, value: f32) {
+        if value != 0.0 {
+            self.fixed32(field, value);
+        }
+    }
+
+    fn fixed32(&mut self, field: u32, value: f32) {
+        self.key(field, FIXED32);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, field: u32, value: &str) {
+        if !value.is_empty() {
+            self.message(field, value.as_bytes());
+        }
+    }
+
+    // Repeated entries are written even when empty, or they would drop out of the list
+    fn message(&mut self, field: u32, encoded: &[u8]) {
+        self.key(field, LENGTH_DELIMITED);
+        self.varint(encoded.len() as u64);
+        self.buf.extend_from_slice(encoded);
+    }
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Fixed64, // no message here has a 64-bit fixed field; only skipped
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl Value<'_> {
+    fn uint64(&self, field: &str) -> Result<u64, String> {
+        match self {
+            Value::Varint(v) => Ok(*v),
+            _ => Err(validation::invalid(field, "expected a varint")),
+        }
+    }
+
+    fn float(&self, field: &str) -> Result<f32, String> {
+        match self {
+            Value::Fixed32(bits) => Ok(f32::from_bits(*bits)),
+            _ => Err(validation::invalid(field, "expected a 32-bit float")),
+        }
+    }
+
+    fn string(&self, field: &str) -> Result<String, String> {
+        match self {
+            Value::Bytes(bytes) => String::from_utf8(bytes.to_vec()).map_err(|_| validation::invalid(field, "not valid UTF-8")),
+            _ => Err(validation::invalid(field, "expected a length-delimited string")),
+        }
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn next_field(&mut self) -> Result<Option<(u32, Value<'a>)>, String> {
+        if self.pos >= self.buf.len() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = u32::try_from(key >> 3).ok().filter(|f| *f != 0).ok_or("Malformed protobuf: bad field number")?;
+        let value = match key & 0x7 {
+            VARINT => Value::Varint(self.varint()?),
+            FIXED64 => {
+                self.take(8)?;
+                Value::Fixed64
+            }
+            LENGTH_DELIMITED => {
+                let len = usize::try_from(self.varint()?).map_err(|_| "Malformed protobuf: length overflow")?;
+                Value::Bytes(self.take(len)?)
+            }
+            FIXED32 => Value::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap_or_default())),
+            other => return Err(format!("Malformed protobuf: unsupported wire type {}", other)),
+        };
+        Ok(Some((field, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self.buf.get(self.pos).ok_or("Malformed protobuf: truncated varint")?;
+            self.pos += 1;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Malformed protobuf: varint longer than 10 bytes".to_string())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos
+            .checked_add(len)
+            .filter(|end| *end <= self.buf.len())
+            .ok_or("Malformed protobuf: truncated field")?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+}
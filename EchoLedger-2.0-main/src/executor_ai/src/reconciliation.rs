@@ -0,0 +1,276 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::{
+    audit, body_donation, clock, custody, digital_legacy, disposition, events, ids, tissue, ContactNotification,
+    EXECUTION_HISTORY,
+};
+
+// Most of what this canister sends goes out fire-and-forget: an offer that never arrived or a
+// report nobody picked up looks the same as one that did until someone asks. Once a day every
+// message sent in one day is reconciled against what came back, each one counted as sent,
+// delivered and acted on, and the ones that stopped short are listed. The day reconciled ends
+// SETTLE_HOURS before the run, so partners have had their usual time to answer.
+
+// One outbound message as the module that sent it last saw it
+pub(crate) struct Outbound {
+    pub(crate) channel: &'static str,
+    pub(crate) reference_id: String,
+    pub(crate) counterparty: String,
+    pub(crate) sent_at: u64,
+    pub(crate) delivered: bool,
+    pub(crate) acted: bool,
+    pub(crate) detail: Option<String>, // why it was not delivered, when that is known
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReconciliationDiscrepancy {
+    pub channel: String,
+    pub reference_id: String,
+    pub counterparty: String,
+    pub sent_at: u64,
+    pub kind: String, // "SENT_NOT_DELIVERED", "DELIVERED_NOT_ACTED"
+    pub detail: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ChannelReconciliation {
+    pub channel: String,
+    pub sent: u32,
+    pub delivered: u32,
+    pub acted: u32,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReconciliationReport {
+    pub report_id: String,
+    pub generated_at: u64,
+    pub window_start: u64,
+    pub window_end: u64,
+    pub channels: Vec<ChannelReconciliation>,
+    pub discrepancies: Vec<ReconciliationDiscrepancy>,
+    pub discrepancies_truncated: bool,
+}
+
+// What notify_contacts answered for one execution's next-of-kin notice
+#[derive(CandidType, Deserialize, Clone)]
+struct ContactNotice {
+    sent_at: u64,
+    outcome: Result<Vec<(String, String)>, String>, // (notification_id, contact_id) per contact
+}
+
+// Carried across upgrades with the event log
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct ReconciliationState {
+    reports: Vec<ReconciliationReport>,
+    contact_notices: Vec<(String, ContactNotice)>,
+}
+
+const SETTLE_HOURS: u64 = 24;
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const RECONCILIATION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_REPORTS: usize = 90;
+const MAX_DISCREPANCIES_PER_REPORT: usize = 1_000;
+const MAX_CONTACT_NOTICES: usize = 10_000;
+
+thread_local! {
+    static REPORTS: RefCell<Vec<ReconciliationReport>> = const { RefCell::new(Vec::new()) };
+    // execution_id -> the next-of-kin notice; ids sort by age
+    static CONTACT_NOTICES: RefCell<BTreeMap<String, ContactNotice>> = const { RefCell::new(BTreeMap::new()) };
+    static RECONCILIATION_TIMER_STARTED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// Reconciles the most recent settled day now, outside the schedule
+#[update]
+fn run_reconciliation() -> Result<ReconciliationReport, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may run reconciliation".to_string());
+    }
+    Ok(reconcile())
+}
+
+#[query]
+fn get_reconciliation_reports() -> Result<Vec<ReconciliationReport>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read reconciliation reports".to_string());
+    }
+    Ok(REPORTS.with(|r| r.borrow().clone()))
+}
+
+pub(crate) fn ensure_reconciliation_timer() {
+    if RECONCILIATION_TIMER_STARTED.with(|s| s.replace(true)) {
+        return;
+    }
+    ic_cdk_timers::set_timer_interval(RECONCILIATION_INTERVAL, || {
+        reconcile();
+    });
+}
+
+// notify_next_of_kin reports what directive_manager made of each notice
+pub(crate) fn record_contact_notices(execution_id: &str, outcome: Result<Vec<ContactNotification>, String>) {
+    let notice = ContactNotice {
+        sent_at: clock::now(),
+        outcome: outcome.map(|notifications| {
+            notifications.into_iter().map(|n| (n.notification_id, n.contact_id)).collect()
+        }),
+    };
+    CONTACT_NOTICES.with(|c| {
+        let mut notices = c.borrow_mut();
+        if notices.len() >= MAX_CONTACT_NOTICES {
+            notices.pop_first();
+        }
+        notices.insert(execution_id.to_string(), notice);
+    });
+}
+
+fn reconcile() -> ReconciliationReport {
+    let generated_at = clock::now();
+    let window_end = generated_at.saturating_sub(SETTLE_HOURS * NANOS_PER_HOUR);
+    let window_start = window_end.saturating_sub(24 * NANOS_PER_HOUR);
+
+    let mut outbound = organ_offers(window_start, window_end);
+    outbound.extend(next_of_kin_notices(window_start, window_end));
+    outbound.extend(tissue::outbound_between(window_start, window_end));
+    outbound.extend(body_donation::outbound_between(window_start, window_end));
+    outbound.extend(digital_legacy::outbound_between(window_start, window_end));
+    outbound.extend(disposition::outbound_between(window_start, window_end));
+
+    let mut channels: BTreeMap<&str, ChannelReconciliation> = BTreeMap::new();
+    let mut discrepancies = Vec::new();
+    for item in outbound {
+        let tally = channels.entry(item.channel).or_insert_with(|| ChannelReconciliation {
+            channel: item.channel.to_string(),
+            sent: 0,
+            delivered: 0,
+            acted: 0,
+        });
+        tally.sent += 1;
+        tally.delivered += item.delivered as u32;
+        tally.acted += (item.delivered && item.acted) as u32;
+        let kind = match (item.delivered, item.acted) {
+            (false, _) => "SENT_NOT_DELIVERED",
+            (true, false) => "DELIVERED_NOT_ACTED",
+            (true, true) => continue,
+        };
+        discrepancies.push(ReconciliationDiscrepancy {
+            channel: item.channel.to_string(),
+            reference_id: item.reference_id,
+            counterparty: item.counterparty,
+            sent_at: item.sent_at,
+            kind: kind.to_string(),
+            detail: item.detail,
+        });
+    }
+    let discrepancies_truncated = discrepancies.len() > MAX_DISCREPANCIES_PER_REPORT;
+    discrepancies.truncate(MAX_DISCREPANCIES_PER_REPORT);
+
+    let report = ReconciliationReport {
+        report_id: ids::new_id("RECON"),
+        generated_at,
+        window_start,
+        window_end,
+        channels: channels.into_values().collect(),
+        discrepancies,
+        discrepancies_truncated,
+    };
+    if !report.discrepancies.is_empty() {
+        ic_cdk::println!("⚠️ Reconciliation {}: {} discrepancies", report.report_id, report.discrepancies.len());
+    }
+    if let Ok(payload) = serde_json::to_vec(&report) {
+        audit::append_audit_entry("RECONCILIATION_REPORT", &report.report_id, &payload);
+    }
+    REPORTS.with(|r| {
+        let mut reports = r.borrow_mut();
+        if reports.len() >= MAX_REPORTS {
+            reports.remove(0);
+        }
+        reports.push(report.clone());
+    });
+    // Notices older than the window just closed will never be reconciled again
+    CONTACT_NOTICES.with(|c| c.borrow_mut().retain(|_, notice| notice.sent_at >= window_start));
+    report
+}
+
+// An offer is acted on once the organ goes into custody; retracted offers are settled
+fn organ_offers(from: u64, to: u64) -> Vec<Outbound> {
+    let mut outbound = Vec::new();
+    for (completed_at, execution_id) in events::completed_between(from, to) {
+        let Some(execution) = EXECUTION_HISTORY.with(|h| h.borrow().get(&execution_id).cloned()) else {
+            continue;
+        };
+        if execution.consent_retractions.iter().any(|x| x.consent_type == "ORGAN_DONATION") {
+            continue;
+        }
+        let offers = execution.directives_executed.iter()
+            .filter(|d| d.directive_type == "ORGAN_DONATION")
+            .flat_map(|d| d.recipient_matches.iter());
+        for offer in offers {
+            outbound.push(Outbound {
+                channel: "ORGAN_OFFER",
+                reference_id: format!("{}:{}", execution.execution_id, offer.organ),
+                counterparty: offer.transplant_center.clone(),
+                sent_at: completed_at,
+                delivered: offer.notification_sent,
+                acted: custody::custody_opened(&execution.patient_id, &offer.organ),
+                detail: None,
+            });
+        }
+    }
+    outbound
+}
+
+// A notice is acted on once the contact acknowledges it
+fn next_of_kin_notices(from: u64, to: u64) -> Vec<Outbound> {
+    let notices: Vec<(String, ContactNotice)> = CONTACT_NOTICES.with(|c| {
+        c.borrow().iter().filter(|(_, n)| n.sent_at >= from && n.sent_at < to).map(|(id, n)| (id.clone(), n.clone())).collect()
+    });
+    let mut outbound = Vec::new();
+    for (execution_id, notice) in notices {
+        let acknowledged: Vec<String> = EXECUTION_HISTORY.with(|h| {
+            h.borrow().get(&execution_id).map_or_else(Vec::new, |x| {
+                x.contact_acknowledgments.iter().map(|a| a.notification_id.clone()).collect()
+            })
+        });
+        match notice.outcome {
+            Ok(notifications) => outbound.extend(notifications.into_iter().map(|(notification_id, contact_id)| Outbound {
+                channel: "NEXT_OF_KIN_NOTICE",
+                acted: acknowledged.contains(&notification_id),
+                reference_id: notification_id,
+                counterparty: contact_id,
+                sent_at: notice.sent_at,
+                delivered: true,
+                detail: None,
+            })),
+            Err(e) => outbound.push(Outbound {
+                channel: "NEXT_OF_KIN_NOTICE",
+                reference_id: execution_id,
+                counterparty: "directive_manager".to_string(),
+                sent_at: notice.sent_at,
+                delivered: false,
+                acted: false,
+                detail: Some(e),
+            }),
+        }
+    }
+    outbound
+}
+
+pub(crate) fn snapshot() -> ReconciliationState {
+    ReconciliationState {
+        reports: REPORTS.with(|r| r.borrow().clone()),
+        contact_notices: CONTACT_NOTICES.with(|c| c.borrow().iter().map(|(id, n)| (id.clone(), n.clone())).collect()),
+    }
+}
+
+pub(crate) fn restore(state: Option<ReconciliationState>) {
+    let Some(state) = state else {
+        return;
+    };
+    REPORTS.with(|r| *r.borrow_mut() = state.reports);
+    CONTACT_NOTICES.with(|c| *c.borrow_mut() = state.contact_notices.into_iter().collect());
+}
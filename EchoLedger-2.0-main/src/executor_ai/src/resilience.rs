@@ -0,0 +1,241 @@
+use ic_cdk::api::call::{CallResult, RejectionCode};
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::future::Future;
+
+use crate::{clock, traps};
+
+// Health of one downstream dependency ("directive_manager", "network:UNOS", ...)
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CircuitBreaker {
+    pub target: String,
+    pub state: String, // "CLOSED", "OPEN", "HALF_OPEN"
+    pub consecutive_failures: u32,
+    pub opened_at: Option<u64>,
+    pub probe_in_flight: bool,
+    pub total_calls: u64,
+    pub total_failures: u64,
+    pub total_retries: u64,
+    pub short_circuited: u64,
+    pub degraded_responses: u64,
+    pub last_error: Option<String>,
+    pub last_latency_ms: u64,
+}
+
+thread_local! {
+    static BREAKERS: RefCell<BTreeMap<String, CircuitBreaker>> = RefCell::new(BTreeMap::new());
+}
+
+pub(crate) const DIRECTIVE_MANAGER: &str = "directive_manager";
+pub(crate) const LLM_CANISTER: &str = "llm_canister";
+
+const MAX_ATTEMPTS: u32 = 3;
+// Consensus rounds to wait before the 2nd and 3rd attempt
+const BACKOFF_ROUNDS: [u32; 2] = [1, 2];
+// No retry starts once this much time has passed since the first attempt
+const RETRY_DEADLINE_NANOS: u64 = 60 * 1_000_000_000;
+// A reply slower than this is used, but counts against the target as a timeout
+const SLOW_CALL_NANOS: u64 = 30 * 1_000_000_000;
+const FAILURE_THRESHOLD: u32 = 5;
+// How long an open breaker refuses calls before letting one probe through
+const OPEN_NANOS: u64 = 60 * 1_000_000_000;
+
+#[query]
+fn get_circuit_breakers() -> Vec<CircuitBreaker> {
+    BREAKERS.with(|b| b.borrow().values().cloned().collect())
+}
+
+#[update]
+fn reset_circuit_breaker(target: String) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may reset circuit breakers".to_string());
+    }
+    with_breaker(&target, |breaker| {
+        breaker.state = "CLOSED".to_string();
+        breaker.consecutive_failures = 0;
+        breaker.opened_at = None;
+        breaker.probe_in_flight = false;
+    });
+    Ok(())
+}
+
+// Runs a call against `target`, retrying transient rejections with backoff. SysTransient means the
+// message never reached the callee, so retrying is safe even for updates. Once the target has
+// failed FAILURE_THRESHOLD times in a row its breaker opens and calls fail fast until it recovers.
+pub(crate) async fn guarded_call<T, F, Fut>(target: &str, mut attempt: F) -> Result<T, String>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = CallResult<T>>,
+{
+    let _probe = admit(target)?;
+
+    let first_started = clock::now();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let started = clock::now();
+        let result = attempt().await;
+        let elapsed = clock::now().saturating_sub(started);
+
+        match result {
+            Ok(value) => {
+                if elapsed > SLOW_CALL_NANOS {
+                    record_failure(target, elapsed, format!("Reply took {} ms", elapsed / 1_000_000));
+                } else {
+                    record_success(target, elapsed);
+                }
+                return Ok(value);
+            }
+            Err((RejectionCode::SysTransient, msg))
+                if attempts < MAX_ATTEMPTS
+                    && clock::now().saturating_sub(first_started) < RETRY_DEADLINE_NANOS =>
+            {
+                ic_cdk::println!("🔁 {} transient failure (attempt {}): {}", target, attempts, msg);
+                with_breaker(target, |breaker| breaker.total_retries += 1);
+                wait_rounds(BACKOFF_ROUNDS[attempts as usize - 1]).await;
+            }
+            Err((code, msg)) => {
+                if code == RejectionCode::CanisterError {
+                    traps::record_callee_trap(target);
+                }
+                let error = format!("{:?}: {}", code, msg);
+                record_failure(target, elapsed, error.clone());
+                return Err(error);
+            }
+        }
+    }
+}
+
+// As guarded_call, but answers with `degraded` instead of an error so callers can carry on
+pub(crate) async fn guarded_call_or<T, F, Fut>(target: &str, attempt: F, degraded: T) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = CallResult<T>>,
+{
+    match guarded_call(target, attempt).await {
+        Ok(value) => value,
+        Err(msg) => {
+            ic_cdk::println!("⚠️ {} unavailable, serving degraded response: {}", target, msg);
+            with_breaker(target, |breaker| breaker.degraded_responses += 1);
+            degraded
+        }
+    }
+}
+
+// Held for the length of a guarded call. A probe's outcome normally clears probe_in_flight; if the
+// call's future is dropped first - the caller trapped after an await - nothing else would, and the
+// breaker would turn every call away for good. The probe then counts as failed and the cool-down
+// starts again.
+struct ProbeGuard {
+    target: String,
+    probe: bool,
+}
+
+impl Drop for ProbeGuard {
+    fn drop(&mut self) {
+        if !self.probe {
+            return;
+        }
+        let now = clock::now();
+        with_breaker(&self.target, |breaker| {
+            if breaker.state == "HALF_OPEN" && breaker.probe_in_flight {
+                breaker.probe_in_flight = false;
+                breaker.state = "OPEN".to_string();
+                breaker.opened_at = Some(now);
+                breaker.last_error = Some("Probe abandoned before it completed".to_string());
+            }
+        });
+    }
+}
+
+fn admit(target: &str) -> Result<ProbeGuard, String> {
+    let now = clock::now();
+    with_breaker(target, |breaker| {
+        let mut probe = false;
+        match breaker.state.as_str() {
+            "OPEN" if breaker.opened_at.is_some_and(|at| now.saturating_sub(at) >= OPEN_NANOS) => {
+                // Cool-down over: let a single probe decide whether the target has recovered
+                breaker.state = "HALF_OPEN".to_string();
+                breaker.probe_in_flight = true;
+                probe = true;
+            }
+            "OPEN" => {
+                breaker.short_circuited += 1;
+                return Err(format!("Circuit breaker for {} is open", target));
+            }
+            "HALF_OPEN" if breaker.probe_in_flight => {
+                breaker.short_circuited += 1;
+                return Err(format!("Circuit breaker for {} is waiting on a probe", target));
+            }
+            "HALF_OPEN" => {
+                breaker.probe_in_flight = true;
+                probe = true;
+            }
+            _ => {}
+        }
+        breaker.total_calls += 1;
+        Ok(ProbeGuard { target: target.to_string(), probe })
+    })
+}
+
+fn record_success(target: &str, elapsed: u64) {
+    with_breaker(target, |breaker| {
+        breaker.state = "CLOSED".to_string();
+        breaker.consecutive_failures = 0;
+        breaker.opened_at = None;
+        breaker.probe_in_flight = false;
+        breaker.last_latency_ms = elapsed / 1_000_000;
+    });
+}
+
+fn record_failure(target: &str, elapsed: u64, error: String) {
+    with_breaker(target, |breaker| {
+        breaker.consecutive_failures += 1;
+        breaker.total_failures += 1;
+        breaker.last_latency_ms = elapsed / 1_000_000;
+        breaker.last_error = Some(error);
+        let failed_probe = breaker.state == "HALF_OPEN";
+        breaker.probe_in_flight = false;
+        if failed_probe || breaker.consecutive_failures >= FAILURE_THRESHOLD {
+            if breaker.state != "OPEN" {
+                ic_cdk::println!("🚨 Circuit breaker for {} opened after {} failures", target, breaker.consecutive_failures);
+            }
+            breaker.state = "OPEN".to_string();
+            breaker.opened_at = Some(clock::now());
+        }
+    });
+}
+
+// A timer cannot be awaited from within a call, so each round trip to the management canister
+// stands in for one consensus round of backoff
+async fn wait_rounds(rounds: u32) {
+    for _ in 0..rounds {
+        let _ = raw_rand().await;
+    }
+}
+
+fn with_breaker<R>(target: &str, f: impl FnOnce(&mut CircuitBreaker) -> R) -> R {
+    BREAKERS.with(|b| {
+        let mut breakers = b.borrow_mut();
+        let breaker = breakers.entry(target.to_string()).or_insert_with(|| CircuitBreaker {
+            target: target.to_string(),
+            state: "CLOSED".to_string(),
+            consecutive_failures: 0,
+            opened_at: None,
+            probe_in_flight: false,
+            total_calls: 0,
+            total_failures: 0,
+            total_retries: 0,
+            short_circuited: 0,
+            degraded_responses: 0,
+            last_error: None,
+            last_latency_ms: 0,
+        });
+        f(breaker)
+    })
+}
@@ -0,0 +1,349 @@
+use super::*;
+
+use canister_clock::at_hours as at;
+
+// Canister messages are simulated by holding guards the way an execution holds one across its awaits
+#[test]
+fn second_execution_for_a_patient_is_refused_while_the_first_is_suspended() {
+    at(0);
+    let first = patient_locks::PatientGuard::acquire("patient_lock_001", "EXECUTION").unwrap();
+
+    let second = patient_locks::PatientGuard::acquire("patient_lock_001", "EXECUTION");
+    let error = second.err().unwrap();
+    assert!(error.contains("EXECUTION already in progress"));
+
+    drop(first);
+    assert!(patient_locks::PatientGuard::acquire("patient_lock_001", "EXECUTION").is_ok());
+}
+
+#[test]
+fn held_lock_is_never_reclaimed_while_its_future_is_pending() {
+    at(0);
+    let _first = patient_locks::PatientGuard::acquire("patient_lock_002", "EXECUTION").unwrap();
+
+    // A plugin or outcall await may take far longer than any timeout would have allowed
+    at(48);
+    assert!(patient_locks::PatientGuard::acquire("patient_lock_002", "EXECUTION").is_err());
+    assert!(patient_locks::PatientGuard::acquire("patient_lock_002", "EVIDENCE_EXPORT").is_err());
+}
+
+#[test]
+fn other_patients_proceed_alongside_a_held_lock() {
+    at(0);
+    let _first = patient_locks::PatientGuard::acquire("patient_lock_003", "EXECUTION").unwrap();
+    assert!(patient_locks::PatientGuard::acquire("patient_lock_004", "EXECUTION").is_ok());
+}
+
+#[test]
+fn untyped_upheld_objection_blocks_every_step() {
+    let upheld = vec![None];
+    assert!(is_blocked(&upheld, "ORGAN_DONATION"));
+    assert!(is_blocked(&upheld, "DATA_CONSENT"));
+}
+
+#[test]
+fn typed_upheld_objection_blocks_only_its_step() {
+    let upheld = vec![Some("ORGAN_DONATION".to_string())];
+    assert!(is_blocked(&upheld, "ORGAN_DONATION"));
+    assert!(!is_blocked(&upheld, "TISSUE_DONATION"));
+    assert!(!is_blocked(&[], "ORGAN_DONATION"));
+}
+
+#[test]
+fn step_gate_is_open_without_holds_or_objections() {
+    assert_eq!(step_allowed("patient_gate_001", "ORGAN_DONATION"), Ok(true));
+}
+
+fn history_record(execution_id: &str) -> Option<ExecutionResult> {
+    EXECUTION_HISTORY.with(|h| h.borrow().get(execution_id).cloned())
+}
+
+#[test]
+fn failed_step_reaches_the_execution_record() {
+    at(0);
+    let execution_id = "EXEC_FAILED_STEP_001".to_string();
+    let patient_id = "patient_events_001".to_string();
+    events::record(events::ExecutionEventKind::ExecutionStarted {
+        execution_id: execution_id.clone(),
+        patient_id: patient_id.clone(),
+    });
+    events::record(events::ExecutionEventKind::ExecutionStepCompleted {
+        execution_id: execution_id.clone(),
+        step: DirectiveExecution { execution_status: "COMPLETED".to_string(), ..failed_step("TISSUE_DONATION") },
+    });
+    record_step_failure(&execution_id, "ORGAN_DONATION", "Network unreachable");
+    assert!(history_record(&execution_id).is_none(), "a running execution is not history yet");
+
+    events::record(events::ExecutionEventKind::ExecutionFailed {
+        patient_id,
+        execution_id: Some(execution_id.clone()),
+        error: "Network unreachable".to_string(),
+    });
+    let record = history_record(&execution_id).expect("failed execution missing from history");
+    let statuses: Vec<(&str, &str)> = record.directives_executed.iter()
+        .map(|d| (d.directive_type.as_str(), d.execution_status.as_str()))
+        .collect();
+    assert_eq!(statuses, vec![("TISSUE_DONATION", "COMPLETED"), ("ORGAN_DONATION", "FAILED")]);
+}
+
+#[test]
+fn execution_failed_between_steps_is_marked_failed() {
+    at(0);
+    let execution_id = "EXEC_FAILED_GATE_001".to_string();
+    events::record(events::ExecutionEventKind::ExecutionStarted {
+        execution_id: execution_id.clone(),
+        patient_id: "patient_events_002".to_string(),
+    });
+    events::record(events::ExecutionEventKind::ExecutionFailed {
+        patient_id: "patient_events_002".to_string(),
+        execution_id: Some(execution_id.clone()),
+        error: "Execution on hold pending dispute".to_string(),
+    });
+    let record = history_record(&execution_id).expect("failed execution missing from history");
+    assert_eq!(record.directives_executed.len(), 1);
+    assert_eq!(record.directives_executed[0].execution_status, "FAILED");
+}
+
+fn principal(byte: u8) -> Principal {
+    Principal::from_slice(&[byte; 29])
+}
+
+fn hex(text: &str) -> Vec<u8> {
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+}
+
+fn dispute(dispute_id: &str, patient_id: &str, filed_by: Principal, reviewer: Option<Principal>, status: &str) -> disputes::Dispute {
+    disputes::Dispute {
+        dispute_id: dispute_id.to_string(),
+        patient_id: patient_id.to_string(),
+        filed_by,
+        directive_type: None,
+        reason: "Family disagrees".to_string(),
+        status: status.to_string(),
+        filed_at: clock::now(),
+        review_deadline: clock::now() + 72 * 60 * 60 * 1_000_000_000,
+        ethics_reviewer: reviewer,
+        resolution: None,
+        resolved_at: None,
+    }
+}
+
+fn file_dispute(dispute: disputes::Dispute) {
+    disputes::DISPUTES.with(|d| d.borrow_mut().insert(dispute.dispute_id.clone(), dispute));
+}
+
+#[test]
+fn dispute_parties_read_only_the_disputes_they_filed_or_review() {
+    at(0);
+    let (daughter, son, reviewer, stranger) = (principal(1), principal(2), principal(3), principal(4));
+    file_dispute(dispute("DISPUTE_001", "patient_dispute_001", daughter, None, "UNDER_REVIEW"));
+    file_dispute(dispute("DISPUTE_002", "patient_dispute_001", son, Some(reviewer), "UNDER_REVIEW"));
+    file_dispute(dispute("DISPUTE_003", "patient_dispute_002", son, None, "UNDER_REVIEW"));
+
+    let ids = |requester| -> Vec<String> {
+        disputes::disputes_party_to("patient_dispute_001", requester).unwrap().into_iter().map(|d| d.dispute_id).collect()
+    };
+    assert_eq!(ids(daughter), vec!["DISPUTE_001"]);
+    assert_eq!(ids(son), vec!["DISPUTE_002"]);
+    assert_eq!(ids(reviewer), vec!["DISPUTE_002"]);
+    assert!(disputes::disputes_party_to("patient_dispute_001", stranger).is_err());
+    assert!(disputes::disputes_party_to("patient_dispute_002", daughter).is_err());
+}
+
+#[test]
+fn open_dispute_holds_every_step_and_an_upheld_one_blocks_only_its_type() {
+    at(0);
+    file_dispute(dispute("DISPUTE_004", "patient_dispute_003", principal(1), None, "UNDER_REVIEW"));
+    let held = step_allowed("patient_dispute_003", "TISSUE_DONATION").unwrap_err();
+    assert!(held.contains("on hold pending dispute DISPUTE_004"));
+
+    disputes::DISPUTES.with(|d| {
+        let mut disputes = d.borrow_mut();
+        let dispute = disputes.get_mut("DISPUTE_004").unwrap();
+        dispute.status = "RESOLVED_UPHELD".to_string();
+        dispute.directive_type = Some("ORGAN_DONATION".to_string());
+    });
+    assert_eq!(step_allowed("patient_dispute_003", "ORGAN_DONATION"), Ok(false));
+    assert_eq!(step_allowed("patient_dispute_003", "TISSUE_DONATION"), Ok(true));
+}
+
+fn refer(case_id: &str, patient_id: &str, source: &str, source_reference: &str) {
+    ethics::ETHICS_CASES.with(|cases| {
+        cases.borrow_mut().insert(case_id.to_string(), ethics::EthicsCase {
+            case_id: case_id.to_string(),
+            patient_id: patient_id.to_string(),
+            source: source.to_string(),
+            source_reference: source_reference.to_string(),
+            summary: "Referred".to_string(),
+            status: "OPEN".to_string(),
+            votes: vec![],
+            decision: None,
+            decided_at: None,
+            voting_record_hash: None,
+            referred_at: clock::now(),
+        })
+    });
+}
+
+fn case_status(case_id: &str) -> (String, Option<String>) {
+    ethics::ETHICS_CASES.with(|cases| {
+        let cases = cases.borrow();
+        let case = &cases[case_id];
+        (case.status.clone(), case.decision.clone())
+    })
+}
+
+#[test]
+fn resolved_dispute_supersedes_its_referral_on_the_audit_chain() {
+    at(0);
+    refer("ETHICS_001", "patient_ethics_001", "DISPUTE", "DISPUTE_010");
+    refer("ETHICS_002", "patient_ethics_001", "DISPUTE", "DISPUTE_011");
+
+    ethics::supersede_dispute_case("DISPUTE_010", "RESOLVED_PROCEED");
+    assert_eq!(case_status("ETHICS_001"), ("SUPERSEDED".to_string(), None));
+    assert_eq!(case_status("ETHICS_002"), ("OPEN".to_string(), None));
+
+    let entries = audit::entries_for("ETHICS_001");
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].event_type, "ETHICS_SUPERSEDED");
+    assert_eq!(audit::verify_audit_chain(), Ok(1));
+
+    // Already superseded; a second resolution leaves no further trace
+    ethics::supersede_dispute_case("DISPUTE_010", "RESOLVED_PROCEED");
+    assert_eq!(audit::verify_audit_chain(), Ok(1));
+}
+
+#[test]
+fn open_validity_referral_holds_execution_until_the_committee_lets_it_proceed() {
+    at(0);
+    refer("ETHICS_003", "patient_ethics_002", "LOW_LEGAL_VALIDITY", "TRACE_1");
+    let held = step_allowed("patient_ethics_002", "ORGAN_DONATION").unwrap_err();
+    assert!(held.contains("ethics committee case ETHICS_003"));
+
+    let decide = |decision: &str| ethics::ETHICS_CASES.with(|cases| {
+        let mut cases = cases.borrow_mut();
+        let case = cases.get_mut("ETHICS_003").unwrap();
+        case.status = "DECIDED".to_string();
+        case.decision = Some(decision.to_string());
+    });
+    decide("PROCEED");
+    assert_eq!(step_allowed("patient_ethics_002", "ORGAN_DONATION"), Ok(true));
+    decide("BLOCK");
+    assert!(step_allowed("patient_ethics_002", "ORGAN_DONATION").is_err());
+}
+
+#[test]
+fn audit_entry_hash_keeps_adjacent_fields_apart() {
+    let hash = |event_type: &str, reference_id: &str| audit::hash_entry(0, 0, event_type, reference_id, &[1; 32], &[0; 32]);
+    assert_ne!(hash("AB", "C"), hash("A", "BC"));
+    assert_eq!(hash("AB", "C"), hash("AB", "C"));
+}
+
+fn completed(execution_id: &str, patient_id: &str, steps: Vec<DirectiveExecution>) -> ExecutionResult {
+    ExecutionResult {
+        execution_id: execution_id.to_string(),
+        patient_id: patient_id.to_string(),
+        directives_executed: steps,
+        total_execution_time_ms: 12,
+        blockchain_verification: "VERIFIED".to_string(),
+        audit_log_created: true,
+        compliance_verified: true,
+        contact_acknowledgments: vec![],
+        consent_states: vec![],
+        consent_retractions: vec![],
+    }
+}
+
+#[test]
+fn execution_state_replays_the_same_after_an_upgrade() {
+    at(0);
+    let tissue = DirectiveExecution { execution_status: "COMPLETED".to_string(), ..failed_step("TISSUE_DONATION") };
+    events::record(events::ExecutionEventKind::ExecutionStarted {
+        execution_id: "EXEC_UPGRADE_001".to_string(),
+        patient_id: "patient_upgrade_001".to_string(),
+    });
+    events::record(events::ExecutionEventKind::ExecutionCompleted(
+        completed("EXEC_UPGRADE_001", "patient_upgrade_001", vec![tissue.clone()]),
+    ));
+    events::record(events::ExecutionEventKind::ExecutionStarted {
+        execution_id: "EXEC_UPGRADE_002".to_string(),
+        patient_id: "patient_upgrade_002".to_string(),
+    });
+    events::record(events::ExecutionEventKind::ExecutionStepCompleted {
+        execution_id: "EXEC_UPGRADE_002".to_string(),
+        step: tissue,
+    });
+    let before = format!("{:?}", history_record("EXEC_UPGRADE_001").unwrap());
+
+    let saved = events::saved_state();
+    events::restore_state((None, None, None, None, None, None, None));
+    assert!(history_record("EXEC_UPGRADE_001").is_none());
+
+    events::restore_state(saved);
+    assert_eq!(format!("{:?}", history_record("EXEC_UPGRADE_001").unwrap()), before);
+
+    // The execution that was in flight keeps its steps, and the log carries on from its last sequence
+    let sequence = events::record(events::ExecutionEventKind::ExecutionFailed {
+        patient_id: "patient_upgrade_002".to_string(),
+        execution_id: Some("EXEC_UPGRADE_002".to_string()),
+        error: "Network unreachable".to_string(),
+    });
+    assert_eq!(sequence, 4);
+    let statuses: Vec<String> = history_record("EXEC_UPGRADE_002").unwrap().directives_executed.iter()
+        .map(|d| format!("{} {}", d.directive_type, d.execution_status))
+        .collect();
+    assert_eq!(statuses, vec!["TISSUE_DONATION COMPLETED", "EXECUTION FAILED"]);
+}
+
+#[test]
+fn sealed_offer_opens_only_with_the_center_key_and_its_own_metadata() {
+    use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+    use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    let center_secret = StaticSecret::from([9; 32]);
+    let center_public = PublicKey::from(&center_secret);
+    let key = center_keys::CenterKey {
+        key_id: "CKEY_001".to_string(),
+        public_key: center_public.as_bytes().to_vec(),
+        registered_by: principal(5),
+        registered_at: 0,
+        retired_at: None,
+    };
+    let offer = br#"{"organ":"KIDNEY","patient_id":"patient_seal_001"}"#;
+    let sealed = center_keys::seal_to("MAYO_TRANSPLANT", &key, [3; 32], "application/json", offer).unwrap();
+    assert!(!String::from_utf8_lossy(&sealed).contains("KIDNEY"));
+
+    // What the center does on receipt, from the published scheme alone
+    let body: serde_json::Value = serde_json::from_slice(&sealed).unwrap();
+    assert_eq!(body["scheme"], "X25519-SHA256-CHACHA20POLY1305");
+    assert_eq!(body["key_id"], "CKEY_001");
+    let ephemeral: [u8; 32] = hex(body["ephemeral_public_key"].as_str().unwrap()).try_into().unwrap();
+    let ephemeral = PublicKey::from(ephemeral);
+    let mut key_material = b"echoledger-offer-seal-v1".to_vec();
+    key_material.extend_from_slice(center_secret.diffie_hellman(&ephemeral).as_bytes());
+    key_material.extend_from_slice(ephemeral.as_bytes());
+    key_material.extend_from_slice(center_public.as_bytes());
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&ic_cdk::api::sha256(&key_material)));
+    let ciphertext = hex(body["ciphertext"].as_str().unwrap());
+    let open = |associated_data: &str| {
+        cipher.decrypt(Nonce::from_slice(&[0; 12]), Payload { msg: &ciphertext, aad: associated_data.as_bytes() })
+    };
+    assert_eq!(open("X25519-SHA256-CHACHA20POLY1305|CKEY_001|application/json").unwrap(), offer.to_vec());
+    assert!(open("X25519-SHA256-CHACHA20POLY1305|CKEY_001|text/plain").is_err());
+
+    // A key on which no shared secret can be agreed is refused rather than sealed to
+    let unusable = center_keys::CenterKey { public_key: vec![0; 32], ..key };
+    assert!(center_keys::seal_to("MAYO_TRANSPLANT", &unusable, [3; 32], "application/json", offer).is_err());
+}
+
+#[test]
+fn an_upgrade_releases_a_lock_whose_call_never_resumed() {
+    at(0);
+    // A call waiting on a reply when the code is replaced never drops its guard
+    std::mem::forget(patient_locks::PatientGuard::acquire("patient_lock_001", "EXECUTION").unwrap());
+    assert!(patient_locks::PatientGuard::acquire("patient_lock_001", "EXECUTION").is_err());
+
+    events::restore_state(events::saved_state());
+    assert!(patient_locks::PatientGuard::acquire("patient_lock_001", "EXECUTION").is_ok());
+}
@@ -0,0 +1,269 @@
+use ic_cdk::{caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::reconciliation::Outbound;
+use crate::{audit, clock, ids, viability, DirectiveExecution};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TissueBank {
+    pub bank_id: String,
+    pub name: String,
+    pub tissue_types: Vec<String>,
+    pub principal: Option<Principal>,
+    pub active: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TissueReferral {
+    pub referral_id: String,
+    pub patient_id: String,
+    pub bank_id: String,
+    pub tissues: Vec<String>,
+    pub referred_at: u64,
+    pub recovery_deadline: u64,
+    pub status: String, // "QUEUED", "SENT", "ACCEPTED", "EXPIRED", "CANCELLED", "RETRACTED"
+}
+
+thread_local! {
+    static TISSUE_BANKS: RefCell<BTreeMap<String, TissueBank>> = RefCell::new({
+        let mut banks = BTreeMap::new();
+        for (bank_id, name, tissues) in [
+            ("MAYO_EYE", "Mayo Clinic Eye Bank", vec!["corneas"]),
+            ("MTF", "MTF Biologics", vec!["skin", "bone", "tendons"]),
+            ("LIFENET", "LifeNet Health", vec!["heart_valves", "bone", "skin"]),
+        ] {
+            banks.insert(bank_id.to_string(), TissueBank {
+                bank_id: bank_id.to_string(),
+                name: name.to_string(),
+                tissue_types: tissues.into_iter().map(String::from).collect(),
+                principal: None,
+                active: true,
+            });
+        }
+        banks
+    });
+    static TISSUE_REFERRALS: RefCell<BTreeMap<String, TissueReferral>> = RefCell::new(BTreeMap::new());
+    static BATCH_SCHEDULED: RefCell<bool> = RefCell::new(false);
+}
+
+pub(crate) const TISSUE_TYPES: [&str; 5] = ["corneas", "skin", "bone", "heart_valves", "tendons"];
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+// Tissue can be recovered up to 24 hours post-mortem, unlike minutes-to-hours for solid organs
+const RECOVERY_WINDOW_HOURS: u64 = 24;
+// Referrals are grouped per bank and flushed together rather than paged one by one
+const BATCH_INTERVAL_MINUTES: u64 = 30;
+
+#[update]
+fn register_tissue_bank(bank: TissueBank) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage tissue banks".to_string());
+    }
+    if let Some(unknown) = bank.tissue_types.iter().find(|t| !TISSUE_TYPES.contains(&t.as_str())) {
+        return Err(format!("Unknown tissue type: {}", unknown));
+    }
+    TISSUE_BANKS.with(|b| {
+        b.borrow_mut().insert(bank.bank_id.clone(), bank);
+    });
+    Ok(())
+}
+
+#[query]
+fn get_tissue_banks() -> Vec<TissueBank> {
+    TISSUE_BANKS.with(|b| b.borrow().values().cloned().collect())
+}
+
+#[update]
+fn accept_tissue_referral(referral_id: String) -> Result<TissueReferral, String> {
+    let bank_principal = caller();
+    let referral = TISSUE_REFERRALS.with(|r| {
+        let mut referrals = r.borrow_mut();
+        let referral = referrals.get_mut(&referral_id).ok_or_else(|| format!("Tissue referral not found: {}", referral_id))?;
+        let owner = TISSUE_BANKS.with(|b| b.borrow().get(&referral.bank_id).and_then(|bank| bank.principal));
+        if owner != Some(bank_principal) {
+            return Err("Caller does not represent the referred tissue bank".to_string());
+        }
+        if referral.status == "CANCELLED" || referral.status == "RETRACTED" {
+            return Err("The donor's consent to this referral has been withdrawn".to_string());
+        }
+        if referral.status == "EXPIRED" || clock::now() > referral.recovery_deadline {
+            referral.status = "EXPIRED".to_string();
+            return Err("Recovery window has closed".to_string());
+        }
+        referral.status = "ACCEPTED".to_string();
+        Ok(referral.clone())
+    })?;
+
+    if let Ok(payload) = serde_json::to_vec(&referral) {
+        audit::append_audit_entry("TISSUE_REFERRAL_ACCEPTED", &referral.referral_id, &payload);
+    }
+    Ok(referral)
+}
+
+#[query]
+fn get_tissue_referrals(patient_id: String) -> Vec<TissueReferral> {
+    TISSUE_REFERRALS.with(|r| {
+        r.borrow()
+            .values()
+            .filter(|x| x.patient_id == patient_id)
+            .cloned()
+            .collect()
+    })
+}
+
+// Tissue path: queue a referral per bank, flush in batches, expire after the recovery window
+pub(crate) fn execute_tissue_donation(patient_id: &str) -> Result<Option<DirectiveExecution>, String> {
+    let donor = viability::donor_data(patient_id)
+        .ok_or_else(|| format!("No donor clinical data submitted for patient: {}", patient_id))?;
+    let tissues: Vec<String> = donor.organs_offered
+        .iter()
+        .filter(|o| TISSUE_TYPES.contains(&o.as_str()))
+        .cloned()
+        .collect();
+    if tissues.is_empty() {
+        return Ok(None);
+    }
+    ic_cdk::println!("👁️ Executing tissue donation for patient: {}", patient_id);
+
+    // Banks decline donors with any reactive viral screen
+    if donor.infection_screens.iter().any(|s| s.result != "NEGATIVE") {
+        return Err("Donor infection screen excludes tissue donation".to_string());
+    }
+
+    let now = clock::now();
+    let recovery_deadline = now + RECOVERY_WINDOW_HOURS * NANOS_PER_HOUR;
+    let banks: Vec<TissueBank> = TISSUE_BANKS.with(|b| b.borrow().values().filter(|x| x.active).cloned().collect());
+
+    let mut remaining = tissues.clone();
+    let mut referrals = Vec::new();
+    for bank in banks {
+        let assigned: Vec<String> = remaining.iter().filter(|t| bank.tissue_types.contains(t)).cloned().collect();
+        if assigned.is_empty() {
+            continue;
+        }
+        remaining.retain(|t| !assigned.contains(t));
+        referrals.push(TissueReferral {
+            referral_id: ids::new_id("TISSUE"),
+            patient_id: patient_id.to_string(),
+            bank_id: bank.bank_id,
+            tissues: assigned,
+            referred_at: now,
+            recovery_deadline,
+            status: "QUEUED".to_string(),
+        });
+    }
+
+    let banks_referred = referrals.len() as u32;
+    let referral_ids: Vec<String> = referrals.iter().map(|r| r.referral_id.clone()).collect();
+    TISSUE_REFERRALS.with(|r| {
+        let mut stored = r.borrow_mut();
+        for referral in referrals {
+            stored.insert(referral.referral_id.clone(), referral);
+        }
+    });
+    schedule_batch();
+    ic_cdk_timers::set_timer(Duration::from_nanos(RECOVERY_WINDOW_HOURS * NANOS_PER_HOUR), move || {
+        expire_referrals(&referral_ids);
+    });
+
+    Ok(Some(DirectiveExecution {
+        directive_type: "TISSUE_DONATION".to_string(),
+        execution_status: if remaining.is_empty() { "COMPLETED" } else { "PARTIAL" }.to_string(),
+        organs_processed: tissues,
+        recipient_matches: vec![],
+        total_recipients_notified: banks_referred,
+        estimated_lives_saved: 0,
+        data_shared_with: vec![],
+        anonymization_verified: true,
+        research_impact_score: 0.0,
+    }))
+}
+
+fn schedule_batch() {
+    let already_scheduled = BATCH_SCHEDULED.with(|s| std::mem::replace(&mut *s.borrow_mut(), true));
+    if already_scheduled {
+        return;
+    }
+    ic_cdk_timers::set_timer(Duration::from_secs(BATCH_INTERVAL_MINUTES * 60), flush_batch);
+}
+
+// One notification per bank covering every queued referral
+fn flush_batch() {
+    BATCH_SCHEDULED.with(|s| *s.borrow_mut() = false);
+
+    let mut by_bank: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    TISSUE_REFERRALS.with(|r| {
+        for referral in r.borrow_mut().values_mut().filter(|x| x.status == "QUEUED") {
+            referral.status = "SENT".to_string();
+            by_bank.entry(referral.bank_id.clone()).or_default().push(referral.referral_id.clone());
+        }
+    });
+
+    for (bank_id, referral_ids) in by_bank {
+        ic_cdk::println!("📦 TISSUE BATCH: {} - {} referral(s): {}", bank_id, referral_ids.len(), referral_ids.join(", "));
+    }
+}
+
+fn expire_referrals(referral_ids: &[String]) {
+    TISSUE_REFERRALS.with(|r| {
+        let mut referrals = r.borrow_mut();
+        for id in referral_ids {
+            if let Some(referral) = referrals.get_mut(id) {
+                if referral.status == "QUEUED" || referral.status == "SENT" {
+                    referral.status = "EXPIRED".to_string();
+                }
+            }
+        }
+    });
+}
+
+// Consent withdrawn: queued referrals are cancelled before the batch goes out, banks already told
+// get a retraction. Returns the cancelled referral ids and the banks retracted from.
+pub(crate) fn withdraw_referrals(patient_id: &str) -> (Vec<String>, Vec<String>) {
+    let mut cancelled = Vec::new();
+    let mut retracted: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    TISSUE_REFERRALS.with(|r| {
+        for referral in r.borrow_mut().values_mut().filter(|x| x.patient_id == patient_id) {
+            match referral.status.as_str() {
+                "QUEUED" => {
+                    referral.status = "CANCELLED".to_string();
+                    cancelled.push(referral.referral_id.clone());
+                }
+                "SENT" | "ACCEPTED" => {
+                    referral.status = "RETRACTED".to_string();
+                    retracted.entry(referral.bank_id.clone()).or_default().push(referral.referral_id.clone());
+                }
+                _ => {}
+            }
+        }
+    });
+
+    for (bank_id, referral_ids) in &retracted {
+        ic_cdk::println!("📦 TISSUE RETRACTION: {} - consent withdrawn for referral(s): {}", bank_id, referral_ids.join(", "));
+    }
+    (cancelled, retracted.into_keys().collect())
+}
+
+// Referrals made in [from, to); withdrawn ones are settled, not outstanding
+pub(crate) fn outbound_between(from: u64, to: u64) -> Vec<Outbound> {
+    TISSUE_REFERRALS.with(|r| {
+        r.borrow()
+            .values()
+            .filter(|x| x.referred_at >= from && x.referred_at < to)
+            .filter(|x| x.status != "CANCELLED" && x.status != "RETRACTED")
+            .map(|x| Outbound {
+                channel: "TISSUE_REFERRAL",
+                reference_id: x.referral_id.clone(),
+                counterparty: x.bank_id.clone(),
+                sent_at: x.referred_at,
+                delivered: x.status != "QUEUED",
+                acted: x.status == "ACCEPTED",
+                detail: (x.status == "QUEUED").then(|| "Still queued; the batch never went out".to_string()),
+            })
+            .collect()
+    })
+}
@@ -0,0 +1,134 @@
+use ic_cdk::caller;
+use ic_cdk::api::call::is_recovering_from_trap;
+use ic_cdk_macros::query;
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::events::{self, ExecutionEventKind};
+use crate::{clock, evidence};
+
+// A trap rolls the message back to its last await and nothing after it happens. What came before
+// an await is already committed, so an execution that traps midway would keep its started and
+// completed-step events and never reach an end. ic-cdk drops the trapped future in a cleanup
+// call whose changes are kept; a Watch held across the awaits notices that drop, counts the trap
+// and, for an execution, fails the step that was running and the execution with it. A trap before
+// the first await undoes the whole message and leaves nothing behind to count or mark, and so does
+// any trap in a synchronous update or timer. Those are counted where they can still be seen: every
+// inter-canister call goes through resilience, which counts a callee's trap reject against it.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TrapMetrics {
+    pub total_traps: u64,
+    pub by_operation: BTreeMap<String, u64>,
+    pub last_trap_at: Option<u64>,
+    pub last_operation: Option<String>,
+}
+
+thread_local! {
+    static TRAP_METRICS: RefCell<TrapMetrics> = RefCell::new(TrapMetrics::default());
+}
+
+pub(crate) struct Watch {
+    operation: &'static str,
+    execution: Option<(String, String)>, // (execution_id, patient_id)
+    step: Option<&'static str>,
+    settled: bool,
+}
+
+impl Watch {
+    pub(crate) fn start(operation: &'static str) -> Watch {
+        Watch { operation, execution: None, step: None, settled: false }
+    }
+
+    pub(crate) fn execution(execution_id: &str, patient_id: &str) -> Watch {
+        Watch {
+            operation: "EXECUTION",
+            execution: Some((execution_id.to_string(), patient_id.to_string())),
+            step: None,
+            settled: false,
+        }
+    }
+
+    pub(crate) fn step(&mut self, step: &'static str) {
+        self.step = Some(step);
+    }
+
+    pub(crate) fn step_done(&mut self) {
+        self.step = None;
+    }
+
+    // The execution has its final event; a later trap is counted but changes nothing recorded
+    pub(crate) fn settled(&mut self) {
+        self.settled = true;
+        self.step = None;
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        if !is_recovering_from_trap() {
+            return;
+        }
+        count(self.operation);
+        ic_cdk::println!("💥 TRAP during {}", self.operation);
+
+        let Some((execution_id, patient_id)) = &self.execution else {
+            return;
+        };
+        if self.settled {
+            return;
+        }
+        let error = match self.step {
+            Some(step) => {
+                let error = format!("Trapped during {} step", step);
+                crate::record_step_failure(execution_id, step, &error);
+                error
+            }
+            None => "Trapped between execution steps".to_string(),
+        };
+        evidence::record_failed_attempt(patient_id, &error);
+        events::record(ExecutionEventKind::ExecutionFailed {
+            patient_id: patient_id.clone(),
+            execution_id: Some(execution_id.clone()),
+            error,
+        });
+    }
+}
+
+// A call rejected because the callee trapped; its own rolled-back state could not count it
+pub(crate) fn record_callee_trap(target: &str) {
+    count(&format!("CALL:{}", target));
+    ic_cdk::println!("💥 TRAP in callee {}", target);
+}
+
+fn count(operation: &str) {
+    let now = clock::now();
+    TRAP_METRICS.with(|m| {
+        let mut metrics = m.borrow_mut();
+        metrics.total_traps += 1;
+        *metrics.by_operation.entry(operation.to_string()).or_default() += 1;
+        metrics.last_trap_at = Some(now);
+        metrics.last_operation = Some(operation.to_string());
+    });
+}
+
+#[query]
+fn get_trap_metrics() -> Result<TrapMetrics, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read trap metrics".to_string());
+    }
+    Ok(TRAP_METRICS.with(|m| m.borrow().clone()))
+}
+
+pub(crate) fn snapshot() -> TrapMetrics {
+    TRAP_METRICS.with(|m| m.borrow().clone())
+}
+
+pub(crate) fn restore(state: Option<TrapMetrics>) {
+    let Some(state) = state else {
+        return;
+    };
+    TRAP_METRICS.with(|m| *m.borrow_mut() = state);
+}
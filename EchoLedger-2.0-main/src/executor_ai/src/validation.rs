@@ -0,0 +1,75 @@
+use ic_cdk_macros::inspect_message;
+
+// Shape checks for everything that arrives over Candid. Each failure reads
+// "Invalid <field>: <reason>" so integrators can map errors back to their payloads.
+
+// Ingress argument caps, checked before the argument is even decoded
+const DEFAULT_MAX_ARG_BYTES: usize = 64 * 1024;
+const LARGE_ARG_METHODS: [(&str, usize); 3] = [
+    ("preview_allocation", 512 * 1024),
+    ("relay_hl7_batch", 1024 * 1024),
+    ("http_request_update", 2 * MAX_TELEMETRY_BODY_BYTES),
+];
+
+pub(crate) const MAX_TELEMETRY_BODY_BYTES: usize = 16 * 1024;
+pub(crate) const MAX_REASON_BYTES: usize = 4 * 1024;
+pub(crate) const MAX_ANTIGENS: usize = 100;
+pub(crate) const MAX_ALLOCATION_CANDIDATES: usize = 1_000;
+const MAX_IDENTIFIER_BYTES: usize = 128;
+
+// Inter-canister calls skip this hook and are held to the same limits by the per-field checks
+#[inspect_message]
+fn inspect_message() {
+    let method = ic_cdk::api::call::method_name();
+    let limit = LARGE_ARG_METHODS.iter()
+        .find(|(name, _)| *name == method)
+        .map_or(DEFAULT_MAX_ARG_BYTES, |(_, limit)| *limit);
+    if ic_cdk::api::call::arg_data_raw_size() <= limit {
+        ic_cdk::api::call::accept_message();
+    }
+}
+
+// Free text: at most `max_bytes`, no control characters other than newline and tab, and
+// normalized (no byte-order mark, LF line endings, exotic spaces made plain, trimmed)
+pub(crate) fn text(field: &str, value: &str, max_bytes: usize) -> Result<String, String> {
+    if value.len() > max_bytes {
+        return Err(invalid(field, &format!("longer than {} bytes", max_bytes)));
+    }
+    let normalized = value.replace("\r\n", "\n").replace('\r', "\n");
+    let mut cleaned = String::with_capacity(normalized.len());
+    for c in normalized.chars() {
+        match c {
+            '\u{FEFF}' => {}
+            '\u{00A0}' | '\u{2000}'..='\u{200A}' | '\u{202F}' | '\u{205F}' | '\u{3000}' => cleaned.push(' '),
+            '\n' | '\t' => cleaned.push(c),
+            // U+FFFD is what binary content turns into once forced through a UTF-8 decoder
+            '\u{FFFD}' | '\u{FFFE}' | '\u{FFFF}' => return Err(invalid(field, "contains binary content")),
+            _ if c.is_control() => return Err(invalid(field, "contains control characters")),
+            _ => cleaned.push(c),
+        }
+    }
+    Ok(cleaned.trim().to_string())
+}
+
+// Ids and codes: non-empty single-line text
+pub(crate) fn identifier(field: &str, value: &str) -> Result<String, String> {
+    let value = text(field, value, MAX_IDENTIFIER_BYTES)?;
+    if value.is_empty() {
+        return Err(invalid(field, "must not be empty"));
+    }
+    if value.contains(['\n', '\t']) {
+        return Err(invalid(field, "must be a single line"));
+    }
+    Ok(value)
+}
+
+pub(crate) fn collection(field: &str, len: usize, max_items: usize) -> Result<(), String> {
+    if len > max_items {
+        return Err(invalid(field, &format!("more than {} entries", max_items)));
+    }
+    Ok(())
+}
+
+pub(crate) fn invalid(field: &str, reason: &str) -> String {
+    format!("Invalid {}: {}", field, reason)
+}
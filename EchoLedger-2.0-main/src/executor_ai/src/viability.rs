@@ -0,0 +1,299 @@
+use ic_cdk::{caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::cell::RefCell;
+
+use crate::{clock, dcd, kidney_indices, tissue, OrganAvailability};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LabResult {
+    pub code: String, // e.g. "CREATININE", "BILIRUBIN", "ALT", "LVEF", "PF_RATIO"
+    pub value: f32,
+    pub unit: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct InfectionScreen {
+    pub pathogen: String, // "HIV", "HBV", "HCV", ...
+    pub result: String, // "NEGATIVE", "POSITIVE", "INDETERMINATE"
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DonorClinicalData {
+    pub patient_id: String,
+    pub blood_type: String,
+    pub hla_typing: Vec<String>,
+    pub age_years: u8,
+    pub height_cm: Option<f32>,
+    pub weight_kg: Option<f32>,
+    pub history_hypertension: bool,
+    pub history_diabetes: bool,
+    pub cause_of_death: String, // "CVA", "ANOXIA", "HEAD_TRAUMA", "OTHER"
+    pub donation_after_circulatory_death: bool,
+    pub warm_ischemia_minutes: Option<u32>,
+    pub labs: Vec<LabResult>,
+    pub infection_screens: Vec<InfectionScreen>,
+    pub organs_offered: Vec<String>,
+    pub location: String,
+    pub submitted_by: Principal,
+    pub submitted_at: u64,
+}
+
+thread_local! {
+    static DONOR_CLINICAL_DATA: RefCell<BTreeMap<String, DonorClinicalData>> = RefCell::new(BTreeMap::new());
+    static INTAKE_HOSPITALS: RefCell<BTreeSet<Principal>> = RefCell::new(BTreeSet::new());
+}
+
+const REQUIRED_SCREENS: [&str; 3] = ["HIV", "HBV", "HCV"];
+const BLOOD_TYPES: [&str; 8] = ["O+", "O-", "A+", "A-", "B+", "B-", "AB+", "AB-"];
+const MIN_VIABILITY: f32 = 0.6;
+const DCD_MAX_LIVER_DONOR_AGE: u8 = 60;
+
+// Function markers that must be present before an organ can be scored
+fn required_labs(organ_type: &str) -> &'static [&'static str] {
+    match organ_type {
+        "kidney_left" | "kidney_right" => &["CREATININE"],
+        "liver" => &["BILIRUBIN", "ALT"],
+        "heart" => &["LVEF"],
+        "lungs" => &["PF_RATIO"],
+        _ => &[],
+    }
+}
+
+// Longest warm ischemia an organ tolerates, in minutes
+fn ischemia_tolerance(organ_type: &str) -> Option<u32> {
+    match organ_type {
+        "kidney_left" | "kidney_right" | "lungs" => Some(60),
+        "liver" => Some(30),
+        "heart" => Some(20),
+        _ => None,
+    }
+}
+
+#[update]
+fn authorize_intake_hospital(hospital: Principal) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may authorize intake hospitals".to_string());
+    }
+    INTAKE_HOSPITALS.with(|h| h.borrow_mut().insert(hospital));
+    Ok(())
+}
+
+// Hospital submits the donor work-up that viability is computed from
+#[update]
+fn submit_donor_clinical_data(mut data: DonorClinicalData) -> Result<(), String> {
+    let submitter = caller();
+    if !is_intake_hospital(&submitter) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+
+    let missing = missing_fields(&data);
+    if !missing.is_empty() {
+        return Err(format!("Donor clinical data incomplete: {}", missing.join(", ")));
+    }
+    // NaN compares false against every threshold, so a NaN lab would pass every viability check
+    if let Some(lab) = data.labs.iter().find(|l| !l.value.is_finite() || l.value < 0.0) {
+        return Err(format!("Lab {} must be a non-negative number", lab.code));
+    }
+    for (field, value) in [("height_cm", data.height_cm), ("weight_kg", data.weight_kg)] {
+        if value.is_some_and(|v| !v.is_finite() || v <= 0.0) {
+            return Err(format!("{} must be a positive number", field));
+        }
+    }
+
+    data.submitted_by = submitter;
+    data.submitted_at = clock::now();
+    DONOR_CLINICAL_DATA.with(|d| {
+        d.borrow_mut().insert(data.patient_id.clone(), data);
+    });
+    Ok(())
+}
+
+pub(crate) fn is_intake_hospital(principal: &Principal) -> bool {
+    INTAKE_HOSPITALS.with(|h| h.borrow().contains(principal)) || ic_cdk::api::is_controller(principal)
+}
+
+pub(crate) fn donor_data(patient_id: &str) -> Option<DonorClinicalData> {
+    DONOR_CLINICAL_DATA.with(|d| d.borrow().get(patient_id).cloned())
+}
+
+// The donor work-up is clinical data; only the hospitals that submit it and controllers read it back
+#[query]
+fn get_donor_clinical_data(patient_id: String) -> Result<Option<DonorClinicalData>, String> {
+    if !is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    Ok(donor_data(&patient_id))
+}
+
+fn missing_fields(data: &DonorClinicalData) -> Vec<String> {
+    let mut missing = Vec::new();
+
+    if !BLOOD_TYPES.contains(&data.blood_type.as_str()) {
+        missing.push("blood_type".to_string());
+    }
+    if data.organs_offered.is_empty() {
+        missing.push("organs_offered".to_string());
+    }
+    for pathogen in REQUIRED_SCREENS {
+        if !data.infection_screens.iter().any(|s| s.pathogen == pathogen) {
+            missing.push(format!("infection_screen:{}", pathogen));
+        }
+    }
+    for organ in &data.organs_offered {
+        // DCD warm ischemia is measured by the withdrawal protocol, not submitted up front
+        if ischemia_tolerance(organ).is_some()
+            && data.warm_ischemia_minutes.is_none()
+            && !data.donation_after_circulatory_death
+        {
+            missing.push("warm_ischemia_minutes".to_string());
+        }
+        if !tissue::TISSUE_TYPES.contains(&organ.as_str()) && data.hla_typing.is_empty() {
+            missing.push("hla_typing".to_string());
+        }
+        if organ.starts_with("kidney") {
+            if data.height_cm.is_none() {
+                missing.push("height_cm".to_string());
+            }
+            if data.weight_kg.is_none() {
+                missing.push("weight_kg".to_string());
+            }
+        }
+        for code in required_labs(organ) {
+            if lab_value(data, code).is_none() {
+                missing.push(format!("lab:{}", code));
+            }
+        }
+    }
+
+    missing.sort();
+    missing.dedup();
+    missing
+}
+
+fn lab_value(data: &DonorClinicalData, code: &str) -> Option<f32> {
+    data.labs.iter().find(|l| l.code == code).map(|l| l.value)
+}
+
+fn screen_positive(data: &DonorClinicalData, pathogen: &str) -> bool {
+    data.infection_screens
+        .iter()
+        .any(|s| s.pathogen == pathogen && s.result != "NEGATIVE")
+}
+
+// Rule-based score in [0, 1]; None when the organ must not be offered
+fn score_organ(data: &DonorClinicalData, organ_type: &str) -> Option<f32> {
+    if screen_positive(data, "HIV") {
+        return None;
+    }
+
+    // DCD eligibility differs from brain-death donation
+    if data.donation_after_circulatory_death {
+        match organ_type {
+            "heart" => return None,
+            "liver" if data.age_years > DCD_MAX_LIVER_DONOR_AGE => return None,
+            _ => {}
+        }
+    }
+
+    let mut score: f32 = 1.0;
+
+    if let (Some(tolerance), Some(minutes)) = (ischemia_tolerance(organ_type), data.warm_ischemia_minutes) {
+        if minutes > tolerance {
+            return None;
+        }
+        score -= 0.3 * minutes as f32 / tolerance as f32;
+    }
+
+    if screen_positive(data, "HBV") || screen_positive(data, "HCV") {
+        score -= 0.2;
+    }
+    if data.age_years > 60 {
+        score -= 0.1;
+    }
+    if data.donation_after_circulatory_death && organ_type.starts_with("kidney") {
+        // Higher delayed graft function risk
+        score -= 0.05;
+    }
+
+    // Organ-specific function markers
+    let marker_penalty = match organ_type {
+        "kidney_left" | "kidney_right" => {
+            let creatinine = lab_value(data, "CREATININE")?;
+            if creatinine > 1.5 { (creatinine - 1.5) * 0.15 } else { 0.0 }
+        }
+        "liver" => {
+            let bilirubin = lab_value(data, "BILIRUBIN")?;
+            let alt = lab_value(data, "ALT")?;
+            let mut penalty = 0.0;
+            if bilirubin > 2.0 { penalty += (bilirubin - 2.0) * 0.1; }
+            if alt > 120.0 { penalty += 0.15; }
+            penalty
+        }
+        "heart" => {
+            let lvef = lab_value(data, "LVEF")?;
+            if lvef < 45.0 { return None; }
+            if lvef < 55.0 { 0.15 } else { 0.0 }
+        }
+        "lungs" => {
+            let pf_ratio = lab_value(data, "PF_RATIO")?;
+            if pf_ratio < 300.0 { return None; }
+            if pf_ratio < 400.0 { 0.1 } else { 0.0 }
+        }
+        _ => 0.0,
+    };
+    score -= marker_penalty;
+
+    let score = score.clamp(0.0, 1.0);
+    (score >= MIN_VIABILITY).then_some(score)
+}
+
+fn condition_label(score: f32) -> &'static str {
+    if score >= 0.9 {
+        "Excellent"
+    } else if score >= 0.75 {
+        "Good"
+    } else {
+        "Fair"
+    }
+}
+
+// Compute viability from the submitted work-up; refuses to guess when data is missing
+pub(crate) fn assess(patient_id: &str) -> Result<Vec<OrganAvailability>, String> {
+    let mut data = donor_data(patient_id)
+        .ok_or_else(|| format!("No donor clinical data submitted for patient: {}", patient_id))?;
+
+    if data.donation_after_circulatory_death {
+        dcd::ensure_ready_for_allocation(patient_id)?;
+        data.warm_ischemia_minutes = dcd::dcd_case(patient_id).and_then(|c| c.warm_ischemia_minutes);
+    }
+
+    let missing = missing_fields(&data);
+    if !missing.is_empty() {
+        return Err(format!("Donor clinical data incomplete: {}", missing.join(", ")));
+    }
+
+    let elapsed_minutes = (clock::now().saturating_sub(data.submitted_at)) / 60_000_000_000;
+    let kdpi = kidney_indices::kdpi(&data);
+
+    // Tissue follows its own path through tissue banks
+    Ok(data.organs_offered
+        .iter()
+        .filter(|organ_type| !tissue::TISSUE_TYPES.contains(&organ_type.as_str()))
+        .filter_map(|organ_type| {
+            let viability_score = score_organ(&data, organ_type)?;
+            Some(OrganAvailability {
+                organ_type: organ_type.clone(),
+                blood_type: data.blood_type.clone(),
+                hla_typing: data.hla_typing.clone(),
+                organ_condition: condition_label(viability_score).to_string(),
+                time_since_harvest: elapsed_minutes,
+                location: data.location.clone(),
+                viability_score,
+                kdpi: if organ_type.starts_with("kidney") { kdpi } else { None },
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,188 @@
+use ic_cdk::{caller, Principal};
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::{audit, calendar, clock, derive_patient_hash, external_reference, networks, traps, validation, viability, RecipientMatch, EXECUTION_HISTORY};
+
+// Allocation confirmations for payers and OPO administrative systems, laid out like an X12 278
+// (005010X217) response: one transaction set per recipient offer, an HCR action code per offer.
+// The interchange is not submitted anywhere; it is generated for the requester to reconcile against.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AllocationConfirmation {
+    pub transaction_set_control_number: String,
+    pub recipient_id: String,
+    pub organ: String,
+    pub transplant_center: String,
+    pub network_id: Option<String>,
+    pub action_code: String, // HCR01: "A1" offer delivered, "A3" not delivered
+    pub allocation_profile: String,
+    pub allocation_score: f32,
+    pub compatibility_score: f32,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AllocationTransactionExport {
+    pub execution_id: String,
+    pub receiver_id: String,
+    pub interchange_control_number: String,
+    pub confirmations: Vec<AllocationConfirmation>,
+    pub document: String, // '~' ends segments, '*' separates elements, ':' separates components
+    pub document_sha256: Vec<u8>,
+    pub generated_by: Principal,
+    pub generated_at: u64,
+}
+
+thread_local! {
+    static INTERCHANGE_CONTROL_NUMBER: RefCell<u32> = const { RefCell::new(0) };
+    static ALLOCATION_TRANSACTIONS: RefCell<BTreeMap<String, Vec<AllocationTransactionExport>>> = RefCell::new(BTreeMap::new());
+}
+
+const SENDER_ID: &str = "ECHOLEDGER";
+const IMPLEMENTATION_GUIDE: &str = "005010X217";
+const MAX_RECEIVER_ID_BYTES: usize = 15; // ISA08 is a fixed 15-character field
+const MAX_EXPORTS_PER_EXECUTION: usize = 20;
+
+#[update]
+async fn export_allocation_transactions(execution_id: String, receiver_id: String) -> Result<AllocationTransactionExport, String> {
+    let _watch = traps::Watch::start("ALLOCATION_EXPORT");
+    let requester = caller();
+    if !viability::is_intake_hospital(&requester) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    let receiver_id = validation::identifier("receiver_id", &receiver_id)?;
+    if receiver_id.len() > MAX_RECEIVER_ID_BYTES || receiver_id.contains(['*', '~', ':', '^']) {
+        return Err(validation::invalid("receiver_id", "must be at most 15 characters without X12 delimiters"));
+    }
+    let execution = EXECUTION_HISTORY.with(|h| h.borrow().get(&execution_id).cloned())
+        .ok_or_else(|| format!("Execution not found: {}", execution_id))?;
+    let matches: Vec<RecipientMatch> = execution.directives_executed
+        .iter()
+        .filter(|d| d.directive_type == "ORGAN_DONATION")
+        .flat_map(|d| d.recipient_matches.iter().cloned())
+        .collect();
+    if matches.is_empty() {
+        return Err(format!("Execution {} allocated no organs", execution_id));
+    }
+    // The donor appears only by a reference issued for this receiver
+    let donor_hash = derive_patient_hash(&execution.patient_id).await?;
+    let donor_reference = external_reference(&donor_hash, &format!("x12:{}", receiver_id)).await?;
+
+    let now = clock::now();
+    let control_number = INTERCHANGE_CONTROL_NUMBER.with(|n| {
+        let mut n = n.borrow_mut();
+        *n = if *n >= 999_999_999 { 1 } else { *n + 1 };
+        *n
+    });
+    let confirmations: Vec<AllocationConfirmation> = matches.iter().enumerate().map(|(i, m)| AllocationConfirmation {
+        transaction_set_control_number: format!("{:04}", i + 1),
+        recipient_id: m.recipient_id.clone(),
+        organ: m.organ.clone(),
+        transplant_center: m.transplant_center.clone(),
+        network_id: networks::network_for_center(&m.transplant_center).map(|n| n.network_id),
+        action_code: if m.notification_sent { "A1" } else { "A3" }.to_string(),
+        allocation_profile: m.allocation_profile.clone(),
+        allocation_score: m.allocation_score,
+        compatibility_score: m.compatibility_score,
+    }).collect();
+
+    // BHT03 carries a digest; the execution id itself stays internal
+    let reference = format!("EL{}", &hex(&ic_cdk::api::sha256(execution_id.as_bytes()))[..16]);
+    let document = interchange(&receiver_id, control_number, now, &reference, &donor_reference, &confirmations);
+    let document_sha256 = ic_cdk::api::sha256(document.as_bytes());
+    let export = AllocationTransactionExport {
+        execution_id: execution_id.clone(),
+        receiver_id,
+        interchange_control_number: format!("{:09}", control_number),
+        confirmations,
+        document,
+        document_sha256: document_sha256.clone(),
+        generated_by: requester,
+        generated_at: now,
+    };
+
+    audit::append_audit_entry("ALLOCATION_TRANSACTIONS_EXPORTED", &execution_id, &document_sha256);
+    ALLOCATION_TRANSACTIONS.with(|t| {
+        let mut transactions = t.borrow_mut();
+        let exports = transactions.entry(execution_id).or_default();
+        if exports.len() >= MAX_EXPORTS_PER_EXECUTION {
+            exports.remove(0);
+        }
+        exports.push(export.clone());
+    });
+    Ok(export)
+}
+
+#[query]
+fn get_allocation_transactions(execution_id: String) -> Result<Vec<AllocationTransactionExport>, String> {
+    if !viability::is_intake_hospital(&caller()) {
+        return Err("Caller is not an authorized intake hospital".to_string());
+    }
+    Ok(ALLOCATION_TRANSACTIONS.with(|t| t.borrow().get(&execution_id).cloned().unwrap_or_default()))
+}
+
+fn interchange(
+    receiver_id: &str,
+    control_number: u32,
+    now: u64,
+    reference: &str,
+    donor_reference: &str,
+    confirmations: &[AllocationConfirmation]
+) -> String {
+    let (year, month, day, hour, minute, _) = calendar::utc_parts(now);
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let time = format!("{:02}{:02}", hour, minute);
+
+    let mut segments = vec![
+        format!(
+            "ISA*00*{:10}*00*{:10}*ZZ*{:15}*ZZ*{:15}*{}*{}*^*00501*{:09}*0*P*:",
+            "", "", SENDER_ID, receiver_id, &date[2..], time, control_number
+        ),
+        format!("GS*HI*{}*{}*{}*{}*{}*X*{}", SENDER_ID, receiver_id, date, time, control_number, IMPLEMENTATION_GUIDE),
+    ];
+    for confirmation in confirmations {
+        let set = vec![
+            format!("ST*278*{}*{}", confirmation.transaction_set_control_number, IMPLEMENTATION_GUIDE),
+            format!("BHT*0007*11*{}*{}*{}", reference, date, time),
+            // Information source (the allocating network), receiver (the center), subscriber (the donor), event
+            "HL*1**20*1".to_string(),
+            format!("NM1*X3*2*{}", element(confirmation.network_id.as_deref().unwrap_or("UNAFFILIATED"))),
+            "HL*2*1*21*1".to_string(),
+            format!("NM1*1P*2*{}", element(&confirmation.transplant_center)),
+            "HL*3*2*22*1".to_string(),
+            format!("NM1*QC*1*DONOR****ZZ*{}", donor_reference),
+            "HL*4*3*EV*0".to_string(),
+            "UM*HS*I".to_string(),
+            format!("HCR*{}*{}{}", confirmation.action_code, reference, confirmation.transaction_set_control_number),
+            format!("REF*BB*{}", element(&confirmation.recipient_id)),
+            format!("DTP*472*D8*{}", date),
+            format!(
+                "MSG*{} ALLOCATED UNDER {} SCORE {:.3} COMPATIBILITY {:.3}",
+                element(&confirmation.organ),
+                element(&confirmation.allocation_profile),
+                confirmation.allocation_score,
+                confirmation.compatibility_score
+            ),
+        ];
+        // SE01 counts the segments from ST through SE
+        let count = set.len() + 1;
+        segments.extend(set);
+        segments.push(format!("SE*{}*{}", count, confirmation.transaction_set_control_number));
+    }
+    segments.push(format!("GE*{}*{}", confirmations.len(), control_number));
+    segments.push(format!("IEA*1*{:09}", control_number));
+
+    segments.into_iter().map(|s| s + "~").collect::<Vec<_>>().join("\n")
+}
+
+// Free text cannot carry the delimiters the document declares
+fn element(value: &str) -> String {
+    value.chars().map(|c| if matches!(c, '*' | '~' | ':' | '^') || c.is_control() { ' ' } else { c }).collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,78 @@
+// Interleaving across awaits, checked on a PocketIC replica. Build the canister first:
+//
+//     cargo build -p executor_ai --target wasm32-unknown-unknown --release
+//
+// and point POCKET_IC_BIN at a pocket-ic server binary. EXECUTOR_AI_WASM overrides the wasm path.
+//
+// Both calls are submitted before the replica runs a round, so the second is executed while the
+// first is suspended at its first inter-canister await - the window a patient lock has to cover.
+
+use candid::{decode_one, encode_one, Principal};
+use pocket_ic::{PocketIc, WasmResult};
+
+const DEFAULT_WASM: &str = "../../target/wasm32-unknown-unknown/release/executor_ai.wasm";
+
+fn install_executor() -> (PocketIc, Principal) {
+    let path = std::env::var("EXECUTOR_AI_WASM").unwrap_or_else(|_| DEFAULT_WASM.to_string());
+    let wasm = std::fs::read(&path).unwrap_or_else(|e| panic!("executor_ai wasm not found at {}: {}", path, e));
+    let pic = PocketIc::new();
+    let canister = pic.create_canister();
+    pic.add_cycles(canister, 2_000_000_000_000);
+    pic.install_canister(canister, wasm, encode_one(()).unwrap(), None);
+    (pic, canister)
+}
+
+fn execute(pic: &PocketIc, canister: Principal, patient_id: &str) -> pocket_ic::common::rest::RawMessageId {
+    pic.submit_call(canister, Principal::anonymous(), "execute_death_directives", encode_one(patient_id.to_string()).unwrap())
+        .expect("execute_death_directives was not accepted")
+}
+
+// The canister's own error, if the call got as far as replying
+fn error_of(pic: &PocketIc, message: pocket_ic::common::rest::RawMessageId) -> Option<String> {
+    match pic.await_call(message).expect("call failed") {
+        WasmResult::Reply(bytes) => {
+            let result: Result<candid::Reserved, String> = decode_one(&bytes).expect("undecodable reply");
+            result.err()
+        }
+        WasmResult::Reject(message) => Some(message),
+    }
+}
+
+#[test]
+fn concurrent_executions_for_one_patient_do_not_interleave() {
+    let (pic, canister) = install_executor();
+    let first = execute(&pic, canister, "pic_patient_001");
+    let second = execute(&pic, canister, "pic_patient_001");
+
+    let first_error = error_of(&pic, first);
+    let second_error = error_of(&pic, second);
+
+    // Exactly one of the two ran; the other was turned away at the lock rather than repeating its steps
+    let refused = [&first_error, &second_error]
+        .iter()
+        .filter(|e| e.as_deref().is_some_and(|e| e.contains("already in progress")))
+        .count();
+    assert_eq!(refused, 1, "first: {:?}, second: {:?}", first_error, second_error);
+}
+
+#[test]
+fn executions_for_different_patients_run_alongside_each_other() {
+    let (pic, canister) = install_executor();
+    let first = execute(&pic, canister, "pic_patient_002");
+    let second = execute(&pic, canister, "pic_patient_003");
+
+    for error in [error_of(&pic, first), error_of(&pic, second)] {
+        assert!(!error.as_deref().is_some_and(|e| e.contains("already in progress")), "{:?}", error);
+    }
+}
+
+#[test]
+fn lock_is_released_once_the_execution_settles() {
+    let (pic, canister) = install_executor();
+    let first = execute(&pic, canister, "pic_patient_004");
+    let _ = error_of(&pic, first);
+
+    let again = execute(&pic, canister, "pic_patient_004");
+    let error = error_of(&pic, again);
+    assert!(!error.as_deref().is_some_and(|e| e.contains("already in progress")), "{:?}", error);
+}
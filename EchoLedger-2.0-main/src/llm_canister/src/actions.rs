@@ -0,0 +1,136 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::{clock, tenancy, traps, validation};
+
+// Recommended actions are drawn from a fixed vocabulary so a hospital system can act on them:
+// each carries a stable code and a label for people, and a hospital can map codes to its own
+// order sets, which come back with every assessment its staff request.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ActionCode {
+    pub code: String,
+    pub label: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RecommendedAction {
+    pub code: String,
+    pub label: String,
+    pub order_set_id: Option<String>, // The caller's hospital's order set for the code, when it has mapped one
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TenantOrderSets {
+    pub tenant_id: String,
+    pub mappings: BTreeMap<String, String>, // action code -> order set id
+    pub version: u64,
+    pub updated_by: Principal,
+    pub updated_at: u64,
+}
+
+const ACTION_VOCABULARY: [(&str, &str); 13] = [
+    ("CARDIAC_INTERVENTION", "Immediate cardiac intervention"),
+    ("VENTILATORY_SUPPORT_ASSESSMENT", "Ventilatory support assessment"),
+    ("ICU_ADMISSION_ASSESSMENT", "ICU admission assessment"),
+    ("NEUROLOGY_CONSULT", "Neurology consultation"),
+    ("ONCOLOGY_CONSULT", "Oncology consultation"),
+    ("INFECTION_WORKUP", "Sepsis and infection work-up"),
+    ("RENAL_FUNCTION_REVIEW", "Renal function review"),
+    ("PALLIATIVE_CARE_CONSULT", "Palliative care consultation"),
+    ("GOALS_OF_CARE_DISCUSSION", "Goals-of-care discussion with the patient or surrogate"),
+    ("ADVANCE_DIRECTIVE_REVIEW", "Review the advance directives on file"),
+    ("URGENT_CLINICAL_REVIEW", "Urgent clinical review of deteriorating trend"),
+    ("INCREASE_MONITORING", "Increase monitoring frequency"),
+    ("CONTINUE_CURRENT_MANAGEMENT", "Continue current management"),
+];
+
+thread_local! {
+    static ORDER_SETS: RefCell<BTreeMap<String, TenantOrderSets>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+#[query]
+fn get_action_vocabulary() -> Vec<ActionCode> {
+    ACTION_VOCABULARY.iter().map(|(code, label)| ActionCode { code: code.to_string(), label: label.to_string() }).collect()
+}
+
+// Replaces the tenant's mapping; tenant admins manage their own, controllers may manage any
+#[update]
+async fn set_action_order_sets(tenant_id: String, mappings: BTreeMap<String, String>) -> Result<TenantOrderSets, String> {
+    let _watch = traps::Watch::start("SET_ACTION_ORDER_SETS");
+    let requester = caller();
+    if !ic_cdk::api::is_controller(&requester) {
+        let membership = tenancy::resolve(requester).await?;
+        if !membership.is_some_and(|m| m.tenant_id == tenant_id && m.is_admin) {
+            return Err(format!("Only admins of tenant {} may map its order sets", tenant_id));
+        }
+    }
+
+    let mut validated = BTreeMap::new();
+    for (code, order_set_id) in mappings {
+        if !is_known(&code) {
+            return Err(format!("Unknown action code: {}", code));
+        }
+        validated.insert(code, validation::identifier("order_set_id", &order_set_id)?);
+    }
+
+    Ok(ORDER_SETS.with(|o| {
+        let mut order_sets = o.borrow_mut();
+        let version = order_sets.get(&tenant_id).map_or(0, |x| x.version) + 1;
+        let mapping = TenantOrderSets {
+            tenant_id: tenant_id.clone(),
+            mappings: validated,
+            version,
+            updated_by: requester,
+            updated_at: clock::now(),
+        };
+        order_sets.insert(tenant_id, mapping.clone());
+        mapping
+    }))
+}
+
+// Members of the tenant (once seen here) and controllers
+#[query]
+fn get_action_order_sets(tenant_id: String) -> Result<Option<TenantOrderSets>, String> {
+    let is_member = tenancy::cached_tenant(caller()).is_some_and(|t| t == tenant_id);
+    if !is_member && !ic_cdk::api::is_controller(&caller()) {
+        return Err(format!("Only members of tenant {} may read its order set mappings", tenant_id));
+    }
+    Ok(ORDER_SETS.with(|o| o.borrow().get(&tenant_id).cloned()))
+}
+
+pub(crate) fn is_known(code: &str) -> bool {
+    ACTION_VOCABULARY.iter().any(|(known, _)| *known == code)
+}
+
+// Codes reach here from this canister or a validated model reply, so every one has a label
+pub(crate) fn recommend(code: &str) -> RecommendedAction {
+    let label = ACTION_VOCABULARY.iter().find(|(known, _)| *known == code).map_or(code, |(_, label)| label);
+    RecommendedAction { code: code.to_string(), label: label.to_string(), order_set_id: None }
+}
+
+// Adds the action unless one with the same code is already there
+pub(crate) fn push_unique(actions: &mut Vec<RecommendedAction>, code: &str) {
+    if !actions.iter().any(|a| a.code == code) {
+        actions.push(recommend(code));
+    }
+}
+
+pub(crate) fn map_order_sets(tenant_id: Option<&str>, actions: &mut [RecommendedAction]) {
+    let Some(tenant_id) = tenant_id else {
+        return;
+    };
+    ORDER_SETS.with(|o| {
+        let order_sets = o.borrow();
+        let Some(mapping) = order_sets.get(tenant_id) else {
+            return;
+        };
+        for action in actions {
+            action.order_set_id = mapping.mappings.get(&action.code).cloned();
+        }
+    });
+}
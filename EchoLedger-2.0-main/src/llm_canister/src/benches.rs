@@ -0,0 +1,82 @@
+use canbench_rs::{bench, bench_fn, BenchResult};
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+use crate::{analyze_directive_text, calculate_processing_cost, extract_simple_patterns, preprocess_medical_text, replay};
+
+// Ceilings for the on-chain path, well inside the per-message limit; tighten them as
+// canbench_results.yml settles rather than raising them to make a regression pass
+const BUDGET_1KB: u64 = 25_000_000;
+const BUDGET_10KB: u64 = 200_000_000;
+const BUDGET_100KB: u64 = 2_000_000_000;
+const BUDGET_PROCESS_DIRECTIVE: u64 = 60_000_000;
+
+const SAMPLE_PARAGRAPH: &str = "I, the undersigned, being of sound mind, direct that in the event of \
+    brain death or a terminal condition with no reasonable chance of recovery I do not wish to receive \
+    CPR, mechanical ventilation or artificial nutrition. I wish to donate my organs, including heart, \
+    kidneys and liver, for transplant. Palliative care and comfort measures only.\n\t";
+
+fn document(size: usize) -> String {
+    SAMPLE_PARAGRAPH.repeat(size / SAMPLE_PARAGRAPH.len() + 1)[..size].to_string()
+}
+
+// The synchronous part of process_medical_directive; the hybrid escalation is an outcall and not metered here
+fn analyze(text: &str) {
+    let preprocessed = preprocess_medical_text(text).expect("preprocessing failed");
+    let analysis = extract_simple_patterns(&preprocessed, &replay::ruleset_in_effect(None)).expect("extraction failed");
+    calculate_processing_cost(&analysis.processing_method, text.len());
+}
+
+// Without an outcall or inter-canister call to wait on, one poll finishes the endpoint
+fn run_on_chain<F: Future>(future: F) -> F::Output {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("benchmark waited on a call"),
+    }
+}
+
+fn within_budget(name: &str, result: BenchResult, budget: u64) -> BenchResult {
+    assert!(
+        result.total.instructions <= budget,
+        "{} used {} instructions, budget is {}",
+        name,
+        result.total.instructions,
+        budget
+    );
+    result
+}
+
+#[bench(raw)]
+fn process_directive_1kb() -> BenchResult {
+    let text = document(1_024);
+    within_budget("process_directive_1kb", bench_fn(|| analyze(&text)), BUDGET_1KB)
+}
+
+#[bench(raw)]
+fn process_directive_10kb() -> BenchResult {
+    let text = document(10 * 1_024);
+    within_budget("process_directive_10kb", bench_fn(|| analyze(&text)), BUDGET_10KB)
+}
+
+#[bench(raw)]
+fn process_directive_100kb() -> BenchResult {
+    let text = document(100 * 1_024);
+    within_budget("process_directive_100kb", bench_fn(|| analyze(&text)), BUDGET_100KB)
+}
+
+// process_medical_directive past the tenant lookup: screening, extraction, settling, statistics and
+// the decision trace. No provider is registered in the bench canister, so the hybrid pass settles as
+// unavailable without an outcall.
+#[bench(raw)]
+fn process_medical_directive_on_chain() -> BenchResult {
+    let text = document(10 * 1_024);
+    within_budget(
+        "process_medical_directive_on_chain",
+        bench_fn(|| {
+            run_on_chain(analyze_directive_text("process_medical_directive", None, "BENCH_PATIENT", &text, 0))
+                .expect("analysis failed")
+        }),
+        BUDGET_PROCESS_DIRECTIVE,
+    )
+}
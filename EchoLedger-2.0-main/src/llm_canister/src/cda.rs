@@ -0,0 +1,420 @@
+use ic_cdk::caller;
+use ic_cdk_macros::update;
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::{clock, injection, tenancy, traps, validation, MedicalDirectiveAnalysis};
+
+// C-CDA exports carry advance directives twice: as coded Advance Directive Observations,
+// which are stored as they are, and as the section's narrative block, which is free text
+// and goes through the same NLP pipeline as any other directive.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CdaDirectiveEntry {
+    pub entry_id: Option<String>, // observation id, "root^extension"
+    pub directive_type: Option<String>, // One of the supported types, when the coding maps to one
+    pub code: Option<String>,
+    pub code_system: Option<String>,
+    pub code_display: Option<String>,
+    pub value_code: Option<String>,
+    pub value_display: Option<String>,
+    pub value_boolean: Option<bool>,
+    pub negated: bool,
+    pub status: Option<String>,
+    pub effective_from: Option<String>, // HL7 timestamps as written in the document
+    pub effective_to: Option<String>,
+    pub custodian: Option<String>,
+    pub document_reference: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CdaImport {
+    pub patient_id: String,
+    pub document_id: Option<String>,
+    pub section_title: Option<String>,
+    pub entries: Vec<CdaDirectiveEntry>,
+    pub narrative_analysis: Option<MedicalDirectiveAnalysis>, // None when the section has no narrative
+    pub imported_at: u64,
+}
+
+thread_local! {
+    // Latest import per (tenant, patient); tenants never see each other's documents
+    static CDA_IMPORTS: RefCell<BTreeMap<(Option<String>, String), CdaImport>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// LOINC 42348-3 "Advance directives" and the C-CDA section template, entries optional or required
+const SECTION_CODE: &str = "42348-3";
+const SECTION_TEMPLATE_PREFIX: &str = "2.16.840.1.113883.10.20.22.2.21";
+const MAX_ENTRIES: usize = 200;
+const MAX_DEPTH: usize = 64;
+
+// Narrative elements that begin a new line when flattened to text
+const BLOCK_ELEMENTS: [&str; 7] = ["paragraph", "item", "tr", "br", "caption", "list", "table"];
+
+#[update]
+async fn process_cda_document(patient_id: String, document: String) -> Result<CdaImport, String> {
+    let _watch = traps::Watch::start("PROCESS_CDA_DOCUMENT");
+    let start_time = clock::now();
+    let patient_id = validation::identifier("patient_id", &patient_id)?;
+    let document = validation::text("document", &document, validation::MAX_DIRECTIVE_TEXT_BYTES)?;
+    let tenant = tenancy::active_tenant(caller()).await?;
+
+    let root = parse(&document)?;
+    if root.name != "ClinicalDocument" {
+        return Err(validation::invalid("document", "root element is not a ClinicalDocument"));
+    }
+    let section = find_directives_section(&root).ok_or("Document has no Advance Directives section")?;
+    let entries: Vec<CdaDirectiveEntry> = section.children_named("entry")
+        .flat_map(|entry| entry.descendants_named("observation"))
+        .map(directive_entry)
+        .collect();
+    validation::collection("entries", entries.len(), MAX_ENTRIES)?;
+
+    let narrative = section.child("text").map(narrative_text).unwrap_or_default();
+    let narrative_analysis = if narrative.is_empty() {
+        None
+    } else {
+        Some(crate::analyze_directive_text("process_cda_document", tenant.as_deref(), &patient_id, &narrative, start_time).await?)
+    };
+
+    let import = CdaImport {
+        patient_id: patient_id.clone(),
+        document_id: root.child("id").and_then(instance_id),
+        section_title: section.child("title").map(narrative_text).filter(|t| !t.is_empty()),
+        entries,
+        narrative_analysis,
+        imported_at: clock::now(),
+    };
+    ic_cdk::println!(
+        "📄 C-CDA advance directives imported for patient {}: {} coded entries, narrative {}",
+        patient_id,
+        import.entries.len(),
+        if import.narrative_analysis.is_some() { "analyzed" } else { "absent" }
+    );
+    CDA_IMPORTS.with(|imports| imports.borrow_mut().insert((tenant, patient_id), import.clone()));
+    Ok(import)
+}
+
+#[update]
+async fn get_cda_directive_entries(patient_id: String) -> Result<Option<CdaImport>, String> {
+    let _watch = traps::Watch::start("GET_CDA_DIRECTIVE_ENTRIES");
+    let tenant = tenancy::active_tenant(caller()).await?;
+    Ok(CDA_IMPORTS.with(|imports| imports.borrow().get(&(tenant, patient_id)).cloned()))
+}
+
+fn find_directives_section(root: &Element) -> Option<&Element> {
+    root.descendants_named("section").into_iter().find(|section| {
+        section.children_named("code").any(|c| c.attr("code") == Some(SECTION_CODE))
+            || section.children_named("templateId").any(|t| t.attr("root").is_some_and(|r| r.starts_with(SECTION_TEMPLATE_PREFIX)))
+    })
+}
+
+// An Advance Directive Observation (templateId 2.16.840.1.113883.10.20.22.4.48). R1.1 documents
+// code the directive in the value; R2.1 codes the directive type and sets a boolean value.
+fn directive_entry(observation: &Element) -> CdaDirectiveEntry {
+    let code = observation.child("code");
+    let value = observation.child("value");
+    let effective = observation.child("effectiveTime");
+    let custodian = observation.children_named("participant")
+        .find(|p| p.attr("typeCode") == Some("CST"))
+        .and_then(|p| p.descendants_named("name").into_iter().next())
+        .map(narrative_text)
+        .filter(|n| !n.is_empty())
+        .map(|n| injection::encode_output(&n));
+    let document_reference = observation.descendants_named("externalDocument")
+        .into_iter()
+        .find_map(|d| d.child("text").and_then(|t| t.child("reference")).and_then(|r| r.attr("value")))
+        .map(String::from);
+
+    // Stored as coded, so display text is encoded here rather than by the analysis pipeline
+    let code_display = code.and_then(|c| c.attr("displayName")).map(injection::encode_output);
+    let value_display = value.and_then(|v| v.attr("displayName").map(String::from).or_else(|| {
+        // xsi:type ST carries the value as element text
+        Some(narrative_text(v)).filter(|t| !t.is_empty())
+    })).map(|d| injection::encode_output(&d));
+    let value_code = value.and_then(|v| v.attr("code")).map(String::from);
+    CdaDirectiveEntry {
+        entry_id: observation.child("id").and_then(instance_id),
+        directive_type: directive_type(value_code.as_deref(), &[code_display.as_deref(), value_display.as_deref()]),
+        code: code.and_then(|c| c.attr("code")).map(String::from),
+        code_system: code.and_then(|c| c.attr("codeSystem")).map(String::from),
+        code_display,
+        value_code,
+        value_display,
+        value_boolean: value.and_then(|v| v.attr("value")).and_then(|v| v.parse().ok()),
+        negated: observation.attr("negationInd") == Some("true"),
+        status: observation.child("statusCode").and_then(|s| s.attr("code")).map(String::from),
+        effective_from: effective.and_then(|e| e.child("low")).and_then(|l| l.attr("value")).map(String::from),
+        effective_to: effective.and_then(|e| e.child("high")).and_then(|h| h.attr("value")).map(String::from),
+        custodian,
+        document_reference,
+    }
+}
+
+// SNOMED CT 304253006 "Not for resuscitation" is unambiguous; anything else is mapped from its display names
+fn directive_type(value_code: Option<&str>, displays: &[Option<&str>]) -> Option<String> {
+    if value_code == Some("304253006") {
+        return Some("DNR".to_string());
+    }
+    let display = displays.iter().flatten().copied().collect::<Vec<_>>().join(" ").to_lowercase();
+    // "Resuscitation" on its own says a preference was recorded, not which one
+    let directive_type = if ["not for resuscitation", "do not resuscitate", "dnr", "no cpr"].iter().any(|p| display.contains(p)) {
+        "DNR"
+    } else if display.contains("organ") || display.contains("tissue donation") {
+        "ORGAN_DONATION"
+    } else if display.contains("attorney") || display.contains("proxy") || display.contains("agent") || display.contains("surrogate") {
+        "POWER_OF_ATTORNEY"
+    } else if display.contains("research") {
+        "DATA_CONSENT"
+    } else if display.contains("living will")
+        || display.contains("intubation")
+        || display.contains("tube feeding")
+        || display.contains("life support")
+        || display.contains("advance directive")
+    {
+        "LIVING_WILL"
+    } else {
+        return None;
+    };
+    Some(directive_type.to_string())
+}
+
+fn instance_id(id: &Element) -> Option<String> {
+    match (id.attr("root"), id.attr("extension")) {
+        (Some(root), Some(extension)) => Some(format!("{}^{}", root, extension)),
+        (Some(root), None) => Some(root.to_string()),
+        (None, Some(extension)) => Some(extension.to_string()),
+        (None, None) => None,
+    }
+}
+
+// The narrative block flattened to prose, one line per paragraph, list item or table row
+fn narrative_text(element: &Element) -> String {
+    fn collect(element: &Element, out: &mut String) {
+        for child in &element.children {
+            match child {
+                Node::Text(text) => out.push_str(text),
+                Node::Element(child) => {
+                    let block = BLOCK_ELEMENTS.contains(&child.name.as_str());
+                    if block && !out.ends_with('\n') {
+                        out.push('\n');
+                    }
+                    collect(child, out);
+                    if child.name == "td" || child.name == "th" {
+                        out.push(' ');
+                    }
+                    if block && !out.ends_with('\n') {
+                        out.push('\n');
+                    }
+                }
+            }
+        }
+    }
+    let mut text = String::new();
+    collect(element, &mut text);
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+struct Element {
+    name: String, // Local name; namespace prefixes are dropped
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(n, _)| local_name(n) == name).map(|(_, v)| v.as_str())
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find_map(|c| match c {
+            Node::Element(e) if e.name == name => Some(e),
+            _ => None,
+        })
+    }
+
+    fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter_map(move |c| match c {
+            Node::Element(e) if e.name == name => Some(e),
+            _ => None,
+        })
+    }
+
+    // Depth-first, document order; depth is bounded by the parser
+    fn descendants_named(&self, name: &str) -> Vec<&Element> {
+        let mut found = Vec::new();
+        for child in &self.children {
+            if let Node::Element(e) = child {
+                if e.name == name {
+                    found.push(e);
+                }
+                found.extend(e.descendants_named(name));
+            }
+        }
+        found
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+// A small non-validating XML reader: enough for well-formed C-CDA, with no DTDs, external
+// entities or processing instructions honored
+fn parse(xml: &str) -> Result<Element, String> {
+    let malformed = |reason: &str| validation::invalid("document", reason);
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root = None;
+    let mut rest = xml;
+
+    while !rest.is_empty() {
+        let Some(open) = rest.find('<') else {
+            if !rest.trim().is_empty() && !stack.is_empty() {
+                return Err(malformed("unexpected end of document"));
+            }
+            break;
+        };
+        let text = &rest[..open];
+        if let Some(parent) = stack.last_mut() {
+            if !text.is_empty() {
+                parent.children.push(Node::Text(decode_entities(text)?));
+            }
+        }
+        rest = &rest[open..];
+
+        if let Some(after) = rest.strip_prefix("<!--") {
+            let end = after.find("-->").ok_or_else(|| malformed("unterminated comment"))?;
+            rest = &after[end + 3..];
+        } else if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = after.find("]]>").ok_or_else(|| malformed("unterminated CDATA section"))?;
+            if let Some(parent) = stack.last_mut() {
+                parent.children.push(Node::Text(after[..end].to_string()));
+            }
+            rest = &after[end + 3..];
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            let end = rest.find('>').ok_or_else(|| malformed("unterminated declaration"))?;
+            rest = &rest[end + 1..];
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>').ok_or_else(|| malformed("unterminated end tag"))?;
+            let name = local_name(after[..end].trim()).to_string();
+            let element = stack.pop().ok_or_else(|| malformed("end tag without a start tag"))?;
+            if element.name != name {
+                return Err(malformed(&format!("</{}> closes <{}>", name, element.name)));
+            }
+            attach(&mut stack, &mut root, element)?;
+            rest = &after[end + 1..];
+        } else {
+            let end = tag_end(rest).ok_or_else(|| malformed("unterminated start tag"))?;
+            let tag = &rest[1..end];
+            let (tag, self_closing) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let element = start_tag(tag)?;
+            if stack.len() >= MAX_DEPTH {
+                return Err(malformed(&format!("nested deeper than {} elements", MAX_DEPTH)));
+            }
+            if self_closing {
+                attach(&mut stack, &mut root, element)?;
+            } else {
+                stack.push(element);
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+
+    if !stack.is_empty() {
+        return Err(malformed("unclosed elements at end of document"));
+    }
+    root.ok_or_else(|| malformed("no root element"))
+}
+
+fn attach(stack: &mut [Element], root: &mut Option<Element>, element: Element) -> Result<(), String> {
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(Node::Element(element)),
+        None if root.is_none() => *root = Some(element),
+        None => return Err(validation::invalid("document", "more than one root element")),
+    }
+    Ok(())
+}
+
+// The closing '>' of a start tag, skipping any inside quoted attribute values
+fn tag_end(tag: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in tag.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn start_tag(tag: &str) -> Result<Element, String> {
+    let malformed = |reason: &str| validation::invalid("document", reason);
+    let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+    let name = &tag[..name_end];
+    if name.is_empty() {
+        return Err(malformed("element without a name"));
+    }
+
+    let mut attributes = Vec::new();
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest.find('=').ok_or_else(|| malformed(&format!("attribute without a value in <{}>", name)))?;
+        let attribute = rest[..eq].trim().to_string();
+        let value = rest[eq + 1..].trim_start();
+        let quote = value.chars().next().filter(|q| *q == '"' || *q == '\'')
+            .ok_or_else(|| malformed(&format!("unquoted attribute {} in <{}>", attribute, name)))?;
+        let close = value[1..].find(quote).ok_or_else(|| malformed(&format!("unterminated attribute {}", attribute)))?;
+        attributes.push((attribute, decode_entities(&value[1..close + 1])?));
+        rest = value[close + 2..].trim_start();
+    }
+
+    Ok(Element { name: local_name(name).to_string(), attributes, children: Vec::new() })
+}
+
+// The five predefined entities and character references; nothing else is defined without a DTD
+fn decode_entities(text: &str) -> Result<String, String> {
+    if !text.contains('&') {
+        return Ok(text.to_string());
+    }
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        let after = &rest[amp + 1..];
+        let end = after.find(';').filter(|end| *end <= 10)
+            .ok_or_else(|| validation::invalid("document", "unterminated entity reference"))?;
+        let entity = &after[..end];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X"))
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        decoded.push(c.ok_or_else(|| validation::invalid("document", &format!("unknown entity &{};", entity)))?);
+        rest = &after[end + 1..];
+    }
+    decoded.push_str(rest);
+    Ok(decoded)
+}
@@ -0,0 +1,173 @@
+use ic_cdk::{call, caller};
+use ic_cdk::api::call::RejectionCode;
+use ic_cdk_macros::update;
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::replay::{self, DecisionRuleset};
+use crate::{
+    assemble_on_chain_analysis, assess_legal_validity, contains_complex_medical_terms, detect_contraindications,
+    join_keywords, match_directive_types, scoring, traps, ExtractedDirective, MedicalDirectiveAnalysis,
+};
+
+// Above this the extraction scans are split across self-calls, so no single message runs out of instructions
+pub(crate) const CHUNKED_THRESHOLD_BYTES: usize = 64 * 1024;
+const WINDOW_BYTES: usize = 32 * 1024;
+// Longer than any dictionary phrase (set_directive_keywords enforces it), so a phrase cut at one
+// window's end is whole in the next
+pub(crate) const WINDOW_OVERLAP_BYTES: usize = 256;
+// Ingress messages are capped at 2MB, which is 64 windows
+const MAX_WINDOWS: usize = 64;
+
+// Unthresholded extraction for one window; keyword spans are still relative to the window
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct WindowAnalysis {
+    pub start: u64,
+    pub candidates: Vec<ExtractedDirective>,
+    pub contraindications: Vec<String>,
+    pub legal_validity_score: f32,
+    pub has_complex_terms: bool,
+}
+
+// Each window runs in its own message with its own instruction budget, under the ruleset the
+// whole document is analyzed with
+#[update]
+fn analyze_window(start: u64, window: String, ruleset_version: u64) -> Result<WindowAnalysis, String> {
+    if caller() != ic_cdk::id() {
+        return Err("Only the LLM canister itself may analyze document windows".to_string());
+    }
+    let ruleset = replay::ruleset(ruleset_version).ok_or(format!("Ruleset not found: {}", ruleset_version))?;
+
+    // Windows are cut from already-normalized text
+    Ok(WindowAnalysis {
+        start,
+        candidates: match_directive_types(&window, &ruleset),
+        contraindications: detect_contraindications(&window),
+        legal_validity_score: assess_legal_validity(&window),
+        has_complex_terms: contains_complex_medical_terms(&window),
+    })
+}
+
+pub(crate) async fn extract_in_windows(text: &str, ruleset: &DecisionRuleset) -> Result<MedicalDirectiveAnalysis, String> {
+    let windows = split_windows(text);
+    if windows.len() > MAX_WINDOWS {
+        return Err(format!(
+            "Directive of {} bytes needs {} windows; at most {} are supported",
+            text.len(),
+            windows.len(),
+            MAX_WINDOWS
+        ));
+    }
+
+    ic_cdk::println!("📄 Long directive ({} bytes) split into {} windows", text.len(), windows.len());
+
+    let mut results = Vec::with_capacity(windows.len());
+    for (start, end) in windows {
+        let (result,): (Result<WindowAnalysis, String>,) =
+            call(ic_cdk::id(), "analyze_window", (start as u64, &text[start..end], ruleset.version))
+                .await
+                .map_err(|(code, msg)| {
+                    if code == RejectionCode::CanisterError {
+                        traps::record_callee_trap("analyze_window");
+                    }
+                    format!("Window at byte {} failed: {}", start, msg)
+                })?;
+        results.push(result?);
+    }
+
+    Ok(merge_windows(results, text.len(), ruleset))
+}
+
+// Byte ranges on char boundaries, each overlapping the previous one by WINDOW_OVERLAP_BYTES
+fn split_windows(text: &str) -> Vec<(usize, usize)> {
+    let mut windows = Vec::new();
+    let mut start = 0;
+    loop {
+        let mut end = (start + WINDOW_BYTES).min(text.len());
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        windows.push((start, end));
+        if end == text.len() {
+            return windows;
+        }
+
+        start = end - WINDOW_OVERLAP_BYTES;
+        while !text.is_char_boundary(start) {
+            start -= 1;
+        }
+    }
+}
+
+// Windows arrive in document order, so the first span recorded for a keyword is its earliest;
+// a repeat from the overlap region is dropped rather than counted twice
+fn merge_windows(windows: Vec<WindowAnalysis>, text_length: usize, ruleset: &DecisionRuleset) -> MedicalDirectiveAnalysis {
+    let mut merged: BTreeMap<String, ExtractedDirective> = BTreeMap::new();
+    let mut contraindications = Vec::new();
+    let mut legal_validity_score: f32 = 0.0;
+    let mut has_complex_terms = false;
+
+    for window in windows {
+        for mut candidate in window.candidates {
+            for span in &mut candidate.keyword_spans {
+                span.start += window.start;
+                span.end += window.start;
+            }
+
+            match merged.get_mut(&candidate.directive_type) {
+                None => {
+                    merged.insert(candidate.directive_type.clone(), candidate);
+                }
+                Some(existing) => {
+                    for span in candidate.keyword_spans {
+                        if !existing.keyword_spans.iter().any(|s| s.keyword == span.keyword) {
+                            existing.keyword_spans.push(span);
+                        }
+                    }
+                    union_into(&mut existing.conditions, candidate.conditions);
+                    union_into(&mut existing.medical_terminology, candidate.medical_terminology);
+                    if candidate.confidence > existing.confidence {
+                        existing.confidence = candidate.confidence;
+                        existing.score_breakdown = candidate.score_breakdown;
+                    }
+                }
+            }
+        }
+
+        union_into(&mut contraindications, window.contraindications);
+        // Attestation clauses sit in one place, so the strongest window speaks for the document;
+        // coercion language elsewhere still surfaces through the contraindications
+        legal_validity_score = legal_validity_score.max(window.legal_validity_score);
+        has_complex_terms |= window.has_complex_terms;
+    }
+
+    // Keywords spread across windows count together, as they would in a single pass
+    for directive in merged.values_mut() {
+        let total_keywords = ruleset.keyword_count(&directive.directive_type);
+        let (combined, breakdown) =
+            scoring::score_with(&ruleset.scoring_features, directive.keyword_spans.len(), total_keywords, "");
+        if combined > directive.confidence {
+            directive.confidence = combined;
+            directive.score_breakdown = breakdown;
+        }
+        directive.extracted_text = join_keywords(&directive.keyword_spans);
+    }
+
+    assemble_on_chain_analysis(
+        ruleset,
+        merged.into_values().collect(),
+        contraindications,
+        legal_validity_score,
+        text_length,
+        has_complex_terms,
+    )
+}
+
+fn union_into(target: &mut Vec<String>, items: Vec<String>) {
+    for item in items {
+        if !target.contains(&item) {
+            target.push(item);
+        }
+    }
+}
@@ -0,0 +1,19 @@
+// Time comes from the shared canister_clock crate; tests, and builds made with
+// the "test-clock" feature, pin it so membership caching, provider health windows and threshold
+// schedules can be exercised without waiting them out. The test endpoint is declared here so this
+// canister's wasm exports it.
+
+pub(crate) use canister_clock::{now};
+
+#[cfg(test)]
+pub(crate) use canister_clock::advance;
+
+// Never compiled into production builds
+#[cfg(feature = "test-clock")]
+#[ic_cdk_macros::update]
+fn set_test_clock(at: Option<u64>, advance_nanos: u64) -> Result<u64, String> {
+    if !ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        return Err("Only canister controllers may set the test clock".to_string());
+    }
+    Ok(canister_clock::apply_test_setting(at, advance_nanos))
+}
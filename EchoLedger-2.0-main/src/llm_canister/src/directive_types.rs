@@ -0,0 +1,452 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::chunking::WINDOW_OVERLAP_BYTES;
+use crate::thresholds::{MAX_CONFIDENCE_THRESHOLD, MIN_CONFIDENCE_THRESHOLD};
+use crate::{validation, DICTIONARY_VERSION};
+
+// Everything on-chain extraction knows about a directive type, in one definition: the keywords
+// that detect it, the patterns that pull its conditions out of the text, its built-in confidence
+// threshold (thresholds.rs schedules changes over it), when it applies and what executes it.
+// The built-in types are seeded from BUILT_IN_TYPES; controllers register further types here
+// without a code change.
+
+// A condition is reported when the text holds every `all_of` phrase, at least one `any_of`
+// phrase (if any are given) and no `none_of` phrase
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ConditionPattern {
+    pub condition: String,
+    pub all_of: Vec<String>,
+    pub any_of: Vec<String>,
+    pub none_of: Vec<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DirectiveTypeDefinition {
+    pub directive_type: String,
+    pub description: String,
+    pub keywords: Vec<String>,
+    pub condition_patterns: Vec<ConditionPattern>,
+    pub confidence_threshold: f32,
+    pub applicability: String, // One of APPLICABILITY
+    pub executor_hook: Option<String>, // One of EXECUTOR_HOOKS; None when nothing runs after death
+    pub built_in: bool,
+}
+
+// When a directive of the type is acted on
+const APPLICABILITY: [&str; 3] = ["EMERGENCY", "INCAPACITY", "POST_DEATH"];
+// BUILT_IN: a step of executor_ai's own; PLUGIN: an executor plugin registered for the type
+const EXECUTOR_HOOKS: [&str; 2] = ["BUILT_IN", "PLUGIN"];
+const MAX_CONDITION_PATTERNS: usize = 32;
+const MAX_PHRASES_PER_PATTERN: usize = 16;
+const MAX_DESCRIPTION_BYTES: usize = 1024;
+const MAX_CONDITION_BYTES: usize = 256;
+
+// (condition, all_of, any_of, none_of)
+type BuiltInPattern = (&'static str, &'static [&'static str], &'static [&'static str], &'static [&'static str]);
+
+struct BuiltInType {
+    directive_type: &'static str,
+    description: &'static str,
+    keywords: &'static [&'static str],
+    condition_patterns: &'static [BuiltInPattern],
+    confidence_threshold: f32,
+    applicability: &'static str,
+    executor_hook: Option<&'static str>,
+}
+
+const AUTOPSY_REFUSALS: &[&str] = &["no autopsy", "refuse", "do not want", "not consent"];
+const ENROLLMENT_REFUSALS: &[&str] = &["not be enrolled", "do not enroll", "refuse"];
+
+const BUILT_IN_TYPES: [BuiltInType; 10] = [
+    BuiltInType {
+        directive_type: "DNR",
+        description: "Do-not-resuscitate and limits on life-sustaining treatment",
+        keywords: &[
+            "do not resuscitate", "dnr", "no resuscitation", "do not revive", "no cpr", "no life support",
+            "no mechanical ventilation", "comfort care only", "palliative care", "end of life",
+        ],
+        condition_patterns: &[
+            ("Recovery probability threshold specified", &["less than"], &["percent", "%"], &[]),
+            ("Terminal condition specified", &[], &["terminal", "end stage"], &[]),
+            ("Persistent vegetative state specified", &[], &["vegetative"], &[]),
+            ("Comfort care preference", &[], &["comfort care", "palliative"], &[]),
+        ],
+        confidence_threshold: 0.85,
+        applicability: "EMERGENCY",
+        executor_hook: None,
+    },
+    BuiltInType {
+        directive_type: "ORGAN_DONATION",
+        description: "Organ and tissue donation after death",
+        keywords: &[
+            "donate organs", "organ donation", "donate my", "kidney", "liver", "heart", "cornea",
+            "tissue donation", "transplant", "organ harvesting",
+        ],
+        condition_patterns: &[
+            ("Kidney donation", &[], &["kidney"], &[]),
+            ("Liver donation", &[], &["liver"], &[]),
+            ("Heart donation", &[], &["heart"], &[]),
+            ("Cornea donation", &[], &["cornea"], &[]),
+            ("Tissue donation", &[], &["tissue"], &[]),
+        ],
+        confidence_threshold: 0.80,
+        applicability: "POST_DEATH",
+        executor_hook: Some("BUILT_IN"),
+    },
+    BuiltInType {
+        directive_type: "DATA_CONSENT",
+        description: "Sharing medical data with research after death",
+        keywords: &[
+            "research", "anonymized data", "medical research", "share data", "cancer research",
+            "genetic studies", "clinical trials", "medical studies",
+        ],
+        condition_patterns: &[
+            ("Anonymization required", &[], &["anonymized"], &[]),
+            ("Cancer research consent", &[], &["cancer"], &[]),
+            ("Genetic research consent", &[], &["genetic"], &[]),
+            ("Clinical trial participation", &[], &["clinical trial"], &[]),
+        ],
+        confidence_threshold: 0.75,
+        applicability: "POST_DEATH",
+        executor_hook: Some("BUILT_IN"),
+    },
+    BuiltInType {
+        directive_type: "POWER_OF_ATTORNEY",
+        description: "A healthcare agent who decides when the patient cannot",
+        keywords: &["power of attorney", "healthcare proxy", "medical decisions", "surrogate", "healthcare agent"],
+        condition_patterns: &[],
+        confidence_threshold: 0.88,
+        applicability: "INCAPACITY",
+        executor_hook: None,
+    },
+    BuiltInType {
+        directive_type: "LIVING_WILL",
+        description: "General treatment wishes for when the patient cannot state them",
+        keywords: &["living will", "advance directive", "healthcare directive", "medical directive", "end-of-life wishes"],
+        condition_patterns: &[],
+        confidence_threshold: 0.82,
+        applicability: "INCAPACITY",
+        executor_hook: None,
+    },
+    BuiltInType {
+        directive_type: "DIGITAL_LEGACY",
+        description: "What happens to online accounts and personal data after death",
+        keywords: &[
+            "digital legacy", "online accounts", "social media", "memorialize", "delete my accounts",
+            "delete my data", "email account", "digital assets", "passwords",
+        ],
+        condition_patterns: &[
+            ("Delete accounts and personal data", &[], &["delete", "erase"], &[]),
+            ("Memorialize accounts", &[], &["memorial"], &[]),
+            ("Transfer account access to a named person", &[], &["transfer", "give access", "pass on"], &[]),
+        ],
+        confidence_threshold: 0.80,
+        applicability: "POST_DEATH",
+        executor_hook: Some("BUILT_IN"),
+    },
+    BuiltInType {
+        directive_type: "DISPOSITION",
+        description: "Funeral and disposition of remains",
+        keywords: &["burial", "buried", "cremation", "cremated", "funeral", "my remains", "ashes"],
+        condition_patterns: &[
+            ("Cremation", &[], &["cremat", "ashes"], &[]),
+            ("Burial", &[], &["buri"], &["cremat"]),
+            ("Whole-body donation to science", &[], &["donate my body", "body donation", "to science"], &[]),
+            ("Religious requirements specified", &[], &["religious", "rites", "within 24 hours"], &[]),
+            ("Preferred funeral home named", &[], &["funeral home"], &[]),
+        ],
+        confidence_threshold: 0.80,
+        applicability: "POST_DEATH",
+        executor_hook: Some("BUILT_IN"),
+    },
+    BuiltInType {
+        directive_type: "WHOLE_BODY_DONATION",
+        description: "Donation of the whole body to an anatomical program",
+        keywords: &[
+            "donate my body", "body donation", "whole-body donation", "anatomical gift", "anatomical donation",
+            "to science", "medical school", "willed body",
+        ],
+        condition_patterns: &[
+            ("Anatomical program at a medical school", &[], &["medical school", "anatomy"], &[]),
+            ("Organ donation also requested", &["organ"], &["first", "also"], &[]),
+            ("Cremated remains returned to family", &["return"], &["ashes", "cremains"], &[]),
+        ],
+        confidence_threshold: 0.82,
+        applicability: "POST_DEATH",
+        executor_hook: Some("BUILT_IN"),
+    },
+    BuiltInType {
+        directive_type: "AUTOPSY",
+        description: "Consent to or refusal of autopsy",
+        keywords: &["autopsy", "post-mortem", "postmortem", "post mortem", "necropsy"],
+        condition_patterns: &[
+            ("Autopsy refused", &[], AUTOPSY_REFUSALS, &[]),
+            ("Autopsy consented", &[], &["consent", "agree", "permit"], AUTOPSY_REFUSALS),
+            ("Religious objection", &[], &["religio", "faith"], &[]),
+            ("Limited or external examination only", &[], &["limited", "external"], &[]),
+            ("Imaging in place of dissection acceptable", &[], &["imaging", "scan"], &[]),
+        ],
+        confidence_threshold: 0.85,
+        applicability: "POST_DEATH",
+        executor_hook: None,
+    },
+    BuiltInType {
+        directive_type: "RESEARCH_ENROLLMENT",
+        description: "Enrollment in emergency research under an exception from informed consent",
+        keywords: &[
+            "emergency research", "experimental treatment", "research study", "without my consent",
+            "exception from informed consent", "enroll me", "enrolled in",
+        ],
+        condition_patterns: &[
+            ("Emergency research enrollment refused", &[], ENROLLMENT_REFUSALS, &[]),
+            ("Emergency research enrollment pre-consented", &[], &["willing", "agree", "consent"], ENROLLMENT_REFUSALS),
+            ("Cardiac arrest trials", &[], &["cardiac arrest"], &[]),
+            ("Stroke trials", &[], &["stroke"], &[]),
+            ("Trauma trials", &[], &["trauma", "brain injury"], &[]),
+        ],
+        confidence_threshold: 0.85,
+        applicability: "EMERGENCY",
+        executor_hook: None,
+    },
+];
+
+// Carried across upgrades so thresholds and overrides set for a registered type still resolve. Built-in
+// types keep the release's definition and take back only their saved keywords, which controllers edit.
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct DirectiveTypeState {
+    definitions: Vec<DirectiveTypeDefinition>,
+    dictionary_version: u64,
+}
+
+thread_local! {
+    static DIRECTIVE_TYPES: RefCell<BTreeMap<String, DirectiveTypeDefinition>> = RefCell::new(
+        BUILT_IN_TYPES.iter().map(|t| (t.directive_type.to_string(), built_in(t))).collect()
+    );
+}
+
+// Adds a directive type or replaces one's definition; returns the new dictionary version
+#[update]
+fn register_directive_type(definition: DirectiveTypeDefinition) -> Result<u64, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may register directive types".to_string());
+    }
+    let directive_type = validation::identifier("directive_type", &definition.directive_type)?.to_uppercase();
+    if !directive_type.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_') {
+        return Err(validation::invalid("directive_type", "must be letters, digits and underscores"));
+    }
+    let description = validation::text("description", &definition.description, MAX_DESCRIPTION_BYTES)?;
+    let keywords = checked_keywords(&definition.keywords)?;
+    validation::collection("condition_patterns", definition.condition_patterns.len(), MAX_CONDITION_PATTERNS)?;
+    let condition_patterns = definition.condition_patterns.iter().map(checked_pattern).collect::<Result<Vec<_>, _>>()?;
+    if !(MIN_CONFIDENCE_THRESHOLD..=MAX_CONFIDENCE_THRESHOLD).contains(&definition.confidence_threshold) {
+        return Err(validation::invalid(
+            "confidence_threshold",
+            &format!("must be within {}-{}", MIN_CONFIDENCE_THRESHOLD, MAX_CONFIDENCE_THRESHOLD),
+        ));
+    }
+    let applicability = definition.applicability.trim().to_uppercase();
+    if !APPLICABILITY.contains(&applicability.as_str()) {
+        return Err(validation::invalid("applicability", &format!("must be one of {}", APPLICABILITY.join(", "))));
+    }
+    let executor_hook = definition.executor_hook.map(|h| h.trim().to_uppercase());
+    if executor_hook.as_deref().is_some_and(|h| !EXECUTOR_HOOKS.contains(&h)) {
+        return Err(validation::invalid("executor_hook", &format!("must be one of {}", EXECUTOR_HOOKS.join(", "))));
+    }
+
+    DIRECTIVE_TYPES.with(|t| {
+        let mut types = t.borrow_mut();
+        let built_in = types.get(&directive_type).is_some_and(|existing| existing.built_in);
+        types.insert(directive_type.clone(), DirectiveTypeDefinition {
+            directive_type: directive_type.clone(),
+            description,
+            keywords,
+            condition_patterns,
+            confidence_threshold: definition.confidence_threshold,
+            applicability,
+            executor_hook,
+            built_in,
+        });
+    });
+    ic_cdk::println!("AUDIT: Directive type {} registered by {}", directive_type, caller());
+    Ok(bump_dictionary_version())
+}
+
+// Only registered types can be removed; the built-in ones are what the rest of EchoLedger expects
+#[update]
+fn remove_directive_type(directive_type: String) -> Result<u64, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may remove directive types".to_string());
+    }
+    DIRECTIVE_TYPES.with(|t| {
+        let mut types = t.borrow_mut();
+        match types.get(&directive_type) {
+            None => Err(format!("Unknown directive type: {}", directive_type)),
+            Some(definition) if definition.built_in => Err(format!("{} is built in and cannot be removed", directive_type)),
+            Some(_) => {
+                types.remove(&directive_type);
+                Ok(())
+            }
+        }
+    })?;
+    ic_cdk::println!("AUDIT: Directive type {} removed by {}", directive_type, caller());
+    Ok(bump_dictionary_version())
+}
+
+// Replace one directive type's keyword list; the matcher picks the change up on the next scan
+#[update]
+fn set_directive_keywords(directive_type: String, keywords: Vec<String>) -> Result<u64, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may change the keyword dictionary".to_string());
+    }
+    let directive_type = validation::identifier("directive_type", &directive_type)?;
+    let keywords = checked_keywords(&keywords)?;
+    DIRECTIVE_TYPES.with(|t| {
+        let mut types = t.borrow_mut();
+        let definition = types.get_mut(&directive_type).ok_or_else(|| format!("Unknown directive type: {}", directive_type))?;
+        definition.keywords = keywords;
+        Ok::<_, String>(())
+    })?;
+    Ok(bump_dictionary_version())
+}
+
+#[query]
+fn get_directive_type_registry() -> Vec<DirectiveTypeDefinition> {
+    DIRECTIVE_TYPES.with(|t| t.borrow().values().cloned().collect())
+}
+
+#[query]
+fn get_supported_directive_types() -> Vec<String> {
+    DIRECTIVE_TYPES.with(|t| t.borrow().keys().cloned().collect())
+}
+
+pub(crate) fn is_known(directive_type: &str) -> bool {
+    DIRECTIVE_TYPES.with(|t| t.borrow().contains_key(directive_type))
+}
+
+pub(crate) fn keywords(directive_type: &str) -> Option<Vec<String>> {
+    DIRECTIVE_TYPES.with(|t| t.borrow().get(directive_type).map(|d| d.keywords.clone()))
+}
+
+// Every type's keywords, in type order
+pub(crate) fn all_keywords() -> BTreeMap<String, Vec<String>> {
+    DIRECTIVE_TYPES.with(|t| t.borrow().iter().map(|(k, d)| (k.clone(), d.keywords.clone())).collect())
+}
+
+// The type's own threshold, before any scheduled change
+pub(crate) fn base_threshold(directive_type: &str) -> Option<f32> {
+    DIRECTIVE_TYPES.with(|t| t.borrow().get(directive_type).map(|d| d.confidence_threshold))
+}
+
+// The conditions the type's patterns find in normalized text, in pattern order
+pub(crate) fn extract_conditions(text: &str, directive_type: &str) -> Vec<String> {
+    DIRECTIVE_TYPES.with(|t| {
+        let types = t.borrow();
+        let Some(definition) = types.get(directive_type) else {
+            return Vec::new();
+        };
+        definition.condition_patterns.iter()
+            .filter(|p| {
+                p.all_of.iter().all(|phrase| text.contains(phrase.as_str()))
+                    && (p.any_of.is_empty() || p.any_of.iter().any(|phrase| text.contains(phrase.as_str())))
+                    && !p.none_of.iter().any(|phrase| text.contains(phrase.as_str()))
+            })
+            .map(|p| p.condition.clone())
+            .collect()
+    })
+}
+
+fn built_in(t: &BuiltInType) -> DirectiveTypeDefinition {
+    let phrases = |list: &[&str]| list.iter().map(|p| p.to_string()).collect();
+    DirectiveTypeDefinition {
+        directive_type: t.directive_type.to_string(),
+        description: t.description.to_string(),
+        keywords: phrases(t.keywords),
+        condition_patterns: t.condition_patterns.iter().map(|(condition, all_of, any_of, none_of)| ConditionPattern {
+            condition: condition.to_string(),
+            all_of: phrases(all_of),
+            any_of: phrases(any_of),
+            none_of: phrases(none_of),
+        }).collect(),
+        confidence_threshold: t.confidence_threshold,
+        applicability: t.applicability.to_string(),
+        executor_hook: t.executor_hook.map(String::from),
+        built_in: true,
+    }
+}
+
+fn checked_keywords(keywords: &[String]) -> Result<Vec<String>, String> {
+    validation::collection("keywords", keywords.len(), validation::MAX_KEYWORDS_PER_TYPE)?;
+    if keywords.is_empty() {
+        return Err("A directive type needs at least one keyword".to_string());
+    }
+    // A keyword longer than the window overlap could be split across windows and never seen whole
+    if let Some(keyword) = keywords.iter().find(|k| k.trim().is_empty() || k.len() > WINDOW_OVERLAP_BYTES) {
+        return Err(format!("Keyword {:?} must be non-empty and at most {} bytes", keyword, WINDOW_OVERLAP_BYTES));
+    }
+    Ok(keywords.iter().map(|k| k.trim().to_lowercase()).collect())
+}
+
+// Phrases are matched against lowercased text, so they are stored lowercased
+fn checked_pattern(pattern: &ConditionPattern) -> Result<ConditionPattern, String> {
+    let condition = validation::identifier("condition_patterns.condition", &pattern.condition)?;
+    if condition.len() > MAX_CONDITION_BYTES {
+        return Err(validation::invalid("condition_patterns.condition", &format!("longer than {} bytes", MAX_CONDITION_BYTES)));
+    }
+    let phrases = |field: &str, list: &[String]| -> Result<Vec<String>, String> {
+        validation::collection(field, list.len(), MAX_PHRASES_PER_PATTERN)?;
+        list.iter().map(|p| validation::identifier(field, p).map(|p| p.to_lowercase())).collect()
+    };
+    let all_of = phrases("condition_patterns.all_of", &pattern.all_of)?;
+    let any_of = phrases("condition_patterns.any_of", &pattern.any_of)?;
+    if all_of.is_empty() && any_of.is_empty() {
+        return Err(validation::invalid("condition_patterns", &format!("{:?} needs an all_of or any_of phrase", condition)));
+    }
+    Ok(ConditionPattern {
+        condition,
+        all_of,
+        any_of,
+        none_of: phrases("condition_patterns.none_of", &pattern.none_of)?,
+    })
+}
+
+fn bump_dictionary_version() -> u64 {
+    DICTIONARY_VERSION.with(|v| {
+        let mut v = v.borrow_mut();
+        *v += 1;
+        *v
+    })
+}
+
+pub(crate) fn snapshot() -> DirectiveTypeState {
+    DirectiveTypeState {
+        definitions: DIRECTIVE_TYPES.with(|t| t.borrow().values().cloned().collect()),
+        dictionary_version: DICTIONARY_VERSION.with(|v| *v.borrow()),
+    }
+}
+
+// The version moves past the saved one, so nothing compiled against the old dictionary is reused
+pub(crate) fn restore(state: Option<DirectiveTypeState>) {
+    let Some(state) = state else {
+        return;
+    };
+    DIRECTIVE_TYPES.with(|t| {
+        let mut types = t.borrow_mut();
+        for saved in state.definitions {
+            match types.get_mut(&saved.directive_type) {
+                Some(current) if current.built_in => current.keywords = saved.keywords,
+                // A type that was built in and no longer is stays retired
+                None if saved.built_in => {}
+                _ => {
+                    types.insert(saved.directive_type.clone(), saved);
+                }
+            }
+        }
+    });
+    DICTIONARY_VERSION.with(|v| *v.borrow_mut() = state.dictionary_version);
+    bump_dictionary_version();
+}
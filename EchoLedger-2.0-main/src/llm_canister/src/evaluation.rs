@@ -0,0 +1,320 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::cell::RefCell;
+
+use crate::chunking::WINDOW_OVERLAP_BYTES;
+use crate::injection;
+use crate::validation;
+use crate::scoring::{self, ScoringFeature};
+use crate::thresholds::{self, MAX_CONFIDENCE_THRESHOLD, MIN_CONFIDENCE_THRESHOLD};
+use crate::{clock, directive_types, preprocess_medical_text, tenant_config, DICTIONARY_VERSION};
+
+// A labeled directive: the types a careful human reviewer extracted from the text
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EvaluationExample {
+    pub example_id: String,
+    pub text: String,
+    pub expected_directive_types: Vec<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EvaluationDataset {
+    pub dataset_id: String,
+    pub description: String,
+    pub examples: Vec<EvaluationExample>,
+    pub uploaded_by: Principal,
+    pub uploaded_at: u64,
+}
+
+// Everything on-chain extraction depends on, frozen so a change can be scored before it goes live
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Ruleset {
+    pub version: u64,
+    pub label: String,
+    pub keywords: BTreeMap<String, Vec<String>>,
+    pub confidence_thresholds: BTreeMap<String, f32>,
+    pub scoring_features: Vec<ScoringFeature>,
+    pub created_by: Principal,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TypeMetrics {
+    pub directive_type: String,
+    pub true_positives: u32,
+    pub false_positives: u32,
+    pub false_negatives: u32,
+    pub precision: f32,
+    pub recall: f32,
+    pub f1: f32,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EvaluationRun {
+    pub run_id: u64,
+    pub dataset_id: String,
+    pub ruleset_version: Option<u64>, // None: the live configuration when the run happened
+    pub dictionary_version: u64,
+    pub examples_evaluated: u32,
+    pub per_type: Vec<TypeMetrics>,
+    pub micro_f1: f32,
+    pub run_by: Principal,
+    pub run_at: u64,
+}
+
+thread_local! {
+    static EVALUATION_DATASETS: RefCell<BTreeMap<String, EvaluationDataset>> = const { RefCell::new(BTreeMap::new()) };
+    static RULESETS: RefCell<BTreeMap<u64, Ruleset>> = const { RefCell::new(BTreeMap::new()) };
+    static EVALUATION_RUNS: RefCell<BTreeMap<u64, EvaluationRun>> = const { RefCell::new(BTreeMap::new()) };
+    static NEXT_RUN_ID: RefCell<u64> = const { RefCell::new(0) };
+}
+
+// A whole dataset is scored in one message, so both bounds keep a run inside the instruction limit
+pub(crate) const MAX_EXAMPLES_PER_DATASET: usize = 500;
+const MAX_EXAMPLE_BYTES: usize = 16 * 1024;
+const MAX_DESCRIPTION_BYTES: usize = 1024;
+const MAX_RUNS_KEPT: usize = 200;
+
+// Replaces any dataset with the same id
+#[update]
+fn upload_evaluation_dataset(
+    dataset_id: String,
+    description: String,
+    examples: Vec<EvaluationExample>,
+) -> Result<u32, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may upload evaluation datasets".to_string());
+    }
+    store_dataset(dataset_id, description, examples)
+}
+
+// Shared with the synthetic generator, which builds its datasets in place
+pub(crate) fn store_dataset(dataset_id: String, description: String, examples: Vec<EvaluationExample>) -> Result<u32, String> {
+    let dataset_id = validation::identifier("dataset_id", &dataset_id)?;
+    let description = validation::text("description", &description, MAX_DESCRIPTION_BYTES)?;
+    if examples.is_empty() || examples.len() > MAX_EXAMPLES_PER_DATASET {
+        return Err(format!("Datasets hold between 1 and {} examples", MAX_EXAMPLES_PER_DATASET));
+    }
+    let mut validated = Vec::with_capacity(examples.len());
+    for example in examples {
+        let example_id = validation::identifier("example_id", &example.example_id)?;
+        let text = validation::text(&format!("text of example {}", example_id), &example.text, MAX_EXAMPLE_BYTES)?;
+        if let Some(unknown) = example.expected_directive_types.iter().find(|t| !directive_types::is_known(t)) {
+            return Err(format!("Example {} expects unknown directive type {}", example_id, unknown));
+        }
+        // Stored as the live path would see it, with hidden characters already stripped
+        validated.push(EvaluationExample {
+            example_id,
+            text: injection::sanitize_input(&text).0,
+            expected_directive_types: example.expected_directive_types,
+        });
+    }
+    let examples = validated;
+    let count = examples.len() as u32;
+    EVALUATION_DATASETS.with(|d| d.borrow_mut().insert(dataset_id.clone(), EvaluationDataset {
+        dataset_id,
+        description,
+        examples,
+        uploaded_by: caller(),
+        uploaded_at: clock::now(),
+    }));
+    Ok(count)
+}
+
+// A candidate ruleset: the live configuration with any of its parts replaced
+#[update]
+fn save_ruleset(
+    label: String,
+    keywords: Option<BTreeMap<String, Vec<String>>>,
+    confidence_thresholds: Option<BTreeMap<String, f32>>,
+    scoring_features: Option<Vec<ScoringFeature>>,
+) -> Result<Ruleset, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may save rulesets".to_string());
+    }
+    let live = live_ruleset();
+    let keywords = match keywords {
+        None => live.keywords,
+        Some(keywords) => {
+            let mut normalized = BTreeMap::new();
+            for (directive_type, list) in keywords {
+                // Same bound as set_directive_keywords, so the ruleset could be promoted as is
+                if let Some(keyword) = list.iter().find(|k| k.trim().is_empty() || k.len() > WINDOW_OVERLAP_BYTES) {
+                    return Err(format!("Keyword {:?} must be non-empty and at most {} bytes", keyword, WINDOW_OVERLAP_BYTES));
+                }
+                if list.is_empty() {
+                    return Err(format!("{} needs at least one keyword", directive_type));
+                }
+                normalized.insert(directive_type, list.iter().map(|k| k.trim().to_lowercase()).collect());
+            }
+            normalized
+        }
+    };
+    let confidence_thresholds = confidence_thresholds.unwrap_or(live.confidence_thresholds);
+    if let Some((directive_type, _)) = confidence_thresholds
+        .iter()
+        .find(|(_, t)| !(MIN_CONFIDENCE_THRESHOLD..=MAX_CONFIDENCE_THRESHOLD).contains(*t))
+    {
+        return Err(format!(
+            "Threshold for {} must be within {}-{}",
+            directive_type, MIN_CONFIDENCE_THRESHOLD, MAX_CONFIDENCE_THRESHOLD
+        ));
+    }
+    let scoring_features = match scoring_features {
+        None => live.scoring_features,
+        Some(features) if features.len() > scoring::MAX_FEATURES => {
+            return Err(format!("At most {} scoring features", scoring::MAX_FEATURES));
+        }
+        Some(features) => features.into_iter().map(scoring::validate_feature).collect::<Result<_, _>>()?,
+    };
+
+    let ruleset = RULESETS.with(|r| {
+        let mut rulesets = r.borrow_mut();
+        let ruleset = Ruleset {
+            version: rulesets.keys().next_back().map_or(1, |v| v + 1),
+            label,
+            keywords,
+            confidence_thresholds,
+            scoring_features,
+            created_by: caller(),
+            created_at: clock::now(),
+        };
+        rulesets.insert(ruleset.version, ruleset.clone());
+        ruleset
+    });
+    Ok(ruleset)
+}
+
+// Scores a dataset under a saved ruleset, or the live configuration for None
+#[update]
+fn run_evaluation(dataset_id: String, ruleset_version: Option<u64>) -> Result<EvaluationRun, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may run evaluations".to_string());
+    }
+    let dataset = EVALUATION_DATASETS.with(|d| d.borrow().get(&dataset_id).cloned())
+        .ok_or(format!("Evaluation dataset not found: {}", dataset_id))?;
+    let ruleset = match ruleset_version {
+        Some(version) => RULESETS.with(|r| r.borrow().get(&version).cloned())
+            .ok_or(format!("Ruleset not found: {}", version))?,
+        None => live_ruleset(),
+    };
+
+    // directive_type -> (true positives, false positives, false negatives)
+    let mut counts: BTreeMap<String, (u32, u32, u32)> =
+        ruleset.keywords.keys().map(|t| (t.clone(), (0, 0, 0))).collect();
+    for example in &dataset.examples {
+        let expected: BTreeSet<String> = example.expected_directive_types.iter().cloned().collect();
+        let extracted = extract_types(&example.text, &ruleset)?;
+        for directive_type in extracted.union(&expected) {
+            let entry = counts.entry(directive_type.clone()).or_default();
+            match (extracted.contains(directive_type), expected.contains(directive_type)) {
+                (true, true) => entry.0 += 1,
+                (true, false) => entry.1 += 1,
+                _ => entry.2 += 1,
+            }
+        }
+    }
+
+    let per_type: Vec<TypeMetrics> = counts.iter().map(|(t, (tp, fp, fn_))| metrics(t, *tp, *fp, *fn_)).collect();
+    let (tp, fp, fn_) = counts.values().fold((0, 0, 0), |acc, c| (acc.0 + c.0, acc.1 + c.1, acc.2 + c.2));
+    let run = EvaluationRun {
+        run_id: NEXT_RUN_ID.with(|id| {
+            let mut id = id.borrow_mut();
+            *id += 1;
+            *id
+        }),
+        dataset_id,
+        ruleset_version,
+        dictionary_version: DICTIONARY_VERSION.with(|v| *v.borrow()),
+        examples_evaluated: dataset.examples.len() as u32,
+        per_type,
+        micro_f1: metrics("", tp, fp, fn_).f1,
+        run_by: caller(),
+        run_at: clock::now(),
+    };
+    EVALUATION_RUNS.with(|r| {
+        let mut runs = r.borrow_mut();
+        runs.insert(run.run_id, run.clone());
+        while runs.len() > MAX_RUNS_KEPT {
+            runs.pop_first();
+        }
+    });
+    Ok(run)
+}
+
+#[query]
+fn get_evaluation_runs(dataset_id: Option<String>, limit: u32) -> Result<Vec<EvaluationRun>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may read evaluation results".to_string());
+    }
+    Ok(EVALUATION_RUNS.with(|r| {
+        r.borrow()
+            .values()
+            .rev()
+            .filter(|run| dataset_id.as_ref().is_none_or(|d| *d == run.dataset_id))
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    }))
+}
+
+#[query]
+fn get_rulesets() -> Result<Vec<Ruleset>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may read rulesets".to_string());
+    }
+    Ok(RULESETS.with(|r| r.borrow().values().cloned().collect()))
+}
+
+// The global configuration in force right now; tenant overrides are not part of a ruleset
+fn live_ruleset() -> Ruleset {
+    let now = clock::now();
+    let keywords = directive_types::all_keywords();
+    let confidence_thresholds = keywords.keys()
+        .filter_map(|t| thresholds::global_threshold(t, now).map(|value| (t.clone(), value)))
+        .collect();
+    Ruleset {
+        version: 0,
+        label: "live".to_string(),
+        keywords,
+        confidence_thresholds,
+        scoring_features: scoring::pipeline(),
+        created_by: ic_cdk::id(),
+        created_at: now,
+    }
+}
+
+// The on-chain extraction decision under a given ruleset. Substring search finds exactly
+// what the live overlapping automaton finds, without compiling one per ruleset.
+fn extract_types(text: &str, ruleset: &Ruleset) -> Result<BTreeSet<String>, String> {
+    let text = preprocess_medical_text(text)?;
+    Ok(ruleset.keywords.iter().filter_map(|(directive_type, keywords)| {
+        let matches = keywords.iter().filter(|k| text.contains(k.as_str())).count();
+        if matches == 0 {
+            return None;
+        }
+        let (confidence, _) = scoring::score_with(&ruleset.scoring_features, matches, keywords.len(), &text);
+        let threshold = ruleset.confidence_thresholds.get(directive_type).copied()
+            .unwrap_or(tenant_config::DEFAULT_CONFIDENCE_THRESHOLD);
+        (confidence >= threshold).then(|| directive_type.clone())
+    }).collect())
+}
+
+fn metrics(directive_type: &str, tp: u32, fp: u32, fn_: u32) -> TypeMetrics {
+    let ratio = |num: u32, den: u32| if den == 0 { 0.0 } else { num as f32 / den as f32 };
+    let precision = ratio(tp, tp + fp);
+    let recall = ratio(tp, tp + fn_);
+    let f1 = if precision + recall == 0.0 { 0.0 } else { 2.0 * precision * recall / (precision + recall) };
+    TypeMetrics {
+        directive_type: directive_type.to_string(),
+        true_positives: tp,
+        false_positives: fp,
+        false_negatives: fn_,
+        precision,
+        recall,
+        f1,
+    }
+}
@@ -0,0 +1,140 @@
+use aho_corasick::{AhoCorasick, MatchKind};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::replay::DecisionRuleset;
+use crate::{directive_types, tenant_config, KeywordSpan, DICTIONARY_VERSION, MEDICAL_TERMINOLOGY};
+
+// Which dictionary a pattern belongs to; names are interned once per build and shared by every pattern
+enum PatternSource {
+    Keyword { directive_type: Rc<str> },
+    Terminology { label: Rc<str> },
+}
+
+// Both dictionaries compiled into one automaton, so a document is scanned once however many terms there are
+struct DictionaryMatcher {
+    version: (u64, u64), // (dictionary, tenant overrides)
+    automaton: AhoCorasick,
+    patterns: Vec<(PatternSource, Rc<str>)>,
+}
+
+pub(crate) struct DictionaryMatches {
+    // By directive type; first occurrence of each keyword, in dictionary order
+    pub keywords: BTreeMap<Rc<str>, Vec<KeywordSpan>>,
+    // "category: term" for every terminology entry present
+    pub terminology: Vec<String>,
+}
+
+thread_local! {
+    // The global dictionary under None; a tenant gets its own automaton only once it extends the keywords
+    static MATCHERS: RefCell<BTreeMap<Option<String>, Rc<DictionaryMatcher>>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// The compiled automaton while the ruleset is still the live dictionary; one recorded before the
+// dictionary moved on is scanned plainly instead
+pub(crate) fn scan(text_lower: &str, ruleset: &DecisionRuleset) -> DictionaryMatches {
+    let matcher = current_matcher(ruleset.tenant_id.as_deref());
+    if matcher.version != (ruleset.dictionary_version, ruleset.overrides_version) {
+        return scan_plain(text_lower, &ruleset.keywords);
+    }
+
+    // Overlapping search, so "heart" is still found inside "heart failure"
+    let mut first_seen: Vec<Option<usize>> = vec![None; matcher.patterns.len()];
+    for found in matcher.automaton.find_overlapping_iter(text_lower) {
+        let slot = &mut first_seen[found.pattern().as_usize()];
+        if slot.is_none() {
+            *slot = Some(found.start());
+        }
+    }
+
+    let mut matches = DictionaryMatches { keywords: BTreeMap::new(), terminology: Vec::new() };
+    for ((source, pattern), start) in matcher.patterns.iter().zip(first_seen) {
+        let Some(start) = start else { continue };
+        match source {
+            PatternSource::Keyword { directive_type } => {
+                matches.keywords.entry(Rc::clone(directive_type)).or_default().push(KeywordSpan {
+                    keyword: pattern.to_string(),
+                    start: start as u64,
+                    end: (start + pattern.len()) as u64,
+                });
+            }
+            PatternSource::Terminology { label } => {
+                matches.terminology.push(label.to_string());
+            }
+        }
+    }
+    matches
+}
+
+// Substring search finds the same first occurrences as the overlapping automaton, just more slowly
+fn scan_plain(text_lower: &str, keywords: &BTreeMap<String, Vec<String>>) -> DictionaryMatches {
+    let mut matches = DictionaryMatches { keywords: BTreeMap::new(), terminology: Vec::new() };
+    for (directive_type, keyword_list) in keywords {
+        let spans: Vec<KeywordSpan> = keyword_list.iter()
+            .filter_map(|keyword| text_lower.find(keyword.as_str()).map(|start| KeywordSpan {
+                keyword: keyword.clone(),
+                start: start as u64,
+                end: (start + keyword.len()) as u64,
+            }))
+            .collect();
+        if !spans.is_empty() {
+            matches.keywords.insert(Rc::from(directive_type.as_str()), spans);
+        }
+    }
+    MEDICAL_TERMINOLOGY.with(|terminology| {
+        for (category, term_list) in terminology.borrow().iter() {
+            for term in term_list.iter().filter(|term| text_lower.contains(term.as_str())) {
+                matches.terminology.push(format!("{}: {}", category, term));
+            }
+        }
+    });
+    matches
+}
+
+// Built lazily and reused until the dictionary or the tenant's overrides move on
+fn current_matcher(tenant_id: Option<&str>) -> Rc<DictionaryMatcher> {
+    let (overrides_version, extensions) = tenant_config::keyword_extensions(tenant_id);
+    let key = if extensions.is_empty() { None } else { tenant_id.map(String::from) };
+    let version = (DICTIONARY_VERSION.with(|v| *v.borrow()), overrides_version);
+    MATCHERS.with(|cached| {
+        let mut cached = cached.borrow_mut();
+        match cached.get(&key) {
+            Some(matcher) if matcher.version == version => matcher.clone(),
+            _ => {
+                let matcher = Rc::new(build_matcher(version, &extensions));
+                cached.insert(key, matcher.clone());
+                matcher
+            }
+        }
+    })
+}
+
+fn build_matcher(version: (u64, u64), extensions: &BTreeMap<String, Vec<String>>) -> DictionaryMatcher {
+    let mut patterns: Vec<(PatternSource, Rc<str>)> = Vec::new();
+    for (directive_type, keyword_list) in directive_types::all_keywords() {
+        let directive_type: Rc<str> = Rc::from(directive_type.as_str());
+        // Tenant keywords follow the global ones; one already in the global list is not added twice
+        let extra = extensions.get(directive_type.as_ref()).into_iter().flatten().filter(|k| !keyword_list.contains(k));
+        for keyword in keyword_list.iter().chain(extra) {
+            let source = PatternSource::Keyword { directive_type: Rc::clone(&directive_type) };
+            patterns.push((source, Rc::from(keyword.as_str())));
+        }
+    }
+    MEDICAL_TERMINOLOGY.with(|terminology| {
+        for (category, term_list) in terminology.borrow().iter() {
+            for term in term_list {
+                let label = Rc::from(format!("{}: {}", category, term));
+                patterns.push((PatternSource::Terminology { label }, Rc::from(term.as_str())));
+            }
+        }
+    });
+
+    let automaton = AhoCorasick::builder()
+        .match_kind(MatchKind::Standard)
+        .build(patterns.iter().map(|(_, pattern)| pattern.as_bytes()))
+        .expect("Failed to build dictionary automaton");
+
+    ic_cdk::println!("🔤 Dictionary v{}.{} compiled: {} patterns", version.0, version.1, patterns.len());
+    DictionaryMatcher { version, automaton, patterns }
+}
@@ -0,0 +1,337 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::actions;
+use crate::clock;
+use crate::directive_types;
+
+pub(crate) const EXTRACTION_TEMPLATE: &str = "EXTRACTION";
+pub(crate) const RISK_ASSESSMENT_TEMPLATE: &str = "RISK_ASSESSMENT";
+pub(crate) const TRANSLATION_TEMPLATE: &str = "TRANSLATION";
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PromptTemplate {
+    pub template_id: String,
+    pub version: u32,
+    pub instructions: String,
+    pub published_by: Principal,
+    pub published_at: u64,
+}
+
+// A provider reply that failed validation; the reply itself is not kept since it may echo PHI
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RejectedResponse {
+    pub provider_id: String,
+    pub template_id: String,
+    pub template_version: u32,
+    pub reason: String,
+    pub response_bytes: u64,
+    pub rejected_at: u64,
+}
+
+// Extraction replies must match this exactly: every field present, nothing extra
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ExtractionResponse {
+    pub confidence_score: f32,
+    pub directives: Vec<ExtractionResponseDirective>,
+    pub contraindications: Vec<String>,
+    pub legal_validity_score: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ExtractionResponseDirective {
+    pub directive_type: String,
+    pub confidence: f32,
+    pub conditions: Vec<String>,
+    pub extracted_text: String,
+    pub medical_terminology: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RiskAssessmentResponse {
+    pub recovery_probability: f32,
+    pub risk_factors: Vec<String>,
+    pub contraindications: Vec<String>,
+    pub recommended_actions: Vec<String>,
+    pub confidence_score: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TranslationResponse {
+    pub detected_language: String,
+    pub summary: String,
+    pub directives: Vec<TranslationResponseDirective>,
+    pub confidence_score: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TranslationResponseDirective {
+    pub directive_type: String,
+    pub conditions: Vec<String>,
+}
+
+// Carried across upgrades: every published version and which one is active. The rejection log is
+// operational and starts over.
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct PromptState {
+    templates: Vec<PromptTemplate>,
+    active_versions: Vec<(String, u32)>,
+}
+
+thread_local! {
+    // Every published version is kept; the active one is the pointer in ACTIVE_TEMPLATE_VERSIONS
+    static PROMPT_TEMPLATES: RefCell<BTreeMap<String, Vec<PromptTemplate>>> = RefCell::new({
+        let mut templates = BTreeMap::new();
+        for (template_id, instructions) in [
+            (EXTRACTION_TEMPLATE, DEFAULT_EXTRACTION_INSTRUCTIONS),
+            (RISK_ASSESSMENT_TEMPLATE, DEFAULT_RISK_ASSESSMENT_INSTRUCTIONS),
+            (TRANSLATION_TEMPLATE, DEFAULT_TRANSLATION_INSTRUCTIONS),
+        ] {
+            templates.insert(template_id.to_string(), vec![PromptTemplate {
+                template_id: template_id.to_string(),
+                version: 1,
+                instructions: instructions.to_string(),
+                published_by: Principal::anonymous(),
+                published_at: 0,
+            }]);
+        }
+        templates
+    });
+
+    static ACTIVE_TEMPLATE_VERSIONS: RefCell<BTreeMap<String, u32>> = const { RefCell::new(BTreeMap::new()) };
+
+    static REJECTED_RESPONSES: RefCell<Vec<RejectedResponse>> = const { RefCell::new(Vec::new()) };
+}
+
+const MAX_TEMPLATE_BYTES: usize = 16 * 1024;
+const MAX_REJECTIONS_KEPT: usize = 1_000;
+const MAX_LIST_ITEMS: usize = 50;
+const MAX_ITEM_BYTES: usize = 500;
+const MAX_SUMMARY_BYTES: usize = 4 * 1024;
+
+const DEFAULT_EXTRACTION_INSTRUCTIONS: &str = "You extract medical directives from advance-directive documents. \
+    Reply with a single JSON object and nothing else, with exactly these fields: \
+    {\"confidence_score\": number 0-1, \"directives\": [{\"directive_type\": one of DNR, ORGAN_DONATION, \
+    DATA_CONSENT, POWER_OF_ATTORNEY, LIVING_WILL, DIGITAL_LEGACY, DISPOSITION, WHOLE_BODY_DONATION, AUTOPSY, RESEARCH_ENROLLMENT, \"confidence\": number 0-1, \"conditions\": [string], \
+    \"extracted_text\": string, \"medical_terminology\": [string]}], \"contraindications\": [string], \
+    \"legal_validity_score\": number 0-1}";
+
+const DEFAULT_RISK_ASSESSMENT_INSTRUCTIONS: &str = "You assess a patient's recovery prospects from their medical \
+    history and current condition. Reply with a single JSON object and nothing else, with exactly these fields: \
+    {\"recovery_probability\": number 0-1, \"risk_factors\": [string], \"contraindications\": [string], \
+    \"recommended_actions\": [one of CARDIAC_INTERVENTION, VENTILATORY_SUPPORT_ASSESSMENT, ICU_ADMISSION_ASSESSMENT, \
+    NEUROLOGY_CONSULT, ONCOLOGY_CONSULT, INFECTION_WORKUP, RENAL_FUNCTION_REVIEW, PALLIATIVE_CARE_CONSULT, \
+    GOALS_OF_CARE_DISCUSSION, ADVANCE_DIRECTIVE_REVIEW, URGENT_CLINICAL_REVIEW, INCREASE_MONITORING, \
+    CONTINUE_CURRENT_MANAGEMENT], \"confidence_score\": number 0-1}";
+
+const DEFAULT_TRANSLATION_INSTRUCTIONS: &str = "You translate advance-directive documents written in any language \
+    into English for emergency clinicians. Do not add, soften or strengthen anything the document says. \
+    Reply with a single JSON object and nothing else, with exactly these fields: \
+    {\"detected_language\": ISO 639-1 code of the document's language, \"summary\": string, a plain English \
+    summary of what the patient wants, \"directives\": [{\"directive_type\": one of DNR, ORGAN_DONATION, \
+    DATA_CONSENT, POWER_OF_ATTORNEY, LIVING_WILL, DIGITAL_LEGACY, DISPOSITION, WHOLE_BODY_DONATION, AUTOPSY, RESEARCH_ENROLLMENT, \"conditions\": [string], each an English statement of a \
+    condition or instruction]}], \"confidence_score\": number 0-1, how faithful the translation is}";
+
+// Publishing makes the new version active; earlier versions stay available for rollback
+#[update]
+fn publish_prompt_template(template_id: String, instructions: String) -> Result<u32, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may publish prompt templates".to_string());
+    }
+    if ![EXTRACTION_TEMPLATE, RISK_ASSESSMENT_TEMPLATE, TRANSLATION_TEMPLATE].contains(&template_id.as_str()) {
+        return Err(format!("Unknown prompt template: {}", template_id));
+    }
+    if instructions.trim().is_empty() || instructions.len() > MAX_TEMPLATE_BYTES {
+        return Err(format!("Instructions must be non-empty and at most {} bytes", MAX_TEMPLATE_BYTES));
+    }
+
+    let version = PROMPT_TEMPLATES.with(|templates| {
+        let mut templates = templates.borrow_mut();
+        let versions = templates.entry(template_id.clone()).or_default();
+        let version = versions.len() as u32 + 1;
+        versions.push(PromptTemplate {
+            template_id: template_id.clone(),
+            version,
+            instructions,
+            published_by: caller(),
+            published_at: clock::now(),
+        });
+        version
+    });
+    ACTIVE_TEMPLATE_VERSIONS.with(|active| active.borrow_mut().insert(template_id, version));
+    Ok(version)
+}
+
+#[update]
+fn activate_prompt_template(template_id: String, version: u32) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may activate prompt templates".to_string());
+    }
+    let exists = PROMPT_TEMPLATES.with(|templates| {
+        templates.borrow().get(&template_id).is_some_and(|versions| versions.iter().any(|t| t.version == version))
+    });
+    if !exists {
+        return Err(format!("No version {} of prompt template {}", version, template_id));
+    }
+    ACTIVE_TEMPLATE_VERSIONS.with(|active| active.borrow_mut().insert(template_id, version));
+    Ok(())
+}
+
+#[query]
+fn get_prompt_templates(template_id: String) -> Vec<PromptTemplate> {
+    PROMPT_TEMPLATES.with(|templates| templates.borrow().get(&template_id).cloned().unwrap_or_default())
+}
+
+#[query]
+fn get_rejected_llm_responses(limit: u32) -> Result<Vec<RejectedResponse>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read rejected responses".to_string());
+    }
+    Ok(REJECTED_RESPONSES.with(|rejected| {
+        rejected.borrow().iter().rev().take(limit as usize).cloned().collect()
+    }))
+}
+
+// The latest version unless a controller has pinned another
+pub(crate) fn active_template(template_id: &str) -> Option<PromptTemplate> {
+    let pinned = ACTIVE_TEMPLATE_VERSIONS.with(|active| active.borrow().get(template_id).copied());
+    PROMPT_TEMPLATES.with(|templates| {
+        let templates = templates.borrow();
+        let versions = templates.get(template_id)?;
+        match pinned {
+            Some(version) => versions.iter().find(|t| t.version == version).cloned(),
+            None => versions.last().cloned(),
+        }
+    })
+}
+
+pub(crate) fn record_rejection(provider_id: &str, template: &PromptTemplate, reason: &str, response_bytes: usize) {
+    REJECTED_RESPONSES.with(|rejected| {
+        let mut rejected = rejected.borrow_mut();
+        if rejected.len() >= MAX_REJECTIONS_KEPT {
+            rejected.remove(0);
+        }
+        rejected.push(RejectedResponse {
+            provider_id: provider_id.to_string(),
+            template_id: template.template_id.clone(),
+            template_version: template.version,
+            reason: reason.to_string(),
+            response_bytes: response_bytes as u64,
+            rejected_at: clock::now(),
+        });
+    });
+}
+
+pub(crate) fn parse_extraction_response(completion: &str) -> Result<ExtractionResponse, String> {
+    let response: ExtractionResponse = serde_json::from_str(completion.trim())
+        .map_err(|e| format!("Extraction reply does not match the schema: {}", e))?;
+
+    check_score("confidence_score", response.confidence_score)?;
+    check_score("legal_validity_score", response.legal_validity_score)?;
+    check_list("contraindications", &response.contraindications)?;
+    if response.directives.len() > MAX_LIST_ITEMS {
+        return Err(format!("directives has more than {} entries", MAX_LIST_ITEMS));
+    }
+
+    for directive in &response.directives {
+        if !directive_types::is_known(&directive.directive_type) {
+            return Err(format!("Unknown directive type: {}", directive.directive_type));
+        }
+        check_score("confidence", directive.confidence)?;
+        check_list("conditions", &directive.conditions)?;
+        check_list("medical_terminology", &directive.medical_terminology)?;
+        if directive.extracted_text.len() > MAX_ITEM_BYTES {
+            return Err(format!("extracted_text is longer than {} bytes", MAX_ITEM_BYTES));
+        }
+    }
+    Ok(response)
+}
+
+pub(crate) fn parse_risk_assessment_response(completion: &str) -> Result<RiskAssessmentResponse, String> {
+    let response: RiskAssessmentResponse = serde_json::from_str(completion.trim())
+        .map_err(|e| format!("Risk assessment reply does not match the schema: {}", e))?;
+
+    check_score("recovery_probability", response.recovery_probability)?;
+    check_score("confidence_score", response.confidence_score)?;
+    check_list("risk_factors", &response.risk_factors)?;
+    check_list("contraindications", &response.contraindications)?;
+    check_list("recommended_actions", &response.recommended_actions)?;
+    if let Some(unknown) = response.recommended_actions.iter().find(|code| !actions::is_known(code)) {
+        return Err(format!("Unknown action code: {}", unknown));
+    }
+    Ok(response)
+}
+
+pub(crate) fn parse_translation_response(completion: &str) -> Result<TranslationResponse, String> {
+    let response: TranslationResponse = serde_json::from_str(completion.trim())
+        .map_err(|e| format!("Translation reply does not match the schema: {}", e))?;
+
+    check_score("confidence_score", response.confidence_score)?;
+    let language = response.detected_language.as_str();
+    if !(2..=3).contains(&language.len()) || !language.chars().all(|c| c.is_ascii_lowercase()) {
+        return Err(format!("detected_language {} is not an ISO 639 code", language));
+    }
+    if response.summary.trim().is_empty() || response.summary.len() > MAX_SUMMARY_BYTES {
+        return Err(format!("summary must be non-empty and at most {} bytes", MAX_SUMMARY_BYTES));
+    }
+    if response.directives.len() > MAX_LIST_ITEMS {
+        return Err(format!("directives has more than {} entries", MAX_LIST_ITEMS));
+    }
+
+    for directive in &response.directives {
+        if !directive_types::is_known(&directive.directive_type) {
+            return Err(format!("Unknown directive type: {}", directive.directive_type));
+        }
+        check_list("conditions", &directive.conditions)?;
+    }
+    Ok(response)
+}
+
+fn check_score(field: &str, score: f32) -> Result<(), String> {
+    if (0.0..=1.0).contains(&score) {
+        Ok(())
+    } else {
+        Err(format!("{} {} is outside 0-1", field, score))
+    }
+}
+
+fn check_list(field: &str, items: &[String]) -> Result<(), String> {
+    if items.len() > MAX_LIST_ITEMS {
+        return Err(format!("{} has more than {} entries", field, MAX_LIST_ITEMS));
+    }
+    if items.iter().any(|item| item.trim().is_empty() || item.len() > MAX_ITEM_BYTES) {
+        return Err(format!("{} entries must be non-empty and at most {} bytes", field, MAX_ITEM_BYTES));
+    }
+    Ok(())
+}
+
+pub(crate) fn snapshot() -> PromptState {
+    PromptState {
+        templates: PROMPT_TEMPLATES.with(|t| t.borrow().values().flatten().cloned().collect()),
+        active_versions: ACTIVE_TEMPLATE_VERSIONS.with(|a| a.borrow().clone().into_iter().collect()),
+    }
+}
+
+// Templates are saved version by version, in order, so each list is rebuilt as it was published
+pub(crate) fn restore(state: Option<PromptState>) {
+    let Some(state) = state else {
+        return;
+    };
+    PROMPT_TEMPLATES.with(|t| {
+        let mut templates = t.borrow_mut();
+        templates.clear();
+        for template in state.templates {
+            templates.entry(template.template_id.clone()).or_default().push(template);
+        }
+    });
+    ACTIVE_TEMPLATE_VERSIONS.with(|a| *a.borrow_mut() = state.active_versions.into_iter().collect());
+}
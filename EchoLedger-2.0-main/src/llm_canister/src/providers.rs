@@ -0,0 +1,469 @@
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
+};
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Nat};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::prompts::{self, ExtractionResponse};
+use crate::injection;
+use crate::redaction::{self, RedactionRecord};
+use crate::residency;
+use crate::validation;
+use crate::{clock, ExtractedDirective, MedicalDirectiveAnalysis};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LlmProviderConfig {
+    pub provider_id: String,
+    pub api_format: String, // "OPENAI", "ANTHROPIC", "VLLM"
+    pub endpoint_url: String,
+    pub model: String,
+    pub region: String,
+    pub priority: u32, // Lower is tried first
+    pub max_response_bytes: u64,
+    pub cost_per_1k_chars_usd: f32,
+    pub enabled: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProviderStats {
+    pub provider_id: String,
+    pub requests: u64,
+    pub successes: u64,
+    pub failures: u64,
+    pub schema_rejections: u64,
+    pub consecutive_failures: u32,
+    pub total_latency_ms: u64,
+    pub total_cost_usd: f32,
+    pub last_error: Option<String>,
+    pub last_failure_at: Option<u64>,
+}
+
+// Each API family knows how to frame a chat request and where the completion sits in its reply
+pub(crate) trait LlmProvider {
+    fn encode_request(&self, config: &LlmProviderConfig, instructions: &str, text: &str) -> Vec<u8>;
+    fn auth_headers(&self, credential: Option<&str>) -> Vec<HttpHeader>;
+    fn extract_completion(&self, response: &serde_json::Value) -> Option<String>;
+}
+
+struct OpenAiProvider;
+struct AnthropicProvider;
+struct VllmProvider;
+
+impl LlmProvider for OpenAiProvider {
+    fn encode_request(&self, config: &LlmProviderConfig, instructions: &str, text: &str) -> Vec<u8> {
+        chat_completion_request(config, instructions, text)
+    }
+
+    fn auth_headers(&self, credential: Option<&str>) -> Vec<HttpHeader> {
+        bearer(credential)
+    }
+
+    fn extract_completion(&self, response: &serde_json::Value) -> Option<String> {
+        chat_completion_content(response)
+    }
+}
+
+impl LlmProvider for AnthropicProvider {
+    fn encode_request(&self, config: &LlmProviderConfig, instructions: &str, text: &str) -> Vec<u8> {
+        serde_json::json!({
+            "model": config.model,
+            "max_tokens": 2_048,
+            "temperature": 0,
+            "system": instructions,
+            "messages": [{ "role": "user", "content": text }],
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn auth_headers(&self, credential: Option<&str>) -> Vec<HttpHeader> {
+        let mut headers = vec![HttpHeader {
+            name: "anthropic-version".to_string(),
+            value: "2023-06-01".to_string(),
+        }];
+        if let Some(key) = credential {
+            headers.push(HttpHeader { name: "x-api-key".to_string(), value: key.to_string() });
+        }
+        headers
+    }
+
+    fn extract_completion(&self, response: &serde_json::Value) -> Option<String> {
+        response["content"]
+            .as_array()?
+            .iter()
+            .find(|block| block["type"] == "text")
+            .and_then(|block| block["text"].as_str())
+            .map(String::from)
+    }
+}
+
+// Self-hosted vLLM speaks the OpenAI chat API; a token is optional behind a private gateway
+impl LlmProvider for VllmProvider {
+    fn encode_request(&self, config: &LlmProviderConfig, instructions: &str, text: &str) -> Vec<u8> {
+        chat_completion_request(config, instructions, text)
+    }
+
+    fn auth_headers(&self, credential: Option<&str>) -> Vec<HttpHeader> {
+        bearer(credential)
+    }
+
+    fn extract_completion(&self, response: &serde_json::Value) -> Option<String> {
+        chat_completion_content(response)
+    }
+}
+
+fn chat_completion_request(config: &LlmProviderConfig, instructions: &str, text: &str) -> Vec<u8> {
+    serde_json::json!({
+        "model": config.model,
+        "temperature": 0,
+        "seed": 0,
+        "response_format": { "type": "json_object" },
+        "messages": [
+            { "role": "system", "content": instructions },
+            { "role": "user", "content": text },
+        ],
+    })
+    .to_string()
+    .into_bytes()
+}
+
+fn chat_completion_content(response: &serde_json::Value) -> Option<String> {
+    response["choices"][0]["message"]["content"].as_str().map(String::from)
+}
+
+fn bearer(credential: Option<&str>) -> Vec<HttpHeader> {
+    credential
+        .map(|token| HttpHeader { name: "Authorization".to_string(), value: format!("Bearer {}", token) })
+        .into_iter()
+        .collect()
+}
+
+fn provider_for(api_format: &str) -> Box<dyn LlmProvider> {
+    match api_format {
+        "ANTHROPIC" => Box::new(AnthropicProvider),
+        "VLLM" => Box::new(VllmProvider),
+        _ => Box::new(OpenAiProvider),
+    }
+}
+
+// Carried across upgrades, credentials included, so failover keeps working after a redeploy
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct ProviderState {
+    providers: Vec<LlmProviderConfig>,
+    credentials: Vec<(String, String)>,
+    stats: Vec<(String, ProviderStats)>,
+}
+
+thread_local! {
+    static LLM_PROVIDERS: RefCell<BTreeMap<String, LlmProviderConfig>> = const { RefCell::new(BTreeMap::new()) };
+
+    // Kept apart from the registry so API keys never leave the canister via queries
+    static PROVIDER_CREDENTIALS: RefCell<BTreeMap<String, String>> = const { RefCell::new(BTreeMap::new()) };
+
+    static PROVIDER_STATS: RefCell<BTreeMap<String, ProviderStats>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+const API_FORMATS: [&str; 3] = ["OPENAI", "ANTHROPIC", "VLLM"];
+const MAX_ENDPOINT_URL_BYTES: usize = 2048;
+const OUTCALL_CYCLES: u128 = 50_000_000_000;
+// A provider failing this many times in a row is tried last until the cooldown passes
+const FAILURE_COOLDOWN_THRESHOLD: u32 = 3;
+const FAILURE_COOLDOWN_NANOS: u64 = 5 * 60 * 1_000_000_000;
+
+#[update]
+fn register_llm_provider(config: LlmProviderConfig, credential: Option<String>) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage LLM providers".to_string());
+    }
+    validation::identifier("provider_id", &config.provider_id)?;
+    validation::identifier("model", &config.model)?;
+    validation::identifier("region", &config.region)?;
+    if config.endpoint_url.len() > MAX_ENDPOINT_URL_BYTES {
+        return Err(validation::invalid("endpoint_url", &format!("longer than {} bytes", MAX_ENDPOINT_URL_BYTES)));
+    }
+    if !API_FORMATS.contains(&config.api_format.as_str()) {
+        return Err(format!("Unsupported API format: {}", config.api_format));
+    }
+    if !config.endpoint_url.starts_with("https://") {
+        return Err("Provider endpoints must use HTTPS".to_string());
+    }
+    if config.model.trim().is_empty() {
+        return Err("A provider needs a model name".to_string());
+    }
+    if config.api_format != "VLLM" && credential.is_none() {
+        let has_stored = PROVIDER_CREDENTIALS.with(|c| c.borrow().contains_key(&config.provider_id));
+        if !has_stored {
+            return Err("Hosted providers require an API key".to_string());
+        }
+    }
+
+    if let Some(credential) = credential {
+        PROVIDER_CREDENTIALS.with(|c| c.borrow_mut().insert(config.provider_id.clone(), credential));
+    }
+    LLM_PROVIDERS.with(|providers| {
+        providers.borrow_mut().insert(config.provider_id.clone(), config);
+    });
+    Ok(())
+}
+
+#[update]
+fn set_llm_provider_enabled(provider_id: String, enabled: bool) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may manage LLM providers".to_string());
+    }
+    LLM_PROVIDERS.with(|providers| {
+        let mut providers = providers.borrow_mut();
+        let provider = providers.get_mut(&provider_id).ok_or_else(|| format!("Unknown provider: {}", provider_id))?;
+        provider.enabled = enabled;
+        Ok(())
+    })
+}
+
+#[query]
+fn get_llm_providers() -> Vec<LlmProviderConfig> {
+    LLM_PROVIDERS.with(|providers| providers.borrow().values().cloned().collect())
+}
+
+#[query]
+fn get_llm_provider_stats() -> Vec<ProviderStats> {
+    PROVIDER_STATS.with(|stats| stats.borrow().values().cloned().collect())
+}
+
+// A validated reply and where it came from
+pub(crate) struct ProviderReply<T> {
+    pub value: T,
+    pub provider_id: String,
+    pub template_version: u32,
+    pub cost_usd: f32,
+    pub latency_ms: u64,
+    pub redaction: RedactionRecord,
+}
+
+// Try enabled providers in failover order until one returns a reply that passes `parse`;
+// rejected replies are logged and count against the provider. Ok(None) means no provider is configured.
+// Only the redacted text is ever sent, and only to providers the tenant's residency policy allows.
+pub(crate) async fn complete_with_failover<T>(
+    tenant_id: Option<&str>,
+    template_id: &str,
+    patient_id: &str,
+    text: &str,
+    parse: fn(&str) -> Result<T, String>,
+) -> Result<Option<ProviderReply<T>>, String> {
+    let candidates = failover_order();
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+    let candidates = residency::permitted_providers(tenant_id, template_id, candidates);
+    if candidates.is_empty() {
+        return Err(format!("No LLM provider satisfies the data residency policy for {}", tenant_id.unwrap_or_default()));
+    }
+    let template = prompts::active_template(template_id)
+        .ok_or_else(|| format!("No active prompt template: {}", template_id))?;
+    let redacted = redaction::redact(patient_id, text);
+    ic_cdk::println!(
+        "🛡️ {} identifiers redacted before outcall ({})",
+        redacted.record.entities.len(),
+        redacted.record.redaction_id
+    );
+    let text = injection::fence(&redacted.text);
+    let instructions = format!("{}\n\n{}", template.instructions, injection::DOCUMENT_GUARD);
+
+    let mut errors = Vec::new();
+    for config in candidates {
+        let started = clock::now();
+        let cost_usd = text.chars().count() as f32 / 1000.0 * config.cost_per_1k_chars_usd;
+
+        let outcome = match request_completion(&config, &instructions, &text).await {
+            Ok(completion) => parse(&completion).map_err(|e| {
+                prompts::record_rejection(&config.provider_id, &template, &e, completion.len());
+                (true, e)
+            }),
+            Err(e) => Err((false, e)),
+        };
+        let latency_ms = clock::now().saturating_sub(started) / 1_000_000;
+
+        match outcome {
+            Ok(value) => {
+                record_success(&config.provider_id, latency_ms, cost_usd);
+                redaction::keep(&redacted.record);
+                ic_cdk::println!(
+                    "🤖 {} reply accepted for {} v{} in {}ms",
+                    config.provider_id,
+                    template.template_id,
+                    template.version,
+                    latency_ms
+                );
+                return Ok(Some(ProviderReply {
+                    value,
+                    provider_id: config.provider_id,
+                    template_version: template.version,
+                    cost_usd,
+                    latency_ms,
+                    redaction: redacted.record,
+                }));
+            }
+            Err((schema_rejected, error)) => {
+                ic_cdk::println!("⚠️ {} failed, trying next provider: {}", config.provider_id, error);
+                record_failure(&config.provider_id, latency_ms, cost_usd, schema_rejected, &error);
+                errors.push(format!("{}: {}", config.provider_id, error));
+            }
+        }
+    }
+
+    redaction::keep(&redacted.record);
+    Err(format!("All LLM providers failed - {}", errors.join("; ")))
+}
+
+pub(crate) async fn extract_with_failover(
+    tenant_id: Option<&str>,
+    patient_id: &str,
+    text: &str,
+) -> Result<Option<MedicalDirectiveAnalysis>, String> {
+    let reply =
+        complete_with_failover(tenant_id, prompts::EXTRACTION_TEMPLATE, patient_id, text, prompts::parse_extraction_response)
+            .await?;
+    Ok(reply.map(into_analysis))
+}
+
+// Priority order, with providers in a failure cooldown pushed to the back rather than skipped
+fn failover_order() -> Vec<LlmProviderConfig> {
+    let now = clock::now();
+    let mut providers: Vec<(bool, LlmProviderConfig)> = LLM_PROVIDERS.with(|providers| {
+        providers.borrow()
+            .values()
+            .filter(|p| p.enabled)
+            .map(|p| (cooling_down(&p.provider_id, now), p.clone()))
+            .collect()
+    });
+    providers.sort_by_key(|(cooling, p)| (*cooling, p.priority));
+    providers.into_iter().map(|(_, p)| p).collect()
+}
+
+fn cooling_down(provider_id: &str, now: u64) -> bool {
+    PROVIDER_STATS.with(|stats| {
+        stats.borrow().get(provider_id).is_some_and(|s| {
+            s.consecutive_failures >= FAILURE_COOLDOWN_THRESHOLD
+                && s.last_failure_at.is_some_and(|at| now.saturating_sub(at) < FAILURE_COOLDOWN_NANOS)
+        })
+    })
+}
+
+async fn request_completion(config: &LlmProviderConfig, instructions: &str, text: &str) -> Result<String, String> {
+    let provider = provider_for(&config.api_format);
+    let credential = PROVIDER_CREDENTIALS.with(|c| c.borrow().get(&config.provider_id).cloned());
+
+    let mut headers = vec![HttpHeader {
+        name: "Content-Type".to_string(),
+        value: "application/json".to_string(),
+    }];
+    headers.extend(provider.auth_headers(credential.as_deref()));
+
+    let request = CanisterHttpRequestArgument {
+        url: config.endpoint_url.clone(),
+        max_response_bytes: Some(config.max_response_bytes),
+        method: HttpMethod::POST,
+        headers,
+        body: Some(provider.encode_request(config, instructions, text)),
+        transform: None,
+    };
+
+    let (response,) = http_request(request, OUTCALL_CYCLES)
+        .await
+        .map_err(|(_, msg)| format!("request failed: {}", msg))?;
+
+    if response.status < Nat::from(200u32) || response.status >= Nat::from(300u32) {
+        return Err(format!("returned status {}", response.status));
+    }
+
+    let envelope: serde_json::Value = serde_json::from_slice(&response.body)
+        .map_err(|e| format!("response is not JSON: {}", e))?;
+    provider.extract_completion(&envelope).ok_or_else(|| "response carries no completion".to_string())
+}
+
+// Placeholders the model echoed back are re-identified before the analysis leaves this module
+fn into_analysis(reply: ProviderReply<ExtractionResponse>) -> MedicalDirectiveAnalysis {
+    let extraction = reply.value;
+    let restore = |items: Vec<String>| -> Vec<String> { items.iter().map(|i| reply.redaction.restore(i)).collect() };
+    MedicalDirectiveAnalysis {
+        confidence_score: extraction.confidence_score,
+        extracted_directives: extraction.directives
+            .into_iter()
+            .map(|d| ExtractedDirective {
+                directive_type: d.directive_type,
+                conditions: restore(d.conditions),
+                confidence: d.confidence,
+                extracted_text: reply.redaction.restore(&d.extracted_text),
+                medical_terminology: restore(d.medical_terminology),
+                keyword_spans: Vec::new(),
+                provenance: Vec::new(),
+                score_breakdown: Vec::new(),
+            })
+            .collect(),
+        contraindications: restore(extraction.contraindications),
+        legal_validity_score: extraction.legal_validity_score,
+        requires_human_review: extraction.confidence_score < 0.85,
+        processing_method: "EXTERNAL_LLM".to_string(),
+        processing_cost_usd: reply.cost_usd,
+        processing_time_ms: reply.latency_ms,
+        source_disagreements: Vec::new(),
+        injection_flags: Vec::new(),
+        trace_id: 0,
+    }
+}
+
+fn record_success(provider_id: &str, latency_ms: u64, cost: f32) {
+    PROVIDER_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        let s = stats.entry(provider_id.to_string()).or_insert_with(|| ProviderStats {
+            provider_id: provider_id.to_string(),
+            ..Default::default()
+        });
+        s.requests += 1;
+        s.successes += 1;
+        s.consecutive_failures = 0;
+        s.total_latency_ms += latency_ms;
+        s.total_cost_usd += cost;
+    });
+}
+
+fn record_failure(provider_id: &str, latency_ms: u64, cost: f32, schema_rejected: bool, error: &str) {
+    PROVIDER_STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        let s = stats.entry(provider_id.to_string()).or_insert_with(|| ProviderStats {
+            provider_id: provider_id.to_string(),
+            ..Default::default()
+        });
+        s.requests += 1;
+        s.failures += 1;
+        if schema_rejected {
+            // The provider was paid for a reply we could not use
+            s.schema_rejections += 1;
+            s.total_cost_usd += cost;
+        }
+        s.consecutive_failures += 1;
+        s.total_latency_ms += latency_ms;
+        s.last_error = Some(error.to_string());
+        s.last_failure_at = Some(clock::now());
+    });
+}
+
+pub(crate) fn snapshot() -> ProviderState {
+    ProviderState {
+        providers: LLM_PROVIDERS.with(|p| p.borrow().values().cloned().collect()),
+        credentials: PROVIDER_CREDENTIALS.with(|c| c.borrow().clone().into_iter().collect()),
+        stats: PROVIDER_STATS.with(|s| s.borrow().clone().into_iter().collect()),
+    }
+}
+
+pub(crate) fn restore(state: Option<ProviderState>) {
+    let Some(state) = state else {
+        return;
+    };
+    LLM_PROVIDERS.with(|p| *p.borrow_mut() = state.providers.into_iter().map(|x| (x.provider_id.clone(), x)).collect());
+    PROVIDER_CREDENTIALS.with(|c| *c.borrow_mut() = state.credentials.into_iter().collect());
+    PROVIDER_STATS.with(|s| *s.borrow_mut() = state.stats.into_iter().collect());
+}
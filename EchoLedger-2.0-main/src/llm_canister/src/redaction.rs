@@ -0,0 +1,376 @@
+use ic_cdk::caller;
+use ic_cdk_macros::query;
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::clock;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RedactedEntity {
+    pub placeholder: String,
+    pub category: String, // "NAME", "PATIENT_ID", "MRN", "SSN", "PHONE", "EMAIL", "DATE_OF_BIRTH", "DATE", "ADDRESS"
+    pub original: String,
+    pub start: u64, // Byte offsets into the text as submitted
+    pub end: u64,
+}
+
+// What was taken out of one outbound request; stays in the canister so replies can be re-identified
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RedactionRecord {
+    pub redaction_id: String,
+    pub patient_id: String,
+    pub created_at: u64,
+    pub entities: Vec<RedactedEntity>,
+}
+
+pub(crate) struct Redacted {
+    pub text: String,
+    pub record: RedactionRecord,
+}
+
+thread_local! {
+    static REDACTION_RECORDS: RefCell<BTreeMap<String, RedactionRecord>> = const { RefCell::new(BTreeMap::new()) };
+    static NEXT_REDACTION_SEQ: RefCell<u64> = const { RefCell::new(0) };
+}
+
+// The mappings hold the very identifiers redaction keeps from providers. A reply is re-identified
+// within the call that made it; the record is kept only so an operator can look into a recent
+// request, and is dropped once it ages out or the store is full.
+const REDACTION_RETENTION_NANOS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+const MAX_REDACTION_RECORDS: usize = 10_000;
+
+const HONORIFICS: [&str; 7] = ["mr", "mrs", "ms", "miss", "mx", "dr", "rev"];
+// Labels after which the next capitalized words are a person's name
+const NAME_LABELS: [&str; 8] = ["name:", "patient:", "signed:", "signature:", "witness:", "agent:", "proxy:", "physician:"];
+const MRN_CUES: [&str; 5] = ["mrn", "medical record number", "medical record no", "record number", "patient number"];
+const DOB_CUES: [&str; 4] = ["date of birth", "d.o.b", "dob", "born on"];
+const STREET_SUFFIXES: [&str; 22] = [
+    "street", "st", "avenue", "ave", "road", "rd", "boulevard", "blvd", "lane", "ln", "drive", "dr",
+    "court", "ct", "way", "place", "pl", "terrace", "highway", "hwy", "strasse", "straße",
+];
+const MONTHS: [&str; 12] = [
+    "january", "february", "march", "april", "may", "june", "july", "august", "september", "october",
+    "november", "december",
+];
+// Common given names; a dictionary hit followed by a capitalized word is taken as a full name
+const GIVEN_NAMES: [&str; 48] = [
+    "james", "john", "robert", "michael", "william", "david", "richard", "joseph", "thomas", "charles",
+    "mary", "patricia", "jennifer", "linda", "elizabeth", "barbara", "susan", "jessica", "sarah", "karen",
+    "maria", "anna", "hans", "peter", "klaus", "jürgen", "sophie", "marie", "jean", "pierre",
+    "giuseppe", "marco", "francesca", "carlos", "jose", "juan", "ana", "luis", "mohammed", "ahmed",
+    "fatima", "wei", "li", "yuki", "hiroshi", "priya", "raj", "olga",
+];
+
+#[query]
+fn get_redaction_record(redaction_id: String) -> Result<RedactionRecord, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read redaction mappings".to_string());
+    }
+    REDACTION_RECORDS.with(|records| records.borrow().get(&redaction_id).cloned())
+        .ok_or_else(|| format!("Unknown redaction: {}", redaction_id))
+}
+
+// Replace every detected identifier with a stable placeholder; the caller keeps the mapping
+pub(crate) fn redact(patient_id: &str, text: &str) -> Redacted {
+    let words = words(text);
+    let mut spans: Vec<(usize, usize, &'static str)> = Vec::new();
+
+    if !patient_id.is_empty() {
+        for (start, _) in text.match_indices(patient_id) {
+            spans.push((start, start + patient_id.len(), "PATIENT_ID"));
+        }
+    }
+    find_emails(&words, &mut spans);
+    find_numbers(text, &mut spans);
+    find_cued_values(text, &words, &MRN_CUES, "MRN", 1, &mut spans);
+    find_cued_values(text, &words, &DOB_CUES, "DATE_OF_BIRTH", 3, &mut spans);
+    find_addresses(&words, &mut spans);
+    find_names(&words, &mut spans);
+
+    // Earliest first, longest first at the same offset; anything overlapping an accepted span is dropped
+    spans.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+    let mut accepted: Vec<(usize, usize, &'static str)> = Vec::new();
+    for span in spans {
+        if accepted.last().is_none_or(|last| span.0 >= last.1) {
+            accepted.push(span);
+        }
+    }
+
+    let mut redacted = String::with_capacity(text.len());
+    let mut entities: Vec<RedactedEntity> = Vec::new();
+    let mut counters: BTreeMap<&str, u32> = BTreeMap::new();
+    let mut cursor = 0;
+    for (start, end, category) in accepted {
+        let original = &text[start..end];
+        // The same identifier gets the same placeholder, so the model can still follow references
+        let placeholder = match entities.iter().find(|e| e.category == category && e.original == original) {
+            Some(existing) => existing.placeholder.clone(),
+            None => {
+                let n = counters.entry(category).or_default();
+                *n += 1;
+                format!("[{}_{}]", category, n)
+            }
+        };
+        redacted.push_str(&text[cursor..start]);
+        redacted.push_str(&placeholder);
+        cursor = end;
+        entities.push(RedactedEntity {
+            placeholder,
+            category: category.to_string(),
+            original: original.to_string(),
+            start: start as u64,
+            end: end as u64,
+        });
+    }
+    redacted.push_str(&text[cursor..]);
+
+    let redaction_id = NEXT_REDACTION_SEQ.with(|seq| {
+        let mut seq = seq.borrow_mut();
+        *seq += 1;
+        format!("REDACTION_{:08}", *seq)
+    });
+    let record = RedactionRecord {
+        redaction_id,
+        patient_id: patient_id.to_string(),
+        created_at: clock::now(),
+        entities,
+    };
+
+    Redacted { text: redacted, record }
+}
+
+// Stores the mapping once the outcalls that carried the redacted text have settled, alongside the
+// provider stats they produced, rather than in an earlier message a trap could leave orphaned
+pub(crate) fn keep(record: &RedactionRecord) {
+    let now = clock::now();
+    REDACTION_RECORDS.with(|records| {
+        let mut records = records.borrow_mut();
+        records.retain(|_, r| now.saturating_sub(r.created_at) < REDACTION_RETENTION_NANOS);
+        while records.len() >= MAX_REDACTION_RECORDS {
+            // Ids sort by creation, so the first is the oldest
+            records.pop_first();
+        }
+        records.insert(record.redaction_id.clone(), record.clone());
+    });
+}
+
+impl RedactionRecord {
+    // Put identifiers back into model output that echoes placeholders
+    pub(crate) fn restore(&self, text: &str) -> String {
+        let mut restored = text.to_string();
+        for entity in &self.entities {
+            if restored.contains(&entity.placeholder) {
+                restored = restored.replace(&entity.placeholder, &entity.original);
+            }
+        }
+        restored
+    }
+}
+
+// Whitespace-separated words with their byte offsets
+fn words(text: &str) -> Vec<(usize, &str)> {
+    text.split(char::is_whitespace)
+        .filter(|w| !w.is_empty())
+        .map(|w| (w.as_ptr() as usize - text.as_ptr() as usize, w))
+        .collect()
+}
+
+fn trim_punctuation(word: &str) -> &str {
+    word.trim_matches(|c: char| matches!(c, ',' | '.' | ';' | ':' | '(' | ')' | '"' | '\''))
+}
+
+fn is_capitalized(word: &str) -> bool {
+    let word = trim_punctuation(word);
+    let mut chars = word.chars();
+    chars.next().is_some_and(char::is_uppercase)
+        && word.len() > 1
+        && chars.all(|c| c.is_alphabetic() || c == '-' || c == '\'')
+}
+
+// Byte range of a word with surrounding punctuation left out
+fn trimmed_span(offset: usize, word: &str) -> (usize, usize) {
+    let trimmed = trim_punctuation(word);
+    let start = offset + (trimmed.as_ptr() as usize - word.as_ptr() as usize);
+    (start, start + trimmed.len())
+}
+
+fn find_emails(words: &[(usize, &str)], spans: &mut Vec<(usize, usize, &'static str)>) {
+    for (offset, word) in words {
+        let trimmed = trim_punctuation(word);
+        if let Some(at) = trimmed.find('@') {
+            if at > 0 && trimmed[at + 1..].contains('.') {
+                let (start, end) = trimmed_span(*offset, word);
+                spans.push((start, end, "EMAIL"));
+            }
+        }
+    }
+}
+
+// Digit runs: ddd-dd-dddd is an SSN, 10-15 digits with separators a PHONE, a full numeric date a DATE
+fn find_numbers(text: &str, spans: &mut Vec<(usize, usize, &'static str)>) {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if !(bytes[i].is_ascii_digit() || bytes[i] == b'+' || bytes[i] == b'(') {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut end = i;
+        let mut digits = 0;
+        while end < bytes.len() && (bytes[end].is_ascii_digit() || b"()+-./ ".contains(&bytes[end])) {
+            if bytes[end].is_ascii_digit() {
+                digits += 1;
+            }
+            end += 1;
+        }
+        // Don't swallow trailing separators or a following sentence
+        while end > start && !(bytes[end - 1].is_ascii_digit() || bytes[end - 1] == b')') {
+            end -= 1;
+        }
+
+        let run = &text[start..end];
+        let category = if is_ssn(run) {
+            Some("SSN")
+        } else if (10..=15).contains(&digits) && !run.contains('/') {
+            Some("PHONE")
+        } else if is_numeric_date(run) {
+            Some("DATE")
+        } else {
+            None
+        };
+        if let Some(category) = category {
+            spans.push((start, end, category));
+        }
+        i = end.max(start + 1);
+    }
+}
+
+fn is_ssn(run: &str) -> bool {
+    let parts: Vec<&str> = run.split('-').collect();
+    parts.len() == 3
+        && [3, 2, 4].iter().zip(&parts).all(|(len, part)| part.len() == *len && part.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn is_numeric_date(run: &str) -> bool {
+    let separator = if run.contains('/') { '/' } else if run.contains('.') { '.' } else { '-' };
+    let parts: Vec<&str> = run.split(separator).collect();
+    parts.len() == 3
+        && parts.iter().all(|p| !p.is_empty() && p.len() <= 4 && p.bytes().all(|b| b.is_ascii_digit()))
+        && parts.iter().any(|p| p.len() == 4 || p.len() == 2)
+}
+
+// The value following a cue phrase, up to `max_words` words. Cues are ASCII, so an ASCII
+// lowercase copy keeps byte offsets aligned with the original text.
+fn find_cued_values(
+    text: &str,
+    words: &[(usize, &str)],
+    cues: &[&str],
+    category: &'static str,
+    max_words: usize,
+    spans: &mut Vec<(usize, usize, &'static str)>,
+) {
+    let lower = text.to_ascii_lowercase();
+    for cue in cues {
+        for (cue_start, _) in lower.match_indices(cue) {
+            // Whole-word cues only: "dob" must not fire inside "adobe"
+            if cue_start > 0 && lower.as_bytes()[cue_start - 1].is_ascii_alphanumeric() {
+                continue;
+            }
+            take_value_after(words, cue_start + cue.len(), category, max_words, spans);
+        }
+    }
+}
+
+fn take_value_after(
+    words: &[(usize, &str)],
+    after: usize,
+    category: &'static str,
+    max_words: usize,
+    spans: &mut Vec<(usize, usize, &'static str)>,
+) {
+    let value: Vec<&(usize, &str)> = words
+        .iter()
+        .filter(|(offset, _)| *offset >= after)
+        .filter(|(_, word)| !matches!(trim_punctuation(word).to_lowercase().as_str(), "" | "is" | "on" | "of" | "#" | "no"))
+        .take(max_words)
+        .take_while(|(_, word)| {
+            let word = trim_punctuation(word).to_lowercase();
+            word.chars().any(|c| c.is_ascii_digit()) || MONTHS.iter().any(|m| word.starts_with(&m[..3]))
+        })
+        .collect();
+    if let (Some(first), Some(last)) = (value.first(), value.last()) {
+        let (start, _) = trimmed_span(first.0, first.1);
+        let (_, end) = trimmed_span(last.0, last.1);
+        if end > start {
+            spans.push((start, end, category));
+        }
+    }
+}
+
+// A house number, up to four words, then a street suffix
+fn find_addresses(words: &[(usize, &str)], spans: &mut Vec<(usize, usize, &'static str)>) {
+    for (i, (offset, word)) in words.iter().enumerate() {
+        let number = trim_punctuation(word);
+        if number.is_empty() || number.len() > 6 || !number.bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        for (j, (suffix_offset, suffix)) in words.iter().enumerate().skip(i + 2).take(4) {
+            let suffix_lower = trim_punctuation(suffix).to_lowercase();
+            if !words[i + 1..j].iter().all(|(_, w)| is_capitalized(w)) {
+                break;
+            }
+            if STREET_SUFFIXES.contains(&suffix_lower.as_str()) {
+                let (start, _) = trimmed_span(*offset, word);
+                let (_, end) = trimmed_span(*suffix_offset, suffix);
+                spans.push((start, end, "ADDRESS"));
+                break;
+            }
+        }
+    }
+}
+
+fn find_names(words: &[(usize, &str)], spans: &mut Vec<(usize, usize, &'static str)>) {
+    for (i, (_, word)) in words.iter().enumerate() {
+        let lower = word.to_lowercase();
+        let bare = trim_punctuation(&lower);
+
+        // "Dr. Jane Smith", "Name: John Doe", "I, Maria Rossi, ..."
+        let introduces_name = HONORIFICS.contains(&bare)
+            || NAME_LABELS.contains(&lower.as_str())
+            || (bare == "i" && word.ends_with(','));
+        if introduces_name {
+            push_capitalized_run(words, i + 1, 3, spans);
+            continue;
+        }
+
+        // Dictionary given name followed by a capitalized surname
+        if is_capitalized(word)
+            && GIVEN_NAMES.contains(&bare)
+            && words.get(i + 1).is_some_and(|(_, next)| is_capitalized(next))
+        {
+            push_capitalized_run(words, i, 3, spans);
+        }
+    }
+}
+
+fn push_capitalized_run(words: &[(usize, &str)], from: usize, max_words: usize, spans: &mut Vec<(usize, usize, &'static str)>) {
+    let mut last = None;
+    for (k, (_, word)) in words.iter().enumerate().skip(from).take(max_words) {
+        if !is_capitalized(word) {
+            break;
+        }
+        last = Some(k);
+        // A name ends at a comma or full stop
+        if word.ends_with(',') || word.ends_with('.') {
+            break;
+        }
+    }
+    if let Some(last) = last {
+        let (start, _) = trimmed_span(words[from].0, words[from].1);
+        let (_, end) = trimmed_span(words[last].0, words[last].1);
+        spans.push((start, end, "NAME"));
+    }
+}
@@ -0,0 +1,414 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::scoring::{self, ScoringFeature};
+use crate::{
+    chunking, clock, directive_types, extract_simple_patterns, injection, preprocess_medical_text, settle,
+    tenant_config, traps, MedicalDirectiveAnalysis, DICTIONARY_VERSION,
+};
+
+// When a family or a regulator questions an outcome, the question is whether the canister would
+// decide the same way again. Every directive analysis is recorded as a trace: the screened text it
+// saw, what the external model answered if it was asked, and the on-chain ruleset in effect, frozen
+// under a version. Replay runs the same pipeline over those records, without asking the external
+// model again, and reports whether the decision comes out the same.
+
+// The on-chain settings one analysis ran under: global keywords with the tenant's extensions,
+// the thresholds in force at the time, the scoring pipeline and the on-chain cutoff
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DecisionRuleset {
+    pub version: u64,
+    pub tenant_id: Option<String>,
+    pub keywords: BTreeMap<String, Vec<String>>,
+    pub confidence_thresholds: BTreeMap<String, f32>,
+    pub scoring_features: Vec<ScoringFeature>,
+    pub on_chain_cutoff: f32,
+    pub dictionary_version: u64,
+    pub overrides_version: u64, // Of the tenant's keyword extensions; 0 without any
+    pub in_effect_from: u64,
+}
+
+// What the external model contributed to a decision
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum ExternalOutcome {
+    NotNeeded,
+    SkippedForInjection,
+    Unavailable(String),
+    Extracted(MedicalDirectiveAnalysis),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DirectiveDecision {
+    pub directive_type: String,
+    pub confidence: f32,
+}
+
+// The parts of an analysis that decide what happens to the directive
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DecisionOutcome {
+    pub processing_method: String,
+    pub directives: Vec<DirectiveDecision>,
+    pub confidence_score: f32,
+    pub legal_validity_score: f32,
+    pub contraindications: Vec<String>,
+    pub requires_human_review: bool,
+    pub source_disagreements: Vec<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DecisionTrace {
+    pub trace_id: u64,
+    pub endpoint: String,
+    pub tenant_id: Option<String>,
+    pub patient_id: String,
+    pub screened_text: String, // As the pipeline saw it, after hidden characters were stripped
+    pub injection_flags: Vec<String>,
+    pub ruleset_version: u64,
+    pub external: ExternalOutcome,
+    pub decision: DecisionOutcome,
+    pub recorded_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReplayReport {
+    pub trace_id: u64,
+    pub ruleset: DecisionRuleset,
+    pub recorded: DecisionOutcome,
+    pub replayed: DecisionOutcome,
+    pub reproduced: bool,
+    pub differences: Vec<String>,
+    pub replayed_by: Principal,
+    pub replayed_at: u64,
+}
+
+// What executor_ai needs to decide whether a patient's newest analysis goes to the ethics committee
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DirectiveReview {
+    pub trace_id: u64,
+    pub legal_validity_score: f32,
+    pub contradictions: Vec<String>, // Where the on-chain and external extractions disagreed
+    pub recorded_at: u64,
+}
+
+thread_local! {
+    static DECISION_TRACES: RefCell<BTreeMap<u64, DecisionTrace>> = const { RefCell::new(BTreeMap::new()) };
+    static NEXT_TRACE_ID: RefCell<u64> = const { RefCell::new(0) };
+    static RULESETS_IN_EFFECT: RefCell<BTreeMap<u64, DecisionRuleset>> = const { RefCell::new(BTreeMap::new()) };
+    // Tenant (None: global callers) -> the version its next analysis runs under unless something changed
+    static CURRENT_RULESETS: RefCell<BTreeMap<Option<String>, u64>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// The executor reads reviews to refer contested directives to its ethics committee
+const EXECUTOR_AI_CANISTER_ID: &str = "rrkah-fqaaa-aaaaa-aaaaq-cai";
+
+// Directive text dominates a trace, so the log is bounded by its bytes as well as its length
+const MAX_TRACES: usize = 10_000;
+const MAX_TRACED_TEXT_BYTES: usize = 256 * 1024 * 1024;
+
+// Reruns a recorded decision; a difference means the pipeline is not deterministic for that input
+#[update]
+async fn replay_decision(trace_id: u64) -> Result<ReplayReport, String> {
+    let _watch = traps::Watch::start("REPLAY_DECISION");
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may replay decisions".to_string());
+    }
+    let trace = DECISION_TRACES.with(|t| t.borrow().get(&trace_id).cloned())
+        .ok_or(format!("Decision trace not found: {}", trace_id))?;
+    let ruleset = ruleset(trace.ruleset_version)
+        .ok_or(format!("Ruleset {} of trace {} is no longer held", trace.ruleset_version, trace_id))?;
+
+    let replayed = DecisionOutcome::of(&replay(&trace, &ruleset).await?);
+    let differences = differences(&trace.decision, &replayed);
+    ic_cdk::println!(
+        "🔁 Trace {} replayed under ruleset v{}: {}",
+        trace_id,
+        ruleset.version,
+        if differences.is_empty() { "reproduced" } else { "NOT reproduced" }
+    );
+    Ok(ReplayReport {
+        trace_id,
+        ruleset,
+        recorded: trace.decision,
+        replayed,
+        reproduced: differences.is_empty(),
+        differences,
+        replayed_by: caller(),
+        replayed_at: clock::now(),
+    })
+}
+
+// Newest first; an investigation usually starts from the patient the outcome concerned
+#[query]
+fn get_decision_traces(patient_id: String, limit: u32) -> Result<Vec<DecisionTrace>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may read decision traces".to_string());
+    }
+    Ok(DECISION_TRACES.with(|t| {
+        t.borrow()
+            .values()
+            .rev()
+            .filter(|trace| trace.patient_id == patient_id)
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    }))
+}
+
+// The patient's newest analysis; only one where the external model answered can contradict the on-chain
+// extraction, the notes left when it was skipped or unavailable are not contradictions
+#[query]
+fn get_directive_review(patient_id: String) -> Result<Option<DirectiveReview>, String> {
+    let requester = caller();
+    let executor = Principal::from_text(EXECUTOR_AI_CANISTER_ID).ok();
+    if !ic_cdk::api::is_controller(&requester) && executor != Some(requester) {
+        return Err("Only controllers or executor_ai may read directive reviews".to_string());
+    }
+    Ok(DECISION_TRACES.with(|t| {
+        t.borrow()
+            .values()
+            .rev()
+            .find(|trace| trace.patient_id == patient_id)
+            .map(|trace| DirectiveReview {
+                trace_id: trace.trace_id,
+                legal_validity_score: trace.decision.legal_validity_score,
+                contradictions: match trace.external {
+                    ExternalOutcome::Extracted(_) => trace.decision.source_disagreements.clone(),
+                    _ => Vec::new(),
+                },
+                recorded_at: trace.recorded_at,
+            })
+    }))
+}
+
+#[query]
+fn get_decision_ruleset(version: u64) -> Result<DecisionRuleset, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may read rulesets".to_string());
+    }
+    ruleset(version).ok_or(format!("Ruleset not found: {}", version))
+}
+
+// The ruleset a new analysis for the tenant runs under; the current version is reused until something in it changes
+pub(crate) fn ruleset_in_effect(tenant_id: Option<&str>) -> DecisionRuleset {
+    let (overrides_version, extensions) = tenant_config::keyword_extensions(tenant_id);
+    // Tenant keywords follow the global ones, in the order the compiled matcher holds them
+    let keywords: BTreeMap<String, Vec<String>> = directive_types::all_keywords()
+        .into_iter()
+        .map(|(directive_type, mut list)| {
+            let extra: Vec<String> = extensions.get(&directive_type)
+                .into_iter()
+                .flatten()
+                .filter(|k| !list.contains(k))
+                .cloned()
+                .collect();
+            list.extend(extra);
+            (directive_type, list)
+        })
+        .collect();
+    let confidence_thresholds = keywords.keys()
+        .map(|t| (t.clone(), tenant_config::confidence_threshold(tenant_id, t)))
+        .collect();
+    let frozen = DecisionRuleset {
+        version: 0,
+        tenant_id: tenant_id.map(String::from),
+        keywords,
+        confidence_thresholds,
+        scoring_features: scoring::pipeline(),
+        on_chain_cutoff: tenant_config::on_chain_cutoff(tenant_id),
+        dictionary_version: DICTIONARY_VERSION.with(|v| *v.borrow()),
+        overrides_version,
+        in_effect_from: clock::now(),
+    };
+
+    let current = CURRENT_RULESETS.with(|c| c.borrow().get(&frozen.tenant_id).copied()).and_then(ruleset);
+    if let Some(current) = current.filter(|current| current.same_rules(&frozen)) {
+        return current;
+    }
+    let ruleset = RULESETS_IN_EFFECT.with(|r| {
+        let mut rulesets = r.borrow_mut();
+        let ruleset = DecisionRuleset { version: rulesets.keys().next_back().map_or(1, |v| v + 1), ..frozen };
+        rulesets.insert(ruleset.version, ruleset.clone());
+        ruleset
+    });
+    CURRENT_RULESETS.with(|c| c.borrow_mut().insert(ruleset.tenant_id.clone(), ruleset.version));
+    ruleset
+}
+
+pub(crate) fn ruleset(version: u64) -> Option<DecisionRuleset> {
+    RULESETS_IN_EFFECT.with(|r| r.borrow().get(&version).cloned())
+}
+
+impl DecisionRuleset {
+    // Global plus tenant keywords for a directive type, for confidence scoring
+    pub(crate) fn keyword_count(&self, directive_type: &str) -> usize {
+        self.keywords.get(directive_type).map_or(0, Vec::len).max(1)
+    }
+
+    pub(crate) fn threshold(&self, directive_type: &str) -> f32 {
+        self.confidence_thresholds.get(directive_type).copied().unwrap_or(tenant_config::DEFAULT_CONFIDENCE_THRESHOLD)
+    }
+
+    fn same_rules(&self, other: &DecisionRuleset) -> bool {
+        self.keywords == other.keywords
+            && self.confidence_thresholds == other.confidence_thresholds
+            && self.scoring_features == other.scoring_features
+            && self.on_chain_cutoff == other.on_chain_cutoff
+            && self.dictionary_version == other.dictionary_version
+            && self.overrides_version == other.overrides_version
+    }
+}
+
+impl DecisionOutcome {
+    fn of(analysis: &MedicalDirectiveAnalysis) -> Self {
+        DecisionOutcome {
+            processing_method: analysis.processing_method.clone(),
+            directives: analysis.extracted_directives.iter().map(|d| DirectiveDecision {
+                directive_type: d.directive_type.clone(),
+                confidence: d.confidence,
+            }).collect(),
+            confidence_score: analysis.confidence_score,
+            legal_validity_score: analysis.legal_validity_score,
+            contraindications: analysis.contraindications.clone(),
+            requires_human_review: analysis.requires_human_review,
+            source_disagreements: analysis.source_disagreements.clone(),
+        }
+    }
+}
+
+// Called once a decision is settled, before its text fields are encoded for output
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn record(
+    endpoint: &str,
+    tenant_id: Option<&str>,
+    patient_id: &str,
+    screened_text: &str,
+    injection_flags: &[String],
+    ruleset_version: u64,
+    external: ExternalOutcome,
+    analysis: &MedicalDirectiveAnalysis,
+) -> u64 {
+    let trace_id = NEXT_TRACE_ID.with(|id| {
+        let mut id = id.borrow_mut();
+        *id += 1;
+        *id
+    });
+    let trace = DecisionTrace {
+        trace_id,
+        endpoint: endpoint.to_string(),
+        tenant_id: tenant_id.map(String::from),
+        patient_id: patient_id.to_string(),
+        screened_text: screened_text.to_string(),
+        injection_flags: injection_flags.to_vec(),
+        ruleset_version,
+        external,
+        decision: DecisionOutcome::of(analysis),
+        recorded_at: clock::now(),
+    };
+    DECISION_TRACES.with(|t| {
+        let mut traces = t.borrow_mut();
+        traces.insert(trace_id, trace);
+        let mut traced_bytes: usize = traces.values().map(|x| x.screened_text.len()).sum();
+        let mut evicted_any = false;
+        while traces.len() > MAX_TRACES || traced_bytes > MAX_TRACED_TEXT_BYTES {
+            let Some((_, evicted)) = traces.pop_first() else { break };
+            traced_bytes -= evicted.screened_text.len();
+            evicted_any = true;
+        }
+        if !evicted_any {
+            return;
+        }
+        // Superseded rulesets older than every remaining trace can no longer be replayed against;
+        // newer ones are kept, as an analysis still in flight may be running under one
+        let oldest_referenced = traces.values().map(|x| x.ruleset_version).min().unwrap_or(u64::MAX);
+        let current: Vec<u64> = CURRENT_RULESETS.with(|c| c.borrow().values().copied().collect());
+        RULESETS_IN_EFFECT.with(|r| {
+            r.borrow_mut().retain(|version, _| *version >= oldest_referenced || current.contains(version));
+        });
+    });
+    trace_id
+}
+
+// The live pipeline from preprocessing on, with the recorded ruleset in place of the live one and
+// the recorded external answer in place of an outcall
+async fn replay(trace: &DecisionTrace, ruleset: &DecisionRuleset) -> Result<MedicalDirectiveAnalysis, String> {
+    let preprocessed = preprocess_medical_text(&trace.screened_text)?;
+    let simple_extraction = if preprocessed.len() > chunking::CHUNKED_THRESHOLD_BYTES {
+        chunking::extract_in_windows(&preprocessed, ruleset).await?
+    } else {
+        extract_simple_patterns(&preprocessed, ruleset)?
+    };
+    let external = if simple_extraction.confidence_score >= ruleset.on_chain_cutoff {
+        ExternalOutcome::NotNeeded
+    } else {
+        match &trace.external {
+            // The replay wants the external model where the original did not ask it
+            ExternalOutcome::NotNeeded if injection::blocks_outcall(&trace.injection_flags) => {
+                ExternalOutcome::SkippedForInjection
+            }
+            ExternalOutcome::NotNeeded => ExternalOutcome::Unavailable("No external answer was recorded".to_string()),
+            recorded => recorded.clone(),
+        }
+    };
+    Ok(settle(simple_extraction, &external, &trace.injection_flags))
+}
+
+// Exact comparison: the same inputs under the same ruleset must give bit-identical scores
+fn differences(recorded: &DecisionOutcome, replayed: &DecisionOutcome) -> Vec<String> {
+    let mut differences = Vec::new();
+    if recorded.processing_method != replayed.processing_method {
+        differences.push(format!(
+            "Processing method: recorded {}, replayed {}",
+            recorded.processing_method, replayed.processing_method
+        ));
+    }
+    for directive in &recorded.directives {
+        match replayed.directives.iter().find(|d| d.directive_type == directive.directive_type) {
+            None => differences.push(format!("{}: extracted originally, not on replay", directive.directive_type)),
+            Some(again) if again.confidence != directive.confidence => differences.push(format!(
+                "{}: confidence recorded {}, replayed {}",
+                directive.directive_type, directive.confidence, again.confidence
+            )),
+            Some(_) => {}
+        }
+    }
+    for directive in &replayed.directives {
+        if !recorded.directives.iter().any(|d| d.directive_type == directive.directive_type) {
+            differences.push(format!("{}: extracted on replay, not originally", directive.directive_type));
+        }
+    }
+    if recorded.confidence_score != replayed.confidence_score {
+        differences.push(format!(
+            "Overall confidence: recorded {}, replayed {}",
+            recorded.confidence_score, replayed.confidence_score
+        ));
+    }
+    if recorded.legal_validity_score != replayed.legal_validity_score {
+        differences.push(format!(
+            "Legal validity: recorded {}, replayed {}",
+            recorded.legal_validity_score, replayed.legal_validity_score
+        ));
+    }
+    if recorded.contraindications != replayed.contraindications {
+        differences.push(format!(
+            "Contraindications: recorded {:?}, replayed {:?}",
+            recorded.contraindications, replayed.contraindications
+        ));
+    }
+    if recorded.requires_human_review != replayed.requires_human_review {
+        differences.push(format!(
+            "Human review: recorded {}, replayed {}",
+            recorded.requires_human_review, replayed.requires_human_review
+        ));
+    }
+    if recorded.source_disagreements != replayed.source_disagreements {
+        differences.push(format!(
+            "Source disagreements: recorded {:?}, replayed {:?}",
+            recorded.source_disagreements, replayed.source_disagreements
+        ));
+    }
+    differences
+}
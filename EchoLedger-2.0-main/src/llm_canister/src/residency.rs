@@ -0,0 +1,165 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::clock;
+use crate::providers::LlmProviderConfig;
+use crate::tenancy;
+
+// Where a tenant's text may be sent. Tenants without a policy, and callers outside any tenant, are unrestricted.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ResidencyPolicy {
+    pub tenant_id: String,
+    pub allowed_regions: Vec<String>,   // "EU" also admits "EU-WEST", "EU-CENTRAL", ...
+    pub allowed_providers: Vec<String>, // Empty means any provider in an allowed region
+    pub updated_by: Principal,
+    pub updated_at: u64,
+}
+
+// A provider the failover order would have tried but the tenant's policy ruled out
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ResidencyViolation {
+    pub tenant_id: String,
+    pub provider_id: String,
+    pub provider_region: String,
+    pub template_id: String,
+    pub reason: String,
+    pub blocked_at: u64,
+}
+
+// Carried across upgrades; a policy that lapsed on redeploy would let text leave its region
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct ResidencyState {
+    policies: Vec<ResidencyPolicy>,
+}
+
+thread_local! {
+    static RESIDENCY_POLICIES: RefCell<BTreeMap<String, ResidencyPolicy>> = const { RefCell::new(BTreeMap::new()) };
+
+    static RESIDENCY_VIOLATIONS: RefCell<Vec<ResidencyViolation>> = const { RefCell::new(Vec::new()) };
+}
+
+const MAX_VIOLATIONS_KEPT: usize = 1_000;
+
+#[update]
+fn set_residency_policy(
+    tenant_id: String,
+    allowed_regions: Vec<String>,
+    allowed_providers: Vec<String>,
+) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may set data residency policies".to_string());
+    }
+    if allowed_regions.iter().all(|r| r.trim().is_empty()) {
+        return Err("A residency policy must allow at least one region".to_string());
+    }
+
+    let policy = ResidencyPolicy {
+        tenant_id: tenant_id.clone(),
+        allowed_regions: allowed_regions.iter().map(|r| r.trim().to_uppercase()).filter(|r| !r.is_empty()).collect(),
+        allowed_providers,
+        updated_by: caller(),
+        updated_at: clock::now(),
+    };
+    RESIDENCY_POLICIES.with(|policies| policies.borrow_mut().insert(tenant_id, policy));
+    Ok(())
+}
+
+#[update]
+fn remove_residency_policy(tenant_id: String) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may remove data residency policies".to_string());
+    }
+    RESIDENCY_POLICIES.with(|policies| policies.borrow_mut().remove(&tenant_id))
+        .map(|_| ())
+        .ok_or_else(|| format!("No residency policy for {}", tenant_id))
+}
+
+// Members may read their own tenant's policy once this canister has seen them; controllers may read any
+#[query]
+fn get_residency_policy(tenant_id: String) -> Result<Option<ResidencyPolicy>, String> {
+    let is_member = tenancy::cached_tenant(caller()).is_some_and(|t| t == tenant_id);
+    if !is_member && !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only the tenant's members or a canister controller may read this policy".to_string());
+    }
+    Ok(RESIDENCY_POLICIES.with(|policies| policies.borrow().get(&tenant_id).cloned()))
+}
+
+#[query]
+fn get_residency_violations(limit: u32) -> Result<Vec<ResidencyViolation>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read residency violations".to_string());
+    }
+    Ok(RESIDENCY_VIOLATIONS.with(|violations| {
+        violations.borrow().iter().rev().take(limit as usize).cloned().collect()
+    }))
+}
+
+// Drop the providers the tenant may not use, logging each one, and keep the rest in order
+pub(crate) fn permitted_providers(
+    tenant_id: Option<&str>,
+    template_id: &str,
+    candidates: Vec<LlmProviderConfig>,
+) -> Vec<LlmProviderConfig> {
+    let Some(policy) = tenant_id.and_then(|t| RESIDENCY_POLICIES.with(|policies| policies.borrow().get(t).cloned()))
+    else {
+        return candidates;
+    };
+
+    candidates
+        .into_iter()
+        .filter(|config| match check_provider(&policy, config) {
+            Ok(()) => true,
+            Err(reason) => {
+                ic_cdk::println!("🚫 Residency policy blocked {} for {}: {}", config.provider_id, policy.tenant_id, reason);
+                record_violation(&policy.tenant_id, config, template_id, reason);
+                false
+            }
+        })
+        .collect()
+}
+
+fn check_provider(policy: &ResidencyPolicy, config: &LlmProviderConfig) -> Result<(), String> {
+    if !policy.allowed_providers.is_empty() && !policy.allowed_providers.contains(&config.provider_id) {
+        return Err(format!("provider {} is not on the tenant's allow-list", config.provider_id));
+    }
+    let region = config.region.trim().to_uppercase();
+    let in_allowed_region = policy.allowed_regions.iter().any(|allowed| {
+        region == *allowed || region.strip_prefix(allowed.as_str()).is_some_and(|rest| rest.starts_with('-'))
+    });
+    if !in_allowed_region {
+        return Err(format!("region {} is outside {}", config.region, policy.allowed_regions.join(", ")));
+    }
+    Ok(())
+}
+
+fn record_violation(tenant_id: &str, config: &LlmProviderConfig, template_id: &str, reason: String) {
+    RESIDENCY_VIOLATIONS.with(|violations| {
+        let mut violations = violations.borrow_mut();
+        if violations.len() >= MAX_VIOLATIONS_KEPT {
+            violations.remove(0);
+        }
+        violations.push(ResidencyViolation {
+            tenant_id: tenant_id.to_string(),
+            provider_id: config.provider_id.clone(),
+            provider_region: config.region.clone(),
+            template_id: template_id.to_string(),
+            reason,
+            blocked_at: clock::now(),
+        });
+    });
+}
+
+pub(crate) fn snapshot() -> ResidencyState {
+    ResidencyState { policies: RESIDENCY_POLICIES.with(|p| p.borrow().values().cloned().collect()) }
+}
+
+pub(crate) fn restore(state: Option<ResidencyState>) {
+    let Some(state) = state else {
+        return;
+    };
+    RESIDENCY_POLICIES.with(|p| *p.borrow_mut() = state.policies.into_iter().map(|x| (x.tenant_id.clone(), x)).collect());
+}
@@ -0,0 +1,141 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::cell::RefCell;
+
+// One named step of on-chain confidence scoring. Features run in order and their
+// contributions are summed, then clamped to 0-1.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ScoringFeature {
+    pub name: String,
+    pub kind: String, // "KEYWORD_COVERAGE": weight x share of the type's keywords found; "PHRASE": weight if any phrase occurs
+    pub phrases: Vec<String>,
+    pub weight: f32,
+    pub enabled: bool,
+}
+
+// What one feature added to a directive's confidence, for the explanation output
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FeatureContribution {
+    pub feature: String,
+    pub contribution: f32,
+}
+
+thread_local! {
+    static SCORING_PIPELINE: RefCell<Vec<ScoringFeature>> = RefCell::new(default_pipeline());
+}
+
+const FEATURE_KINDS: [&str; 2] = ["KEYWORD_COVERAGE", "PHRASE"];
+pub(crate) const MAX_FEATURES: usize = 32;
+const MAX_FEATURE_WEIGHT: f32 = 1.0;
+
+// The boosters that used to be hard-coded in calculate_keyword_confidence, unchanged in effect
+fn default_pipeline() -> Vec<ScoringFeature> {
+    let feature = |name: &str, kind: &str, phrases: &[&str], weight: f32| ScoringFeature {
+        name: name.to_string(),
+        kind: kind.to_string(),
+        phrases: phrases.iter().map(|p| p.to_string()).collect(),
+        weight,
+        enabled: true,
+    };
+    vec![
+        feature("keyword_coverage", "KEYWORD_COVERAGE", &[], 1.0),
+        feature("explicit_refusal", "PHRASE", &["i do not want", "i refuse"], 0.1),
+        feature("execution_formalities", "PHRASE", &["witnessed", "signed"], 0.05),
+        feature("capacity_statement", "PHRASE", &["sound mind"], 0.05),
+    ]
+}
+
+// Adds a feature, or replaces the one with the same name in place
+#[update]
+fn set_scoring_feature(feature: ScoringFeature) -> Result<Vec<ScoringFeature>, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may change the scoring pipeline".to_string());
+    }
+    let feature = validate_feature(feature)?;
+    SCORING_PIPELINE.with(|p| {
+        let mut pipeline = p.borrow_mut();
+        match pipeline.iter().position(|f| f.name == feature.name) {
+            Some(index) => pipeline[index] = feature,
+            None if pipeline.len() >= MAX_FEATURES => {
+                return Err(format!("At most {} scoring features", MAX_FEATURES));
+            }
+            None => pipeline.push(feature),
+        }
+        Ok(pipeline.clone())
+    })
+}
+
+// Checks a feature and normalizes its phrases; shared with candidate rulesets
+pub(crate) fn validate_feature(feature: ScoringFeature) -> Result<ScoringFeature, String> {
+    if feature.name.trim().is_empty() {
+        return Err("Scoring features need a name".to_string());
+    }
+    if !FEATURE_KINDS.contains(&feature.kind.as_str()) {
+        return Err(format!("Unknown scoring feature kind: {}", feature.kind));
+    }
+    if !(-MAX_FEATURE_WEIGHT..=MAX_FEATURE_WEIGHT).contains(&feature.weight) {
+        return Err(format!("Feature weights must be within -{0}..{0}", MAX_FEATURE_WEIGHT));
+    }
+    if feature.kind == "PHRASE" && feature.phrases.iter().all(|p| p.trim().is_empty()) {
+        return Err("PHRASE features need at least one phrase".to_string());
+    }
+
+    // Matched against preprocessed text, which is lowercase with single spaces
+    Ok(ScoringFeature {
+        phrases: feature.phrases.iter()
+            .map(|p| p.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase())
+            .filter(|p| !p.is_empty())
+            .collect(),
+        ..feature
+    })
+}
+
+#[update]
+fn set_scoring_feature_enabled(name: String, enabled: bool) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may change the scoring pipeline".to_string());
+    }
+    SCORING_PIPELINE.with(|p| {
+        let mut pipeline = p.borrow_mut();
+        let feature = pipeline.iter_mut().find(|f| f.name == name).ok_or(format!("Unknown scoring feature: {}", name))?;
+        feature.enabled = enabled;
+        Ok(())
+    })
+}
+
+#[query]
+fn get_scoring_pipeline() -> Vec<ScoringFeature> {
+    pipeline()
+}
+
+pub(crate) fn pipeline() -> Vec<ScoringFeature> {
+    SCORING_PIPELINE.with(|p| p.borrow().clone())
+}
+
+// Confidence for a directive type with `matches` of its `total_keywords` found in `text`, and
+// what each enabled feature contributed, under the pipeline of a ruleset. `text` is empty when
+// only the keyword counts are known.
+pub(crate) fn score_with(
+    pipeline: &[ScoringFeature],
+    matches: usize,
+    total_keywords: usize,
+    text: &str,
+) -> (f32, Vec<FeatureContribution>) {
+    let breakdown: Vec<FeatureContribution> = pipeline
+        .iter()
+        .filter(|feature| feature.enabled)
+        .map(|feature| {
+            let signal = match feature.kind.as_str() {
+                "KEYWORD_COVERAGE" => matches as f32 / total_keywords.max(1) as f32,
+                _ if feature.phrases.iter().any(|phrase| text.contains(phrase.as_str())) => 1.0,
+                _ => 0.0,
+            };
+            FeatureContribution { feature: feature.name.clone(), contribution: feature.weight * signal }
+        })
+        .filter(|c| c.contribution != 0.0)
+        .collect();
+    let confidence = breakdown.iter().map(|c| c.contribution).sum::<f32>().clamp(0.0, 1.0);
+    (confidence, breakdown)
+}
@@ -0,0 +1,231 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+use crate::evaluation::{self, EvaluationExample, MAX_EXAMPLES_PER_DATASET};
+use crate::validation;
+
+// Parameters for a batch of synthetic advance directives. The same spec and seed always
+// produce the same texts, so an evaluation can be rerun against an identical dataset.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SyntheticSpec {
+    pub count: u32,
+    pub directive_types: Vec<String>,
+    pub jurisdictions: Vec<String>, // "US-CA", "US-NY", "UK", "DE"
+    pub languages: Vec<String>,     // "en", "es", "fr"
+    pub noise_level: f32,           // 0 is clean prose; 1 adds typos, shouting and filler to most sentences
+    pub seed: u64,
+}
+
+const LANGUAGES: [&str; 3] = ["en", "es", "fr"];
+const JURISDICTIONS: [&str; 4] = ["US-CA", "US-NY", "UK", "DE"];
+// Fictional declarants and agents; nothing here is drawn from a real record
+const NAMES: [&str; 6] = ["Alex Morgan", "Sam Rivera", "Jordan Lee", "Casey Novak", "Robin Okafor", "Taylor Brandt"];
+const AGENT_RELATIONS: [(&str, &str, &str); 3] = [("my sister", "mi hermana", "ma sœur"), ("my son", "mi hijo", "mon fils"), ("my partner", "mi pareja", "mon conjoint")];
+const MAX_DIRECTIVE_TYPES_PER_TEXT: usize = 3;
+const MAX_SPEC_ENTRIES: usize = 16;
+
+// Generated texts, labeled with the directive types they were built from
+#[query]
+fn generate_synthetic_directives(spec: SyntheticSpec) -> Result<Vec<EvaluationExample>, String> {
+    generate(&spec)
+}
+
+// Generates straight into an evaluation dataset, so large datasets never cross the wire
+#[update]
+fn generate_evaluation_dataset(dataset_id: String, spec: SyntheticSpec) -> Result<u32, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may upload evaluation datasets".to_string());
+    }
+    let description = format!(
+        "Synthetic: {} texts, types {:?}, jurisdictions {:?}, languages {:?}, noise {:.2}, seed {}",
+        spec.count, spec.directive_types, spec.jurisdictions, spec.languages, spec.noise_level, spec.seed
+    );
+    evaluation::store_dataset(dataset_id, description, generate(&spec)?)
+}
+
+fn generate(spec: &SyntheticSpec) -> Result<Vec<EvaluationExample>, String> {
+    if spec.count == 0 || spec.count as usize > MAX_EXAMPLES_PER_DATASET {
+        return Err(format!("Generate between 1 and {} texts at a time", MAX_EXAMPLES_PER_DATASET));
+    }
+    validation::collection("directive_types", spec.directive_types.len(), MAX_SPEC_ENTRIES)?;
+    validation::collection("jurisdictions", spec.jurisdictions.len(), MAX_SPEC_ENTRIES)?;
+    validation::collection("languages", spec.languages.len(), MAX_SPEC_ENTRIES)?;
+    if spec.directive_types.is_empty() {
+        return Err("Name at least one directive type".to_string());
+    }
+    if let Some(unknown) = spec.directive_types.iter().find(|t| clause(t, "en", "").is_none()) {
+        return Err(format!("No synthetic phrasing for directive type {}", unknown));
+    }
+    if let Some(unknown) = spec.languages.iter().find(|l| !LANGUAGES.contains(&l.as_str())) {
+        return Err(format!("Unsupported language: {}", unknown));
+    }
+    if let Some(unknown) = spec.jurisdictions.iter().find(|j| !JURISDICTIONS.contains(&j.as_str())) {
+        return Err(format!("Unsupported jurisdiction: {}", unknown));
+    }
+    if !(0.0..=1.0).contains(&spec.noise_level) {
+        return Err("The noise level must be within 0-1".to_string());
+    }
+
+    let languages: Vec<&str> = if spec.languages.is_empty() { vec!["en"] } else { spec.languages.iter().map(String::as_str).collect() };
+    let jurisdictions: Vec<&str> = if spec.jurisdictions.is_empty() { vec!["US-CA"] } else { spec.jurisdictions.iter().map(String::as_str).collect() };
+    let mut rng = SplitMix64(spec.seed);
+
+    Ok((0..spec.count).map(|i| {
+        let language = *rng.pick(&languages);
+        let jurisdiction = *rng.pick(&jurisdictions);
+        let name = *rng.pick(&NAMES);
+        let relation = *rng.pick(&AGENT_RELATIONS);
+        let agent = match language { "es" => relation.1, "fr" => relation.2, _ => relation.0 };
+
+        // A non-empty subset of the requested types, in the order requested
+        let wanted = 1 + rng.below(spec.directive_types.len().min(MAX_DIRECTIVE_TYPES_PER_TEXT));
+        let mut chosen: Vec<&String> = Vec::new();
+        while chosen.len() < wanted {
+            let candidate = rng.pick(&spec.directive_types);
+            if !chosen.contains(&candidate) {
+                chosen.push(candidate);
+            }
+        }
+        chosen.sort_by_key(|t| spec.directive_types.iter().position(|x| x == *t));
+
+        let mut sentences = vec![opening(language, name), jurisdiction_clause(jurisdiction, language)];
+        for directive_type in &chosen {
+            sentences.push(clause(directive_type, language, agent).unwrap_or_default());
+            if rng.chance(spec.noise_level * 0.5) {
+                sentences.push(filler(language, rng.below(3)).to_string());
+            }
+        }
+        sentences.push(closing(language, 1 + rng.below(28), 1 + rng.below(12)));
+        let text = sentences.into_iter()
+            .map(|s| add_noise(&s, spec.noise_level, &mut rng))
+            .collect::<Vec<_>>()
+            .join(if rng.chance(spec.noise_level * 0.3) { "\n\n" } else { " " });
+
+        EvaluationExample {
+            example_id: format!("SYN_{}_{}", spec.seed, i + 1),
+            text,
+            expected_directive_types: chosen.into_iter().cloned().collect(),
+        }
+    }).collect())
+}
+
+fn opening(language: &str, name: &str) -> String {
+    match language {
+        "es" => format!("Yo, {}, en pleno uso de mis facultades mentales, otorgo el siguiente documento.", name),
+        "fr" => format!("Je soussigné(e), {}, sain(e) d'esprit, rédige les présentes volontés.", name),
+        _ => format!("I, {}, being of sound mind, make this declaration voluntarily.", name),
+    }
+}
+
+fn jurisdiction_clause(jurisdiction: &str, language: &str) -> String {
+    let law = match jurisdiction {
+        "US-NY" => "New York Public Health Law, article 29-C",
+        "UK" => "Mental Capacity Act 2005",
+        "DE" => "§ 1827 BGB",
+        _ => "California Probate Code, section 4701",
+    };
+    match language {
+        "es" => format!("Este documento se otorga conforme a {}.", law),
+        "fr" => format!("Le présent document est établi conformément à {}.", law),
+        _ => format!("This directive is made under {}.", law),
+    }
+}
+
+// One sentence expressing a directive type; None for a type the generator cannot phrase
+fn clause(directive_type: &str, language: &str, agent: &str) -> Option<String> {
+    let text = match (directive_type, language) {
+        ("DNR", "es") => "No deseo reanimación cardiopulmonar ni ventilación mecánica; solo cuidados paliativos.".to_string(),
+        ("DNR", "fr") => "Je ne veux ni réanimation ni ventilation mécanique ; uniquement des soins palliatifs.".to_string(),
+        ("DNR", _) => "I do not want CPR or mechanical ventilation if my heart stops; comfort care only.".to_string(),
+        ("ORGAN_DONATION", "es") => "Deseo donar mis órganos, incluidos el riñón y el hígado, para trasplante.".to_string(),
+        ("ORGAN_DONATION", "fr") => "Je souhaite faire don de mes organes, dont les reins et le foie, pour une greffe.".to_string(),
+        ("ORGAN_DONATION", _) => "I wish to donate my organs, including kidney and liver, for transplant.".to_string(),
+        ("DATA_CONSENT", "es") => "Autorizo el uso de mis datos anonimizados para investigación médica.".to_string(),
+        ("DATA_CONSENT", "fr") => "J'accepte que mes données anonymisées servent à la recherche médicale.".to_string(),
+        ("DATA_CONSENT", _) => "I consent to anonymized data from my records being shared for medical research.".to_string(),
+        ("POWER_OF_ATTORNEY", "es") => format!("Nombro a {} como representante para las decisiones médicas.", agent),
+        ("POWER_OF_ATTORNEY", "fr") => format!("Je désigne {} comme personne de confiance pour les décisions médicales.", agent),
+        ("POWER_OF_ATTORNEY", _) => format!("I appoint {} as my healthcare proxy with power of attorney for medical decisions.", agent),
+        ("LIVING_WILL", "es") => "Este testamento vital recoge mis instrucciones previas sobre el final de la vida.".to_string(),
+        ("LIVING_WILL", "fr") => "Ces directives anticipées expriment mes volontés concernant la fin de vie.".to_string(),
+        ("LIVING_WILL", _) => "This living will is my advance directive and sets out my end-of-life wishes.".to_string(),
+        ("DIGITAL_LEGACY", "es") => format!("Quiero que se eliminen mis cuentas en línea y que {} reciba acceso a mi correo electrónico.", agent),
+        ("DIGITAL_LEGACY", "fr") => format!("Je souhaite que mes comptes en ligne soient supprimés et que {} reçoive l'accès à ma messagerie.", agent),
+        ("DIGITAL_LEGACY", _) => format!("For my digital legacy, delete my social media accounts and transfer my email account to {}.", agent),
+        ("DISPOSITION", "es") => "Deseo ser incinerado según los ritos de mi religión; que se encargue una funeraria local.".to_string(),
+        ("DISPOSITION", "fr") => "Je souhaite être incinéré selon les rites de ma religion, avec des obsèques confiées aux pompes funèbres de ma ville.".to_string(),
+        ("DISPOSITION", _) => "I wish to be cremated according to my religious rites, with my funeral arranged by a local funeral home.".to_string(),
+        ("WHOLE_BODY_DONATION", "es") => "Deseo donar mi cuerpo a la ciencia, al programa de donación de una facultad de medicina.".to_string(),
+        ("WHOLE_BODY_DONATION", "fr") => "Je souhaite faire don de mon corps à la science, auprès du centre de don d'une faculté de médecine.".to_string(),
+        ("WHOLE_BODY_DONATION", _) => "I wish to donate my body to science through a medical school's anatomical donation program.".to_string(),
+        ("AUTOPSY", "es") => "Por motivos religiosos no deseo que se me practique una autopsia; acepto un examen externo.".to_string(),
+        ("AUTOPSY", "fr") => "Pour des raisons religieuses, je refuse toute autopsie ; un examen externe est acceptable.".to_string(),
+        ("AUTOPSY", _) => "For religious reasons I refuse an autopsy; an external examination or imaging is acceptable.".to_string(),
+        ("RESEARCH_ENROLLMENT", "es") => "No deseo ser incluido en estudios de investigación de emergencia sin mi consentimiento, salvo en ensayos sobre paro cardíaco.".to_string(),
+        ("RESEARCH_ENROLLMENT", "fr") => "Je refuse d'être inclus dans une recherche d'urgence sans mon consentement, sauf pour les essais sur l'arrêt cardiaque.".to_string(),
+        ("RESEARCH_ENROLLMENT", _) => "I do not want to be enrolled in emergency research without my consent, except cardiac arrest trials, which I agree to.".to_string(),
+        _ => return None,
+    };
+    Some(text)
+}
+
+// Sentences with no directive content, to dilute keyword density
+fn filler(language: &str, variant: usize) -> &'static str {
+    let options: [&str; 3] = match language {
+        "es" => ["Mi familia conoce estas decisiones.", "He hablado de esto con mi médico.", "Guardo una copia en casa."],
+        "fr" => ["Ma famille connaît ces décisions.", "J'en ai parlé avec mon médecin.", "Une copie est conservée chez moi."],
+        _ => ["My family is aware of these decisions.", "I have discussed this with my physician.", "A copy is kept at home."],
+    };
+    options[variant]
+}
+
+fn closing(language: &str, day: usize, month: usize) -> String {
+    match language {
+        "es" => format!("Firmado ante testigos el {} del {} de 2024.", day, month),
+        "fr" => format!("Signé devant témoins le {}/{}/2024.", day, month),
+        _ => format!("Signed and witnessed on {}/{}/2024.", month, day),
+    }
+}
+
+// Swapped letters in some words and the occasional sentence in capitals, at a rate set by the noise level
+fn add_noise(sentence: &str, noise_level: f32, rng: &mut SplitMix64) -> String {
+    let mut words: Vec<String> = sentence.split(' ').map(|w| {
+        let mut chars: Vec<char> = w.chars().collect();
+        if chars.len() > 3 && rng.chance(noise_level * 0.1) {
+            let at = 1 + rng.below(chars.len() - 2);
+            chars.swap(at, at - 1);
+        }
+        chars.into_iter().collect()
+    }).collect();
+    if rng.chance(noise_level * 0.2) {
+        words = words.into_iter().map(|w| w.to_uppercase()).collect();
+    }
+    words.join(if rng.chance(noise_level * 0.2) { "  " } else { " " })
+}
+
+// Small, seedable and identical on every replica; not for anything security sensitive
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+
+    fn chance(&mut self, p: f32) -> bool {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32 <= p && p > 0.0
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
@@ -0,0 +1,89 @@
+use ic_cdk::{call, caller};
+use ic_cdk::api::call::RejectionCode;
+use ic_cdk_macros::update;
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::{clock, traps};
+use crate::ProcessingStats;
+
+const DIRECTIVE_MANAGER_CANISTER_ID: &str = "rdmx6-jaaaa-aaaah-qdrva-cai";
+// Membership changes reach this canister within this long
+const MEMBERSHIP_CACHE_NANOS: u64 = 5 * 60 * 1_000_000_000;
+
+// Mirrors directive_manager's TenantMembership
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TenantMembership {
+    pub tenant_id: String,
+    pub status: String,
+    pub is_admin: bool,
+    pub jurisdiction: String,
+}
+
+thread_local! {
+    static MEMBERSHIP_CACHE: RefCell<BTreeMap<Principal, (Option<TenantMembership>, u64)>> =
+        const { RefCell::new(BTreeMap::new()) };
+
+    static TENANT_PROCESSING_STATS: RefCell<BTreeMap<String, ProcessingStats>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// The caller's processing statistics, scoped to their tenant
+#[update]
+async fn get_tenant_processing_statistics() -> Result<ProcessingStats, String> {
+    let _watch = traps::Watch::start("GET_TENANT_PROCESSING_STATISTICS");
+    let tenant_id = active_tenant(caller()).await?.ok_or("Caller does not belong to a tenant")?;
+    Ok(TENANT_PROCESSING_STATS.with(|stats| stats.borrow().get(&tenant_id).cloned()).unwrap_or_default())
+}
+
+// The tenant a caller acts for, or None outside any tenant; members of a suspended tenant are refused.
+// Tenancy is owned by directive_manager, so when it cannot be reached the request fails rather than run unscoped.
+pub(crate) async fn active_tenant(principal: Principal) -> Result<Option<String>, String> {
+    match resolve(principal).await? {
+        Some(membership) if membership.status != "ACTIVE" => {
+            Err(format!("Tenant {} is suspended", membership.tenant_id))
+        }
+        membership => Ok(membership.map(|m| m.tenant_id)),
+    }
+}
+
+// From the cache only; for queries, which cannot make the call
+pub(crate) fn cached_tenant(principal: Principal) -> Option<String> {
+    MEMBERSHIP_CACHE.with(|cache| {
+        cache.borrow().get(&principal).and_then(|(membership, _)| membership.as_ref().map(|m| m.tenant_id.clone()))
+    })
+}
+
+pub(crate) fn tenant_stats_mut<R>(tenant_id: &str, f: impl FnOnce(&mut ProcessingStats) -> R) -> R {
+    TENANT_PROCESSING_STATS.with(|stats| f(stats.borrow_mut().entry(tenant_id.to_string()).or_default()))
+}
+
+pub(crate) async fn resolve(principal: Principal) -> Result<Option<TenantMembership>, String> {
+    let now = clock::now();
+    let cached = MEMBERSHIP_CACHE.with(|cache| {
+        cache.borrow()
+            .get(&principal)
+            .filter(|(_, fetched_at)| now.saturating_sub(*fetched_at) < MEMBERSHIP_CACHE_NANOS)
+            .map(|(membership, _)| membership.clone())
+    });
+    if let Some(membership) = cached {
+        return Ok(membership);
+    }
+
+    let directive_manager = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID)
+        .map_err(|_| "Invalid directive manager canister ID".to_string())?;
+    let (result,): (Result<Option<TenantMembership>, String>,) =
+        call(directive_manager, "resolve_tenant", (principal,))
+            .await
+            .map_err(|(code, msg)| {
+                if code == RejectionCode::CanisterError {
+                    traps::record_callee_trap("directive_manager");
+                }
+                format!("Tenant lookup failed: {}", msg)
+            })?;
+    let membership = result?;
+
+    MEMBERSHIP_CACHE.with(|cache| cache.borrow_mut().insert(principal, (membership.clone(), now)));
+    Ok(membership)
+}
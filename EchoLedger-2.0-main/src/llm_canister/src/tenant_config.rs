@@ -0,0 +1,194 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::chunking::WINDOW_OVERLAP_BYTES;
+use crate::{clock, directive_types, tenancy, thresholds, traps, DICTIONARY_VERSION};
+
+// Directives scoring at least this are settled on-chain without an LLM
+pub(crate) const ON_CHAIN_CONFIDENCE_CUTOFF: f32 = 0.9;
+// Used for a directive type that has no threshold of its own
+pub(crate) const DEFAULT_CONFIDENCE_THRESHOLD: f32 = 0.7;
+const MAX_EXTENSION_KEYWORDS: usize = 100;
+
+// A tenant's departures from the global configuration; anything unset inherits
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TenantLlmOverrides {
+    pub tenant_id: String,
+    pub confidence_thresholds: BTreeMap<String, f32>,
+    pub on_chain_confidence_cutoff: Option<f32>,
+    pub keyword_extensions: BTreeMap<String, Vec<String>>, // Added to, never replacing, the global keywords
+    pub version: u64,
+    pub updated_by: Principal,
+    pub updated_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EffectiveThreshold {
+    pub directive_type: String,
+    pub threshold: f32,
+    pub source: String, // "GLOBAL", "TENANT"
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EffectiveKeywords {
+    pub directive_type: String,
+    pub global_keywords: Vec<String>,
+    pub tenant_keywords: Vec<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EffectiveLlmConfig {
+    pub tenant_id: Option<String>,
+    pub on_chain_confidence_cutoff: f32,
+    pub on_chain_confidence_cutoff_source: String,
+    pub confidence_thresholds: Vec<EffectiveThreshold>,
+    pub keywords: Vec<EffectiveKeywords>,
+    pub dictionary_version: u64,
+    pub overrides_version: u64,
+}
+
+// Carried across upgrades; tenants should not fall back to global settings on a redeploy
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct TenantConfigState {
+    overrides: Vec<TenantLlmOverrides>,
+}
+
+thread_local! {
+    static TENANT_OVERRIDES: RefCell<BTreeMap<String, TenantLlmOverrides>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// Tenant admins manage their own overrides; controllers may manage any
+#[update]
+async fn set_tenant_llm_overrides(
+    tenant_id: String,
+    confidence_thresholds: BTreeMap<String, f32>,
+    on_chain_confidence_cutoff: Option<f32>,
+    keyword_extensions: BTreeMap<String, Vec<String>>,
+) -> Result<EffectiveLlmConfig, String> {
+    let _watch = traps::Watch::start("SET_TENANT_LLM_OVERRIDES");
+    let requester = caller();
+    if !ic_cdk::api::is_controller(&requester) {
+        let membership = tenancy::resolve(requester).await?;
+        if !membership.is_some_and(|m| m.tenant_id == tenant_id && m.is_admin) {
+            return Err(format!("Only admins of tenant {} may change its configuration", tenant_id));
+        }
+    }
+
+    for (directive_type, threshold) in &confidence_thresholds {
+        if !directive_types::is_known(directive_type) {
+            return Err(format!("Unknown directive type: {}", directive_type));
+        }
+        if !(0.0..=1.0).contains(threshold) {
+            return Err(format!("Threshold for {} must be within 0-1", directive_type));
+        }
+    }
+    if on_chain_confidence_cutoff.is_some_and(|cutoff| !(0.0..=1.0).contains(&cutoff)) {
+        return Err("The on-chain confidence cutoff must be within 0-1".to_string());
+    }
+    let mut extensions = BTreeMap::new();
+    for (directive_type, keywords) in keyword_extensions {
+        if !directive_types::is_known(&directive_type) {
+            return Err(format!("Unknown directive type: {}", directive_type));
+        }
+        if keywords.len() > MAX_EXTENSION_KEYWORDS {
+            return Err(format!("At most {} extension keywords per directive type", MAX_EXTENSION_KEYWORDS));
+        }
+        // Same bound as set_directive_keywords, so windowed extraction still sees every phrase whole
+        if let Some(keyword) = keywords.iter().find(|k| k.trim().is_empty() || k.len() > WINDOW_OVERLAP_BYTES) {
+            return Err(format!("Keyword {:?} must be non-empty and at most {} bytes", keyword, WINDOW_OVERLAP_BYTES));
+        }
+        extensions.insert(directive_type, keywords.iter().map(|k| k.trim().to_lowercase()).collect());
+    }
+
+    TENANT_OVERRIDES.with(|overrides| {
+        let mut overrides = overrides.borrow_mut();
+        let version = overrides.get(&tenant_id).map_or(0, |o| o.version) + 1;
+        overrides.insert(tenant_id.clone(), TenantLlmOverrides {
+            tenant_id: tenant_id.clone(),
+            confidence_thresholds,
+            on_chain_confidence_cutoff,
+            keyword_extensions: extensions,
+            version,
+            updated_by: requester,
+            updated_at: clock::now(),
+        });
+    });
+    Ok(effective_config(Some(&tenant_id)))
+}
+
+// The global configuration for None; a tenant's resolved view for its members (once seen here) and controllers
+#[query]
+fn get_effective_llm_config(tenant_id: Option<String>) -> Result<EffectiveLlmConfig, String> {
+    if let Some(tenant_id) = &tenant_id {
+        let is_member = tenancy::cached_tenant(caller()).is_some_and(|t| t == *tenant_id);
+        if !is_member && !ic_cdk::api::is_controller(&caller()) {
+            return Err(format!("Only members of tenant {} may read its configuration", tenant_id));
+        }
+    }
+    Ok(effective_config(tenant_id.as_deref()))
+}
+
+pub(crate) fn confidence_threshold(tenant_id: Option<&str>, directive_type: &str) -> f32 {
+    with_overrides(tenant_id, |o| o.confidence_thresholds.get(directive_type).copied())
+        .or_else(|| thresholds::global_threshold(directive_type, clock::now()))
+        .unwrap_or(DEFAULT_CONFIDENCE_THRESHOLD)
+}
+
+pub(crate) fn on_chain_cutoff(tenant_id: Option<&str>) -> f32 {
+    with_overrides(tenant_id, |o| o.on_chain_confidence_cutoff).unwrap_or(ON_CHAIN_CONFIDENCE_CUTOFF)
+}
+
+// The tenant's extra keywords and the overrides version they belong to; empty when it has none
+pub(crate) fn keyword_extensions(tenant_id: Option<&str>) -> (u64, BTreeMap<String, Vec<String>>) {
+    with_overrides(tenant_id, |o| Some((o.version, o.keyword_extensions.clone())))
+        .filter(|(_, extensions)| !extensions.is_empty())
+        .unwrap_or_default()
+}
+
+fn with_overrides<R>(tenant_id: Option<&str>, f: impl FnOnce(&TenantLlmOverrides) -> Option<R>) -> Option<R> {
+    let tenant_id = tenant_id?;
+    TENANT_OVERRIDES.with(|overrides| overrides.borrow().get(tenant_id).and_then(f))
+}
+
+fn effective_config(tenant_id: Option<&str>) -> EffectiveLlmConfig {
+    let source = |overridden: bool| if overridden { "TENANT" } else { "GLOBAL" }.to_string();
+    let cutoff_override = with_overrides(tenant_id, |o| o.on_chain_confidence_cutoff);
+    let (_, extensions) = keyword_extensions(tenant_id);
+
+    let global = directive_types::all_keywords();
+    let confidence_thresholds = global.keys().map(|directive_type| EffectiveThreshold {
+        directive_type: directive_type.clone(),
+        threshold: confidence_threshold(tenant_id, directive_type),
+        source: source(with_overrides(tenant_id, |o| o.confidence_thresholds.get(directive_type).copied()).is_some()),
+    }).collect();
+    let keywords = global.into_iter().map(|(directive_type, global_keywords)| EffectiveKeywords {
+        tenant_keywords: extensions.get(&directive_type).cloned().unwrap_or_default(),
+        directive_type,
+        global_keywords,
+    }).collect();
+
+    EffectiveLlmConfig {
+        tenant_id: tenant_id.map(String::from),
+        on_chain_confidence_cutoff: cutoff_override.unwrap_or(ON_CHAIN_CONFIDENCE_CUTOFF),
+        on_chain_confidence_cutoff_source: source(cutoff_override.is_some()),
+        confidence_thresholds,
+        keywords,
+        dictionary_version: DICTIONARY_VERSION.with(|v| *v.borrow()),
+        overrides_version: with_overrides(tenant_id, |o| Some(o.version)).unwrap_or(0),
+    }
+}
+
+pub(crate) fn snapshot() -> TenantConfigState {
+    TenantConfigState { overrides: TENANT_OVERRIDES.with(|o| o.borrow().values().cloned().collect()) }
+}
+
+pub(crate) fn restore(state: Option<TenantConfigState>) {
+    let Some(state) = state else {
+        return;
+    };
+    TENANT_OVERRIDES.with(|o| *o.borrow_mut() = state.overrides.into_iter().map(|x| (x.tenant_id.clone(), x)).collect());
+}
@@ -0,0 +1,43 @@
+use super::*;
+
+// The clock is pinned so skew limits and trend windows can be exercised without waiting them out
+use canister_clock::at_hours as at;
+use trajectory::VitalObservation;
+
+const NANOS_PER_MINUTE: u64 = 60 * 1_000_000_000;
+
+fn heart_rate(value: f32, observed_at: u64) -> VitalObservation {
+    VitalObservation { measure: "HEART_RATE".to_string(), value, observed_at }
+}
+
+#[test]
+fn observations_ahead_of_the_clock_are_refused_beyond_the_skew() {
+    at(0);
+    let now = clock::now();
+    assert!(trajectory::validate(&[heart_rate(80.0, now + NANOS_PER_MINUTE)]).is_ok());
+    assert!(trajectory::validate(&[heart_rate(80.0, now + 10 * NANOS_PER_MINUTE)]).is_err());
+
+    // The same reading is acceptable once the clock has caught up with it
+    clock::advance(10 * NANOS_PER_MINUTE);
+    assert!(trajectory::validate(&[heart_rate(80.0, now + 10 * NANOS_PER_MINUTE)]).is_ok());
+}
+
+#[test]
+fn later_assessment_reads_the_trend_from_the_earlier_one() {
+    at(0);
+    trajectory::record_and_assess(None, "trajectory_patient_001", vec![heart_rate(80.0, clock::now())], 0.5);
+
+    at(2);
+    let preview = trajectory::preview(None, "trajectory_patient_001", &[heart_rate(110.0, clock::now())], 0.5);
+    let feature = preview.features.iter().find(|f| f.measure == "HEART_RATE").unwrap();
+    assert_eq!(feature.points, 2);
+    assert_eq!(feature.direction, "DETERIORATING");
+    assert_eq!(preview.trend, "DETERIORATING");
+}
+
+#[test]
+fn a_single_assessment_has_no_trend() {
+    at(0);
+    let preview = trajectory::preview(None, "trajectory_patient_002", &[heart_rate(110.0, clock::now())], 0.5);
+    assert_eq!(preview.trend, "INSUFFICIENT_DATA");
+}
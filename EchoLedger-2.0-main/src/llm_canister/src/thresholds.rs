@@ -0,0 +1,205 @@
+use ic_cdk::caller;
+use ic_cdk_macros::{update, query};
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::validation;
+use crate::{clock, directive_types};
+
+// One proposed move of a global confidence threshold. Nothing is ever deleted, so the
+// list doubles as the change history clinicians and auditors read.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ThresholdChange {
+    pub change_id: u64,
+    pub directive_type: String,
+    pub previous_value: f32,
+    pub new_value: f32,
+    pub reason: String,
+    pub effective_at: u64,
+    pub status: String, // "PENDING_APPROVAL", "SCHEDULED", "CANCELLED"
+    pub proposed_by: Principal,
+    pub proposed_at: u64,
+    pub approved_by: Option<Principal>,
+    pub approved_at: Option<u64>,
+    pub cancelled_by: Option<Principal>,
+}
+
+// Carried across upgrades; a redeploy must not revert thresholds or lose their history
+#[derive(CandidType, Deserialize, Clone)]
+pub(crate) struct ThresholdState {
+    changes: Vec<ThresholdChange>,
+    next_change_id: u64,
+    require_second_approver: bool,
+}
+
+thread_local! {
+    static THRESHOLD_CHANGES: RefCell<BTreeMap<u64, ThresholdChange>> = const { RefCell::new(BTreeMap::new()) };
+    static NEXT_CHANGE_ID: RefCell<u64> = const { RefCell::new(0) };
+    // When set, a change only takes effect once a second controller approves it
+    static REQUIRE_SECOND_APPROVER: RefCell<bool> = const { RefCell::new(false) };
+}
+
+// A threshold outside this range either extracts almost anything or almost nothing
+pub(crate) const MIN_CONFIDENCE_THRESHOLD: f32 = 0.5;
+pub(crate) const MAX_CONFIDENCE_THRESHOLD: f32 = 0.99;
+const MAX_REASON_BYTES: usize = 1024;
+const MAX_SCHEDULE_AHEAD_NANOS: u64 = 90 * 24 * 60 * 60 * 1_000_000_000;
+
+#[update]
+fn set_confidence_threshold(
+    directive_type: String,
+    value: f32,
+    effective_at: Option<u64>,
+    reason: String,
+) -> Result<ThresholdChange, String> {
+    let requester = caller();
+    if !ic_cdk::api::is_controller(&requester) {
+        return Err("Only controllers may change confidence thresholds".to_string());
+    }
+    if !directive_types::is_known(&directive_type) {
+        return Err(format!("Unknown directive type: {}", directive_type));
+    }
+    if !(MIN_CONFIDENCE_THRESHOLD..=MAX_CONFIDENCE_THRESHOLD).contains(&value) {
+        return Err(format!(
+            "Thresholds must be within {}-{}",
+            MIN_CONFIDENCE_THRESHOLD, MAX_CONFIDENCE_THRESHOLD
+        ));
+    }
+    let reason = validation::text("reason", &reason, MAX_REASON_BYTES)?;
+    if reason.is_empty() {
+        return Err("A reason is required for every threshold change".to_string());
+    }
+    let now = clock::now();
+    let effective_at = effective_at.unwrap_or(now).max(now);
+    if effective_at - now > MAX_SCHEDULE_AHEAD_NANOS {
+        return Err("Threshold changes may be scheduled at most 90 days ahead".to_string());
+    }
+
+    let change_id = NEXT_CHANGE_ID.with(|id| {
+        let mut id = id.borrow_mut();
+        *id += 1;
+        *id
+    });
+    let needs_approval = REQUIRE_SECOND_APPROVER.with(|r| *r.borrow());
+    let change = ThresholdChange {
+        change_id,
+        previous_value: global_threshold(&directive_type, now).unwrap_or(value),
+        directive_type,
+        new_value: value,
+        reason,
+        effective_at,
+        status: if needs_approval { "PENDING_APPROVAL" } else { "SCHEDULED" }.to_string(),
+        proposed_by: requester,
+        proposed_at: now,
+        approved_by: None,
+        approved_at: None,
+        cancelled_by: None,
+    };
+    THRESHOLD_CHANGES.with(|c| c.borrow_mut().insert(change_id, change.clone()));
+    ic_cdk::println!(
+        "AUDIT: Threshold change {} proposed by {}: {} {:.2} -> {:.2}",
+        change_id, requester, change.directive_type, change.previous_value, change.new_value
+    );
+    Ok(change)
+}
+
+#[update]
+fn approve_threshold_change(change_id: u64) -> Result<ThresholdChange, String> {
+    let requester = caller();
+    if !ic_cdk::api::is_controller(&requester) {
+        return Err("Only controllers may approve threshold changes".to_string());
+    }
+    let now = clock::now();
+    THRESHOLD_CHANGES.with(|c| {
+        let mut changes = c.borrow_mut();
+        let change = changes.get_mut(&change_id).ok_or(format!("Threshold change not found: {}", change_id))?;
+        if change.status != "PENDING_APPROVAL" {
+            return Err(format!("Threshold change {} is {}", change_id, change.status));
+        }
+        if change.proposed_by == requester {
+            return Err("A threshold change must be approved by someone other than its proposer".to_string());
+        }
+        change.status = "SCHEDULED".to_string();
+        change.approved_by = Some(requester);
+        change.approved_at = Some(now);
+        // Approved late, it takes effect on approval rather than retroactively
+        change.effective_at = change.effective_at.max(now);
+        Ok(change.clone())
+    })
+}
+
+// Withdraws a change that is pending or scheduled but not yet in effect
+#[update]
+fn cancel_threshold_change(change_id: u64) -> Result<ThresholdChange, String> {
+    let requester = caller();
+    if !ic_cdk::api::is_controller(&requester) {
+        return Err("Only controllers may cancel threshold changes".to_string());
+    }
+    let now = clock::now();
+    THRESHOLD_CHANGES.with(|c| {
+        let mut changes = c.borrow_mut();
+        let change = changes.get_mut(&change_id).ok_or(format!("Threshold change not found: {}", change_id))?;
+        let in_effect = change.status == "SCHEDULED" && change.effective_at <= now;
+        if change.status == "CANCELLED" || in_effect {
+            return Err(format!("Threshold change {} can no longer be cancelled", change_id));
+        }
+        change.status = "CANCELLED".to_string();
+        change.cancelled_by = Some(requester);
+        Ok(change.clone())
+    })
+}
+
+#[update]
+fn set_threshold_approval_required(required: bool) -> Result<(), String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only controllers may change threshold approval rules".to_string());
+    }
+    REQUIRE_SECOND_APPROVER.with(|r| *r.borrow_mut() = required);
+    Ok(())
+}
+
+// Newest first, optionally for one directive type
+#[query]
+fn get_threshold_change_history(directive_type: Option<String>, limit: u32) -> Vec<ThresholdChange> {
+    THRESHOLD_CHANGES.with(|c| {
+        c.borrow()
+            .values()
+            .rev()
+            .filter(|change| directive_type.as_ref().is_none_or(|t| *t == change.directive_type))
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    })
+}
+
+// The global threshold in force at `at`: the latest scheduled change already effective, else the type's own
+pub(crate) fn global_threshold(directive_type: &str, at: u64) -> Option<f32> {
+    THRESHOLD_CHANGES.with(|c| {
+        c.borrow()
+            .values()
+            .filter(|change| change.directive_type == directive_type)
+            .filter(|change| change.status == "SCHEDULED" && change.effective_at <= at)
+            .max_by_key(|change| (change.effective_at, change.change_id))
+            .map(|change| change.new_value)
+    })
+    .or_else(|| directive_types::base_threshold(directive_type))
+}
+
+pub(crate) fn snapshot() -> ThresholdState {
+    ThresholdState {
+        changes: THRESHOLD_CHANGES.with(|c| c.borrow().values().cloned().collect()),
+        next_change_id: NEXT_CHANGE_ID.with(|id| *id.borrow()),
+        require_second_approver: REQUIRE_SECOND_APPROVER.with(|r| *r.borrow()),
+    }
+}
+
+pub(crate) fn restore(state: Option<ThresholdState>) {
+    let Some(state) = state else {
+        return;
+    };
+    THRESHOLD_CHANGES.with(|c| *c.borrow_mut() = state.changes.into_iter().map(|x| (x.change_id, x)).collect());
+    NEXT_CHANGE_ID.with(|id| *id.borrow_mut() = state.next_change_id);
+    REQUIRE_SECOND_APPROVER.with(|r| *r.borrow_mut() = state.require_second_approver);
+}
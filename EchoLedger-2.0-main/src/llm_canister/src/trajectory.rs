@@ -0,0 +1,277 @@
+use ic_cdk::caller;
+use ic_cdk_macros::update;
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::actions::{self, RecommendedAction};
+use crate::{clock, tenancy, traps, validation};
+
+// A single snapshot says how sick a patient is, not which way they are going. Each assessment
+// opens or extends a session for the patient that keeps the vitals and labs submitted with it and
+// the snapshot recovery probability of every assessment, so repeated assessments form a series
+// of their own. Each measure's recent slope is classed as deteriorating, stable or improving,
+// and the overall trend moves the recovery probability and the recommended actions.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VitalObservation {
+    pub measure: String, // One of MEASURES
+    pub value: f32,
+    pub observed_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TrajectoryFeature {
+    pub measure: String,
+    pub points: u32,
+    pub first_value: f32,
+    pub last_value: f32,
+    pub slope_per_hour: f32,
+    pub direction: String, // "DETERIORATING", "STABLE", "IMPROVING"
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RiskTrajectory {
+    pub trend: String, // "DETERIORATING", "STABLE", "IMPROVING", "INSUFFICIENT_DATA"
+    pub features: Vec<TrajectoryFeature>,
+    pub window_start: u64,
+    pub window_end: u64,
+}
+
+// Everything submitted for one patient since the session opened
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AssessmentSession {
+    pub patient_id: String,
+    pub observations: Vec<VitalObservation>, // Oldest first
+    pub opened_at: u64,
+    pub last_assessed_at: u64,
+    pub latest_trajectory: Option<RiskTrajectory>,
+}
+
+// (measure, whether a rise is the bad direction, change over the window that counts as a trend)
+const MEASURES: [(&str, bool, f32); 9] = [
+    ("HEART_RATE", true, 15.0),
+    ("SYSTOLIC_BP", false, 15.0),
+    ("RESPIRATORY_RATE", true, 5.0),
+    ("SPO2", false, 3.0),
+    ("TEMPERATURE", true, 1.0),
+    ("GCS", false, 2.0),
+    ("LACTATE", true, 1.0),
+    ("CREATININE", true, 0.3),
+    // Recorded by the canister itself from each assessment's snapshot
+    ("ASSESSED_RECOVERY_PROBABILITY", false, 0.1),
+];
+const ASSESSED_MEASURE: &str = "ASSESSED_RECOVERY_PROBABILITY";
+
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+// Trends are read over the most recent day of observations
+const TRAJECTORY_WINDOW_HOURS: u64 = 24;
+// A session idle this long is closed; the next assessment starts a fresh one
+const SESSION_IDLE_HOURS: u64 = 72;
+// Observations may be timestamped slightly ahead of the canister's clock
+const MAX_CLOCK_SKEW_NANOS: u64 = 5 * 60 * 1_000_000_000;
+const MAX_OBSERVATIONS_PER_CALL: usize = 500;
+const MAX_OBSERVATIONS_PER_SESSION: usize = 2_000;
+const MAX_SESSIONS: usize = 10_000;
+// Each deteriorating measure cuts the recovery probability, at most MAX_DETERIORATING_PENALTIES times
+const DETERIORATION_FACTOR: f32 = 0.85;
+const MAX_DETERIORATING_PENALTIES: i32 = 3;
+const IMPROVEMENT_FACTOR: f32 = 1.1;
+
+thread_local! {
+    // (tenant, patient_id) -> the open session; None is the tenant of callers outside any hospital
+    static SESSIONS: RefCell<BTreeMap<(Option<String>, String), AssessmentSession>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+// The caller's hospital's open session for a patient
+#[update]
+async fn get_risk_trajectory(patient_id: String) -> Result<Option<AssessmentSession>, String> {
+    let _watch = traps::Watch::start("GET_RISK_TRAJECTORY");
+    let tenant = tenancy::active_tenant(caller()).await?;
+    let now = clock::now();
+    Ok(SESSIONS.with(|s| s.borrow().get(&(tenant, patient_id)).filter(|session| !is_idle(session, now)).cloned()))
+}
+
+pub(crate) fn validate(observations: &[VitalObservation]) -> Result<(), String> {
+    validation::collection("observations", observations.len(), MAX_OBSERVATIONS_PER_CALL)?;
+    let latest_allowed = clock::now().saturating_add(MAX_CLOCK_SKEW_NANOS);
+    for observation in observations {
+        if observation.measure == ASSESSED_MEASURE || !MEASURES.iter().any(|(m, _, _)| *m == observation.measure) {
+            return Err(validation::invalid("observations.measure", &format!("{:?} is not a supported measure", observation.measure)));
+        }
+        if !observation.value.is_finite() {
+            return Err(validation::invalid("observations.value", &format!("{} must be a finite number", observation.measure)));
+        }
+        if observation.observed_at > latest_allowed {
+            return Err(validation::invalid("observations.observed_at", "must not be in the future"));
+        }
+    }
+    Ok(())
+}
+
+// The trend this assessment would read, without touching the session. An assessment that goes on
+// to await an outcall records itself only once that settles, so an assessment lost to a trap after
+// the await leaves no snapshot behind for its retry to add a second time.
+pub(crate) fn preview(
+    tenant: Option<&str>,
+    patient_id: &str,
+    observations: &[VitalObservation],
+    snapshot_probability: f32,
+) -> RiskTrajectory {
+    let now = clock::now();
+    let key = (tenant.map(String::from), patient_id.to_string());
+    let mut session = SESSIONS.with(|s| s.borrow().get(&key).filter(|session| !is_idle(session, now)).cloned())
+        .unwrap_or_else(|| open_session(patient_id, now));
+    merge(&mut session, observations.to_vec(), snapshot_probability, now);
+    trajectory(&session.observations)
+}
+
+// Adds the new observations and this assessment's snapshot to the session, then reads the trend
+pub(crate) fn record_and_assess(
+    tenant: Option<&str>,
+    patient_id: &str,
+    observations: Vec<VitalObservation>,
+    snapshot_probability: f32,
+) -> RiskTrajectory {
+    let now = clock::now();
+    let key = (tenant.map(String::from), patient_id.to_string());
+    SESSIONS.with(|s| {
+        let mut sessions = s.borrow_mut();
+        sessions.retain(|_, session| !is_idle(session, now));
+        if !sessions.contains_key(&key) && sessions.len() >= MAX_SESSIONS {
+            let stalest = sessions.iter().min_by_key(|(_, session)| session.last_assessed_at).map(|(k, _)| k.clone());
+            if let Some(stalest) = stalest {
+                sessions.remove(&stalest);
+            }
+        }
+        let session = sessions.entry(key).or_insert_with(|| open_session(patient_id, now));
+        merge(session, observations, snapshot_probability, now);
+
+        let trajectory = trajectory(&session.observations);
+        session.last_assessed_at = now;
+        session.latest_trajectory = Some(trajectory.clone());
+        trajectory
+    })
+}
+
+fn open_session(patient_id: &str, now: u64) -> AssessmentSession {
+    AssessmentSession {
+        patient_id: patient_id.to_string(),
+        observations: Vec::new(),
+        opened_at: now,
+        last_assessed_at: now,
+        latest_trajectory: None,
+    }
+}
+
+fn merge(session: &mut AssessmentSession, observations: Vec<VitalObservation>, snapshot_probability: f32, now: u64) {
+    for observation in observations {
+        // A reading sent again with a later batch is kept once
+        let repeat = session.observations.iter()
+            .any(|o| o.measure == observation.measure && o.observed_at == observation.observed_at);
+        if !repeat {
+            session.observations.push(observation);
+        }
+    }
+    session.observations.push(VitalObservation {
+        measure: ASSESSED_MEASURE.to_string(),
+        value: snapshot_probability,
+        observed_at: now,
+    });
+    session.observations.sort_by_key(|o| o.observed_at);
+    let excess = session.observations.len().saturating_sub(MAX_OBSERVATIONS_PER_SESSION);
+    session.observations.drain(..excess);
+}
+
+// Moves the snapshot's probability and actions with the trend; bounds are applied by the caller
+pub(crate) fn apply(
+    trajectory: &RiskTrajectory,
+    recovery_probability: &mut f32,
+    risk_factors: &mut Vec<String>,
+    recommended_actions: &mut Vec<RecommendedAction>,
+) {
+    let deteriorating: Vec<&str> = trajectory.features.iter()
+        .filter(|f| f.direction == "DETERIORATING")
+        .map(|f| f.measure.as_str())
+        .collect();
+    match trajectory.trend.as_str() {
+        "DETERIORATING" => {
+            let penalties = (deteriorating.len() as i32).min(MAX_DETERIORATING_PENALTIES);
+            *recovery_probability *= DETERIORATION_FACTOR.powi(penalties);
+            risk_factors.push(format!("Deteriorating trajectory: {}", deteriorating.join(", ")));
+            actions::push_unique(recommended_actions, "URGENT_CLINICAL_REVIEW");
+            actions::push_unique(recommended_actions, "INCREASE_MONITORING");
+        }
+        "IMPROVING" => {
+            *recovery_probability *= IMPROVEMENT_FACTOR;
+            actions::push_unique(recommended_actions, "CONTINUE_CURRENT_MANAGEMENT");
+        }
+        _ => {}
+    }
+}
+
+fn is_idle(session: &AssessmentSession, now: u64) -> bool {
+    now.saturating_sub(session.last_assessed_at) > SESSION_IDLE_HOURS * NANOS_PER_HOUR
+}
+
+// The window ends at the latest observation; a measure needs two points in it to have a direction
+fn trajectory(observations: &[VitalObservation]) -> RiskTrajectory {
+    let window_end = observations.iter().map(|o| o.observed_at).max().unwrap_or_default();
+    let window_start = window_end.saturating_sub(TRAJECTORY_WINDOW_HOURS * NANOS_PER_HOUR);
+
+    let mut features = Vec::new();
+    for (measure, worse_when_rising, significant_change) in MEASURES {
+        let points: Vec<(f64, f32)> = observations.iter()
+            .filter(|o| o.measure == measure && o.observed_at >= window_start)
+            .map(|o| ((o.observed_at - window_start) as f64 / NANOS_PER_HOUR as f64, o.value))
+            .collect();
+        let (Some(first), Some(last)) = (points.first(), points.last()) else { continue };
+        let span_hours = last.0 - first.0;
+        if points.len() < 2 || span_hours <= 0.0 {
+            continue;
+        }
+        let slope_per_hour = slope(&points);
+        // The fitted change across the readings, so one outlier does not set the direction
+        let change = slope_per_hour * span_hours as f32;
+        let direction = if change.abs() < significant_change {
+            "STABLE"
+        } else if (change > 0.0) == worse_when_rising {
+            "DETERIORATING"
+        } else {
+            "IMPROVING"
+        };
+        features.push(TrajectoryFeature {
+            measure: measure.to_string(),
+            points: points.len() as u32,
+            first_value: first.1,
+            last_value: last.1,
+            slope_per_hour,
+            direction: direction.to_string(),
+        });
+    }
+
+    let count = |direction: &str| features.iter().filter(|f| f.direction == direction).count();
+    let trend = if features.is_empty() {
+        "INSUFFICIENT_DATA"
+    } else if count("DETERIORATING") > 0 {
+        // One failing system outweighs others recovering
+        "DETERIORATING"
+    } else if count("IMPROVING") > 0 {
+        "IMPROVING"
+    } else {
+        "STABLE"
+    };
+    RiskTrajectory { trend: trend.to_string(), features, window_start, window_end }
+}
+
+// Least-squares slope of value over hours
+fn slope(points: &[(f64, f32)]) -> f32 {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| *y as f64).sum::<f64>() / n;
+    let (covariance, variance) = points.iter().fold((0.0, 0.0), |(cov, var), (x, y)| {
+        (cov + (x - mean_x) * (*y as f64 - mean_y), var + (x - mean_x).powi(2))
+    });
+    if variance == 0.0 { 0.0 } else { (covariance / variance) as f32 }
+}
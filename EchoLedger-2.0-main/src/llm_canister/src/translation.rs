@@ -0,0 +1,105 @@
+use ic_cdk::{caller, Principal};
+use ic_cdk_macros::update;
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+use crate::{clock, injection, prompts, providers, traps, validation};
+
+// Directives written in another language, summarized in English for the clinicians who have to act
+// on them. The result is a machine translation and is labeled as one wherever it is shown; the
+// original stays the legal record. directive_manager owns the directive and stores the translation.
+
+const DIRECTIVE_MANAGER_CANISTER_ID: &str = "rdmx6-jaaaa-aaaah-qdrva-cai";
+const TARGET_LANGUAGE: &str = "en";
+const MACHINE_TRANSLATION_NOTICE: &str =
+    "Machine translation for orientation only. The original directive is authoritative; confirm with an interpreter when time allows.";
+
+// Mirrored by directive_manager and emergency_bridge
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DirectiveTranslation {
+    pub source_language: String,
+    pub target_language: String,
+    pub summary: String,
+    pub directives: Vec<TranslatedDirective>,
+    pub confidence_score: f32,
+    pub machine_translated: bool,
+    pub notice: String,
+    pub provider_id: String,
+    pub template_version: u32,
+    pub translated_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TranslatedDirective {
+    pub directive_type: String,
+    pub conditions: Vec<String>,
+}
+
+// directive_manager has already checked the requester may read the directive, and names the
+// patient's tenant so that the tenant's residency policy applies to the outcall
+#[update]
+async fn translate_directive_text(
+    patient_id: String,
+    source_language: String,
+    directive_text: String,
+    tenant_id: Option<String>,
+) -> Result<DirectiveTranslation, String> {
+    let _watch = traps::Watch::start("TRANSLATE_DIRECTIVE_TEXT");
+    let directive_manager = Principal::from_text(DIRECTIVE_MANAGER_CANISTER_ID).ok();
+    if Some(caller()) != directive_manager && !ic_cdk::api::is_controller(&caller()) {
+        return Err("Directive translations are requested through directive_manager".to_string());
+    }
+    let patient_id = validation::identifier("patient_id", &patient_id)?;
+    let source_language = validation::identifier("source_language", &source_language)?.to_lowercase();
+    let directive_text = validation::text("directive_text", &directive_text, validation::MAX_DIRECTIVE_TEXT_BYTES)?;
+    if directive_text.is_empty() {
+        return Err(validation::invalid("directive_text", "must not be empty"));
+    }
+
+    let (directive_text, injection_flags) = injection::screen("translate_directive_text", tenant_id.as_deref(), &directive_text);
+    if injection::blocks_outcall(&injection_flags) {
+        return Err(format!(
+            "Directive text was flagged for possible prompt injection ({}) and was not sent for translation",
+            injection_flags.join(", ")
+        ));
+    }
+    let reply = providers::complete_with_failover(
+        tenant_id.as_deref(),
+        prompts::TRANSLATION_TEMPLATE,
+        &patient_id,
+        &directive_text,
+        prompts::parse_translation_response,
+    ).await?.ok_or("No LLM provider is configured for translation")?;
+
+    let translated = reply.value;
+    let restore = |text: &str| injection::encode_output(&reply.redaction.restore(text));
+    // A document in some other language than the requester declared was translated from a wrong premise
+    let language_matches = source_language.split('-').next() == Some(translated.detected_language.as_str());
+    let confidence_score = if language_matches {
+        translated.confidence_score
+    } else {
+        translated.confidence_score.min(0.5)
+    };
+    ic_cdk::println!(
+        "🌐 Directive translated {} -> {} by {} (confidence {:.2})",
+        translated.detected_language,
+        TARGET_LANGUAGE,
+        reply.provider_id,
+        confidence_score
+    );
+    Ok(DirectiveTranslation {
+        source_language: translated.detected_language.clone(),
+        target_language: TARGET_LANGUAGE.to_string(),
+        summary: restore(&translated.summary),
+        directives: translated.directives.iter().map(|d| TranslatedDirective {
+            directive_type: d.directive_type.clone(),
+            conditions: d.conditions.iter().map(|c| restore(c)).collect(),
+        }).collect(),
+        confidence_score,
+        machine_translated: true,
+        notice: MACHINE_TRANSLATION_NOTICE.to_string(),
+        provider_id: reply.provider_id,
+        template_version: reply.template_version,
+        translated_at: clock::now(),
+    })
+}
@@ -0,0 +1,71 @@
+use ic_cdk::caller;
+use ic_cdk::api::call::is_recovering_from_trap;
+use ic_cdk_macros::query;
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::cell::RefCell;
+
+use crate::clock;
+
+// A trap rolls the message back to its last await. ic-cdk drops the trapped future in a cleanup
+// call whose changes are kept; a Watch held by every async update notices that drop and counts it.
+// A trap before the first await, or in a synchronous update, undoes the whole message, counter
+// included; those are seen by the caller as a CanisterError reject, and the calls this canister
+// makes - the directive window fan-out, tenant lookups - count them for the callee.
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TrapMetrics {
+    pub total_traps: u64,
+    pub by_operation: BTreeMap<String, u64>,
+    pub last_trap_at: Option<u64>,
+    pub last_operation: Option<String>,
+}
+
+thread_local! {
+    static TRAP_METRICS: RefCell<TrapMetrics> = RefCell::new(TrapMetrics::default());
+}
+
+pub(crate) struct Watch {
+    operation: &'static str,
+}
+
+impl Watch {
+    pub(crate) fn start(operation: &'static str) -> Watch {
+        Watch { operation }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        if is_recovering_from_trap() {
+            count(self.operation);
+            ic_cdk::println!("💥 TRAP during {}", self.operation);
+        }
+    }
+}
+
+// A call rejected because the callee trapped; its own rolled-back state could not count it
+pub(crate) fn record_callee_trap(target: &str) {
+    count(&format!("CALL:{}", target));
+    ic_cdk::println!("💥 TRAP in callee {}", target);
+}
+
+fn count(operation: &str) {
+    let now = clock::now();
+    TRAP_METRICS.with(|m| {
+        let mut metrics = m.borrow_mut();
+        metrics.total_traps += 1;
+        *metrics.by_operation.entry(operation.to_string()).or_default() += 1;
+        metrics.last_trap_at = Some(now);
+        metrics.last_operation = Some(operation.to_string());
+    });
+}
+
+#[query]
+fn get_trap_metrics() -> Result<TrapMetrics, String> {
+    if !ic_cdk::api::is_controller(&caller()) {
+        return Err("Only canister controllers may read trap metrics".to_string());
+    }
+    Ok(TRAP_METRICS.with(|m| m.borrow().clone()))
+}